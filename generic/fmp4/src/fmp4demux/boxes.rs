@@ -0,0 +1,1213 @@
+// Copyright (C) 2026 agent <agent@local>
+//
+// This Source Code Form is subject to the terms of the Mozilla Public License, v2.0.
+// If a copy of the MPL was not distributed with this file, You can obtain one at
+// <https://mozilla.org/MPL/2.0/>.
+//
+// SPDX-License-Identifier: MPL-2.0
+
+use gst::prelude::*;
+
+use anyhow::{bail, Context, Error};
+
+use crate::fmp4mux::boxes::{
+    BASE_DATA_OFFSET_PRESENT, DATA_OFFSET_PRESENT, DEFAULT_BASE_IS_MOOF,
+    DEFAULT_SAMPLE_DURATION_PRESENT, DEFAULT_SAMPLE_FLAGS_PRESENT, DEFAULT_SAMPLE_SIZE_PRESENT,
+    FIRST_SAMPLE_FLAGS_PRESENT, SAMPLE_COMPOSITION_TIME_OFFSET_PRESENT,
+    SAMPLE_DESCRIPTION_INDEX_PRESENT, SAMPLE_DURATION_PRESENT, SAMPLE_FLAGS_PRESENT,
+    SAMPLE_IS_NON_SYNC_SAMPLE, SAMPLE_SIZE_PRESENT,
+};
+
+/// Maximum size of a box header: 32 bit size, fourcc and 64 bit extended size.
+pub(super) const MAX_BOX_HEADER_SIZE: usize = 16;
+
+/// Reads big-endian values from a byte slice.
+struct Reader<'a> {
+    data: &'a [u8],
+}
+
+impl<'a> Reader<'a> {
+    fn new(data: &'a [u8]) -> Self {
+        Reader { data }
+    }
+
+    fn remaining(&self) -> usize {
+        self.data.len()
+    }
+
+    fn read_bytes(&mut self, len: usize) -> Result<&'a [u8], Error> {
+        if self.data.len() < len {
+            bail!("not enough data");
+        }
+
+        let (bytes, data) = self.data.split_at(len);
+        self.data = data;
+
+        Ok(bytes)
+    }
+
+    fn skip(&mut self, len: usize) -> Result<(), Error> {
+        self.read_bytes(len).map(|_| ())
+    }
+
+    fn read_array<const N: usize>(&mut self) -> Result<[u8; N], Error> {
+        let mut array = [0u8; N];
+        array.copy_from_slice(self.read_bytes(N)?);
+        Ok(array)
+    }
+
+    fn read_u8(&mut self) -> Result<u8, Error> {
+        Ok(self.read_array::<1>()?[0])
+    }
+
+    fn read_u16(&mut self) -> Result<u16, Error> {
+        Ok(u16::from_be_bytes(self.read_array()?))
+    }
+
+    fn read_u32(&mut self) -> Result<u32, Error> {
+        Ok(u32::from_be_bytes(self.read_array()?))
+    }
+
+    fn read_i32(&mut self) -> Result<i32, Error> {
+        Ok(i32::from_be_bytes(self.read_array()?))
+    }
+
+    fn read_u64(&mut self) -> Result<u64, Error> {
+        Ok(u64::from_be_bytes(self.read_array()?))
+    }
+
+    /// Reads a 32 bit value for version 0 and a 64 bit value for version 1 full boxes.
+    fn read_u32_or_u64(&mut self, version: u8) -> Result<u64, Error> {
+        if version == 0 {
+            self.read_u32().map(u64::from)
+        } else {
+            self.read_u64()
+        }
+    }
+
+    /// Reads a variable length unsigned integer of 1 to 4 bytes.
+    fn read_uint(&mut self, len: usize) -> Result<u32, Error> {
+        Ok(self
+            .read_bytes(len)?
+            .iter()
+            .fold(0u32, |acc, b| (acc << 8) | u32::from(*b)))
+    }
+
+    /// Reads a NUL-terminated UTF-8 string.
+    fn read_cstr(&mut self) -> Result<&'a str, Error> {
+        let len = self
+            .data
+            .iter()
+            .position(|b| *b == 0)
+            .context("unterminated string")?;
+        let s = std::str::from_utf8(self.read_bytes(len)?).context("invalid string")?;
+        self.skip(1)?;
+
+        Ok(s)
+    }
+
+    /// Reads the version and flags of a full box.
+    fn read_full_box_header(&mut self) -> Result<(u8, u32), Error> {
+        let version_flags = self.read_u32()?;
+
+        Ok(((version_flags >> 24) as u8, version_flags & 0x00_ff_ff_ff))
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub(super) struct BoxHeader {
+    pub(super) fourcc: [u8; 4],
+    /// Size of the header itself, 8 or 16 bytes.
+    pub(super) header_size: u64,
+    /// Size of the box including the header, `None` if it extends until the end of the file.
+    pub(super) size: Option<u64>,
+}
+
+/// Parses a box header from the start of `data`.
+///
+/// Returns `None` if not enough data is available yet.
+pub(super) fn parse_box_header(data: &[u8]) -> Result<Option<BoxHeader>, Error> {
+    if data.len() < 8 {
+        return Ok(None);
+    }
+
+    let mut r = Reader::new(data);
+    let size = r.read_u32()?;
+    let fourcc = r.read_array::<4>()?;
+
+    let (header_size, size) = match size {
+        0 => (8, None),
+        1 => {
+            if r.remaining() < 8 {
+                return Ok(None);
+            }
+
+            (16, Some(r.read_u64()?))
+        }
+        size => (8, Some(u64::from(size))),
+    };
+
+    if size.map_or(false, |size| size < header_size) {
+        bail!(
+            "invalid size {} for box {}",
+            size.unwrap(),
+            String::from_utf8_lossy(&fourcc)
+        );
+    }
+
+    Ok(Some(BoxHeader {
+        fourcc,
+        header_size,
+        size,
+    }))
+}
+
+/// Splits the first box off `data` and returns its fourcc and content.
+fn next_box<'a>(data: &mut &'a [u8]) -> Result<([u8; 4], &'a [u8]), Error> {
+    let d = *data;
+
+    let header = parse_box_header(d)?.context("truncated box header")?;
+    let size = header.size.unwrap_or(d.len() as u64);
+    if size > d.len() as u64 {
+        bail!("truncated box {}", String::from_utf8_lossy(&header.fourcc));
+    }
+
+    let content = &d[header.header_size as usize..size as usize];
+    *data = &d[size as usize..];
+
+    Ok((header.fourcc, content))
+}
+
+/// Iterates over the child boxes contained in `data`, returning the fourcc and content of each.
+fn iter_boxes<'a>(data: &'a [u8]) -> impl Iterator<Item = Result<([u8; 4], &'a [u8]), Error>> {
+    let mut data = data;
+
+    std::iter::from_fn(move || {
+        if data.is_empty() {
+            return None;
+        }
+
+        let res = next_box(&mut data);
+        if res.is_err() {
+            data = &[];
+        }
+
+        Some(res)
+    })
+}
+
+/// Returns the content of the first child box with the given fourcc.
+fn find_box<'a>(data: &'a [u8], fourcc: &[u8; 4]) -> Result<Option<&'a [u8]>, Error> {
+    for res in iter_boxes(data) {
+        let (child_fourcc, content) = res?;
+        if &child_fourcc == fourcc {
+            return Ok(Some(content));
+        }
+    }
+
+    Ok(None)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(super) enum TrackType {
+    Video,
+    Audio,
+    Text,
+}
+
+/// Defaults for the samples of a track from the `trex` box.
+#[derive(Debug, Default, Clone, Copy)]
+struct TrackExtends {
+    default_sample_duration: u32,
+    default_sample_size: u32,
+    default_sample_flags: u32,
+}
+
+#[derive(Debug)]
+pub(super) struct Track {
+    pub(super) track_id: u32,
+    pub(super) track_type: TrackType,
+    pub(super) timescale: u32,
+    pub(super) caps: gst::Caps,
+    trex: TrackExtends,
+}
+
+#[derive(Debug)]
+pub(super) struct Movie {
+    pub(super) timescale: u32,
+    /// Duration of the whole fragmented movie from the `mehd` box, in the movie timescale.
+    pub(super) fragment_duration: Option<u64>,
+    pub(super) tracks: Vec<Track>,
+}
+
+impl Movie {
+    pub(super) fn track_index(&self, track_id: u32) -> Option<usize> {
+        self.tracks
+            .iter()
+            .position(|track| track.track_id == track_id)
+    }
+}
+
+/// Parses the content of a `moov` box.
+pub(super) fn parse_moov(data: &[u8]) -> Result<Movie, Error> {
+    let mvhd = find_box(data, b"mvhd")?.context("no mvhd")?;
+    let mut r = Reader::new(mvhd);
+    let (version, _flags) = r.read_full_box_header()?;
+    // Creation and modification time
+    r.skip(if version == 0 { 2 * 4 } else { 2 * 8 })?;
+    let timescale = r.read_u32()?;
+    if timescale == 0 {
+        bail!("invalid movie timescale");
+    }
+
+    let mvex = find_box(data, b"mvex")?.context("no mvex, not a fragmented MP4")?;
+
+    let fragment_duration = find_box(mvex, b"mehd")?
+        .map(|mehd| {
+            let mut r = Reader::new(mehd);
+            let (version, _flags) = r.read_full_box_header()?;
+            r.read_u32_or_u64(version)
+        })
+        .transpose()?
+        .filter(|duration| *duration > 0);
+
+    let mut tracks = Vec::new();
+    for res in iter_boxes(data) {
+        let (fourcc, content) = res?;
+        if &fourcc != b"trak" {
+            continue;
+        }
+
+        if let Some(track) = parse_trak(content)? {
+            tracks.push(track);
+        }
+    }
+
+    for res in iter_boxes(mvex) {
+        let (fourcc, content) = res?;
+        if &fourcc != b"trex" {
+            continue;
+        }
+
+        let mut r = Reader::new(content);
+        let (_version, _flags) = r.read_full_box_header()?;
+        let track_id = r.read_u32()?;
+        let _default_sample_description_index = r.read_u32()?;
+        let trex = TrackExtends {
+            default_sample_duration: r.read_u32()?,
+            default_sample_size: r.read_u32()?,
+            default_sample_flags: r.read_u32()?,
+        };
+
+        if let Some(track) = tracks.iter_mut().find(|track| track.track_id == track_id) {
+            track.trex = trex;
+        }
+    }
+
+    if tracks.is_empty() {
+        bail!("no supported tracks");
+    }
+
+    Ok(Movie {
+        timescale,
+        fragment_duration,
+        tracks,
+    })
+}
+
+/// Parses a `trak` box, or returns `None` if the track type or codec is not supported.
+fn parse_trak(data: &[u8]) -> Result<Option<Track>, Error> {
+    let tkhd = find_box(data, b"tkhd")?.context("no tkhd")?;
+    let mut r = Reader::new(tkhd);
+    let (version, _flags) = r.read_full_box_header()?;
+    // Creation and modification time
+    r.skip(if version == 0 { 2 * 4 } else { 2 * 8 })?;
+    let track_id = r.read_u32()?;
+
+    let mdia = find_box(data, b"mdia")?.context("no mdia")?;
+
+    let mdhd = find_box(mdia, b"mdhd")?.context("no mdhd")?;
+    let mut r = Reader::new(mdhd);
+    let (version, _flags) = r.read_full_box_header()?;
+    // Creation and modification time
+    r.skip(if version == 0 { 2 * 4 } else { 2 * 8 })?;
+    let timescale = r.read_u32()?;
+    if timescale == 0 {
+        bail!("invalid timescale for track {}", track_id);
+    }
+
+    let hdlr = find_box(mdia, b"hdlr")?.context("no hdlr")?;
+    let mut r = Reader::new(hdlr);
+    let (_version, _flags) = r.read_full_box_header()?;
+    // Pre-defined
+    r.skip(4)?;
+    let track_type = match &r.read_array::<4>()? {
+        b"vide" => TrackType::Video,
+        b"soun" => TrackType::Audio,
+        b"text" | b"subt" => TrackType::Text,
+        _ => return Ok(None),
+    };
+
+    let stsd = find_box(mdia, b"minf")?
+        .and_then(|minf| find_box(minf, b"stbl").transpose())
+        .transpose()?
+        .and_then(|stbl| find_box(stbl, b"stsd").transpose())
+        .transpose()?
+        .context("no stsd")?;
+    let mut r = Reader::new(stsd);
+    let (_version, _flags) = r.read_full_box_header()?;
+    let entry_count = r.read_u32()?;
+    if entry_count == 0 {
+        bail!("no sample entries for track {}", track_id);
+    }
+
+    // Only the first sample entry is supported
+    let (fourcc, entry) = iter_boxes(r.data).next().context("no sample entry")??;
+
+    let caps = match track_type {
+        TrackType::Video => parse_visual_sample_entry(&fourcc, entry)?,
+        TrackType::Audio => parse_audio_sample_entry(&fourcc, entry)?,
+        TrackType::Text => parse_text_sample_entry(&fourcc, entry)?,
+    };
+
+    Ok(caps.map(|caps| Track {
+        track_id,
+        track_type,
+        timescale,
+        caps,
+        trex: TrackExtends::default(),
+    }))
+}
+
+fn parse_visual_sample_entry(fourcc: &[u8; 4], data: &[u8]) -> Result<Option<gst::Caps>, Error> {
+    let mut r = Reader::new(data);
+    // Reserved and data reference index
+    r.skip(6 + 2)?;
+    // Pre-defined and reserved
+    r.skip(2 + 2 + 3 * 4)?;
+    let width = r.read_u16()?;
+    let height = r.read_u16()?;
+    // Resolution, reserved, frame count, compressor name, depth and pre-defined
+    r.skip(4 + 4 + 4 + 2 + 32 + 2 + 2)?;
+    let children = r.data;
+
+    let mut caps = match fourcc {
+        b"avc1" | b"avc3" => {
+            let avcc = find_box(children, b"avcC")?.context("no avcC")?;
+            gst::Caps::builder("video/x-h264")
+                .field(
+                    "stream-format",
+                    if fourcc == b"avc1" { "avc" } else { "avc3" },
+                )
+                .field("alignment", "au")
+                .field("codec_data", gst::Buffer::from_mut_slice(avcc.to_vec()))
+                .build()
+        }
+        b"hvc1" | b"hev1" => {
+            let hvcc = find_box(children, b"hvcC")?.context("no hvcC")?;
+            gst::Caps::builder("video/x-h265")
+                .field(
+                    "stream-format",
+                    if fourcc == b"hvc1" { "hvc1" } else { "hev1" },
+                )
+                .field("alignment", "au")
+                .field("codec_data", gst::Buffer::from_mut_slice(hvcc.to_vec()))
+                .build()
+        }
+        b"vp09" => {
+            let vpcc = find_box(children, b"vpcC")?.context("no vpcC")?;
+            parse_vpcc(vpcc)?
+        }
+        b"av01" => {
+            let av1c = find_box(children, b"av1C")?.context("no av1C")?;
+            if av1c.len() < 4 || av1c[0] != 0x81 {
+                bail!("invalid av1C");
+            }
+
+            gst::Caps::builder("video/x-av1")
+                .field("stream-format", "obu-stream")
+                .field("alignment", "tu")
+                .field("codec_data", gst::Buffer::from_mut_slice(av1c.to_vec()))
+                .build()
+        }
+        _ => return Ok(None),
+    };
+
+    {
+        let caps = caps.get_mut().unwrap();
+        let s = caps.structure_mut(0).unwrap();
+        s.set("width", i32::from(width));
+        s.set("height", i32::from(height));
+
+        if let Some(pasp) = find_box(children, b"pasp")? {
+            let mut r = Reader::new(pasp);
+            let par_n = r.read_u32()?;
+            let par_d = r.read_u32()?;
+            if par_n > 0 && par_d > 0 && par_n <= i32::MAX as u32 && par_d <= i32::MAX as u32 {
+                s.set(
+                    "pixel-aspect-ratio",
+                    gst::Fraction::new(par_n as i32, par_d as i32),
+                );
+            }
+        }
+    }
+
+    Ok(Some(caps))
+}
+
+fn parse_vpcc(data: &[u8]) -> Result<gst::Caps, Error> {
+    let mut r = Reader::new(data);
+    let (version, _flags) = r.read_full_box_header()?;
+    if version != 1 {
+        bail!("unsupported vpcC version {}", version);
+    }
+
+    let profile = r.read_u8()?;
+    let _level = r.read_u8()?;
+    let bits = r.read_u8()?;
+    let bit_depth = u32::from(bits >> 4);
+    let chroma_subsampling = (bits >> 1) & 0x07;
+    let full_range = (bits & 0x01) != 0;
+    let primaries = r.read_u8()?;
+    let transfer = r.read_u8()?;
+    let matrix = r.read_u8()?;
+
+    let (chroma_format, chroma_site) = match chroma_subsampling {
+        0 => ("4:2:0", Some("mpeg2")),
+        1 => ("4:2:0", Some("jpeg")),
+        2 => ("4:2:2", None),
+        3 => ("4:4:4", None),
+        _ => bail!("invalid chroma subsampling {}", chroma_subsampling),
+    };
+
+    let mut builder = gst::Caps::builder("video/x-vp9")
+        .field("profile", profile.to_string())
+        .field("chroma-format", chroma_format)
+        .field("bit-depth-luma", bit_depth)
+        .field("bit-depth-chroma", bit_depth);
+    if let Some(chroma_site) = chroma_site {
+        builder = builder.field("chroma-site", chroma_site);
+    }
+    if let Some(colorimetry) = colorimetry_from_iso(primaries, transfer, matrix, full_range) {
+        builder = builder.field("colorimetry", colorimetry.to_string());
+    }
+
+    Ok(builder.build())
+}
+
+/// Converts the ISO/IEC 23091-4 colour primaries, transfer characteristics and matrix
+/// coefficients to a colorimetry, if all of them are known.
+fn colorimetry_from_iso(
+    primaries: u8,
+    transfer: u8,
+    matrix: u8,
+    full_range: bool,
+) -> Option<gst_video::VideoColorimetry> {
+    // 2 is "unspecified"
+    if primaries == 2 || transfer == 2 || matrix == 2 {
+        return None;
+    }
+
+    let range = if full_range {
+        gst_video::VideoColorRange::Range0_255
+    } else {
+        gst_video::VideoColorRange::Range16_235
+    };
+
+    #[cfg(feature = "v1_18")]
+    {
+        Some(gst_video::VideoColorimetry::new(
+            range,
+            gst_video::VideoColorMatrix::from_iso(u32::from(matrix)),
+            gst_video::VideoTransferFunction::from_iso(u32::from(transfer)),
+            gst_video::VideoColorPrimaries::from_iso(u32::from(primaries)),
+        ))
+    }
+    #[cfg(not(feature = "v1_18"))]
+    {
+        let primaries = match primaries {
+            1 => gst_video::VideoColorPrimaries::Bt709,
+            4 => gst_video::VideoColorPrimaries::Bt470m,
+            5 => gst_video::VideoColorPrimaries::Bt470bg,
+            6 => gst_video::VideoColorPrimaries::Smpte170m,
+            7 => gst_video::VideoColorPrimaries::Smpte240m,
+            8 => gst_video::VideoColorPrimaries::Film,
+            9 => gst_video::VideoColorPrimaries::Bt2020,
+            _ => return None,
+        };
+        let transfer = match transfer {
+            1 | 6 => gst_video::VideoTransferFunction::Bt709,
+            4 => gst_video::VideoTransferFunction::Gamma22,
+            5 => gst_video::VideoTransferFunction::Gamma28,
+            7 => gst_video::VideoTransferFunction::Smpte240m,
+            8 => gst_video::VideoTransferFunction::Gamma10,
+            9 => gst_video::VideoTransferFunction::Log100,
+            10 => gst_video::VideoTransferFunction::Log316,
+            13 => gst_video::VideoTransferFunction::Srgb,
+            15 => gst_video::VideoTransferFunction::Bt202012,
+            _ => return None,
+        };
+        let matrix = match matrix {
+            0 => gst_video::VideoColorMatrix::Rgb,
+            1 => gst_video::VideoColorMatrix::Bt709,
+            4 => gst_video::VideoColorMatrix::Fcc,
+            5 | 6 => gst_video::VideoColorMatrix::Bt601,
+            7 => gst_video::VideoColorMatrix::Smpte240m,
+            9 => gst_video::VideoColorMatrix::Bt2020,
+            _ => return None,
+        };
+
+        Some(gst_video::VideoColorimetry::new(
+            range, matrix, transfer, primaries,
+        ))
+    }
+}
+
+fn parse_audio_sample_entry(fourcc: &[u8; 4], data: &[u8]) -> Result<Option<gst::Caps>, Error> {
+    let mut r = Reader::new(data);
+    // Reserved and data reference index
+    r.skip(6 + 2)?;
+    let version = r.read_u16()?;
+    if version != 0 {
+        bail!("unsupported audio sample entry version {}", version);
+    }
+    // Reserved
+    r.skip(2 + 4)?;
+    let channels = r.read_u16()?;
+    // Sample size, pre-defined and reserved
+    r.skip(2 + 2 + 2)?;
+    let mut rate = r.read_u32()? >> 16;
+    let children = r.data;
+
+    if let Some(srat) = find_box(children, b"srat")? {
+        let mut r = Reader::new(srat);
+        let (_version, _flags) = r.read_full_box_header()?;
+        rate = r.read_u32()?;
+    }
+
+    let caps = match fourcc {
+        b"mp4a" => {
+            let esds = find_box(children, b"esds")?.context("no esds")?;
+            let codec_data = parse_esds_aac(esds)?;
+            gst::Caps::builder("audio/mpeg")
+                .field("mpegversion", 4i32)
+                .field("stream-format", "raw")
+                .field("framed", true)
+                .field("channels", i32::from(channels))
+                .field("rate", rate as i32)
+                .field("codec_data", gst::Buffer::from_mut_slice(codec_data))
+                .build()
+        }
+        b"Opus" => {
+            let dops = find_box(children, b"dOps")?.context("no dOps")?;
+            parse_dops(dops)?
+        }
+        b"fLaC" => {
+            let dfla = find_box(children, b"dfLa")?.context("no dfLa")?;
+            let streamheader = parse_dfla(dfla)?;
+            gst::Caps::builder("audio/x-flac")
+                .field("framed", true)
+                .field("channels", i32::from(channels))
+                .field("rate", rate as i32)
+                .field(
+                    "streamheader",
+                    gst::Array::from_owned(
+                        streamheader
+                            .into_iter()
+                            .map(|header| gst::Buffer::from_mut_slice(header).to_send_value())
+                            .collect::<Vec<_>>(),
+                    ),
+                )
+                .build()
+        }
+        _ => return Ok(None),
+    };
+
+    Ok(Some(caps))
+}
+
+/// Reads the tag and length of an MPEG-4 descriptor.
+fn read_descriptor_header(r: &mut Reader) -> Result<(u8, usize), Error> {
+    let tag = r.read_u8()?;
+
+    let mut len = 0usize;
+    for _ in 0..4 {
+        let b = r.read_u8()?;
+        len = (len << 7) | usize::from(b & 0x7f);
+        if b & 0x80 == 0 {
+            break;
+        }
+    }
+
+    Ok((tag, len))
+}
+
+/// Extracts the AAC decoder specific info from an `esds` box.
+fn parse_esds_aac(data: &[u8]) -> Result<Vec<u8>, Error> {
+    let mut r = Reader::new(data);
+    let (_version, _flags) = r.read_full_box_header()?;
+
+    let (tag, _len) = read_descriptor_header(&mut r)?;
+    if tag != 0x03 {
+        bail!("no ES descriptor");
+    }
+    // ES ID
+    r.skip(2)?;
+    let flags = r.read_u8()?;
+    if flags & 0x80 != 0 {
+        // Depends on ES ID
+        r.skip(2)?;
+    }
+    if flags & 0x40 != 0 {
+        // URL
+        let len = r.read_u8()?;
+        r.skip(usize::from(len))?;
+    }
+    if flags & 0x20 != 0 {
+        // OCR ES ID
+        r.skip(2)?;
+    }
+
+    let (tag, _len) = read_descriptor_header(&mut r)?;
+    if tag != 0x04 {
+        bail!("no decoder config descriptor");
+    }
+    let object_type = r.read_u8()?;
+    // MPEG-4 AAC and MPEG-2 AAC main, LC and SSR profiles
+    if ![0x40, 0x66, 0x67, 0x68].contains(&object_type) {
+        bail!("unsupported object type {:#x}", object_type);
+    }
+    // Stream type, buffer size, max and average bitrate
+    r.skip(1 + 3 + 4 + 4)?;
+
+    let (tag, len) = read_descriptor_header(&mut r)?;
+    if tag != 0x05 {
+        bail!("no decoder specific info");
+    }
+    let codec_data = r.read_bytes(len)?;
+    if codec_data.len() < 2 {
+        bail!("too small decoder specific info");
+    }
+
+    Ok(codec_data.to_vec())
+}
+
+/// Creates Opus caps with `OpusHead` and `OpusTags` streamheaders from a `dOps` box.
+fn parse_dops(data: &[u8]) -> Result<gst::Caps, Error> {
+    let mut r = Reader::new(data);
+    let version = r.read_u8()?;
+    if version != 0 {
+        bail!("unsupported dOps version {}", version);
+    }
+    let channels = r.read_u8()?;
+    let pre_skip = r.read_u16()?;
+    let rate = r.read_u32()?;
+    let output_gain = r.read_u16()?;
+    let mapping_family = r.read_u8()?;
+
+    if channels == 0 {
+        bail!("no channels");
+    }
+
+    let (stream_count, coupled_count, channel_mapping) = if mapping_family != 0 {
+        let stream_count = r.read_u8()?;
+        let coupled_count = r.read_u8()?;
+        let channel_mapping = r.read_bytes(usize::from(channels))?.to_vec();
+        (stream_count, coupled_count, channel_mapping)
+    } else {
+        if channels > 2 {
+            bail!("too many channels for mapping family 0");
+        }
+        (1, channels - 1, (0..channels).collect())
+    };
+
+    let mut head = Vec::with_capacity(21 + usize::from(channels));
+    head.extend(b"OpusHead");
+    head.push(1);
+    head.push(channels);
+    head.extend(pre_skip.to_le_bytes());
+    head.extend(rate.to_le_bytes());
+    head.extend(output_gain.to_le_bytes());
+    head.push(mapping_family);
+    if mapping_family != 0 {
+        head.push(stream_count);
+        head.push(coupled_count);
+        head.extend_from_slice(&channel_mapping);
+    }
+
+    let vendor = b"gst-plugin-fmp4";
+    let mut tags = Vec::with_capacity(8 + 4 + vendor.len() + 4);
+    tags.extend(b"OpusTags");
+    tags.extend((vendor.len() as u32).to_le_bytes());
+    tags.extend(vendor);
+    // No user comments
+    tags.extend(0u32.to_le_bytes());
+
+    let mut builder = gst::Caps::builder("audio/x-opus")
+        .field("channel-mapping-family", i32::from(mapping_family))
+        .field("channels", i32::from(channels))
+        .field("rate", rate as i32)
+        .field("stream-count", i32::from(stream_count))
+        .field("coupled-count", i32::from(coupled_count));
+    if mapping_family != 0 {
+        builder = builder.field(
+            "channel-mapping",
+            gst::Array::from_owned(
+                channel_mapping
+                    .iter()
+                    .map(|c| i32::from(*c).to_send_value())
+                    .collect::<Vec<_>>(),
+            ),
+        );
+    }
+
+    Ok(builder
+        .field(
+            "streamheader",
+            gst::Array::new(&[
+                &gst::Buffer::from_mut_slice(head),
+                &gst::Buffer::from_mut_slice(tags),
+            ]),
+        )
+        .build())
+}
+
+/// Creates FLAC streamheaders from a `dfLa` box.
+///
+/// The first streamheader contains the FLAC mapping header, the fLaC marker and the STREAMINFO
+/// metadata block, every following one contains one additional metadata block.
+fn parse_dfla(data: &[u8]) -> Result<Vec<Vec<u8>>, Error> {
+    let mut r = Reader::new(data);
+    let (_version, _flags) = r.read_full_box_header()?;
+
+    let mut blocks = Vec::new();
+    while r.remaining() > 0 {
+        let header = r.read_array::<4>()?;
+        let len = u32::from_be_bytes([0, header[1], header[2], header[3]]) as usize;
+        let content = r.read_bytes(len)?;
+
+        let mut block = Vec::with_capacity(4 + len);
+        block.extend(header);
+        block.extend_from_slice(content);
+        blocks.push(block);
+
+        if header[0] & 0x80 != 0 {
+            break;
+        }
+    }
+
+    if blocks.first().map_or(true, |block| block[0] & 0x7f != 0) {
+        bail!("no STREAMINFO metadata block");
+    }
+    if blocks[0].len() != 4 + 34 {
+        bail!("invalid STREAMINFO metadata block");
+    }
+
+    let num_headers = u16::try_from(blocks.len() - 1).context("too many metadata blocks")?;
+
+    let mut streamheader = Vec::with_capacity(blocks.len());
+    let mut first = Vec::with_capacity(13 + 4 + 34);
+    first.extend([0x7f, b'F', b'L', b'A', b'C']);
+    // Mapping version 1.0
+    first.extend([1, 0]);
+    first.extend(num_headers.to_be_bytes());
+    first.extend(b"fLaC");
+
+    let mut blocks = blocks.into_iter();
+    first.extend(blocks.next().unwrap());
+    streamheader.push(first);
+    streamheader.extend(blocks);
+
+    Ok(streamheader)
+}
+
+fn parse_text_sample_entry(fourcc: &[u8; 4], data: &[u8]) -> Result<Option<gst::Caps>, Error> {
+    let mut r = Reader::new(data);
+    // Reserved and data reference index
+    r.skip(6 + 2)?;
+
+    let caps = match fourcc {
+        b"wvtt" => gst::Caps::builder("text/x-raw")
+            .field("format", "utf8")
+            .build(),
+        b"stpp" => {
+            let namespace = r.read_cstr()?;
+            if !namespace
+                .split(' ')
+                .any(|namespace| namespace == "http://www.w3.org/ns/ttml")
+            {
+                return Ok(None);
+            }
+
+            gst::Caps::builder("application/ttml+xml").build()
+        }
+        _ => return Ok(None),
+    };
+
+    Ok(Some(caps))
+}
+
+#[derive(Debug)]
+pub(super) struct Sample {
+    /// Track index
+    pub(super) idx: usize,
+    /// Absolute offset of the sample data
+    pub(super) offset: u64,
+    pub(super) size: u32,
+    /// Decode time in the track timescale
+    pub(super) dts: u64,
+    pub(super) duration: u32,
+    pub(super) composition_time_offset: i32,
+    pub(super) sync: bool,
+}
+
+impl Sample {
+    /// Presentation time in the track timescale.
+    pub(super) fn pts(&self) -> u64 {
+        if self.composition_time_offset < 0 {
+            self.dts
+                .saturating_sub(u64::from(self.composition_time_offset.unsigned_abs()))
+        } else {
+            self.dts + self.composition_time_offset as u64
+        }
+    }
+}
+
+#[derive(Debug)]
+pub(super) struct Fragment {
+    pub(super) sequence_number: u32,
+    /// All samples of the fragment, sorted by their offset
+    pub(super) samples: Vec<Sample>,
+}
+
+/// Parses the content of a `moof` box that started at `moof_offset`.
+///
+/// `decode_times` contains the decode time of the next sample of each track, which is used if a
+/// `traf` has no `tfdt`, and is updated with the end of each track's samples in this fragment.
+pub(super) fn parse_moof(
+    data: &[u8],
+    moof_offset: u64,
+    movie: &Movie,
+    decode_times: &mut [u64],
+) -> Result<Fragment, Error> {
+    let mfhd = find_box(data, b"mfhd")?.context("no mfhd")?;
+    let mut r = Reader::new(mfhd);
+    let (_version, _flags) = r.read_full_box_header()?;
+    let sequence_number = r.read_u32()?;
+
+    let mut samples = Vec::new();
+    // End of the data of the previous traf, the default base data offset if there is neither an
+    // explicit base data offset nor is the moof the base
+    let mut prev_traf_end = moof_offset;
+
+    for res in iter_boxes(data) {
+        let (fourcc, content) = res?;
+        if &fourcc != b"traf" {
+            continue;
+        }
+
+        prev_traf_end = parse_traf(
+            content,
+            moof_offset,
+            prev_traf_end,
+            movie,
+            decode_times,
+            &mut samples,
+        )?;
+    }
+
+    samples.sort_by_key(|sample| sample.offset);
+
+    Ok(Fragment {
+        sequence_number,
+        samples,
+    })
+}
+
+fn parse_traf(
+    data: &[u8],
+    moof_offset: u64,
+    prev_traf_end: u64,
+    movie: &Movie,
+    decode_times: &mut [u64],
+    samples: &mut Vec<Sample>,
+) -> Result<u64, Error> {
+    let tfhd = find_box(data, b"tfhd")?.context("no tfhd")?;
+    let mut r = Reader::new(tfhd);
+    let (_version, tf_flags) = r.read_full_box_header()?;
+    let track_id = r.read_u32()?;
+
+    let idx = match movie.track_index(track_id) {
+        Some(idx) => idx,
+        // Unsupported track
+        None => return Ok(prev_traf_end),
+    };
+    let trex = &movie.tracks[idx].trex;
+
+    let base_data_offset = if tf_flags & BASE_DATA_OFFSET_PRESENT != 0 {
+        r.read_u64()?
+    } else if tf_flags & DEFAULT_BASE_IS_MOOF != 0 {
+        moof_offset
+    } else {
+        prev_traf_end
+    };
+    if tf_flags & SAMPLE_DESCRIPTION_INDEX_PRESENT != 0 {
+        let sample_description_index = r.read_u32()?;
+        if sample_description_index != 1 {
+            bail!(
+                "unsupported sample description index {}",
+                sample_description_index
+            );
+        }
+    }
+    let default_duration = if tf_flags & DEFAULT_SAMPLE_DURATION_PRESENT != 0 {
+        r.read_u32()?
+    } else {
+        trex.default_sample_duration
+    };
+    let default_size = if tf_flags & DEFAULT_SAMPLE_SIZE_PRESENT != 0 {
+        r.read_u32()?
+    } else {
+        trex.default_sample_size
+    };
+    let default_flags = if tf_flags & DEFAULT_SAMPLE_FLAGS_PRESENT != 0 {
+        r.read_u32()?
+    } else {
+        trex.default_sample_flags
+    };
+
+    if let Some(tfdt) = find_box(data, b"tfdt")? {
+        let mut r = Reader::new(tfdt);
+        let (version, _flags) = r.read_full_box_header()?;
+        decode_times[idx] = r.read_u32_or_u64(version)?;
+    }
+
+    let mut data_offset = base_data_offset;
+    for res in iter_boxes(data) {
+        let (fourcc, content) = res?;
+        if &fourcc != b"trun" {
+            continue;
+        }
+
+        let mut r = Reader::new(content);
+        let (version, tr_flags) = r.read_full_box_header()?;
+        let sample_count = r.read_u32()?;
+
+        if tr_flags & DATA_OFFSET_PRESENT != 0 {
+            let offset = r.read_i32()?;
+            data_offset = if offset < 0 {
+                base_data_offset
+                    .checked_sub(u64::from(offset.unsigned_abs()))
+                    .context("invalid data offset")?
+            } else {
+                base_data_offset + offset as u64
+            };
+        }
+
+        let first_sample_flags = if tr_flags & FIRST_SAMPLE_FLAGS_PRESENT != 0 {
+            Some(r.read_u32()?)
+        } else {
+            None
+        };
+
+        for i in 0..sample_count {
+            let duration = if tr_flags & SAMPLE_DURATION_PRESENT != 0 {
+                r.read_u32()?
+            } else {
+                default_duration
+            };
+            let size = if tr_flags & SAMPLE_SIZE_PRESENT != 0 {
+                r.read_u32()?
+            } else {
+                default_size
+            };
+            let flags = if tr_flags & SAMPLE_FLAGS_PRESENT != 0 {
+                r.read_u32()?
+            } else {
+                match first_sample_flags {
+                    Some(first_sample_flags) if i == 0 => first_sample_flags,
+                    _ => default_flags,
+                }
+            };
+            let composition_time_offset = if tr_flags & SAMPLE_COMPOSITION_TIME_OFFSET_PRESENT != 0
+            {
+                // Unsigned in version 0 but values above i32::MAX are not sensible anyway
+                let offset = r.read_u32()?;
+                if version == 0 {
+                    i32::try_from(offset).context("too big composition time offset")?
+                } else {
+                    offset as i32
+                }
+            } else {
+                0
+            };
+
+            samples.push(Sample {
+                idx,
+                offset: data_offset,
+                size,
+                dts: decode_times[idx],
+                duration,
+                composition_time_offset,
+                sync: flags & SAMPLE_IS_NON_SYNC_SAMPLE == 0,
+            });
+
+            data_offset += u64::from(size);
+            decode_times[idx] += u64::from(duration);
+        }
+    }
+
+    Ok(data_offset)
+}
+
+#[derive(Debug, Clone, Copy)]
+pub(super) struct RandomAccessEntry {
+    /// Track index
+    pub(super) idx: usize,
+    /// Time in the track timescale
+    pub(super) time: u64,
+    pub(super) moof_offset: u64,
+}
+
+/// Parses the content of a `mfro` box and returns the size of the `mfra` box.
+pub(super) fn parse_mfro(data: &[u8]) -> Result<u32, Error> {
+    let mut r = Reader::new(data);
+    let (_version, _flags) = r.read_full_box_header()?;
+
+    r.read_u32()
+}
+
+/// Parses the content of a `mfra` box into random access entries sorted by track and time.
+pub(super) fn parse_mfra(data: &[u8], movie: &Movie) -> Result<Vec<RandomAccessEntry>, Error> {
+    let mut entries = Vec::new();
+
+    for res in iter_boxes(data) {
+        let (fourcc, content) = res?;
+        if &fourcc != b"tfra" {
+            continue;
+        }
+
+        let mut r = Reader::new(content);
+        let (version, _flags) = r.read_full_box_header()?;
+        let track_id = r.read_u32()?;
+        let lengths = r.read_u32()?;
+        let traf_number_len = ((lengths >> 4) & 0x3) as usize + 1;
+        let trun_number_len = ((lengths >> 2) & 0x3) as usize + 1;
+        let sample_number_len = (lengths & 0x3) as usize + 1;
+        let number_of_entries = r.read_u32()?;
+
+        let idx = match movie.track_index(track_id) {
+            Some(idx) => idx,
+            None => continue,
+        };
+
+        for _ in 0..number_of_entries {
+            let time = r.read_u32_or_u64(version)?;
+            let moof_offset = r.read_u32_or_u64(version)?;
+            let _traf_number = r.read_uint(traf_number_len)?;
+            let _trun_number = r.read_uint(trun_number_len)?;
+            let _sample_number = r.read_uint(sample_number_len)?;
+
+            entries.push(RandomAccessEntry {
+                idx,
+                time,
+                moof_offset,
+            });
+        }
+    }
+
+    entries.sort_by_key(|entry| (entry.idx, entry.time));
+
+    Ok(entries)
+}
+
+#[derive(Debug)]
+pub(super) struct EventMessage {
+    pub(super) scheme_id_uri: String,
+    pub(super) value: String,
+    /// Presentation time in nanoseconds, only known for version 1 boxes
+    pub(super) presentation_time: Option<gst::ClockTime>,
+    pub(super) duration: Option<gst::ClockTime>,
+    pub(super) id: u32,
+    pub(super) message_data: gst::Buffer,
+}
+
+/// Parses the content of an `emsg` box.
+pub(super) fn parse_emsg(data: &[u8]) -> Result<EventMessage, Error> {
+    let mut r = Reader::new(data);
+    let (version, _flags) = r.read_full_box_header()?;
+
+    let to_clock_time = |time: u64, timescale: u32| {
+        time.mul_div_round(gst::ClockTime::SECOND.nseconds(), u64::from(timescale))
+            .map(gst::ClockTime::from_nseconds)
+    };
+
+    let (scheme_id_uri, value, timescale, presentation_time, duration, id) = if version == 0 {
+        let scheme_id_uri = r.read_cstr()?;
+        let value = r.read_cstr()?;
+        let timescale = r.read_u32()?;
+        let _presentation_time_delta = r.read_u32()?;
+        let duration = r.read_u32()?;
+        let id = r.read_u32()?;
+
+        (scheme_id_uri, value, timescale, None, duration, id)
+    } else {
+        let timescale = r.read_u32()?;
+        let presentation_time = r.read_u64()?;
+        let duration = r.read_u32()?;
+        let id = r.read_u32()?;
+        let scheme_id_uri = r.read_cstr()?;
+        let value = r.read_cstr()?;
+
+        (
+            scheme_id_uri,
+            value,
+            timescale,
+            Some(presentation_time),
+            duration,
+            id,
+        )
+    };
+
+    if timescale == 0 {
+        bail!("invalid timescale");
+    }
+
+    Ok(EventMessage {
+        scheme_id_uri: scheme_id_uri.to_string(),
+        value: value.to_string(),
+        presentation_time: presentation_time.and_then(|time| to_clock_time(time, timescale)),
+        // 0xffffffff is an unknown duration
+        duration: Some(duration)
+            .filter(|duration| *duration != u32::MAX)
+            .and_then(|duration| to_clock_time(u64::from(duration), timescale)),
+        id,
+        message_data: gst::Buffer::from_mut_slice(r.data.to_vec()),
+    })
+}
+
+/// Extracts the cue payload from a `wvtt` sample, or returns `None` for an empty cue.
+pub(super) fn parse_wvtt_sample(data: &[u8]) -> Result<Option<String>, Error> {
+    let mut payload = None;
+
+    for res in iter_boxes(data) {
+        let (fourcc, content) = res?;
+        if &fourcc != b"vttc" {
+            continue;
+        }
+
+        // Multiple cues in one sample are separated by newlines
+        if let Some(payl) = find_box(content, b"payl")? {
+            let payl = std::str::from_utf8(payl).context("cue not valid UTF-8")?;
+            match payload {
+                None => payload = Some(String::from(payl)),
+                Some(ref mut payload) => {
+                    payload.push('\n');
+                    payload.push_str(payl);
+                }
+            }
+        }
+    }
+
+    Ok(payload)
+}
@@ -0,0 +1,1476 @@
+// Copyright (C) 2026 agent <agent@local>
+//
+// This Source Code Form is subject to the terms of the Mozilla Public License, v2.0.
+// If a copy of the MPL was not distributed with this file, You can obtain one at
+// <https://mozilla.org/MPL/2.0/>.
+//
+// SPDX-License-Identifier: MPL-2.0
+
+use gst::glib;
+use gst::prelude::*;
+use gst::subclass::prelude::*;
+use gst::{gst_debug, gst_error, gst_info, gst_log, gst_trace, gst_warning};
+
+use std::collections::VecDeque;
+use std::sync::Mutex;
+
+use once_cell::sync::Lazy;
+
+use super::boxes;
+
+static CAT: Lazy<gst::DebugCategory> = Lazy::new(|| {
+    gst::DebugCategory::new(
+        "fmp4demux",
+        gst::DebugColorFlags::empty(),
+        Some("FMP4Demux Element"),
+    )
+});
+
+/// Boxes that are parsed as a whole, everything else apart from the samples in the `mdat` is
+/// skipped.
+const PARSED_BOXES: [&[u8; 4]; 3] = [b"moov", b"moof", b"emsg"];
+
+/// Maximum size of a box that is parsed as a whole.
+const MAX_PARSED_BOX_SIZE: u64 = 64 * 1024 * 1024;
+
+struct Stream {
+    srcpad: gst::Pad,
+    track_type: boxes::TrackType,
+    timescale: u32,
+    /// Whether samples have to be converted from `wvtt` to plain text
+    wvtt: bool,
+    need_segment: bool,
+    discont: bool,
+    eos: bool,
+}
+
+#[derive(Default)]
+struct PullState {
+    /// Whether the `mfra` was already looked for
+    index_loaded: bool,
+    /// Random access entries from the `mfra`, sorted by track and time
+    index: Vec<boxes::RandomAccessEntry>,
+}
+
+/// Output that is produced while holding the state lock and pushed downstream after releasing it.
+enum Output {
+    /// New source pads and their caps, to be exposed
+    Pads(Vec<(gst::Pad, gst::Caps)>),
+    /// Event for a single source pad, or all of them
+    Event(Option<gst::Pad>, gst::Event),
+    Buffer(gst::Pad, gst::Buffer),
+}
+
+struct State {
+    /// Offset of the next input byte to be handled
+    offset: u64,
+    /// Push mode: number of bytes to skip before handling further input
+    skip: u64,
+    /// Push mode: end of the `mdat` whose samples are currently handled
+    box_end: Option<u64>,
+    adapter: gst_base::UniqueAdapter,
+
+    movie: Option<boxes::Movie>,
+    streams: Vec<Stream>,
+    /// Decode time of the next sample of each track, in the track timescale
+    decode_times: Vec<u64>,
+    /// Samples of the current fragment that still have to be output, sorted by offset
+    pending_samples: VecDeque<boxes::Sample>,
+
+    segment: gst::FormattedSegment<gst::ClockTime>,
+    seek_seqnum: Option<gst::Seqnum>,
+    duration: Option<gst::ClockTime>,
+
+    // Pull mode
+    pull: Option<PullState>,
+}
+
+impl Default for State {
+    fn default() -> Self {
+        Self {
+            offset: 0,
+            skip: 0,
+            box_end: None,
+            adapter: gst_base::UniqueAdapter::new(),
+            movie: None,
+            streams: Vec::new(),
+            decode_times: Vec::new(),
+            pending_samples: VecDeque::new(),
+            segment: gst::FormattedSegment::new(),
+            seek_seqnum: None,
+            duration: None,
+            pull: None,
+        }
+    }
+}
+
+impl State {
+    /// Converts a time in the given timescale to a `gst::ClockTime`.
+    fn to_clock_time(time: u64, timescale: u32) -> Option<gst::ClockTime> {
+        time.mul_div_round(gst::ClockTime::SECOND.nseconds(), timescale as u64)
+            .map(gst::ClockTime::from_nseconds)
+    }
+
+    /// Index of the track used for seeking: the first video track, or otherwise the first track.
+    fn reference_track(&self) -> usize {
+        self.streams
+            .iter()
+            .position(|stream| stream.track_type == boxes::TrackType::Video)
+            .unwrap_or(0)
+    }
+}
+
+pub struct FMP4Demux {
+    sinkpad: gst::Pad,
+    state: Mutex<State>,
+    flow_combiner: Mutex<gst_base::UniqueFlowCombiner>,
+}
+
+impl FMP4Demux {
+    fn sink_activate(
+        &self,
+        pad: &gst::Pad,
+        _element: &super::FMP4Demux,
+    ) -> Result<(), gst::LoggableError> {
+        let mode = {
+            let mut query = gst::query::Scheduling::new();
+            let mut state = self.state.lock().unwrap();
+
+            state.pull = None;
+
+            if !pad.peer_query(&mut query) {
+                gst_debug!(CAT, obj: pad, "Scheduling query failed on peer");
+                gst::PadMode::Push
+            } else if query
+                .has_scheduling_mode_with_flags(gst::PadMode::Pull, gst::SchedulingFlags::SEEKABLE)
+            {
+                gst_debug!(CAT, obj: pad, "Activating in Pull mode");
+
+                state.pull = Some(PullState::default());
+
+                gst::PadMode::Pull
+            } else {
+                gst_debug!(CAT, obj: pad, "Activating in Push mode");
+                gst::PadMode::Push
+            }
+        };
+
+        pad.activate_mode(mode, true)?;
+        Ok(())
+    }
+
+    fn sink_activatemode(
+        &self,
+        _pad: &gst::Pad,
+        element: &super::FMP4Demux,
+        mode: gst::PadMode,
+        active: bool,
+    ) -> Result<(), gst::LoggableError> {
+        if mode == gst::PadMode::Pull {
+            if active {
+                self.start_task(element)?;
+            } else {
+                let _ = self.sinkpad.stop_task();
+            }
+        }
+
+        Ok(())
+    }
+
+    fn start_task(&self, element: &super::FMP4Demux) -> Result<(), gst::LoggableError> {
+        let element_weak = element.downgrade();
+        let pad_weak = self.sinkpad.downgrade();
+        let res = self.sinkpad.start_task(move || {
+            let element = match element_weak.upgrade() {
+                Some(element) => element,
+                None => {
+                    if let Some(pad) = pad_weak.upgrade() {
+                        pad.pause_task().unwrap();
+                    }
+                    return;
+                }
+            };
+
+            let demux = Self::from_instance(&element);
+            demux.loop_fn(&element);
+        });
+        if res.is_err() {
+            return Err(gst::loggable_error!(CAT, "Failed to start pad task"));
+        }
+        Ok(())
+    }
+
+    fn loop_fn(&self, element: &super::FMP4Demux) {
+        let res = self.pull_step(element);
+
+        match res {
+            Ok(_) => (),
+            Err(gst::FlowError::Flushing) => {
+                gst_debug!(CAT, obj: element, "Pausing after flow {:?}", gst::FlowError::Flushing);
+                let _ = self.sinkpad.pause_task();
+            }
+            Err(gst::FlowError::Eos) => {
+                gst_debug!(CAT, obj: element, "Pausing after flow {:?}", gst::FlowError::Eos);
+                self.push_eos(element);
+                let _ = self.sinkpad.pause_task();
+            }
+            Err(flow) => {
+                gst_error!(CAT, obj: element, "Pausing after flow {:?}", flow);
+                if flow != gst::FlowError::Error {
+                    gst::element_error!(
+                        element,
+                        gst::StreamError::Failed,
+                        ["Streaming stopped, reason: {:?}", flow]
+                    );
+                }
+                self.push_eos(element);
+                let _ = self.sinkpad.pause_task();
+            }
+        }
+    }
+
+    /// Pulls exactly `size` bytes at `offset` and maps them readable.
+    fn pull_exact(
+        &self,
+        offset: u64,
+        size: u64,
+    ) -> Result<gst::MappedBuffer<gst::buffer::Readable>, gst::FlowError> {
+        let size = u32::try_from(size).map_err(|_| gst::FlowError::Error)?;
+        let buffer = self.sinkpad.pull_range(offset, size)?;
+        if buffer.size() != size as usize {
+            gst_debug!(
+                CAT,
+                obj: &self.sinkpad,
+                "Short read at offset {}: {} < {}",
+                offset,
+                buffer.size(),
+                size
+            );
+            return Err(gst::FlowError::Eos);
+        }
+
+        buffer
+            .into_mapped_buffer_readable()
+            .map_err(|_| gst::FlowError::Error)
+    }
+
+    /// Handles the next sample or top-level box in pull mode.
+    fn pull_step(&self, element: &super::FMP4Demux) -> Result<gst::FlowSuccess, gst::FlowError> {
+        let mut state = self.state.lock().unwrap();
+
+        if let Some(sample) = state.pending_samples.pop_front() {
+            drop(state);
+
+            let buffer = self
+                .sinkpad
+                .pull_range(sample.offset, sample.size)
+                .map_err(|err| {
+                    if err != gst::FlowError::Flushing {
+                        gst_error!(CAT, obj: element, "Failed to pull sample: {:?}", err);
+                    }
+                    err
+                })?;
+
+            let mut state = self.state.lock().unwrap();
+            let outputs = self.handle_sample(element, &mut state, sample, buffer)?;
+            drop(state);
+
+            let res = self.push_output(element, outputs)?;
+
+            let state = self.state.lock().unwrap();
+            if !state.streams.is_empty() && state.streams.iter().all(|stream| stream.eos) {
+                gst_debug!(CAT, obj: element, "All streams are EOS");
+                return Err(gst::FlowError::Eos);
+            }
+
+            return Ok(res);
+        }
+
+        let offset = state.offset;
+        drop(state);
+
+        let buffer = self
+            .sinkpad
+            .pull_range(offset, boxes::MAX_BOX_HEADER_SIZE as u32)?;
+        let header = {
+            let map = buffer.map_readable().map_err(|_| gst::FlowError::Error)?;
+            boxes::parse_box_header(&map).map_err(|err| {
+                gst::element_error!(
+                    element,
+                    gst::StreamError::Demux,
+                    ["Invalid box header at offset {}: {}", offset, err]
+                );
+                gst::FlowError::Error
+            })?
+        };
+
+        let header = match header {
+            Some(header) => header,
+            None => {
+                gst_debug!(CAT, obj: element, "Reached end of input at offset {}", offset);
+                return Err(gst::FlowError::Eos);
+            }
+        };
+
+        gst_trace!(
+            CAT,
+            obj: element,
+            "Box {} at offset {} with size {:?}",
+            String::from_utf8_lossy(&header.fourcc),
+            offset,
+            header.size,
+        );
+
+        let size = match header.size {
+            Some(size) => size,
+            None if PARSED_BOXES.contains(&&header.fourcc) => {
+                gst::element_error!(
+                    element,
+                    gst::StreamError::Demux,
+                    [
+                        "Box {} without size",
+                        String::from_utf8_lossy(&header.fourcc)
+                    ]
+                );
+                return Err(gst::FlowError::Error);
+            }
+            None => {
+                // Extends until the end of the input
+                return Err(gst::FlowError::Eos);
+            }
+        };
+
+        let outputs = if PARSED_BOXES.contains(&&header.fourcc) {
+            if size > MAX_PARSED_BOX_SIZE {
+                gst::element_error!(
+                    element,
+                    gst::StreamError::Demux,
+                    [
+                        "Too big {} box of {} bytes",
+                        String::from_utf8_lossy(&header.fourcc),
+                        size
+                    ]
+                );
+                return Err(gst::FlowError::Error);
+            }
+
+            let map = self.pull_exact(offset, size)?;
+
+            let mut state = self.state.lock().unwrap();
+            state.offset = offset + size;
+            self.handle_box(element, &mut state, &header, offset, &map)?
+        } else {
+            let mut state = self.state.lock().unwrap();
+            state.offset = offset + size;
+            Vec::new()
+        };
+
+        self.push_output(element, outputs)
+    }
+
+    /// Loads the random access index from the `mfra` at the end of the input and determines the
+    /// duration from the last fragment if it's not known from the `moov` already.
+    fn load_index(&self, element: &super::FMP4Demux, state: &mut State) {
+        let size = match self.sinkpad.peer_query_duration::<gst::format::Bytes>() {
+            Some(gst::format::Bytes(size)) => size,
+            _ => {
+                gst_debug!(CAT, obj: element, "Unknown upstream size, not loading index");
+                return;
+            }
+        };
+
+        let movie = state.movie.as_ref().unwrap();
+
+        let index = (|| -> Option<Vec<boxes::RandomAccessEntry>> {
+            // The mfro is always the last box of the mfra and has a fixed size
+            let mfro_offset = size.checked_sub(16)?;
+            let map = self.pull_exact(mfro_offset, 16).ok()?;
+            let header = boxes::parse_box_header(&map).ok()??;
+            if &header.fourcc != b"mfro" || header.size != Some(16) {
+                return None;
+            }
+            let mfra_size = boxes::parse_mfro(&map[8..]).ok()? as u64;
+
+            let mfra_offset = size.checked_sub(mfra_size)?;
+            if mfra_size < 16 || mfra_size > MAX_PARSED_BOX_SIZE {
+                return None;
+            }
+            let map = self.pull_exact(mfra_offset, mfra_size).ok()?;
+            let header = boxes::parse_box_header(&map).ok()??;
+            if &header.fourcc != b"mfra" || header.size != Some(mfra_size) {
+                return None;
+            }
+
+            match boxes::parse_mfra(&map[header.header_size as usize..], movie) {
+                Ok(index) => Some(index),
+                Err(err) => {
+                    gst_warning!(CAT, obj: element, "Failed to parse mfra: {}", err);
+                    None
+                }
+            }
+        })()
+        .unwrap_or_default();
+
+        gst_debug!(
+            CAT,
+            obj: element,
+            "Loaded index with {} entries",
+            index.len()
+        );
+
+        // Determine the duration from the end of the last fragment
+        if state.duration.is_none() {
+            let last_moof_offset = index.iter().map(|entry| entry.moof_offset).max();
+
+            let duration = last_moof_offset.and_then(|moof_offset| {
+                let map = self
+                    .pull_exact(moof_offset, boxes::MAX_BOX_HEADER_SIZE as u64)
+                    .ok()?;
+                let header = boxes::parse_box_header(&map).ok()??;
+                let moof_size = header.size?;
+                if &header.fourcc != b"moof" || moof_size > MAX_PARSED_BOX_SIZE {
+                    return None;
+                }
+                let map = self.pull_exact(moof_offset, moof_size).ok()?;
+
+                let mut decode_times = vec![0; movie.tracks.len()];
+                let fragment = boxes::parse_moof(
+                    &map[header.header_size as usize..],
+                    moof_offset,
+                    movie,
+                    &mut decode_times,
+                )
+                .ok()?;
+
+                fragment
+                    .samples
+                    .iter()
+                    .filter_map(|sample| {
+                        State::to_clock_time(
+                            sample.pts() + u64::from(sample.duration),
+                            movie.tracks[sample.idx].timescale,
+                        )
+                    })
+                    .max()
+            });
+
+            gst_debug!(CAT, obj: element, "Duration {}", duration.display());
+            state.duration = duration;
+        }
+
+        let pull = state.pull.as_mut().unwrap();
+        pull.index = index;
+        pull.index_loaded = true;
+    }
+
+    /// Handles the next sample or top-level box from the adapter in push mode.
+    ///
+    /// Returns `None` if more data is needed.
+    fn handle_push_data(
+        &self,
+        element: &super::FMP4Demux,
+        state: &mut State,
+    ) -> Result<Option<Vec<Output>>, gst::FlowError> {
+        loop {
+            let available = state.adapter.available() as u64;
+
+            if state.skip > 0 {
+                let skip = std::cmp::min(state.skip, available);
+                if skip == 0 {
+                    return Ok(None);
+                }
+
+                state.adapter.flush(skip as usize);
+                state.offset += skip;
+                state.skip -= skip;
+                continue;
+            }
+
+            if let Some(sample) = state.pending_samples.front() {
+                if sample.offset < state.offset {
+                    gst_warning!(
+                        CAT,
+                        obj: element,
+                        "Sample at offset {} already skipped",
+                        sample.offset
+                    );
+                    state.pending_samples.pop_front();
+                    continue;
+                }
+
+                if sample.offset > state.offset {
+                    state.skip = sample.offset - state.offset;
+                    continue;
+                }
+
+                let size = sample.size as u64;
+                if available < size {
+                    return Ok(None);
+                }
+
+                let buffer = state
+                    .adapter
+                    .take_buffer(size as usize)
+                    .map_err(|_| gst::FlowError::Error)?;
+                state.offset += size;
+
+                let sample = state.pending_samples.pop_front().unwrap();
+                return self.handle_sample(element, state, sample, buffer).map(Some);
+            }
+
+            // Skip the remainder of the mdat once all its samples are handled
+            if let Some(box_end) = state.box_end.take() {
+                if box_end > state.offset {
+                    state.skip = box_end - state.offset;
+                    continue;
+                }
+            }
+
+            if available < 8 {
+                return Ok(None);
+            }
+
+            let header = {
+                let map = state
+                    .adapter
+                    .map(std::cmp::min(available, boxes::MAX_BOX_HEADER_SIZE as u64) as usize)
+                    .unwrap();
+                boxes::parse_box_header(&map)
+            };
+
+            let header = match header {
+                Ok(Some(header)) => header,
+                Ok(None) => return Ok(None),
+                Err(err) => {
+                    gst::element_error!(
+                        element,
+                        gst::StreamError::Demux,
+                        ["Invalid box header at offset {}: {}", state.offset, err]
+                    );
+                    return Err(gst::FlowError::Error);
+                }
+            };
+
+            gst_trace!(
+                CAT,
+                obj: element,
+                "Box {} at offset {} with size {:?}",
+                String::from_utf8_lossy(&header.fourcc),
+                state.offset,
+                header.size,
+            );
+
+            // Handle the samples of the mdat one by one
+            if &header.fourcc == b"mdat" && !state.pending_samples.is_empty() {
+                state.box_end = Some(
+                    header
+                        .size
+                        .map(|size| state.offset + size)
+                        .unwrap_or(u64::MAX),
+                );
+                state.skip = header.header_size;
+                continue;
+            }
+
+            let size = match header.size {
+                Some(size) => size,
+                None if PARSED_BOXES.contains(&&header.fourcc) => {
+                    gst::element_error!(
+                        element,
+                        gst::StreamError::Demux,
+                        [
+                            "Box {} without size",
+                            String::from_utf8_lossy(&header.fourcc)
+                        ]
+                    );
+                    return Err(gst::FlowError::Error);
+                }
+                None => u64::MAX,
+            };
+
+            if !PARSED_BOXES.contains(&&header.fourcc) {
+                state.skip = size;
+                continue;
+            }
+
+            if size > MAX_PARSED_BOX_SIZE {
+                gst::element_error!(
+                    element,
+                    gst::StreamError::Demux,
+                    [
+                        "Too big {} box of {} bytes",
+                        String::from_utf8_lossy(&header.fourcc),
+                        size
+                    ]
+                );
+                return Err(gst::FlowError::Error);
+            }
+
+            if available < size {
+                return Ok(None);
+            }
+
+            let offset = state.offset;
+            let map = state
+                .adapter
+                .take_buffer(size as usize)
+                .map_err(|_| gst::FlowError::Error)?
+                .into_mapped_buffer_readable()
+                .map_err(|_| gst::FlowError::Error)?;
+            state.offset += size;
+
+            return self
+                .handle_box(element, state, &header, offset, &map)
+                .map(Some);
+        }
+    }
+
+    /// Handles a complete top-level box.
+    fn handle_box(
+        &self,
+        element: &super::FMP4Demux,
+        state: &mut State,
+        header: &boxes::BoxHeader,
+        offset: u64,
+        data: &[u8],
+    ) -> Result<Vec<Output>, gst::FlowError> {
+        let content = &data[header.header_size as usize..];
+
+        match &header.fourcc {
+            b"moov" => {
+                if state.movie.is_some() {
+                    gst_debug!(CAT, obj: element, "Ignoring additional moov");
+                    return Ok(Vec::new());
+                }
+
+                let movie = boxes::parse_moov(content).map_err(|err| {
+                    gst::element_error!(
+                        element,
+                        gst::StreamError::Demux,
+                        ["Failed to parse moov: {}", err]
+                    );
+                    gst::FlowError::Error
+                })?;
+
+                gst_debug!(CAT, obj: element, "Parsed movie {:?}", movie);
+
+                Ok(self.create_streams(element, state, movie))
+            }
+            b"moof" => {
+                let movie = match state.movie {
+                    Some(ref movie) => movie,
+                    None => {
+                        gst::element_error!(
+                            element,
+                            gst::StreamError::Demux,
+                            ["Fragment before movie header"]
+                        );
+                        return Err(gst::FlowError::Error);
+                    }
+                };
+
+                let fragment = boxes::parse_moof(content, offset, movie, &mut state.decode_times)
+                    .map_err(|err| {
+                    gst::element_error!(
+                        element,
+                        gst::StreamError::Demux,
+                        ["Failed to parse moof: {}", err]
+                    );
+                    gst::FlowError::Error
+                })?;
+
+                gst_debug!(
+                    CAT,
+                    obj: element,
+                    "Fragment {} with {} samples",
+                    fragment.sequence_number,
+                    fragment.samples.len()
+                );
+
+                state.pending_samples = fragment.samples.into();
+
+                Ok(Vec::new())
+            }
+            b"emsg" => {
+                if state.streams.is_empty() {
+                    gst_debug!(CAT, obj: element, "Ignoring emsg before movie header");
+                    return Ok(Vec::new());
+                }
+
+                let emsg = match boxes::parse_emsg(content) {
+                    Ok(emsg) => emsg,
+                    Err(err) => {
+                        gst_warning!(CAT, obj: element, "Failed to parse emsg: {}", err);
+                        return Ok(Vec::new());
+                    }
+                };
+
+                gst_debug!(CAT, obj: element, "Event message {:?}", emsg);
+
+                let s = gst::Structure::builder("emsg")
+                    .field("scheme-id-uri", &emsg.scheme_id_uri)
+                    .field("value", &emsg.value)
+                    .field("pts", emsg.presentation_time)
+                    .field("duration", emsg.duration)
+                    .field("id", emsg.id)
+                    .field("message-data", &emsg.message_data)
+                    .build();
+
+                Ok(vec![Output::Event(
+                    None,
+                    gst::event::CustomDownstream::new(s),
+                )])
+            }
+            _ => unreachable!(),
+        }
+    }
+
+    /// Creates a source pad for every supported track of the movie.
+    fn create_streams(
+        &self,
+        element: &super::FMP4Demux,
+        state: &mut State,
+        movie: boxes::Movie,
+    ) -> Vec<Output> {
+        let mut pads = Vec::new();
+        let (mut num_video, mut num_audio, mut num_text) = (0, 0, 0);
+
+        for track in &movie.tracks {
+            let name = match track.track_type {
+                boxes::TrackType::Video => {
+                    num_video += 1;
+                    format!("video_{}", num_video - 1)
+                }
+                boxes::TrackType::Audio => {
+                    num_audio += 1;
+                    format!("audio_{}", num_audio - 1)
+                }
+                boxes::TrackType::Text => {
+                    num_text += 1;
+                    format!("subtitle_{}", num_text - 1)
+                }
+            };
+
+            let templ_name = match track.track_type {
+                boxes::TrackType::Video => "video_%u",
+                boxes::TrackType::Audio => "audio_%u",
+                boxes::TrackType::Text => "subtitle_%u",
+            };
+
+            let templ = element.element_class().pad_template(templ_name).unwrap();
+            let srcpad = gst::Pad::builder_with_template(&templ, Some(&name))
+                .event_function(|pad, parent, event| {
+                    FMP4Demux::catch_panic_pad_function(
+                        parent,
+                        || false,
+                        |demux, element| demux.src_event(pad, element, event),
+                    )
+                })
+                .query_function(|pad, parent, query| {
+                    FMP4Demux::catch_panic_pad_function(
+                        parent,
+                        || false,
+                        |demux, element| demux.src_query(pad, element, query),
+                    )
+                })
+                .build();
+
+            gst_debug!(
+                CAT,
+                obj: element,
+                "Creating pad {} for track {} with caps {:?}",
+                name,
+                track.track_id,
+                track.caps
+            );
+
+            state.streams.push(Stream {
+                srcpad: srcpad.clone(),
+                track_type: track.track_type,
+                timescale: track.timescale,
+                wvtt: track.track_type == boxes::TrackType::Text
+                    && track.caps.structure(0).unwrap().name() == "text/x-raw",
+                need_segment: true,
+                discont: true,
+                eos: false,
+            });
+
+            pads.push((srcpad, track.caps.clone()));
+        }
+
+        state.decode_times = vec![0; movie.tracks.len()];
+        if state.duration.is_none() {
+            state.duration = movie
+                .fragment_duration
+                .and_then(|duration| State::to_clock_time(duration, movie.timescale));
+        }
+        state.movie = Some(movie);
+
+        if state.pull.is_some() {
+            self.load_index(element, state);
+        }
+
+        vec![Output::Pads(pads)]
+    }
+
+    /// Converts a sample into a buffer for its stream.
+    fn handle_sample(
+        &self,
+        element: &super::FMP4Demux,
+        state: &mut State,
+        sample: boxes::Sample,
+        buffer: gst::Buffer,
+    ) -> Result<Vec<Output>, gst::FlowError> {
+        let mut outputs = Vec::new();
+
+        let State {
+            ref mut streams,
+            ref segment,
+            seek_seqnum,
+            ..
+        } = *state;
+        let stream = &mut streams[sample.idx];
+
+        if stream.eos {
+            return Ok(outputs);
+        }
+
+        let (pts, dts, duration) = match (
+            State::to_clock_time(sample.pts(), stream.timescale),
+            State::to_clock_time(sample.dts, stream.timescale),
+            State::to_clock_time(u64::from(sample.duration), stream.timescale),
+        ) {
+            (Some(pts), Some(dts), Some(duration)) => (pts, dts, duration),
+            _ => {
+                gst::element_error!(element, gst::StreamError::Demux, ["Timestamp overflow"]);
+                return Err(gst::FlowError::Error);
+            }
+        };
+
+        gst_log!(
+            CAT,
+            obj: &stream.srcpad,
+            "Sample at offset {} with PTS {}, DTS {}, duration {}, sync {}",
+            sample.offset,
+            pts,
+            dts,
+            duration,
+            sample.sync,
+        );
+
+        if segment.stop().map_or(false, |stop| pts >= stop) {
+            gst_debug!(CAT, obj: &stream.srcpad, "Reached segment stop");
+            stream.eos = true;
+
+            let mut eos = gst::event::Eos::builder();
+            if let Some(seek_seqnum) = seek_seqnum {
+                eos = eos.seqnum(seek_seqnum);
+            }
+            outputs.push(Output::Event(Some(stream.srcpad.clone()), eos.build()));
+
+            return Ok(outputs);
+        }
+
+        if stream.need_segment {
+            let mut event = gst::event::Segment::builder(segment);
+            if let Some(seek_seqnum) = seek_seqnum {
+                event = event.seqnum(seek_seqnum);
+            }
+            outputs.push(Output::Event(Some(stream.srcpad.clone()), event.build()));
+            stream.need_segment = false;
+        }
+
+        let mut buffer = if stream.wvtt {
+            let map = buffer.map_readable().map_err(|_| gst::FlowError::Error)?;
+            match boxes::parse_wvtt_sample(&map) {
+                Ok(Some(payload)) => gst::Buffer::from_mut_slice(payload.into_bytes()),
+                Ok(None) => {
+                    outputs.push(Output::Event(
+                        Some(stream.srcpad.clone()),
+                        gst::event::Gap::builder(pts).duration(duration).build(),
+                    ));
+                    return Ok(outputs);
+                }
+                Err(err) => {
+                    gst_warning!(CAT, obj: &stream.srcpad, "Invalid wvtt sample: {}", err);
+                    return Ok(outputs);
+                }
+            }
+        } else {
+            buffer
+        };
+
+        {
+            let buffer = buffer.make_mut();
+            buffer.set_offset(gst::BUFFER_OFFSET_NONE);
+            buffer.set_offset_end(gst::BUFFER_OFFSET_NONE);
+            buffer.set_pts(pts);
+            buffer.set_dts(dts);
+            buffer.set_duration(duration);
+
+            let mut flags = gst::BufferFlags::empty();
+            if !sample.sync {
+                flags |= gst::BufferFlags::DELTA_UNIT;
+            }
+            if stream.discont {
+                flags |= gst::BufferFlags::DISCONT;
+                stream.discont = false;
+            }
+            buffer.set_flags(flags);
+        }
+
+        outputs.push(Output::Buffer(stream.srcpad.clone(), buffer));
+
+        Ok(outputs)
+    }
+
+    /// Pushes output downstream. Must be called without the state lock.
+    fn push_output(
+        &self,
+        element: &super::FMP4Demux,
+        outputs: Vec<Output>,
+    ) -> Result<gst::FlowSuccess, gst::FlowError> {
+        let mut res = Ok(gst::FlowSuccess::Ok);
+
+        for output in outputs {
+            match output {
+                Output::Pads(pads) => {
+                    let group_id = gst::GroupId::next();
+
+                    for (pad, caps) in pads {
+                        pad.set_active(true).unwrap();
+
+                        let stream_id = pad.create_stream_id(element, Some(pad.name().as_str()));
+                        pad.push_event(
+                            gst::event::StreamStart::builder(&stream_id)
+                                .group_id(group_id)
+                                .build(),
+                        );
+                        pad.push_event(gst::event::Caps::new(&caps));
+
+                        self.flow_combiner.lock().unwrap().add_pad(&pad);
+                        element.add_pad(&pad).unwrap();
+                    }
+
+                    element.no_more_pads();
+                }
+                Output::Event(Some(pad), event) => {
+                    gst_debug!(CAT, obj: &pad, "Pushing event {:?}", event);
+                    pad.push_event(event);
+                }
+                Output::Event(None, event) => {
+                    for pad in element.src_pads() {
+                        gst_debug!(CAT, obj: &pad, "Pushing event {:?}", event);
+                        pad.push_event(event.clone());
+                    }
+                }
+                Output::Buffer(pad, buffer) => {
+                    gst_trace!(CAT, obj: &pad, "Pushing buffer {:?}", buffer);
+                    let pad_res = pad.push(buffer);
+                    res = self
+                        .flow_combiner
+                        .lock()
+                        .unwrap()
+                        .update_pad_flow(&pad, pad_res);
+                    if let Err(err) = res {
+                        if err != gst::FlowError::Flushing {
+                            gst_debug!(CAT, obj: element, "Combined flow {:?}", err);
+                        }
+                        return res;
+                    }
+                }
+            }
+        }
+
+        res
+    }
+
+    /// Pushes EOS on all streams that are not EOS yet.
+    fn push_eos(&self, element: &super::FMP4Demux) {
+        let mut state = self.state.lock().unwrap();
+
+        if state.streams.is_empty() {
+            drop(state);
+            gst::element_error!(element, gst::StreamError::Demux, ["No streams found"]);
+            return;
+        }
+
+        let seek_seqnum = state.seek_seqnum;
+        let pads = state
+            .streams
+            .iter_mut()
+            .filter(|stream| !stream.eos)
+            .map(|stream| {
+                stream.eos = true;
+                stream.srcpad.clone()
+            })
+            .collect::<Vec<_>>();
+        drop(state);
+
+        for pad in pads {
+            let mut eos = gst::event::Eos::builder();
+            if let Some(seek_seqnum) = seek_seqnum {
+                eos = eos.seqnum(seek_seqnum);
+            }
+            pad.push_event(eos.build());
+        }
+    }
+
+    fn sink_chain(
+        &self,
+        pad: &gst::Pad,
+        element: &super::FMP4Demux,
+        buffer: gst::Buffer,
+    ) -> Result<gst::FlowSuccess, gst::FlowError> {
+        gst_log!(CAT, obj: pad, "Handling buffer {:?}", buffer);
+
+        let mut state = self.state.lock().unwrap();
+        state.adapter.push(buffer);
+
+        let mut res = Ok(gst::FlowSuccess::Ok);
+        while let Some(outputs) = self.handle_push_data(element, &mut state)? {
+            drop(state);
+            res = self.push_output(element, outputs);
+            if res.is_err() {
+                return res;
+            }
+            state = self.state.lock().unwrap();
+        }
+
+        res
+    }
+
+    fn flush(&self, state: &mut State) {
+        state.adapter.clear();
+        state.skip = 0;
+        state.box_end = None;
+        state.pending_samples.clear();
+        for stream in &mut state.streams {
+            stream.need_segment = true;
+            stream.discont = true;
+            stream.eos = false;
+        }
+        self.flow_combiner.lock().unwrap().reset();
+    }
+
+    fn sink_event(&self, pad: &gst::Pad, element: &super::FMP4Demux, event: gst::Event) -> bool {
+        use gst::EventView;
+
+        gst_log!(CAT, obj: pad, "Handling event {:?}", event);
+
+        match event.view() {
+            EventView::Caps(_) => {
+                // Caps are sent on the source pads once the moov is parsed
+                true
+            }
+            EventView::Segment(ev) => {
+                // We send our own TIME segments, but keep track of the byte offset
+                let mut state = self.state.lock().unwrap();
+                if let Some(segment) = ev.segment().downcast_ref::<gst::format::Bytes>() {
+                    if let Some(gst::format::Bytes(start)) = segment.start() {
+                        state.offset = start;
+                    }
+                }
+                true
+            }
+            EventView::FlushStop(_) => {
+                let mut state = self.state.lock().unwrap();
+                self.flush(&mut state);
+                drop(state);
+
+                pad.event_default(Some(element), event)
+            }
+            EventView::Eos(_) => {
+                if self.state.lock().unwrap().streams.is_empty() {
+                    gst::element_error!(element, gst::StreamError::Demux, ["No streams found"]);
+                }
+
+                pad.event_default(Some(element), event)
+            }
+            _ => pad.event_default(Some(element), event),
+        }
+    }
+
+    fn perform_seek(
+        &self,
+        event: &gst::event::Seek<&gst::EventRef>,
+        element: &super::FMP4Demux,
+    ) -> bool {
+        {
+            let state = self.state.lock().unwrap();
+            match state.pull {
+                Some(ref pull) if !pull.index.is_empty() => (),
+                _ => {
+                    gst_debug!(CAT, obj: element, "Seeking needs pull mode and an index");
+                    return false;
+                }
+            }
+        }
+
+        let (rate, flags, start_type, start, stop_type, stop) = event.get();
+
+        let start: Option<gst::ClockTime> = match start.try_into() {
+            Ok(start) => start,
+            Err(_) => {
+                gst_error!(CAT, obj: element, "seek has invalid format");
+                return false;
+            }
+        };
+
+        let stop: Option<gst::ClockTime> = match stop.try_into() {
+            Ok(stop) => stop,
+            Err(_) => {
+                gst_error!(CAT, obj: element, "seek has invalid format");
+                return false;
+            }
+        };
+
+        if rate <= 0.0 {
+            gst_error!(CAT, obj: element, "only forward playback is supported");
+            return false;
+        }
+
+        if !flags.contains(gst::SeekFlags::FLUSH) {
+            gst_error!(CAT, obj: element, "only flushing seeks are supported");
+            return false;
+        }
+
+        if start_type == gst::SeekType::End || stop_type == gst::SeekType::End {
+            gst_error!(CAT, obj: element, "Relative seeks are not supported");
+            return false;
+        }
+
+        let seek_seqnum = event.seqnum();
+
+        // Look up the fragment to start from before flushing so that a seek that can't be
+        // handled leaves the element running as before
+        let (mut segment, time, moof_offset) = {
+            let state = self.state.lock().unwrap();
+
+            let mut segment = state.segment.clone();
+            segment.do_seek(rate, flags, start_type, start, stop_type, stop);
+
+            // Start from the last fragment of the reference track that starts before the
+            // target position
+            let target = segment.start().unwrap_or(gst::ClockTime::ZERO);
+            let reference_track = state.reference_track();
+            let timescale = state.streams[reference_track].timescale;
+            let pull = state.pull.as_ref().unwrap();
+            let entries = pull
+                .index
+                .iter()
+                .filter(|entry| entry.idx == reference_track)
+                .filter_map(|entry| {
+                    State::to_clock_time(entry.time, timescale)
+                        .map(|time| (time, entry.moof_offset))
+                })
+                .collect::<Vec<_>>();
+            let entry = entries
+                .iter()
+                .rev()
+                .find(|(time, _)| *time <= target)
+                .or_else(|| entries.first())
+                .copied();
+
+            let (time, moof_offset) = match entry {
+                Some(entry) => entry,
+                None => {
+                    gst_error!(CAT, obj: element, "No index entries for reference track");
+                    return false;
+                }
+            };
+
+            gst_debug!(
+                CAT,
+                obj: element,
+                "Seeking to {} via fragment at {} (offset {})",
+                target,
+                time,
+                moof_offset
+            );
+
+            (segment, time, moof_offset)
+        };
+
+        let flush_start = gst::event::FlushStart::builder()
+            .seqnum(seek_seqnum)
+            .build();
+        gst_debug!(CAT, obj: element, "Sending event {:?} upstream", flush_start);
+        self.sinkpad.push_event(flush_start.clone());
+        for pad in element.src_pads() {
+            pad.push_event(flush_start.clone());
+        }
+
+        let _ = self.sinkpad.pause_task();
+
+        let mut state = self.state.lock().unwrap();
+
+        if flags.contains(gst::SeekFlags::KEY_UNIT) {
+            segment.set_start(time);
+            segment.set_time(time);
+            segment.set_position(time);
+        }
+
+        self.flush(&mut state);
+        state.segment = segment;
+        state.offset = moof_offset;
+        state.seek_seqnum = Some(seek_seqnum);
+        drop(state);
+
+        let flush_stop = gst::event::FlushStop::builder(true)
+            .seqnum(seek_seqnum)
+            .build();
+        gst_debug!(CAT, obj: element, "Sending event {:?} upstream", flush_stop);
+        self.sinkpad.push_event(flush_stop.clone());
+        for pad in element.src_pads() {
+            pad.push_event(flush_stop.clone());
+        }
+
+        match self.start_task(element) {
+            Err(error) => {
+                error.log();
+                false
+            }
+            _ => true,
+        }
+    }
+
+    fn src_event(&self, pad: &gst::Pad, element: &super::FMP4Demux, event: gst::Event) -> bool {
+        use gst::EventView;
+
+        gst_log!(CAT, obj: pad, "Handling event {:?}", event);
+        match event.view() {
+            EventView::Seek(e) if self.state.lock().unwrap().pull.is_some() => {
+                self.perform_seek(&e, element)
+            }
+            _ => pad.event_default(Some(element), event),
+        }
+    }
+
+    fn src_query(
+        &self,
+        pad: &gst::Pad,
+        element: &super::FMP4Demux,
+        query: &mut gst::QueryRef,
+    ) -> bool {
+        use gst::QueryView;
+
+        gst_log!(CAT, obj: pad, "Handling query {:?}", query);
+
+        match query.view_mut() {
+            QueryView::Seeking(mut q) if q.format() == gst::Format::Time => {
+                let state = self.state.lock().unwrap();
+
+                // Only answer once it is known whether there is an index
+                if let Some(ref pull) = state.pull {
+                    if pull.index_loaded {
+                        q.set(
+                            !pull.index.is_empty(),
+                            gst::GenericFormattedValue::Time(Some(gst::ClockTime::ZERO)),
+                            gst::GenericFormattedValue::Time(state.duration),
+                        );
+                        return true;
+                    }
+                }
+            }
+            QueryView::Duration(ref mut q) if q.format() == gst::Format::Time => {
+                if let Some(duration) = self.state.lock().unwrap().duration {
+                    q.set(Some(duration));
+                    return true;
+                }
+            }
+            _ => (),
+        }
+
+        pad.query_default(Some(element), query)
+    }
+}
+
+#[glib::object_subclass]
+impl ObjectSubclass for FMP4Demux {
+    const NAME: &'static str = "GstFMP4Demux";
+    type Type = super::FMP4Demux;
+    type ParentType = gst::Element;
+
+    fn with_class(klass: &Self::Class) -> Self {
+        let templ = klass.pad_template("sink").unwrap();
+        let sinkpad = gst::Pad::builder_with_template(&templ, Some("sink"))
+            .activate_function(|pad, parent| {
+                FMP4Demux::catch_panic_pad_function(
+                    parent,
+                    || Err(gst::loggable_error!(CAT, "Panic activating sink pad")),
+                    |demux, element| demux.sink_activate(pad, element),
+                )
+            })
+            .activatemode_function(|pad, parent, mode, active| {
+                FMP4Demux::catch_panic_pad_function(
+                    parent,
+                    || {
+                        Err(gst::loggable_error!(
+                            CAT,
+                            "Panic activating sink pad with mode"
+                        ))
+                    },
+                    |demux, element| demux.sink_activatemode(pad, element, mode, active),
+                )
+            })
+            .chain_function(|pad, parent, buffer| {
+                FMP4Demux::catch_panic_pad_function(
+                    parent,
+                    || Err(gst::FlowError::Error),
+                    |demux, element| demux.sink_chain(pad, element, buffer),
+                )
+            })
+            .event_function(|pad, parent, event| {
+                FMP4Demux::catch_panic_pad_function(
+                    parent,
+                    || false,
+                    |demux, element| demux.sink_event(pad, element, event),
+                )
+            })
+            .build();
+
+        Self {
+            sinkpad,
+            state: Mutex::new(State::default()),
+            flow_combiner: Mutex::new(gst_base::UniqueFlowCombiner::new()),
+        }
+    }
+}
+
+impl ObjectImpl for FMP4Demux {
+    fn constructed(&self, obj: &Self::Type) {
+        self.parent_constructed(obj);
+
+        obj.add_pad(&self.sinkpad).unwrap();
+    }
+}
+
+impl GstObjectImpl for FMP4Demux {}
+
+impl ElementImpl for FMP4Demux {
+    fn metadata() -> Option<&'static gst::subclass::ElementMetadata> {
+        static ELEMENT_METADATA: Lazy<gst::subclass::ElementMetadata> = Lazy::new(|| {
+            gst::subclass::ElementMetadata::new(
+                "FMP4Demux",
+                "Codec/Demuxer",
+                "Fragmented MP4 demuxer",
+                "Sebastian Dröge <sebastian@centricular.com>",
+            )
+        });
+
+        Some(&*ELEMENT_METADATA)
+    }
+
+    fn pad_templates() -> &'static [gst::PadTemplate] {
+        static PAD_TEMPLATES: Lazy<Vec<gst::PadTemplate>> = Lazy::new(|| {
+            let sink_pad_template = gst::PadTemplate::new(
+                "sink",
+                gst::PadDirection::Sink,
+                gst::PadPresence::Always,
+                &gst::Caps::builder("video/quicktime").build(),
+            )
+            .unwrap();
+
+            let video_pad_template = gst::PadTemplate::new(
+                "video_%u",
+                gst::PadDirection::Src,
+                gst::PadPresence::Sometimes,
+                &[
+                    gst::Structure::builder("video/x-h264")
+                        .field("stream-format", gst::List::new(&[&"avc", &"avc3"]))
+                        .field("alignment", "au")
+                        .build(),
+                    gst::Structure::builder("video/x-h265")
+                        .field("stream-format", gst::List::new(&[&"hvc1", &"hev1"]))
+                        .field("alignment", "au")
+                        .build(),
+                    gst::Structure::builder("video/x-vp9").build(),
+                    gst::Structure::builder("video/x-av1")
+                        .field("stream-format", "obu-stream")
+                        .field("alignment", "tu")
+                        .build(),
+                ]
+                .into_iter()
+                .collect::<gst::Caps>(),
+            )
+            .unwrap();
+
+            let audio_pad_template = gst::PadTemplate::new(
+                "audio_%u",
+                gst::PadDirection::Src,
+                gst::PadPresence::Sometimes,
+                &[
+                    gst::Structure::builder("audio/mpeg")
+                        .field("mpegversion", 4i32)
+                        .field("stream-format", "raw")
+                        .build(),
+                    gst::Structure::builder("audio/x-opus").build(),
+                    gst::Structure::builder("audio/x-flac")
+                        .field("framed", true)
+                        .build(),
+                ]
+                .into_iter()
+                .collect::<gst::Caps>(),
+            )
+            .unwrap();
+
+            let subtitle_pad_template = gst::PadTemplate::new(
+                "subtitle_%u",
+                gst::PadDirection::Src,
+                gst::PadPresence::Sometimes,
+                &[
+                    gst::Structure::builder("text/x-raw")
+                        .field("format", "utf8")
+                        .build(),
+                    gst::Structure::builder("application/ttml+xml").build(),
+                ]
+                .into_iter()
+                .collect::<gst::Caps>(),
+            )
+            .unwrap();
+
+            vec![
+                sink_pad_template,
+                video_pad_template,
+                audio_pad_template,
+                subtitle_pad_template,
+            ]
+        });
+
+        PAD_TEMPLATES.as_ref()
+    }
+
+    fn change_state(
+        &self,
+        element: &Self::Type,
+        transition: gst::StateChange,
+    ) -> Result<gst::StateChangeSuccess, gst::StateChangeError> {
+        gst_trace!(CAT, obj: element, "Changing state {:?}", transition);
+
+        if transition == gst::StateChange::ReadyToPaused {
+            *self.state.lock().unwrap() = State::default();
+        }
+
+        let res = self.parent_change_state(element, transition)?;
+
+        if transition == gst::StateChange::PausedToReady {
+            let mut state = self.state.lock().unwrap();
+            let streams = std::mem::take(&mut state.streams);
+            *state = State::default();
+            drop(state);
+
+            let mut flow_combiner = self.flow_combiner.lock().unwrap();
+            for stream in streams {
+                flow_combiner.remove_pad(&stream.srcpad);
+                let _ = element.remove_pad(&stream.srcpad);
+            }
+            flow_combiner.reset();
+            drop(flow_combiner);
+
+            gst_info!(CAT, obj: element, "Removed all source pads");
+        }
+
+        Ok(res)
+    }
+}
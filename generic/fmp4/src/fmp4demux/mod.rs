@@ -0,0 +1,29 @@
+// Copyright (C) 2026 agent <agent@local>
+//
+// This Source Code Form is subject to the terms of the Mozilla Public License, v2.0.
+// If a copy of the MPL was not distributed with this file, You can obtain one at
+// <https://mozilla.org/MPL/2.0/>.
+//
+// SPDX-License-Identifier: MPL-2.0
+
+use gst::glib;
+use gst::prelude::*;
+
+mod boxes;
+mod imp;
+
+glib::wrapper! {
+    pub(crate) struct FMP4Demux(ObjectSubclass<imp::FMP4Demux>) @extends gst::Element, gst::Object;
+}
+
+unsafe impl Send for FMP4Demux {}
+unsafe impl Sync for FMP4Demux {}
+
+pub fn register(plugin: &gst::Plugin) -> Result<(), glib::BoolError> {
+    gst::Element::register(
+        Some(plugin),
+        "fmp4demux",
+        gst::Rank::Marginal,
+        FMP4Demux::static_type(),
+    )
+}
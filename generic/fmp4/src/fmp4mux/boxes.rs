@@ -176,6 +176,21 @@ fn cmaf_brands_from_caps(caps: &gst::CapsRef, compatible_brands: &mut Vec<&'stat
         "audio/mpeg" => {
             compatible_brands.push(b"caac");
         }
+        "audio/x-opus" => {
+            compatible_brands.push(b"opus");
+        }
+        "video/x-vp9" => {
+            compatible_brands.push(b"vp09");
+        }
+        "video/x-av1" => {
+            compatible_brands.push(b"av01");
+        }
+        "application/x-subtitle-vtt" | "application/x-subtitle-vtt-fragmented" => {
+            compatible_brands.push(b"cwvt");
+        }
+        "application/ttml+xml" => {
+            compatible_brands.push(b"im1t");
+        }
         "video/x-h265" => {
             let width = s.get::<i32>("width").ok();
             let height = s.get::<i32>("height").ok();
@@ -330,9 +345,9 @@ fn cmaf_brands_from_caps(caps: &gst::CapsRef, compatible_brands: &mut Vec<&'stat
     }
 }
 
-fn brands_from_variant_and_caps(
+fn brands_from_variant_and_caps<'a>(
     variant: super::Variant,
-    caps: &gst::CapsRef,
+    caps: impl IntoIterator<Item = &'a gst::Caps>,
 ) -> (&'static [u8; 4], Vec<&'static [u8; 4]>) {
     match variant {
         super::Variant::ISO => (b"iso6", vec![b"iso6"]),
@@ -343,7 +358,13 @@ fn brands_from_variant_and_caps(
         super::Variant::CMAF => {
             let mut compatible_brands = vec![b"iso6", b"cmfc"];
 
-            cmaf_brands_from_caps(caps, &mut compatible_brands);
+            for caps in caps {
+                cmaf_brands_from_caps(caps, &mut compatible_brands);
+            }
+
+            // Streams can add the same brands, only keep the first occurrence of each
+            let mut seen = std::collections::HashSet::new();
+            compatible_brands.retain(|brand| seen.insert(*brand));
 
             (b"cmf2", compatible_brands)
         }
@@ -354,7 +375,7 @@ fn brands_from_variant_and_caps(
 pub(super) fn create_fmp4_header(cfg: super::HeaderConfiguration) -> Result<gst::Buffer, Error> {
     let mut v = vec![];
 
-    let (brand, compatible_brands) = brands_from_variant_and_caps(cfg.variant, cfg.caps);
+    let (brand, compatible_brands) = brands_from_variant_and_caps(cfg.variant, cfg.streams);
 
     write_box(&mut v, b"ftyp", |v| {
         // major brand
@@ -383,15 +404,28 @@ fn write_moov(v: &mut Vec<u8>, cfg: &super::HeaderConfiguration) -> Result<(), E
     write_full_box(v, b"mvhd", FULL_BOX_VERSION_1, FULL_BOX_FLAGS_NONE, |v| {
         write_mvhd(v, cfg, creation_time)
     })?;
-    write_box(v, b"trak", |v| write_trak(v, cfg, creation_time))?;
+    for (idx, caps) in cfg.streams.iter().enumerate() {
+        write_box(v, b"trak", |v| write_trak(v, cfg, idx, caps, creation_time))?;
+    }
     write_box(v, b"mvex", |v| write_mvex(v, cfg))?;
 
+    // Protection system specific headers are passed through as-is
+    for pssh in cfg.pssh {
+        let map = pssh.map_readable().context("pssh not mappable")?;
+        v.extend_from_slice(&map);
+    }
+
     Ok(())
 }
 
 fn caps_to_timescale(caps: &gst::CapsRef) -> u32 {
     let s = caps.structure(0).unwrap();
 
+    // Opus is always decoded at 48kHz
+    if s.name() == "audio/x-opus" {
+        return 48_000;
+    }
+
     if let Ok(fps) = s.get::<gst::Fraction>("framerate") {
         if fps.numer() == 0 {
             return 10_000;
@@ -427,8 +461,8 @@ fn write_mvhd(
     v.extend(creation_time.to_be_bytes());
     // Modification time
     v.extend(creation_time.to_be_bytes());
-    // Timescale
-    v.extend(caps_to_timescale(cfg.caps).to_be_bytes());
+    // Timescale: uses the timescale of the first stream
+    v.extend(caps_to_timescale(&cfg.streams[0]).to_be_bytes());
     // Duration
     v.extend(0u64.to_be_bytes());
 
@@ -459,7 +493,7 @@ fn write_mvhd(
     v.extend([0u8; 6 * 4]);
 
     // Next track id
-    v.extend(2u32.to_be_bytes());
+    v.extend((cfg.streams.len() as u32 + 1).to_be_bytes());
 
     Ok(())
 }
@@ -471,6 +505,8 @@ const TKHD_FLAGS_TRACK_IN_PREVIEW: u32 = 0x4;
 fn write_trak(
     v: &mut Vec<u8>,
     cfg: &super::HeaderConfiguration,
+    idx: usize,
+    caps: &gst::CapsRef,
     creation_time: u64,
 ) -> Result<(), Error> {
     write_full_box(
@@ -478,20 +514,22 @@ fn write_trak(
         b"tkhd",
         FULL_BOX_VERSION_1,
         TKHD_FLAGS_TRACK_ENABLED | TKHD_FLAGS_TRACK_IN_MOVIE | TKHD_FLAGS_TRACK_IN_PREVIEW,
-        |v| write_tkhd(v, cfg, creation_time),
+        |v| write_tkhd(v, cfg, idx, caps, creation_time),
     )?;
 
     // TODO: write edts if necessary: for audio tracks to remove initialization samples
     // TODO: write edts optionally for negative DTS instead of offsetting the DTS
 
-    write_box(v, b"mdia", |v| write_mdia(v, cfg, creation_time))?;
+    write_box(v, b"mdia", |v| write_mdia(v, cfg, idx, caps, creation_time))?;
 
     Ok(())
 }
 
 fn write_tkhd(
     v: &mut Vec<u8>,
-    cfg: &super::HeaderConfiguration,
+    _cfg: &super::HeaderConfiguration,
+    idx: usize,
+    caps: &gst::CapsRef,
     creation_time: u64,
 ) -> Result<(), Error> {
     // Creation time
@@ -499,7 +537,7 @@ fn write_tkhd(
     // Modification time
     v.extend(creation_time.to_be_bytes());
     // Track ID
-    v.extend(1u32.to_be_bytes());
+    v.extend((idx as u32 + 1).to_be_bytes());
     // Reserved
     v.extend(0u32.to_be_bytes());
     // Duration
@@ -514,9 +552,9 @@ fn write_tkhd(
     v.extend(0u16.to_be_bytes());
 
     // Volume
-    let s = cfg.caps.structure(0).unwrap();
+    let s = caps.structure(0).unwrap();
     match s.name() {
-        "audio/mpeg" => v.extend((1u16 << 8).to_be_bytes()),
+        "audio/mpeg" | "audio/x-opus" | "audio/x-flac" => v.extend((1u16 << 8).to_be_bytes()),
         _ => v.extend(0u16.to_be_bytes()),
     }
 
@@ -541,7 +579,7 @@ fn write_tkhd(
 
     // Width/height
     match s.name() {
-        "video/x-h264" | "video/x-h265" => {
+        "video/x-h264" | "video/x-h265" | "video/x-vp9" | "video/x-av1" => {
             let width = s.get::<i32>("width").context("video caps without width")? as u32;
             let height = s
                 .get::<i32>("height")
@@ -570,18 +608,20 @@ fn write_tkhd(
 fn write_mdia(
     v: &mut Vec<u8>,
     cfg: &super::HeaderConfiguration,
+    idx: usize,
+    caps: &gst::CapsRef,
     creation_time: u64,
 ) -> Result<(), Error> {
     write_full_box(v, b"mdhd", FULL_BOX_VERSION_1, FULL_BOX_FLAGS_NONE, |v| {
-        write_mdhd(v, cfg, creation_time)
+        write_mdhd(v, cfg, caps, creation_time)
     })?;
     write_full_box(v, b"hdlr", FULL_BOX_VERSION_0, FULL_BOX_FLAGS_NONE, |v| {
-        write_hdlr(v, cfg)
+        write_hdlr(v, cfg, caps)
     })?;
 
     // TODO: write elng if needed
 
-    write_box(v, b"minf", |v| write_minf(v, cfg))?;
+    write_box(v, b"minf", |v| write_minf(v, cfg, idx, caps))?;
 
     Ok(())
 }
@@ -599,7 +639,8 @@ fn language_code(lang: impl std::borrow::Borrow<[u8; 3]>) -> u16 {
 
 fn write_mdhd(
     v: &mut Vec<u8>,
-    cfg: &super::HeaderConfiguration,
+    _cfg: &super::HeaderConfiguration,
+    caps: &gst::CapsRef,
     creation_time: u64,
 ) -> Result<(), Error> {
     // Creation time
@@ -607,7 +648,7 @@ fn write_mdhd(
     // Modification time
     v.extend(creation_time.to_be_bytes());
     // Timescale
-    v.extend(caps_to_timescale(cfg.caps).to_be_bytes());
+    v.extend(caps_to_timescale(caps).to_be_bytes());
     // Duration
     v.extend(0u64.to_be_bytes());
 
@@ -621,14 +662,24 @@ fn write_mdhd(
     Ok(())
 }
 
-fn write_hdlr(v: &mut Vec<u8>, cfg: &super::HeaderConfiguration) -> Result<(), Error> {
+fn write_hdlr(
+    v: &mut Vec<u8>,
+    _cfg: &super::HeaderConfiguration,
+    caps: &gst::CapsRef,
+) -> Result<(), Error> {
     // Pre-defined
     v.extend([0u8; 4]);
 
-    let s = cfg.caps.structure(0).unwrap();
+    let s = caps.structure(0).unwrap();
     let (handler_type, name) = match s.name() {
-        "video/x-h264" | "video/x-h265" => (b"vide", b"VideoHandler\0"),
-        "audio/mpeg" => (b"soun", b"SoundHandler\0"),
+        "video/x-h264" | "video/x-h265" | "video/x-vp9" | "video/x-av1" => {
+            (b"vide", b"VideoHandler\0")
+        }
+        "audio/mpeg" | "audio/x-opus" | "audio/x-flac" => (b"soun", b"SoundHandler\0"),
+        "application/x-subtitle-vtt" | "application/x-subtitle-vtt-fragmented" => {
+            (b"text", b"TextHandler\0")
+        }
+        "application/ttml+xml" => (b"subt", b"SubtitleHandler\0"),
         _ => unreachable!(),
     };
 
@@ -644,23 +695,42 @@ fn write_hdlr(v: &mut Vec<u8>, cfg: &super::HeaderConfiguration) -> Result<(), E
     Ok(())
 }
 
-fn write_minf(v: &mut Vec<u8>, cfg: &super::HeaderConfiguration) -> Result<(), Error> {
-    let s = cfg.caps.structure(0).unwrap();
+fn write_minf(
+    v: &mut Vec<u8>,
+    cfg: &super::HeaderConfiguration,
+    idx: usize,
+    caps: &gst::CapsRef,
+) -> Result<(), Error> {
+    let s = caps.structure(0).unwrap();
 
     match s.name() {
-        "video/x-h264" | "video/x-h265" => {
+        "video/x-h264" | "video/x-h265" | "video/x-vp9" | "video/x-av1" => {
             // Flags are always 1 for unspecified reasons
             write_full_box(v, b"vmhd", FULL_BOX_VERSION_0, 1, |v| write_vmhd(v, cfg))?
         }
-        "audio/mpeg" => write_full_box(v, b"smhd", FULL_BOX_VERSION_0, FULL_BOX_FLAGS_NONE, |v| {
-            write_smhd(v, cfg)
-        })?,
+        "audio/mpeg" | "audio/x-opus" | "audio/x-flac" => {
+            write_full_box(v, b"smhd", FULL_BOX_VERSION_0, FULL_BOX_FLAGS_NONE, |v| {
+                write_smhd(v, cfg)
+            })?
+        }
+        "application/x-subtitle-vtt" | "application/x-subtitle-vtt-fragmented" => {
+            // Null media header without any content
+            write_full_box(v, b"nmhd", FULL_BOX_VERSION_0, FULL_BOX_FLAGS_NONE, |_v| {
+                Ok(())
+            })?
+        }
+        "application/ttml+xml" => {
+            // Subtitle media header without any content
+            write_full_box(v, b"sthd", FULL_BOX_VERSION_0, FULL_BOX_FLAGS_NONE, |_v| {
+                Ok(())
+            })?
+        }
         _ => unreachable!(),
     }
 
     write_box(v, b"dinf", |v| write_dinf(v, cfg))?;
 
-    write_box(v, b"stbl", |v| write_stbl(v, cfg))?;
+    write_box(v, b"stbl", |v| write_stbl(v, cfg, idx, caps))?;
 
     Ok(())
 }
@@ -710,9 +780,14 @@ fn write_dref(v: &mut Vec<u8>, _cfg: &super::HeaderConfiguration) -> Result<(),
     Ok(())
 }
 
-fn write_stbl(v: &mut Vec<u8>, cfg: &super::HeaderConfiguration) -> Result<(), Error> {
+fn write_stbl(
+    v: &mut Vec<u8>,
+    cfg: &super::HeaderConfiguration,
+    idx: usize,
+    caps: &gst::CapsRef,
+) -> Result<(), Error> {
     write_full_box(v, b"stsd", FULL_BOX_VERSION_0, FULL_BOX_FLAGS_NONE, |v| {
-        write_stsd(v, cfg)
+        write_stsd(v, cfg, idx, caps)
     })?;
     write_full_box(v, b"stts", FULL_BOX_VERSION_0, FULL_BOX_FLAGS_NONE, |v| {
         write_stts(v, cfg)
@@ -729,9 +804,9 @@ fn write_stbl(v: &mut Vec<u8>, cfg: &super::HeaderConfiguration) -> Result<(), E
     })?;
 
     // For video write a sync sample box as indication that not all samples are sync samples
-    let s = cfg.caps.structure(0).unwrap();
+    let s = caps.structure(0).unwrap();
     match s.name() {
-        "video/x-h264" | "video/x-h265" => {
+        "video/x-h264" | "video/x-h265" | "video/x-vp9" | "video/x-av1" => {
             write_full_box(v, b"stss", FULL_BOX_VERSION_0, FULL_BOX_FLAGS_NONE, |v| {
                 write_stss(v, cfg)
             })?
@@ -742,14 +817,28 @@ fn write_stbl(v: &mut Vec<u8>, cfg: &super::HeaderConfiguration) -> Result<(), E
     Ok(())
 }
 
-fn write_stsd(v: &mut Vec<u8>, cfg: &super::HeaderConfiguration) -> Result<(), Error> {
+fn write_stsd(
+    v: &mut Vec<u8>,
+    cfg: &super::HeaderConfiguration,
+    idx: usize,
+    caps: &gst::CapsRef,
+) -> Result<(), Error> {
     // Entry count
     v.extend(1u32.to_be_bytes());
 
-    let s = cfg.caps.structure(0).unwrap();
+    let encryption = cfg.encryption.get(idx).and_then(Option::as_ref);
+
+    let s = caps.structure(0).unwrap();
     match s.name() {
-        "video/x-h264" | "video/x-h265" => write_visual_sample_entry(v, cfg)?,
-        "audio/mpeg" => write_audio_sample_entry(v, cfg)?,
+        "video/x-h264" | "video/x-h265" | "video/x-vp9" | "video/x-av1" => {
+            write_visual_sample_entry(v, cfg, caps, encryption)?
+        }
+        "audio/mpeg" | "audio/x-opus" | "audio/x-flac" => {
+            write_audio_sample_entry(v, cfg, caps, encryption)?
+        }
+        "application/x-subtitle-vtt"
+        | "application/x-subtitle-vtt-fragmented"
+        | "application/ttml+xml" => write_text_sample_entry(v, cfg, caps)?,
         _ => unreachable!(),
     }
 
@@ -774,9 +863,11 @@ fn write_sample_entry_box<T, F: FnOnce(&mut Vec<u8>) -> Result<T, Error>>(
 
 fn write_visual_sample_entry(
     v: &mut Vec<u8>,
-    cfg: &super::HeaderConfiguration,
+    _cfg: &super::HeaderConfiguration,
+    caps: &gst::CapsRef,
+    encryption: Option<&super::TrackEncryption>,
 ) -> Result<(), Error> {
-    let s = cfg.caps.structure(0).unwrap();
+    let s = caps.structure(0).unwrap();
     let fourcc = match s.name() {
         "video/x-h264" => {
             let stream_format = s.get::<&str>("stream-format").context("no stream-format")?;
@@ -794,10 +885,19 @@ fn write_visual_sample_entry(
                 _ => unreachable!(),
             }
         }
+        "video/x-vp9" => b"vp09",
+        "video/x-av1" => b"av01",
         _ => unreachable!(),
     };
 
-    write_sample_entry_box(v, fourcc, move |v| {
+    // Encrypted samples use a generic sample entry and the original one is stored in the `sinf`
+    let sample_entry_fourcc = if encryption.is_some() {
+        b"encv"
+    } else {
+        fourcc
+    };
+
+    write_sample_entry_box(v, sample_entry_fourcc, move |v| {
         // pre-defined
         v.extend([0u8; 2]);
         // Reserved
@@ -862,6 +962,14 @@ fn write_visual_sample_entry(
                     Ok(())
                 })?;
             }
+            "video/x-vp9" => {
+                write_full_box(v, b"vpcC", 1, FULL_BOX_FLAGS_NONE, move |v| {
+                    write_vpcc(v, s)
+                })?;
+            }
+            "video/x-av1" => {
+                write_box(v, b"av1C", move |v| write_av1c(v, s))?;
+            }
             _ => unreachable!(),
         }
 
@@ -880,52 +988,7 @@ fn write_visual_sample_entry(
         {
             write_box(v, b"colr", move |v| {
                 v.extend(b"nclx");
-                let (primaries, transfer, matrix) = {
-                    #[cfg(feature = "v1_18")]
-                    {
-                        (
-                            (colorimetry.primaries().to_iso() as u16),
-                            (colorimetry.transfer().to_iso() as u16),
-                            (colorimetry.matrix().to_iso() as u16),
-                        )
-                    }
-                    #[cfg(not(feature = "v1_18"))]
-                    {
-                        let primaries = match colorimetry.primaries() {
-                            gst_video::VideoColorPrimaries::Bt709 => 1u16,
-                            gst_video::VideoColorPrimaries::Bt470m => 4u16,
-                            gst_video::VideoColorPrimaries::Bt470bg => 5u16,
-                            gst_video::VideoColorPrimaries::Smpte170m => 6u16,
-                            gst_video::VideoColorPrimaries::Smpte240m => 7u16,
-                            gst_video::VideoColorPrimaries::Film => 8u16,
-                            gst_video::VideoColorPrimaries::Bt2020 => 9u16,
-                            _ => 2,
-                        };
-                        let transfer = match colorimetry.transfer() {
-                            gst_video::VideoTransferFunction::Bt709 => 1u16,
-                            gst_video::VideoTransferFunction::Gamma22 => 4u16,
-                            gst_video::VideoTransferFunction::Gamma28 => 5u16,
-                            gst_video::VideoTransferFunction::Smpte240m => 7u16,
-                            gst_video::VideoTransferFunction::Gamma10 => 8u16,
-                            gst_video::VideoTransferFunction::Log100 => 9u16,
-                            gst_video::VideoTransferFunction::Log316 => 10u16,
-                            gst_video::VideoTransferFunction::Srgb => 13u16,
-                            gst_video::VideoTransferFunction::Bt202012 => 15u16,
-                            _ => 2,
-                        };
-                        let matrix = match colorimetry.matrix() {
-                            gst_video::VideoColorMatrix::Rgb => 0u16,
-                            gst_video::VideoColorMatrix::Bt709 => 1u16,
-                            gst_video::VideoColorMatrix::Fcc => 4u16,
-                            gst_video::VideoColorMatrix::Bt601 => 6u16,
-                            gst_video::VideoColorMatrix::Smpte240m => 7u16,
-                            gst_video::VideoColorMatrix::Bt2020 => 9u16,
-                            _ => 2,
-                        };
-
-                        (primaries, transfer, matrix)
-                    }
-                };
+                let (primaries, transfer, matrix) = colorimetry_to_iso(&colorimetry);
 
                 let full_range = match colorimetry.range() {
                     gst_video::VideoColorRange::Range0_255 => 0x80u8,
@@ -944,7 +1007,7 @@ fn write_visual_sample_entry(
 
         #[cfg(feature = "v1_18")]
         {
-            if let Ok(cll) = gst_video::VideoContentLightLevel::from_caps(cfg.caps) {
+            if let Ok(cll) = gst_video::VideoContentLightLevel::from_caps(caps) {
                 write_box(v, b"clli", move |v| {
                     v.extend((cll.max_content_light_level() as u16).to_be_bytes());
                     v.extend((cll.max_frame_average_light_level() as u16).to_be_bytes());
@@ -952,7 +1015,7 @@ fn write_visual_sample_entry(
                 })?;
             }
 
-            if let Ok(mastering) = gst_video::VideoMasteringDisplayInfo::from_caps(cfg.caps) {
+            if let Ok(mastering) = gst_video::VideoMasteringDisplayInfo::from_caps(caps) {
                 write_box(v, b"mdcv", move |v| {
                     for primary in mastering.display_primaries() {
                         v.extend(primary.x.to_be_bytes());
@@ -969,23 +1032,256 @@ fn write_visual_sample_entry(
 
         // TODO: write btrt bitrate box based on tags
 
+        if let Some(encryption) = encryption {
+            write_box(v, b"sinf", move |v| write_sinf(v, fourcc, encryption))?;
+        }
+
         Ok(())
     })?;
 
     Ok(())
 }
 
+/// Converts the colorimetry to the ISO/IEC 23091-4 colour primaries, transfer characteristics and
+/// matrix coefficients.
+fn colorimetry_to_iso(colorimetry: &gst_video::VideoColorimetry) -> (u16, u16, u16) {
+    #[cfg(feature = "v1_18")]
+    {
+        (
+            (colorimetry.primaries().to_iso() as u16),
+            (colorimetry.transfer().to_iso() as u16),
+            (colorimetry.matrix().to_iso() as u16),
+        )
+    }
+    #[cfg(not(feature = "v1_18"))]
+    {
+        let primaries = match colorimetry.primaries() {
+            gst_video::VideoColorPrimaries::Bt709 => 1u16,
+            gst_video::VideoColorPrimaries::Bt470m => 4u16,
+            gst_video::VideoColorPrimaries::Bt470bg => 5u16,
+            gst_video::VideoColorPrimaries::Smpte170m => 6u16,
+            gst_video::VideoColorPrimaries::Smpte240m => 7u16,
+            gst_video::VideoColorPrimaries::Film => 8u16,
+            gst_video::VideoColorPrimaries::Bt2020 => 9u16,
+            _ => 2,
+        };
+        let transfer = match colorimetry.transfer() {
+            gst_video::VideoTransferFunction::Bt709 => 1u16,
+            gst_video::VideoTransferFunction::Gamma22 => 4u16,
+            gst_video::VideoTransferFunction::Gamma28 => 5u16,
+            gst_video::VideoTransferFunction::Smpte240m => 7u16,
+            gst_video::VideoTransferFunction::Gamma10 => 8u16,
+            gst_video::VideoTransferFunction::Log100 => 9u16,
+            gst_video::VideoTransferFunction::Log316 => 10u16,
+            gst_video::VideoTransferFunction::Srgb => 13u16,
+            gst_video::VideoTransferFunction::Bt202012 => 15u16,
+            _ => 2,
+        };
+        let matrix = match colorimetry.matrix() {
+            gst_video::VideoColorMatrix::Rgb => 0u16,
+            gst_video::VideoColorMatrix::Bt709 => 1u16,
+            gst_video::VideoColorMatrix::Fcc => 4u16,
+            gst_video::VideoColorMatrix::Bt601 => 6u16,
+            gst_video::VideoColorMatrix::Smpte240m => 7u16,
+            gst_video::VideoColorMatrix::Bt2020 => 9u16,
+            _ => 2,
+        };
+
+        (primaries, transfer, matrix)
+    }
+}
+
+fn write_vpcc(v: &mut Vec<u8>, s: &gst::StructureRef) -> Result<(), Error> {
+    let profile = match s.get::<&str>("profile").context("no profile")? {
+        "0" => 0u8,
+        "1" => 1,
+        "2" => 2,
+        "3" => 3,
+        profile => bail!("unsupported profile {}", profile),
+    };
+    v.push(profile);
+
+    // Level is not signalled in the caps so derive it from the resolution and framerate
+    let width = s.get::<i32>("width").context("no width")? as u64;
+    let height = s.get::<i32>("height").context("no height")? as u64;
+    let fps = s
+        .get::<gst::Fraction>("framerate")
+        .ok()
+        .filter(|fps| fps.numer() > 0 && fps.denom() > 0)
+        .unwrap_or_else(|| gst::Fraction::new(30, 1));
+    let picture_size = width * height;
+    let sample_rate = picture_size
+        .mul_div_ceil(fps.numer() as u64, fps.denom() as u64)
+        .context("too big sample rate")?;
+
+    // (level, max luma sample rate, max luma picture size)
+    const LEVELS: [(u8, u64, u64); 14] = [
+        (10, 829_440, 36_864),
+        (11, 2_764_800, 73_728),
+        (20, 4_608_000, 122_880),
+        (21, 9_216_000, 245_760),
+        (30, 20_736_000, 552_960),
+        (31, 36_864_000, 983_040),
+        (40, 83_558_400, 2_228_224),
+        (41, 160_432_128, 2_228_224),
+        (50, 311_951_360, 8_912_896),
+        (51, 588_251_136, 8_912_896),
+        (52, 1_176_502_272, 8_912_896),
+        (60, 1_176_502_272, 35_651_584),
+        (61, 2_353_004_544, 35_651_584),
+        (62, 4_706_009_088, 35_651_584),
+    ];
+    let level = LEVELS
+        .iter()
+        .find(|(_, max_sample_rate, max_picture_size)| {
+            sample_rate <= *max_sample_rate && picture_size <= *max_picture_size
+        })
+        .map(|(level, _, _)| *level)
+        .unwrap_or(62);
+    v.push(level);
+
+    let bit_depth = s
+        .get::<u32>("bit-depth-luma")
+        .context("no bit-depth-luma")?;
+    if ![8, 10, 12].contains(&bit_depth) {
+        bail!("unsupported bit depth {}", bit_depth);
+    }
+
+    let chroma_subsampling = match s.get::<&str>("chroma-format").context("no chroma-format")? {
+        "4:2:0" => match s.get::<&str>("chroma-site").ok() {
+            // Left-sited chroma
+            Some("mpeg2") | Some("h-cosited") => 0u8,
+            // Co-located with the top-left luma sample
+            _ => 1,
+        },
+        "4:2:2" => 2,
+        "4:4:4" => 3,
+        chroma_format => bail!("unsupported chroma format {}", chroma_format),
+    };
+
+    let colorimetry = s
+        .get::<&str>("colorimetry")
+        .ok()
+        .and_then(|c| c.parse::<gst_video::VideoColorimetry>().ok());
+
+    let full_range = colorimetry.as_ref().map_or(false, |colorimetry| {
+        colorimetry.range() == gst_video::VideoColorRange::Range0_255
+    });
+
+    v.push(((bit_depth as u8) << 4) | (chroma_subsampling << 1) | (full_range as u8));
+
+    let (primaries, transfer, matrix) = colorimetry
+        .as_ref()
+        .map(colorimetry_to_iso)
+        .unwrap_or((2, 2, 2));
+    v.push(primaries as u8);
+    v.push(transfer as u8);
+    v.push(matrix as u8);
+
+    // Codec initialization data size, must be 0 for VP9
+    v.extend(0u16.to_be_bytes());
+
+    Ok(())
+}
+
+fn write_av1c(v: &mut Vec<u8>, s: &gst::StructureRef) -> Result<(), Error> {
+    // If upstream provides the configuration record then use it as is
+    if let Ok(codec_data) = s.get::<&gst::BufferRef>("codec_data") {
+        let map = codec_data
+            .map_readable()
+            .context("codec_data not mappable")?;
+
+        if map.len() < 4 || map[0] != 0x81 {
+            bail!("invalid codec_data");
+        }
+
+        v.extend_from_slice(&map);
+
+        return Ok(());
+    }
+
+    // Otherwise create one from the caps without any configuration OBUs
+
+    // Marker and version
+    v.push(0x81);
+
+    let profile = match s.get::<&str>("profile").ok() {
+        Some("main") | None => 0u8,
+        Some("high") => 1,
+        Some("professional") => 2,
+        Some(profile) => bail!("unsupported profile {}", profile),
+    };
+
+    // Sequence level index, 31 is "maximum parameters"
+    let level = s
+        .get::<&str>("level")
+        .ok()
+        .and_then(|level| level.split_once('.'))
+        .and_then(|(major, minor)| Some((major.parse::<u8>().ok()?, minor.parse::<u8>().ok()?)))
+        .filter(|(major, minor)| (2..=7).contains(major) && *minor <= 3)
+        .map(|(major, minor)| ((major - 2) << 2) | minor)
+        .unwrap_or(31);
+    v.push((profile << 5) | level);
+
+    let tier = matches!(s.get::<&str>("tier").ok(), Some("high")) as u8;
+    let bit_depth = s.get::<u32>("bit-depth-luma").unwrap_or(8);
+    let (high_bitdepth, twelve_bit) = match bit_depth {
+        8 => (0u8, 0u8),
+        10 => (1, 0),
+        12 => (1, 1),
+        _ => bail!("unsupported bit depth {}", bit_depth),
+    };
+    let (monochrome, subsampling_x, subsampling_y) =
+        match s.get::<&str>("chroma-format").unwrap_or("4:2:0") {
+            "4:0:0" => (1u8, 1u8, 1u8),
+            "4:2:0" => (0, 1, 1),
+            "4:2:2" => (0, 1, 0),
+            "4:4:4" => (0, 0, 0),
+            chroma_format => bail!("unsupported chroma format {}", chroma_format),
+        };
+    let chroma_sample_position = match s.get::<&str>("chroma-site").ok() {
+        Some("v-cosited") => 1u8,
+        Some("cosited") | Some("dv") => 2,
+        _ => 0,
+    };
+    v.push(
+        (tier << 7)
+            | (high_bitdepth << 6)
+            | (twelve_bit << 5)
+            | (monochrome << 4)
+            | (subsampling_x << 3)
+            | (subsampling_y << 2)
+            | chroma_sample_position,
+    );
+
+    // No initial presentation delay
+    v.push(0);
+
+    Ok(())
+}
+
 fn write_audio_sample_entry(
     v: &mut Vec<u8>,
-    cfg: &super::HeaderConfiguration,
+    _cfg: &super::HeaderConfiguration,
+    caps: &gst::CapsRef,
+    encryption: Option<&super::TrackEncryption>,
 ) -> Result<(), Error> {
-    let s = cfg.caps.structure(0).unwrap();
+    let s = caps.structure(0).unwrap();
     let fourcc = match s.name() {
         "audio/mpeg" => b"mp4a",
+        "audio/x-opus" => b"Opus",
+        "audio/x-flac" => b"fLaC",
         _ => unreachable!(),
     };
 
-    write_sample_entry_box(v, fourcc, move |v| {
+    // Encrypted samples use a generic sample entry and the original one is stored in the `sinf`
+    let sample_entry_fourcc = if encryption.is_some() {
+        b"enca"
+    } else {
+        fourcc
+    };
+
+    write_sample_entry_box(v, sample_entry_fourcc, move |v| {
         // Reserved
         v.extend([0u8; 2 * 4]);
 
@@ -1003,8 +1299,12 @@ fn write_audio_sample_entry(
         // Reserved
         v.extend([0u8; 2]);
 
-        // Sample rate
-        let rate = u16::try_from(s.get::<i32>("rate").context("no rate")?).unwrap_or(0);
+        // Sample rate, Opus is always decoded at 48kHz
+        let rate = if s.name() == "audio/x-opus" {
+            48_000
+        } else {
+            u16::try_from(s.get::<i32>("rate").context("no rate")?).unwrap_or(0)
+        };
         v.extend((u32::from(rate) << 16).to_be_bytes());
 
         // Codec specific boxes
@@ -1021,6 +1321,18 @@ fn write_audio_sample_entry(
                 }
                 write_esds_aac(v, &map)?;
             }
+            "audio/x-opus" => {
+                write_box(v, b"dOps", move |v| write_dops(v, s))?;
+            }
+            "audio/x-flac" => {
+                write_full_box(
+                    v,
+                    b"dfLa",
+                    FULL_BOX_VERSION_0,
+                    FULL_BOX_FLAGS_NONE,
+                    move |v| write_dfla(v, s),
+                )?;
+            }
             _ => unreachable!(),
         }
 
@@ -1044,12 +1356,238 @@ fn write_audio_sample_entry(
 
         // TODO: chnl box for channel ordering? probably not needed for AAC
 
+        if let Some(encryption) = encryption {
+            write_box(v, b"sinf", move |v| write_sinf(v, fourcc, encryption))?;
+        }
+
         Ok(())
     })?;
 
     Ok(())
 }
 
+fn write_sinf(
+    v: &mut Vec<u8>,
+    original_fourcc: &[u8; 4],
+    encryption: &super::TrackEncryption,
+) -> Result<(), Error> {
+    write_box(v, b"frma", |v| {
+        v.extend(original_fourcc);
+        Ok(())
+    })?;
+
+    write_full_box(v, b"schm", FULL_BOX_VERSION_0, FULL_BOX_FLAGS_NONE, |v| {
+        // Scheme type
+        match encryption.scheme {
+            super::EncryptionScheme::Cenc => v.extend(b"cenc"),
+            super::EncryptionScheme::Cbcs => v.extend(b"cbcs"),
+            super::EncryptionScheme::None => unreachable!(),
+        }
+        // Scheme version 1.0
+        v.extend(0x0001_0000u32.to_be_bytes());
+        Ok(())
+    })?;
+
+    write_box(v, b"schi", |v| {
+        // Version 1 is required for pattern encryption
+        let version = if encryption.crypt_byte_block != 0 || encryption.skip_byte_block != 0 {
+            FULL_BOX_VERSION_1
+        } else {
+            FULL_BOX_VERSION_0
+        };
+
+        write_full_box(v, b"tenc", version, FULL_BOX_FLAGS_NONE, |v| {
+            write_tenc(v, version, encryption)
+        })
+    })?;
+
+    Ok(())
+}
+
+fn write_tenc(
+    v: &mut Vec<u8>,
+    version: u8,
+    encryption: &super::TrackEncryption,
+) -> Result<(), Error> {
+    // Reserved
+    v.push(0);
+
+    if version == FULL_BOX_VERSION_0 {
+        // Reserved
+        v.push(0);
+    } else {
+        v.push((encryption.crypt_byte_block << 4) | (encryption.skip_byte_block & 0x0f));
+    }
+
+    // Default is protected
+    v.push(1);
+
+    // Default per-sample IV size
+    v.push(encryption.per_sample_iv_size);
+
+    // Default key ID
+    v.extend(encryption.key_id);
+
+    if encryption.per_sample_iv_size == 0 {
+        let constant_iv = encryption.constant_iv.context("no constant IV")?;
+        v.push(constant_iv.len() as u8);
+        v.extend(constant_iv);
+    }
+
+    Ok(())
+}
+
+fn write_dops(v: &mut Vec<u8>, s: &gst::StructureRef) -> Result<(), Error> {
+    // Use the OpusHead from the streamheader if available, otherwise the fields of the caps
+    let header = s.get::<gst::ArrayRef>("streamheader").ok().and_then(|a| {
+        a.as_slice()
+            .first()
+            .and_then(|v| v.get::<gst::Buffer>().ok())
+    });
+
+    let (channels, pre_skip, rate, output_gain, mapping_family, mapping) = if let Some(header) =
+        header
+    {
+        let map = header.map_readable().context("streamheader not mappable")?;
+        if map.len() < 19 || &map[0..8] != b"OpusHead" {
+            bail!("invalid OpusHead");
+        }
+
+        let channels = map[9];
+        let pre_skip = u16::from_le_bytes([map[10], map[11]]);
+        let rate = u32::from_le_bytes([map[12], map[13], map[14], map[15]]);
+        let output_gain = i16::from_le_bytes([map[16], map[17]]);
+        let mapping_family = map[18];
+
+        let mapping = if mapping_family != 0 {
+            if map.len() < 21 + channels as usize {
+                bail!("too short OpusHead");
+            }
+            Some((map[19], map[20], map[21..][..channels as usize].to_vec()))
+        } else {
+            None
+        };
+
+        (
+            channels,
+            pre_skip,
+            rate,
+            output_gain,
+            mapping_family,
+            mapping,
+        )
+    } else {
+        let channels = u8::try_from(s.get::<i32>("channels").context("no channels")?)
+            .context("too many channels")?;
+        let rate = s.get::<i32>("rate").context("no rate")? as u32;
+        let mapping_family = s.get::<i32>("channel-mapping-family").unwrap_or(0) as u8;
+
+        let mapping = if mapping_family != 0 {
+            let stream_count = s.get::<i32>("stream-count").context("no stream-count")? as u8;
+            let coupled_count = s.get::<i32>("coupled-count").context("no coupled-count")? as u8;
+            let channel_mapping = s
+                .get::<gst::ArrayRef>("channel-mapping")
+                .context("no channel-mapping")?
+                .as_slice()
+                .iter()
+                .map(|v| v.get::<i32>().map(|v| v as u8))
+                .collect::<Result<Vec<_>, _>>()
+                .context("invalid channel-mapping")?;
+            if channel_mapping.len() != channels as usize {
+                bail!("invalid channel-mapping");
+            }
+
+            Some((stream_count, coupled_count, channel_mapping))
+        } else {
+            None
+        };
+
+        (channels, 0, rate, 0, mapping_family, mapping)
+    };
+
+    // Version
+    v.push(0);
+    v.push(channels);
+    v.extend(pre_skip.to_be_bytes());
+    v.extend(rate.to_be_bytes());
+    v.extend(output_gain.to_be_bytes());
+    v.push(mapping_family);
+
+    if let Some((stream_count, coupled_count, channel_mapping)) = mapping {
+        v.push(stream_count);
+        v.push(coupled_count);
+        v.extend_from_slice(&channel_mapping);
+    }
+
+    Ok(())
+}
+
+fn write_dfla(v: &mut Vec<u8>, s: &gst::StructureRef) -> Result<(), Error> {
+    // The first streamheader contains the FLAC mapping header, the fLaC marker and the
+    // STREAMINFO metadata block
+    let header = s
+        .get::<gst::ArrayRef>("streamheader")
+        .context("no streamheader")?
+        .as_slice()
+        .first()
+        .and_then(|v| v.get::<gst::Buffer>().ok())
+        .context("no streamheader")?;
+    let map = header.map_readable().context("streamheader not mappable")?;
+
+    if map.len() < 13 + 4 + 34
+        || map[0..5] != [0x7f, b'F', b'L', b'A', b'C']
+        || &map[9..13] != b"fLaC"
+    {
+        bail!("invalid FLAC streamheader");
+    }
+
+    // Only write the STREAMINFO metadata block and mark it as the last one
+    let streaminfo = &map[13..][..4 + 34];
+    if streaminfo[0] & 0x7f != 0 {
+        bail!("no STREAMINFO metadata block");
+    }
+    v.push(0x80);
+    v.extend_from_slice(&streaminfo[1..]);
+
+    Ok(())
+}
+
+fn write_text_sample_entry(
+    v: &mut Vec<u8>,
+    _cfg: &super::HeaderConfiguration,
+    caps: &gst::CapsRef,
+) -> Result<(), Error> {
+    let s = caps.structure(0).unwrap();
+    match s.name() {
+        "application/x-subtitle-vtt" | "application/x-subtitle-vtt-fragmented" => {
+            write_sample_entry_box(v, b"wvtt", move |v| {
+                // WebVTT configuration, i.e. the text of the WebVTT file header
+                write_box(v, b"vttC", move |v| {
+                    v.extend(b"WEBVTT");
+                    Ok(())
+                })?;
+
+                Ok(())
+            })?;
+        }
+        "application/ttml+xml" => {
+            write_sample_entry_box(v, b"stpp", move |v| {
+                // Namespace
+                v.extend(b"http://www.w3.org/ns/ttml\0");
+                // Schema location
+                v.push(0);
+                // Auxiliary MIME types
+                v.push(0);
+
+                Ok(())
+            })?;
+        }
+        _ => unreachable!(),
+    }
+
+    Ok(())
+}
+
 fn write_esds_aac(v: &mut Vec<u8>, codec_data: &[u8]) -> Result<(), Error> {
     let calculate_len = |mut len| {
         if len > 260144641 {
@@ -1209,15 +1747,18 @@ fn write_mvex(v: &mut Vec<u8>, cfg: &super::HeaderConfiguration) -> Result<(), E
         }
     }
 
-    write_full_box(v, b"trex", FULL_BOX_VERSION_0, FULL_BOX_FLAGS_NONE, |v| {
-        write_trex(v, cfg)
-    })?;
+    for (idx, _caps) in cfg.streams.iter().enumerate() {
+        write_full_box(v, b"trex", FULL_BOX_VERSION_0, FULL_BOX_FLAGS_NONE, |v| {
+            write_trex(v, cfg, idx)
+        })?;
+    }
 
     Ok(())
 }
 
 fn write_mehd(v: &mut Vec<u8>, cfg: &super::HeaderConfiguration) -> Result<(), Error> {
-    let timescale = caps_to_timescale(cfg.caps);
+    // Use the reference track timescale
+    let timescale = caps_to_timescale(&cfg.streams[0]);
 
     let duration = cfg
         .duration
@@ -1231,9 +1772,9 @@ fn write_mehd(v: &mut Vec<u8>, cfg: &super::HeaderConfiguration) -> Result<(), E
     Ok(())
 }
 
-fn write_trex(v: &mut Vec<u8>, _cfg: &super::HeaderConfiguration) -> Result<(), Error> {
+fn write_trex(v: &mut Vec<u8>, _cfg: &super::HeaderConfiguration, idx: usize) -> Result<(), Error> {
     // Track ID
-    v.extend(1u32.to_be_bytes());
+    v.extend((idx as u32 + 1).to_be_bytes());
 
     // Default sample description index
     v.extend(1u32.to_be_bytes());
@@ -1259,7 +1800,8 @@ pub(super) fn create_fmp4_fragment_header(
 ) -> Result<(gst::Buffer, u64), Error> {
     let mut v = vec![];
 
-    let (brand, compatible_brands) = brands_from_variant_and_caps(cfg.variant, cfg.caps);
+    let (brand, compatible_brands) =
+        brands_from_variant_and_caps(cfg.variant, cfg.streams.iter().map(|(caps, _)| caps));
 
     write_box(&mut v, b"styp", |v| {
         // major brand
@@ -1272,9 +1814,21 @@ pub(super) fn create_fmp4_fragment_header(
         Ok(())
     })?;
 
-    let styp_len = v.len();
+    // Event messages have to be placed before the `moof`
+    let timescale = caps_to_timescale(&cfg.streams[0].0);
+    for emsg in cfg.emsgs {
+        write_full_box(
+            &mut v,
+            b"emsg",
+            FULL_BOX_VERSION_1,
+            FULL_BOX_FLAGS_NONE,
+            |v| write_emsg(v, emsg, timescale),
+        )?;
+    }
+
+    let moof_offset = v.len();
 
-    let data_offset_offset = write_box(&mut v, b"moof", |v| write_moof(v, &cfg))?;
+    let data_offset_offsets = write_box(&mut v, b"moof", |v| write_moof(v, &cfg, moof_offset))?;
 
     let size = cfg
         .buffers
@@ -1290,20 +1844,179 @@ pub(super) fn create_fmp4_fragment_header(
         v.extend((size + 16).to_be_bytes());
     }
 
-    let data_offset = v.len() - styp_len;
-    v[data_offset_offset..][..4].copy_from_slice(&(data_offset as u32).to_be_bytes());
+    // The samples of each stream are stored one after another in the `mdat`, in the same order
+    // as the `traf`s.
+    let mut data_offset = v.len() - moof_offset;
+    for (data_offset_offset, size) in data_offset_offsets {
+        let offset = u32::try_from(data_offset).context("too big data offset")?;
+        v[data_offset_offset..][..4].copy_from_slice(&offset.to_be_bytes());
+        data_offset += size;
+    }
 
-    Ok((gst::Buffer::from_mut_slice(v), styp_len as u64))
+    Ok((gst::Buffer::from_mut_slice(v), moof_offset as u64))
 }
 
-#[allow(clippy::too_many_arguments)]
-fn write_moof(v: &mut Vec<u8>, cfg: &super::FragmentHeaderConfiguration) -> Result<usize, Error> {
+fn write_emsg(v: &mut Vec<u8>, emsg: &super::Emsg, timescale: u32) -> Result<(), Error> {
+    // Timescale
+    v.extend(timescale.to_be_bytes());
+
+    // Presentation time
+    let presentation_time = emsg
+        .presentation_time
+        .nseconds()
+        .mul_div_round(timescale as u64, gst::ClockTime::SECOND.nseconds())
+        .context("too big presentation time")?;
+    v.extend(presentation_time.to_be_bytes());
+
+    // Event duration, 0xffffffff if unknown
+    let duration = match emsg.duration {
+        Some(duration) => u32::try_from(
+            duration
+                .nseconds()
+                .mul_div_round(timescale as u64, gst::ClockTime::SECOND.nseconds())
+                .context("too big event duration")?,
+        )
+        .unwrap_or(u32::MAX - 1),
+        None => u32::MAX,
+    };
+    v.extend(duration.to_be_bytes());
+
+    // ID
+    v.extend(emsg.id.to_be_bytes());
+
+    // Scheme ID URI and value
+    v.extend(emsg.scheme_id_uri.as_bytes());
+    v.push(0);
+    v.extend(emsg.value.as_bytes());
+    v.push(0);
+
+    // Message data
+    if let Some(ref message_data) = emsg.message_data {
+        let map = message_data
+            .map_readable()
+            .context("message data not mappable")?;
+        v.extend_from_slice(&map);
+    }
+
+    Ok(())
+}
+
+/// Creates a `wvtt` sample from a WebVTT cue, or an empty cue if `None`.
+///
+/// The cue can contain an optional identifier line and the timing line followed by the cue
+/// payload. Without timing line the whole text is used as payload.
+pub(super) fn create_wvtt_sample(cue: Option<&[u8]>) -> Result<gst::Buffer, Error> {
+    let mut v = vec![];
+
+    let cue = cue
+        .map(|cue| std::str::from_utf8(cue).context("cue not valid UTF-8"))
+        .transpose()?;
+
+    let (identifier, settings, payload) = match cue {
+        None => (None, None, ""),
+        Some(cue) => {
+            let lines = cue.lines().collect::<Vec<_>>();
+            match lines.iter().position(|line| line.contains("-->")) {
+                Some(timing_line) if timing_line <= 1 => {
+                    let identifier = if timing_line == 1 {
+                        Some(lines[0])
+                    } else {
+                        None
+                    };
+                    // Settings follow the end timestamp of the timing line
+                    let settings = lines[timing_line]
+                        .split_once("-->")
+                        .and_then(|(_, end)| end.trim_start().split_once(char::is_whitespace))
+                        .map(|(_, settings)| settings.trim())
+                        .filter(|settings| !settings.is_empty());
+                    let payload = cue
+                        .splitn(timing_line + 2, '\n')
+                        .nth(timing_line + 1)
+                        .unwrap_or("");
+
+                    (identifier, settings, payload.trim_end())
+                }
+                _ => (None, None, cue.trim_end()),
+            }
+        }
+    };
+
+    if payload.is_empty() {
+        write_box(&mut v, b"vtte", |_v| Ok(()))?;
+    } else {
+        write_box(&mut v, b"vttc", |v| {
+            if let Some(identifier) = identifier {
+                write_box(v, b"iden", |v| {
+                    v.extend(identifier.as_bytes());
+                    Ok(())
+                })?;
+            }
+
+            if let Some(settings) = settings {
+                write_box(v, b"sttg", |v| {
+                    v.extend(settings.as_bytes());
+                    Ok(())
+                })?;
+            }
+
+            write_box(v, b"payl", |v| {
+                v.extend(payload.as_bytes());
+                Ok(())
+            })
+        })?;
+    }
+
+    Ok(gst::Buffer::from_mut_slice(v))
+}
+
+/// Returns the buffers of the stream with the given index
+fn stream_buffers<'a>(buffers: &'a [Buffer], idx: usize) -> &'a [Buffer] {
+    let start = buffers
+        .iter()
+        .position(|buffer| buffer.idx == idx)
+        .unwrap_or(buffers.len());
+    let len = buffers[start..]
+        .iter()
+        .take_while(|buffer| buffer.idx == idx)
+        .count();
+
+    &buffers[start..][..len]
+}
+
+/// Writes the `moof` contents and returns the position of the data offset in each `trun` together
+/// with the size of the samples of its stream
+fn write_moof(
+    v: &mut Vec<u8>,
+    cfg: &super::FragmentHeaderConfiguration,
+    moof_offset: usize,
+) -> Result<Vec<(usize, usize)>, Error> {
     write_full_box(v, b"mfhd", FULL_BOX_VERSION_0, FULL_BOX_FLAGS_NONE, |v| {
         write_mfhd(v, cfg)
     })?;
-    let data_offset_offset = write_box(v, b"traf", |v| write_traf(v, cfg))?;
 
-    Ok(data_offset_offset)
+    let mut data_offset_offsets = vec![];
+    for (idx, (caps, timing_info)) in cfg.streams.iter().enumerate() {
+        let timing_info = match timing_info {
+            None => continue,
+            Some(ref timing_info) => timing_info,
+        };
+
+        let buffers = stream_buffers(cfg.buffers, idx);
+        if buffers.is_empty() {
+            continue;
+        }
+
+        let data_offset_offset = write_box(v, b"traf", |v| {
+            write_traf(v, cfg, idx, caps, timing_info, buffers, moof_offset)
+        })?;
+        let size = buffers
+            .iter()
+            .map(|buffer| buffer.buffer.size())
+            .sum::<usize>();
+        data_offset_offsets.push((data_offset_offset, size));
+    }
+
+    Ok(data_offset_offsets)
 }
 
 fn write_mfhd(v: &mut Vec<u8>, cfg: &super::FragmentHeaderConfiguration) -> Result<(), Error> {
@@ -1390,21 +2103,29 @@ fn timestamp_from_pts_dts(
     }
 }
 
-const DEFAULT_SAMPLE_DURATION_PRESENT: u32 = 0x08;
-const DEFAULT_SAMPLE_SIZE_PRESENT: u32 = 0x10;
-const DEFAULT_SAMPLE_FLAGS_PRESENT: u32 = 0x20;
-const DEFAULT_BASE_IS_MOOF: u32 = 0x2_00_00;
-
-const DATA_OFFSET_PRESENT: u32 = 0x0_01;
-const FIRST_SAMPLE_FLAGS_PRESENT: u32 = 0x0_04;
-const SAMPLE_DURATION_PRESENT: u32 = 0x1_00;
-const SAMPLE_SIZE_PRESENT: u32 = 0x2_00;
-const SAMPLE_FLAGS_PRESENT: u32 = 0x4_00;
-const SAMPLE_COMPOSITION_TIME_OFFSET_PRESENT: u32 = 0x8_00;
+// tfhd flags
+pub(crate) const BASE_DATA_OFFSET_PRESENT: u32 = 0x01;
+pub(crate) const SAMPLE_DESCRIPTION_INDEX_PRESENT: u32 = 0x02;
+pub(crate) const DEFAULT_SAMPLE_DURATION_PRESENT: u32 = 0x08;
+pub(crate) const DEFAULT_SAMPLE_SIZE_PRESENT: u32 = 0x10;
+pub(crate) const DEFAULT_SAMPLE_FLAGS_PRESENT: u32 = 0x20;
+pub(crate) const DEFAULT_BASE_IS_MOOF: u32 = 0x2_00_00;
+
+// trun flags
+pub(crate) const DATA_OFFSET_PRESENT: u32 = 0x0_01;
+pub(crate) const FIRST_SAMPLE_FLAGS_PRESENT: u32 = 0x0_04;
+pub(crate) const SAMPLE_DURATION_PRESENT: u32 = 0x1_00;
+pub(crate) const SAMPLE_SIZE_PRESENT: u32 = 0x2_00;
+pub(crate) const SAMPLE_FLAGS_PRESENT: u32 = 0x4_00;
+pub(crate) const SAMPLE_COMPOSITION_TIME_OFFSET_PRESENT: u32 = 0x8_00;
+
+// Sample flags
+pub(crate) const SAMPLE_IS_NON_SYNC_SAMPLE: u32 = 0x1_00_00;
 
 #[allow(clippy::type_complexity)]
 fn analyze_buffers(
-    cfg: &super::FragmentHeaderConfiguration,
+    timing_info: &super::FragmentTimingInfo,
+    buffers: &[Buffer],
     check_dts: bool,
     intra_only: bool,
     timescale: u32,
@@ -1436,7 +2157,10 @@ fn analyze_buffers(
 
     let mut negative_composition_time_offsets = false;
 
-    for Buffer { buffer, pts, dts } in cfg.buffers {
+    for Buffer {
+        buffer, pts, dts, ..
+    } in buffers
+    {
         if size.is_none() {
             size = Some(buffer.size() as u32);
         }
@@ -1493,9 +2217,9 @@ fn analyze_buffers(
     // Check duration of the last buffer against end_pts / end_dts
     {
         let current_timestamp = if check_dts {
-            cfg.end_dts.expect("no end DTS")
+            timing_info.end_dts.expect("no end DTS")
         } else {
-            cfg.end_pts
+            timing_info.end_pts
         };
         let current_timestamp = current_timestamp
             .nseconds()
@@ -1543,12 +2267,28 @@ fn analyze_buffers(
     ))
 }
 
-fn write_traf(v: &mut Vec<u8>, cfg: &super::FragmentHeaderConfiguration) -> Result<usize, Error> {
-    let s = cfg.caps.structure(0).unwrap();
-    let timescale = caps_to_timescale(cfg.caps);
+fn write_traf(
+    v: &mut Vec<u8>,
+    cfg: &super::FragmentHeaderConfiguration,
+    idx: usize,
+    caps: &gst::CapsRef,
+    timing_info: &super::FragmentTimingInfo,
+    buffers: &[Buffer],
+    moof_offset: usize,
+) -> Result<usize, Error> {
+    let s = caps.structure(0).unwrap();
+    let timescale = caps_to_timescale(caps);
 
     let check_dts = matches!(s.name(), "video/x-h264" | "video/x-h265");
-    let intra_only = matches!(s.name(), "audio/mpeg");
+    let intra_only = matches!(
+        s.name(),
+        "audio/mpeg"
+            | "audio/x-opus"
+            | "audio/x-flac"
+            | "application/x-subtitle-vtt"
+            | "application/x-subtitle-vtt-fragmented"
+            | "application/ttml+xml"
+    );
 
     // Analyze all buffers to know what values can be put into the tfhd for all samples and what
     // has to be stored for every single sample
@@ -1559,13 +2299,13 @@ fn write_traf(v: &mut Vec<u8>, cfg: &super::FragmentHeaderConfiguration) -> Resu
         default_duration,
         default_flags,
         negative_composition_time_offsets,
-    ) = analyze_buffers(cfg, check_dts, intra_only, timescale)?;
+    ) = analyze_buffers(timing_info, buffers, check_dts, intra_only, timescale)?;
 
     write_full_box(v, b"tfhd", FULL_BOX_VERSION_0, tf_flags, |v| {
-        write_tfhd(v, cfg, default_size, default_duration, default_flags)
+        write_tfhd(v, cfg, idx, default_size, default_duration, default_flags)
     })?;
     write_full_box(v, b"tfdt", FULL_BOX_VERSION_1, FULL_BOX_FLAGS_NONE, |v| {
-        write_tfdt(v, cfg, timescale)
+        write_tfdt(v, cfg, timing_info, timescale)
     })?;
 
     let data_offset_offset = write_full_box(
@@ -1577,23 +2317,137 @@ fn write_traf(v: &mut Vec<u8>, cfg: &super::FragmentHeaderConfiguration) -> Resu
             FULL_BOX_VERSION_0
         },
         tr_flags,
-        |v| write_trun(v, cfg, tr_flags, check_dts, intra_only, timescale),
+        |v| {
+            write_trun(
+                v,
+                cfg,
+                timing_info,
+                buffers,
+                tr_flags,
+                check_dts,
+                intra_only,
+                timescale,
+            )
+        },
     )?;
 
-    // TODO: saio, saiz, sbgp, sgpd, subs?
+    if cfg.encryption.get(idx).and_then(Option::as_ref).is_some() {
+        write_sample_encryption(v, buffers, moof_offset)?;
+    }
+
+    // TODO: sbgp, sgpd, subs?
 
     Ok(data_offset_offset)
 }
 
+const SENC_FLAGS_USE_SUBSAMPLE_ENCRYPTION: u32 = 0x2;
+
+/// Writes the `senc`, `saiz` and `saio` boxes with the sample auxiliary information of an
+/// encrypted track
+fn write_sample_encryption(
+    v: &mut Vec<u8>,
+    buffers: &[Buffer],
+    moof_offset: usize,
+) -> Result<(), Error> {
+    let sample_encryption = buffers
+        .iter()
+        .map(|buffer| {
+            buffer
+                .encryption
+                .as_ref()
+                .context("unencrypted sample in encrypted track")
+        })
+        .collect::<Result<Vec<_>, Error>>()?;
+
+    let use_subsamples = sample_encryption
+        .iter()
+        .any(|encryption| !encryption.subsamples.is_empty());
+
+    let sample_info_sizes = sample_encryption
+        .iter()
+        .map(|encryption| {
+            let mut size = encryption.iv.len();
+            if use_subsamples {
+                size += 2 + 6 * encryption.subsamples.len();
+            }
+            u8::try_from(size).context("too many subsamples")
+        })
+        .collect::<Result<Vec<_>, Error>>()?;
+
+    let aux_info_offset = write_full_box(
+        v,
+        b"senc",
+        FULL_BOX_VERSION_0,
+        if use_subsamples {
+            SENC_FLAGS_USE_SUBSAMPLE_ENCRYPTION
+        } else {
+            FULL_BOX_FLAGS_NONE
+        },
+        |v| {
+            // Sample count
+            v.extend((sample_encryption.len() as u32).to_be_bytes());
+
+            let aux_info_offset = v.len();
+            for encryption in &sample_encryption {
+                v.extend_from_slice(&encryption.iv);
+
+                if use_subsamples {
+                    v.extend((encryption.subsamples.len() as u16).to_be_bytes());
+                    for (clear, protected) in &encryption.subsamples {
+                        v.extend(clear.to_be_bytes());
+                        v.extend(protected.to_be_bytes());
+                    }
+                }
+            }
+
+            Ok(aux_info_offset)
+        },
+    )?;
+
+    write_full_box(v, b"saiz", FULL_BOX_VERSION_0, FULL_BOX_FLAGS_NONE, |v| {
+        let default_size = sample_info_sizes[0];
+        if sample_info_sizes.iter().all(|size| *size == default_size) {
+            // Default sample info size
+            v.push(default_size);
+            // Sample count
+            v.extend((sample_info_sizes.len() as u32).to_be_bytes());
+        } else {
+            // Default sample info size
+            v.push(0);
+            // Sample count
+            v.extend((sample_info_sizes.len() as u32).to_be_bytes());
+            // Sample info sizes
+            v.extend(&sample_info_sizes);
+        }
+
+        Ok(())
+    })?;
+
+    write_full_box(v, b"saio", FULL_BOX_VERSION_0, FULL_BOX_FLAGS_NONE, |v| {
+        // Entry count
+        v.extend(1u32.to_be_bytes());
+
+        // Offset of the auxiliary information in the `senc` relative to the `moof`
+        let offset =
+            u32::try_from(aux_info_offset - moof_offset).context("too big aux info offset")?;
+        v.extend(offset.to_be_bytes());
+
+        Ok(())
+    })?;
+
+    Ok(())
+}
+
 fn write_tfhd(
     v: &mut Vec<u8>,
     _cfg: &super::FragmentHeaderConfiguration,
+    idx: usize,
     default_size: Option<u32>,
     default_duration: Option<u32>,
     default_flags: Option<u32>,
 ) -> Result<(), Error> {
     // Track ID
-    v.extend(1u32.to_be_bytes());
+    v.extend((idx as u32 + 1).to_be_bytes());
 
     // No base data offset, no sample description index
 
@@ -1614,12 +2468,13 @@ fn write_tfhd(
 
 fn write_tfdt(
     v: &mut Vec<u8>,
-    cfg: &super::FragmentHeaderConfiguration,
+    _cfg: &super::FragmentHeaderConfiguration,
+    timing_info: &super::FragmentTimingInfo,
     timescale: u32,
 ) -> Result<(), Error> {
-    let base_time = cfg
+    let base_time = timing_info
         .start_dts
-        .unwrap_or(cfg.earliest_pts)
+        .unwrap_or(timing_info.earliest_pts)
         .mul_div_floor(timescale as u64, gst::ClockTime::SECOND.nseconds())
         .context("base time overflow")?;
 
@@ -1631,36 +2486,43 @@ fn write_tfdt(
 #[allow(clippy::too_many_arguments)]
 fn write_trun(
     v: &mut Vec<u8>,
-    cfg: &super::FragmentHeaderConfiguration,
+    _cfg: &super::FragmentHeaderConfiguration,
+    timing_info: &super::FragmentTimingInfo,
+    buffers: &[Buffer],
     tr_flags: u32,
     check_dts: bool,
     intra_only: bool,
     timescale: u32,
 ) -> Result<usize, Error> {
     // Sample count
-    v.extend((cfg.buffers.len() as u32).to_be_bytes());
+    v.extend((buffers.len() as u32).to_be_bytes());
 
     let data_offset_offset = v.len();
     // Data offset, will be rewritten later
     v.extend(0i32.to_be_bytes());
 
     if (tr_flags & FIRST_SAMPLE_FLAGS_PRESENT) != 0 {
-        v.extend(sample_flags_from_buffer(&cfg.buffers[0].buffer, intra_only).to_be_bytes());
+        v.extend(sample_flags_from_buffer(&buffers[0].buffer, intra_only).to_be_bytes());
     }
 
     let last_timestamp = if check_dts {
-        cfg.end_dts.expect("no end DTS")
+        timing_info.end_dts.expect("no end DTS")
     } else {
-        cfg.end_pts
+        timing_info.end_pts
     };
     let last_timestamp = last_timestamp
         .nseconds()
         .mul_div_round(timescale as u64, gst::ClockTime::SECOND.nseconds())
         .context("too big timestamp")?;
 
-    for (Buffer { buffer, pts, dts }, next_timestamp) in Iterator::zip(
-        cfg.buffers.iter(),
-        cfg.buffers
+    for (
+        Buffer {
+            buffer, pts, dts, ..
+        },
+        next_timestamp,
+    ) in Iterator::zip(
+        buffers.iter(),
+        buffers
             .iter()
             .skip(1)
             .map(|Buffer { pts, dts, .. }| {
@@ -1703,45 +2565,62 @@ fn write_trun(
 
 /// Creates `mfra` box
 pub(crate) fn create_mfra(
-    caps: &gst::CapsRef,
+    streams: &[gst::Caps],
     fragment_offsets: &[super::FragmentOffset],
 ) -> Result<gst::Buffer, Error> {
-    let timescale = caps_to_timescale(caps);
-
     let mut v = vec![];
 
     let offset = write_box(&mut v, b"mfra", |v| {
-        write_full_box(v, b"tfra", FULL_BOX_VERSION_1, FULL_BOX_FLAGS_NONE, |v| {
-            // Track ID
-            v.extend(1u32.to_be_bytes());
-
-            // Reserved / length of traf/trun/sample
-            v.extend(0u32.to_be_bytes());
-
-            // Number of entries
-            v.extend(
-                u32::try_from(fragment_offsets.len())
-                    .context("too many fragments")?
-                    .to_be_bytes(),
-            );
-
-            for super::FragmentOffset { time, offset } in fragment_offsets {
-                // Time
-                let time = time
-                    .nseconds()
-                    .mul_div_round(timescale as u64, gst::ClockTime::SECOND.nseconds())
-                    .context("time overflow")?;
-                v.extend(time.to_be_bytes());
-
-                // moof offset
-                v.extend(offset.to_be_bytes());
+        for (idx, caps) in streams.iter().enumerate() {
+            let timescale = caps_to_timescale(caps);
+
+            // All fragments this stream is part of, together with the time of its first sample
+            // and the 1-based number of its traf in the moof
+            let entries = fragment_offsets
+                .iter()
+                .filter_map(|fragment_offset| {
+                    fragment_offset
+                        .trafs
+                        .iter()
+                        .enumerate()
+                        .find(|(_, (stream_idx, _))| *stream_idx == idx)
+                        .map(|(traf_idx, (_, time))| (*time, fragment_offset.offset, traf_idx + 1))
+                })
+                .collect::<Vec<_>>();
+
+            write_full_box(v, b"tfra", FULL_BOX_VERSION_1, FULL_BOX_FLAGS_NONE, |v| {
+                // Track ID
+                v.extend((idx as u32 + 1).to_be_bytes());
+
+                // Reserved / length of traf/trun/sample
+                v.extend(0u32.to_be_bytes());
 
-                // traf/trun/sample number
-                v.extend_from_slice(&[1u8; 3][..]);
-            }
+                // Number of entries
+                v.extend(
+                    u32::try_from(entries.len())
+                        .context("too many fragments")?
+                        .to_be_bytes(),
+                );
+
+                for (time, offset, traf_number) in entries {
+                    // Time
+                    let time = time
+                        .nseconds()
+                        .mul_div_round(timescale as u64, gst::ClockTime::SECOND.nseconds())
+                        .context("time overflow")?;
+                    v.extend(time.to_be_bytes());
+
+                    // moof offset
+                    v.extend(offset.to_be_bytes());
+
+                    // traf/trun/sample number
+                    v.push(u8::try_from(traf_number).context("too many trafs")?);
+                    v.extend_from_slice(&[1u8; 2][..]);
+                }
 
-            Ok(())
-        })?;
+                Ok(())
+            })?;
+        }
 
         let offset = write_full_box(v, b"mfro", FULL_BOX_VERSION_0, FULL_BOX_FLAGS_NONE, |v| {
             let offset = v.len();
@@ -0,0 +1,566 @@
+// Copyright (C) 2026 agent <agent@local>
+//
+// This Source Code Form is subject to the terms of the Mozilla Public License, v2.0.
+// If a copy of the MPL was not distributed with this file, You can obtain one at
+// <https://mozilla.org/MPL/2.0/>.
+//
+// SPDX-License-Identifier: MPL-2.0
+
+use aes::cipher::{generic_array::GenericArray, BlockEncrypt, NewBlockCipher};
+use aes::Aes128;
+
+use anyhow::{bail, Context, Error};
+
+#[derive(Debug, Clone, Copy)]
+enum Codec {
+    H264 {
+        nal_length_size: usize,
+    },
+    H265 {
+        nal_length_size: usize,
+    },
+    /// Whole samples are protected
+    Other,
+}
+
+/// Encrypts the samples of a single track according to ISO/IEC 23001-7.
+///
+/// Only H.264, H.265, AAC, Opus and FLAC are supported. VP9 and AV1 would need codec-specific
+/// subsample encryption of their superframes and OBUs, which is not implemented.
+pub(super) struct Encryptor {
+    info: super::TrackEncryption,
+    cipher: Aes128,
+    codec: Codec,
+    /// Per-sample IV of the next sample for `cenc`
+    next_iv: u64,
+}
+
+impl std::fmt::Debug for Encryptor {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Encryptor")
+            .field("info", &self.info)
+            .field("codec", &self.codec)
+            .field("next_iv", &self.next_iv)
+            .finish()
+    }
+}
+
+impl Encryptor {
+    /// Creates a new encryptor for a stream with the given caps.
+    ///
+    /// `iv` is the initial 8 byte per-sample IV for `cenc` or the 16 byte constant IV for `cbcs`.
+    /// A random IV is used if none is given.
+    pub(super) fn new(
+        caps: &gst::CapsRef,
+        scheme: super::EncryptionScheme,
+        key_id: &[u8],
+        key: &[u8],
+        iv: Option<&[u8]>,
+    ) -> Result<Self, Error> {
+        let s = caps.structure(0).unwrap();
+
+        let nal_length_size = |min_size: usize, pos: usize| -> Result<usize, Error> {
+            let codec_data = s
+                .get::<&gst::BufferRef>("codec_data")
+                .context("no codec_data")?;
+            let map = codec_data
+                .map_readable()
+                .context("codec_data not mappable")?;
+            if map.len() < min_size {
+                bail!("too small codec_data");
+            }
+            Ok((map[pos] & 0x03) as usize + 1)
+        };
+
+        let codec = match s.name() {
+            "video/x-h264" => Codec::H264 {
+                nal_length_size: nal_length_size(7, 4)?,
+            },
+            "video/x-h265" => Codec::H265 {
+                nal_length_size: nal_length_size(23, 21)?,
+            },
+            "audio/mpeg" | "audio/x-opus" | "audio/x-flac" => Codec::Other,
+            name => bail!("encryption of {} not supported", name),
+        };
+
+        let key_id = <[u8; 16]>::try_from(key_id).context("key ID must be 16 bytes")?;
+        let key = <[u8; 16]>::try_from(key).context("key must be 16 bytes")?;
+        let cipher = Aes128::new(GenericArray::from_slice(&key));
+
+        let (info, next_iv) = match scheme {
+            super::EncryptionScheme::None => unreachable!(),
+            super::EncryptionScheme::Cenc => {
+                let next_iv = match iv {
+                    Some(iv) => u64::from_be_bytes(
+                        <[u8; 8]>::try_from(iv).context("cenc IV must be 8 bytes")?,
+                    ),
+                    None => rand::random(),
+                };
+
+                (
+                    super::TrackEncryption {
+                        scheme,
+                        key_id,
+                        per_sample_iv_size: 8,
+                        constant_iv: None,
+                        crypt_byte_block: 0,
+                        skip_byte_block: 0,
+                    },
+                    next_iv,
+                )
+            }
+            super::EncryptionScheme::Cbcs => {
+                let constant_iv = match iv {
+                    Some(iv) => <[u8; 16]>::try_from(iv).context("cbcs IV must be 16 bytes")?,
+                    None => rand::random(),
+                };
+
+                // Video is encrypted with a 1:9 pattern, audio completely
+                let (crypt_byte_block, skip_byte_block) = match codec {
+                    Codec::H264 { .. } | Codec::H265 { .. } => (1, 9),
+                    Codec::Other => (0, 0),
+                };
+
+                (
+                    super::TrackEncryption {
+                        scheme,
+                        key_id,
+                        per_sample_iv_size: 0,
+                        constant_iv: Some(constant_iv),
+                        crypt_byte_block,
+                        skip_byte_block,
+                    },
+                    0,
+                )
+            }
+        };
+
+        Ok(Encryptor {
+            info,
+            cipher,
+            codec,
+            next_iv,
+        })
+    }
+
+    /// Encryption parameters for the `tenc` box.
+    pub(super) fn track_encryption(&self) -> &super::TrackEncryption {
+        &self.info
+    }
+
+    /// Encrypts a sample and returns the encrypted sample and its auxiliary information.
+    pub(super) fn encrypt(
+        &mut self,
+        buffer: &gst::BufferRef,
+    ) -> Result<(gst::Buffer, super::SampleEncryption), Error> {
+        let mut data = buffer
+            .map_readable()
+            .context("buffer not mappable")?
+            .to_vec();
+
+        let subsamples = match self.codec {
+            Codec::H264 { nal_length_size } => self.nal_subsamples(&data, nal_length_size, 1)?,
+            Codec::H265 { nal_length_size } => self.nal_subsamples(&data, nal_length_size, 2)?,
+            Codec::Other => vec![],
+        };
+
+        // Byte ranges of the protected parts of the sample
+        let mut protected_ranges = vec![];
+        if subsamples.is_empty() {
+            protected_ranges.push(0..data.len());
+        } else {
+            let mut offset = 0;
+            for (clear, protected) in &subsamples {
+                offset += *clear as usize;
+                protected_ranges.push(offset..(offset + *protected as usize));
+                offset += *protected as usize;
+            }
+        }
+
+        let iv = match self.info.scheme {
+            super::EncryptionScheme::None => unreachable!(),
+            super::EncryptionScheme::Cenc => {
+                let iv = self.next_iv.to_be_bytes();
+                self.next_iv = self.next_iv.wrapping_add(1);
+
+                self.encrypt_ctr(&mut data, &protected_ranges, iv);
+
+                iv.to_vec()
+            }
+            super::EncryptionScheme::Cbcs => {
+                self.encrypt_cbc_pattern(&mut data, &protected_ranges);
+
+                vec![]
+            }
+        };
+
+        let mut encrypted = gst::Buffer::from_mut_slice(data);
+        {
+            let encrypted = encrypted.get_mut().unwrap();
+            let _ = buffer.copy_into(
+                encrypted,
+                gst::BufferCopyFlags::FLAGS
+                    | gst::BufferCopyFlags::TIMESTAMPS
+                    | gst::BufferCopyFlags::META,
+                0,
+                None,
+            );
+        }
+
+        Ok((encrypted, super::SampleEncryption { iv, subsamples }))
+    }
+
+    /// Splits a sample with length-prefixed NAL units into subsamples.
+    ///
+    /// The length prefix and NAL header of all NAL units stay clear, as do non-VCL NAL units.
+    fn nal_subsamples(
+        &self,
+        data: &[u8],
+        nal_length_size: usize,
+        nal_header_size: usize,
+    ) -> Result<Vec<(u16, u32)>, Error> {
+        let mut subsamples = vec![];
+        let mut push_subsample = |mut clear: usize, protected: usize| -> Result<(), Error> {
+            while clear > u16::MAX as usize {
+                subsamples.push((u16::MAX, 0));
+                clear -= u16::MAX as usize;
+            }
+            subsamples.push((
+                clear as u16,
+                u32::try_from(protected).context("too big NAL unit")?,
+            ));
+
+            Ok(())
+        };
+
+        let mut clear = 0;
+        let mut offset = 0;
+        while offset < data.len() {
+            if offset + nal_length_size + nal_header_size > data.len() {
+                bail!("truncated NAL unit");
+            }
+
+            let nal_size = data[offset..][..nal_length_size]
+                .iter()
+                .fold(0usize, |size, b| (size << 8) | *b as usize);
+            let nal_end = offset + nal_length_size + nal_size;
+            if nal_size < nal_header_size || nal_end > data.len() {
+                bail!("invalid NAL unit size {}", nal_size);
+            }
+
+            let nal_header = data[offset + nal_length_size];
+            let is_vcl = match self.codec {
+                Codec::H264 { .. } => (1..=5).contains(&(nal_header & 0x1f)),
+                Codec::H265 { .. } => (nal_header >> 1) & 0x3f < 32,
+                Codec::Other => unreachable!(),
+            };
+
+            let mut protected = if is_vcl {
+                nal_size - nal_header_size
+            } else {
+                0
+            };
+            // With cenc the protected part of each subsample must be a multiple of the block
+            // size, the remainder is left clear at the start of the NAL unit
+            if self.info.scheme == super::EncryptionScheme::Cenc {
+                protected -= protected % 16;
+            }
+
+            clear += nal_end - offset - protected;
+            if protected > 0 {
+                push_subsample(clear, protected)?;
+                clear = 0;
+            }
+
+            offset = nal_end;
+        }
+
+        if clear > 0 || subsamples.is_empty() {
+            push_subsample(clear, 0)?;
+        }
+
+        Ok(subsamples)
+    }
+
+    /// AES-CTR with the 8 byte IV as upper half of the counter block.
+    ///
+    /// The key stream continues over all protected ranges of the sample.
+    fn encrypt_ctr(&self, data: &mut [u8], ranges: &[std::ops::Range<usize>], iv: [u8; 8]) {
+        let mut block_counter = 0u64;
+        let mut key_stream = aes::Block::default();
+        let mut pos = 16;
+
+        for range in ranges {
+            for b in &mut data[range.clone()] {
+                if pos == 16 {
+                    key_stream[..8].copy_from_slice(&iv);
+                    key_stream[8..].copy_from_slice(&block_counter.to_be_bytes());
+                    self.cipher.encrypt_block(&mut key_stream);
+                    block_counter = block_counter.wrapping_add(1);
+                    pos = 0;
+                }
+
+                *b ^= key_stream[pos];
+                pos += 1;
+            }
+        }
+    }
+
+    /// AES-CBC with the constant IV and the encryption pattern of the track.
+    ///
+    /// The CBC chain restarts at every protected range and trailing partial blocks stay clear.
+    fn encrypt_cbc_pattern(&self, data: &mut [u8], ranges: &[std::ops::Range<usize>]) {
+        let constant_iv = self.info.constant_iv.expect("no constant IV");
+        let crypt_byte_block = self.info.crypt_byte_block as usize;
+        let pattern_length = crypt_byte_block + self.info.skip_byte_block as usize;
+
+        for range in ranges {
+            let mut chain = constant_iv;
+
+            for (idx, block) in data[range.clone()].chunks_exact_mut(16).enumerate() {
+                if pattern_length > 0 && idx % pattern_length >= crypt_byte_block {
+                    continue;
+                }
+
+                for (b, c) in Iterator::zip(block.iter_mut(), chain.iter()) {
+                    *b ^= *c;
+                }
+                self.cipher
+                    .encrypt_block(GenericArray::from_mut_slice(block));
+                chain.copy_from_slice(block);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::fmp4mux::EncryptionScheme;
+
+    const KEY_ID: [u8; 16] = [1; 16];
+    const KEY: [u8; 16] = [2; 16];
+
+    fn init() {
+        use std::sync::Once;
+        static INIT: Once = Once::new();
+
+        INIT.call_once(|| {
+            gst::init().unwrap();
+        });
+    }
+
+    fn aac_caps() -> gst::Caps {
+        gst::Caps::builder("audio/mpeg")
+            .field("mpegversion", 4i32)
+            .field("stream-format", "raw")
+            .build()
+    }
+
+    fn h264_caps() -> gst::Caps {
+        // avcC with 4 byte NAL unit lengths
+        gst::Caps::builder("video/x-h264")
+            .field("stream-format", "avc")
+            .field(
+                "codec_data",
+                gst::Buffer::from_slice([0x01, 0x64, 0x00, 0x1f, 0xff, 0xe0, 0x00]),
+            )
+            .build()
+    }
+
+    /// Creates a length-prefixed NAL unit of the given type with `size` payload bytes.
+    fn nal_unit(nal_type: u8, size: usize) -> Vec<u8> {
+        let mut nal = ((size + 1) as u32).to_be_bytes().to_vec();
+        nal.push(0x60 | nal_type);
+        nal.extend((0..size).map(|i| i as u8));
+        nal
+    }
+
+    /// Decrypts the concatenated protected bytes of a sample with AES-CTR.
+    fn decrypt_ctr(data: &[u8], iv: &[u8]) -> Vec<u8> {
+        let cipher = Aes128::new(GenericArray::from_slice(&KEY));
+
+        data.chunks(16)
+            .enumerate()
+            .flat_map(|(idx, chunk)| {
+                let mut counter = aes::Block::default();
+                counter[..8].copy_from_slice(iv);
+                counter[8..].copy_from_slice(&(idx as u64).to_be_bytes());
+                cipher.encrypt_block(&mut counter);
+
+                Iterator::zip(chunk.iter(), counter.into_iter())
+                    .map(|(b, k)| b ^ k)
+                    .collect::<Vec<_>>()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_cenc_full_sample() {
+        init();
+
+        let iv = [1, 2, 3, 4, 5, 6, 7, 8];
+        let mut encryptor = Encryptor::new(
+            &aac_caps(),
+            EncryptionScheme::Cenc,
+            &KEY_ID,
+            &KEY,
+            Some(&iv),
+        )
+        .unwrap();
+
+        let plaintext = (0..40).collect::<Vec<u8>>();
+        let buffer = gst::Buffer::from_slice(plaintext.clone());
+
+        let (encrypted, info) = encryptor.encrypt(&buffer).unwrap();
+        let map = encrypted.map_readable().unwrap();
+        assert_eq!(info.iv, iv);
+        assert!(info.subsamples.is_empty());
+        assert_eq!(
+            &*map,
+            &[
+                0xb3, 0xaf, 0xe0, 0x96, 0x7f, 0xd4, 0x41, 0x1d, 0x02, 0xc5, 0xe5, 0xef, 0xb6, 0xbf,
+                0x70, 0x17, 0x87, 0x66, 0x9d, 0xf6, 0xfa, 0x0e, 0x63, 0x9f, 0x73, 0x48, 0xd0, 0x92,
+                0x09, 0x36, 0xdf, 0x3f, 0x98, 0xc6, 0x80, 0x1c, 0x35, 0x9f, 0x73, 0xf8,
+            ][..]
+        );
+        assert_eq!(decrypt_ctr(&map, &info.iv), plaintext);
+
+        // The next sample uses the next IV
+        let (encrypted, info) = encryptor.encrypt(&buffer).unwrap();
+        let map = encrypted.map_readable().unwrap();
+        assert_eq!(info.iv, [1, 2, 3, 4, 5, 6, 7, 9]);
+        assert_eq!(
+            &*map,
+            &[
+                0xae, 0xa0, 0x16, 0x1e, 0x45, 0x65, 0x27, 0x10, 0x5a, 0x62, 0x97, 0x3c, 0xa8, 0xf7,
+                0x1c, 0x0a, 0x08, 0x4a, 0xfc, 0x2e, 0x80, 0x12, 0x7b, 0x49, 0x3d, 0xef, 0x50, 0xf9,
+                0x3a, 0xeb, 0xb8, 0xb2, 0x23, 0xb1, 0xfe, 0xd5, 0x57, 0xcc, 0x13, 0x3a,
+            ][..]
+        );
+        assert_eq!(decrypt_ctr(&map, &info.iv), plaintext);
+    }
+
+    #[test]
+    fn test_cenc_subsamples() {
+        init();
+
+        let iv = [1, 2, 3, 4, 5, 6, 7, 8];
+        let mut encryptor = Encryptor::new(
+            &h264_caps(),
+            EncryptionScheme::Cenc,
+            &KEY_ID,
+            &KEY,
+            Some(&iv),
+        )
+        .unwrap();
+
+        let mut plaintext = nal_unit(7, 9);
+        plaintext.extend(nal_unit(5, 40));
+        plaintext.extend(nal_unit(1, 100));
+
+        let buffer = gst::Buffer::from_slice(plaintext.clone());
+        let (encrypted, info) = encryptor.encrypt(&buffer).unwrap();
+        let map = encrypted.map_readable().unwrap();
+        assert_eq!(info.iv, iv);
+        assert_eq!(info.subsamples, vec![(27, 32), (9, 96)]);
+
+        // The clear parts are unchanged and the protected parts form a single key stream
+        let mut offset = 0;
+        let mut protected = vec![];
+        let mut protected_plaintext = vec![];
+        for (clear, size) in &info.subsamples {
+            let clear = *clear as usize;
+            let size = *size as usize;
+            assert_eq!(map[offset..][..clear], plaintext[offset..][..clear]);
+            offset += clear;
+            protected.extend_from_slice(&map[offset..][..size]);
+            protected_plaintext.extend_from_slice(&plaintext[offset..][..size]);
+            offset += size;
+        }
+        assert_eq!(offset, plaintext.len());
+        assert_eq!(decrypt_ctr(&protected, &iv), protected_plaintext);
+    }
+
+    #[test]
+    fn test_cbcs_pattern() {
+        init();
+
+        let encryptor = Encryptor::new(
+            &h264_caps(),
+            EncryptionScheme::Cbcs,
+            &KEY_ID,
+            &KEY,
+            Some(&[3; 16]),
+        )
+        .unwrap();
+
+        // 12 full blocks and a trailing partial block
+        let plaintext = (0..200).map(|i| i as u8).collect::<Vec<_>>();
+        let mut data = plaintext.clone();
+        encryptor.encrypt_cbc_pattern(&mut data, &[0..200]);
+
+        // Only the first block of each 1:9 pattern is encrypted, and the CBC chain continues
+        // from the last encrypted block
+        assert_eq!(
+            data[0..16],
+            [
+                0x9e, 0xd4, 0xd3, 0xfd, 0x04, 0x17, 0xcf, 0x3c, 0x18, 0x9b, 0x5f, 0x44, 0xc5, 0x85,
+                0x12, 0xf2,
+            ]
+        );
+        assert_eq!(data[16..160], plaintext[16..160]);
+        assert_eq!(
+            data[160..176],
+            [
+                0x66, 0x84, 0x54, 0xf9, 0xfd, 0x5b, 0x4f, 0xbe, 0x2b, 0x77, 0xf7, 0x3a, 0xac, 0x79,
+                0x0c, 0xa9,
+            ]
+        );
+        assert_eq!(data[176..], plaintext[176..]);
+    }
+
+    #[test]
+    fn test_nal_subsamples() {
+        init();
+
+        let mut sample = nal_unit(7, 9);
+        sample.extend(nal_unit(5, 40));
+        // Less than a block of protected data
+        sample.extend(nal_unit(1, 10));
+        sample.extend(nal_unit(1, 70000));
+        // Clear run that does not fit into a single subsample
+        sample.extend(nal_unit(6, 70000));
+
+        let encryptor = Encryptor::new(
+            &h264_caps(),
+            EncryptionScheme::Cenc,
+            &KEY_ID,
+            &KEY,
+            Some(&[0; 8]),
+        )
+        .unwrap();
+        assert_eq!(
+            encryptor.nal_subsamples(&sample, 4, 1).unwrap(),
+            vec![(27, 32), (20, 70000), (65535, 0), (4470, 0)]
+        );
+
+        // Without the block size restriction of cenc
+        let encryptor = Encryptor::new(
+            &h264_caps(),
+            EncryptionScheme::Cbcs,
+            &KEY_ID,
+            &KEY,
+            Some(&[0; 16]),
+        )
+        .unwrap();
+        assert_eq!(
+            encryptor.nal_subsamples(&sample, 4, 1).unwrap(),
+            vec![(19, 40), (5, 10), (5, 70000), (65535, 0), (4470, 0)]
+        );
+
+        // Truncated NAL unit
+        assert!(encryptor.nal_subsamples(&sample[..100], 4, 1).is_err());
+    }
+}
@@ -11,12 +11,16 @@ use gst::prelude::*;
 use gst::subclass::prelude::*;
 use gst::{gst_debug, gst_error, gst_info, gst_trace, gst_warning};
 
+use gst_base::prelude::*;
+use gst_base::subclass::prelude::*;
+
 use std::collections::VecDeque;
 use std::sync::Mutex;
 
 use once_cell::sync::Lazy;
 
 use super::boxes;
+use super::cenc;
 use super::Buffer;
 
 static CAT: Lazy<gst::DebugCategory> = Lazy::new(|| {
@@ -28,25 +32,46 @@ static CAT: Lazy<gst::DebugCategory> = Lazy::new(|| {
 });
 
 const DEFAULT_FRAGMENT_DURATION: gst::ClockTime = gst::ClockTime::from_seconds(10);
+const DEFAULT_CHUNK_DURATION: Option<gst::ClockTime> = gst::ClockTime::NONE;
 const DEFAULT_HEADER_UPDATE_MODE: super::HeaderUpdateMode = super::HeaderUpdateMode::None;
 const DEFAULT_WRITE_MFRA: bool = false;
 const DEFAULT_WRITE_MEHD: bool = false;
+const DEFAULT_ENCRYPTION_SCHEME: super::EncryptionScheme = super::EncryptionScheme::None;
+
+const SIGNAL_REQUEST_KEY: &str = "request-key";
 
 #[derive(Debug, Clone)]
 struct Settings {
     fragment_duration: gst::ClockTime,
+    chunk_duration: Option<gst::ClockTime>,
     header_update_mode: super::HeaderUpdateMode,
     write_mfra: bool,
     write_mehd: bool,
+    encryption_scheme: super::EncryptionScheme,
+    key_id: Option<gst::Buffer>,
+    key: Option<gst::Buffer>,
+    iv: Option<gst::Buffer>,
+}
+
+impl Settings {
+    /// Maximum amount of data that has to be queued before output is produced.
+    fn latency(&self) -> gst::ClockTime {
+        self.chunk_duration.unwrap_or(self.fragment_duration)
+    }
 }
 
 impl Default for Settings {
     fn default() -> Self {
         Settings {
             fragment_duration: DEFAULT_FRAGMENT_DURATION,
+            chunk_duration: DEFAULT_CHUNK_DURATION,
             header_update_mode: DEFAULT_HEADER_UPDATE_MODE,
             write_mfra: DEFAULT_WRITE_MFRA,
             write_mehd: DEFAULT_WRITE_MEHD,
+            encryption_scheme: DEFAULT_ENCRYPTION_SCHEME,
+            key_id: None,
+            key: None,
+            iv: None,
         }
     }
 }
@@ -63,46 +88,118 @@ struct Gop {
     // DTS plus duration of last buffer, or start of next GOP
     end_dts: Option<gst::ClockTime>,
 
-    // Buffer positions
-    earliest_pts_position: gst::ClockTime,
-    start_dts_position: Option<gst::ClockTime>,
-
     // Buffer, PTS running time, DTS running time
     buffers: Vec<Buffer>,
 }
 
+struct Stream {
+    sinkpad: gst_base::AggregatorPad,
+
+    caps: gst::Caps,
+    intra_only: bool,
+
+    queued_gops: VecDeque<Gop>,
+
+    // Difference between the first DTS and 0 in case of negative DTS
+    dts_offset: Option<gst::ClockTime>,
+
+    last_force_keyunit_time: Option<gst::ClockTime>,
+
+    // End PTS of the last WebVTT cue, used for filling gaps with empty cues
+    text_end_pts: Option<gst::ClockTime>,
+
+    // Set if the samples of this stream are encrypted
+    encryptor: Option<cenc::Encryptor>,
+}
+
+impl Stream {
+    /// End PTS of the newest GOP for which all buffers are queued and whose end PTS can't change
+    /// anymore.
+    ///
+    /// Once the stream is EOS all queued GOPs are complete.
+    fn complete_end_pts(&self) -> Option<gst::ClockTime> {
+        if self.sinkpad.is_eos() {
+            return self.queued_gops.front().map(|gop| gop.end_pts);
+        }
+
+        // The end PTS of a GOP is the earliest PTS of the next GOP, which is only known once that
+        // one has its final earliest PTS.
+        let mut gops = self.queued_gops.iter();
+        let newest_gop = gops.next()?;
+        if !newest_gop.final_earliest_pts {
+            gops.next()?;
+        }
+
+        gops.next().map(|gop| gop.end_pts)
+    }
+
+    /// Whether enough data is queued for a fragment ending at `end_pts`, or no more data can
+    /// arrive.
+    fn is_filled(&self, end_pts: gst::ClockTime) -> bool {
+        self.sinkpad.is_eos() || self.complete_end_pts().map_or(false, |end| end >= end_pts)
+    }
+
+    /// Whether all buffers for a chunk ending at `end_pts` are queued, or no more data can arrive.
+    ///
+    /// This is the case once a buffer at or after the end of the chunk is queued.
+    fn is_chunk_filled(&self, end_pts: gst::ClockTime) -> bool {
+        self.sinkpad.is_eos()
+            || self
+                .queued_gops
+                .iter()
+                .flat_map(|gop| gop.buffers.iter())
+                .any(|buffer| buffer.pts >= end_pts)
+    }
+}
+
 #[derive(Default)]
 struct State {
-    segment: Option<gst::FormattedSegment<gst::ClockTime>>,
-    caps: Option<gst::Caps>,
-    intra_only: bool,
+    streams: Vec<Stream>,
 
     // Created once we received caps and kept up to date with the caps,
     // sent as part of the buffer list for the first fragment.
     stream_header: Option<gst::Buffer>,
 
     sequence_number: u32,
-    queued_gops: VecDeque<Gop>,
-    // Duration of all GOPs except for the newest one that is still being filled
-    queued_duration: gst::ClockTime,
-
-    // Difference between the first DTS and 0 in case of negative DTS
-    dts_offset: Option<gst::ClockTime>,
 
-    last_force_keyunit_time: Option<gst::ClockTime>,
+    // Start PTS of the next fragment, known once all streams have their first GOP queued
+    fragment_start_pts: Option<gst::ClockTime>,
+    // Start PTS of the next chunk if in chunk mode
+    chunk_start_pts: Option<gst::ClockTime>,
 
     // Fragment tracking for mfra
     current_offset: u64,
     fragment_offsets: Vec<super::FragmentOffset>,
 
+    // Event messages for the next fragments
+    pending_emsgs: Vec<super::Emsg>,
+
+    // Protection system specific headers from upstream for the moov
+    pssh: Vec<gst::Buffer>,
+
     // Start / end PTS of the whole stream
     earliest_pts: Option<gst::ClockTime>,
     end_pts: Option<gst::ClockTime>,
 }
 
+/// Buffers and timing information of all streams for the next fragment or chunk.
+struct DrainedBuffers {
+    streams: Vec<(gst::Caps, Option<super::FragmentTimingInfo>)>,
+    buffers: Vec<Buffer>,
+    /// Whether this starts a new fragment, always the case if not in chunk mode
+    fragment_start: bool,
+}
+
+/// Key ID, key and IV for encrypting the stream of a pad.
+struct StreamKey {
+    pad: gst_base::AggregatorPad,
+    key_id: Option<gst::Buffer>,
+    key: Option<gst::Buffer>,
+    iv: Option<gst::Buffer>,
+}
+
+#[derive(Default)]
 pub(crate) struct FMP4Mux {
-    srcpad: gst::Pad,
-    sinkpad: gst::Pad,
     state: Mutex<State>,
     settings: Mutex<Settings>,
 }
@@ -110,39 +207,32 @@ pub(crate) struct FMP4Mux {
 impl FMP4Mux {
     fn queue_input(
         &self,
-        element: &super::FMP4Mux,
-        state: &mut State,
+        _element: &super::FMP4Mux,
+        idx: usize,
+        stream: &mut Stream,
+        segment: &gst::FormattedSegment<gst::ClockTime>,
         buffer: gst::Buffer,
     ) -> Result<(), gst::FlowError> {
-        gst_trace!(CAT, obj: element, "Handling buffer {:?}", buffer);
-
-        let segment = match state.segment {
-            Some(ref segment) => segment,
-            None => {
-                gst_error!(CAT, obj: element, "Got buffer before segment");
-                return Err(gst::FlowError::Error);
-            }
-        };
+        gst_trace!(CAT, obj: &stream.sinkpad, "Handling buffer {:?}", buffer);
 
-        if state.caps.is_none() {
-            gst_error!(CAT, obj: element, "Got buffer before caps");
-            return Err(gst::FlowError::NotNegotiated);
-        }
-
-        let intra_only = state.intra_only;
+        let intra_only = stream.intra_only;
 
         if !intra_only && buffer.dts().is_none() {
-            gst_error!(CAT, obj: element, "Require DTS for video streams");
+            gst_error!(CAT, obj: &stream.sinkpad, "Require DTS for video streams");
             return Err(gst::FlowError::Error);
         }
 
         if intra_only && buffer.flags().contains(gst::BufferFlags::DELTA_UNIT) {
-            gst_error!(CAT, obj: element, "Intra-only stream with delta units");
+            gst_error!(
+                CAT,
+                obj: &stream.sinkpad,
+                "Intra-only stream with delta units"
+            );
             return Err(gst::FlowError::Error);
         }
 
         let pts = buffer.pts().ok_or_else(|| {
-            gst_error!(CAT, obj: element, "Require timestamped buffers");
+            gst_error!(CAT, obj: &stream.sinkpad, "Require timestamped buffers");
             gst::FlowError::Error
         })?;
         let duration = buffer.duration();
@@ -150,11 +240,15 @@ impl FMP4Mux {
 
         let pts = match segment.to_running_time_full(pts) {
             (_, None) => {
-                gst_error!(CAT, obj: element, "Couldn't convert PTS to running time");
+                gst_error!(
+                    CAT,
+                    obj: &stream.sinkpad,
+                    "Couldn't convert PTS to running time"
+                );
                 return Err(gst::FlowError::Error);
             }
             (pts_signum, _) if pts_signum < 0 => {
-                gst_error!(CAT, obj: element, "Negative PTSs are not supported");
+                gst_error!(CAT, obj: &stream.sinkpad, "Negative PTSs are not supported");
                 return Err(gst::FlowError::Error);
             }
             (_, Some(pts)) => pts,
@@ -164,13 +258,13 @@ impl FMP4Mux {
             (_, None) => {
                 gst_error!(
                     CAT,
-                    obj: element,
+                    obj: &stream.sinkpad,
                     "Couldn't convert end PTS to running time"
                 );
                 return Err(gst::FlowError::Error);
             }
             (pts_signum, _) if pts_signum < 0 => {
-                gst_error!(CAT, obj: element, "Negative PTSs are not supported");
+                gst_error!(CAT, obj: &stream.sinkpad, "Negative PTSs are not supported");
                 return Err(gst::FlowError::Error);
             }
             (_, Some(pts)) => pts,
@@ -185,24 +279,28 @@ impl FMP4Mux {
 
             let dts = match segment.to_running_time_full(dts) {
                 (_, None) => {
-                    gst_error!(CAT, obj: element, "Couldn't convert DTS to running time");
+                    gst_error!(
+                        CAT,
+                        obj: &stream.sinkpad,
+                        "Couldn't convert DTS to running time"
+                    );
                     return Err(gst::FlowError::Error);
                 }
                 (pts_signum, Some(dts)) if pts_signum < 0 => {
-                    if state.dts_offset.is_none() {
-                        state.dts_offset = Some(dts);
+                    if stream.dts_offset.is_none() {
+                        stream.dts_offset = Some(dts);
                     }
 
-                    let dts_offset = state.dts_offset.unwrap();
+                    let dts_offset = stream.dts_offset.unwrap();
                     if dts > dts_offset {
-                        gst_warning!(CAT, obj: element, "DTS before first DTS");
+                        gst_warning!(CAT, obj: &stream.sinkpad, "DTS before first DTS");
                         gst::ClockTime::ZERO
                     } else {
                         dts_offset - dts
                     }
                 }
                 (_, Some(dts)) => {
-                    if let Some(dts_offset) = state.dts_offset {
+                    if let Some(dts_offset) = stream.dts_offset {
                         dts + dts_offset
                     } else {
                         dts
@@ -214,26 +312,26 @@ impl FMP4Mux {
                 (_, None) => {
                     gst_error!(
                         CAT,
-                        obj: element,
+                        obj: &stream.sinkpad,
                         "Couldn't convert end DTS to running time"
                     );
                     return Err(gst::FlowError::Error);
                 }
                 (pts_signum, Some(dts)) if pts_signum < 0 => {
-                    if state.dts_offset.is_none() {
-                        state.dts_offset = Some(dts);
+                    if stream.dts_offset.is_none() {
+                        stream.dts_offset = Some(dts);
                     }
 
-                    let dts_offset = state.dts_offset.unwrap();
+                    let dts_offset = stream.dts_offset.unwrap();
                     if dts > dts_offset {
-                        gst_warning!(CAT, obj: element, "End DTS before first DTS");
+                        gst_warning!(CAT, obj: &stream.sinkpad, "End DTS before first DTS");
                         gst::ClockTime::ZERO
                     } else {
                         dts_offset - dts
                     }
                 }
                 (_, Some(dts)) => {
-                    if let Some(dts_offset) = state.dts_offset {
+                    if let Some(dts_offset) = stream.dts_offset {
                         dts + dts_offset
                     } else {
                         dts
@@ -246,7 +344,7 @@ impl FMP4Mux {
         if !buffer.flags().contains(gst::BufferFlags::DELTA_UNIT) {
             gst_debug!(
                 CAT,
-                obj: element,
+                obj: &stream.sinkpad,
                 "Starting new GOP at PTS {} DTS {}",
                 pts,
                 dts.display()
@@ -255,20 +353,24 @@ impl FMP4Mux {
             let gop = Gop {
                 start_pts: pts,
                 start_dts: dts,
-                start_dts_position: if intra_only { None } else { buffer.dts() },
                 earliest_pts: pts,
-                earliest_pts_position: buffer.pts().expect("no PTS"),
                 final_earliest_pts: intra_only,
                 end_pts,
                 end_dts,
-                buffers: vec![Buffer { buffer, pts, dts }],
+                buffers: vec![Buffer {
+                    idx,
+                    buffer,
+                    pts,
+                    dts,
+                    encryption: None,
+                }],
             };
-            state.queued_gops.push_front(gop);
+            stream.queued_gops.push_front(gop);
 
-            if let Some(prev_gop) = state.queued_gops.get_mut(1) {
+            if let Some(prev_gop) = stream.queued_gops.get_mut(1) {
                 gst_debug!(
                     CAT,
-                    obj: element,
+                    obj: &stream.sinkpad,
                     "Updating previous GOP starting at PTS {} to end PTS {} DTS {}",
                     prev_gop.earliest_pts,
                     pts,
@@ -283,64 +385,46 @@ impl FMP4Mux {
                     if !intra_only {
                         gst_debug!(
                             CAT,
-                            obj: element,
+                            obj: &stream.sinkpad,
                             "Previous GOP has final earliest PTS at {}",
                             prev_gop.earliest_pts
                         );
                     }
 
                     prev_gop.final_earliest_pts = true;
-
-                    state.queued_duration =
-                        prev_gop.end_pts - state.queued_gops.back().unwrap().earliest_pts;
-                    gst_debug!(
-                        CAT,
-                        obj: element,
-                        "Queued duration updated to {}",
-                        state.queued_duration
-                    );
-                } else if intra_only {
-                    state.queued_duration =
-                        prev_gop.end_pts - state.queued_gops.back().unwrap().earliest_pts;
-                    gst_debug!(
-                        CAT,
-                        obj: element,
-                        "Queued duration updated to {}",
-                        state.queued_duration
-                    );
                 }
             }
-        } else if let Some(gop) = state.queued_gops.front_mut() {
+        } else if let Some(gop) = stream.queued_gops.front_mut() {
             assert!(!intra_only);
 
             // We require DTS for non-intra-only streams
             let dts = dts.unwrap();
             let end_dts = end_dts.unwrap();
-            let pts_position = buffer.pts().expect("no PTS");
 
             gop.end_pts = std::cmp::max(gop.end_pts, end_pts);
             gop.end_dts = Some(std::cmp::max(gop.end_dts.expect("no end DTS"), end_dts));
             gop.buffers.push(Buffer {
+                idx,
                 buffer,
                 pts,
                 dts: Some(dts),
+                encryption: None,
             });
 
             if gop.earliest_pts > pts && !gop.final_earliest_pts {
                 gst_debug!(
                     CAT,
-                    obj: element,
+                    obj: &stream.sinkpad,
                     "Updating current GOP earliest PTS from {} to {}",
                     gop.earliest_pts,
                     pts
                 );
                 gop.earliest_pts = pts;
-                gop.earliest_pts_position = pts_position;
 
-                if let Some(prev_gop) = state.queued_gops.get_mut(1) {
+                if let Some(prev_gop) = stream.queued_gops.get_mut(1) {
                     gst_debug!(
                         CAT,
-                        obj: element,
+                        obj: &stream.sinkpad,
                         "Updating previous GOP starting PTS {} end time from {} to {}",
                         pts,
                         prev_gop.end_pts,
@@ -350,7 +434,7 @@ impl FMP4Mux {
                 }
             }
 
-            let gop = state.queued_gops.front_mut().unwrap();
+            let gop = stream.queued_gops.front_mut().unwrap();
 
             // The earliest PTS is known when the current DTS is bigger or equal to the first
             // PTS that was observed in this GOP. If there was another frame later that had a
@@ -359,27 +443,16 @@ impl FMP4Mux {
             if gop.start_pts <= dts && !gop.final_earliest_pts {
                 gst_debug!(
                     CAT,
-                    obj: element,
+                    obj: &stream.sinkpad,
                     "GOP has final earliest PTS at {}",
                     gop.earliest_pts
                 );
                 gop.final_earliest_pts = true;
-
-                if let Some(prev_gop) = state.queued_gops.get_mut(1) {
-                    state.queued_duration =
-                        prev_gop.end_pts - state.queued_gops.back().unwrap().earliest_pts;
-                    gst_debug!(
-                        CAT,
-                        obj: element,
-                        "Queued duration updated to {}",
-                        state.queued_duration
-                    );
-                }
             }
         } else {
             gst_warning!(
                 CAT,
-                obj: element,
+                obj: &stream.sinkpad,
                 "Waiting for keyframe at the beginning of the stream"
             );
         }
@@ -389,657 +462,1182 @@ impl FMP4Mux {
 
     fn create_force_keyunit_event(
         &self,
-        element: &super::FMP4Mux,
-        state: &mut State,
+        _element: &super::FMP4Mux,
+        stream: &mut Stream,
         settings: &Settings,
+        fragment_start_pts: gst::ClockTime,
         pts: gst::ClockTime,
-    ) -> Result<Option<gst::Event>, gst::FlowError> {
-        let segment = state.segment.as_ref().expect("no segment");
+    ) -> Option<gst::Event> {
+        // Intra-only streams don't need any keyframes to be requested
+        if stream.intra_only {
+            return None;
+        }
 
-        // If we never sent a force-keyunit event then wait until the earliest PTS of the first GOP
-        // is known and send one now.
+        // If we never sent a force-keyunit event then send one for the end of the first fragment
+        // now.
         //
         // Otherwise if the current PTS is a fragment duration in the future, send the next one
         // now.
-        let oldest_gop = state.queued_gops.back().unwrap();
-        let earliest_pts = oldest_gop.earliest_pts;
-        let pts = segment.to_running_time(pts).expect("no running time");
+        //
+        // This uses the same fragment start for all streams so that all their keyframes are
+        // requested at the same running times.
+        let fku_running_time = match stream.last_force_keyunit_time {
+            None => {
+                let fku_running_time = fragment_start_pts + settings.fragment_duration;
+                gst_debug!(
+                    CAT,
+                    obj: &stream.sinkpad,
+                    "Sending first force-keyunit event for running time {}",
+                    fku_running_time
+                );
 
-        if state.last_force_keyunit_time.is_none() && oldest_gop.final_earliest_pts {
-            let fku_running_time = earliest_pts + settings.fragment_duration;
-            gst_debug!(
-                CAT,
-                obj: element,
-                "Sending first force-keyunit event for running time {}",
                 fku_running_time
-            );
-            state.last_force_keyunit_time = Some(fku_running_time);
+            }
+            Some(last_force_keyunit_time) if last_force_keyunit_time <= pts => {
+                let fku_running_time = last_force_keyunit_time + settings.fragment_duration;
+                gst_debug!(
+                    CAT,
+                    obj: &stream.sinkpad,
+                    "Sending force-keyunit event for running time {}",
+                    fku_running_time
+                );
 
-            return Ok(Some(
-                gst_video::UpstreamForceKeyUnitEvent::builder()
-                    .running_time(fku_running_time)
-                    .all_headers(true)
-                    .build(),
-            ));
-        } else if state.last_force_keyunit_time.is_some()
-            && state.last_force_keyunit_time <= Some(pts)
-        {
-            let fku_running_time =
-                state.last_force_keyunit_time.unwrap() + settings.fragment_duration;
-            gst_debug!(
-                CAT,
-                obj: element,
-                "Sending force-keyunit event for running time {}",
                 fku_running_time
-            );
-            state.last_force_keyunit_time = Some(fku_running_time);
+            }
+            Some(_) => return None,
+        };
 
-            return Ok(Some(
-                gst_video::UpstreamForceKeyUnitEvent::builder()
-                    .running_time(fku_running_time)
-                    .all_headers(true)
-                    .build(),
-            ));
+        stream.last_force_keyunit_time = Some(fku_running_time);
+
+        Some(
+            gst_video::UpstreamForceKeyUnitEvent::builder()
+                .running_time(fku_running_time)
+                .all_headers(true)
+                .build(),
+        )
+    }
+
+    /// Index of the stream that decides on the fragment boundaries.
+    ///
+    /// This is the first stream (i.e. the first video stream if there is any) that still has data
+    /// queued or can still get new data.
+    fn reference_stream(state: &State) -> Option<usize> {
+        state
+            .streams
+            .iter()
+            .position(|stream| !stream.queued_gops.is_empty() || !stream.sinkpad.is_eos())
+    }
+
+    /// End PTS of the next fragment, or `None` if the reference stream does not have enough data
+    /// queued yet.
+    ///
+    /// Fragments end at a GOP boundary of the reference stream and are at most the configured
+    /// fragment duration long, unless a single GOP is already longer than that. All other
+    /// streams are cut at the same PTS.
+    fn fragment_end_pts(state: &State, settings: &Settings) -> Option<gst::ClockTime> {
+        let fragment_start_pts = state.fragment_start_pts?;
+        let target_end_pts = fragment_start_pts + settings.fragment_duration;
+
+        let stream = &state.streams[Self::reference_stream(state)?];
+        let complete_end_pts = stream.complete_end_pts()?;
+
+        if complete_end_pts < target_end_pts && !stream.sinkpad.is_eos() {
+            return None;
+        }
+
+        let mut fragment_end_pts = None;
+        for gop in stream.queued_gops.iter().rev() {
+            if gop.end_pts > complete_end_pts
+                || (fragment_end_pts.is_some() && gop.end_pts > target_end_pts)
+            {
+                break;
+            }
+
+            fragment_end_pts = Some(gop.end_pts);
+
+            if gop.end_pts >= target_end_pts {
+                break;
+            }
         }
 
-        Ok(None)
+        fragment_end_pts
     }
 
-    fn drain(
+    /// Pops buffers from all sink pads until each stream has enough data queued for the next
+    /// fragment, or until no more buffers are available.
+    ///
+    /// Streams that have enough data queued are not consumed from further so that upstream is
+    /// blocked and the streams are interleaved.
+    fn queue_available_buffers(
         &self,
         element: &super::FMP4Mux,
         state: &mut State,
         settings: &Settings,
-        at_eos: bool,
-    ) -> Result<Option<gst::BufferList>, gst::FlowError> {
-        let class = element.class();
-
-        if state.queued_duration < settings.fragment_duration && !at_eos {
-            return Ok(None);
-        }
+        upstream_events: &mut Vec<(gst_base::AggregatorPad, gst::Event)>,
+    ) -> Result<(), gst::FlowError> {
+        for idx in 0..state.streams.len() {
+            loop {
+                let fragment_start_pts = state.fragment_start_pts;
+
+                // Until the start of the first fragment is known only queue up the first GOP of
+                // each stream. Afterwards the reference stream is queued up to the fragment
+                // duration and all other streams up to the end of its fragment. In chunk mode
+                // all streams are queued up to the end of the next chunk.
+                let filled = match fragment_start_pts {
+                    None => state.streams[idx]
+                        .queued_gops
+                        .back()
+                        .map_or(false, |gop| gop.final_earliest_pts),
+                    Some(_) if settings.chunk_duration.is_some() => {
+                        match Self::chunk_end_pts(state, settings) {
+                            Some((chunk_end_pts, _)) => {
+                                state.streams[idx].is_chunk_filled(chunk_end_pts)
+                            }
+                            None => false,
+                        }
+                    }
+                    Some(fragment_start_pts) => {
+                        let end_pts = if Self::reference_stream(state) == Some(idx) {
+                            fragment_start_pts + settings.fragment_duration
+                        } else {
+                            Self::fragment_end_pts(state, settings)
+                                .unwrap_or(fragment_start_pts + settings.fragment_duration)
+                        };
+
+                        state.streams[idx].is_filled(end_pts)
+                    }
+                };
 
-        assert!(at_eos || state.queued_gops.get(1).map(|gop| gop.final_earliest_pts) == Some(true));
+                if filled {
+                    break;
+                }
 
-        // At EOS, finalize all GOPs and drain them out. Otherwise if the queued duration is
-        // equal to the fragment duration then drain out all complete GOPs, otherwise all
-        // except for the newest complete GOP.
-        let drain_gops = if at_eos {
-            gst_info!(CAT, obj: element, "Draining at EOS");
-            state.queued_duration = gst::ClockTime::ZERO;
-            state
-                .queued_gops
-                .drain(..)
-                .map(|mut gop| {
-                    gop.final_earliest_pts = true;
-                    gop
-                })
-                .collect::<Vec<_>>()
-        } else if state.queued_duration == settings.fragment_duration
-            || state.queued_gops.len() == 2
-        {
-            state.queued_duration = gst::ClockTime::ZERO;
-            state.queued_gops.drain(1..).collect::<Vec<_>>()
-        } else {
-            let gops = state.queued_gops.drain(2..).collect::<Vec<_>>();
+                let stream = &mut state.streams[idx];
 
-            let gop = state.queued_gops.front().unwrap();
-            if gop.final_earliest_pts {
-                let prev_gop = state.queued_gops.get(1).unwrap();
-                state.queued_duration =
-                    prev_gop.end_pts - state.queued_gops.back().unwrap().earliest_pts;
-            } else {
-                state.queued_duration = gst::ClockTime::ZERO;
-            }
+                let buffer = match stream.sinkpad.pop_buffer() {
+                    None => break,
+                    Some(buffer) => buffer,
+                };
 
-            gops
-        };
+                // Skip in-band headers, the codec configuration is part of the sample entry
+                if buffer.flags().contains(gst::BufferFlags::HEADER)
+                    && matches!(
+                        stream.caps.structure(0).unwrap().name(),
+                        "audio/x-opus" | "audio/x-flac"
+                    )
+                {
+                    gst_trace!(CAT, obj: &stream.sinkpad, "Dropping header buffer");
+                    continue;
+                }
 
-        let mut buffer_list = None;
+                let is_gap = buffer.flags().contains(gst::BufferFlags::GAP)
+                    && buffer.flags().contains(gst::BufferFlags::DROPPABLE)
+                    && buffer.size() == 0;
 
-        if !drain_gops.is_empty() {
-            let earliest_pts = drain_gops.last().unwrap().earliest_pts;
-            let earliest_pts_position = drain_gops.last().unwrap().earliest_pts_position;
-            let start_dts = drain_gops.last().unwrap().start_dts;
-            let start_dts_position = drain_gops.last().unwrap().start_dts_position;
-            let end_pts = drain_gops[0].end_pts;
-            let end_dts = drain_gops[0].end_dts;
-            let dts_offset = state.dts_offset;
+                // Subtitle buffers are converted to samples, and gaps into empty samples to keep
+                // the sparse streams going. Gap buffers of all other streams are skipped.
+                let buffers = match stream.caps.structure(0).unwrap().name() {
+                    "application/x-subtitle-vtt" | "application/x-subtitle-vtt-fragmented" => {
+                        Self::create_wvtt_buffers(stream, buffer, is_gap)?
+                    }
+                    "application/ttml+xml" if is_gap => {
+                        Self::create_empty_ttml_buffer(buffer).into_iter().collect()
+                    }
+                    _ if is_gap => {
+                        gst_trace!(CAT, obj: &stream.sinkpad, "Dropping gap buffer");
+                        continue;
+                    }
+                    _ => vec![buffer],
+                };
 
-            gst_info!(
-                CAT,
-                obj: element,
-                "Draining {} worth of buffers starting at PTS {} DTS {}, DTS offset {}",
-                end_pts - earliest_pts,
-                earliest_pts,
-                start_dts.display(),
-                dts_offset.display(),
-            );
+                let segment = match stream.sinkpad.segment().downcast::<gst::ClockTime>() {
+                    Ok(segment) => segment,
+                    Err(_) => {
+                        gst_error!(CAT, obj: &stream.sinkpad, "Got buffer before segment");
+                        return Err(gst::FlowError::Error);
+                    }
+                };
 
-            let mut fmp4_header = None;
-            if state.sequence_number == 0 {
-                let mut buffer = state.stream_header.as_ref().unwrap().copy();
-                {
-                    let buffer = buffer.get_mut().unwrap();
+                let pts = buffers.last().and_then(|buffer| buffer.pts());
 
-                    buffer.set_pts(earliest_pts_position);
-                    buffer.set_dts(start_dts_position);
+                // Queue up the buffers and update GOP tracking state
+                for buffer in buffers {
+                    self.queue_input(element, idx, stream, &segment, buffer)?;
+                }
 
-                    // Header is DISCONT|HEADER
-                    buffer.set_flags(gst::BufferFlags::DISCONT | gst::BufferFlags::HEADER);
+                // If we have a PTS with this buffer, check if a new force-keyunit event for the
+                // next fragment start has to be created
+                if let (Some(fragment_start_pts), Some(pts)) = (
+                    fragment_start_pts,
+                    pts.and_then(|pts| segment.to_running_time(pts)),
+                ) {
+                    if let Some(event) = self.create_force_keyunit_event(
+                        element,
+                        stream,
+                        settings,
+                        fragment_start_pts,
+                        pts,
+                    ) {
+                        upstream_events.push((stream.sinkpad.clone(), event));
+                    }
                 }
+            }
+        }
 
-                fmp4_header = Some(buffer);
+        Ok(())
+    }
 
-                state.earliest_pts = Some(earliest_pts);
-                state.sequence_number = 1;
+    /// Converts a WebVTT cue into a `wvtt` sample, preceded by an empty sample if there is a gap
+    /// since the end of the previous cue.
+    ///
+    /// Gap buffers are converted to empty samples and WebVTT headers are dropped.
+    fn create_wvtt_buffers(
+        stream: &mut Stream,
+        buffer: gst::Buffer,
+        is_gap: bool,
+    ) -> Result<Vec<gst::Buffer>, gst::FlowError> {
+        let pts = match buffer.pts() {
+            // Error out later when queueing the buffer
+            None => return Ok(vec![buffer]),
+            Some(pts) => pts,
+        };
+        let duration = buffer.duration();
+
+        let map = if is_gap {
+            if duration.is_none() {
+                gst_trace!(CAT, obj: &stream.sinkpad, "Dropping gap buffer without duration");
+                return Ok(vec![]);
             }
 
-            let mut buffers = drain_gops
-                .into_iter()
-                .rev()
-                .map(|gop| gop.buffers)
-                .flatten()
-                .collect::<Vec<Buffer>>();
+            None
+        } else {
+            let map = buffer.map_readable().map_err(|_| {
+                gst_error!(CAT, obj: &stream.sinkpad, "Failed to map buffer");
+                gst::FlowError::Error
+            })?;
+
+            // Headers of fragmented WebVTT, the configuration is part of the sample entry
+            if map.starts_with(b"WEBVTT") {
+                gst_trace!(CAT, obj: &stream.sinkpad, "Dropping WebVTT header");
+                return Ok(vec![]);
+            }
 
-            // TODO: Write prft boxes before moof
-            // TODO: Write sidx boxes before moof and rewrite once offsets are known
+            Some(map)
+        };
 
-            let sequence_number = state.sequence_number;
-            state.sequence_number += 1;
-            let (mut fmp4_fragment_header, moof_offset) =
-                boxes::create_fmp4_fragment_header(super::FragmentHeaderConfiguration {
-                    variant: class.as_ref().variant,
-                    sequence_number,
-                    caps: state.caps.as_ref().unwrap(),
-                    buffers: &buffers,
-                    earliest_pts,
-                    start_dts,
+        let mut buffers = Vec::with_capacity(2);
+
+        if let Some(end_pts) = stream.text_end_pts {
+            if pts > end_pts {
+                gst_trace!(
+                    CAT,
+                    obj: &stream.sinkpad,
+                    "Filling gap from {} to {} with empty cue",
                     end_pts,
-                    end_dts,
-                    dts_offset,
-                })
-                .map_err(|err| {
-                    gst_error!(
-                        CAT,
-                        obj: element,
-                        "Failed to create FMP4 fragment header: {}",
-                        err
-                    );
+                    pts
+                );
+
+                let mut empty = boxes::create_wvtt_sample(None).map_err(|err| {
+                    gst_error!(CAT, obj: &stream.sinkpad, "Failed to create sample: {}", err);
                     gst::FlowError::Error
                 })?;
-
-            {
-                let buffer = fmp4_fragment_header.get_mut().unwrap();
-                buffer.set_pts(earliest_pts_position);
-                buffer.set_dts(start_dts_position);
-                buffer.set_duration(end_pts.checked_sub(earliest_pts));
-
-                // Fragment header is HEADER
-                buffer.set_flags(gst::BufferFlags::HEADER);
-
-                // Copy metas from the first actual buffer to the fragment header. This allows
-                // getting things like the reference timestamp meta or the timecode meta to identify
-                // the fragment.
-                let _ = buffers[0]
-                    .buffer
-                    .copy_into(buffer, gst::BufferCopyFlags::META, 0, None);
+                {
+                    let empty = empty.get_mut().unwrap();
+                    empty.set_pts(end_pts);
+                    empty.set_duration(pts - end_pts);
+                }
+                buffers.push(empty);
             }
+        }
 
-            let moof_offset = state.current_offset
-                + fmp4_header.as_ref().map(|h| h.size()).unwrap_or(0) as u64
-                + moof_offset;
+        let mut sample = boxes::create_wvtt_sample(map.as_deref()).map_err(|err| {
+            gst_error!(CAT, obj: &stream.sinkpad, "Failed to create sample: {}", err);
+            gst::FlowError::Error
+        })?;
+        {
+            let sample = sample.get_mut().unwrap();
+            sample.set_pts(pts);
+            sample.set_duration(duration);
+            let _ = buffer.copy_into(sample, gst::BufferCopyFlags::META, 0, None);
+        }
+        buffers.push(sample);
 
-            let buffers_len = buffers.len();
-            for (idx, buffer) in buffers.iter_mut().enumerate() {
-                // Fix up buffer flags, all other buffers are DELTA_UNIT
-                let buffer_ref = buffer.buffer.make_mut();
-                buffer_ref.unset_flags(gst::BufferFlags::all());
-                buffer_ref.set_flags(gst::BufferFlags::DELTA_UNIT);
+        stream.text_end_pts = duration.map(|duration| pts + duration);
 
-                // Set the marker flag for the last buffer of the segment
-                if idx == buffers_len - 1 {
-                    buffer_ref.set_flags(gst::BufferFlags::MARKER);
-                }
-            }
+        Ok(buffers)
+    }
 
-            buffer_list = Some(
-                fmp4_header
-                    .into_iter()
-                    .chain(Some(fmp4_fragment_header))
-                    .chain(buffers.into_iter().map(|buffer| buffer.buffer))
-                    .inspect(|b| {
-                        state.current_offset += b.size() as u64;
-                    })
-                    .collect::<gst::BufferList>(),
-            );
+    /// Converts a gap buffer into an empty TTML document with the same timestamps.
+    fn create_empty_ttml_buffer(buffer: gst::Buffer) -> Option<gst::Buffer> {
+        const EMPTY_TTML_DOCUMENT: &[u8] = b"<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n<tt xmlns=\"http://www.w3.org/ns/ttml\"/>\n";
 
-            state.fragment_offsets.push(super::FragmentOffset {
-                time: earliest_pts,
-                offset: moof_offset,
-            });
-            state.end_pts = Some(end_pts);
+        // Without duration it's not known until when the document is valid
+        let duration = buffer.duration()?;
 
-            gst_debug!(
-                CAT,
-                obj: element,
-                "Queued duration updated to {} after draining",
-                state.queued_duration
-            );
+        let mut empty = gst::Buffer::from_slice(EMPTY_TTML_DOCUMENT);
+        {
+            let empty = empty.get_mut().unwrap();
+            empty.set_pts(buffer.pts());
+            empty.set_duration(duration);
         }
 
-        if settings.write_mfra && at_eos {
-            match boxes::create_mfra(state.caps.as_ref().unwrap(), &state.fragment_offsets) {
-                Ok(mut mfra) => {
-                    {
-                        let mfra = mfra.get_mut().unwrap();
-                        // mfra is HEADER|DELTA_UNIT like other boxes
-                        mfra.set_flags(gst::BufferFlags::HEADER | gst::BufferFlags::DELTA_UNIT);
-                    }
+        Some(empty)
+    }
 
-                    if buffer_list.is_none() {
-                        buffer_list = Some(gst::BufferList::new_sized(1));
+    /// Parses an `emsg` custom downstream event.
+    ///
+    /// The presentation time is taken from the `pts` field, or the current position of the pad if
+    /// there is none.
+    fn parse_emsg(pad: &gst_base::AggregatorPad, s: &gst::StructureRef) -> Option<super::Emsg> {
+        let segment = pad.segment().downcast::<gst::ClockTime>().ok()?;
+
+        let pts = s
+            .get_optional::<gst::ClockTime>("pts")
+            .ok()?
+            .or_else(|| segment.position());
+        let presentation_time = pts
+            .and_then(|pts| segment.to_running_time(pts))
+            .unwrap_or(gst::ClockTime::ZERO);
+
+        Some(super::Emsg {
+            scheme_id_uri: s.get::<String>("scheme-id-uri").ok()?,
+            value: s.get_optional::<String>("value").ok()?.unwrap_or_default(),
+            presentation_time,
+            duration: s.get_optional::<gst::ClockTime>("duration").ok()?,
+            id: s.get_optional::<u32>("id").ok()?.unwrap_or(0),
+            message_data: s.get_optional::<gst::Buffer>("message-data").ok()?,
+        })
+    }
+
+    /// Once all streams have their first GOP queued, or are EOS, the start of the first fragment
+    /// is the earliest PTS of all streams.
+    fn update_fragment_start_pts(&self, element: &super::FMP4Mux, state: &mut State) -> bool {
+        if state.fragment_start_pts.is_some() {
+            return false;
+        }
+
+        let mut fragment_start_pts = None;
+        for stream in &state.streams {
+            match stream.queued_gops.back() {
+                None if stream.sinkpad.is_eos() => continue,
+                Some(gop) if gop.final_earliest_pts || stream.sinkpad.is_eos() => {
+                    if fragment_start_pts.map_or(true, |start| gop.earliest_pts < start) {
+                        fragment_start_pts = Some(gop.earliest_pts);
                     }
-                    buffer_list.as_mut().unwrap().get_mut().unwrap().add(mfra);
-                }
-                Err(err) => {
-                    gst_error!(CAT, obj: element, "Failed to create mfra box: {}", err);
                 }
+                _ => return false,
             }
         }
 
-        // TODO: Write edit list at EOS
-        // TODO: Rewrite bitrates at EOS
-
-        Ok(buffer_list)
+        if let Some(fragment_start_pts) = fragment_start_pts {
+            gst_debug!(
+                CAT,
+                obj: element,
+                "Starting first fragment at PTS {}",
+                fragment_start_pts
+            );
+            state.fragment_start_pts = Some(fragment_start_pts);
+            state.chunk_start_pts = Some(fragment_start_pts);
+            true
+        } else {
+            false
+        }
     }
 
-    fn update_header(
-        &self,
+    /// Takes all GOPs of all streams that start before the end of the next fragment, or all GOPs
+    /// at EOS.
+    fn drain_fragment_buffers(
         element: &super::FMP4Mux,
         state: &mut State,
         settings: &Settings,
         at_eos: bool,
-    ) -> Result<Option<(gst::BufferList, gst::Caps)>, gst::FlowError> {
-        let class = element.class();
-        let variant = class.as_ref().variant;
+    ) -> Option<DrainedBuffers> {
+        // At EOS, finalize all GOPs and drain them out. Otherwise drain all GOPs that start
+        // before the end of the next fragment once all streams have enough data queued.
+        let fragment_end_pts = if at_eos {
+            gst_info!(CAT, obj: element, "Draining at EOS");
+            None
+        } else {
+            let fragment_end_pts = Self::fragment_end_pts(state, settings)?;
 
-        if settings.header_update_mode == super::HeaderUpdateMode::None && at_eos {
-            return Ok(None);
-        }
+            if !state
+                .streams
+                .iter()
+                .all(|stream| stream.is_filled(fragment_end_pts))
+            {
+                return None;
+            }
 
-        assert!(!at_eos || state.queued_gops.is_empty());
+            Some(fragment_end_pts)
+        };
 
-        let duration = state
-            .end_pts
-            .opt_checked_sub(state.earliest_pts)
-            .ok()
-            .flatten();
+        let mut drain_streams = Vec::with_capacity(state.streams.len());
+        let mut drain_buffers = Vec::new();
 
-        let mut buffer = boxes::create_fmp4_header(super::HeaderConfiguration {
-            variant,
-            update: at_eos,
-            caps: state.caps.as_ref().unwrap(),
-            write_mehd: settings.write_mehd,
-            duration: if at_eos { duration } else { None },
-        })
-        .map_err(|err| {
-            gst_error!(CAT, obj: element, "Failed to create FMP4 header: {}", err);
-            gst::FlowError::Error
-        })?;
+        for stream in &mut state.streams {
+            let mut gops = Vec::new();
+            while let Some(gop) = stream.queued_gops.back() {
+                if fragment_end_pts.map_or(false, |end_pts| gop.earliest_pts >= end_pts) {
+                    break;
+                }
 
-        {
-            let buffer = buffer.get_mut().unwrap();
+                let mut gop = stream.queued_gops.pop_back().unwrap();
+                gop.final_earliest_pts = true;
+                gops.push(gop);
+            }
 
-            // No timestamps
+            if gops.is_empty() {
+                gst_debug!(CAT, obj: &stream.sinkpad, "No buffers to drain");
+                drain_streams.push((stream.caps.clone(), None));
+                continue;
+            }
 
-            // Header is DISCONT|HEADER
-            buffer.set_flags(gst::BufferFlags::DISCONT | gst::BufferFlags::HEADER);
-        }
+            let first_gop = gops.first().unwrap();
+            let last_gop = gops.last().unwrap();
+            let earliest_pts = first_gop.earliest_pts;
+            let start_dts = first_gop.start_dts;
+            let end_pts = last_gop.end_pts;
+            let end_dts = last_gop.end_dts;
+            let dts_offset = stream.dts_offset;
 
-        // Remember stream header for later
-        state.stream_header = Some(buffer.clone());
+            gst_info!(
+                CAT,
+                obj: &stream.sinkpad,
+                "Draining {} worth of buffers starting at PTS {} DTS {}, DTS offset {}",
+                end_pts.saturating_sub(earliest_pts),
+                earliest_pts,
+                start_dts.display(),
+                dts_offset.display(),
+            );
 
-        let variant = match variant {
-            super::Variant::ISO | super::Variant::DASH => "iso-fragmented",
-            super::Variant::CMAF => "cmaf",
-        };
-        let caps = gst::Caps::builder("video/quicktime")
-            .field("variant", variant)
-            .field("streamheader", gst::Array::new(&[&buffer]))
-            .build();
+            drain_streams.push((
+                stream.caps.clone(),
+                Some(super::FragmentTimingInfo {
+                    earliest_pts,
+                    start_dts,
+                    end_pts,
+                    end_dts,
+                    dts_offset,
+                }),
+            ));
+            drain_buffers.extend(gops.into_iter().flat_map(|gop| gop.buffers.into_iter()));
+        }
 
-        let mut list = gst::BufferList::new_sized(1);
-        {
-            let list = list.get_mut().unwrap();
-            list.add(buffer);
+        if let Some(fragment_end_pts) = fragment_end_pts {
+            state.fragment_start_pts = Some(fragment_end_pts);
         }
 
-        Ok(Some((list, caps)))
+        Some(DrainedBuffers {
+            streams: drain_streams,
+            buffers: drain_buffers,
+            fragment_start: true,
+        })
     }
 
-    fn sink_chain(
-        &self,
-        _pad: &gst::Pad,
+    /// End PTS of the next chunk and whether a new fragment starts there.
+    ///
+    /// Chunks are at most the configured chunk duration long. If the reference stream starts a
+    /// new GOP after the fragment duration has passed then the chunk ends there and the GOP starts
+    /// the next fragment.
+    fn chunk_end_pts(state: &State, settings: &Settings) -> Option<(gst::ClockTime, bool)> {
+        let fragment_start_pts = state.fragment_start_pts?;
+        let chunk_start_pts = state.chunk_start_pts?;
+        let chunk_duration = settings.chunk_duration?;
+        let fragment_target_pts = fragment_start_pts + settings.fragment_duration;
+        let chunk_end_pts = chunk_start_pts + chunk_duration;
+
+        let fragment_end_pts = Self::reference_stream(state).and_then(|idx| {
+            state.streams[idx]
+                .queued_gops
+                .iter()
+                .rev()
+                .map(|gop| gop.start_pts)
+                .find(|&start_pts| {
+                    start_pts > chunk_start_pts
+                        && start_pts >= fragment_target_pts
+                        && start_pts <= chunk_end_pts
+                })
+        });
+
+        match fragment_end_pts {
+            Some(fragment_end_pts) => Some((fragment_end_pts, true)),
+            None => Some((chunk_end_pts, false)),
+        }
+    }
+
+    /// Takes the buffers of all streams up to the end of the next chunk in decoding order, or all
+    /// buffers at EOS.
+    fn drain_chunk_buffers(
         element: &super::FMP4Mux,
-        buffer: gst::Buffer,
-    ) -> Result<gst::FlowSuccess, gst::FlowError> {
-        let settings = self.settings.lock().unwrap().clone();
+        state: &mut State,
+        settings: &Settings,
+        at_eos: bool,
+    ) -> Option<DrainedBuffers> {
+        let chunk_start_pts = state.chunk_start_pts?;
+        let fragment_start = state.fragment_start_pts == Some(chunk_start_pts);
 
-        let mut upstream_events = vec![];
+        let chunk_end = if at_eos {
+            gst_info!(CAT, obj: element, "Draining at EOS");
+            None
+        } else {
+            let (chunk_end_pts, fragment_end) = Self::chunk_end_pts(state, settings)?;
 
-        let buffers = {
-            let mut state = self.state.lock().unwrap();
+            if !state
+                .streams
+                .iter()
+                .all(|stream| stream.is_chunk_filled(chunk_end_pts))
+            {
+                return None;
+            }
 
-            let pts = buffer.pts();
+            Some((chunk_end_pts, fragment_end))
+        };
 
-            // Queue up the buffer and update GOP tracking state
-            self.queue_input(element, &mut state, buffer)?;
+        let mut drain_streams = Vec::with_capacity(state.streams.len());
+        let mut drain_buffers = Vec::new();
+
+        for stream in &mut state.streams {
+            let is_eos = stream.sinkpad.is_eos();
+
+            // Buffers are queued in decoding order, take all of them until the first one that
+            // belongs to the next chunk.
+            let mut buffers = Vec::new();
+            while let Some(gop) = stream.queued_gops.back_mut() {
+                let n_buffers = gop
+                    .buffers
+                    .iter()
+                    .position(|buffer| {
+                        chunk_end.map_or(false, |(end_pts, _)| buffer.pts >= end_pts)
+                    })
+                    .unwrap_or(gop.buffers.len());
+                let complete = n_buffers == gop.buffers.len();
+                buffers.extend(gop.buffers.drain(..n_buffers));
 
-            // If we have a PTS with this buffer, check if a new force-keyunit event for the next
-            // fragment start has to be created
-            if let Some(pts) = pts {
-                if let Some(event) =
-                    self.create_force_keyunit_event(element, &mut state, &settings, pts)?
-                {
-                    upstream_events.push(event);
+                // Keep the newest GOP around as long as more buffers can be added to it
+                if !complete || (stream.queued_gops.len() == 1 && !is_eos) {
+                    break;
                 }
+
+                stream.queued_gops.pop_back();
             }
 
-            // If enough GOPs were queued, drain and create the output fragment
-            self.drain(element, &mut state, &settings, false)?
-        };
+            if buffers.is_empty() {
+                gst_debug!(CAT, obj: &stream.sinkpad, "No buffers to drain");
+                drain_streams.push((stream.caps.clone(), None));
+                continue;
+            }
 
-        for event in upstream_events {
-            self.sinkpad.push_event(event);
+            let next_buffer = stream
+                .queued_gops
+                .iter()
+                .rev()
+                .find_map(|gop| gop.buffers.first());
+
+            let buffer_end_pts = |buffer: &Buffer| {
+                buffer.pts + buffer.buffer.duration().unwrap_or(gst::ClockTime::ZERO)
+            };
+
+            let first_buffer = buffers.first().unwrap();
+            let last_buffer = buffers.last().unwrap();
+            let earliest_pts = buffers.iter().map(|buffer| buffer.pts).min().unwrap();
+            let start_dts = first_buffer.dts;
+            let (end_pts, end_dts) = if stream.intra_only {
+                (
+                    next_buffer.map_or_else(|| buffer_end_pts(last_buffer), |buffer| buffer.pts),
+                    None,
+                )
+            } else {
+                (
+                    buffers.iter().map(buffer_end_pts).max().unwrap(),
+                    next_buffer.and_then(|buffer| buffer.dts).or_else(|| {
+                        last_buffer.dts.map(|dts| {
+                            dts + last_buffer
+                                .buffer
+                                .duration()
+                                .unwrap_or(gst::ClockTime::ZERO)
+                        })
+                    }),
+                )
+            };
+            let dts_offset = stream.dts_offset;
+
+            gst_info!(
+                CAT,
+                obj: &stream.sinkpad,
+                "Draining chunk of {} starting at PTS {} DTS {}, DTS offset {}",
+                end_pts.saturating_sub(earliest_pts),
+                earliest_pts,
+                start_dts.display(),
+                dts_offset.display(),
+            );
+
+            drain_streams.push((
+                stream.caps.clone(),
+                Some(super::FragmentTimingInfo {
+                    earliest_pts,
+                    start_dts,
+                    end_pts,
+                    end_dts,
+                    dts_offset,
+                }),
+            ));
+            drain_buffers.extend(buffers);
         }
 
-        if let Some(buffers) = buffers {
-            gst_trace!(CAT, obj: element, "Pushing buffer list {:?}", buffers);
-            self.srcpad.push_list(buffers)?;
+        if let Some((chunk_end_pts, fragment_end)) = chunk_end {
+            state.chunk_start_pts = Some(chunk_end_pts);
+            if fragment_end {
+                state.fragment_start_pts = Some(chunk_end_pts);
+            }
         }
 
-        Ok(gst::FlowSuccess::Ok)
+        Some(DrainedBuffers {
+            streams: drain_streams,
+            buffers: drain_buffers,
+            fragment_start,
+        })
     }
 
-    fn sink_event(&self, pad: &gst::Pad, element: &super::FMP4Mux, mut event: gst::Event) -> bool {
-        use gst::EventView;
+    fn drain(
+        &self,
+        element: &super::FMP4Mux,
+        state: &mut State,
+        settings: &Settings,
+        at_eos: bool,
+    ) -> Result<Option<gst::BufferList>, gst::FlowError> {
+        let class = element.class();
 
-        gst_trace!(CAT, obj: pad, "Handling event {:?}", event);
+        let drained = if settings.chunk_duration.is_some() {
+            Self::drain_chunk_buffers(element, state, settings, at_eos)
+        } else {
+            Self::drain_fragment_buffers(element, state, settings, at_eos)
+        };
+        let DrainedBuffers {
+            streams: drain_streams,
+            buffers: mut drain_buffers,
+            fragment_start,
+        } = match drained {
+            None => return Ok(None),
+            Some(drained) => drained,
+        };
 
-        match event.view() {
-            EventView::Segment(ev) => {
-                let segment = match ev.segment().downcast_ref::<gst::ClockTime>() {
-                    Some(segment) => {
-                        gst_info!(CAT, obj: pad, "Received segment {:?}", segment);
-                        segment.clone()
-                    }
-                    None => {
-                        gst_warning!(
-                            CAT,
-                            obj: pad,
-                            "Received non-TIME segment, replacing with default TIME segment"
-                        );
-                        let segment = gst::FormattedSegment::new();
-                        event = gst::event::Segment::builder(&segment)
-                            .seqnum(event.seqnum())
-                            .build();
-                        segment
-                    }
-                };
+        let dts_offsets = state
+            .streams
+            .iter()
+            .map(|stream| stream.dts_offset)
+            .collect::<Vec<_>>();
+
+        // Encrypt all samples of encrypted streams
+        for buffer in &mut drain_buffers {
+            let stream = &mut state.streams[buffer.idx];
+            let encryptor = match stream.encryptor {
+                Some(ref mut encryptor) => encryptor,
+                None => continue,
+            };
 
-                self.state.lock().unwrap().segment = Some(segment);
+            let (encrypted, encryption) = encryptor.encrypt(&buffer.buffer).map_err(|err| {
+                gst_error!(CAT, obj: &stream.sinkpad, "Failed to encrypt buffer: {}", err);
+                gst::FlowError::Error
+            })?;
+            buffer.buffer = encrypted;
+            buffer.encryption = Some(encryption);
+        }
+        let encryption = state
+            .streams
+            .iter()
+            .map(|stream| {
+                stream
+                    .encryptor
+                    .as_ref()
+                    .map(|encryptor| encryptor.track_encryption().clone())
+            })
+            .collect::<Vec<_>>();
 
-                self.srcpad.push_event(event)
-            }
-            EventView::Caps(ev) => {
-                let caps = ev.caps_owned();
+        let mut buffer_list = None;
 
-                gst_info!(CAT, obj: pad, "Received caps {:?}", caps);
-                let caps = {
-                    let settings = self.settings.lock().unwrap().clone();
-                    let mut state = self.state.lock().unwrap();
+        if !drain_buffers.is_empty() {
+            // Converts a DTS from the stream to a running time, or `None` if it would be negative
+            let dts_to_running_time =
+                |idx: usize, dts: Option<gst::ClockTime>| match dts_offsets[idx] {
+                    Some(dts_offset) => dts.and_then(|dts| dts.checked_sub(dts_offset)),
+                    None => dts,
+                };
 
-                    let s = caps.structure(0).unwrap();
+            let (earliest_pts, end_pts) = drain_streams
+                .iter()
+                .filter_map(|(_, timing_info)| timing_info.as_ref())
+                .fold((None, None), |(earliest_pts, end_pts), timing_info| {
+                    (
+                        Some(
+                            earliest_pts.map_or(timing_info.earliest_pts, |earliest_pts| {
+                                std::cmp::min(earliest_pts, timing_info.earliest_pts)
+                            }),
+                        ),
+                        Some(end_pts.map_or(timing_info.end_pts, |end_pts| {
+                            std::cmp::max(end_pts, timing_info.end_pts)
+                        })),
+                    )
+                });
+            let earliest_pts = earliest_pts.unwrap();
+            let end_pts = end_pts.unwrap();
+
+            // The DTS of the fragment is the one of the first stream with buffers
+            let start_dts = drain_streams
+                .iter()
+                .enumerate()
+                .find_map(|(idx, (_, timing_info))| {
+                    timing_info
+                        .as_ref()
+                        .map(|timing_info| dts_to_running_time(idx, timing_info.start_dts))
+                })
+                .flatten();
 
-                    match s.name() {
-                        "video/x-h264" | "video/x-h265" => {
-                            if !s.has_field_with_type("codec_data", gst::Buffer::static_type()) {
-                                gst_error!(CAT, obj: pad, "Received caps without codec_data");
-                                return false;
-                            }
-                        }
-                        "audio/mpeg" => {
-                            if !s.has_field_with_type("codec_data", gst::Buffer::static_type()) {
-                                gst_error!(CAT, obj: pad, "Received caps without codec_data");
-                                return false;
-                            }
-                            state.intra_only = true;
-                        }
-                        _ => unreachable!(),
-                    }
+            let mut fmp4_header = None;
+            if state.sequence_number == 0 {
+                let mut buffer = state.stream_header.as_ref().unwrap().copy();
+                {
+                    let buffer = buffer.get_mut().unwrap();
 
-                    state.caps = Some(caps);
+                    buffer.set_pts(earliest_pts);
+                    buffer.set_dts(start_dts);
 
-                    let (_, caps) = match self.update_header(element, &mut state, &settings, false)
-                    {
-                        Ok(Some(res)) => res,
-                        _ => {
-                            return false;
-                        }
-                    };
+                    // Header is DISCONT|HEADER
+                    buffer.set_flags(gst::BufferFlags::DISCONT | gst::BufferFlags::HEADER);
+                }
 
-                    caps
-                };
+                fmp4_header = Some(buffer);
 
-                self.srcpad.push_event(gst::event::Caps::new(&caps))
+                state.earliest_pts = Some(earliest_pts);
+                state.sequence_number = 1;
             }
-            EventView::Tag(_ev) => {
-                // TODO: Maybe store for putting into the headers of the next fragment?
 
-                pad.event_default(Some(element), event)
-            }
-            EventView::Gap(_ev) => {
-                // TODO: queue up and check if draining is needed now
-                // i.e. make the last sample much longer
-                true
-            }
-            EventView::Eos(_ev) => {
-                let settings = self.settings.lock().unwrap().clone();
+            // TODO: Write prft boxes before moof
+            // TODO: Write sidx boxes before moof and rewrite once offsets are known
 
-                let drained = self.drain(element, &mut self.state.lock().unwrap(), &settings, true);
-                let update_header =
-                    drained.is_ok() && settings.header_update_mode != super::HeaderUpdateMode::None;
+            // Put all event messages that start before the end of this fragment or chunk in front
+            // of it
+            let (emsgs, pending_emsgs) = std::mem::take(&mut state.pending_emsgs)
+                .into_iter()
+                .partition::<Vec<_>, _>(|emsg| at_eos || emsg.presentation_time < end_pts);
+            state.pending_emsgs = pending_emsgs;
 
-                match drained {
-                    Ok(Some(buffers)) => {
-                        gst_trace!(CAT, obj: element, "Pushing buffer list {:?}", buffers);
+            let sequence_number = state.sequence_number;
+            state.sequence_number += 1;
+            let (mut fmp4_fragment_header, moof_offset) =
+                boxes::create_fmp4_fragment_header(super::FragmentHeaderConfiguration {
+                    variant: class.as_ref().variant,
+                    sequence_number,
+                    streams: &drain_streams,
+                    buffers: &drain_buffers,
+                    emsgs: &emsgs,
+                    encryption: &encryption,
+                })
+                .map_err(|err| {
+                    gst_error!(
+                        CAT,
+                        obj: element,
+                        "Failed to create FMP4 fragment header: {}",
+                        err
+                    );
+                    gst::FlowError::Error
+                })?;
 
-                        if let Err(err) = self.srcpad.push_list(buffers) {
-                            gst_error!(
-                                CAT,
-                                obj: element,
-                                "Failed pushing EOS buffers downstream: {:?}",
-                                err,
-                            );
-                        }
-                    }
-                    Ok(None) => {}
-                    Err(err) => {
-                        gst_error!(CAT, obj: element, "Failed draining at EOS: {:?}", err);
-                    }
+            {
+                let buffer = fmp4_fragment_header.get_mut().unwrap();
+                buffer.set_pts(earliest_pts);
+                buffer.set_dts(start_dts);
+                buffer.set_duration(end_pts.checked_sub(earliest_pts));
+
+                // Fragment header is HEADER, chunks that don't start a new fragment are
+                // HEADER|DELTA_UNIT
+                if fragment_start {
+                    buffer.set_flags(gst::BufferFlags::HEADER);
+                } else {
+                    buffer.set_flags(gst::BufferFlags::HEADER | gst::BufferFlags::DELTA_UNIT);
                 }
 
-                if update_header {
-                    let updated_header = self.update_header(
-                        element,
-                        &mut self.state.lock().unwrap(),
-                        &settings,
-                        true,
-                    );
-                    match updated_header {
-                        Ok(Some((buffer_list, caps))) => {
-                            match settings.header_update_mode {
-                                super::HeaderUpdateMode::None => unreachable!(),
-                                super::HeaderUpdateMode::Rewrite => {
-                                    let mut q = gst::query::Seeking::new(gst::Format::Bytes);
-                                    if self.srcpad.peer_query(&mut q) && q.result().0 {
-                                        // Seek to the beginning with a default bytes segment
-                                        self.srcpad.push_event(gst::event::Segment::new(
-                                            &gst::FormattedSegment::<gst::format::Bytes>::new(),
-                                        ));
-
-                                        self.srcpad.push_event(gst::event::Caps::new(&caps));
-                                        if let Err(err) = self.srcpad.push_list(buffer_list) {
-                                            gst_error!(
-                                                CAT,
-                                                obj: element,
-                                                "Failed pushing updated header buffer downstream: {:?}",
-                                                err,
-                                            );
-                                        }
-                                    } else {
-                                        gst_error!(CAT, obj: element, "Can't rewrite header because downstream is not seekable");
-                                    }
-                                }
-                                super::HeaderUpdateMode::Update => {
-                                    self.srcpad.push_event(gst::event::Caps::new(&caps));
-                                    if let Err(err) = self.srcpad.push_list(buffer_list) {
-                                        gst_error!(
-                                            CAT,
-                                            obj: element,
-                                            "Failed pushing updated header buffer downstream: {:?}",
-                                            err,
-                                        );
-                                    }
-                                }
-                            }
-                        }
-                        Ok(None) => {}
-                        Err(err) => {
-                            gst_error!(
-                                CAT,
-                                obj: element,
-                                "Failed to generate updated header: {:?}",
-                                err
-                            );
-                        }
-                    }
+                // Copy metas from the first actual buffer to the fragment header. This allows
+                // getting things like the reference timestamp meta or the timecode meta to identify
+                // the fragment.
+                let _ =
+                    drain_buffers[0]
+                        .buffer
+                        .copy_into(buffer, gst::BufferCopyFlags::META, 0, None);
+            }
+
+            let moof_offset = state.current_offset
+                + fmp4_header.as_ref().map(|h| h.size()).unwrap_or(0) as u64
+                + moof_offset;
+
+            let buffers_len = drain_buffers.len();
+            for (i, buffer) in drain_buffers.iter_mut().enumerate() {
+                let dts = dts_to_running_time(buffer.idx, buffer.dts);
+                let buffer_ref = buffer.buffer.make_mut();
+
+                // Output timestamps are running times
+                buffer_ref.set_pts(buffer.pts);
+                buffer_ref.set_dts(dts);
+
+                // Fix up buffer flags, all other buffers are DELTA_UNIT
+                buffer_ref.unset_flags(gst::BufferFlags::all());
+                buffer_ref.set_flags(gst::BufferFlags::DELTA_UNIT);
+
+                // Set the marker flag for the last buffer of the fragment or chunk
+                if i == buffers_len - 1 {
+                    buffer_ref.set_flags(gst::BufferFlags::MARKER);
                 }
+            }
+
+            buffer_list = Some(
+                fmp4_header
+                    .into_iter()
+                    .chain(Some(fmp4_fragment_header))
+                    .chain(drain_buffers.into_iter().map(|buffer| buffer.buffer))
+                    .inspect(|b| {
+                        state.current_offset += b.size() as u64;
+                    })
+                    .collect::<gst::BufferList>(),
+            );
 
-                pad.event_default(Some(element), event)
+            // Only the start of each fragment is a random access point
+            if fragment_start {
+                state.fragment_offsets.push(super::FragmentOffset {
+                    offset: moof_offset,
+                    trafs: drain_streams
+                        .iter()
+                        .enumerate()
+                        .filter_map(|(idx, (_, timing_info))| {
+                            timing_info
+                                .as_ref()
+                                .map(|timing_info| (idx, timing_info.earliest_pts))
+                        })
+                        .collect(),
+                });
             }
-            EventView::FlushStop(_ev) => {
-                let mut state = self.state.lock().unwrap();
+            state.end_pts = Some(
+                state
+                    .end_pts
+                    .map_or(end_pts, |prev_end_pts| std::cmp::max(prev_end_pts, end_pts)),
+            );
+        }
 
-                state.segment = None;
-                state.queued_gops.clear();
-                state.queued_duration = gst::ClockTime::ZERO;
-                state.dts_offset = None;
-                state.last_force_keyunit_time = None;
-                state.current_offset = 0;
-                state.fragment_offsets.clear();
+        if settings.write_mfra && at_eos {
+            let streams = state
+                .streams
+                .iter()
+                .map(|stream| stream.caps.clone())
+                .collect::<Vec<_>>();
+            match boxes::create_mfra(&streams, &state.fragment_offsets) {
+                Ok(mut mfra) => {
+                    {
+                        let mfra = mfra.get_mut().unwrap();
+                        // mfra is HEADER|DELTA_UNIT like other boxes
+                        mfra.set_flags(gst::BufferFlags::HEADER | gst::BufferFlags::DELTA_UNIT);
+                    }
 
-                pad.event_default(Some(element), event)
+                    if buffer_list.is_none() {
+                        buffer_list = Some(gst::BufferList::new_sized(1));
+                    }
+                    buffer_list.as_mut().unwrap().get_mut().unwrap().add(mfra);
+                }
+                Err(err) => {
+                    gst_error!(CAT, obj: element, "Failed to create mfra box: {}", err);
+                }
             }
-            _ => pad.event_default(Some(element), event),
         }
+
+        // TODO: Write edit list at EOS
+        // TODO: Rewrite bitrates at EOS
+
+        Ok(buffer_list)
     }
 
-    fn sink_query(
+    fn create_streams(
         &self,
-        pad: &gst::Pad,
         element: &super::FMP4Mux,
-        query: &mut gst::QueryRef,
-    ) -> bool {
-        use gst::QueryView;
+        state: &mut State,
+        settings: &Settings,
+        stream_keys: &[StreamKey],
+    ) -> Result<(), gst::FlowError> {
+        for pad in element
+            .sink_pads()
+            .into_iter()
+            .map(|pad| pad.downcast::<gst_base::AggregatorPad>().unwrap())
+        {
+            let caps = match pad.current_caps() {
+                Some(caps) => caps,
+                None => {
+                    gst_warning!(CAT, obj: &pad, "Skipping pad without caps");
+                    continue;
+                }
+            };
 
-        gst_trace!(CAT, obj: pad, "Handling query {:?}", query);
+            gst_info!(CAT, obj: &pad, "Configuring caps {:?}", caps);
 
-        match query.view_mut() {
-            QueryView::Caps(mut q) => {
-                let state = self.state.lock().unwrap();
+            let s = caps.structure(0).unwrap();
 
-                let allowed_caps = if let Some(ref caps) = state.caps {
-                    // TODO: Maybe allow codec_data changes and similar?
-                    caps.clone()
-                } else {
-                    pad.pad_template_caps()
-                };
+            let mut intra_only = false;
+            match s.name() {
+                "video/x-h264" | "video/x-h265" => {
+                    if !s.has_field_with_type("codec_data", gst::Buffer::static_type()) {
+                        gst_error!(CAT, obj: &pad, "Received caps without codec_data");
+                        return Err(gst::FlowError::NotNegotiated);
+                    }
+                }
+                "audio/mpeg" => {
+                    if !s.has_field_with_type("codec_data", gst::Buffer::static_type()) {
+                        gst_error!(CAT, obj: &pad, "Received caps without codec_data");
+                        return Err(gst::FlowError::NotNegotiated);
+                    }
+                    intra_only = true;
+                }
+                "video/x-vp9" => {
+                    if !s.has_field_with_type("profile", String::static_type())
+                        || !s.has_field_with_type("chroma-format", String::static_type())
+                        || !s.has_field_with_type("bit-depth-luma", u32::static_type())
+                    {
+                        gst_error!(
+                            CAT,
+                            obj: &pad,
+                            "Received caps without profile, chroma-format or bit-depth-luma"
+                        );
+                        return Err(gst::FlowError::NotNegotiated);
+                    }
+                }
+                "video/x-av1" => (),
+                "audio/x-opus" => {
+                    intra_only = true;
+                }
+                "application/x-subtitle-vtt"
+                | "application/x-subtitle-vtt-fragmented"
+                | "application/ttml+xml" => {
+                    intra_only = true;
+                }
+                "audio/x-flac" => {
+                    if !s.has_field_with_type("streamheader", gst::Array::static_type()) {
+                        gst_error!(CAT, obj: &pad, "Received caps without streamheader");
+                        return Err(gst::FlowError::NotNegotiated);
+                    }
+                    intra_only = true;
+                }
+                _ => unreachable!(),
+            }
 
-                if let Some(filter_caps) = q.filter() {
-                    let res = filter_caps
-                        .intersect_with_mode(&allowed_caps, gst::CapsIntersectMode::First);
-                    q.set_result(&res);
+            // Subtitles are never encrypted
+            let encryptor = if settings.encryption_scheme != super::EncryptionScheme::None
+                && (s.name().starts_with("video/") || s.name().starts_with("audio/"))
+            {
+                let stream_key = stream_keys.iter().find(|stream_key| stream_key.pad == pad);
+                Some(self.create_encryptor(settings, &pad, &caps, stream_key)?)
+            } else {
+                None
+            };
+
+            state.streams.push(Stream {
+                sinkpad: pad,
+                caps,
+                intra_only,
+                queued_gops: VecDeque::new(),
+                dts_offset: None,
+                last_force_keyunit_time: None,
+                text_end_pts: None,
+                encryptor,
+            });
+        }
+
+        if state.streams.is_empty() {
+            gst_error!(CAT, obj: element, "No streams available");
+            return Err(gst::FlowError::Error);
+        }
+
+        // Sort video streams first and then audio streams and then anything else, and for each
+        // stream type by pad name.
+        state.streams.sort_by(|a, b| {
+            let order_of_caps = |caps: &gst::CapsRef| {
+                let s = caps.structure(0).unwrap();
+
+                if s.name().starts_with("video/") {
+                    0
+                } else if s.name().starts_with("audio/") {
+                    1
                 } else {
-                    q.set_result(&allowed_caps);
+                    2
                 }
+            };
 
-                true
+            let st_a = order_of_caps(&a.caps);
+            let st_b = order_of_caps(&b.caps);
+
+            if st_a == st_b {
+                return a.sinkpad.name().cmp(&b.sinkpad.name());
             }
-            _ => pad.query_default(Some(element), query),
-        }
+
+            st_a.cmp(&st_b)
+        });
+
+        Ok(())
     }
 
-    fn src_event(&self, pad: &gst::Pad, element: &super::FMP4Mux, event: gst::Event) -> bool {
-        use gst::EventView;
+    /// Requests the keys for all audio and video pads via the `request-key` signal, falling back
+    /// to the properties.
+    ///
+    /// This must be called without the state lock as signal handlers might access the element.
+    fn request_stream_keys(&self, element: &super::FMP4Mux, settings: &Settings) -> Vec<StreamKey> {
+        element
+            .sink_pads()
+            .into_iter()
+            .map(|pad| pad.downcast::<gst_base::AggregatorPad>().unwrap())
+            .filter(|pad| {
+                pad.current_caps().map_or(false, |caps| {
+                    let name = caps.structure(0).unwrap().name();
+                    name.starts_with("video/") || name.starts_with("audio/")
+                })
+            })
+            .map(|pad| {
+                match element.emit_by_name::<Option<gst::Structure>>(SIGNAL_REQUEST_KEY, &[&pad]) {
+                    Some(s) => StreamKey {
+                        key_id: s.get::<Option<gst::Buffer>>("key-id").ok().flatten(),
+                        key: s.get::<Option<gst::Buffer>>("key").ok().flatten(),
+                        iv: s.get::<Option<gst::Buffer>>("iv").ok().flatten(),
+                        pad,
+                    },
+                    None => StreamKey {
+                        key_id: settings.key_id.clone(),
+                        key: settings.key.clone(),
+                        iv: settings.iv.clone(),
+                        pad,
+                    },
+                }
+            })
+            .collect()
+    }
 
-        gst_trace!(CAT, obj: pad, "Handling event {:?}", event);
+    /// Creates the encryptor for a stream with the key requested by `request_stream_keys()`.
+    fn create_encryptor(
+        &self,
+        settings: &Settings,
+        pad: &gst_base::AggregatorPad,
+        caps: &gst::Caps,
+        stream_key: Option<&StreamKey>,
+    ) -> Result<cenc::Encryptor, gst::FlowError> {
+        let (key_id, key, iv) = match stream_key {
+            Some(stream_key) => (
+                stream_key.key_id.as_ref(),
+                stream_key.key.as_ref(),
+                stream_key.iv.as_ref(),
+            ),
+            None => (None, None, None),
+        };
 
-        match event.view() {
-            EventView::Seek(_ev) => false,
-            _ => pad.event_default(Some(element), event),
-        }
+        let (key_id, key) = match (key_id, key) {
+            (Some(key_id), Some(key)) => (key_id, key),
+            _ => {
+                gst_error!(CAT, obj: pad, "No key ID or key for encryption");
+                return Err(gst::FlowError::NotNegotiated);
+            }
+        };
+
+        let key_id = key_id.map_readable().map_err(|_| gst::FlowError::Error)?;
+        let key = key.map_readable().map_err(|_| gst::FlowError::Error)?;
+        let iv = iv
+            .as_ref()
+            .map(|iv| iv.map_readable())
+            .transpose()
+            .map_err(|_| gst::FlowError::Error)?;
+
+        cenc::Encryptor::new(
+            caps,
+            settings.encryption_scheme,
+            &key_id,
+            &key,
+            iv.as_ref().map(|iv| iv.as_slice()),
+        )
+        .map_err(|err| {
+            gst_error!(CAT, obj: pad, "Failed to set up encryption: {}", err);
+            gst::FlowError::NotNegotiated
+        })
     }
 
-    fn src_query(
+    fn update_header(
         &self,
-        pad: &gst::Pad,
         element: &super::FMP4Mux,
-        query: &mut gst::QueryRef,
-    ) -> bool {
-        use gst::QueryView;
+        state: &mut State,
+        settings: &Settings,
+        at_eos: bool,
+    ) -> Result<Option<(gst::BufferList, gst::Caps)>, gst::FlowError> {
+        let class = element.class();
+        let variant = class.as_ref().variant;
 
-        gst_trace!(CAT, obj: pad, "Handling query {:?}", query);
+        if settings.header_update_mode == super::HeaderUpdateMode::None && at_eos {
+            return Ok(None);
+        }
 
-        match query.view_mut() {
-            QueryView::Seeking(mut q) => {
-                // We can't really handle seeking, it would break everything
-                q.set(false, gst::ClockTime::ZERO.into(), gst::ClockTime::NONE);
-                true
-            }
-            QueryView::Latency(mut q) => {
-                if !self.sinkpad.peer_query(q.query_mut()) {
-                    return false;
-                }
+        assert!(!at_eos || state.streams.iter().all(|s| s.queued_gops.is_empty()));
 
-                let settings = self.settings.lock().unwrap();
-                let (live, min, max) = q.result();
-                gst_info!(
-                    CAT,
-                    obj: pad,
-                    "Upstream latency: live {}, min {}, max {}",
-                    live,
-                    min,
-                    max.display()
-                );
-                let (min, max) = (
-                    min + settings.fragment_duration,
-                    max.opt_add(settings.fragment_duration),
-                );
-                gst_info!(
-                    CAT,
-                    obj: pad,
-                    "Returning latency: live {}, min {}, max {}",
-                    live,
-                    min,
-                    max.display()
-                );
-                q.set(live, min, max);
+        let duration = state
+            .end_pts
+            .opt_checked_sub(state.earliest_pts)
+            .ok()
+            .flatten();
 
-                true
-            }
-            _ => pad.query_default(Some(element), query),
+        let streams = state
+            .streams
+            .iter()
+            .map(|s| s.caps.clone())
+            .collect::<Vec<_>>();
+
+        let encryption = state
+            .streams
+            .iter()
+            .map(|s| {
+                s.encryptor
+                    .as_ref()
+                    .map(|encryptor| encryptor.track_encryption().clone())
+            })
+            .collect::<Vec<_>>();
+
+        let mut buffer = boxes::create_fmp4_header(super::HeaderConfiguration {
+            variant,
+            update: at_eos,
+            streams: &streams,
+            write_mehd: settings.write_mehd,
+            duration: if at_eos { duration } else { None },
+            encryption: &encryption,
+            pssh: &state.pssh,
+        })
+        .map_err(|err| {
+            gst_error!(CAT, obj: element, "Failed to create FMP4 header: {}", err);
+            gst::FlowError::Error
+        })?;
+
+        {
+            let buffer = buffer.get_mut().unwrap();
+
+            // No timestamps
+
+            // Header is DISCONT|HEADER
+            buffer.set_flags(gst::BufferFlags::DISCONT | gst::BufferFlags::HEADER);
         }
+
+        // Remember stream header for later
+        state.stream_header = Some(buffer.clone());
+
+        let variant = match variant {
+            super::Variant::ISO | super::Variant::DASH => "iso-fragmented",
+            super::Variant::CMAF => "cmaf",
+        };
+        let caps = gst::Caps::builder("video/quicktime")
+            .field("variant", variant)
+            .field("streamheader", gst::Array::new(&[&buffer]))
+            .build();
+
+        let mut list = gst::BufferList::new_sized(1);
+        {
+            let list = list.get_mut().unwrap();
+            list.add(buffer);
+        }
+
+        Ok(Some((list, caps)))
     }
 }
 
@@ -1047,69 +1645,14 @@ impl FMP4Mux {
 impl ObjectSubclass for FMP4Mux {
     const NAME: &'static str = "GstFMP4Mux";
     type Type = super::FMP4Mux;
-    type ParentType = gst::Element;
+    type ParentType = gst_base::Aggregator;
     type Class = Class;
-
-    fn with_class(klass: &Self::Class) -> Self {
-        let templ = klass.pad_template("sink").unwrap();
-        let sinkpad = gst::Pad::builder_with_template(&templ, Some("sink"))
-            .chain_function(|pad, parent, buffer| {
-                FMP4Mux::catch_panic_pad_function(
-                    parent,
-                    || Err(gst::FlowError::Error),
-                    |fmp4mux, element| fmp4mux.sink_chain(pad, element, buffer),
-                )
-            })
-            .event_function(|pad, parent, event| {
-                FMP4Mux::catch_panic_pad_function(
-                    parent,
-                    || false,
-                    |fmp4mux, element| fmp4mux.sink_event(pad, element, event),
-                )
-            })
-            .query_function(|pad, parent, query| {
-                FMP4Mux::catch_panic_pad_function(
-                    parent,
-                    || false,
-                    |fmp4mux, element| fmp4mux.sink_query(pad, element, query),
-                )
-            })
-            .flags(gst::PadFlags::ACCEPT_INTERSECT)
-            .build();
-
-        let templ = klass.pad_template("src").unwrap();
-        let srcpad = gst::Pad::builder_with_template(&templ, Some("src"))
-            .event_function(|pad, parent, event| {
-                FMP4Mux::catch_panic_pad_function(
-                    parent,
-                    || false,
-                    |fmp4mux, element| fmp4mux.src_event(pad, element, event),
-                )
-            })
-            .query_function(|pad, parent, query| {
-                FMP4Mux::catch_panic_pad_function(
-                    parent,
-                    || false,
-                    |fmp4mux, element| fmp4mux.src_query(pad, element, query),
-                )
-            })
-            .flags(gst::PadFlags::FIXED_CAPS | gst::PadFlags::ACCEPT_TEMPLATE)
-            .build();
-
-        Self {
-            srcpad,
-            sinkpad,
-            settings: Mutex::default(),
-            state: Mutex::default(),
-        }
-    }
 }
 
 impl ObjectImpl for FMP4Mux {
     fn properties() -> &'static [glib::ParamSpec] {
         static PROPERTIES: Lazy<Vec<glib::ParamSpec>> = Lazy::new(|| {
             vec![
-                // TODO: Add chunk-duration property separate from fragment-size
                 glib::ParamSpecUInt64::new(
                     "fragment-duration",
                     "Fragment Duration",
@@ -1119,6 +1662,15 @@ impl ObjectImpl for FMP4Mux {
                     DEFAULT_FRAGMENT_DURATION.nseconds(),
                     glib::ParamFlags::READWRITE | gst::PARAM_FLAG_MUTABLE_READY,
                 ),
+                glib::ParamSpecUInt64::new(
+                    "chunk-duration",
+                    "Chunk Duration",
+                    "Duration for each FMP4 chunk, at most the fragment duration (default = no chunks)",
+                    1,
+                    u64::MAX,
+                    DEFAULT_CHUNK_DURATION.map(gst::ClockTime::nseconds).unwrap_or(u64::MAX),
+                    glib::ParamFlags::READWRITE | gst::PARAM_FLAG_MUTABLE_READY,
+                ),
                 glib::ParamSpecEnum::new(
                     "header-update-mode",
                     "Header update mode",
@@ -1141,15 +1693,62 @@ impl ObjectImpl for FMP4Mux {
                     DEFAULT_WRITE_MFRA,
                     glib::ParamFlags::READWRITE | gst::PARAM_FLAG_MUTABLE_READY,
                 ),
+                glib::ParamSpecEnum::new(
+                    "encryption-scheme",
+                    "Encryption scheme",
+                    "Common Encryption scheme for encrypting audio and video samples (H.264, H.265, AAC, Opus and FLAC only, VP9 and AV1 are not supported)",
+                    super::EncryptionScheme::static_type(),
+                    DEFAULT_ENCRYPTION_SCHEME as i32,
+                    glib::ParamFlags::READWRITE | gst::PARAM_FLAG_MUTABLE_READY,
+                ),
+                glib::ParamSpecBoxed::new(
+                    "key-id",
+                    "Key ID",
+                    "16 byte key ID used if no key is provided via the request-key signal",
+                    gst::Buffer::static_type(),
+                    glib::ParamFlags::READWRITE | gst::PARAM_FLAG_MUTABLE_READY,
+                ),
+                glib::ParamSpecBoxed::new(
+                    "key",
+                    "Key",
+                    "16 byte AES key used if no key is provided via the request-key signal",
+                    gst::Buffer::static_type(),
+                    glib::ParamFlags::WRITABLE | gst::PARAM_FLAG_MUTABLE_READY,
+                ),
+                glib::ParamSpecBoxed::new(
+                    "iv",
+                    "IV",
+                    "Initial 8 byte IV for cenc or 16 byte constant IV for cbcs (default = random)",
+                    gst::Buffer::static_type(),
+                    glib::ParamFlags::READWRITE | gst::PARAM_FLAG_MUTABLE_READY,
+                ),
             ]
         });
 
         &*PROPERTIES
     }
 
+    fn signals() -> &'static [glib::subclass::Signal] {
+        static SIGNALS: Lazy<Vec<glib::subclass::Signal>> = Lazy::new(|| {
+            vec![glib::subclass::Signal::builder(
+                SIGNAL_REQUEST_KEY,
+                &[gst::Pad::static_type().into()],
+                gst::Structure::static_type().into(),
+            )
+            .accumulator(|_hint, ret, value| {
+                // First signal handler wins
+                *ret = value.clone();
+                false
+            })
+            .build()]
+        });
+
+        SIGNALS.as_ref()
+    }
+
     fn set_property(
         &self,
-        _obj: &Self::Type,
+        obj: &Self::Type,
         _id: usize,
         value: &glib::Value,
         pspec: &glib::ParamSpec,
@@ -1157,7 +1756,24 @@ impl ObjectImpl for FMP4Mux {
         match pspec.name() {
             "fragment-duration" => {
                 let mut settings = self.settings.lock().unwrap();
-                settings.fragment_duration = value.get().expect("type checked upstream");
+                let fragment_duration = value.get().expect("type checked upstream");
+                if settings.fragment_duration != fragment_duration {
+                    settings.fragment_duration = fragment_duration;
+                    let latency = settings.latency();
+                    drop(settings);
+                    obj.set_latency(latency, None);
+                }
+            }
+
+            "chunk-duration" => {
+                let mut settings = self.settings.lock().unwrap();
+                let chunk_duration = value.get().expect("type checked upstream");
+                if settings.chunk_duration != chunk_duration {
+                    settings.chunk_duration = chunk_duration;
+                    let latency = settings.latency();
+                    drop(settings);
+                    obj.set_latency(latency, None);
+                }
             }
 
             "header-update-mode" => {
@@ -1175,6 +1791,26 @@ impl ObjectImpl for FMP4Mux {
                 settings.write_mehd = value.get().expect("type checked upstream");
             }
 
+            "encryption-scheme" => {
+                let mut settings = self.settings.lock().unwrap();
+                settings.encryption_scheme = value.get().expect("type checked upstream");
+            }
+
+            "key-id" => {
+                let mut settings = self.settings.lock().unwrap();
+                settings.key_id = value.get().expect("type checked upstream");
+            }
+
+            "key" => {
+                let mut settings = self.settings.lock().unwrap();
+                settings.key = value.get().expect("type checked upstream");
+            }
+
+            "iv" => {
+                let mut settings = self.settings.lock().unwrap();
+                settings.iv = value.get().expect("type checked upstream");
+            }
+
             _ => unimplemented!(),
         }
     }
@@ -1186,6 +1822,11 @@ impl ObjectImpl for FMP4Mux {
                 settings.fragment_duration.to_value()
             }
 
+            "chunk-duration" => {
+                let settings = self.settings.lock().unwrap();
+                settings.chunk_duration.to_value()
+            }
+
             "header-update-mode" => {
                 let settings = self.settings.lock().unwrap();
                 settings.header_update_mode.to_value()
@@ -1201,6 +1842,21 @@ impl ObjectImpl for FMP4Mux {
                 settings.write_mehd.to_value()
             }
 
+            "encryption-scheme" => {
+                let settings = self.settings.lock().unwrap();
+                settings.encryption_scheme.to_value()
+            }
+
+            "key-id" => {
+                let settings = self.settings.lock().unwrap();
+                settings.key_id.to_value()
+            }
+
+            "iv" => {
+                let settings = self.settings.lock().unwrap();
+                settings.iv.to_value()
+            }
+
             _ => unimplemented!(),
         }
     }
@@ -1208,38 +1864,415 @@ impl ObjectImpl for FMP4Mux {
     fn constructed(&self, obj: &Self::Type) {
         self.parent_constructed(obj);
 
-        obj.add_pad(&self.sinkpad).unwrap();
-        obj.add_pad(&self.srcpad).unwrap();
+        // Variants with a single always sink pad
+        if let Some(templ) = obj.element_class().pad_template("sink") {
+            let sinkpad =
+                gst::PadBuilder::<gst_base::AggregatorPad>::from_template(&templ, Some("sink"))
+                    .flags(gst::PadFlags::ACCEPT_INTERSECT)
+                    .build();
+
+            obj.add_pad(&sinkpad).unwrap();
+        }
+
+        let latency = self.settings.lock().unwrap().latency();
+        obj.set_latency(latency, None);
     }
 }
 
 impl GstObjectImpl for FMP4Mux {}
 
-impl ElementImpl for FMP4Mux {
-    #[allow(clippy::single_match)]
-    fn change_state(
+impl ElementImpl for FMP4Mux {}
+
+impl AggregatorImpl for FMP4Mux {
+    fn sink_query(
+        &self,
+        aggregator: &Self::Type,
+        aggregator_pad: &gst_base::AggregatorPad,
+        query: &mut gst::QueryRef,
+    ) -> bool {
+        use gst::QueryView;
+
+        gst_trace!(CAT, obj: aggregator_pad, "Handling query {:?}", query);
+
+        match query.view_mut() {
+            QueryView::Caps(mut q) => {
+                let allowed_caps = aggregator_pad
+                    .current_caps()
+                    .unwrap_or_else(|| aggregator_pad.pad_template_caps());
+
+                // TODO: Maybe allow codec_data changes and similar?
+                if let Some(filter_caps) = q.filter() {
+                    let res = filter_caps
+                        .intersect_with_mode(&allowed_caps, gst::CapsIntersectMode::First);
+                    q.set_result(&res);
+                } else {
+                    q.set_result(&allowed_caps);
+                }
+
+                true
+            }
+            _ => self.parent_sink_query(aggregator, aggregator_pad, query),
+        }
+    }
+
+    fn sink_event_pre_queue(
+        &self,
+        aggregator: &Self::Type,
+        aggregator_pad: &gst_base::AggregatorPad,
+        mut event: gst::Event,
+    ) -> Result<gst::FlowSuccess, gst::FlowError> {
+        use gst::EventView;
+
+        gst_trace!(CAT, obj: aggregator_pad, "Handling event {:?}", event);
+
+        let is_non_time_segment = match event.view() {
+            EventView::Segment(ev) => ev.segment().format() != gst::Format::Time,
+            _ => false,
+        };
+
+        if is_non_time_segment {
+            gst_warning!(
+                CAT,
+                obj: aggregator_pad,
+                "Received non-TIME segment, replacing with default TIME segment"
+            );
+            let segment = gst::FormattedSegment::<gst::ClockTime>::new();
+            event = gst::event::Segment::builder(&segment)
+                .seqnum(event.seqnum())
+                .build();
+        }
+
+        self.parent_sink_event_pre_queue(aggregator, aggregator_pad, event)
+    }
+
+    fn sink_event(
+        &self,
+        aggregator: &Self::Type,
+        aggregator_pad: &gst_base::AggregatorPad,
+        event: gst::Event,
+    ) -> bool {
+        use gst::EventView;
+
+        gst_trace!(CAT, obj: aggregator_pad, "Handling event {:?}", event);
+
+        match event.view() {
+            EventView::Segment(ev) => {
+                gst_info!(CAT, obj: aggregator_pad, "Received segment {:?}", ev.segment());
+
+                self.parent_sink_event(aggregator, aggregator_pad, event)
+            }
+            EventView::Tag(_ev) => {
+                // TODO: Maybe store for putting into the headers of the next fragment?
+
+                self.parent_sink_event(aggregator, aggregator_pad, event)
+            }
+            EventView::Protection(ev) => {
+                let (system_id, data, _origin) = ev.get();
+                gst_debug!(
+                    CAT,
+                    obj: aggregator_pad,
+                    "Received protection data for system {}",
+                    system_id
+                );
+
+                // The data is a complete pssh box that is passed through into the moov
+                let mut state = self.state.lock().unwrap();
+                let is_duplicate = state.pssh.iter().any(|pssh| {
+                    match (pssh.map_readable(), data.map_readable()) {
+                        (Ok(a), Ok(b)) => a.as_slice() == b.as_slice(),
+                        _ => false,
+                    }
+                });
+                if !is_duplicate {
+                    state.pssh.push(data.to_owned());
+                }
+
+                true
+            }
+            EventView::CustomDownstream(ev) => match ev.structure() {
+                Some(s) if s.name() == "emsg" => {
+                    match Self::parse_emsg(aggregator_pad, s) {
+                        Some(emsg) => {
+                            gst_debug!(CAT, obj: aggregator_pad, "Queueing {:?}", emsg);
+                            self.state.lock().unwrap().pending_emsgs.push(emsg);
+                        }
+                        None => {
+                            gst_warning!(CAT, obj: aggregator_pad, "Invalid emsg event");
+                        }
+                    }
+
+                    true
+                }
+                _ => self.parent_sink_event(aggregator, aggregator_pad, event),
+            },
+            _ => self.parent_sink_event(aggregator, aggregator_pad, event),
+        }
+    }
+
+    fn src_query(&self, aggregator: &Self::Type, query: &mut gst::QueryRef) -> bool {
+        use gst::QueryView;
+
+        gst_trace!(CAT, obj: aggregator, "Handling query {:?}", query);
+
+        match query.view_mut() {
+            QueryView::Seeking(mut q) => {
+                // We can't really handle seeking, it would break everything
+                q.set(false, gst::ClockTime::ZERO.into(), gst::ClockTime::NONE);
+                true
+            }
+            _ => self.parent_src_query(aggregator, query),
+        }
+    }
+
+    fn src_event(&self, aggregator: &Self::Type, event: gst::Event) -> bool {
+        use gst::EventView;
+
+        gst_trace!(CAT, obj: aggregator, "Handling event {:?}", event);
+
+        match event.view() {
+            EventView::Seek(_ev) => false,
+            _ => self.parent_src_event(aggregator, event),
+        }
+    }
+
+    fn flush(&self, aggregator: &Self::Type) -> Result<gst::FlowSuccess, gst::FlowError> {
+        let mut state = self.state.lock().unwrap();
+
+        for stream in &mut state.streams {
+            stream.queued_gops.clear();
+            stream.dts_offset = None;
+            stream.last_force_keyunit_time = None;
+            stream.text_end_pts = None;
+        }
+
+        state.fragment_start_pts = None;
+        state.chunk_start_pts = None;
+        state.current_offset = 0;
+        state.fragment_offsets.clear();
+        state.pending_emsgs.clear();
+
+        drop(state);
+
+        self.parent_flush(aggregator)
+    }
+
+    fn stop(&self, aggregator: &Self::Type) -> Result<(), gst::ErrorMessage> {
+        gst_trace!(CAT, obj: aggregator, "Stopping");
+
+        let _ = self.parent_stop(aggregator);
+
+        *self.state.lock().unwrap() = State::default();
+
+        Ok(())
+    }
+
+    fn start(&self, aggregator: &Self::Type) -> Result<(), gst::ErrorMessage> {
+        gst_trace!(CAT, obj: aggregator, "Starting");
+
+        self.parent_start(aggregator)?;
+
+        {
+            let settings = self.settings.lock().unwrap();
+            if let Some(chunk_duration) = settings.chunk_duration {
+                if chunk_duration > settings.fragment_duration {
+                    return Err(gst::error_msg!(
+                        gst::LibraryError::Settings,
+                        [
+                            "Chunk duration {} is longer than fragment duration {}",
+                            chunk_duration,
+                            settings.fragment_duration
+                        ]
+                    ));
+                }
+            }
+        }
+
+        *self.state.lock().unwrap() = State::default();
+
+        Ok(())
+    }
+
+    fn negotiate(&self, _aggregator: &Self::Type) -> bool {
+        true
+    }
+
+    fn create_new_pad(
+        &self,
+        aggregator: &Self::Type,
+        templ: &gst::PadTemplate,
+        req_name: Option<&str>,
+        caps: Option<&gst::Caps>,
+    ) -> Option<gst_base::AggregatorPad> {
+        let state = self.state.lock().unwrap();
+
+        // Only allow requesting new pads until the header was created
+        if !state.streams.is_empty() {
+            gst_error!(
+                CAT,
+                obj: aggregator,
+                "Can't request new pads after the header was created"
+            );
+            return None;
+        }
+
+        drop(state);
+
+        self.parent_create_new_pad(aggregator, templ, req_name, caps)
+    }
+
+    fn aggregate(
         &self,
-        element: &Self::Type,
-        transition: gst::StateChange,
-    ) -> Result<gst::StateChangeSuccess, gst::StateChangeError> {
-        gst_trace!(CAT, obj: element, "Changing state {:?}", transition);
+        aggregator: &Self::Type,
+        _timeout: bool,
+    ) -> Result<gst::FlowSuccess, gst::FlowError> {
+        let settings = self.settings.lock().unwrap().clone();
+
+        // Request the encryption keys before the streams are created, without the state lock
+        let stream_keys = if settings.encryption_scheme != super::EncryptionScheme::None
+            && self.state.lock().unwrap().streams.is_empty()
+        {
+            self.request_stream_keys(aggregator, &settings)
+        } else {
+            vec![]
+        };
+
+        let mut upstream_events = vec![];
+
+        let all_eos;
+        let (caps, buffers) = {
+            let mut state = self.state.lock().unwrap();
+
+            // Create streams and the initial header
+            let mut caps = None;
+            if state.streams.is_empty() {
+                self.create_streams(aggregator, &mut state, &settings, &stream_keys)?;
+
+                let (_, new_caps) = self
+                    .update_header(aggregator, &mut state, &settings, false)?
+                    .unwrap();
+                caps = Some(new_caps);
+            }
+
+            // Queue buffers from all streams that don't have enough data for the next fragment
+            // queued yet. Once the start of the first fragment is known, more data has to be
+            // queued from all streams.
+            loop {
+                self.queue_available_buffers(
+                    aggregator,
+                    &mut state,
+                    &settings,
+                    &mut upstream_events,
+                )?;
+
+                if !self.update_fragment_start_pts(aggregator, &mut state) {
+                    break;
+                }
+            }
+
+            all_eos = state.streams.iter().all(|stream| stream.sinkpad.is_eos());
+            if all_eos {
+                gst_debug!(CAT, obj: aggregator, "All streams are EOS now");
+            }
+
+            // If enough GOPs were queued, drain and create the output fragments or chunks. Each
+            // of them is pushed as a separate buffer list.
+            let mut buffers = vec![];
+            while let Some(buffer_list) = self.drain(aggregator, &mut state, &settings, all_eos)? {
+                buffers.push(buffer_list);
+
+                if all_eos {
+                    break;
+                }
+            }
+
+            (caps, buffers)
+        };
+
+        for (sinkpad, event) in upstream_events {
+            sinkpad.push_event(event);
+        }
+
+        if let Some(caps) = caps {
+            gst_debug!(CAT, obj: aggregator, "Setting caps on source pad: {:?}", caps);
+            aggregator.set_src_caps(&caps);
+        }
+
+        for buffer_list in buffers {
+            gst_trace!(CAT, obj: aggregator, "Pushing buffer list {:?}", buffer_list);
+            aggregator.finish_buffer_list(buffer_list)?;
+        }
 
-        let res = self.parent_change_state(element, transition)?;
+        if !all_eos {
+            return Ok(gst::FlowSuccess::Ok);
+        }
 
-        match transition {
-            gst::StateChange::PausedToReady => {
-                *self.state.lock().unwrap() = State::default();
+        // Create and push an updated header at EOS if needed
+        if settings.header_update_mode != super::HeaderUpdateMode::None {
+            let updated_header =
+                self.update_header(aggregator, &mut self.state.lock().unwrap(), &settings, true);
+            match updated_header {
+                Ok(Some((buffer_list, caps))) => match settings.header_update_mode {
+                    super::HeaderUpdateMode::None => unreachable!(),
+                    super::HeaderUpdateMode::Rewrite => {
+                        let src_pad = aggregator.static_pad("src").unwrap();
+                        let mut q = gst::query::Seeking::new(gst::Format::Bytes);
+                        if src_pad.peer_query(&mut q) && q.result().0 {
+                            aggregator.set_src_caps(&caps);
+
+                            // Seek to the beginning with a default bytes segment
+                            let segment = gst::FormattedSegment::<gst::format::Bytes>::new();
+                            src_pad.push_event(gst::event::Segment::new(&segment));
+
+                            if let Err(err) = aggregator.finish_buffer_list(buffer_list) {
+                                gst_error!(
+                                    CAT,
+                                    obj: aggregator,
+                                    "Failed pushing updated header buffer downstream: {:?}",
+                                    err,
+                                );
+                            }
+                        } else {
+                            gst_error!(
+                                CAT,
+                                obj: aggregator,
+                                "Can't rewrite header because downstream is not seekable"
+                            );
+                        }
+                    }
+                    super::HeaderUpdateMode::Update => {
+                        aggregator.set_src_caps(&caps);
+                        if let Err(err) = aggregator.finish_buffer_list(buffer_list) {
+                            gst_error!(
+                                CAT,
+                                obj: aggregator,
+                                "Failed pushing updated header buffer downstream: {:?}",
+                                err,
+                            );
+                        }
+                    }
+                },
+                Ok(None) => {}
+                Err(err) => {
+                    gst_error!(
+                        CAT,
+                        obj: aggregator,
+                        "Failed to generate updated header: {:?}",
+                        err
+                    );
+                }
             }
-            _ => (),
         }
 
-        Ok(res)
+        // Need to output new headers if started again after EOS
+        self.state.lock().unwrap().sequence_number = 0;
+
+        Err(gst::FlowError::Eos)
     }
 }
 
 #[repr(C)]
 pub(crate) struct Class {
-    parent: gst::ffi::GstElementClass,
+    parent: gst_base::ffi::GstAggregatorClass,
     variant: super::Variant,
 }
 
@@ -1248,7 +2281,7 @@ unsafe impl ClassStruct for Class {
 }
 
 impl std::ops::Deref for Class {
-    type Target = glib::Class<gst::Element>;
+    type Target = glib::Class<gst_base::Aggregator>;
 
     fn deref(&self) -> &Self::Target {
         unsafe { &*(&self.parent as *const _ as *const _) }
@@ -1264,7 +2297,7 @@ unsafe impl<T: FMP4MuxImpl> IsSubclassable<T> for super::FMP4Mux {
     }
 }
 
-pub(crate) trait FMP4MuxImpl: ElementImpl {
+pub(crate) trait FMP4MuxImpl: AggregatorImpl {
     const VARIANT: super::Variant;
 }
 
@@ -1298,20 +2331,21 @@ impl ElementImpl for ISOFMP4Mux {
 
     fn pad_templates() -> &'static [gst::PadTemplate] {
         static PAD_TEMPLATES: Lazy<Vec<gst::PadTemplate>> = Lazy::new(|| {
-            let src_pad_template = gst::PadTemplate::new(
+            let src_pad_template = gst::PadTemplate::with_gtype(
                 "src",
                 gst::PadDirection::Src,
                 gst::PadPresence::Always,
                 &gst::Caps::builder("video/quicktime")
                     .field("variant", "iso-fragmented")
                     .build(),
+                gst_base::AggregatorPad::static_type(),
             )
             .unwrap();
 
-            let sink_pad_template = gst::PadTemplate::new(
-                "sink",
+            let sink_pad_template = gst::PadTemplate::with_gtype(
+                "sink_%u",
                 gst::PadDirection::Sink,
-                gst::PadPresence::Always,
+                gst::PadPresence::Request,
                 &[
                     gst::Structure::builder("video/x-h264")
                         .field("stream-format", gst::List::new(["avc", "avc3"]))
@@ -1325,15 +2359,43 @@ impl ElementImpl for ISOFMP4Mux {
                         .field("width", gst::IntRange::new(1, u16::MAX as i32))
                         .field("height", gst::IntRange::new(1, u16::MAX as i32))
                         .build(),
+                    gst::Structure::builder("video/x-vp9")
+                        .field("profile", gst::List::new(["0", "1", "2", "3"]))
+                        .field("chroma-format", gst::List::new(["4:2:0", "4:2:2", "4:4:4"]))
+                        .field("bit-depth-luma", gst::List::new([8u32, 10u32, 12u32]))
+                        .field("bit-depth-chroma", gst::List::new([8u32, 10u32, 12u32]))
+                        .field("width", gst::IntRange::new(1, u16::MAX as i32))
+                        .field("height", gst::IntRange::new(1, u16::MAX as i32))
+                        .build(),
+                    gst::Structure::builder("video/x-av1")
+                        .field("stream-format", "obu-stream")
+                        .field("alignment", "tu")
+                        .field("width", gst::IntRange::new(1, u16::MAX as i32))
+                        .field("height", gst::IntRange::new(1, u16::MAX as i32))
+                        .build(),
                     gst::Structure::builder("audio/mpeg")
                         .field("mpegversion", 4i32)
                         .field("stream-format", "raw")
                         .field("channels", gst::IntRange::new(1, u16::MAX as i32))
                         .field("rate", gst::IntRange::new(1, i32::MAX))
                         .build(),
+                    gst::Structure::builder("audio/x-opus")
+                        .field("channel-mapping-family", gst::IntRange::new(0i32, 255))
+                        .field("channels", gst::IntRange::new(1i32, 8))
+                        .field("rate", gst::IntRange::new(1, i32::MAX))
+                        .build(),
+                    gst::Structure::builder("audio/x-flac")
+                        .field("framed", true)
+                        .field("channels", gst::IntRange::new(1i32, 8))
+                        .field("rate", gst::IntRange::new(1, 10 * u16::MAX as i32))
+                        .build(),
+                    gst::Structure::builder("application/x-subtitle-vtt").build(),
+                    gst::Structure::builder("application/x-subtitle-vtt-fragmented").build(),
+                    gst::Structure::builder("application/ttml+xml").build(),
                 ]
                 .into_iter()
                 .collect::<gst::Caps>(),
+                gst_base::AggregatorPad::static_type(),
             )
             .unwrap();
 
@@ -1344,6 +2406,8 @@ impl ElementImpl for ISOFMP4Mux {
     }
 }
 
+impl AggregatorImpl for ISOFMP4Mux {}
+
 impl FMP4MuxImpl for ISOFMP4Mux {
     const VARIANT: super::Variant = super::Variant::ISO;
 }
@@ -1378,17 +2442,18 @@ impl ElementImpl for CMAFMux {
 
     fn pad_templates() -> &'static [gst::PadTemplate] {
         static PAD_TEMPLATES: Lazy<Vec<gst::PadTemplate>> = Lazy::new(|| {
-            let src_pad_template = gst::PadTemplate::new(
+            let src_pad_template = gst::PadTemplate::with_gtype(
                 "src",
                 gst::PadDirection::Src,
                 gst::PadPresence::Always,
                 &gst::Caps::builder("video/quicktime")
                     .field("variant", "cmaf")
                     .build(),
+                gst_base::AggregatorPad::static_type(),
             )
             .unwrap();
 
-            let sink_pad_template = gst::PadTemplate::new(
+            let sink_pad_template = gst::PadTemplate::with_gtype(
                 "sink",
                 gst::PadDirection::Sink,
                 gst::PadPresence::Always,
@@ -1405,15 +2470,43 @@ impl ElementImpl for CMAFMux {
                         .field("width", gst::IntRange::new(1, u16::MAX as i32))
                         .field("height", gst::IntRange::new(1, u16::MAX as i32))
                         .build(),
+                    gst::Structure::builder("video/x-vp9")
+                        .field("profile", gst::List::new(["0", "1", "2", "3"]))
+                        .field("chroma-format", gst::List::new(["4:2:0", "4:2:2", "4:4:4"]))
+                        .field("bit-depth-luma", gst::List::new([8u32, 10u32, 12u32]))
+                        .field("bit-depth-chroma", gst::List::new([8u32, 10u32, 12u32]))
+                        .field("width", gst::IntRange::new(1, u16::MAX as i32))
+                        .field("height", gst::IntRange::new(1, u16::MAX as i32))
+                        .build(),
+                    gst::Structure::builder("video/x-av1")
+                        .field("stream-format", "obu-stream")
+                        .field("alignment", "tu")
+                        .field("width", gst::IntRange::new(1, u16::MAX as i32))
+                        .field("height", gst::IntRange::new(1, u16::MAX as i32))
+                        .build(),
                     gst::Structure::builder("audio/mpeg")
                         .field("mpegversion", 4i32)
                         .field("stream-format", "raw")
                         .field("channels", gst::IntRange::new(1, u16::MAX as i32))
                         .field("rate", gst::IntRange::new(1, i32::MAX))
                         .build(),
+                    gst::Structure::builder("audio/x-opus")
+                        .field("channel-mapping-family", gst::IntRange::new(0i32, 255))
+                        .field("channels", gst::IntRange::new(1i32, 8))
+                        .field("rate", gst::IntRange::new(1, i32::MAX))
+                        .build(),
+                    gst::Structure::builder("audio/x-flac")
+                        .field("framed", true)
+                        .field("channels", gst::IntRange::new(1i32, 8))
+                        .field("rate", gst::IntRange::new(1, 10 * u16::MAX as i32))
+                        .build(),
+                    gst::Structure::builder("application/x-subtitle-vtt").build(),
+                    gst::Structure::builder("application/x-subtitle-vtt-fragmented").build(),
+                    gst::Structure::builder("application/ttml+xml").build(),
                 ]
                 .into_iter()
                 .collect::<gst::Caps>(),
+                gst_base::AggregatorPad::static_type(),
             )
             .unwrap();
 
@@ -1424,6 +2517,8 @@ impl ElementImpl for CMAFMux {
     }
 }
 
+impl AggregatorImpl for CMAFMux {}
+
 impl FMP4MuxImpl for CMAFMux {
     const VARIANT: super::Variant = super::Variant::CMAF;
 }
@@ -1458,41 +2553,71 @@ impl ElementImpl for DASHMP4Mux {
 
     fn pad_templates() -> &'static [gst::PadTemplate] {
         static PAD_TEMPLATES: Lazy<Vec<gst::PadTemplate>> = Lazy::new(|| {
-            let src_pad_template = gst::PadTemplate::new(
+            let src_pad_template = gst::PadTemplate::with_gtype(
                 "src",
                 gst::PadDirection::Src,
                 gst::PadPresence::Always,
                 &gst::Caps::builder("video/quicktime")
                     .field("variant", "iso-fragmented")
                     .build(),
+                gst_base::AggregatorPad::static_type(),
             )
             .unwrap();
 
-            let sink_pad_template = gst::PadTemplate::new(
-                "sink",
+            let sink_pad_template = gst::PadTemplate::with_gtype(
+                "sink_%u",
                 gst::PadDirection::Sink,
-                gst::PadPresence::Always,
-                &std::array::IntoIter::new([
+                gst::PadPresence::Request,
+                &[
                     gst::Structure::builder("video/x-h264")
-                        .field("stream-format", gst::List::new(&[&"avc", &"avc3"]))
+                        .field("stream-format", gst::List::new(["avc", "avc3"]))
                         .field("alignment", "au")
-                        .field("width", gst::IntRange::<i32>::new(1, u16::MAX as i32))
-                        .field("height", gst::IntRange::<i32>::new(1, u16::MAX as i32))
+                        .field("width", gst::IntRange::new(1, u16::MAX as i32))
+                        .field("height", gst::IntRange::new(1, u16::MAX as i32))
                         .build(),
                     gst::Structure::builder("video/x-h265")
-                        .field("stream-format", gst::List::new(&[&"hvc1", &"hev1"]))
+                        .field("stream-format", gst::List::new(["hvc1", "hev1"]))
                         .field("alignment", "au")
-                        .field("width", gst::IntRange::<i32>::new(1, u16::MAX as i32))
-                        .field("height", gst::IntRange::<i32>::new(1, u16::MAX as i32))
+                        .field("width", gst::IntRange::new(1, u16::MAX as i32))
+                        .field("height", gst::IntRange::new(1, u16::MAX as i32))
+                        .build(),
+                    gst::Structure::builder("video/x-vp9")
+                        .field("profile", gst::List::new(["0", "1", "2", "3"]))
+                        .field("chroma-format", gst::List::new(["4:2:0", "4:2:2", "4:4:4"]))
+                        .field("bit-depth-luma", gst::List::new([8u32, 10u32, 12u32]))
+                        .field("bit-depth-chroma", gst::List::new([8u32, 10u32, 12u32]))
+                        .field("width", gst::IntRange::new(1, u16::MAX as i32))
+                        .field("height", gst::IntRange::new(1, u16::MAX as i32))
+                        .build(),
+                    gst::Structure::builder("video/x-av1")
+                        .field("stream-format", "obu-stream")
+                        .field("alignment", "tu")
+                        .field("width", gst::IntRange::new(1, u16::MAX as i32))
+                        .field("height", gst::IntRange::new(1, u16::MAX as i32))
                         .build(),
                     gst::Structure::builder("audio/mpeg")
                         .field("mpegversion", 4i32)
                         .field("stream-format", "raw")
-                        .field("channels", gst::IntRange::<i32>::new(1, u16::MAX as i32))
-                        .field("rate", gst::IntRange::<i32>::new(1, i32::MAX))
+                        .field("channels", gst::IntRange::new(1, u16::MAX as i32))
+                        .field("rate", gst::IntRange::new(1, i32::MAX))
+                        .build(),
+                    gst::Structure::builder("audio/x-opus")
+                        .field("channel-mapping-family", gst::IntRange::new(0i32, 255))
+                        .field("channels", gst::IntRange::new(1i32, 8))
+                        .field("rate", gst::IntRange::new(1, i32::MAX))
                         .build(),
-                ])
+                    gst::Structure::builder("audio/x-flac")
+                        .field("framed", true)
+                        .field("channels", gst::IntRange::new(1i32, 8))
+                        .field("rate", gst::IntRange::new(1, 10 * u16::MAX as i32))
+                        .build(),
+                    gst::Structure::builder("application/x-subtitle-vtt").build(),
+                    gst::Structure::builder("application/x-subtitle-vtt-fragmented").build(),
+                    gst::Structure::builder("application/ttml+xml").build(),
+                ]
+                .into_iter()
                 .collect::<gst::Caps>(),
+                gst_base::AggregatorPad::static_type(),
             )
             .unwrap();
 
@@ -1503,6 +2628,8 @@ impl ElementImpl for DASHMP4Mux {
     }
 }
 
+impl AggregatorImpl for DASHMP4Mux {}
+
 impl FMP4MuxImpl for DASHMP4Mux {
     const VARIANT: super::Variant = super::Variant::DASH;
 }
@@ -9,32 +9,33 @@
 use gst::glib;
 use gst::prelude::*;
 
-mod boxes;
+pub(crate) mod boxes;
+mod cenc;
 mod imp;
 
 glib::wrapper! {
-    pub(crate) struct FMP4Mux(ObjectSubclass<imp::FMP4Mux>) @extends gst::Element, gst::Object;
+    pub(crate) struct FMP4Mux(ObjectSubclass<imp::FMP4Mux>) @extends gst_base::Aggregator, gst::Element, gst::Object;
 }
 
 unsafe impl Send for FMP4Mux {}
 unsafe impl Sync for FMP4Mux {}
 
 glib::wrapper! {
-    pub(crate) struct ISOFMP4Mux(ObjectSubclass<imp::ISOFMP4Mux>) @extends FMP4Mux, gst::Element, gst::Object;
+    pub(crate) struct ISOFMP4Mux(ObjectSubclass<imp::ISOFMP4Mux>) @extends FMP4Mux, gst_base::Aggregator, gst::Element, gst::Object;
 }
 
 unsafe impl Send for ISOFMP4Mux {}
 unsafe impl Sync for ISOFMP4Mux {}
 
 glib::wrapper! {
-    pub(crate) struct CMAFMux(ObjectSubclass<imp::CMAFMux>) @extends FMP4Mux, gst::Element, gst::Object;
+    pub(crate) struct CMAFMux(ObjectSubclass<imp::CMAFMux>) @extends FMP4Mux, gst_base::Aggregator, gst::Element, gst::Object;
 }
 
 unsafe impl Send for CMAFMux {}
 unsafe impl Sync for CMAFMux {}
 
 glib::wrapper! {
-    pub(crate) struct DASHMP4Mux(ObjectSubclass<imp::DASHMP4Mux>) @extends FMP4Mux, gst::Element, gst::Object;
+    pub(crate) struct DASHMP4Mux(ObjectSubclass<imp::DASHMP4Mux>) @extends FMP4Mux, gst_base::Aggregator, gst::Element, gst::Object;
 }
 
 unsafe impl Send for DASHMP4Mux {}
@@ -65,27 +66,82 @@ pub fn register(plugin: &gst::Plugin) -> Result<(), glib::BoolError> {
 
 #[derive(Debug)]
 pub(crate) struct Buffer {
+    /// Track index
+    idx: usize,
     buffer: gst::Buffer,
     // Running times
     pts: gst::ClockTime,
     dts: Option<gst::ClockTime>,
+    /// Sample auxiliary information if the buffer is encrypted
+    encryption: Option<SampleEncryption>,
 }
 
 #[derive(Debug)]
 pub(crate) struct HeaderConfiguration<'a> {
     variant: Variant,
     update: bool,
-    caps: &'a gst::Caps,
+    /// First caps must be the video/reference stream. Must be in the order the tracks are going to
+    /// be used later for the fragments too.
+    streams: &'a [gst::Caps],
     write_mehd: bool,
     duration: Option<gst::ClockTime>,
+    /// Encryption of each stream, `None` for clear streams. Same order as `streams`.
+    encryption: &'a [Option<TrackEncryption>],
+    /// Protection system specific header boxes.
+    pssh: &'a [gst::Buffer],
 }
 
 #[derive(Debug)]
 pub(crate) struct FragmentHeaderConfiguration<'a> {
     variant: Variant,
     sequence_number: u32,
-    caps: &'a gst::Caps,
+    /// Caps and timing information of each stream, `None` if the stream has no buffers in this
+    /// fragment. Same order as in the `HeaderConfiguration`.
+    streams: &'a [(gst::Caps, Option<FragmentTimingInfo>)],
+    /// Buffers of all streams, grouped by stream and in the same order as `streams`.
     buffers: &'a [Buffer],
+    /// Event messages to put in front of the `moof`.
+    emsgs: &'a [Emsg],
+    /// Encryption of each stream, `None` for clear streams. Same order as `streams`.
+    encryption: &'a [Option<TrackEncryption>],
+}
+
+/// Event message, e.g. SCTE-35 or ID3 timed metadata.
+#[derive(Debug)]
+pub(crate) struct Emsg {
+    scheme_id_uri: String,
+    value: String,
+    /// Running time
+    presentation_time: gst::ClockTime,
+    duration: Option<gst::ClockTime>,
+    id: u32,
+    message_data: Option<gst::Buffer>,
+}
+
+/// Common Encryption parameters of a track as stored in the `tenc` box.
+#[derive(Debug, Clone)]
+pub(crate) struct TrackEncryption {
+    scheme: EncryptionScheme,
+    key_id: [u8; 16],
+    /// Size of the IV stored with each sample, 0 if `constant_iv` is used.
+    per_sample_iv_size: u8,
+    constant_iv: Option<[u8; 16]>,
+    /// Number of encrypted and skipped blocks of the pattern, both 0 if all blocks are encrypted.
+    crypt_byte_block: u8,
+    skip_byte_block: u8,
+}
+
+/// Sample auxiliary information of an encrypted sample as stored in the `senc` box.
+#[derive(Debug, Clone)]
+pub(crate) struct SampleEncryption {
+    /// Empty if the track uses a constant IV.
+    iv: Vec<u8>,
+    /// Clear and protected bytes of each subsample, empty if the whole sample is protected.
+    subsamples: Vec<(u16, u32)>,
+}
+
+#[derive(Debug)]
+pub(crate) struct FragmentTimingInfo {
     earliest_pts: gst::ClockTime,
     start_dts: Option<gst::ClockTime>,
     end_pts: gst::ClockTime,
@@ -104,8 +160,9 @@ pub(crate) enum Variant {
 
 #[derive(Debug)]
 pub(crate) struct FragmentOffset {
-    time: gst::ClockTime,
     offset: u64,
+    /// Stream index and earliest PTS of each `traf` in the `moof`, in order
+    trafs: Vec<(usize, gst::ClockTime)>,
 }
 
 #[allow(clippy::upper_case_acronyms)]
@@ -117,3 +174,12 @@ pub(crate) enum HeaderUpdateMode {
     Rewrite,
     Update,
 }
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, glib::Enum)]
+#[repr(i32)]
+#[enum_type(name = "GstFMP4MuxEncryptionScheme")]
+pub(crate) enum EncryptionScheme {
+    None,
+    Cenc,
+    Cbcs,
+}
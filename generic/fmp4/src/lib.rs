@@ -8,10 +8,14 @@
 
 use gst::glib;
 
+mod fmp4demux;
 mod fmp4mux;
 
 fn plugin_init(plugin: &gst::Plugin) -> Result<(), glib::BoolError> {
-    fmp4mux::register(plugin)
+    fmp4mux::register(plugin)?;
+    fmp4demux::register(plugin)?;
+
+    Ok(())
 }
 
 gst::plugin_define!(
@@ -6,6 +6,8 @@
 // SPDX-License-Identifier: MPL-2.0
 //
 
+use gst::prelude::*;
+
 fn init() {
     use std::sync::Once;
     static INIT: Once = Once::new();
@@ -21,7 +23,10 @@ fn test_buffer_flags() {
     init();
 
     // 5s fragment duration
-    let mut h = gst_check::Harness::new_parse("isofmp4mux fragment-duration=5000000000");
+    let mut h = gst_check::Harness::with_padnames("isofmp4mux", Some("sink_0"), Some("src"));
+    h.element()
+        .unwrap()
+        .set_property("fragment-duration", gst::ClockTime::from_seconds(5));
     h.set_src_caps(
         gst::Caps::builder("video/x-h264")
             .field("width", 1920i32)
@@ -137,3 +142,903 @@ fn test_buffer_flags() {
     let ev = h.pull_event().unwrap();
     assert_eq!(ev.type_(), gst::EventType::Eos);
 }
+
+#[test]
+fn test_buffer_flags_multi_stream() {
+    init();
+
+    let mut h1 = gst_check::Harness::with_padnames("isofmp4mux", Some("sink_0"), Some("src"));
+    let mut h2 = gst_check::Harness::with_element(&h1.element().unwrap(), Some("sink_1"), None);
+
+    // 5s fragment duration
+    h1.element()
+        .unwrap()
+        .set_property("fragment-duration", gst::ClockTime::from_seconds(5));
+
+    h1.set_src_caps(
+        gst::Caps::builder("video/x-h264")
+            .field("width", 1920i32)
+            .field("height", 1080i32)
+            .field("framerate", gst::Fraction::new(30, 1))
+            .field("stream-format", "avc")
+            .field("alignment", "au")
+            .field("codec_data", gst::Buffer::with_size(1).unwrap())
+            .build(),
+    );
+    h1.play();
+
+    h2.set_src_caps(
+        gst::Caps::builder("audio/mpeg")
+            .field("mpegversion", 4i32)
+            .field("channels", 1i32)
+            .field("rate", 44100i32)
+            .field("stream-format", "raw")
+            .field("base-profile", "lc")
+            .field("profile", "lc")
+            .field("level", "2")
+            .field(
+                "codec_data",
+                gst::Buffer::from_slice([0x12, 0x08, 0x56, 0xe5, 0x00]),
+            )
+            .build(),
+    );
+    h2.play();
+
+    // Push 7 buffers of 1s each, 1st and last buffer without DELTA_UNIT flag
+    for i in 0..7 {
+        let mut buffer = gst::Buffer::with_size(1).unwrap();
+        {
+            let buffer = buffer.get_mut().unwrap();
+            buffer.set_pts(gst::ClockTime::from_seconds(i));
+            buffer.set_dts(gst::ClockTime::from_seconds(i));
+            buffer.set_duration(gst::ClockTime::SECOND);
+            if i != 0 && i != 5 {
+                buffer.set_flags(gst::BufferFlags::DELTA_UNIT);
+            }
+        }
+        assert_eq!(h1.push(buffer), Ok(gst::FlowSuccess::Ok));
+
+        let mut buffer = gst::Buffer::with_size(1).unwrap();
+        {
+            let buffer = buffer.get_mut().unwrap();
+            buffer.set_pts(gst::ClockTime::from_seconds(i));
+            buffer.set_dts(gst::ClockTime::from_seconds(i));
+            buffer.set_duration(gst::ClockTime::SECOND);
+        }
+        assert_eq!(h2.push(buffer), Ok(gst::FlowSuccess::Ok));
+
+        if i == 2 {
+            let ev = loop {
+                let ev = h1.pull_upstream_event().unwrap();
+                if ev.type_() != gst::EventType::Reconfigure
+                    && ev.type_() != gst::EventType::Latency
+                {
+                    break ev;
+                }
+            };
+
+            assert_eq!(ev.type_(), gst::EventType::CustomUpstream);
+            assert_eq!(
+                gst_video::UpstreamForceKeyUnitEvent::parse(&ev).unwrap(),
+                gst_video::UpstreamForceKeyUnitEvent {
+                    running_time: Some(gst::ClockTime::from_seconds(5)),
+                    all_headers: true,
+                    count: 0
+                }
+            );
+        }
+    }
+
+    let header = h1.pull().unwrap();
+    assert_eq!(
+        header.flags(),
+        gst::BufferFlags::HEADER | gst::BufferFlags::DISCONT
+    );
+    assert_eq!(header.pts(), Some(gst::ClockTime::ZERO));
+    assert_eq!(header.dts(), Some(gst::ClockTime::ZERO));
+
+    let fragment_header = h1.pull().unwrap();
+    assert_eq!(fragment_header.flags(), gst::BufferFlags::HEADER);
+    assert_eq!(fragment_header.pts(), Some(gst::ClockTime::ZERO));
+    assert_eq!(fragment_header.dts(), Some(gst::ClockTime::ZERO));
+    assert_eq!(
+        fragment_header.duration(),
+        Some(gst::ClockTime::from_seconds(5))
+    );
+
+    // Video buffers first, then audio buffers
+    for j in 0..2 {
+        for i in 0..5 {
+            let buffer = h1.pull().unwrap();
+            if i == 4 && j == 1 {
+                assert_eq!(
+                    buffer.flags(),
+                    gst::BufferFlags::DELTA_UNIT | gst::BufferFlags::MARKER
+                );
+            } else {
+                assert_eq!(buffer.flags(), gst::BufferFlags::DELTA_UNIT);
+            }
+            assert_eq!(buffer.pts(), Some(gst::ClockTime::from_seconds(i)));
+            if j == 0 {
+                assert_eq!(buffer.dts(), Some(gst::ClockTime::from_seconds(i)));
+            }
+            assert_eq!(buffer.duration(), Some(gst::ClockTime::SECOND));
+        }
+    }
+
+    h1.push_event(gst::event::Eos::new());
+    h2.push_event(gst::event::Eos::new());
+
+    let fragment_header = h1.pull().unwrap();
+    assert_eq!(fragment_header.flags(), gst::BufferFlags::HEADER);
+    assert_eq!(fragment_header.pts(), Some(gst::ClockTime::from_seconds(5)));
+    assert_eq!(fragment_header.dts(), Some(gst::ClockTime::from_seconds(5)));
+    assert_eq!(
+        fragment_header.duration(),
+        Some(gst::ClockTime::from_seconds(2))
+    );
+
+    for j in 0..2 {
+        for i in 5..7 {
+            let buffer = h1.pull().unwrap();
+            if i == 6 && j == 1 {
+                assert_eq!(
+                    buffer.flags(),
+                    gst::BufferFlags::DELTA_UNIT | gst::BufferFlags::MARKER
+                );
+            } else {
+                assert_eq!(buffer.flags(), gst::BufferFlags::DELTA_UNIT);
+            }
+            assert_eq!(buffer.pts(), Some(gst::ClockTime::from_seconds(i)));
+            if j == 0 {
+                assert_eq!(buffer.dts(), Some(gst::ClockTime::from_seconds(i)));
+            }
+            assert_eq!(buffer.duration(), Some(gst::ClockTime::SECOND));
+        }
+    }
+
+    let ev = h1.pull_event().unwrap();
+    assert_eq!(ev.type_(), gst::EventType::StreamStart);
+    let ev = h1.pull_event().unwrap();
+    assert_eq!(ev.type_(), gst::EventType::Caps);
+    let ev = h1.pull_event().unwrap();
+    assert_eq!(ev.type_(), gst::EventType::Segment);
+    let ev = h1.pull_event().unwrap();
+    assert_eq!(ev.type_(), gst::EventType::Eos);
+}
+
+#[test]
+fn test_chunking() {
+    init();
+
+    let mut h = gst_check::Harness::with_padnames("isofmp4mux", Some("sink_0"), Some("src"));
+    // 5s fragment duration, 2s chunk duration
+    h.element()
+        .unwrap()
+        .set_property("fragment-duration", gst::ClockTime::from_seconds(5));
+    h.element()
+        .unwrap()
+        .set_property("chunk-duration", gst::ClockTime::from_seconds(2));
+    h.set_src_caps(
+        gst::Caps::builder("video/x-h264")
+            .field("width", 1920i32)
+            .field("height", 1080i32)
+            .field("framerate", gst::Fraction::new(30, 1))
+            .field("stream-format", "avc")
+            .field("alignment", "au")
+            .field("codec_data", gst::Buffer::with_size(1).unwrap())
+            .build(),
+    );
+    h.play();
+
+    // Push 7 buffers of 1s each, 1st and 6th buffer without DELTA_UNIT flag
+    for i in 0..7 {
+        let mut buffer = gst::Buffer::with_size(1).unwrap();
+        {
+            let buffer = buffer.get_mut().unwrap();
+            buffer.set_pts(gst::ClockTime::from_seconds(i));
+            buffer.set_dts(gst::ClockTime::from_seconds(i));
+            buffer.set_duration(gst::ClockTime::SECOND);
+            if i != 0 && i != 5 {
+                buffer.set_flags(gst::BufferFlags::DELTA_UNIT);
+            }
+        }
+        assert_eq!(h.push(buffer), Ok(gst::FlowSuccess::Ok));
+    }
+
+    let header = h.pull().unwrap();
+    assert_eq!(
+        header.flags(),
+        gst::BufferFlags::HEADER | gst::BufferFlags::DISCONT
+    );
+    assert_eq!(header.pts(), Some(gst::ClockTime::ZERO));
+    assert_eq!(header.dts(), Some(gst::ClockTime::ZERO));
+
+    // Fragment start, chunk of 2s, chunk of 2s, chunk of 1s until the next keyframe
+    for (chunk_start, chunk_end, fragment_start) in [(0, 2, true), (2, 4, false), (4, 5, false)] {
+        let chunk_header = h.pull().unwrap();
+        if fragment_start {
+            assert_eq!(chunk_header.flags(), gst::BufferFlags::HEADER);
+        } else {
+            assert_eq!(
+                chunk_header.flags(),
+                gst::BufferFlags::HEADER | gst::BufferFlags::DELTA_UNIT
+            );
+        }
+        assert_eq!(
+            chunk_header.pts(),
+            Some(gst::ClockTime::from_seconds(chunk_start))
+        );
+        assert_eq!(
+            chunk_header.dts(),
+            Some(gst::ClockTime::from_seconds(chunk_start))
+        );
+        assert_eq!(
+            chunk_header.duration(),
+            Some(gst::ClockTime::from_seconds(chunk_end - chunk_start))
+        );
+
+        for i in chunk_start..chunk_end {
+            let buffer = h.pull().unwrap();
+            if i == chunk_end - 1 {
+                assert_eq!(
+                    buffer.flags(),
+                    gst::BufferFlags::DELTA_UNIT | gst::BufferFlags::MARKER
+                );
+            } else {
+                assert_eq!(buffer.flags(), gst::BufferFlags::DELTA_UNIT);
+            }
+            assert_eq!(buffer.pts(), Some(gst::ClockTime::from_seconds(i)));
+            assert_eq!(buffer.dts(), Some(gst::ClockTime::from_seconds(i)));
+            assert_eq!(buffer.duration(), Some(gst::ClockTime::SECOND));
+        }
+    }
+
+    h.push_event(gst::event::Eos::new());
+
+    // Start of the second fragment
+    let chunk_header = h.pull().unwrap();
+    assert_eq!(chunk_header.flags(), gst::BufferFlags::HEADER);
+    assert_eq!(chunk_header.pts(), Some(gst::ClockTime::from_seconds(5)));
+    assert_eq!(chunk_header.dts(), Some(gst::ClockTime::from_seconds(5)));
+    assert_eq!(
+        chunk_header.duration(),
+        Some(gst::ClockTime::from_seconds(2))
+    );
+
+    for i in 5..7 {
+        let buffer = h.pull().unwrap();
+        if i == 6 {
+            assert_eq!(
+                buffer.flags(),
+                gst::BufferFlags::DELTA_UNIT | gst::BufferFlags::MARKER
+            );
+        } else {
+            assert_eq!(buffer.flags(), gst::BufferFlags::DELTA_UNIT);
+        }
+        assert_eq!(buffer.pts(), Some(gst::ClockTime::from_seconds(i)));
+        assert_eq!(buffer.dts(), Some(gst::ClockTime::from_seconds(i)));
+        assert_eq!(buffer.duration(), Some(gst::ClockTime::SECOND));
+    }
+
+    let ev = h.pull_event().unwrap();
+    assert_eq!(ev.type_(), gst::EventType::StreamStart);
+    let ev = h.pull_event().unwrap();
+    assert_eq!(ev.type_(), gst::EventType::Caps);
+    let ev = h.pull_event().unwrap();
+    assert_eq!(ev.type_(), gst::EventType::Segment);
+    let ev = h.pull_event().unwrap();
+    assert_eq!(ev.type_(), gst::EventType::Eos);
+}
+
+/// Muxes a single buffer with the given caps and returns the header
+fn header_for_caps(caps: gst::Caps) -> gst::Buffer {
+    let mut h = gst_check::Harness::with_padnames("isofmp4mux", Some("sink_0"), Some("src"));
+    h.set_src_caps(caps);
+    h.play();
+
+    let mut buffer = gst::Buffer::with_size(1).unwrap();
+    {
+        let buffer = buffer.get_mut().unwrap();
+        buffer.set_pts(gst::ClockTime::ZERO);
+        buffer.set_dts(gst::ClockTime::ZERO);
+        buffer.set_duration(gst::ClockTime::SECOND);
+    }
+    assert_eq!(h.push(buffer), Ok(gst::FlowSuccess::Ok));
+    h.push_event(gst::event::Eos::new());
+
+    let header = h.pull().unwrap();
+    assert_eq!(
+        header.flags(),
+        gst::BufferFlags::HEADER | gst::BufferFlags::DISCONT
+    );
+
+    header
+}
+
+/// Returns the content of the first box of type `fourcc` in `data`
+fn find_box<'a>(data: &'a [u8], fourcc: &[u8; 4]) -> &'a [u8] {
+    for pos in 4..data.len().saturating_sub(4) {
+        if &data[pos..][..4] != fourcc {
+            continue;
+        }
+
+        let size = u32::from_be_bytes(data[pos - 4..pos].try_into().unwrap()) as usize;
+        if size >= 8 && pos - 4 + size <= data.len() {
+            return &data[pos + 4..pos - 4 + size];
+        }
+    }
+
+    panic!("No {} box", String::from_utf8_lossy(fourcc));
+}
+
+#[test]
+fn test_header_vp9() {
+    init();
+
+    let header = header_for_caps(
+        gst::Caps::builder("video/x-vp9")
+            .field("width", 1920i32)
+            .field("height", 1080i32)
+            .field("framerate", gst::Fraction::new(30, 1))
+            .field("profile", "0")
+            .field("chroma-format", "4:2:0")
+            .field("bit-depth-luma", 8u32)
+            .field("bit-depth-chroma", 8u32)
+            .field("colorimetry", "bt709")
+            .build(),
+    );
+    let map = header.map_readable().unwrap();
+
+    let sample_entry = find_box(&map, b"vp09");
+    assert_eq!(&sample_entry[24..28], &[0x07, 0x80, 0x04, 0x38]);
+
+    // Version 1, profile 0, level 4.0, 8 bit 4:2:0 with the chroma co-located with luma,
+    // limited range BT.709 and no codec initialization data
+    assert_eq!(
+        find_box(sample_entry, b"vpcC"),
+        &[1, 0, 0, 0, 0, 40, 0x82, 1, 1, 1, 0, 0]
+    );
+}
+
+#[test]
+fn test_header_av1() {
+    init();
+
+    let header = header_for_caps(
+        gst::Caps::builder("video/x-av1")
+            .field("width", 1280i32)
+            .field("height", 720i32)
+            .field("framerate", gst::Fraction::new(30, 1))
+            .field("stream-format", "obu-stream")
+            .field("alignment", "tu")
+            .field("profile", "main")
+            .field("level", "4.0")
+            .build(),
+    );
+    let map = header.map_readable().unwrap();
+
+    let sample_entry = find_box(&map, b"av01");
+    assert_eq!(&sample_entry[24..28], &[0x05, 0x00, 0x02, 0xd0]);
+
+    // Marker and version, main profile at level 4.0, main tier 8 bit 4:2:0 and no
+    // configuration OBUs
+    assert_eq!(find_box(sample_entry, b"av1C"), &[0x81, 0x08, 0x0c, 0x00]);
+}
+
+#[test]
+fn test_header_opus() {
+    init();
+
+    let header = header_for_caps(
+        gst::Caps::builder("audio/x-opus")
+            .field("channel-mapping-family", 0i32)
+            .field("channels", 2i32)
+            .field("rate", 44_100i32)
+            .build(),
+    );
+    let map = header.map_readable().unwrap();
+
+    // Stereo, always at 48kHz
+    let sample_entry = find_box(&map, b"Opus");
+    assert_eq!(&sample_entry[16..18], &[0x00, 0x02]);
+    assert_eq!(&sample_entry[24..28], &[0xbb, 0x80, 0x00, 0x00]);
+
+    // Version 0, 2 channels, no pre-skip, the input rate, no gain and mapping family 0
+    assert_eq!(
+        find_box(sample_entry, b"dOps"),
+        &[0, 2, 0, 0, 0x00, 0x00, 0xac, 0x44, 0, 0, 0]
+    );
+}
+
+#[test]
+fn test_header_flac() {
+    init();
+
+    let streaminfo = (0..34).collect::<Vec<u8>>();
+    let mut streamheader = vec![0x7f, b'F', b'L', b'A', b'C', 1, 0, 0, 1];
+    streamheader.extend_from_slice(b"fLaC");
+    streamheader.extend_from_slice(&[0x00, 0x00, 0x00, 34]);
+    streamheader.extend_from_slice(&streaminfo);
+
+    let header = header_for_caps(
+        gst::Caps::builder("audio/x-flac")
+            .field("framed", true)
+            .field("channels", 2i32)
+            .field("rate", 44_100i32)
+            .field(
+                "streamheader",
+                gst::Array::new(&[&gst::Buffer::from_slice(streamheader)]),
+            )
+            .build(),
+    );
+    let map = header.map_readable().unwrap();
+
+    let sample_entry = find_box(&map, b"fLaC");
+    assert_eq!(&sample_entry[16..18], &[0x00, 0x02]);
+    assert_eq!(&sample_entry[24..28], &[0xac, 0x44, 0x00, 0x00]);
+
+    // Full box header, followed by the STREAMINFO block marked as the last metadata block
+    let mut expected = vec![0, 0, 0, 0, 0x80, 0x00, 0x00, 34];
+    expected.extend_from_slice(&streaminfo);
+    assert_eq!(find_box(sample_entry, b"dfLa"), &expected[..]);
+}
+
+#[test]
+fn test_chunk_duration_longer_than_fragment() {
+    init();
+
+    let element = gst::ElementFactory::make("isofmp4mux", None).unwrap();
+    element.set_property("fragment-duration", gst::ClockTime::from_seconds(2));
+    element.set_property("chunk-duration", gst::ClockTime::from_seconds(5));
+
+    assert!(element.set_state(gst::State::Paused).is_err());
+    element.set_state(gst::State::Null).unwrap();
+}
+
+#[test]
+fn test_emsg() {
+    init();
+
+    // 5s fragment duration
+    let mut h = gst_check::Harness::with_padnames("isofmp4mux", Some("sink_0"), Some("src"));
+    h.element()
+        .unwrap()
+        .set_property("fragment-duration", gst::ClockTime::from_seconds(5));
+    h.set_src_caps(
+        gst::Caps::builder("video/x-h264")
+            .field("width", 1920i32)
+            .field("height", 1080i32)
+            .field("framerate", gst::Fraction::new(30, 1))
+            .field("stream-format", "avc")
+            .field("alignment", "au")
+            .field("codec_data", gst::Buffer::with_size(1).unwrap())
+            .build(),
+    );
+    h.play();
+
+    // Push 7 buffers of 1s each, 1st and last buffer without DELTA_UNIT flag, and an event
+    // message at 6s
+    for i in 0..7 {
+        if i == 6 {
+            let ev = gst::event::CustomDownstream::new(
+                gst::Structure::builder("emsg")
+                    .field("scheme-id-uri", "urn:scte:scte35:2013:bin")
+                    .field("pts", gst::ClockTime::from_seconds(6))
+                    .field("id", 1u32)
+                    .field("message-data", gst::Buffer::from_slice([0u8; 4]))
+                    .build(),
+            );
+            assert!(h.push_event(ev));
+        }
+
+        let mut buffer = gst::Buffer::with_size(1).unwrap();
+        {
+            let buffer = buffer.get_mut().unwrap();
+            buffer.set_pts(gst::ClockTime::from_seconds(i));
+            buffer.set_dts(gst::ClockTime::from_seconds(i));
+            buffer.set_duration(gst::ClockTime::SECOND);
+            if i != 0 && i != 5 {
+                buffer.set_flags(gst::BufferFlags::DELTA_UNIT);
+            }
+        }
+        assert_eq!(h.push(buffer), Ok(gst::FlowSuccess::Ok));
+    }
+
+    let contains_emsg = |buffer: &gst::Buffer| {
+        let map = buffer.map_readable().unwrap();
+        map.windows(4).any(|w| w == b"emsg")
+    };
+
+    let header = h.pull().unwrap();
+    assert_eq!(
+        header.flags(),
+        gst::BufferFlags::HEADER | gst::BufferFlags::DISCONT
+    );
+
+    // The first fragment ends before the event message
+    let fragment_header = h.pull().unwrap();
+    assert_eq!(fragment_header.flags(), gst::BufferFlags::HEADER);
+    assert!(!contains_emsg(&fragment_header));
+    for _ in 0..5 {
+        let _ = h.pull().unwrap();
+    }
+
+    h.push_event(gst::event::Eos::new());
+
+    let fragment_header = h.pull().unwrap();
+    assert_eq!(fragment_header.flags(), gst::BufferFlags::HEADER);
+    assert_eq!(fragment_header.pts(), Some(gst::ClockTime::from_seconds(5)));
+    assert!(contains_emsg(&fragment_header));
+}
+
+/// Creates a box of type `fourcc` with the given content
+fn mp4_box(fourcc: &[u8; 4], content: &[u8]) -> Vec<u8> {
+    let mut v = (8 + content.len() as u32).to_be_bytes().to_vec();
+    v.extend_from_slice(fourcc);
+    v.extend_from_slice(content);
+    v
+}
+
+#[test]
+fn test_wvtt() {
+    init();
+
+    let mut h = gst_check::Harness::with_padnames("isofmp4mux", Some("sink_0"), Some("src"));
+    h.element()
+        .unwrap()
+        .set_property("fragment-duration", gst::ClockTime::from_seconds(10));
+    h.set_src_caps(gst::Caps::builder("application/x-subtitle-vtt-fragmented").build());
+    h.play();
+
+    let cue = |data: &[u8], start: u64| {
+        let mut buffer = gst::Buffer::from_slice(data.to_vec());
+        {
+            let buffer = buffer.get_mut().unwrap();
+            buffer.set_pts(gst::ClockTime::from_seconds(start));
+            buffer.set_duration(gst::ClockTime::SECOND);
+        }
+        buffer
+    };
+
+    // The WebVTT header is dropped, and the gap between 1s and 3s is filled with an empty cue
+    for buffer in [
+        cue(b"WEBVTT\n\n", 0),
+        cue(
+            b"intro\n00:00:00.000 --> 00:00:01.000 align:start\nHello\n",
+            0,
+        ),
+        cue(b"World\n", 3),
+    ] {
+        assert_eq!(h.push(buffer), Ok(gst::FlowSuccess::Ok));
+    }
+    h.push_event(gst::event::Eos::new());
+
+    let header = h.pull().unwrap();
+    assert_eq!(
+        header.flags(),
+        gst::BufferFlags::HEADER | gst::BufferFlags::DISCONT
+    );
+    {
+        let map = header.map_readable().unwrap();
+        let sample_entry = find_box(&map, b"wvtt");
+        assert_eq!(find_box(sample_entry, b"vttC"), b"WEBVTT");
+    }
+
+    let fragment_header = h.pull().unwrap();
+    assert_eq!(fragment_header.flags(), gst::BufferFlags::HEADER);
+
+    let mut vttc = mp4_box(b"iden", b"intro");
+    vttc.extend(mp4_box(b"sttg", b"align:start"));
+    vttc.extend(mp4_box(b"payl", b"Hello"));
+
+    for (pts, expected) in [
+        (0, mp4_box(b"vttc", &vttc)),
+        (1, mp4_box(b"vtte", &[])),
+        (3, mp4_box(b"vttc", &mp4_box(b"payl", b"World"))),
+    ] {
+        let buffer = h.pull().unwrap();
+        assert_eq!(buffer.pts(), Some(gst::ClockTime::from_seconds(pts)));
+        assert_eq!(&*buffer.map_readable().unwrap(), &expected[..]);
+    }
+
+    assert!(h.try_pull().is_none());
+}
+
+#[test]
+fn test_ttml() {
+    init();
+
+    const DOCUMENT: &[u8] = b"<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n<tt xmlns=\"http://www.w3.org/ns/ttml\"><body><div><p>Hello</p></div></body></tt>\n";
+
+    let mut h = gst_check::Harness::with_padnames("isofmp4mux", Some("sink_0"), Some("src"));
+    h.element()
+        .unwrap()
+        .set_property("fragment-duration", gst::ClockTime::from_seconds(10));
+    h.set_src_caps(gst::Caps::builder("application/ttml+xml").build());
+    h.play();
+
+    // A document, a gap and another document
+    for i in 0..3 {
+        let mut buffer = if i == 1 {
+            let mut buffer = gst::Buffer::new();
+            buffer
+                .get_mut()
+                .unwrap()
+                .set_flags(gst::BufferFlags::GAP | gst::BufferFlags::DROPPABLE);
+            buffer
+        } else {
+            gst::Buffer::from_slice(DOCUMENT)
+        };
+        {
+            let buffer = buffer.get_mut().unwrap();
+            buffer.set_pts(gst::ClockTime::from_seconds(i));
+            buffer.set_duration(gst::ClockTime::SECOND);
+        }
+        assert_eq!(h.push(buffer), Ok(gst::FlowSuccess::Ok));
+    }
+    h.push_event(gst::event::Eos::new());
+
+    let header = h.pull().unwrap();
+    {
+        let map = header.map_readable().unwrap();
+        let sample_entry = find_box(&map, b"stpp");
+
+        // Namespace, empty schema location and empty auxiliary MIME types
+        assert_eq!(&sample_entry[8..], b"http://www.w3.org/ns/ttml\0\0\0");
+    }
+
+    let fragment_header = h.pull().unwrap();
+    assert_eq!(fragment_header.flags(), gst::BufferFlags::HEADER);
+
+    // The gap is replaced by an empty document
+    for i in 0..3 {
+        let buffer = h.pull().unwrap();
+        assert_eq!(buffer.pts(), Some(gst::ClockTime::from_seconds(i)));
+
+        let map = buffer.map_readable().unwrap();
+        if i == 1 {
+            assert!(map.starts_with(b"<?xml"));
+            assert!(!map.windows(5).any(|w| w == b"Hello"));
+        } else {
+            assert_eq!(&*map, DOCUMENT);
+        }
+    }
+
+    assert!(h.try_pull().is_none());
+}
+
+#[test]
+fn test_demux_roundtrip() {
+    init();
+
+    // 5s fragment duration
+    let mut h = gst_check::Harness::with_padnames("isofmp4mux", Some("sink_0"), Some("src"));
+    h.element()
+        .unwrap()
+        .set_property("fragment-duration", gst::ClockTime::from_seconds(5));
+    h.set_src_caps(
+        gst::Caps::builder("video/x-h264")
+            .field("width", 1920i32)
+            .field("height", 1080i32)
+            .field("framerate", gst::Fraction::new(30, 1))
+            .field("stream-format", "avc")
+            .field("alignment", "au")
+            .field("codec_data", gst::Buffer::with_size(1).unwrap())
+            .build(),
+    );
+    h.play();
+
+    // Push 7 buffers of 1s each, 1st and 6th buffer without DELTA_UNIT flag
+    for i in 0..7 {
+        let mut buffer = gst::Buffer::from_mut_slice(vec![i as u8; 10 + i as usize]);
+        {
+            let buffer = buffer.get_mut().unwrap();
+            buffer.set_pts(gst::ClockTime::from_seconds(i));
+            buffer.set_dts(gst::ClockTime::from_seconds(i));
+            buffer.set_duration(gst::ClockTime::SECOND);
+            if i != 0 && i != 5 {
+                buffer.set_flags(gst::BufferFlags::DELTA_UNIT);
+            }
+        }
+        assert_eq!(h.push(buffer), Ok(gst::FlowSuccess::Ok));
+    }
+    h.push_event(gst::event::Eos::new());
+
+    let mut output = vec![];
+    while let Some(buffer) = h.try_pull() {
+        output.push(buffer);
+    }
+
+    let mut demux = gst_check::Harness::with_padnames("fmp4demux", Some("sink"), Some("video_0"));
+    demux.set_src_caps(
+        gst::Caps::builder("video/quicktime")
+            .field("variant", "iso-fragmented")
+            .build(),
+    );
+    demux.play();
+
+    for buffer in output {
+        assert_eq!(demux.push(buffer), Ok(gst::FlowSuccess::Ok));
+    }
+    demux.push_event(gst::event::Eos::new());
+
+    let caps = demux.sinkpad().unwrap().current_caps().unwrap();
+    let s = caps.structure(0).unwrap();
+    assert_eq!(s.name(), "video/x-h264");
+    assert_eq!(s.get::<&str>("stream-format"), Ok("avc"));
+    assert_eq!(s.get::<i32>("width"), Ok(1920));
+    assert_eq!(s.get::<i32>("height"), Ok(1080));
+
+    for i in 0..7 {
+        let buffer = demux.pull().unwrap();
+        assert_eq!(buffer.pts(), Some(gst::ClockTime::from_seconds(i)));
+        assert_eq!(buffer.dts(), Some(gst::ClockTime::from_seconds(i)));
+        assert_eq!(buffer.duration(), Some(gst::ClockTime::SECOND));
+        assert_eq!(
+            buffer.flags().contains(gst::BufferFlags::DELTA_UNIT),
+            i != 0 && i != 5
+        );
+
+        let map = buffer.map_readable().unwrap();
+        assert_eq!(&*map, &vec![i as u8; 10 + i as usize][..]);
+    }
+
+    assert!(demux.try_pull().is_none());
+}
+
+#[test]
+fn test_demux_seek() {
+    init();
+
+    // 5s fragment duration and an mfra at the end for seeking
+    let mut h = gst_check::Harness::with_padnames("isofmp4mux", Some("sink_0"), Some("src"));
+    {
+        let element = h.element().unwrap();
+        element.set_property("fragment-duration", gst::ClockTime::from_seconds(5));
+        element.set_property("write-mfra", true);
+    }
+    h.set_src_caps(
+        gst::Caps::builder("video/x-h264")
+            .field("width", 1920i32)
+            .field("height", 1080i32)
+            .field("framerate", gst::Fraction::new(30, 1))
+            .field("stream-format", "avc")
+            .field("alignment", "au")
+            .field("codec_data", gst::Buffer::with_size(1).unwrap())
+            .build(),
+    );
+    h.play();
+
+    // Push 10 buffers of 1s each, 1st and 6th buffer without DELTA_UNIT flag
+    for i in 0..10 {
+        let mut buffer = gst::Buffer::from_mut_slice(vec![i as u8; 10 + i as usize]);
+        {
+            let buffer = buffer.get_mut().unwrap();
+            buffer.set_pts(gst::ClockTime::from_seconds(i));
+            buffer.set_dts(gst::ClockTime::from_seconds(i));
+            buffer.set_duration(gst::ClockTime::SECOND);
+            if i != 0 && i != 5 {
+                buffer.set_flags(gst::BufferFlags::DELTA_UNIT);
+            }
+        }
+        assert_eq!(h.push(buffer), Ok(gst::FlowSuccess::Ok));
+    }
+    h.push_event(gst::event::Eos::new());
+
+    let mut data = vec![];
+    while let Some(buffer) = h.try_pull() {
+        data.extend_from_slice(&buffer.map_readable().unwrap());
+    }
+
+    let path = std::env::temp_dir().join(format!("fmp4demux-seek-{}.mp4", std::process::id()));
+    std::fs::write(&path, &data).unwrap();
+
+    let pipeline = gst::parse_launch(&format!(
+        "filesrc location={} ! fmp4demux ! appsink name=sink sync=false",
+        path.display()
+    ))
+    .unwrap()
+    .downcast::<gst::Pipeline>()
+    .unwrap();
+    let sink = pipeline
+        .by_name("sink")
+        .unwrap()
+        .downcast::<gst_app::AppSink>()
+        .unwrap();
+
+    pipeline.set_state(gst::State::Paused).unwrap();
+    assert_eq!(
+        pipeline.state(gst::ClockTime::NONE).0,
+        Ok(gst::StateChangeSuccess::Success)
+    );
+
+    // Seeking into the second fragment starts at its beginning
+    pipeline
+        .seek_simple(
+            gst::SeekFlags::FLUSH | gst::SeekFlags::KEY_UNIT,
+            gst::ClockTime::from_seconds(7),
+        )
+        .unwrap();
+    pipeline.set_state(gst::State::Playing).unwrap();
+
+    let sample = sink.pull_sample().unwrap();
+    let buffer = sample.buffer().unwrap();
+    assert_eq!(buffer.pts(), Some(gst::ClockTime::from_seconds(5)));
+    assert!(!buffer.flags().contains(gst::BufferFlags::DELTA_UNIT));
+    assert_eq!(&*buffer.map_readable().unwrap(), &vec![5u8; 15][..]);
+
+    pipeline.set_state(gst::State::Null).unwrap();
+    let _ = std::fs::remove_file(&path);
+}
+
+#[test]
+fn test_encryption() {
+    init();
+
+    let mut h = gst_check::Harness::with_padnames("cmafmux", Some("sink"), Some("src"));
+    {
+        let element = h.element().unwrap();
+        element.set_property("fragment-duration", gst::ClockTime::from_seconds(5));
+        element.set_property_from_str("encryption-scheme", "cenc");
+        element.set_property("key-id", gst::Buffer::from_slice([1u8; 16]));
+        element.set_property("key", gst::Buffer::from_slice([2u8; 16]));
+        element.set_property("iv", gst::Buffer::from_slice([0u8; 8]));
+    }
+    h.set_src_caps(
+        gst::Caps::builder("audio/mpeg")
+            .field("mpegversion", 4i32)
+            .field("channels", 1i32)
+            .field("rate", 44100i32)
+            .field("stream-format", "raw")
+            .field("base-profile", "lc")
+            .field("profile", "lc")
+            .field("level", "2")
+            .field(
+                "codec_data",
+                gst::Buffer::from_slice([0x12, 0x08, 0x56, 0xe5, 0x00]),
+            )
+            .build(),
+    );
+    h.play();
+
+    // Push 7 buffers of 1s each
+    for i in 0..7 {
+        let mut buffer = gst::Buffer::from_mut_slice(vec![0u8; 32]);
+        {
+            let buffer = buffer.get_mut().unwrap();
+            buffer.set_pts(gst::ClockTime::from_seconds(i));
+            buffer.set_dts(gst::ClockTime::from_seconds(i));
+            buffer.set_duration(gst::ClockTime::SECOND);
+        }
+        assert_eq!(h.push(buffer), Ok(gst::FlowSuccess::Ok));
+    }
+    h.push_event(gst::event::Eos::new());
+
+    let contains_box = |buffer: &gst::Buffer, fourcc: &[u8; 4]| {
+        let map = buffer.map_readable().unwrap();
+        map.windows(4).any(|w| w == fourcc)
+    };
+
+    let header = h.pull().unwrap();
+    for fourcc in [b"enca", b"sinf", b"frma", b"schm", b"tenc"] {
+        assert!(contains_box(&header, fourcc));
+    }
+
+    let fragment_header = h.pull().unwrap();
+    for fourcc in [b"senc", b"saiz", b"saio"] {
+        assert!(contains_box(&fragment_header, fourcc));
+    }
+
+    // Each sample is encrypted with a different IV
+    let mut samples = vec![];
+    for _ in 0..5 {
+        let buffer = h.pull().unwrap();
+        let map = buffer.map_readable().unwrap();
+        assert_eq!(map.len(), 32);
+        assert_ne!(&*map, &[0u8; 32][..]);
+        samples.push(map.to_vec());
+    }
+    samples.dedup();
+    assert_eq!(samples.len(), 5);
+}
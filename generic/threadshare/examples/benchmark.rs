@@ -144,6 +144,31 @@ fn main() {
 
                 source
             }
+            "ts-audiotestsrc" => {
+                let source = gst::ElementFactory::make(
+                    "ts-audiotestsrc",
+                    Some(format!("source-{}", i).as_str()),
+                )
+                .unwrap();
+                source.set_property("rate", 8000u32);
+                source.set_property("samples-per-buffer", (wait as u32) * 8000 / 1000);
+                source.set_property("context", format!("context-{}", (i as u32) % n_groups));
+                source.set_property("context-wait", wait);
+
+                source
+            }
+            "ts-videotestsrc" => {
+                let source = gst::ElementFactory::make(
+                    "ts-videotestsrc",
+                    Some(format!("source-{}", i).as_str()),
+                )
+                .unwrap();
+                source.set_property("framerate", gst::Fraction::new(1000, wait as i32));
+                source.set_property("context", format!("context-{}", (i as u32) % n_groups));
+                source.set_property("context-wait", wait);
+
+                source
+            }
             _ => unimplemented!(),
         };
 
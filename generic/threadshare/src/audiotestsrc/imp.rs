@@ -0,0 +1,725 @@
+// Copyright (C) 2026 agent <agent@local>
+//
+// This library is free software; you can redistribute it and/or
+// modify it under the terms of the GNU Library General Public
+// License as published by the Free Software Foundation; either
+// version 2 of the License, or (at your option) any later version.
+//
+// This library is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the GNU
+// Library General Public License for more details.
+//
+// You should have received a copy of the GNU Library General Public
+// License along with this library; if not, write to the
+// Free Software Foundation, Inc., 51 Franklin Street, Suite 500,
+// Boston, MA 02110-1335, USA.
+
+use futures::future::BoxFuture;
+use futures::prelude::*;
+
+use gst::glib;
+use gst::prelude::*;
+use gst::subclass::prelude::*;
+use gst::{gst_debug, gst_error, gst_log, gst_trace};
+
+use once_cell::sync::Lazy;
+
+use std::f64::consts::PI;
+use std::sync::Arc;
+use std::sync::Mutex as StdMutex;
+use std::time::Duration;
+use std::u32;
+
+use crate::runtime::prelude::*;
+use crate::runtime::{time, Context, PadSrc, PadSrcRef, PadSrcWeak, Task, Timer};
+
+const DEFAULT_CONTEXT: &str = "";
+const DEFAULT_CONTEXT_WAIT: Duration = Duration::ZERO;
+const DEFAULT_SAMPLES_PER_BUFFER: u32 = 1024;
+const DEFAULT_FREQ: f64 = 440.0;
+const DEFAULT_VOLUME: f64 = 0.8;
+const DEFAULT_RATE: u32 = 48_000;
+const DEFAULT_CHANNELS: u32 = 1;
+const DEFAULT_NUM_BUFFERS: i32 = -1;
+
+#[derive(Debug, Clone)]
+struct Settings {
+    context: String,
+    context_wait: Duration,
+    samples_per_buffer: u32,
+    freq: f64,
+    volume: f64,
+    rate: u32,
+    channels: u32,
+    num_buffers: i32,
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Settings {
+            context: DEFAULT_CONTEXT.into(),
+            context_wait: DEFAULT_CONTEXT_WAIT,
+            samples_per_buffer: DEFAULT_SAMPLES_PER_BUFFER,
+            freq: DEFAULT_FREQ,
+            volume: DEFAULT_VOLUME,
+            rate: DEFAULT_RATE,
+            channels: DEFAULT_CHANNELS,
+            num_buffers: DEFAULT_NUM_BUFFERS,
+        }
+    }
+}
+
+static CAT: Lazy<gst::DebugCategory> = Lazy::new(|| {
+    gst::DebugCategory::new(
+        "ts-audiotestsrc",
+        gst::DebugColorFlags::empty(),
+        Some("Thread-sharing audio test source"),
+    )
+});
+
+/// Format of the generated audio, fixed when the element is prepared.
+#[derive(Debug, Clone)]
+struct AudioFormat {
+    rate: u32,
+    channels: u32,
+    samples_per_buffer: u32,
+}
+
+impl AudioFormat {
+    fn caps(&self) -> gst::Caps {
+        gst::Caps::builder("audio/x-raw")
+            .field("format", "S16LE")
+            .field("layout", "interleaved")
+            .field("rate", self.rate as i32)
+            .field("channels", self.channels as i32)
+            .build()
+    }
+
+    fn bytes_per_frame(&self) -> usize {
+        2 * self.channels as usize
+    }
+
+    fn samples_to_time(&self, samples: u64) -> gst::ClockTime {
+        samples
+            .mul_div_floor(*gst::ClockTime::SECOND, self.rate as u64)
+            .map(gst::ClockTime::from_nseconds)
+            .unwrap()
+    }
+
+    fn buffer_duration(&self) -> gst::ClockTime {
+        self.samples_to_time(self.samples_per_buffer as u64)
+    }
+}
+
+#[derive(Clone, Debug, Default)]
+struct AudioTestSrcPadHandler(Arc<StdMutex<Option<AudioFormat>>>);
+
+impl AudioTestSrcPadHandler {
+    fn prepare(&self, format: AudioFormat) {
+        *self.0.lock().unwrap() = Some(format);
+    }
+
+    fn unprepare(&self) {
+        *self.0.lock().unwrap() = None;
+    }
+}
+
+impl PadSrcHandler for AudioTestSrcPadHandler {
+    type ElementImpl = AudioTestSrc;
+
+    fn src_event(
+        &self,
+        pad: &PadSrcRef,
+        audiotestsrc: &AudioTestSrc,
+        _element: &gst::Element,
+        event: gst::Event,
+    ) -> bool {
+        use gst::EventView;
+
+        gst_log!(CAT, obj: pad.gst_pad(), "Handling {:?}", event);
+
+        let ret = match event.view() {
+            EventView::FlushStart(..) => audiotestsrc.task.flush_start().is_ok(),
+            EventView::FlushStop(..) => audiotestsrc.task.flush_stop().is_ok(),
+            EventView::Reconfigure(..) => true,
+            EventView::Latency(..) => true,
+            _ => false,
+        };
+
+        if ret {
+            gst_log!(CAT, obj: pad.gst_pad(), "Handled {:?}", event);
+        } else {
+            gst_log!(CAT, obj: pad.gst_pad(), "Didn't handle {:?}", event);
+        }
+
+        ret
+    }
+
+    fn src_query(
+        &self,
+        pad: &PadSrcRef,
+        _audiotestsrc: &AudioTestSrc,
+        _element: &gst::Element,
+        query: &mut gst::QueryRef,
+    ) -> bool {
+        use gst::QueryView;
+
+        gst_log!(CAT, obj: pad.gst_pad(), "Handling {:?}", query);
+        let ret = match query.view_mut() {
+            QueryView::Latency(ref mut q) => match *self.0.lock().unwrap() {
+                Some(ref format) => {
+                    // A buffer is only pushed once all of its samples are due
+                    q.set(true, format.buffer_duration(), gst::ClockTime::NONE);
+                    true
+                }
+                None => false,
+            },
+            QueryView::Scheduling(ref mut q) => {
+                q.set(gst::SchedulingFlags::SEQUENTIAL, 1, -1, 0);
+                q.add_scheduling_modes(&[gst::PadMode::Push]);
+                true
+            }
+            QueryView::Caps(ref mut q) => {
+                let caps = match *self.0.lock().unwrap() {
+                    Some(ref format) => format.caps(),
+                    None => pad.gst_pad().pad_template_caps(),
+                };
+
+                let caps = q
+                    .filter()
+                    .map(|f| f.intersect_with_mode(&caps, gst::CapsIntersectMode::First))
+                    .unwrap_or(caps);
+
+                q.set_result(&caps);
+
+                true
+            }
+            _ => false,
+        };
+
+        if ret {
+            gst_log!(CAT, obj: pad.gst_pad(), "Handled {:?}", query);
+        } else {
+            gst_log!(CAT, obj: pad.gst_pad(), "Didn't handle {:?}", query);
+        }
+        ret
+    }
+}
+
+#[derive(Debug)]
+struct AudioTestSrcTask {
+    element: super::AudioTestSrc,
+    src_pad: PadSrcWeak,
+    format: AudioFormat,
+    freq: f64,
+    volume: f64,
+    num_buffers: Option<u64>,
+    timer: Option<Timer>,
+    need_initial_events: bool,
+    need_segment: bool,
+    n_buffers: u64,
+    n_samples: u64,
+    phase: f64,
+}
+
+impl AudioTestSrcTask {
+    fn new(element: &super::AudioTestSrc, src_pad: &PadSrc, settings: &Settings) -> Self {
+        AudioTestSrcTask {
+            element: element.clone(),
+            src_pad: src_pad.downgrade(),
+            format: AudioFormat {
+                rate: settings.rate,
+                channels: settings.channels,
+                samples_per_buffer: settings.samples_per_buffer,
+            },
+            freq: settings.freq,
+            volume: settings.volume,
+            num_buffers: u64::try_from(settings.num_buffers).ok(),
+            timer: None,
+            need_initial_events: true,
+            need_segment: true,
+            n_buffers: 0,
+            n_samples: 0,
+            phase: 0.0,
+        }
+    }
+
+    async fn push_prelude(&mut self, pad: &PadSrcRef<'_>) {
+        if self.need_initial_events {
+            gst_debug!(CAT, obj: pad.gst_pad(), "Pushing initial events");
+
+            let stream_id = format!("{:08x}{:08x}", rand::random::<u32>(), rand::random::<u32>());
+            let stream_start_evt = gst::event::StreamStart::builder(&stream_id)
+                .group_id(gst::GroupId::next())
+                .build();
+            pad.push_event(stream_start_evt).await;
+
+            pad.push_event(gst::event::Caps::new(&self.format.caps()))
+                .await;
+
+            self.need_initial_events = false;
+        }
+
+        if self.need_segment {
+            let segment_evt =
+                gst::event::Segment::new(&gst::FormattedSegment::<gst::format::Time>::new());
+            pad.push_event(segment_evt).await;
+
+            self.need_segment = false;
+        }
+    }
+
+    /// Generates the next buffer of the sine wave.
+    ///
+    /// Buffers are timestamped from the number of samples produced so far,
+    /// so that the output only depends on the settings.
+    fn generate(&mut self) -> gst::Buffer {
+        let n_samples = self.format.samples_per_buffer as u64;
+        let bytes_per_frame = self.format.bytes_per_frame();
+        let step = 2.0 * PI * self.freq / self.format.rate as f64;
+        let amplitude = self.volume * i16::MAX as f64;
+
+        let mut buffer = gst::Buffer::with_size(n_samples as usize * bytes_per_frame).unwrap();
+        {
+            let buffer = buffer.get_mut().unwrap();
+
+            let pts = self.format.samples_to_time(self.n_samples);
+            let end = self.format.samples_to_time(self.n_samples + n_samples);
+            buffer.set_pts(pts);
+            buffer.set_duration(end - pts);
+            buffer.set_offset(self.n_samples);
+            buffer.set_offset_end(self.n_samples + n_samples);
+            if self.n_buffers == 0 {
+                buffer.set_flags(gst::BufferFlags::DISCONT);
+            }
+
+            let mut map = buffer.map_writable().unwrap();
+            for frame in map.chunks_exact_mut(bytes_per_frame) {
+                let value = ((self.phase.sin() * amplitude) as i16).to_le_bytes();
+                for sample in frame.chunks_exact_mut(2) {
+                    sample.copy_from_slice(&value);
+                }
+
+                self.phase += step;
+                if self.phase >= 2.0 * PI {
+                    self.phase -= 2.0 * PI;
+                }
+            }
+        }
+
+        self.n_samples += n_samples;
+        self.n_buffers += 1;
+
+        buffer
+    }
+}
+
+impl TaskImpl for AudioTestSrcTask {
+    fn start(&mut self) -> BoxFuture<'_, Result<(), gst::ErrorMessage>> {
+        async move {
+            gst_log!(CAT, obj: &self.element, "Starting task");
+
+            // Restart the timer so that buffers missed while paused are not pushed in a burst
+            self.timer = Some(time::interval(Duration::from_nanos(
+                self.format.buffer_duration().nseconds(),
+            )));
+
+            gst_log!(CAT, obj: &self.element, "Task started");
+            Ok(())
+        }
+        .boxed()
+    }
+
+    fn iterate(&mut self) -> BoxFuture<'_, Result<(), gst::FlowError>> {
+        async move {
+            self.timer.as_mut().unwrap().next().await;
+
+            let pad = self.src_pad.upgrade().expect("PadSrc no longer exists");
+
+            if Some(self.n_buffers) == self.num_buffers {
+                gst_debug!(CAT, obj: &self.element, "Reached num-buffers, pushing EOS");
+                self.push_prelude(&pad).await;
+                pad.push_event(gst::event::Eos::new()).await;
+                return Err(gst::FlowError::Eos);
+            }
+
+            self.push_prelude(&pad).await;
+
+            let buffer = self.generate();
+            gst_log!(CAT, obj: &self.element, "Forwarding {:?}", buffer);
+
+            let res = pad.push(buffer).await;
+            match res {
+                Ok(_) => {
+                    gst_log!(CAT, obj: &self.element, "Successfully pushed buffer");
+                }
+                Err(gst::FlowError::Flushing) => {
+                    gst_debug!(CAT, obj: &self.element, "Flushing");
+                }
+                Err(gst::FlowError::Eos) => {
+                    gst_debug!(CAT, obj: &self.element, "EOS");
+                    pad.push_event(gst::event::Eos::new()).await;
+                }
+                Err(err) => {
+                    gst_error!(CAT, obj: &self.element, "Got error {}", err);
+                    gst::element_error!(
+                        &self.element,
+                        gst::StreamError::Failed,
+                        ("Internal data stream error"),
+                        ["streaming stopped, reason {}", err]
+                    );
+                }
+            }
+
+            res.map(drop)
+        }
+        .boxed()
+    }
+
+    fn stop(&mut self) -> BoxFuture<'_, Result<(), gst::ErrorMessage>> {
+        async move {
+            gst_log!(CAT, obj: &self.element, "Stopping task");
+
+            self.timer = None;
+            self.need_initial_events = true;
+            self.need_segment = true;
+            self.n_buffers = 0;
+            self.n_samples = 0;
+            self.phase = 0.0;
+
+            gst_log!(CAT, obj: &self.element, "Task stopped");
+            Ok(())
+        }
+        .boxed()
+    }
+
+    fn flush_start(&mut self) -> BoxFuture<'_, Result<(), gst::ErrorMessage>> {
+        async move {
+            gst_log!(CAT, obj: &self.element, "Starting task flush");
+
+            self.need_segment = true;
+
+            gst_log!(CAT, obj: &self.element, "Task flush started");
+            Ok(())
+        }
+        .boxed()
+    }
+}
+
+#[derive(Debug)]
+pub struct AudioTestSrc {
+    src_pad: PadSrc,
+    src_pad_handler: AudioTestSrcPadHandler,
+    task: Task,
+    settings: StdMutex<Settings>,
+}
+
+impl AudioTestSrc {
+    fn prepare(&self, element: &super::AudioTestSrc) -> Result<(), gst::ErrorMessage> {
+        let settings = self.settings.lock().unwrap().clone();
+        gst_debug!(CAT, obj: element, "Preparing");
+
+        let context =
+            Context::acquire(&settings.context, settings.context_wait).map_err(|err| {
+                gst::error_msg!(
+                    gst::ResourceError::OpenRead,
+                    ["Failed to acquire Context: {}", err]
+                )
+            })?;
+
+        let task_impl = AudioTestSrcTask::new(element, &self.src_pad, &settings);
+        self.src_pad_handler.prepare(task_impl.format.clone());
+
+        self.task.prepare(task_impl, context).map_err(|err| {
+            gst::error_msg!(
+                gst::ResourceError::OpenRead,
+                ["Error preparing Task: {:?}", err]
+            )
+        })?;
+
+        gst_debug!(CAT, obj: element, "Prepared");
+
+        Ok(())
+    }
+
+    fn unprepare(&self, element: &super::AudioTestSrc) {
+        gst_debug!(CAT, obj: element, "Unpreparing");
+
+        self.task.unprepare().unwrap();
+        self.src_pad_handler.unprepare();
+
+        gst_debug!(CAT, obj: element, "Unprepared");
+    }
+
+    fn stop(&self, element: &super::AudioTestSrc) -> Result<(), gst::ErrorMessage> {
+        gst_debug!(CAT, obj: element, "Stopping");
+        self.task.stop()?;
+        gst_debug!(CAT, obj: element, "Stopped");
+        Ok(())
+    }
+
+    fn start(&self, element: &super::AudioTestSrc) -> Result<(), gst::ErrorMessage> {
+        gst_debug!(CAT, obj: element, "Starting");
+        self.task.start()?;
+        gst_debug!(CAT, obj: element, "Started");
+        Ok(())
+    }
+
+    fn pause(&self, element: &super::AudioTestSrc) -> Result<(), gst::ErrorMessage> {
+        gst_debug!(CAT, obj: element, "Pausing");
+        self.task.pause()?;
+        gst_debug!(CAT, obj: element, "Paused");
+        Ok(())
+    }
+}
+
+#[glib::object_subclass]
+impl ObjectSubclass for AudioTestSrc {
+    const NAME: &'static str = "RsTsAudioTestSrc";
+    type Type = super::AudioTestSrc;
+    type ParentType = gst::Element;
+
+    fn with_class(klass: &Self::Class) -> Self {
+        let src_pad_handler = AudioTestSrcPadHandler::default();
+
+        Self {
+            src_pad: PadSrc::new(
+                gst::Pad::from_template(&klass.pad_template("src").unwrap(), Some("src")),
+                src_pad_handler.clone(),
+            ),
+            src_pad_handler,
+            task: Task::default(),
+            settings: StdMutex::new(Settings::default()),
+        }
+    }
+}
+
+impl ObjectImpl for AudioTestSrc {
+    fn properties() -> &'static [glib::ParamSpec] {
+        static PROPERTIES: Lazy<Vec<glib::ParamSpec>> = Lazy::new(|| {
+            vec![
+                glib::ParamSpecString::new(
+                    "context",
+                    "Context",
+                    "Context name to share threads with",
+                    Some(DEFAULT_CONTEXT),
+                    glib::ParamFlags::READWRITE,
+                ),
+                glib::ParamSpecUInt::new(
+                    "context-wait",
+                    "Context Wait",
+                    "Throttle poll loop to run at most once every this many ms",
+                    0,
+                    1000,
+                    DEFAULT_CONTEXT_WAIT.as_millis() as u32,
+                    glib::ParamFlags::READWRITE,
+                ),
+                glib::ParamSpecUInt::new(
+                    "samples-per-buffer",
+                    "Samples Per Buffer",
+                    "Number of samples per channel in each outgoing buffer",
+                    1,
+                    u32::MAX,
+                    DEFAULT_SAMPLES_PER_BUFFER,
+                    glib::ParamFlags::READWRITE,
+                ),
+                glib::ParamSpecDouble::new(
+                    "freq",
+                    "Frequency",
+                    "Frequency of the sine wave in Hz",
+                    0.0,
+                    20_000.0,
+                    DEFAULT_FREQ,
+                    glib::ParamFlags::READWRITE,
+                ),
+                glib::ParamSpecDouble::new(
+                    "volume",
+                    "Volume",
+                    "Volume of the sine wave",
+                    0.0,
+                    1.0,
+                    DEFAULT_VOLUME,
+                    glib::ParamFlags::READWRITE,
+                ),
+                glib::ParamSpecUInt::new(
+                    "rate",
+                    "Rate",
+                    "Sample rate in Hz",
+                    1,
+                    i32::MAX as u32,
+                    DEFAULT_RATE,
+                    glib::ParamFlags::READWRITE,
+                ),
+                glib::ParamSpecUInt::new(
+                    "channels",
+                    "Channels",
+                    "Number of channels",
+                    1,
+                    64,
+                    DEFAULT_CHANNELS,
+                    glib::ParamFlags::READWRITE,
+                ),
+                glib::ParamSpecInt::new(
+                    "num-buffers",
+                    "Num Buffers",
+                    "Number of buffers to output before sending EOS (-1 = unlimited)",
+                    -1,
+                    i32::MAX,
+                    DEFAULT_NUM_BUFFERS,
+                    glib::ParamFlags::READWRITE,
+                ),
+            ]
+        });
+
+        PROPERTIES.as_ref()
+    }
+
+    fn set_property(
+        &self,
+        _obj: &Self::Type,
+        _id: usize,
+        value: &glib::Value,
+        pspec: &glib::ParamSpec,
+    ) {
+        let mut settings = self.settings.lock().unwrap();
+        match pspec.name() {
+            "context" => {
+                settings.context = value
+                    .get::<Option<String>>()
+                    .expect("type checked upstream")
+                    .unwrap_or_else(|| DEFAULT_CONTEXT.into());
+            }
+            "context-wait" => {
+                settings.context_wait = Duration::from_millis(
+                    value.get::<u32>().expect("type checked upstream").into(),
+                );
+            }
+            "samples-per-buffer" => {
+                settings.samples_per_buffer = value.get().expect("type checked upstream");
+            }
+            "freq" => {
+                settings.freq = value.get().expect("type checked upstream");
+            }
+            "volume" => {
+                settings.volume = value.get().expect("type checked upstream");
+            }
+            "rate" => {
+                settings.rate = value.get().expect("type checked upstream");
+            }
+            "channels" => {
+                settings.channels = value.get().expect("type checked upstream");
+            }
+            "num-buffers" => {
+                settings.num_buffers = value.get().expect("type checked upstream");
+            }
+            _ => unimplemented!(),
+        }
+    }
+
+    fn property(&self, _obj: &Self::Type, _id: usize, pspec: &glib::ParamSpec) -> glib::Value {
+        let settings = self.settings.lock().unwrap();
+        match pspec.name() {
+            "context" => settings.context.to_value(),
+            "context-wait" => (settings.context_wait.as_millis() as u32).to_value(),
+            "samples-per-buffer" => settings.samples_per_buffer.to_value(),
+            "freq" => settings.freq.to_value(),
+            "volume" => settings.volume.to_value(),
+            "rate" => settings.rate.to_value(),
+            "channels" => settings.channels.to_value(),
+            "num-buffers" => settings.num_buffers.to_value(),
+            _ => unimplemented!(),
+        }
+    }
+
+    fn constructed(&self, obj: &Self::Type) {
+        self.parent_constructed(obj);
+
+        obj.add_pad(self.src_pad.gst_pad()).unwrap();
+
+        crate::set_element_flags(obj, gst::ElementFlags::SOURCE);
+    }
+}
+
+impl GstObjectImpl for AudioTestSrc {}
+
+impl ElementImpl for AudioTestSrc {
+    fn metadata() -> Option<&'static gst::subclass::ElementMetadata> {
+        static ELEMENT_METADATA: Lazy<gst::subclass::ElementMetadata> = Lazy::new(|| {
+            gst::subclass::ElementMetadata::new(
+                "Thread-sharing audio test source",
+                "Source/Audio",
+                "Generates a live sine wave on Context timers",
+                "Sebastian Dröge <sebastian@centricular.com>",
+            )
+        });
+
+        Some(&*ELEMENT_METADATA)
+    }
+
+    fn pad_templates() -> &'static [gst::PadTemplate] {
+        static PAD_TEMPLATES: Lazy<Vec<gst::PadTemplate>> = Lazy::new(|| {
+            let caps = gst::Caps::builder("audio/x-raw")
+                .field("format", "S16LE")
+                .field("layout", "interleaved")
+                .field("rate", gst::IntRange::new(1, i32::MAX))
+                .field("channels", gst::IntRange::new(1, 64))
+                .build();
+            let src_pad_template = gst::PadTemplate::new(
+                "src",
+                gst::PadDirection::Src,
+                gst::PadPresence::Always,
+                &caps,
+            )
+            .unwrap();
+
+            vec![src_pad_template]
+        });
+
+        PAD_TEMPLATES.as_ref()
+    }
+
+    fn change_state(
+        &self,
+        element: &Self::Type,
+        transition: gst::StateChange,
+    ) -> Result<gst::StateChangeSuccess, gst::StateChangeError> {
+        gst_trace!(CAT, obj: element, "Changing state {:?}", transition);
+
+        match transition {
+            gst::StateChange::NullToReady => {
+                self.prepare(element).map_err(|err| {
+                    element.post_error_message(err);
+                    gst::StateChangeError
+                })?;
+            }
+            gst::StateChange::PlayingToPaused => {
+                self.pause(element).map_err(|_| gst::StateChangeError)?;
+            }
+            gst::StateChange::ReadyToNull => {
+                self.unprepare(element);
+            }
+            _ => (),
+        }
+
+        let mut success = self.parent_change_state(element, transition)?;
+
+        match transition {
+            gst::StateChange::ReadyToPaused => {
+                success = gst::StateChangeSuccess::NoPreroll;
+            }
+            gst::StateChange::PausedToPlaying => {
+                self.start(element).map_err(|_| gst::StateChangeError)?;
+            }
+            gst::StateChange::PlayingToPaused => {
+                success = gst::StateChangeSuccess::NoPreroll;
+            }
+            gst::StateChange::PausedToReady => {
+                self.stop(element).map_err(|_| gst::StateChangeError)?;
+            }
+            _ => (),
+        }
+
+        Ok(success)
+    }
+}
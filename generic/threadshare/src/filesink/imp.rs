@@ -0,0 +1,617 @@
+// Copyright (C) 2026 agent <agent@local>
+//
+// This library is free software; you can redistribute it and/or
+// modify it under the terms of the GNU Library General Public
+// License as published by the Free Software Foundation; either
+// version 2 of the License, or (at your option) any later version.
+//
+// This library is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the GNU
+// Library General Public License for more details.
+//
+// You should have received a copy of the GNU Library General Public
+// License along with this library; if not, write to the
+// Free Software Foundation, Inc., 51 Franklin Street, Suite 500,
+// Boston, MA 02110-1335, USA.
+
+use futures::channel::mpsc;
+use futures::future::BoxFuture;
+use futures::lock::Mutex;
+use futures::prelude::*;
+
+use gst::glib;
+use gst::prelude::*;
+use gst::subclass::prelude::*;
+use gst::EventView;
+use gst::{element_error, error_msg, gst_debug, gst_error, gst_info, gst_log, gst_trace};
+
+use once_cell::sync::Lazy;
+
+use std::fs::File;
+use std::io::{self, Write};
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::sync::Mutex as StdMutex;
+use std::time::Duration;
+
+use crate::runtime::prelude::*;
+use crate::runtime::task;
+use crate::runtime::{unblock, Context, PadSink, PadSinkRef, Task, TaskState};
+
+const DEFAULT_CONTEXT: &str = "";
+const DEFAULT_CONTEXT_WAIT: Duration = Duration::ZERO;
+
+#[derive(Debug, Clone)]
+struct Settings {
+    context: String,
+    context_wait: Duration,
+    location: Option<PathBuf>,
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Settings {
+            context: DEFAULT_CONTEXT.into(),
+            context_wait: DEFAULT_CONTEXT_WAIT,
+            location: None,
+        }
+    }
+}
+
+static CAT: Lazy<gst::DebugCategory> = Lazy::new(|| {
+    gst::DebugCategory::new(
+        "ts-filesink",
+        gst::DebugColorFlags::empty(),
+        Some("Thread-sharing file sink"),
+    )
+});
+
+#[derive(Debug)]
+enum TaskItem {
+    Buffer(gst::Buffer),
+    Event(gst::Event),
+}
+
+/// Writes the whole content of `buffer` to `file`.
+///
+/// This is blocking and must be executed with [`unblock`].
+fn write_buffer(file: &StdMutex<File>, buffer: &gst::Buffer) -> io::Result<()> {
+    let map = buffer
+        .map_readable()
+        .map_err(|_| io::Error::new(io::ErrorKind::Other, "Failed to map buffer"))?;
+
+    file.lock().unwrap().write_all(&map)
+}
+
+#[derive(Clone, Debug, Default)]
+struct FileSinkPadHandler(Arc<Mutex<Option<mpsc::Sender<TaskItem>>>>);
+
+impl PadSinkHandler for FileSinkPadHandler {
+    type ElementImpl = FileSink;
+
+    fn sink_chain(
+        &self,
+        _pad: &PadSinkRef,
+        _filesink: &FileSink,
+        element: &gst::Element,
+        buffer: gst::Buffer,
+    ) -> BoxFuture<'static, Result<gst::FlowSuccess, gst::FlowError>> {
+        let sender = Arc::clone(&self.0);
+        let element = element.clone().downcast::<super::FileSink>().unwrap();
+
+        async move {
+            if let Some(sender) = sender.lock().await.as_mut() {
+                if sender.send(TaskItem::Buffer(buffer)).await.is_err() {
+                    gst_debug!(CAT, obj: &element, "Flushing");
+                    return Err(gst::FlowError::Flushing);
+                }
+            }
+            Ok(gst::FlowSuccess::Ok)
+        }
+        .boxed()
+    }
+
+    fn sink_chain_list(
+        &self,
+        _pad: &PadSinkRef,
+        _filesink: &FileSink,
+        element: &gst::Element,
+        list: gst::BufferList,
+    ) -> BoxFuture<'static, Result<gst::FlowSuccess, gst::FlowError>> {
+        let sender = Arc::clone(&self.0);
+        let element = element.clone().downcast::<super::FileSink>().unwrap();
+
+        async move {
+            if let Some(sender) = sender.lock().await.as_mut() {
+                for buffer in list.iter_owned() {
+                    if sender.send(TaskItem::Buffer(buffer)).await.is_err() {
+                        gst_debug!(CAT, obj: &element, "Flushing");
+                        return Err(gst::FlowError::Flushing);
+                    }
+                }
+            }
+
+            Ok(gst::FlowSuccess::Ok)
+        }
+        .boxed()
+    }
+
+    fn sink_event_serialized(
+        &self,
+        _pad: &PadSinkRef,
+        _filesink: &FileSink,
+        element: &gst::Element,
+        event: gst::Event,
+    ) -> BoxFuture<'static, bool> {
+        let sender = Arc::clone(&self.0);
+        let element = element.clone().downcast::<super::FileSink>().unwrap();
+
+        async move {
+            if let EventView::FlushStop(_) = event.view() {
+                let filesink = FileSink::from_instance(&element);
+                return filesink.task.flush_stop().is_ok();
+            } else if let Some(sender) = sender.lock().await.as_mut() {
+                if sender.send(TaskItem::Event(event)).await.is_err() {
+                    gst_debug!(CAT, obj: &element, "Flushing");
+                }
+            }
+
+            true
+        }
+        .boxed()
+    }
+
+    fn sink_event(
+        &self,
+        _pad: &PadSinkRef,
+        filesink: &FileSink,
+        _element: &gst::Element,
+        event: gst::Event,
+    ) -> bool {
+        if let EventView::FlushStart(..) = event.view() {
+            return filesink.task.flush_start().is_ok();
+        }
+
+        true
+    }
+}
+
+#[derive(Debug)]
+struct FileSinkTask {
+    element: super::FileSink,
+    sink_pad_handler: FileSinkPadHandler,
+    location: PathBuf,
+    file: Option<Arc<StdMutex<File>>>,
+    receiver: Option<mpsc::Receiver<TaskItem>>,
+}
+
+impl FileSinkTask {
+    fn new(
+        element: &super::FileSink,
+        sink_pad_handler: &FileSinkPadHandler,
+        location: PathBuf,
+    ) -> Self {
+        FileSinkTask {
+            element: element.clone(),
+            sink_pad_handler: sink_pad_handler.clone(),
+            location,
+            file: None,
+            receiver: None,
+        }
+    }
+
+    async fn render(&self, buffer: gst::Buffer) -> Result<(), gst::FlowError> {
+        gst_trace!(CAT, obj: &self.element, "Rendering {:?}", buffer);
+
+        let file = Arc::clone(self.file.as_ref().unwrap());
+        unblock(move || write_buffer(&file, &buffer))
+            .await
+            .map_err(|err| {
+                element_error!(
+                    self.element,
+                    gst::ResourceError::Write,
+                    ["Failed to write buffer: {}", err]
+                );
+                gst::FlowError::Error
+            })
+    }
+
+    fn handle_event(&self, event: gst::Event) {
+        match event.view() {
+            EventView::Eos(_) => {
+                let _ = self
+                    .element
+                    .post_message(gst::message::Eos::builder().src(&self.element).build());
+            }
+            EventView::SinkMessage(e) => {
+                let _ = self.element.post_message(e.message());
+            }
+            _ => (),
+        }
+    }
+}
+
+impl TaskImpl for FileSinkTask {
+    fn prepare(&mut self) -> BoxFuture<'_, Result<(), gst::ErrorMessage>> {
+        async move {
+            gst_log!(CAT, obj: &self.element, "Preparing task writing {:?}", self.location);
+
+            let location = self.location.clone();
+            let file = unblock(move || File::create(location))
+                .await
+                .map_err(|err| {
+                    error_msg!(
+                        gst::ResourceError::OpenWrite,
+                        [
+                            "Could not open file {} for writing: {}",
+                            self.location.display(),
+                            err
+                        ]
+                    )
+                })?;
+            gst_debug!(CAT, obj: &self.element, "Opened file {:?}", file);
+
+            self.file = Some(Arc::new(StdMutex::new(file)));
+
+            gst_log!(CAT, obj: &self.element, "Task prepared");
+            Ok(())
+        }
+        .boxed()
+    }
+
+    fn unprepare(&mut self) -> BoxFuture<'_, ()> {
+        async move {
+            gst_log!(CAT, obj: &self.element, "Unpreparing task");
+            self.file = None;
+            gst_log!(CAT, obj: &self.element, "Task unprepared");
+        }
+        .boxed()
+    }
+
+    fn handle_action_error(
+        &mut self,
+        trigger: task::Trigger,
+        state: TaskState,
+        err: gst::ErrorMessage,
+    ) -> BoxFuture<'_, task::Trigger> {
+        async move {
+            match trigger {
+                task::Trigger::Prepare => {
+                    gst_error!(CAT, "Task preparation failed: {:?}", err);
+                    self.element.post_error_message(err);
+
+                    task::Trigger::Error
+                }
+                other => unreachable!("Action error for {:?} in state {:?}", other, state),
+            }
+        }
+        .boxed()
+    }
+
+    fn start(&mut self) -> BoxFuture<'_, Result<(), gst::ErrorMessage>> {
+        async move {
+            gst_log!(CAT, obj: &self.element, "Starting task");
+
+            let (sender, receiver) = mpsc::channel(0);
+            *self.sink_pad_handler.0.lock().await = Some(sender);
+            self.receiver = Some(receiver);
+
+            gst_log!(CAT, obj: &self.element, "Task started");
+            Ok(())
+        }
+        .boxed()
+    }
+
+    fn iterate(&mut self) -> BoxFuture<'_, Result<(), gst::FlowError>> {
+        async move {
+            match self.receiver.as_mut().unwrap().next().await {
+                Some(TaskItem::Buffer(buffer)) => self.render(buffer).await,
+                Some(TaskItem::Event(event)) => {
+                    self.handle_event(event);
+                    Ok(())
+                }
+                None => Err(gst::FlowError::Flushing),
+            }
+        }
+        .boxed()
+    }
+}
+
+#[derive(Debug)]
+pub struct FileSink {
+    sink_pad: PadSink,
+    sink_pad_handler: FileSinkPadHandler,
+    task: Task,
+    settings: StdMutex<Settings>,
+}
+
+impl FileSink {
+    fn set_location(
+        &self,
+        element: &super::FileSink,
+        location: Option<PathBuf>,
+    ) -> Result<(), glib::Error> {
+        if self.task.state() != TaskState::Unprepared {
+            return Err(glib::Error::new(
+                gst::URIError::BadState,
+                "Changing the `location` property on a prepared `ts-filesink` is not supported",
+            ));
+        }
+
+        gst_info!(CAT, obj: element, "Setting `location` to {:?}", location);
+        self.settings.lock().unwrap().location = location;
+
+        Ok(())
+    }
+
+    fn prepare(&self, element: &super::FileSink) -> Result<(), gst::ErrorMessage> {
+        let settings = self.settings.lock().unwrap().clone();
+
+        gst_debug!(CAT, obj: element, "Preparing");
+
+        let location = settings.location.ok_or_else(|| {
+            error_msg!(
+                gst::ResourceError::Settings,
+                ["File location is not defined"]
+            )
+        })?;
+
+        let context =
+            Context::acquire(&settings.context, settings.context_wait).map_err(|err| {
+                error_msg!(
+                    gst::ResourceError::OpenWrite,
+                    ["Failed to acquire Context: {}", err]
+                )
+            })?;
+
+        self.task
+            .prepare(
+                FileSinkTask::new(element, &self.sink_pad_handler, location),
+                context,
+            )
+            .map_err(|err| {
+                error_msg!(
+                    gst::ResourceError::OpenWrite,
+                    ["Error preparing Task: {:?}", err]
+                )
+            })?;
+
+        gst_debug!(CAT, obj: element, "Prepared");
+
+        Ok(())
+    }
+
+    fn unprepare(&self, element: &super::FileSink) {
+        gst_debug!(CAT, obj: element, "Unpreparing");
+        self.task.unprepare().unwrap();
+        gst_debug!(CAT, obj: element, "Unprepared");
+    }
+
+    fn stop(&self, element: &super::FileSink) -> Result<(), gst::ErrorMessage> {
+        gst_debug!(CAT, obj: element, "Stopping");
+        self.task.stop()?;
+        gst_debug!(CAT, obj: element, "Stopped");
+        Ok(())
+    }
+
+    fn start(&self, element: &super::FileSink) -> Result<(), gst::ErrorMessage> {
+        gst_debug!(CAT, obj: element, "Starting");
+        self.task.start()?;
+        gst_debug!(CAT, obj: element, "Started");
+        Ok(())
+    }
+}
+
+#[glib::object_subclass]
+impl ObjectSubclass for FileSink {
+    const NAME: &'static str = "RsTsFileSink";
+    type Type = super::FileSink;
+    type ParentType = gst::Element;
+    type Interfaces = (gst::URIHandler,);
+
+    fn with_class(klass: &Self::Class) -> Self {
+        let sink_pad_handler = FileSinkPadHandler::default();
+
+        Self {
+            sink_pad: PadSink::new(
+                gst::Pad::from_template(&klass.pad_template("sink").unwrap(), Some("sink")),
+                sink_pad_handler.clone(),
+            ),
+            sink_pad_handler,
+            task: Task::default(),
+            settings: StdMutex::new(Settings::default()),
+        }
+    }
+}
+
+impl ObjectImpl for FileSink {
+    fn properties() -> &'static [glib::ParamSpec] {
+        static PROPERTIES: Lazy<Vec<glib::ParamSpec>> = Lazy::new(|| {
+            vec![
+                glib::ParamSpecString::new(
+                    "context",
+                    "Context",
+                    "Context name to share threads with",
+                    Some(DEFAULT_CONTEXT),
+                    glib::ParamFlags::READWRITE,
+                ),
+                glib::ParamSpecUInt::new(
+                    "context-wait",
+                    "Context Wait",
+                    "Throttle poll loop to run at most once every this many ms",
+                    0,
+                    1000,
+                    DEFAULT_CONTEXT_WAIT.as_millis() as u32,
+                    glib::ParamFlags::READWRITE,
+                ),
+                glib::ParamSpecString::new(
+                    "location",
+                    "File Location",
+                    "Location of the file to write",
+                    None,
+                    glib::ParamFlags::READWRITE,
+                ),
+            ]
+        });
+
+        PROPERTIES.as_ref()
+    }
+
+    fn set_property(
+        &self,
+        obj: &Self::Type,
+        _id: usize,
+        value: &glib::Value,
+        pspec: &glib::ParamSpec,
+    ) {
+        match pspec.name() {
+            "location" => {
+                let location = value
+                    .get::<Option<String>>()
+                    .expect("type checked upstream")
+                    .map(PathBuf::from);
+
+                if let Err(err) = self.set_location(obj, location) {
+                    gst_error!(CAT, obj: obj, "Failed to set property `location`: {}", err);
+                }
+            }
+            "context" => {
+                self.settings.lock().unwrap().context = value
+                    .get::<Option<String>>()
+                    .expect("type checked upstream")
+                    .unwrap_or_else(|| DEFAULT_CONTEXT.into());
+            }
+            "context-wait" => {
+                self.settings.lock().unwrap().context_wait = Duration::from_millis(
+                    value.get::<u32>().expect("type checked upstream").into(),
+                );
+            }
+            _ => unimplemented!(),
+        }
+    }
+
+    fn property(&self, _obj: &Self::Type, _id: usize, pspec: &glib::ParamSpec) -> glib::Value {
+        let settings = self.settings.lock().unwrap();
+        match pspec.name() {
+            "location" => settings
+                .location
+                .as_ref()
+                .map(|location| location.to_string_lossy().into_owned())
+                .to_value(),
+            "context" => settings.context.to_value(),
+            "context-wait" => (settings.context_wait.as_millis() as u32).to_value(),
+            _ => unimplemented!(),
+        }
+    }
+
+    fn constructed(&self, obj: &Self::Type) {
+        self.parent_constructed(obj);
+
+        obj.add_pad(self.sink_pad.gst_pad()).unwrap();
+
+        crate::set_element_flags(obj, gst::ElementFlags::SINK);
+    }
+}
+
+impl GstObjectImpl for FileSink {}
+
+impl ElementImpl for FileSink {
+    fn metadata() -> Option<&'static gst::subclass::ElementMetadata> {
+        static ELEMENT_METADATA: Lazy<gst::subclass::ElementMetadata> = Lazy::new(|| {
+            gst::subclass::ElementMetadata::new(
+                "Thread-sharing file sink",
+                "Sink/File",
+                "Writes stream to a file without blocking the Context",
+                "Sebastian Dröge <sebastian@centricular.com>",
+            )
+        });
+
+        Some(&*ELEMENT_METADATA)
+    }
+
+    fn pad_templates() -> &'static [gst::PadTemplate] {
+        static PAD_TEMPLATES: Lazy<Vec<gst::PadTemplate>> = Lazy::new(|| {
+            let caps = gst::Caps::new_any();
+
+            let sink_pad_template = gst::PadTemplate::new(
+                "sink",
+                gst::PadDirection::Sink,
+                gst::PadPresence::Always,
+                &caps,
+            )
+            .unwrap();
+
+            vec![sink_pad_template]
+        });
+
+        PAD_TEMPLATES.as_ref()
+    }
+
+    fn change_state(
+        &self,
+        element: &Self::Type,
+        transition: gst::StateChange,
+    ) -> Result<gst::StateChangeSuccess, gst::StateChangeError> {
+        gst_trace!(CAT, obj: element, "Changing state {:?}", transition);
+
+        match transition {
+            gst::StateChange::NullToReady => {
+                self.prepare(element).map_err(|err| {
+                    element.post_error_message(err);
+                    gst::StateChangeError
+                })?;
+            }
+            gst::StateChange::ReadyToPaused => {
+                self.start(element).map_err(|_| gst::StateChangeError)?;
+            }
+            gst::StateChange::PausedToReady => {
+                self.stop(element).map_err(|_| gst::StateChangeError)?;
+            }
+            gst::StateChange::ReadyToNull => {
+                self.unprepare(element);
+            }
+            _ => (),
+        }
+
+        self.parent_change_state(element, transition)
+    }
+}
+
+impl URIHandlerImpl for FileSink {
+    const URI_TYPE: gst::URIType = gst::URIType::Sink;
+
+    fn protocols() -> &'static [&'static str] {
+        &["file"]
+    }
+
+    fn uri(&self, _element: &Self::Type) -> Option<String> {
+        let settings = self.settings.lock().unwrap();
+        let location = settings.location.as_ref()?;
+
+        let location = if location.is_relative() {
+            std::env::current_dir().ok()?.join(location)
+        } else {
+            location.clone()
+        };
+
+        glib::filename_to_uri(location, None).ok().map(Into::into)
+    }
+
+    fn set_uri(&self, element: &Self::Type, uri: &str) -> Result<(), glib::Error> {
+        // Special case for "file://" as this is used by some applications to test
+        // with `gst_element_make_from_uri` if there's an element that supports the URI protocol
+        if uri == "file://" {
+            return Ok(());
+        }
+
+        let (location, _hostname) = glib::filename_from_uri(uri).map_err(|err| {
+            glib::Error::new(
+                gst::URIError::BadUri,
+                format!("Unsupported URI {}: {}", uri, err).as_str(),
+            )
+        })?;
+
+        self.set_location(element, Some(location))
+    }
+}
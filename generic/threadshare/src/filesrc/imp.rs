@@ -0,0 +1,760 @@
+// Copyright (C) 2026 agent <agent@local>
+//
+// This library is free software; you can redistribute it and/or
+// modify it under the terms of the GNU Library General Public
+// License as published by the Free Software Foundation; either
+// version 2 of the License, or (at your option) any later version.
+//
+// This library is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the GNU
+// Library General Public License for more details.
+//
+// You should have received a copy of the GNU Library General Public
+// License along with this library; if not, write to the
+// Free Software Foundation, Inc., 51 Franklin Street, Suite 500,
+// Boston, MA 02110-1335, USA.
+
+use futures::future::BoxFuture;
+use futures::prelude::*;
+
+use gst::glib;
+use gst::prelude::*;
+use gst::subclass::prelude::*;
+use gst::{gst_debug, gst_error, gst_info, gst_log, gst_trace};
+
+use once_cell::sync::Lazy;
+
+use std::fs::File;
+use std::io::{self, Read, Seek, SeekFrom};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::sync::Mutex as StdMutex;
+use std::time::Duration;
+use std::u32;
+
+use crate::runtime::prelude::*;
+use crate::runtime::task;
+use crate::runtime::{unblock, Context, PadSrc, PadSrcRef, PadSrcWeak, Task, TaskState};
+
+const DEFAULT_CONTEXT: &str = "";
+const DEFAULT_CONTEXT_WAIT: Duration = Duration::ZERO;
+const DEFAULT_BLOCKSIZE: u32 = 4096;
+
+#[derive(Debug, Clone)]
+struct Settings {
+    context: String,
+    context_wait: Duration,
+    location: Option<PathBuf>,
+    blocksize: u32,
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Settings {
+            context: DEFAULT_CONTEXT.into(),
+            context_wait: DEFAULT_CONTEXT_WAIT,
+            location: None,
+            blocksize: DEFAULT_BLOCKSIZE,
+        }
+    }
+}
+
+static CAT: Lazy<gst::DebugCategory> = Lazy::new(|| {
+    gst::DebugCategory::new(
+        "ts-filesrc",
+        gst::DebugColorFlags::empty(),
+        Some("Thread-sharing file source"),
+    )
+});
+
+/// Opens the file at `location` for reading and returns it with its size.
+///
+/// This is blocking and must be executed with [`unblock`].
+fn open_file(location: &Path) -> io::Result<(File, u64)> {
+    let file = File::open(location)?;
+    let metadata = file.metadata()?;
+    if metadata.is_dir() {
+        return Err(io::Error::new(io::ErrorKind::Other, "Is a directory"));
+    }
+
+    Ok((file, metadata.len()))
+}
+
+/// Reads the block of at most `blocksize` bytes at `offset`, `None` at the end of the file.
+///
+/// The read doesn't depend on the previous file position, so a read that is cancelled by a flush
+/// doesn't affect the following ones.
+///
+/// This is blocking and must be executed with [`unblock`].
+fn read_buffer(
+    file: &StdMutex<File>,
+    offset: u64,
+    blocksize: usize,
+) -> io::Result<Option<gst::Buffer>> {
+    let mut buffer = gst::Buffer::with_size(blocksize)
+        .map_err(|_| io::Error::new(io::ErrorKind::Other, "Failed to allocate buffer"))?;
+
+    let read_size = {
+        let buffer = buffer.get_mut().unwrap();
+        let mut map = buffer
+            .map_writable()
+            .map_err(|_| io::Error::new(io::ErrorKind::Other, "Failed to map buffer"))?;
+
+        let mut file = file.lock().unwrap();
+        file.seek(SeekFrom::Start(offset))?;
+        loop {
+            match file.read(&mut map) {
+                Err(err) if err.kind() == io::ErrorKind::Interrupted => continue,
+                res => break res?,
+            }
+        }
+    };
+
+    if read_size == 0 {
+        return Ok(None);
+    }
+
+    buffer.get_mut().unwrap().set_size(read_size);
+
+    Ok(Some(buffer))
+}
+
+#[derive(Clone, Debug, Default)]
+struct FileSrcPadHandler(Arc<StdMutex<Option<u64>>>);
+
+impl FileSrcPadHandler {
+    fn set_size(&self, size: Option<u64>) {
+        *self.0.lock().unwrap() = size;
+    }
+}
+
+impl PadSrcHandler for FileSrcPadHandler {
+    type ElementImpl = FileSrc;
+
+    fn src_event(
+        &self,
+        pad: &PadSrcRef,
+        filesrc: &FileSrc,
+        _element: &gst::Element,
+        event: gst::Event,
+    ) -> bool {
+        use gst::EventView;
+
+        gst_log!(CAT, obj: pad.gst_pad(), "Handling {:?}", event);
+
+        let ret = match event.view() {
+            EventView::FlushStart(..) => filesrc.task.flush_start().is_ok(),
+            EventView::FlushStop(..) => filesrc.task.flush_stop().is_ok(),
+            EventView::Reconfigure(..) => true,
+            EventView::Latency(..) => true,
+            _ => false,
+        };
+
+        if ret {
+            gst_log!(CAT, obj: pad.gst_pad(), "Handled {:?}", event);
+        } else {
+            gst_log!(CAT, obj: pad.gst_pad(), "Didn't handle {:?}", event);
+        }
+
+        ret
+    }
+
+    fn src_query(
+        &self,
+        pad: &PadSrcRef,
+        _filesrc: &FileSrc,
+        _element: &gst::Element,
+        query: &mut gst::QueryRef,
+    ) -> bool {
+        use gst::QueryView;
+
+        gst_log!(CAT, obj: pad.gst_pad(), "Handling {:?}", query);
+        let ret = match query.view_mut() {
+            QueryView::Latency(ref mut q) => {
+                q.set(false, gst::ClockTime::ZERO, gst::ClockTime::NONE);
+                true
+            }
+            QueryView::Scheduling(ref mut q) => {
+                q.set(gst::SchedulingFlags::SEQUENTIAL, 1, -1, 0);
+                q.add_scheduling_modes(&[gst::PadMode::Push]);
+                true
+            }
+            QueryView::Duration(ref mut q) if q.format() == gst::Format::Bytes => {
+                match *self.0.lock().unwrap() {
+                    Some(size) => {
+                        q.set(Some(gst::format::Bytes(size)));
+                        true
+                    }
+                    None => false,
+                }
+            }
+            QueryView::Caps(ref mut q) => {
+                let caps = q
+                    .filter()
+                    .map(|f| f.to_owned())
+                    .unwrap_or_else(gst::Caps::new_any);
+
+                q.set_result(&caps);
+
+                true
+            }
+            _ => false,
+        };
+
+        if ret {
+            gst_log!(CAT, obj: pad.gst_pad(), "Handled {:?}", query);
+        } else {
+            gst_log!(CAT, obj: pad.gst_pad(), "Didn't handle {:?}", query);
+        }
+
+        ret
+    }
+}
+
+#[derive(Debug)]
+struct FileSrcTask {
+    element: super::FileSrc,
+    src_pad: PadSrcWeak,
+    src_pad_handler: FileSrcPadHandler,
+    location: PathBuf,
+    blocksize: usize,
+    file: Option<Arc<StdMutex<File>>>,
+    need_initial_events: bool,
+    need_segment: bool,
+    position: u64,
+}
+
+impl FileSrcTask {
+    fn new(
+        element: &super::FileSrc,
+        src_pad: &PadSrc,
+        src_pad_handler: &FileSrcPadHandler,
+        location: PathBuf,
+        blocksize: u32,
+    ) -> Self {
+        FileSrcTask {
+            element: element.clone(),
+            src_pad: src_pad.downgrade(),
+            src_pad_handler: src_pad_handler.clone(),
+            location,
+            blocksize: blocksize as usize,
+            file: None,
+            need_initial_events: true,
+            need_segment: true,
+            position: 0,
+        }
+    }
+
+    async fn push_prelude(&mut self, pad: &PadSrcRef<'_>) {
+        if self.need_initial_events {
+            gst_debug!(CAT, obj: pad.gst_pad(), "Pushing initial events");
+
+            let stream_id = format!("{:08x}{:08x}", rand::random::<u32>(), rand::random::<u32>());
+            let stream_start_evt = gst::event::StreamStart::builder(&stream_id)
+                .group_id(gst::GroupId::next())
+                .build();
+            pad.push_event(stream_start_evt).await;
+
+            self.need_initial_events = false;
+        }
+
+        if self.need_segment {
+            let segment_evt =
+                gst::event::Segment::new(&gst::FormattedSegment::<gst::format::Bytes>::new());
+            pad.push_event(segment_evt).await;
+
+            self.need_segment = false;
+        }
+    }
+}
+
+impl TaskImpl for FileSrcTask {
+    fn prepare(&mut self) -> BoxFuture<'_, Result<(), gst::ErrorMessage>> {
+        async move {
+            gst_log!(CAT, obj: &self.element, "Preparing task reading {:?}", self.location);
+
+            let location = self.location.clone();
+            let (file, size) = unblock(move || open_file(&location)).await.map_err(|err| {
+                gst::error_msg!(
+                    gst::ResourceError::OpenRead,
+                    [
+                        "Could not open file {} for reading: {}",
+                        self.location.display(),
+                        err
+                    ]
+                )
+            })?;
+            gst_debug!(CAT, obj: &self.element, "Opened file {:?} of size {}", file, size);
+
+            self.file = Some(Arc::new(StdMutex::new(file)));
+            self.src_pad_handler.set_size(Some(size));
+
+            gst_log!(CAT, obj: &self.element, "Task prepared");
+            Ok(())
+        }
+        .boxed()
+    }
+
+    fn unprepare(&mut self) -> BoxFuture<'_, ()> {
+        async move {
+            gst_log!(CAT, obj: &self.element, "Unpreparing task");
+
+            self.file = None;
+            self.src_pad_handler.set_size(None);
+
+            gst_log!(CAT, obj: &self.element, "Task unprepared");
+        }
+        .boxed()
+    }
+
+    fn handle_action_error(
+        &mut self,
+        trigger: task::Trigger,
+        state: TaskState,
+        err: gst::ErrorMessage,
+    ) -> BoxFuture<'_, task::Trigger> {
+        async move {
+            match trigger {
+                task::Trigger::Prepare | task::Trigger::Stop => {
+                    gst_error!(CAT, "Task action {:?} failed: {:?}", trigger, err);
+                    self.element.post_error_message(err);
+
+                    task::Trigger::Error
+                }
+                other => unreachable!("Action error for {:?} in state {:?}", other, state),
+            }
+        }
+        .boxed()
+    }
+
+    fn iterate(&mut self) -> BoxFuture<'_, Result<(), gst::FlowError>> {
+        async move {
+            let file = Arc::clone(self.file.as_ref().unwrap());
+            let position = self.position;
+            let blocksize = self.blocksize;
+            let res = unblock(move || read_buffer(&file, position, blocksize)).await;
+
+            let pad = self.src_pad.upgrade().expect("PadSrc no longer exists");
+            self.push_prelude(&pad).await;
+
+            let mut buffer = match res {
+                Ok(Some(buffer)) => buffer,
+                Ok(None) => {
+                    gst_debug!(CAT, obj: &self.element, "EOS");
+                    pad.push_event(gst::event::Eos::new()).await;
+                    return Err(gst::FlowError::Eos);
+                }
+                Err(err) => {
+                    gst_error!(CAT, obj: &self.element, "Failed to read: {}", err);
+                    gst::element_error!(
+                        &self.element,
+                        gst::ResourceError::Read,
+                        ["Failed to read from {}: {}", self.location.display(), err]
+                    );
+                    return Err(gst::FlowError::Error);
+                }
+            };
+
+            {
+                let buffer = buffer.get_mut().unwrap();
+                let end = self.position + buffer.size() as u64;
+                buffer.set_offset(self.position);
+                buffer.set_offset_end(end);
+                self.position = end;
+            }
+
+            gst_log!(CAT, obj: &self.element, "Forwarding {:?}", buffer);
+            let res = pad.push(buffer).await;
+            match res {
+                Ok(_) => {
+                    gst_log!(CAT, obj: &self.element, "Successfully pushed buffer");
+                }
+                Err(gst::FlowError::Flushing) => {
+                    gst_debug!(CAT, obj: &self.element, "Flushing");
+                }
+                Err(gst::FlowError::Eos) => {
+                    gst_debug!(CAT, obj: &self.element, "EOS");
+                    pad.push_event(gst::event::Eos::new()).await;
+                }
+                Err(err) => {
+                    gst_error!(CAT, obj: &self.element, "Got error {}", err);
+                    gst::element_error!(
+                        &self.element,
+                        gst::StreamError::Failed,
+                        ("Internal data stream error"),
+                        ["streaming stopped, reason {}", err]
+                    );
+                }
+            }
+
+            res.map(drop)
+        }
+        .boxed()
+    }
+
+    fn stop(&mut self) -> BoxFuture<'_, Result<(), gst::ErrorMessage>> {
+        async move {
+            gst_log!(CAT, obj: &self.element, "Stopping task");
+
+            // Start over from the beginning of the file on next start
+            self.need_initial_events = true;
+            self.need_segment = true;
+            self.position = 0;
+
+            gst_log!(CAT, obj: &self.element, "Task stopped");
+            Ok(())
+        }
+        .boxed()
+    }
+
+    fn flush_start(&mut self) -> BoxFuture<'_, Result<(), gst::ErrorMessage>> {
+        async move {
+            gst_log!(CAT, obj: &self.element, "Starting task flush");
+
+            self.need_segment = true;
+
+            gst_log!(CAT, obj: &self.element, "Task flush started");
+            Ok(())
+        }
+        .boxed()
+    }
+}
+
+#[derive(Debug)]
+pub struct FileSrc {
+    src_pad: PadSrc,
+    src_pad_handler: FileSrcPadHandler,
+    task: Task,
+    settings: StdMutex<Settings>,
+}
+
+impl FileSrc {
+    fn set_location(
+        &self,
+        element: &super::FileSrc,
+        location: Option<PathBuf>,
+    ) -> Result<(), glib::Error> {
+        if self.task.state() != TaskState::Unprepared {
+            return Err(glib::Error::new(
+                gst::URIError::BadState,
+                "Changing the `location` property on a prepared `ts-filesrc` is not supported",
+            ));
+        }
+
+        gst_info!(CAT, obj: element, "Setting `location` to {:?}", location);
+        self.settings.lock().unwrap().location = location;
+
+        Ok(())
+    }
+
+    fn prepare(&self, element: &super::FileSrc) -> Result<(), gst::ErrorMessage> {
+        let settings = self.settings.lock().unwrap().clone();
+        gst_debug!(CAT, obj: element, "Preparing");
+
+        let location = settings.location.ok_or_else(|| {
+            gst::error_msg!(
+                gst::ResourceError::Settings,
+                ["File location is not defined"]
+            )
+        })?;
+
+        let context =
+            Context::acquire(&settings.context, settings.context_wait).map_err(|err| {
+                gst::error_msg!(
+                    gst::ResourceError::OpenRead,
+                    ["Failed to acquire Context: {}", err]
+                )
+            })?;
+
+        self.task
+            .prepare(
+                FileSrcTask::new(
+                    element,
+                    &self.src_pad,
+                    &self.src_pad_handler,
+                    location,
+                    settings.blocksize,
+                ),
+                context,
+            )
+            .map_err(|err| {
+                gst::error_msg!(
+                    gst::ResourceError::OpenRead,
+                    ["Error preparing Task: {:?}", err]
+                )
+            })?;
+
+        gst_debug!(CAT, obj: element, "Prepared");
+
+        Ok(())
+    }
+
+    fn unprepare(&self, element: &super::FileSrc) {
+        gst_debug!(CAT, obj: element, "Unpreparing");
+        self.task.unprepare().unwrap();
+        gst_debug!(CAT, obj: element, "Unprepared");
+    }
+
+    fn stop(&self, element: &super::FileSrc) -> Result<(), gst::ErrorMessage> {
+        gst_debug!(CAT, obj: element, "Stopping");
+        self.task.stop()?;
+        gst_debug!(CAT, obj: element, "Stopped");
+        Ok(())
+    }
+
+    fn start(&self, element: &super::FileSrc) -> Result<(), gst::ErrorMessage> {
+        gst_debug!(CAT, obj: element, "Starting");
+        self.task.start()?;
+        gst_debug!(CAT, obj: element, "Started");
+        Ok(())
+    }
+
+    fn pause(&self, element: &super::FileSrc) -> Result<(), gst::ErrorMessage> {
+        gst_debug!(CAT, obj: element, "Pausing");
+        self.task.pause()?;
+        gst_debug!(CAT, obj: element, "Paused");
+        Ok(())
+    }
+}
+
+#[glib::object_subclass]
+impl ObjectSubclass for FileSrc {
+    const NAME: &'static str = "RsTsFileSrc";
+    type Type = super::FileSrc;
+    type ParentType = gst::Element;
+    type Interfaces = (gst::URIHandler,);
+
+    fn with_class(klass: &Self::Class) -> Self {
+        let src_pad_handler = FileSrcPadHandler::default();
+
+        Self {
+            src_pad: PadSrc::new(
+                gst::Pad::from_template(&klass.pad_template("src").unwrap(), Some("src")),
+                src_pad_handler.clone(),
+            ),
+            src_pad_handler,
+            task: Task::default(),
+            settings: StdMutex::new(Settings::default()),
+        }
+    }
+}
+
+impl ObjectImpl for FileSrc {
+    fn properties() -> &'static [glib::ParamSpec] {
+        static PROPERTIES: Lazy<Vec<glib::ParamSpec>> = Lazy::new(|| {
+            vec![
+                glib::ParamSpecString::new(
+                    "context",
+                    "Context",
+                    "Context name to share threads with",
+                    Some(DEFAULT_CONTEXT),
+                    glib::ParamFlags::READWRITE,
+                ),
+                glib::ParamSpecUInt::new(
+                    "context-wait",
+                    "Context Wait",
+                    "Throttle poll loop to run at most once every this many ms",
+                    0,
+                    1000,
+                    DEFAULT_CONTEXT_WAIT.as_millis() as u32,
+                    glib::ParamFlags::READWRITE,
+                ),
+                glib::ParamSpecString::new(
+                    "location",
+                    "File Location",
+                    "Location of the file to read from",
+                    None,
+                    glib::ParamFlags::READWRITE,
+                ),
+                glib::ParamSpecUInt::new(
+                    "blocksize",
+                    "Blocksize",
+                    "Size in bytes to read per buffer",
+                    1,
+                    u32::MAX,
+                    DEFAULT_BLOCKSIZE,
+                    glib::ParamFlags::READWRITE,
+                ),
+            ]
+        });
+
+        PROPERTIES.as_ref()
+    }
+
+    fn set_property(
+        &self,
+        obj: &Self::Type,
+        _id: usize,
+        value: &glib::Value,
+        pspec: &glib::ParamSpec,
+    ) {
+        match pspec.name() {
+            "location" => {
+                let location = value
+                    .get::<Option<String>>()
+                    .expect("type checked upstream")
+                    .map(PathBuf::from);
+
+                if let Err(err) = self.set_location(obj, location) {
+                    gst_error!(CAT, obj: obj, "Failed to set property `location`: {}", err);
+                }
+            }
+            "context" => {
+                self.settings.lock().unwrap().context = value
+                    .get::<Option<String>>()
+                    .expect("type checked upstream")
+                    .unwrap_or_else(|| DEFAULT_CONTEXT.into());
+            }
+            "context-wait" => {
+                self.settings.lock().unwrap().context_wait = Duration::from_millis(
+                    value.get::<u32>().expect("type checked upstream").into(),
+                );
+            }
+            "blocksize" => {
+                self.settings.lock().unwrap().blocksize =
+                    value.get().expect("type checked upstream");
+            }
+            _ => unimplemented!(),
+        }
+    }
+
+    fn property(&self, _obj: &Self::Type, _id: usize, pspec: &glib::ParamSpec) -> glib::Value {
+        let settings = self.settings.lock().unwrap();
+        match pspec.name() {
+            "location" => settings
+                .location
+                .as_ref()
+                .map(|location| location.to_string_lossy().into_owned())
+                .to_value(),
+            "context" => settings.context.to_value(),
+            "context-wait" => (settings.context_wait.as_millis() as u32).to_value(),
+            "blocksize" => settings.blocksize.to_value(),
+            _ => unimplemented!(),
+        }
+    }
+
+    fn constructed(&self, obj: &Self::Type) {
+        self.parent_constructed(obj);
+
+        obj.add_pad(self.src_pad.gst_pad()).unwrap();
+
+        crate::set_element_flags(obj, gst::ElementFlags::SOURCE);
+    }
+}
+
+impl GstObjectImpl for FileSrc {}
+
+impl ElementImpl for FileSrc {
+    fn metadata() -> Option<&'static gst::subclass::ElementMetadata> {
+        static ELEMENT_METADATA: Lazy<gst::subclass::ElementMetadata> = Lazy::new(|| {
+            gst::subclass::ElementMetadata::new(
+                "Thread-sharing file source",
+                "Source/File",
+                "Reads a file without blocking the Context",
+                "Sebastian Dröge <sebastian@centricular.com>",
+            )
+        });
+
+        Some(&*ELEMENT_METADATA)
+    }
+
+    fn pad_templates() -> &'static [gst::PadTemplate] {
+        static PAD_TEMPLATES: Lazy<Vec<gst::PadTemplate>> = Lazy::new(|| {
+            let caps = gst::Caps::new_any();
+            let src_pad_template = gst::PadTemplate::new(
+                "src",
+                gst::PadDirection::Src,
+                gst::PadPresence::Always,
+                &caps,
+            )
+            .unwrap();
+
+            vec![src_pad_template]
+        });
+
+        PAD_TEMPLATES.as_ref()
+    }
+
+    fn change_state(
+        &self,
+        element: &Self::Type,
+        transition: gst::StateChange,
+    ) -> Result<gst::StateChangeSuccess, gst::StateChangeError> {
+        gst_trace!(CAT, obj: element, "Changing state {:?}", transition);
+
+        match transition {
+            gst::StateChange::NullToReady => {
+                self.prepare(element).map_err(|err| {
+                    element.post_error_message(err);
+                    gst::StateChangeError
+                })?;
+            }
+            gst::StateChange::PlayingToPaused => {
+                self.pause(element).map_err(|_| gst::StateChangeError)?;
+            }
+            gst::StateChange::ReadyToNull => {
+                self.unprepare(element);
+            }
+            _ => (),
+        }
+
+        let mut success = self.parent_change_state(element, transition)?;
+
+        match transition {
+            gst::StateChange::ReadyToPaused => {
+                success = gst::StateChangeSuccess::NoPreroll;
+            }
+            gst::StateChange::PausedToPlaying => {
+                self.start(element).map_err(|_| gst::StateChangeError)?;
+            }
+            gst::StateChange::PlayingToPaused => {
+                success = gst::StateChangeSuccess::NoPreroll;
+            }
+            gst::StateChange::PausedToReady => {
+                self.stop(element).map_err(|_| gst::StateChangeError)?;
+            }
+            _ => (),
+        }
+
+        Ok(success)
+    }
+}
+
+impl URIHandlerImpl for FileSrc {
+    const URI_TYPE: gst::URIType = gst::URIType::Src;
+
+    fn protocols() -> &'static [&'static str] {
+        &["file"]
+    }
+
+    fn uri(&self, _element: &Self::Type) -> Option<String> {
+        let settings = self.settings.lock().unwrap();
+        let location = settings.location.as_ref()?;
+
+        let location = if location.is_relative() {
+            std::env::current_dir().ok()?.join(location)
+        } else {
+            location.clone()
+        };
+
+        glib::filename_to_uri(location, None).ok().map(Into::into)
+    }
+
+    fn set_uri(&self, element: &Self::Type, uri: &str) -> Result<(), glib::Error> {
+        // Special case for "file://" as this is used by some applications to test
+        // with `gst_element_make_from_uri` if there's an element that supports the URI protocol
+        if uri == "file://" {
+            return Ok(());
+        }
+
+        let (location, _hostname) = glib::filename_from_uri(uri).map_err(|err| {
+            glib::Error::new(
+                gst::URIError::BadUri,
+                format!("Unsupported URI {}: {}", uri, err).as_str(),
+            )
+        })?;
+
+        self.set_location(element, Some(location))
+    }
+}
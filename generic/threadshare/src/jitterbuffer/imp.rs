@@ -28,7 +28,7 @@ use gst_rtp::RTPBuffer;
 use once_cell::sync::Lazy;
 
 use std::cmp::{max, min, Ordering};
-use std::collections::{BTreeSet, VecDeque};
+use std::collections::{BTreeMap, BTreeSet, VecDeque};
 use std::mem;
 use std::sync::Arc;
 use std::sync::Mutex as StdMutex;
@@ -43,15 +43,38 @@ const DEFAULT_LATENCY: gst::ClockTime = gst::ClockTime::from_mseconds(200);
 const DEFAULT_DO_LOST: bool = false;
 const DEFAULT_MAX_DROPOUT_TIME: u32 = 60000;
 const DEFAULT_MAX_MISORDER_TIME: u32 = 2000;
+const DEFAULT_DO_RETRANSMISSION: bool = false;
+const DEFAULT_RTX_NEXT_SEQNUM: bool = true;
+const DEFAULT_RTX_DELAY: i32 = -1;
+const DEFAULT_RTX_MIN_DELAY: u32 = 0;
+const DEFAULT_RTX_RETRY_TIMEOUT: i32 = -1;
+const DEFAULT_RTX_MIN_RETRY_TIMEOUT: i32 = -1;
+const DEFAULT_RTX_RETRY_PERIOD: i32 = -1;
+const DEFAULT_RTX_MAX_RETRIES: i32 = -1;
+const DEFAULT_RTX_DEADLINE: i32 = -1;
 const DEFAULT_CONTEXT: &str = "";
 const DEFAULT_CONTEXT_WAIT: gst::ClockTime = gst::ClockTime::ZERO;
 
+// Used for the automatic rtx-delay / rtx-retry-timeout until the
+// packet spacing / round-trip time is known
+const DEFAULT_AUTO_RTX_DELAY: gst::ClockTime = gst::ClockTime::from_mseconds(20);
+const DEFAULT_AUTO_RTX_TIMEOUT: gst::ClockTime = gst::ClockTime::from_mseconds(40);
+
 #[derive(Debug, Clone)]
 struct Settings {
     latency: gst::ClockTime,
     do_lost: bool,
     max_dropout_time: u32,
     max_misorder_time: u32,
+    do_retransmission: bool,
+    rtx_next_seqnum: bool,
+    rtx_delay: i32,
+    rtx_min_delay: u32,
+    rtx_retry_timeout: i32,
+    rtx_min_retry_timeout: i32,
+    rtx_retry_period: i32,
+    rtx_max_retries: i32,
+    rtx_deadline: i32,
     context: String,
     context_wait: gst::ClockTime,
 }
@@ -63,12 +86,80 @@ impl Default for Settings {
             do_lost: DEFAULT_DO_LOST,
             max_dropout_time: DEFAULT_MAX_DROPOUT_TIME,
             max_misorder_time: DEFAULT_MAX_MISORDER_TIME,
+            do_retransmission: DEFAULT_DO_RETRANSMISSION,
+            rtx_next_seqnum: DEFAULT_RTX_NEXT_SEQNUM,
+            rtx_delay: DEFAULT_RTX_DELAY,
+            rtx_min_delay: DEFAULT_RTX_MIN_DELAY,
+            rtx_retry_timeout: DEFAULT_RTX_RETRY_TIMEOUT,
+            rtx_min_retry_timeout: DEFAULT_RTX_MIN_RETRY_TIMEOUT,
+            rtx_retry_period: DEFAULT_RTX_RETRY_PERIOD,
+            rtx_max_retries: DEFAULT_RTX_MAX_RETRIES,
+            rtx_deadline: DEFAULT_RTX_DEADLINE,
             context: DEFAULT_CONTEXT.into(),
             context_wait: DEFAULT_CONTEXT_WAIT,
         }
     }
 }
 
+impl Settings {
+    // Time to wait after the expected arrival of a packet before requesting it
+    fn rtx_delay(&self, packet_spacing: gst::ClockTime) -> gst::ClockTime {
+        let delay = if self.rtx_delay >= 0 {
+            gst::ClockTime::from_mseconds(self.rtx_delay as u64)
+        } else if packet_spacing.is_zero() {
+            DEFAULT_AUTO_RTX_DELAY
+        } else {
+            packet_spacing / 2
+        };
+
+        max(
+            delay,
+            gst::ClockTime::from_mseconds(self.rtx_min_delay as u64),
+        )
+    }
+
+    // Time to wait between two requests for the same packet
+    fn rtx_retry_timeout(
+        &self,
+        packet_spacing: gst::ClockTime,
+        avg_rtt: gst::ClockTime,
+    ) -> gst::ClockTime {
+        let timeout = if self.rtx_retry_timeout >= 0 {
+            gst::ClockTime::from_mseconds(self.rtx_retry_timeout as u64)
+        } else if avg_rtt.is_zero() {
+            DEFAULT_AUTO_RTX_TIMEOUT
+        } else {
+            2 * avg_rtt
+        };
+
+        let min_timeout = if self.rtx_min_retry_timeout >= 0 {
+            gst::ClockTime::from_mseconds(self.rtx_min_retry_timeout as u64)
+        } else {
+            packet_spacing
+        };
+
+        // Never retry in a busy loop
+        max(max(timeout, min_timeout), gst::ClockTime::MSECOND)
+    }
+
+    // For how long a packet is requested before giving up on it
+    fn rtx_retry_period(&self, retry_timeout: gst::ClockTime) -> gst::ClockTime {
+        if self.rtx_retry_period >= 0 {
+            gst::ClockTime::from_mseconds(self.rtx_retry_period as u64)
+        } else {
+            self.latency.saturating_sub(retry_timeout)
+        }
+    }
+
+    fn rtx_deadline(&self) -> gst::ClockTime {
+        if self.rtx_deadline >= 0 {
+            gst::ClockTime::from_mseconds(self.rtx_deadline as u64)
+        } else {
+            self.latency
+        }
+    }
+}
+
 #[derive(Eq)]
 struct GapPacket {
     buffer: gst::Buffer,
@@ -115,6 +206,7 @@ struct SinkHandlerInner {
     last_pt: Option<u8>,
 
     last_in_seqnum: Option<u16>,
+    last_in_pts: Option<gst::ClockTime>,
     last_rtptime: Option<u32>,
 }
 
@@ -127,6 +219,7 @@ impl Default for SinkHandlerInner {
             gap_packets: BTreeSet::new(),
             last_pt: None,
             last_in_seqnum: None,
+            last_in_pts: None,
             last_rtptime: None,
         }
     }
@@ -150,19 +243,22 @@ impl SinkHandler {
     ) -> BTreeSet<GapPacket> {
         gst_info!(CAT, obj: element, "Resetting");
 
-        state.jbuf.borrow().flush();
-        state.jbuf.borrow().reset_skew();
+        state.jbuf.flush();
+        state.jbuf.reset_skew();
         state.discont = true;
 
         state.last_popped_seqnum = None;
         state.last_popped_pts = None;
 
         inner.last_in_seqnum = None;
+        inner.last_in_pts = None;
         inner.last_rtptime = None;
 
         state.earliest_pts = None;
         state.earliest_seqnum = None;
 
+        state.rtx_timers.clear();
+
         inner.ips_rtptime = None;
         inner.ips_pts = None;
 
@@ -198,7 +294,7 @@ impl SinkHandler {
         state.clock_rate = Some(clock_rate as u32);
 
         inner.packet_rate_ctx.reset(clock_rate);
-        state.jbuf.borrow().set_clock_rate(clock_rate as u32);
+        state.jbuf.set_clock_rate(clock_rate as u32);
 
         Ok(gst::FlowSuccess::Ok)
     }
@@ -295,6 +391,116 @@ impl SinkHandler {
         reset
     }
 
+    fn schedule_rtx_timer(
+        &self,
+        state: &mut State,
+        element: &super::JitterBuffer,
+        settings: &Settings,
+        seq: u16,
+        expected: gst::ClockTime,
+    ) {
+        let delay = settings.rtx_delay(state.packet_spacing);
+
+        gst_log!(
+            CAT,
+            obj: element,
+            "Scheduling retransmission timer for seq {} expected at {} with delay {}",
+            seq,
+            expected,
+            delay
+        );
+
+        state.rtx_timers.entry(seq).or_insert(RtxTimer {
+            expected,
+            timeout: expected + delay,
+            num_retries: 0,
+            rtx_base: None,
+            rtx_last: None,
+        });
+    }
+
+    // Schedules retransmission timers for all packets missing between
+    // `last_in_seqnum` and `seq`, spreading their expected arrival evenly
+    #[allow(clippy::too_many_arguments)]
+    fn schedule_rtx_gap(
+        &self,
+        inner: &SinkHandlerInner,
+        state: &mut State,
+        element: &super::JitterBuffer,
+        settings: &Settings,
+        last_in_seqnum: u16,
+        seq: u16,
+        pts: gst::ClockTime,
+    ) {
+        let last_in_pts = match inner.last_in_pts {
+            Some(last_in_pts) => last_in_pts,
+            None => return,
+        };
+
+        let gap = gst_rtp::compare_seqnum(last_in_seqnum, seq) as u64;
+        let spacing = pts.saturating_sub(last_in_pts) / gap;
+
+        gst_debug!(
+            CAT,
+            obj: element,
+            "Missing packets between seq {} and {}",
+            last_in_seqnum,
+            seq
+        );
+
+        let mut expected = last_in_pts;
+        let mut lost_seqnum = last_in_seqnum.wrapping_add(1);
+        while lost_seqnum != seq {
+            expected += spacing;
+            self.schedule_rtx_timer(state, element, settings, lost_seqnum, expected);
+            lost_seqnum = lost_seqnum.wrapping_add(1);
+        }
+    }
+
+    fn handle_rtx_arrival(
+        &self,
+        state: &mut State,
+        element: &super::JitterBuffer,
+        seq: u16,
+        is_rtx: bool,
+    ) {
+        let timer = match state.rtx_timers.remove(&seq) {
+            Some(timer) => timer,
+            None => return,
+        };
+
+        if !is_rtx || timer.num_retries == 0 {
+            return;
+        }
+
+        state.stats.num_rtx_success += 1;
+
+        let rtt = element
+            .current_running_time()
+            .opt_checked_sub(timer.rtx_last)
+            .ok()
+            .flatten();
+
+        if let Some(rtt) = rtt {
+            let avg_rtt = state.stats.rtx_rtt;
+            state.stats.rtx_rtt = if avg_rtt.is_zero() {
+                rtt
+            } else {
+                (rtt + 7 * avg_rtt) / 8
+            };
+
+            gst_debug!(
+                CAT,
+                obj: element,
+                "Retransmission of seq {} succeeded after {} retries, rtt {}, average rtt {}",
+                seq,
+                timer.num_retries,
+                rtt,
+                state.stats.rtx_rtt
+            );
+        }
+    }
+
     fn store(
         &self,
         inner: &mut SinkHandlerInner,
@@ -305,21 +511,21 @@ impl SinkHandler {
         let jb = JitterBuffer::from_instance(element);
         let mut state = jb.state.lock().unwrap();
 
-        let (max_misorder_time, max_dropout_time) = {
-            let settings = jb.settings.lock().unwrap();
-            (settings.max_misorder_time, settings.max_dropout_time)
-        };
+        let settings = jb.settings.lock().unwrap().clone();
 
-        let (seq, rtptime, pt) = {
+        let (seq, rtptime, pt, ssrc) = {
             let rtp_buffer =
                 RTPBuffer::from_buffer_readable(&buffer).map_err(|_| gst::FlowError::Error)?;
             (
                 rtp_buffer.seq(),
                 rtp_buffer.timestamp(),
                 rtp_buffer.payload_type(),
+                rtp_buffer.ssrc(),
             )
         };
 
+        let is_rtx = buffer.flags().contains(gst::BufferFlags::RETRANSMISSION);
+
         let mut pts = buffer.pts();
         let mut dts = buffer.dts();
         let mut estimated_dts = false;
@@ -327,10 +533,11 @@ impl SinkHandler {
         gst_log!(
             CAT,
             obj: element,
-            "Storing buffer, seq: {}, rtptime: {}, pt: {}",
+            "Storing buffer, seq: {}, rtptime: {}, pt: {}, rtx: {}",
             seq,
             rtptime,
-            pt
+            pt,
+            is_rtx
         );
 
         if dts.is_none() {
@@ -382,17 +589,16 @@ impl SinkHandler {
 
         inner.packet_rate_ctx.update(seq, rtptime);
 
-        let max_dropout = inner.packet_rate_ctx.max_dropout(max_dropout_time as i32);
-        let max_misorder = inner.packet_rate_ctx.max_dropout(max_misorder_time as i32);
+        let max_dropout = inner
+            .packet_rate_ctx
+            .max_dropout(settings.max_dropout_time as i32);
+        let max_misorder = inner
+            .packet_rate_ctx
+            .max_dropout(settings.max_misorder_time as i32);
 
-        pts = state.jbuf.borrow().calculate_pts(
-            dts,
-            estimated_dts,
-            rtptime,
-            element.base_time(),
-            0,
-            false,
-        );
+        pts = state
+            .jbuf
+            .calculate_pts(dts, estimated_dts, rtptime, element.base_time(), 0, is_rtx);
 
         if pts.is_none() {
             gst_debug!(
@@ -404,7 +610,10 @@ impl SinkHandler {
             return Ok(gst::FlowSuccess::Ok);
         }
 
-        if let Some(last_in_seqnum) = inner.last_in_seqnum {
+        // Retransmitted packets are always older than the last received packet
+        // and must not be taken into account for the gap and spacing estimations
+        let last_in_seqnum = inner.last_in_seqnum.filter(|_| !is_rtx);
+        if let Some(last_in_seqnum) = last_in_seqnum {
             let gap = gst_rtp::compare_seqnum(last_in_seqnum as u16, seq);
             if gap == 1 {
                 self.calculate_packet_spacing(inner, &mut state, rtptime, pts);
@@ -418,6 +627,19 @@ impl SinkHandler {
                         return Ok(gst::FlowSuccess::Ok);
                     }
                 }
+
+                if gap > 1 && settings.do_retransmission {
+                    self.schedule_rtx_gap(
+                        inner,
+                        &mut state,
+                        element,
+                        &settings,
+                        last_in_seqnum,
+                        seq,
+                        pts.unwrap(),
+                    );
+                }
+
                 inner.ips_pts = None;
                 inner.ips_rtptime = None;
             }
@@ -435,7 +657,16 @@ impl SinkHandler {
             }
         }
 
-        inner.last_in_seqnum = Some(seq);
+        if settings.do_retransmission {
+            self.handle_rtx_arrival(&mut state, element, seq, is_rtx);
+        }
+
+        if !is_rtx {
+            inner.last_in_seqnum = Some(seq);
+            inner.last_in_pts = pts;
+        }
+
+        state.last_ssrc = Some(ssrc);
 
         let jb_item = if estimated_dts {
             RTPJitterBufferItem::new(buffer, gst::ClockTime::NONE, pts, Some(seq), rtptime)
@@ -443,7 +674,7 @@ impl SinkHandler {
             RTPJitterBufferItem::new(buffer, dts, pts, Some(seq), rtptime)
         };
 
-        let (success, _, _) = state.jbuf.borrow().insert(jb_item);
+        let (success, _, _) = state.jbuf.insert(jb_item);
 
         if !success {
             /* duplicate */
@@ -458,6 +689,21 @@ impl SinkHandler {
 
         state.equidistant = min(max(state.equidistant, -7), 7);
 
+        if settings.do_retransmission
+            && settings.rtx_next_seqnum
+            && !is_rtx
+            && !state.packet_spacing.is_zero()
+        {
+            let expected = pts.unwrap() + state.packet_spacing;
+            self.schedule_rtx_timer(
+                &mut state,
+                element,
+                &settings,
+                seq.wrapping_add(1),
+                expected,
+            );
+        }
+
         inner.last_rtptime = Some(rtptime);
 
         let must_update = match (state.earliest_pts, pts) {
@@ -517,9 +763,12 @@ impl SinkHandler {
         };
 
         // Reschedule if needed
-        let (_, next_wakeup) =
+        let (now, next_wakeup) =
             jb.src_pad_handler
                 .next_wakeup(element, &state, latency, context_wait);
+        let next_wakeup = jb
+            .src_pad_handler
+            .merge_rtx_wakeup(element, &state, now, next_wakeup);
         if let Some((next_wakeup, _)) = next_wakeup {
             if let Some((previous_next_wakeup, ref abort_handle)) = state.wait_handle {
                 if previous_next_wakeup.is_none()
@@ -759,7 +1008,7 @@ impl SrcHandler {
             let mut state = jb.state.lock().unwrap();
 
             let mut discont = false;
-            let (jb_item, _) = state.jbuf.borrow().pop();
+            let (jb_item, _) = state.jbuf.pop();
 
             let jb_item = match jb_item {
                 None => {
@@ -811,6 +1060,13 @@ impl SrcHandler {
             }
             state.last_popped_seqnum = seq;
 
+            if let Some(seq) = seq {
+                // Too late to retransmit anything up to the popped packet
+                state
+                    .rtx_timers
+                    .retain(|timer_seq, _| gst_rtp::compare_seqnum(seq, *timer_seq) > 0);
+            }
+
             state.stats.num_pushed += 1;
 
             (lost_events, buffer, seq)
@@ -876,6 +1132,132 @@ impl SrcHandler {
 
         (now, Some((next_wakeup, delay.into())))
     }
+
+    // Wakes up earlier than `next_wakeup` if a retransmission timer is due before
+    fn merge_rtx_wakeup(
+        &self,
+        element: &super::JitterBuffer,
+        state: &State,
+        now: Option<gst::ClockTime>,
+        next_wakeup: Option<(Option<gst::ClockTime>, Duration)>,
+    ) -> Option<(Option<gst::ClockTime>, Duration)> {
+        let rtx_wakeup = match state.rtx_timers.values().map(|timer| timer.timeout).min() {
+            Some(rtx_wakeup) => rtx_wakeup,
+            None => return next_wakeup,
+        };
+
+        if let Some((wakeup, _)) = next_wakeup {
+            if wakeup.map_or(true, |wakeup| wakeup <= rtx_wakeup) {
+                return next_wakeup;
+            }
+        }
+
+        let delay = now.map_or(gst::ClockTime::ZERO, |now| rtx_wakeup.saturating_sub(now));
+
+        gst_debug!(
+            CAT,
+            obj: element,
+            "Next retransmission timer at {} with delay {}",
+            rtx_wakeup,
+            delay
+        );
+
+        Some((Some(rtx_wakeup), delay.into()))
+    }
+
+    fn handle_rtx_timers(
+        &self,
+        element: &super::JitterBuffer,
+        state: &mut State,
+        settings: &Settings,
+        now: Option<gst::ClockTime>,
+    ) -> Vec<gst::Event> {
+        let mut events = vec![];
+
+        if !settings.do_retransmission {
+            state.rtx_timers.clear();
+            return events;
+        }
+
+        let now = match now {
+            Some(now) => now,
+            None => return events,
+        };
+
+        let retry_timeout = settings.rtx_retry_timeout(state.packet_spacing, state.stats.rtx_rtt);
+        let retry_period = settings.rtx_retry_period(retry_timeout);
+        let deadline = settings.rtx_deadline();
+
+        let due_seqnums = state
+            .rtx_timers
+            .iter()
+            .filter(|(_, timer)| timer.timeout <= now)
+            .map(|(seq, _)| *seq)
+            .collect::<Vec<_>>();
+
+        for seq in due_seqnums {
+            let mut timer = state.rtx_timers.remove(&seq).unwrap();
+
+            if let Some(last_popped_seqnum) = state.last_popped_seqnum {
+                if gst_rtp::compare_seqnum(last_popped_seqnum, seq) <= 0 {
+                    continue;
+                }
+            }
+
+            let rtx_base = *timer.rtx_base.get_or_insert(now);
+
+            let max_retries_reached = settings.rtx_max_retries >= 0
+                && timer.num_retries >= settings.rtx_max_retries as u32;
+            if max_retries_reached || now.saturating_sub(rtx_base) > retry_period {
+                gst_debug!(
+                    CAT,
+                    obj: element,
+                    "Giving up on retransmission of seq {} after {} retries",
+                    seq,
+                    timer.num_retries
+                );
+                continue;
+            }
+
+            let mut s = gst::Structure::builder("GstRTPRetransmissionRequest")
+                .field("seqnum", seq as u32)
+                .field("running-time", rtx_base.nseconds())
+                .field(
+                    "delay",
+                    now.saturating_sub(timer.expected).mseconds() as u32,
+                )
+                .field("retry", timer.num_retries)
+                .field("frequency", retry_timeout.mseconds() as u32)
+                .field("period", retry_period.mseconds() as u32)
+                .field("deadline", deadline.mseconds() as u32)
+                .field("packet-spacing", state.packet_spacing.nseconds())
+                .field("avg-rtt", state.stats.rtx_rtt.mseconds() as u32)
+                .build();
+
+            if let Some(ssrc) = state.last_ssrc {
+                s.set("ssrc", ssrc);
+            }
+
+            gst_debug!(
+                CAT,
+                obj: element,
+                "Requesting retransmission of seq {}, retry {}",
+                seq,
+                timer.num_retries
+            );
+
+            events.push(gst::event::CustomUpstream::new(s));
+
+            timer.num_retries += 1;
+            timer.rtx_last = Some(now);
+            timer.timeout = now + retry_timeout;
+            state.rtx_timers.insert(seq, timer);
+
+            state.stats.num_rtx_requests += 1;
+        }
+
+        events
+    }
 }
 
 impl PadSrcHandler for SrcHandler {
@@ -972,11 +1354,24 @@ struct Stats {
     num_pushed: u64,
     num_lost: u64,
     num_late: u64,
+    num_rtx_requests: u64,
+    num_rtx_success: u64,
+    rtx_rtt: gst::ClockTime,
+}
+
+// Retransmission timer of a missing packet
+#[derive(Debug)]
+struct RtxTimer {
+    expected: gst::ClockTime,
+    timeout: gst::ClockTime,
+    num_retries: u32,
+    rtx_base: Option<gst::ClockTime>,
+    rtx_last: Option<gst::ClockTime>,
 }
 
 // Shared state between element, sink and source pad
 struct State {
-    jbuf: glib::SendUniqueCell<RTPJitterBuffer>,
+    jbuf: RTPJitterBuffer,
 
     last_res: Result<gst::FlowSuccess, gst::FlowError>,
     position: Option<gst::ClockTime>,
@@ -998,13 +1393,16 @@ struct State {
     earliest_pts: Option<gst::ClockTime>,
     earliest_seqnum: Option<u16>,
 
+    last_ssrc: Option<u32>,
+    rtx_timers: BTreeMap<u16, RtxTimer>,
+
     wait_handle: Option<(Option<gst::ClockTime>, AbortHandle)>,
 }
 
 impl Default for State {
     fn default() -> State {
         State {
-            jbuf: glib::SendUniqueCell::new(RTPJitterBuffer::new()).unwrap(),
+            jbuf: RTPJitterBuffer::new(),
 
             last_res: Ok(gst::FlowSuccess::Ok),
             position: None,
@@ -1026,6 +1424,9 @@ impl Default for State {
             earliest_pts: None,
             earliest_seqnum: None,
 
+            last_ssrc: None,
+            rtx_timers: BTreeMap::new(),
+
             wait_handle: None,
         }
     }
@@ -1071,20 +1472,24 @@ impl TaskImpl for JitterBufferTask {
     fn iterate(&mut self) -> BoxFuture<'_, Result<(), gst::FlowError>> {
         async move {
             let jb = JitterBuffer::from_instance(&self.element);
-            let (latency, context_wait) = {
-                let settings = jb.settings.lock().unwrap();
-                (settings.latency, settings.context_wait)
-            };
+            let settings = jb.settings.lock().unwrap().clone();
+            let (latency, context_wait) = (settings.latency, settings.context_wait);
 
             loop {
                 let delay_fut = {
                     let mut state = jb.state.lock().unwrap();
-                    let (_, next_wakeup) = self.src_pad_handler.next_wakeup(
+                    let (now, next_wakeup) = self.src_pad_handler.next_wakeup(
                         &self.element,
                         &state,
                         latency,
                         context_wait,
                     );
+                    let next_wakeup = self.src_pad_handler.merge_rtx_wakeup(
+                        &self.element,
+                        &state,
+                        now,
+                        next_wakeup,
+                    );
 
                     let (delay_fut, abort_handle) = match next_wakeup {
                         Some((_, delay)) if delay.is_zero() => (None, None),
@@ -1119,8 +1524,8 @@ impl TaskImpl for JitterBufferTask {
                     }
                 }
 
-                let (head_pts, head_seq) = {
-                    let state = jb.state.lock().unwrap();
+                let (rtx_events, head) = {
+                    let mut state = jb.state.lock().unwrap();
                     //
                     // Check earliest PTS as we have just taken the lock
                     let (now, next_wakeup) = self.src_pad_handler.next_wakeup(
@@ -1138,18 +1543,31 @@ impl TaskImpl for JitterBufferTask {
                         state.earliest_pts.display()
                     );
 
-                    if let Some((next_wakeup, _)) = next_wakeup {
-                        if next_wakeup.opt_gt(now).unwrap_or(false) {
-                            // Reschedule and wait a bit longer in the next iteration
-                            return Ok(());
-                        }
-                    } else {
-                        return Ok(());
-                    }
+                    let rtx_events = self.src_pad_handler.handle_rtx_timers(
+                        &self.element,
+                        &mut state,
+                        &settings,
+                        now,
+                    );
 
-                    let (head_pts, head_seq) = state.jbuf.borrow().peek();
+                    let head = match next_wakeup {
+                        // Reschedule and wait a bit longer in the next iteration
+                        Some((next_wakeup, _)) if next_wakeup.opt_gt(now).unwrap_or(false) => None,
+                        Some(_) => Some(state.jbuf.peek()),
+                        None => None,
+                    };
 
-                    (head_pts, head_seq)
+                    (rtx_events, head)
+                };
+
+                for event in rtx_events {
+                    gst_debug!(CAT, obj: jb.sink_pad.gst_pad(), "Pushing rtx event {:?}", event);
+                    let _ = jb.sink_pad.gst_pad().push_event(event);
+                }
+
+                let (head_pts, head_seq) = match head {
+                    Some(head) => head,
+                    None => return Ok(()),
                 };
 
                 let res = self.src_pad_handler.pop_and_push(&self.element).await;
@@ -1160,7 +1578,7 @@ impl TaskImpl for JitterBufferTask {
                     state.last_res = res;
 
                     if head_pts == state.earliest_pts && head_seq == state.earliest_seqnum {
-                        let (earliest_pts, earliest_seqnum) = state.jbuf.borrow().find_earliest();
+                        let (earliest_pts, earliest_seqnum) = state.jbuf.find_earliest();
                         state.earliest_pts = earliest_pts;
                         state.earliest_seqnum = earliest_seqnum;
                     }
@@ -1249,7 +1667,7 @@ impl JitterBuffer {
 
         let mut state = self.state.lock().unwrap();
         state.clock_rate = None;
-        state.jbuf.borrow().reset_skew();
+        state.jbuf.reset_skew();
     }
 
     fn prepare(&self, element: &super::JitterBuffer) -> Result<(), gst::ErrorMessage> {
@@ -1380,6 +1798,83 @@ impl ObjectImpl for JitterBuffer {
                     DEFAULT_MAX_MISORDER_TIME,
                     glib::ParamFlags::READWRITE,
                 ),
+                glib::ParamSpecBoolean::new(
+                    "do-retransmission",
+                    "Do Retransmission",
+                    "Send retransmission events upstream when a packet is late",
+                    DEFAULT_DO_RETRANSMISSION,
+                    glib::ParamFlags::READWRITE,
+                ),
+                glib::ParamSpecBoolean::new(
+                    "rtx-next-seqnum",
+                    "RTX next seqnum",
+                    "Estimate when the next packet should arrive and schedule a retransmission request for it.",
+                    DEFAULT_RTX_NEXT_SEQNUM,
+                    glib::ParamFlags::READWRITE,
+                ),
+                glib::ParamSpecInt::new(
+                    "rtx-delay",
+                    "RTX Delay",
+                    "Extra time in ms to wait before sending retransmission event (-1 automatic)",
+                    -1,
+                    std::i32::MAX,
+                    DEFAULT_RTX_DELAY,
+                    glib::ParamFlags::READWRITE,
+                ),
+                glib::ParamSpecUInt::new(
+                    "rtx-min-delay",
+                    "Minimum RTX Delay",
+                    "Minimum time in ms to wait before sending retransmission event",
+                    0,
+                    std::u32::MAX,
+                    DEFAULT_RTX_MIN_DELAY,
+                    glib::ParamFlags::READWRITE,
+                ),
+                glib::ParamSpecInt::new(
+                    "rtx-retry-timeout",
+                    "RTX Retry Timeout",
+                    "Retry sending a transmission event after this timeout in ms (-1 automatic)",
+                    -1,
+                    std::i32::MAX,
+                    DEFAULT_RTX_RETRY_TIMEOUT,
+                    glib::ParamFlags::READWRITE,
+                ),
+                glib::ParamSpecInt::new(
+                    "rtx-min-retry-timeout",
+                    "RTX Min Retry Timeout",
+                    "Minimum timeout between sending a transmission event in ms (-1 automatic)",
+                    -1,
+                    std::i32::MAX,
+                    DEFAULT_RTX_MIN_RETRY_TIMEOUT,
+                    glib::ParamFlags::READWRITE,
+                ),
+                glib::ParamSpecInt::new(
+                    "rtx-retry-period",
+                    "RTX Retry Period",
+                    "Try to get a retransmission for this many ms (-1 automatic)",
+                    -1,
+                    std::i32::MAX,
+                    DEFAULT_RTX_RETRY_PERIOD,
+                    glib::ParamFlags::READWRITE,
+                ),
+                glib::ParamSpecInt::new(
+                    "rtx-max-retries",
+                    "RTX Max Retries",
+                    "The maximum number of retries to request a retransmission (-1 not limited)",
+                    -1,
+                    std::i32::MAX,
+                    DEFAULT_RTX_MAX_RETRIES,
+                    glib::ParamFlags::READWRITE,
+                ),
+                glib::ParamSpecInt::new(
+                    "rtx-deadline",
+                    "RTX Deadline",
+                    "The deadline for a valid RTX request in ms (-1 automatic)",
+                    -1,
+                    std::i32::MAX,
+                    DEFAULT_RTX_DEADLINE,
+                    glib::ParamFlags::READWRITE,
+                ),
                 glib::ParamSpecBoxed::new(
                     "stats",
                     "Statistics",
@@ -1430,8 +1925,8 @@ impl ObjectImpl for JitterBuffer {
                     settings.latency
                 };
 
-                let state = self.state.lock().unwrap();
-                state.jbuf.borrow().set_delay(latency);
+                let mut state = self.state.lock().unwrap();
+                state.jbuf.set_delay(latency);
 
                 let _ = obj.post_message(gst::message::Latency::builder().src(obj).build());
             }
@@ -1447,6 +1942,42 @@ impl ObjectImpl for JitterBuffer {
                 let mut settings = self.settings.lock().unwrap();
                 settings.max_misorder_time = value.get().expect("type checked upstream");
             }
+            "do-retransmission" => {
+                let mut settings = self.settings.lock().unwrap();
+                settings.do_retransmission = value.get().expect("type checked upstream");
+            }
+            "rtx-next-seqnum" => {
+                let mut settings = self.settings.lock().unwrap();
+                settings.rtx_next_seqnum = value.get().expect("type checked upstream");
+            }
+            "rtx-delay" => {
+                let mut settings = self.settings.lock().unwrap();
+                settings.rtx_delay = value.get().expect("type checked upstream");
+            }
+            "rtx-min-delay" => {
+                let mut settings = self.settings.lock().unwrap();
+                settings.rtx_min_delay = value.get().expect("type checked upstream");
+            }
+            "rtx-retry-timeout" => {
+                let mut settings = self.settings.lock().unwrap();
+                settings.rtx_retry_timeout = value.get().expect("type checked upstream");
+            }
+            "rtx-min-retry-timeout" => {
+                let mut settings = self.settings.lock().unwrap();
+                settings.rtx_min_retry_timeout = value.get().expect("type checked upstream");
+            }
+            "rtx-retry-period" => {
+                let mut settings = self.settings.lock().unwrap();
+                settings.rtx_retry_period = value.get().expect("type checked upstream");
+            }
+            "rtx-max-retries" => {
+                let mut settings = self.settings.lock().unwrap();
+                settings.rtx_max_retries = value.get().expect("type checked upstream");
+            }
+            "rtx-deadline" => {
+                let mut settings = self.settings.lock().unwrap();
+                settings.rtx_deadline = value.get().expect("type checked upstream");
+            }
             "context" => {
                 let mut settings = self.settings.lock().unwrap();
                 settings.context = value
@@ -1482,12 +2013,51 @@ impl ObjectImpl for JitterBuffer {
                 let settings = self.settings.lock().unwrap();
                 settings.max_misorder_time.to_value()
             }
+            "do-retransmission" => {
+                let settings = self.settings.lock().unwrap();
+                settings.do_retransmission.to_value()
+            }
+            "rtx-next-seqnum" => {
+                let settings = self.settings.lock().unwrap();
+                settings.rtx_next_seqnum.to_value()
+            }
+            "rtx-delay" => {
+                let settings = self.settings.lock().unwrap();
+                settings.rtx_delay.to_value()
+            }
+            "rtx-min-delay" => {
+                let settings = self.settings.lock().unwrap();
+                settings.rtx_min_delay.to_value()
+            }
+            "rtx-retry-timeout" => {
+                let settings = self.settings.lock().unwrap();
+                settings.rtx_retry_timeout.to_value()
+            }
+            "rtx-min-retry-timeout" => {
+                let settings = self.settings.lock().unwrap();
+                settings.rtx_min_retry_timeout.to_value()
+            }
+            "rtx-retry-period" => {
+                let settings = self.settings.lock().unwrap();
+                settings.rtx_retry_period.to_value()
+            }
+            "rtx-max-retries" => {
+                let settings = self.settings.lock().unwrap();
+                settings.rtx_max_retries.to_value()
+            }
+            "rtx-deadline" => {
+                let settings = self.settings.lock().unwrap();
+                settings.rtx_deadline.to_value()
+            }
             "stats" => {
                 let state = self.state.lock().unwrap();
                 let s = gst::Structure::builder("application/x-rtp-jitterbuffer-stats")
                     .field("num-pushed", state.stats.num_pushed)
                     .field("num-lost", state.stats.num_lost)
                     .field("num-late", state.stats.num_late)
+                    .field("num-rtx-requests", state.stats.num_rtx_requests)
+                    .field("num-rtx-success", state.stats.num_rtx_success)
+                    .field("rtx-rtt", state.stats.rtx_rtt.nseconds())
                     .build();
                 s.to_value()
             }
@@ -15,59 +15,86 @@
 // Free Software Foundation, Inc., 51 Franklin Street, Suite 500,
 // Boston, MA 02110-1335, USA.
 
-use super::ffi;
-
-use std::ptr;
+// Packet queue and skew / PTS calculation of the jitterbuffer.
+//
+// This is a port of `rtpjitterbuffer.c` and the packet rate context of
+// `rtpstats.c` from the `rtpmanager` plugin of gst-plugins-good.
 
-use glib::translate::*;
-use gst::glib;
 use gst::prelude::*;
+use gst::{gst_debug, gst_info, gst_log, gst_warning};
 
-use std::mem;
+use once_cell::sync::Lazy;
 
-glib::wrapper! {
-    pub struct RTPJitterBuffer(Object<ffi::RTPJitterBuffer>);
+use std::cmp::{max, min};
+use std::collections::VecDeque;
 
-    match fn {
-        type_ => || ffi::rtp_jitter_buffer_get_type(),
-    }
-}
+static CAT: Lazy<gst::DebugCategory> = Lazy::new(|| {
+    gst::DebugCategory::new(
+        "ts-rtpjitterbuffer",
+        gst::DebugColorFlags::empty(),
+        Some("Thread-sharing RTP jitterbuffer core"),
+    )
+});
 
-unsafe impl glib::SendUnique for RTPJitterBuffer {
-    fn is_unique(&self) -> bool {
-        self.ref_count() == 1
-    }
-}
+const MAX_WINDOW: usize = 512;
+const MAX_TIME: gst::ClockTime = gst::ClockTime::from_seconds(2);
+
+const RTP_DEF_DROPOUT: u32 = 3000;
+const RTP_MIN_DROPOUT: u32 = 30;
+const RTP_DEF_MISORDER: u32 = 100;
+const RTP_MIN_MISORDER: u32 = 10;
+
+// Extends the 32 bits `timestamp` to 64 bits based on the previously
+// extended timestamp `ext`, taking wraparounds into account
+fn ext_timestamp(ext: &mut Option<u64>, timestamp: u32) -> u64 {
+    let timestamp = timestamp as u64;
+
+    let result = match *ext {
+        None => timestamp,
+        Some(ext) => {
+            // pick wraparound counter from previous timestamp and add to new timestamp
+            let mut result = timestamp + (ext & !0xffff_ffff);
+
+            let diff = if result < ext {
+                ext - result
+            } else {
+                result - ext
+            };
 
-impl IntoGlib for RTPJitterBufferMode {
-    type GlibType = ffi::RTPJitterBufferMode;
+            if diff > std::i32::MAX as u64 {
+                if result < ext {
+                    // timestamp wrapped around forwards
+                    result += 1 << 32;
+                } else if result >= 1 << 32 {
+                    // timestamp went backwards before a wraparound
+                    result -= 1 << 32;
+                }
+            }
 
-    fn into_glib(self) -> ffi::RTPJitterBufferMode {
-        match self {
-            RTPJitterBufferMode::None => ffi::RTP_JITTER_BUFFER_MODE_NONE,
-            RTPJitterBufferMode::Slave => ffi::RTP_JITTER_BUFFER_MODE_SLAVE,
-            RTPJitterBufferMode::Buffer => ffi::RTP_JITTER_BUFFER_MODE_BUFFER,
-            RTPJitterBufferMode::Synced => ffi::RTP_JITTER_BUFFER_MODE_SYNCED,
-            RTPJitterBufferMode::__Unknown(value) => value,
+            result
         }
-    }
+    };
+
+    *ext = Some(result);
+
+    result
 }
 
-impl FromGlib<ffi::RTPJitterBufferMode> for RTPJitterBufferMode {
-    unsafe fn from_glib(value: ffi::RTPJitterBufferMode) -> Self {
-        match value {
-            0 => RTPJitterBufferMode::None,
-            1 => RTPJitterBufferMode::Slave,
-            2 => RTPJitterBufferMode::Buffer,
-            4 => RTPJitterBufferMode::Synced,
-            value => RTPJitterBufferMode::__Unknown(value),
-        }
+fn clock_time(nseconds: u64) -> Option<gst::ClockTime> {
+    if nseconds == std::u64::MAX {
+        None
+    } else {
+        Some(gst::ClockTime::from_nseconds(nseconds))
     }
 }
 
-pub struct RTPJitterBufferItem(Option<ptr::NonNull<ffi::RTPJitterBufferItem>>);
-
-unsafe impl Send for RTPJitterBufferItem {}
+pub struct RTPJitterBufferItem {
+    buffer: gst::Buffer,
+    dts: Option<gst::ClockTime>,
+    pts: Option<gst::ClockTime>,
+    seqnum: Option<u16>,
+    rtptime: u32,
+}
 
 impl RTPJitterBufferItem {
     pub fn new(
@@ -77,129 +104,138 @@ impl RTPJitterBufferItem {
         seqnum: Option<u16>,
         rtptime: u32,
     ) -> RTPJitterBufferItem {
-        unsafe {
-            let ptr = ptr::NonNull::new(glib::ffi::g_slice_alloc0(mem::size_of::<
-                ffi::RTPJitterBufferItem,
-            >()) as *mut ffi::RTPJitterBufferItem)
-            .expect("Allocation failed");
-            ptr::write(
-                ptr.as_ptr(),
-                ffi::RTPJitterBufferItem {
-                    data: buffer.into_ptr() as *mut _,
-                    next: ptr::null_mut(),
-                    prev: ptr::null_mut(),
-                    r#type: 0,
-                    dts: dts.into().into_glib(),
-                    pts: pts.into().into_glib(),
-                    seqnum: seqnum.map(|s| s as u32).unwrap_or(std::u32::MAX),
-                    count: 1,
-                    rtptime,
-                },
-            );
-
-            RTPJitterBufferItem(Some(ptr))
+        RTPJitterBufferItem {
+            buffer,
+            dts: dts.into(),
+            pts: pts.into(),
+            seqnum,
+            rtptime,
         }
     }
 
-    pub fn into_buffer(mut self) -> gst::Buffer {
-        unsafe {
-            let item = self.0.take().expect("Invalid wrapper");
-            let buf = item.as_ref().data as *mut gst::ffi::GstBuffer;
-            glib::ffi::g_slice_free1(
-                mem::size_of::<ffi::RTPJitterBufferItem>(),
-                item.as_ptr() as *mut _,
-            );
-            from_glib_full(buf)
-        }
+    pub fn into_buffer(self) -> gst::Buffer {
+        self.buffer
     }
 
     pub fn dts(&self) -> Option<gst::ClockTime> {
-        unsafe {
-            let item = self.0.as_ref().expect("Invalid wrapper");
-            if item.as_ref().dts == gst::ffi::GST_CLOCK_TIME_NONE {
-                None
-            } else {
-                Some(gst::ClockTime::from_nseconds(item.as_ref().dts))
-            }
-        }
+        self.dts
     }
 
     pub fn pts(&self) -> Option<gst::ClockTime> {
-        unsafe {
-            let item = self.0.as_ref().expect("Invalid wrapper");
-            if item.as_ref().pts == gst::ffi::GST_CLOCK_TIME_NONE {
-                None
-            } else {
-                Some(gst::ClockTime::from_nseconds(item.as_ref().pts))
-            }
-        }
+        self.pts
     }
 
     pub fn seqnum(&self) -> Option<u16> {
-        unsafe {
-            let item = self.0.as_ref().expect("Invalid wrapper");
-            if item.as_ref().seqnum == std::u32::MAX {
-                None
-            } else {
-                Some(item.as_ref().seqnum as u16)
-            }
-        }
+        self.seqnum
     }
 
     #[allow(dead_code)]
     pub fn rtptime(&self) -> u32 {
-        unsafe {
-            let item = self.0.as_ref().expect("Invalid wrapper");
-            item.as_ref().rtptime
-        }
+        self.rtptime
     }
 }
 
-impl Drop for RTPJitterBufferItem {
-    fn drop(&mut self) {
-        unsafe {
-            if let Some(ref item) = self.0 {
-                if !item.as_ref().data.is_null() {
-                    gst::ffi::gst_mini_object_unref(item.as_ref().data as *mut _);
-                }
-
-                glib::ffi::g_slice_free1(
-                    mem::size_of::<ffi::RTPJitterBufferItem>(),
-                    item.as_ptr() as *mut _,
-                );
-            }
-        }
-    }
+#[derive(Debug)]
+pub struct RTPPacketRateCtx {
+    probed: bool,
+    clock_rate: i32,
+    last_seqnum: u16,
+    last_ts: Option<u64>,
+    // u32::MAX as long as no packet rate could be calculated
+    avg_packet_rate: u32,
 }
 
-pub struct RTPPacketRateCtx(Box<ffi::RTPPacketRateCtx>);
-
-unsafe impl Send for RTPPacketRateCtx {}
-
 impl RTPPacketRateCtx {
     pub fn new() -> RTPPacketRateCtx {
-        unsafe {
-            let mut ptr = std::mem::MaybeUninit::uninit();
-            ffi::gst_rtp_packet_rate_ctx_reset(ptr.as_mut_ptr(), -1);
-            RTPPacketRateCtx(Box::new(ptr.assume_init()))
+        RTPPacketRateCtx {
+            probed: false,
+            clock_rate: -1,
+            last_seqnum: 0,
+            last_ts: None,
+            avg_packet_rate: std::u32::MAX,
         }
     }
 
     pub fn reset(&mut self, clock_rate: i32) {
-        unsafe { ffi::gst_rtp_packet_rate_ctx_reset(&mut *self.0, clock_rate) }
+        *self = RTPPacketRateCtx {
+            clock_rate,
+            ..RTPPacketRateCtx::new()
+        };
     }
 
     pub fn update(&mut self, seqnum: u16, ts: u32) -> u32 {
-        unsafe { ffi::gst_rtp_packet_rate_ctx_update(&mut *self.0, seqnum, ts) }
+        if self.clock_rate <= 0 {
+            return self.avg_packet_rate;
+        }
+
+        let mut new_ts = self.last_ts;
+        let ext_ts = ext_timestamp(&mut new_ts, ts);
+
+        if !self.probed {
+            self.probed = true;
+        } else {
+            let diff_seqnum = gst_rtp::compare_seqnum(self.last_seqnum, seqnum);
+            let last_ts = self.last_ts.unwrap_or(0);
+
+            if diff_seqnum == 1 && ext_ts > last_ts {
+                let new_packet_rate = (ext_ts - last_ts)
+                    .mul_div_floor(gst::ClockTime::SECOND.nseconds(), self.clock_rate as u64)
+                    .and_then(|diff_ts| {
+                        (diff_seqnum as u64)
+                            .mul_div_floor(gst::ClockTime::SECOND.nseconds(), diff_ts)
+                    })
+                    .map(|rate| rate as i32);
+
+                // The goal is that higher packet rates "win".
+                // If there's a sudden burst, the average will go up fast,
+                // but it will go down again slowly.
+                // This is useful for bursty cases, where a lot of packets are close
+                // to each other and should allow a higher reorder/dropout there.
+                // Round up the new average.
+                if let Some(new_packet_rate) = new_packet_rate {
+                    self.avg_packet_rate = if self.avg_packet_rate as i32 > new_packet_rate {
+                        self.avg_packet_rate
+                            .wrapping_mul(7)
+                            .wrapping_add(new_packet_rate as u32)
+                            .wrapping_add(7)
+                            / 8
+                    } else {
+                        self.avg_packet_rate
+                            .wrapping_add(new_packet_rate as u32)
+                            .wrapping_add(1)
+                            / 2
+                    };
+                }
+            }
+        }
+
+        self.last_seqnum = seqnum;
+        self.last_ts = Some(ext_ts);
+
+        self.avg_packet_rate
     }
 
     pub fn max_dropout(&mut self, time_ms: i32) -> u32 {
-        unsafe { ffi::gst_rtp_packet_rate_ctx_get_max_dropout(&mut *self.0, time_ms) }
+        if time_ms <= 0 || !self.probed || self.avg_packet_rate == std::u32::MAX {
+            return RTP_DEF_DROPOUT;
+        }
+
+        max(
+            RTP_MIN_DROPOUT,
+            (self.avg_packet_rate as u64 * time_ms as u64 / 1000) as u32,
+        )
     }
 
     #[allow(dead_code)]
     pub fn max_disorder(&mut self, time_ms: i32) -> u32 {
-        unsafe { ffi::gst_rtp_packet_rate_ctx_get_max_disorder(&mut *self.0, time_ms) }
+        if time_ms <= 0 || !self.probed || self.avg_packet_rate == std::u32::MAX {
+            return RTP_DEF_MISORDER;
+        }
+
+        max(
+            RTP_MIN_MISORDER,
+            (self.avg_packet_rate as u64 * time_ms as u64 / 1000) as u32,
+        )
     }
 }
 
@@ -211,158 +247,740 @@ impl Default for RTPPacketRateCtx {
 
 #[derive(Debug, Eq, PartialEq, Ord, PartialOrd, Hash, Clone, Copy)]
 pub enum RTPJitterBufferMode {
+    // Don't do any skew correction, outgoing timestamps are calculated directly
+    // from the RTP timestamps
     r#None,
+    // Calculate the skew between sender and receiver and produce smoothed
+    // adjusted outgoing timestamps
     Slave,
+    // Buffer packets between low/high watermarks
     Buffer,
+    // Sender and receiver clocks are synchronized, like `Slave` but the skew
+    // is assumed to be 0
     Synced,
-    __Unknown(i32),
+}
+
+pub struct RTPJitterBuffer {
+    packets: VecDeque<RTPJitterBufferItem>,
+
+    mode: RTPJitterBufferMode,
+
+    delay: gst::ClockTime,
+
+    // for buffering
+    buffering: bool,
+    low_level: u64,
+    high_level: u64,
+    buffering_disabled: bool,
+
+    // for calculating skew, all times in nanoseconds
+    need_resync: bool,
+    base_time: Option<u64>,
+    base_rtptime: Option<u64>,
+    base_extrtp: Option<u64>,
+    clock_rate: u32,
+    prev_out_time: Option<u64>,
+    ext_rtptime: Option<u64>,
+    last_rtptime: Option<u64>,
+    window: [i64; MAX_WINDOW],
+    window_pos: usize,
+    window_size: usize,
+    window_filling: bool,
+    window_min: i64,
+    skew: i64,
+    prev_send_diff: Option<i64>,
 }
 
 impl RTPJitterBuffer {
     pub fn new() -> RTPJitterBuffer {
-        unsafe { from_glib_full(ffi::rtp_jitter_buffer_new()) }
+        let mut jbuf = RTPJitterBuffer {
+            packets: VecDeque::new(),
+            mode: RTPJitterBufferMode::Slave,
+            delay: gst::ClockTime::ZERO,
+            buffering: false,
+            low_level: 0,
+            high_level: 0,
+            buffering_disabled: false,
+            need_resync: true,
+            base_time: None,
+            base_rtptime: None,
+            base_extrtp: None,
+            clock_rate: 0,
+            prev_out_time: None,
+            ext_rtptime: None,
+            last_rtptime: None,
+            window: [0; MAX_WINDOW],
+            window_pos: 0,
+            window_size: 0,
+            window_filling: true,
+            window_min: 0,
+            skew: 0,
+            prev_send_diff: None,
+        };
+
+        jbuf.reset_skew();
+
+        jbuf
     }
 
     #[allow(dead_code)]
     pub fn mode(&self) -> RTPJitterBufferMode {
-        unsafe { from_glib(ffi::rtp_jitter_buffer_get_mode(self.to_glib_none().0)) }
+        self.mode
     }
 
     #[allow(dead_code)]
-    pub fn set_mode(&self, mode: RTPJitterBufferMode) {
-        unsafe { ffi::rtp_jitter_buffer_set_mode(self.to_glib_none().0, mode.into_glib()) }
+    pub fn set_mode(&mut self, mode: RTPJitterBufferMode) {
+        self.mode = mode;
     }
 
     #[allow(dead_code)]
     pub fn delay(&self) -> gst::ClockTime {
-        unsafe {
-            try_from_glib(ffi::rtp_jitter_buffer_get_delay(self.to_glib_none().0))
-                .expect("undefined delay")
-        }
+        self.delay
     }
 
-    pub fn set_delay(&self, delay: gst::ClockTime) {
-        unsafe { ffi::rtp_jitter_buffer_set_delay(self.to_glib_none().0, delay.into_glib()) }
+    pub fn set_delay(&mut self, delay: gst::ClockTime) {
+        self.delay = delay;
+        self.low_level = delay.nseconds() * 15 / 100;
+        // the high level is at 90% in order to release packets before we fill up the
+        // buffer up to the latency
+        self.high_level = delay.nseconds() * 90 / 100;
+
+        gst_debug!(
+            CAT,
+            "delay {}, min {}, max {}",
+            self.delay,
+            gst::ClockTime::from_nseconds(self.low_level),
+            gst::ClockTime::from_nseconds(self.high_level),
+        );
     }
 
-    pub fn set_clock_rate(&self, clock_rate: u32) {
-        unsafe { ffi::rtp_jitter_buffer_set_clock_rate(self.to_glib_none().0, clock_rate) }
+    pub fn set_clock_rate(&mut self, clock_rate: u32) {
+        if self.clock_rate != clock_rate {
+            gst_debug!(
+                CAT,
+                "Clock rate changed from {} to {}",
+                self.clock_rate,
+                clock_rate
+            );
+            self.clock_rate = clock_rate;
+            self.reset_skew();
+        }
     }
 
     #[allow(dead_code)]
     pub fn clock_rate(&self) -> u32 {
-        unsafe { ffi::rtp_jitter_buffer_get_clock_rate(self.to_glib_none().0) }
+        self.clock_rate
     }
 
-    pub fn calculate_pts(
-        &self,
-        dts: impl Into<Option<gst::ClockTime>>,
-        estimated_dts: bool,
-        rtptime: u32,
-        base_time: impl Into<Option<gst::ClockTime>>,
-        gap: i32,
-        is_rtx: bool,
-    ) -> Option<gst::ClockTime> {
-        unsafe {
-            from_glib(ffi::rtp_jitter_buffer_calculate_pts(
-                self.to_glib_none().0,
-                dts.into().into_glib(),
-                estimated_dts.into_glib(),
-                rtptime,
-                base_time.into().into_glib(),
-                gap,
-                is_rtx.into_glib(),
-            ))
+    #[allow(dead_code)]
+    pub fn set_buffering_disabled(&mut self, disabled: bool) {
+        self.buffering_disabled = disabled;
+    }
+
+    #[allow(dead_code)]
+    pub fn is_buffering(&self) -> bool {
+        self.buffering && !self.buffering_disabled
+    }
+
+    pub fn reset_skew(&mut self) {
+        self.base_time = None;
+        self.base_rtptime = None;
+        self.base_extrtp = None;
+        self.ext_rtptime = None;
+        self.last_rtptime = None;
+        self.window_pos = 0;
+        self.window_filling = true;
+        self.window_min = 0;
+        self.skew = 0;
+        self.prev_send_diff = None;
+        self.prev_out_time = None;
+        self.need_resync = true;
+
+        gst_debug!(CAT, "reset skew correction");
+    }
+
+    fn resync(&mut self, time: u64, gstrtptime: u64, ext_rtptime: u64, reset_skew: bool) {
+        self.base_time = Some(time);
+        self.base_rtptime = Some(gstrtptime);
+        self.base_extrtp = Some(ext_rtptime);
+        self.prev_out_time = None;
+        self.prev_send_diff = None;
+        if reset_skew {
+            self.window_filling = true;
+            self.window_pos = 0;
+            self.window_min = 0;
+            self.window_size = 0;
+            self.skew = 0;
         }
+        self.need_resync = false;
     }
 
-    pub fn insert(&self, mut item: RTPJitterBufferItem) -> (bool, bool, i32) {
-        unsafe {
-            let mut head = mem::MaybeUninit::uninit();
-            let mut percent = mem::MaybeUninit::uninit();
-            let ptr = item.0.take().expect("Invalid wrapper");
-            let ret: bool = from_glib(ffi::rtp_jitter_buffer_insert(
-                self.to_glib_none().0,
-                ptr.as_ptr(),
-                head.as_mut_ptr(),
-                percent.as_mut_ptr(),
-            ));
-            if !ret {
-                item.0 = Some(ptr);
+    fn buffer_level(&self) -> u64 {
+        let item_time = |(idx, item): (usize, &RTPJitterBufferItem)| {
+            item.dts.or(item.pts).map(|time| (idx, time.nseconds()))
+        };
+
+        // first packets with a timestamp from both ends
+        let high = self.packets.iter().enumerate().rev().find_map(item_time);
+        let low = self.packets.iter().enumerate().find_map(item_time);
+
+        match (high, low) {
+            (Some((high_idx, high_ts)), Some((low_idx, low_ts))) if high_idx != low_idx => {
+                let level = high_ts.saturating_sub(low_ts);
+
+                gst_log!(
+                    CAT,
+                    "low {} high {} level {}",
+                    gst::ClockTime::from_nseconds(low_ts),
+                    gst::ClockTime::from_nseconds(high_ts),
+                    level
+                );
+
+                level
             }
-            (ret, from_glib(head.assume_init()), percent.assume_init())
+            _ => 0,
         }
     }
 
-    pub fn find_earliest(&self) -> (Option<gst::ClockTime>, Option<u16>) {
-        unsafe {
-            let mut pts = mem::MaybeUninit::uninit();
-            let mut seqnum = mem::MaybeUninit::uninit();
-
-            ffi::rtp_jitter_buffer_find_earliest(
-                self.to_glib_none().0,
-                pts.as_mut_ptr(),
-                seqnum.as_mut_ptr(),
-            );
-            let pts = from_glib(pts.assume_init());
-            let seqnum = seqnum.assume_init();
+    // Returns the buffering percent if it changed, -1 otherwise
+    fn update_buffer_level(&mut self) -> i32 {
+        let mut level = self.buffer_level();
+        gst_debug!(CAT, "buffer level {}", gst::ClockTime::from_nseconds(level));
+
+        if self.buffering_disabled {
+            gst_debug!(CAT, "buffering is disabled");
+            level = self.high_level;
+        }
+
+        let mut post = false;
+        if self.buffering {
+            post = true;
+            if level >= self.high_level {
+                gst_debug!(CAT, "buffering finished");
+                self.buffering = false;
+            }
+        } else if level < self.low_level {
+            gst_debug!(CAT, "buffering started");
+            self.buffering = true;
+            post = true;
+        }
+
+        if !post {
+            return -1;
+        }
 
-            let seqnum = if seqnum == std::u32::MAX {
-                None
+        let percent = if self.buffering && self.high_level != 0 {
+            min(level * 100 / self.high_level, 100) as i32
+        } else {
+            100
+        };
+
+        gst_debug!(CAT, "buffering {}", percent);
+
+        percent
+    }
+
+    // For the clock skew we use a windowed low point averaging algorithm as can be
+    // found in Fober, Orlarey and Letz, 2005, "Real Time Clock Skew Estimation
+    // over Network Delays":
+    // http://www.grame.fr/Ressources/pub/TR-050601.pdf
+    // http://citeseerx.ist.psu.edu/viewdoc/summary?doi=10.1.1.102.1546
+    //
+    // The idea is that the jitter is composed of:
+    //
+    //  J = N + n
+    //
+    //   N   : a constant network delay.
+    //   n   : random added noise. The noise is concentrated around 0
+    //
+    // In the receiver we can track the elapsed time at the sender with:
+    //
+    //  send_diff(i) = (Tsi - Ts0);
+    //
+    //   Tsi : The time at the sender at packet i
+    //   Ts0 : The time at the sender at the first packet
+    //
+    // This is the difference between the RTP timestamp in the first received packet
+    // and the current packet.
+    //
+    // At the receiver we have to deal with the jitter introduced by the network.
+    //
+    //  recv_diff(i) = (Tri - Tr0)
+    //
+    //   Tri : The time at the receiver at packet i
+    //   Tr0 : The time at the receiver at the first packet
+    //
+    // Both of these values contain a jitter Ji, a jitter for packet i, so we can
+    // write:
+    //
+    //  recv_diff(i) = (Cri + D + ni) - (Cr0 + D + n0))
+    //
+    //    Cri    : The time of the clock at the receiver for packet i
+    //    D + ni : The jitter when receiving packet i
+    //
+    // We see that the network delay is irrelevant here as we can eliminate D:
+    //
+    //  recv_diff(i) = (Cri + ni) - (Cr0 + n0))
+    //
+    // The drift is now expressed as:
+    //
+    //  Drift(i) = recv_diff(i) - send_diff(i);
+    //
+    // We now keep the W latest values of Drift and find the minimum (this is the
+    // one with the lowest network jitter and thus the one which is least affected
+    // by it). We average this lowest value to smooth out the resulting network skew.
+    //
+    // Both the window and the weighting used for averaging influence the accuracy
+    // of the drift estimation. Finding the correct parameters turns out to be a
+    // compromise between accuracy and inertia.
+    //
+    // We use a 2 second window or up to 512 data points, which is statistically big
+    // enough to catch spikes (FIXME, detect spikes).
+    // We also use a rather large weighting factor (125) to smoothly adapt. During
+    // startup, when filling the window, we use a parabolic weighting factor, the
+    // more the window is filled, the faster we move to the detected possible skew.
+    //
+    // Returns: `time` adjusted with the clock skew.
+    fn calculate_skew(
+        &mut self,
+        ext_rtptime: u64,
+        gstrtptime: u64,
+        time: Option<u64>,
+        mut gap: i32,
+        is_rtx: bool,
+    ) -> Option<u64> {
+        // we don't have an arrival timestamp so we can't do skew detection. we
+        // should still apply a timestamp based on RTP timestamp and base_time
+        let (base_time, base_rtptime) = match (self.base_time, self.base_rtptime) {
+            (Some(base_time), Some(base_rtptime)) => (base_time, base_rtptime),
+            _ => {
+                gst_debug!(CAT, "skew {}, no base time", self.skew);
+                return None;
+            }
+        };
+
+        // elapsed time at sender
+        let mut send_diff = gstrtptime.wrapping_sub(base_rtptime);
+
+        if let Some(time) = time.filter(|_| !is_rtx) {
+            // elapsed time at receiver, includes the jitter
+            let recv_diff = time.wrapping_sub(base_time);
+
+            // measure the diff
+            let mut delta = (recv_diff as i64).wrapping_sub(send_diff as i64);
+
+            // measure the slope, this gives a rough estimate between the sender speed
+            // and the receiver speed. This should be approximately 8, higher values
+            // indicate a burst (especially when the connection starts)
+            let slope = if recv_diff > 0 {
+                send_diff.wrapping_mul(8) / recv_diff
             } else {
-                Some(seqnum as u16)
+                8
             };
 
-            (pts, seqnum)
+            gst_debug!(
+                CAT,
+                "time {}, base {}, recv_diff {}, slope {}",
+                time,
+                base_time,
+                recv_diff,
+                slope
+            );
+
+            // if the difference between the sender timeline and the receiver timeline
+            // changed too quickly we have to resync because the server likely restarted
+            // its timestamps.
+            if delta.wrapping_sub(self.skew).unsigned_abs() > gst::ClockTime::SECOND.nseconds() {
+                gst_warning!(
+                    CAT,
+                    "delta - skew: {} too big, reset skew",
+                    delta.wrapping_sub(self.skew).unsigned_abs()
+                );
+                self.resync(time, gstrtptime, ext_rtptime, true);
+                send_diff = 0;
+                delta = 0;
+                gap = 0;
+            }
+
+            // only do skew calculations if we didn't have a gap. if too much time
+            // has elapsed despite there being a gap, we resynced already.
+            if gap == 0 {
+                self.update_skew(delta, send_diff);
+            }
         }
+
+        // the output time is defined as the base timestamp plus the RTP time
+        // adjusted for the clock skew.
+        let out_time = self.base_time.unwrap().wrapping_add(send_diff);
+        // skew can be negative and we don't want to make invalid timestamps
+        let out_time = if self.skew < 0 && out_time < self.skew.unsigned_abs() {
+            0
+        } else {
+            out_time.wrapping_add(self.skew as u64)
+        };
+
+        gst_debug!(CAT, "skew {}, out {}", self.skew, out_time);
+
+        Some(out_time)
     }
 
-    pub fn pop(&self) -> (Option<RTPJitterBufferItem>, i32) {
-        unsafe {
-            let mut percent = mem::MaybeUninit::uninit();
-            let item = ffi::rtp_jitter_buffer_pop(self.to_glib_none().0, percent.as_mut_ptr());
+    fn update_skew(&mut self, delta: i64, send_diff: u64) {
+        let mut pos = self.window_pos;
 
-            (
-                if item.is_null() {
-                    None
-                } else {
-                    Some(RTPJitterBufferItem(Some(ptr::NonNull::new_unchecked(item))))
-                },
-                percent.assume_init(),
-            )
+        if self.window_filling {
+            // we are filling the window
+            gst_debug!(CAT, "filling {}, delta {}", pos, delta);
+            self.window[pos] = delta;
+            pos += 1;
+            // calc the min delta we observed
+            if pos == 1 || delta < self.window_min {
+                self.window_min = delta;
+            }
+
+            if send_diff >= MAX_TIME.nseconds() || pos >= MAX_WINDOW {
+                self.window_size = pos;
+
+                // window filled
+                gst_debug!(CAT, "min {}", self.window_min);
+
+                // the skew is now the min
+                self.skew = self.window_min;
+                self.window_filling = false;
+            } else {
+                // figure out how much we filled the window, this depends on the amount of
+                // time we have or the max number of points we keep.
+                let perc_time = (send_diff * 100 / MAX_TIME.nseconds()) as i64;
+                let perc_window = (pos * 100 / MAX_WINDOW) as i64;
+                let perc = max(perc_time, perc_window);
+
+                // make a parabolic function, the closer we get to the MAX, the more value
+                // we give to the scaling factor of the new value
+                let perc = perc * perc;
+
+                // quickly go to the min value when we are filling up, slowly when we are
+                // just starting because we're not sure it's a good value yet.
+                self.skew = (perc * self.window_min + ((10000 - perc) * self.skew)) / 10000;
+                self.window_size = pos + 1;
+            }
+        } else {
+            // pick old value and store new value. We keep the previous value in order
+            // to quickly check if the min of the window changed
+            let old = self.window[pos];
+            self.window[pos] = delta;
+            pos += 1;
+
+            if delta <= self.window_min {
+                // if the new value we inserted is smaller or equal to the current min,
+                // it becomes the new min
+                self.window_min = delta;
+            } else if old == self.window_min {
+                // if we removed the old min, we have to find a new min
+                let mut min = std::i64::MAX;
+                for &value in &self.window[..self.window_size] {
+                    // we found another value equal to the old min, we can stop searching now
+                    if value == old {
+                        min = old;
+                        break;
+                    }
+                    if value < min {
+                        min = value;
+                    }
+                }
+                self.window_min = min;
+            }
+            // average the min values
+            self.skew = (self.window_min + (124 * self.skew)) / 125;
+            gst_debug!(CAT, "delta {}, new min: {}", delta, self.window_min);
+        }
+
+        // wrap around in the window
+        if pos >= self.window_size {
+            pos = 0;
         }
+        self.window_pos = pos;
     }
 
-    pub fn peek(&self) -> (Option<gst::ClockTime>, Option<u16>) {
-        unsafe {
-            let item = ffi::rtp_jitter_buffer_peek(self.to_glib_none().0);
-            if item.is_null() {
-                (None, None)
-            } else {
-                let seqnum = (*item).seqnum;
-                let seqnum = if seqnum == std::u32::MAX {
-                    None
+    pub fn calculate_pts(
+        &mut self,
+        dts: impl Into<Option<gst::ClockTime>>,
+        estimated_dts: bool,
+        rtptime: u32,
+        _base_time: impl Into<Option<gst::ClockTime>>,
+        gap: i32,
+        is_rtx: bool,
+    ) -> Option<gst::ClockTime> {
+        let mut dts = dts.into().map(gst::ClockTime::nseconds);
+        let clock_rate = self.clock_rate as u64;
+
+        // rtp time jumps are checked for during skew calculation, but bypassed
+        // in other mode, so mind those here and reset jb if needed.
+        // Only reset if valid input time, which is likely for UDP input
+        // where we expect this might happen due to async thread effects
+        // (in seek and state change cycles), but not so much for TCP input
+        if dts.is_some()
+            && !estimated_dts
+            && self.mode != RTPJitterBufferMode::Slave
+            && self.base_time.is_some()
+        {
+            if let Some(last_rtptime) = self.last_rtptime {
+                let mut ext_rtptime = self.ext_rtptime;
+                let ext_rtptime = ext_timestamp(&mut ext_rtptime, rtptime);
+
+                if ext_rtptime > last_rtptime + 3 * clock_rate
+                    || ext_rtptime + 3 * clock_rate < last_rtptime
+                {
+                    if !is_rtx {
+                        // reset even if we don't have valid incoming time;
+                        // still better than producing possibly very bogus output timestamp
+                        gst_warning!(CAT, "rtp delta too big, reset skew");
+                        self.reset_skew();
+                    } else {
+                        gst_warning!(CAT, "rtp delta too big: ignore rtx packet");
+                        return None;
+                    }
+                }
+            }
+        }
+
+        // Return the last time if we got the same RTP timestamp again
+        let ext_rtptime = ext_timestamp(&mut self.ext_rtptime, rtptime);
+        if self.last_rtptime == Some(ext_rtptime) {
+            return self.prev_out_time.and_then(clock_time);
+        }
+
+        // keep track of the last extended rtptime
+        self.last_rtptime = Some(ext_rtptime);
+
+        let gstrtptime =
+            match ext_rtptime.mul_div_floor(gst::ClockTime::SECOND.nseconds(), clock_rate) {
+                Some(gstrtptime) => gstrtptime,
+                None => {
+                    gst_warning!(CAT, "no clock rate, can't calculate pts");
+                    return None;
+                }
+            };
+
+        if let Some(base_rtptime) = self.base_rtptime {
+            // check elapsed time in RTP units
+            if gstrtptime < base_rtptime {
+                if !is_rtx {
+                    // elapsed time at sender, timestamps can go backwards and thus be
+                    // smaller than our base time, schedule to take a new base time in
+                    // that case.
+                    gst_warning!(CAT, "backward timestamps at server, schedule resync");
+                    self.need_resync = true;
+                } else {
+                    gst_warning!(CAT, "backward timestamps: ignore rtx packet");
+                    return None;
+                }
+            }
+        }
+
+        match self.mode {
+            RTPJitterBufferMode::None | RTPJitterBufferMode::Buffer => {
+                // send 0 as the first timestamp and None for the other ones. This will
+                // interpolate them from the RTP timestamps with a 0 origin. In buffering
+                // mode we will adjust the outgoing timestamps according to the amount of
+                // time we spent buffering.
+                dts = if self.base_time.is_none() {
+                    Some(0)
                 } else {
-                    Some(seqnum as u16)
+                    None
                 };
-                (from_glib((*item).pts), seqnum)
+            }
+            RTPJitterBufferMode::Synced => {
+                // synchronized clocks, take first timestamp as base, use RTP timestamps
+                // to interpolate
+                if self.base_time.is_some() && !self.need_resync {
+                    dts = None;
+                }
+            }
+            RTPJitterBufferMode::Slave => (),
+        }
+
+        // need resync, lock on to time and gstrtptime if we can, otherwise we
+        // do with the previous values
+        if self.need_resync {
+            if let Some(dts) = dts {
+                if is_rtx {
+                    gst_debug!(CAT, "not resyncing on rtx packet, discard");
+                    return None;
+                }
+
+                gst_info!(
+                    CAT,
+                    "resync to time {}, rtptime {}",
+                    gst::ClockTime::from_nseconds(dts),
+                    gst::ClockTime::from_nseconds(gstrtptime)
+                );
+                self.resync(dts, gstrtptime, ext_rtptime, false);
+            }
+        }
+
+        gst_debug!(
+            CAT,
+            "extrtp {}, gstrtp {}, base {:?}",
+            ext_rtptime,
+            gst::ClockTime::from_nseconds(gstrtptime),
+            self.base_rtptime,
+        );
+
+        // do skew calculation by measuring the difference between rtptime and the
+        // receive dts, this function will return the skew corrected rtptime.
+        let mut pts = self.calculate_skew(ext_rtptime, gstrtptime, dts, gap, is_rtx);
+
+        let send_diff = self
+            .base_rtptime
+            .map(|base_rtptime| gstrtptime.wrapping_sub(base_rtptime) as i64);
+
+        // check if timestamps are not going backwards, we can only check this if we
+        // have a previous out time and a previous send_diff
+        if let (Some(out_time), Some(prev_out_time), Some(prev_send_diff), Some(send_diff)) =
+            (pts, self.prev_out_time, self.prev_send_diff, send_diff)
+        {
+            // now check for backwards timestamps
+            // if the server timestamps went up and the out_time backwards
+            if (send_diff > prev_send_diff && out_time < prev_out_time)
+                // if the server timestamps went backwards and the out_time forwards
+                || (send_diff < prev_send_diff && out_time > prev_out_time)
+                // if the server timestamps did not change
+                || send_diff == prev_send_diff
+            {
+                gst_debug!(CAT, "backwards timestamps, using previous time");
+                pts = Some(prev_out_time);
+            }
+        }
+
+        if gap == 0 {
+            if let (Some(dts), Some(out_time)) = (dts, pts) {
+                if out_time.wrapping_add(self.delay.nseconds()) < dts {
+                    // if we are going to produce a timestamp that is later than the input
+                    // timestamp, we need to reset the jitterbuffer. Likely the server paused
+                    // temporarily
+                    gst_debug!(
+                        CAT,
+                        "out {} + {} < time {}, reset jitterbuffer and discard",
+                        out_time,
+                        self.delay,
+                        dts
+                    );
+                    self.reset_skew();
+                    return None;
+                }
             }
         }
+
+        self.prev_out_time = pts;
+        self.prev_send_diff = send_diff;
+
+        pts.and_then(clock_time)
+    }
+
+    // Inserts `item` sorted by seqnum, items without seqnum are appended.
+    //
+    // Returns whether the item was inserted, i.e. it's not a duplicate, whether
+    // it was inserted at the head of the queue and the buffering percent.
+    pub fn insert(&mut self, item: RTPJitterBufferItem) -> (bool, bool, i32) {
+        let prev = match item.seqnum {
+            // no seqnum, simply append then
+            None => self.packets.len().checked_sub(1),
+            Some(seqnum) => {
+                let mut prev = None;
+                let mut event = None;
+
+                // loop the list to skip strictly larger seqnum buffers
+                for (idx, qitem) in self.packets.iter().enumerate().rev() {
+                    let qseq = match qitem.seqnum {
+                        Some(qseq) => qseq,
+                        None => {
+                            // keep a pointer to the first consecutive event if not already
+                            // set. we will insert the packet after the event if we can't find
+                            // a packet with lower sequence number before the event.
+                            if event.is_none() {
+                                event = Some(idx);
+                            }
+                            continue;
+                        }
+                    };
+
+                    // compare the new seqnum to the one in the buffer
+                    let gap = gst_rtp::compare_seqnum(seqnum, qseq);
+
+                    // we hit a packet with the same seqnum, notify a duplicate
+                    if gap == 0 {
+                        gst_debug!(CAT, "duplicate packet {} found", seqnum);
+                        return (false, false, -1);
+                    }
+
+                    // seqnum > qseq, we can stop looking
+                    if gap < 0 {
+                        prev = Some(idx);
+                        break;
+                    }
+
+                    // if we've found a packet with greater sequence number, cleanup the
+                    // event pointer as the packet will be inserted before the event
+                    event = None;
+                }
+
+                // if event is set it means that packets before the event had smaller
+                // sequence number, so we will insert our packet after the event
+                event.or(prev)
+            }
+        };
+
+        self.packets.insert(prev.map_or(0, |prev| prev + 1), item);
+
+        // buffering mode, update buffer stats
+        let percent = if self.mode == RTPJitterBufferMode::Buffer {
+            self.update_buffer_level()
+        } else {
+            -1
+        };
+
+        // head was changed when we did not find a previous packet
+        (true, prev.is_none(), percent)
     }
 
-    pub fn flush(&self) {
-        unsafe extern "C" fn free_item(item: glib::ffi::gpointer, _: glib::ffi::gpointer) {
-            let _ =
-                RTPJitterBufferItem(Some(ptr::NonNull::new(item as *mut _).expect("NULL item")));
+    pub fn find_earliest(&self) -> (Option<gst::ClockTime>, Option<u16>) {
+        // items without pts are considered to be the latest ones
+        let pts = |item: &RTPJitterBufferItem| item.pts.map_or(std::u64::MAX, |pts| pts.nseconds());
+
+        let mut earliest: Option<&RTPJitterBufferItem> = None;
+        for item in &self.packets {
+            if earliest.map_or(true, |earliest| pts(item) <= pts(earliest)) {
+                earliest = Some(item);
+            }
+        }
+
+        match earliest {
+            Some(earliest) => (earliest.pts, earliest.seqnum),
+            None => (None, None),
         }
+    }
+
+    pub fn pop(&mut self) -> (Option<RTPJitterBufferItem>, i32) {
+        let item = self.packets.pop_front();
 
-        unsafe {
-            ffi::rtp_jitter_buffer_flush(self.to_glib_none().0, Some(free_item));
+        // buffering mode, update buffer stats
+        let percent = if self.mode == RTPJitterBufferMode::Buffer {
+            self.update_buffer_level()
+        } else {
+            -1
+        };
+
+        (item, percent)
+    }
+
+    pub fn peek(&self) -> (Option<gst::ClockTime>, Option<u16>) {
+        match self.packets.front() {
+            Some(item) => (item.pts, item.seqnum),
+            None => (None, None),
         }
     }
 
-    pub fn reset_skew(&self) {
-        unsafe { ffi::rtp_jitter_buffer_reset_skew(self.to_glib_none().0) }
+    pub fn flush(&mut self) {
+        self.packets.clear();
     }
 }
 
@@ -371,3 +989,451 @@ impl Default for RTPJitterBuffer {
         RTPJitterBuffer::new()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CLOCK_RATE: u32 = 8000;
+    // 20ms of audio
+    const SAMPLES_PER_PACKET: u32 = 160;
+    const PACKET_DURATION: gst::ClockTime = gst::ClockTime::from_mseconds(20);
+
+    fn init() {
+        use std::sync::Once;
+        static INIT: Once = Once::new();
+
+        INIT.call_once(|| {
+            gst::init().unwrap();
+        });
+    }
+
+    fn jitterbuffer(mode: RTPJitterBufferMode) -> RTPJitterBuffer {
+        let mut jbuf = RTPJitterBuffer::new();
+        jbuf.set_mode(mode);
+        jbuf.set_clock_rate(CLOCK_RATE);
+        jbuf.set_delay(gst::ClockTime::from_mseconds(200));
+
+        jbuf
+    }
+
+    // Replays a trace of (arrival time, rtptime) and returns the calculated PTS
+    fn replay(
+        jbuf: &mut RTPJitterBuffer,
+        trace: impl IntoIterator<Item = (gst::ClockTime, u32)>,
+    ) -> Vec<Option<gst::ClockTime>> {
+        trace
+            .into_iter()
+            .map(|(dts, rtptime)| {
+                jbuf.calculate_pts(dts, false, rtptime, gst::ClockTime::ZERO, 0, false)
+            })
+            .collect()
+    }
+
+    fn item(seqnum: Option<u16>, pts: impl Into<Option<gst::ClockTime>>) -> RTPJitterBufferItem {
+        RTPJitterBufferItem::new(gst::Buffer::new(), gst::ClockTime::NONE, pts, seqnum, 0)
+    }
+
+    #[test]
+    fn ext_timestamp_wraparound() {
+        let mut ext = None;
+
+        assert_eq!(ext_timestamp(&mut ext, 0xffff_ff00), 0xffff_ff00);
+        assert_eq!(ext_timestamp(&mut ext, 0x0000_0010), 0x1_0000_0010);
+        // slightly reordered packet from before the wraparound
+        assert_eq!(ext_timestamp(&mut ext, 0xffff_fff0), 0xffff_fff0);
+        assert_eq!(ext_timestamp(&mut ext, 0x0000_0020), 0x1_0000_0020);
+    }
+
+    #[test]
+    fn packet_rate() {
+        let mut ctx = RTPPacketRateCtx::new();
+
+        // no clock rate
+        assert_eq!(ctx.update(0, 0), std::u32::MAX);
+        assert_eq!(ctx.max_dropout(60000), RTP_DEF_DROPOUT);
+        assert_eq!(ctx.max_disorder(2000), RTP_DEF_MISORDER);
+
+        ctx.reset(CLOCK_RATE as i32);
+        for seq in 0..100u16 {
+            ctx.update(seq, seq as u32 * SAMPLES_PER_PACKET);
+        }
+
+        // 50 packets per second
+        assert_eq!(ctx.update(100, 100 * SAMPLES_PER_PACKET), 50);
+        assert_eq!(ctx.max_dropout(60000), 3000);
+        assert_eq!(ctx.max_dropout(100), RTP_MIN_DROPOUT);
+        assert_eq!(ctx.max_disorder(2000), 100);
+        assert_eq!(ctx.max_disorder(100), RTP_MIN_MISORDER);
+
+        // gaps and reordered packets are not taken into account
+        assert_eq!(ctx.update(110, 110 * SAMPLES_PER_PACKET), 50);
+        assert_eq!(ctx.update(105, 105 * SAMPLES_PER_PACKET), 50);
+    }
+
+    #[test]
+    fn slave_regular_arrival() {
+        init();
+
+        let mut jbuf = jitterbuffer(RTPJitterBufferMode::Slave);
+        let base = gst::ClockTime::from_mseconds(10);
+
+        let trace =
+            (0..200u32).map(|i| (base + i as u64 * PACKET_DURATION, i * SAMPLES_PER_PACKET));
+        for (i, pts) in replay(&mut jbuf, trace).into_iter().enumerate() {
+            assert_eq!(pts, Some(base + i as u64 * PACKET_DURATION));
+        }
+    }
+
+    #[test]
+    fn slave_jitter() {
+        init();
+
+        let mut jbuf = jitterbuffer(RTPJitterBufferMode::Slave);
+        let base = gst::ClockTime::from_mseconds(10);
+
+        // network jitter of up to 7ms, the first packet arrived without jitter
+        let jitter = [0, 3, 7, 1, 5, 2, 6, 0, 4];
+        let trace = (0..500u32).map(|i| {
+            let jitter = gst::ClockTime::from_mseconds(jitter[i as usize % jitter.len()]);
+            (
+                base + i as u64 * PACKET_DURATION + jitter,
+                i * SAMPLES_PER_PACKET,
+            )
+        });
+
+        // the jitter is removed from the output timestamps
+        for (i, pts) in replay(&mut jbuf, trace).into_iter().enumerate() {
+            assert_eq!(pts, Some(base + i as u64 * PACKET_DURATION));
+        }
+    }
+
+    #[test]
+    fn slave_constant_delay() {
+        init();
+
+        let mut jbuf = jitterbuffer(RTPJitterBufferMode::Slave);
+        let base = gst::ClockTime::from_mseconds(10);
+        let delay = gst::ClockTime::from_mseconds(5);
+
+        // all packets after the first one are delayed by the same amount,
+        // the skew converges to this delay once the window is filled
+        let trace = (0..300u32).map(|i| {
+            let delay = if i == 0 { gst::ClockTime::ZERO } else { delay };
+            (
+                base + i as u64 * PACKET_DURATION + delay,
+                i * SAMPLES_PER_PACKET,
+            )
+        });
+        let pts = replay(&mut jbuf, trace);
+
+        // skew is 0 while filling the window as the first packet had no delay
+        assert_eq!(pts[1], Some(base + PACKET_DURATION));
+
+        let mut prev_pts = gst::ClockTime::ZERO;
+        for pts in &pts {
+            let pts = pts.unwrap();
+            assert!(pts > prev_pts);
+            prev_pts = pts;
+        }
+    }
+
+    #[test]
+    fn slave_rtptime_wraparound() {
+        init();
+
+        let mut jbuf = jitterbuffer(RTPJitterBufferMode::Slave);
+        let base = gst::ClockTime::from_seconds(1);
+        let start = std::u32::MAX - 10 * SAMPLES_PER_PACKET;
+
+        let trace = (0..20u32).map(|i| {
+            (
+                base + i as u64 * PACKET_DURATION,
+                start.wrapping_add(i * SAMPLES_PER_PACKET),
+            )
+        });
+        for (i, pts) in replay(&mut jbuf, trace).into_iter().enumerate() {
+            assert_eq!(pts, Some(base + i as u64 * PACKET_DURATION));
+        }
+    }
+
+    #[test]
+    fn same_rtptime() {
+        init();
+
+        let mut jbuf = jitterbuffer(RTPJitterBufferMode::Slave);
+        jbuf.set_clock_rate(90_000);
+
+        // without arrival time there is no base time to calculate the PTS from
+        assert_eq!(
+            jbuf.calculate_pts(
+                gst::ClockTime::NONE,
+                false,
+                0,
+                gst::ClockTime::ZERO,
+                0,
+                false
+            ),
+            None
+        );
+
+        // packets of the same video frame get the same PTS
+        let trace = [
+            (gst::ClockTime::from_mseconds(10), 900),
+            (gst::ClockTime::from_mseconds(11), 900),
+            (gst::ClockTime::from_mseconds(40), 3600),
+            (gst::ClockTime::from_mseconds(45), 3600),
+        ];
+        assert_eq!(
+            replay(&mut jbuf, trace),
+            vec![
+                Some(gst::ClockTime::from_mseconds(10)),
+                Some(gst::ClockTime::from_mseconds(10)),
+                Some(gst::ClockTime::from_mseconds(40)),
+                Some(gst::ClockTime::from_mseconds(40)),
+            ]
+        );
+    }
+
+    #[test]
+    fn slave_resync_on_rtptime_jump() {
+        init();
+
+        let mut jbuf = jitterbuffer(RTPJitterBufferMode::Slave);
+
+        let mut trace = (0..10u32)
+            .map(|i| (i as u64 * PACKET_DURATION, i * SAMPLES_PER_PACKET))
+            .collect::<Vec<_>>();
+        // sender restarted its timestamps 10s in the future
+        let jump = 10 * CLOCK_RATE;
+        trace.extend(
+            (10..20u32).map(|i| (i as u64 * PACKET_DURATION, jump + i * SAMPLES_PER_PACKET)),
+        );
+
+        for (i, pts) in replay(&mut jbuf, trace).into_iter().enumerate() {
+            assert_eq!(pts, Some(i as u64 * PACKET_DURATION));
+        }
+    }
+
+    #[test]
+    fn slave_reset_on_late_arrival() {
+        init();
+
+        let mut jbuf = jitterbuffer(RTPJitterBufferMode::Slave);
+
+        let pts = replay(
+            &mut jbuf,
+            [
+                (gst::ClockTime::ZERO, 0),
+                (PACKET_DURATION, SAMPLES_PER_PACKET),
+                // sender paused for 500ms: more than the delay of the jitterbuffer
+                (gst::ClockTime::from_mseconds(540), 2 * SAMPLES_PER_PACKET),
+                (gst::ClockTime::from_mseconds(560), 3 * SAMPLES_PER_PACKET),
+            ],
+        );
+
+        assert_eq!(
+            pts,
+            vec![
+                Some(gst::ClockTime::ZERO),
+                Some(PACKET_DURATION),
+                None,
+                Some(gst::ClockTime::from_mseconds(560)),
+            ]
+        );
+    }
+
+    #[test]
+    fn rtx_does_not_resync() {
+        init();
+
+        let mut jbuf = jitterbuffer(RTPJitterBufferMode::Slave);
+
+        // no base time yet
+        assert_eq!(
+            jbuf.calculate_pts(
+                gst::ClockTime::ZERO,
+                false,
+                0,
+                gst::ClockTime::ZERO,
+                0,
+                true
+            ),
+            None
+        );
+
+        replay(
+            &mut jbuf,
+            [
+                (gst::ClockTime::ZERO, 0),
+                (PACKET_DURATION, SAMPLES_PER_PACKET),
+            ],
+        );
+
+        // retransmitted packet arriving late keeps its RTP based timestamp
+        assert_eq!(
+            jbuf.calculate_pts(
+                gst::ClockTime::from_mseconds(100),
+                false,
+                2 * SAMPLES_PER_PACKET,
+                gst::ClockTime::ZERO,
+                0,
+                true
+            ),
+            Some(2 * PACKET_DURATION)
+        );
+    }
+
+    #[test]
+    fn none_mode() {
+        init();
+
+        let mut jbuf = jitterbuffer(RTPJitterBufferMode::None);
+
+        // the arrival times are ignored, timestamps start at 0
+        let trace = [
+            (gst::ClockTime::from_seconds(5), 1000),
+            (
+                gst::ClockTime::from_seconds(5) + 3 * PACKET_DURATION,
+                1000 + SAMPLES_PER_PACKET,
+            ),
+            (
+                gst::ClockTime::from_seconds(5),
+                1000 + 2 * SAMPLES_PER_PACKET,
+            ),
+        ];
+        assert_eq!(
+            replay(&mut jbuf, trace),
+            vec![
+                Some(gst::ClockTime::ZERO),
+                Some(PACKET_DURATION),
+                Some(2 * PACKET_DURATION),
+            ]
+        );
+    }
+
+    #[test]
+    fn synced_mode() {
+        init();
+
+        let mut jbuf = jitterbuffer(RTPJitterBufferMode::Synced);
+        let base = gst::ClockTime::from_seconds(1);
+
+        // the first arrival time is the base, the following ones are
+        // interpolated from the RTP timestamps
+        let trace = [
+            (base, 0),
+            (
+                base + PACKET_DURATION + gst::ClockTime::from_mseconds(15),
+                SAMPLES_PER_PACKET,
+            ),
+            (base + 2 * PACKET_DURATION, 2 * SAMPLES_PER_PACKET),
+        ];
+        assert_eq!(
+            replay(&mut jbuf, trace),
+            vec![
+                Some(base),
+                Some(base + PACKET_DURATION),
+                Some(base + 2 * PACKET_DURATION),
+            ]
+        );
+    }
+
+    #[test]
+    fn buffer_mode_percent() {
+        init();
+
+        let mut jbuf = jitterbuffer(RTPJitterBufferMode::Buffer);
+        jbuf.set_delay(gst::ClockTime::from_mseconds(100));
+
+        let mut insert = |seqnum: u16| {
+            let dts = seqnum as u64 * PACKET_DURATION;
+            jbuf.insert(RTPJitterBufferItem::new(
+                gst::Buffer::new(),
+                dts,
+                gst::ClockTime::NONE,
+                Some(seqnum),
+                0,
+            ))
+        };
+
+        // below the low watermark: buffering starts
+        assert_eq!(insert(0), (true, true, 0));
+        assert_eq!(insert(1), (true, false, 22));
+        assert_eq!(insert(2), (true, false, 44));
+        assert_eq!(insert(3), (true, false, 66));
+        assert_eq!(insert(4), (true, false, 88));
+        // high watermark reached: buffering finished
+        assert_eq!(insert(5), (true, false, 100));
+        assert_eq!(insert(6), (true, false, -1));
+
+        assert!(!jbuf.is_buffering());
+        for _ in 0..6 {
+            let (item, _) = jbuf.pop();
+            assert!(item.is_some());
+        }
+        assert!(jbuf.is_buffering());
+    }
+
+    #[test]
+    fn insert_order() {
+        init();
+
+        let mut jbuf = jitterbuffer(RTPJitterBufferMode::Slave);
+
+        let ms = gst::ClockTime::from_mseconds;
+
+        assert_eq!(jbuf.insert(item(Some(2), ms(40))), (true, true, -1));
+        assert_eq!(jbuf.insert(item(Some(4), ms(80))), (true, false, -1));
+        assert_eq!(jbuf.insert(item(Some(3), ms(60))), (true, false, -1));
+        // duplicate
+        assert_eq!(jbuf.insert(item(Some(3), ms(60))), (false, false, -1));
+        // items without seqnum are appended
+        assert_eq!(
+            jbuf.insert(item(None, gst::ClockTime::NONE)),
+            (true, false, -1)
+        );
+        assert_eq!(jbuf.insert(item(Some(1), ms(20))), (true, true, -1));
+        // seqnum wraparound
+        assert_eq!(jbuf.insert(item(Some(0xffff), ms(0))), (true, true, -1));
+
+        assert_eq!(jbuf.find_earliest(), (Some(ms(0)), Some(0xffff)));
+        assert_eq!(jbuf.peek(), (Some(ms(0)), Some(0xffff)));
+
+        let mut seqnums = vec![];
+        while let (Some(item), _) = jbuf.pop() {
+            seqnums.push(item.seqnum());
+        }
+        assert_eq!(
+            seqnums,
+            vec![Some(0xffff), Some(1), Some(2), Some(3), Some(4), None]
+        );
+
+        assert_eq!(jbuf.peek(), (None, None));
+        assert_eq!(jbuf.find_earliest(), (None, None));
+    }
+
+    #[test]
+    fn insert_after_event() {
+        init();
+
+        let mut jbuf = jitterbuffer(RTPJitterBufferMode::Slave);
+
+        jbuf.insert(item(Some(1), gst::ClockTime::NONE));
+        jbuf.insert(item(None, gst::ClockTime::NONE));
+        jbuf.insert(item(Some(3), gst::ClockTime::NONE));
+        // goes after the event as the packet before it has a lower seqnum
+        jbuf.insert(item(Some(2), gst::ClockTime::NONE));
+        assert_eq!(jbuf.find_earliest(), (None, Some(3)));
+
+        let mut seqnums = vec![];
+        while let (Some(item), _) = jbuf.pop() {
+            seqnums.push(item.seqnum());
+        }
+        assert_eq!(seqnums, vec![Some(1), None, Some(2), Some(3)]);
+
+        jbuf.insert(item(Some(5), gst::ClockTime::NONE));
+        jbuf.flush();
+        assert_eq!(jbuf.peek(), (None, None));
+    }
+}
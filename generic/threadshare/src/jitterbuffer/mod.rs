@@ -18,7 +18,6 @@
 use gst::glib;
 use gst::prelude::*;
 
-mod ffi;
 mod imp;
 #[allow(clippy::module_inception)]
 pub mod jitterbuffer;
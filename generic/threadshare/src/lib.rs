@@ -11,15 +11,21 @@ pub mod runtime;
 
 pub mod socket;
 mod tcpclientsrc;
+mod tcpserversink;
+mod tcpserversrc;
 mod udpsink;
 mod udpsrc;
 
 mod appsrc;
+mod audiotestsrc;
 pub mod dataqueue;
+mod filesink;
+mod filesrc;
 mod inputselector;
 mod jitterbuffer;
 mod proxy;
 mod queue;
+mod videotestsrc;
 
 use glib::translate::*;
 use gst::glib;
@@ -28,9 +34,15 @@ fn plugin_init(plugin: &gst::Plugin) -> Result<(), glib::BoolError> {
     udpsrc::register(plugin)?;
     udpsink::register(plugin)?;
     tcpclientsrc::register(plugin)?;
+    tcpserversink::register(plugin)?;
+    tcpserversrc::register(plugin)?;
     queue::register(plugin)?;
     proxy::register(plugin)?;
     appsrc::register(plugin)?;
+    audiotestsrc::register(plugin)?;
+    videotestsrc::register(plugin)?;
+    filesrc::register(plugin)?;
+    filesink::register(plugin)?;
     jitterbuffer::register(plugin)?;
     inputselector::register(plugin)?;
 
@@ -0,0 +1,65 @@
+// Copyright (C) 2026 agent <agent@local>
+//
+// Take a look at the license at the top of the repository in the LICENSE file.
+
+//! Offloading of blocking operations.
+//!
+//! Some operations, such as file I/O, can't be polled for readiness and would block the
+//! [`Context`] thread, and thus all the `Element`s sharing it, while they are executed.
+//! [`unblock`] executes such operations on a pool of threads dedicated to blocking
+//! operations and returns a `Future` which can be awaited from a [`Context`].
+//!
+//! [`Context`]: ../struct.Context.html
+
+use futures::executor::ThreadPool;
+use futures::future::RemoteHandle;
+use futures::task::SpawnExt;
+
+use once_cell::sync::Lazy;
+
+static BLOCKING_POOL: Lazy<ThreadPool> = Lazy::new(|| {
+    ThreadPool::builder()
+        .name_prefix("ts-blocking-")
+        .create()
+        .expect("Failed to create the blocking thread pool")
+});
+
+/// Executes the blocking function `f` on the blocking thread pool.
+///
+/// The returned `Future` resolves to the output of `f`. Dropping it before `f`
+/// started prevents `f` from being executed. Once started, `f` runs to completion
+/// and its output is discarded.
+pub fn unblock<T, F>(f: F) -> RemoteHandle<T>
+where
+    F: FnOnce() -> T + Send + 'static,
+    T: Send + 'static,
+{
+    BLOCKING_POOL
+        .spawn_with_handle(async move { f() })
+        .expect("Failed to spawn on the blocking thread pool")
+}
+
+#[cfg(test)]
+mod tests {
+    use std::thread;
+    use std::time::Duration;
+
+    use super::unblock;
+    use crate::runtime::Context;
+
+    #[test]
+    fn unblock_from_context() {
+        gst::init().unwrap();
+
+        let context = Context::acquire("unblock_from_context", Duration::from_millis(2)).unwrap();
+        let res = futures::executor::block_on(context.spawn(async {
+            let context_thread = thread::current().id();
+            let blocking_thread = unblock(|| thread::current().id()).await;
+
+            context_thread != blocking_thread
+        }))
+        .unwrap();
+
+        assert!(res);
+    }
+}
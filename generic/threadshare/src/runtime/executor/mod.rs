@@ -23,6 +23,9 @@
 pub mod async_wrapper;
 pub use async_wrapper::Async;
 
+pub mod blocking;
+pub use blocking::unblock;
+
 mod context;
 pub use context::{block_on, block_on_or_add_sub_task, yield_now, Context};
 
@@ -31,7 +31,7 @@
 //! [`PadSink`]: pad/struct.PadSink.html
 
 pub mod executor;
-pub use executor::{Async, Context, JoinHandle, SubTaskOutput, Timer};
+pub use executor::{unblock, Async, Context, JoinHandle, SubTaskOutput, Timer};
 
 pub mod pad;
 pub use pad::{PadSink, PadSinkRef, PadSinkWeak, PadSrc, PadSrcRef, PadSrcWeak};
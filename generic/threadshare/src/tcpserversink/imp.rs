@@ -0,0 +1,872 @@
+// Copyright (C) 2026 agent <agent@local>
+//
+// This library is free software; you can redistribute it and/or
+// modify it under the terms of the GNU Library General Public
+// License as published by the Free Software Foundation; either
+// version 2 of the License, or (at your option) any later version.
+//
+// This library is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the GNU
+// Library General Public License for more details.
+//
+// You should have received a copy of the GNU Library General Public
+// License along with this library; if not, write to the
+// Free Software Foundation, Inc., 51 Franklin Street, Suite 500,
+// Boston, MA 02110-1335, USA.
+
+use futures::channel::{mpsc, oneshot};
+use futures::future::BoxFuture;
+use futures::lock::Mutex;
+use futures::prelude::*;
+
+use gst::glib;
+use gst::prelude::*;
+use gst::subclass::prelude::*;
+use gst::EventView;
+use gst::{
+    element_error, error_msg, gst_debug, gst_error, gst_info, gst_log, gst_trace, gst_warning,
+};
+
+use once_cell::sync::Lazy;
+
+use std::mem;
+use std::net::{IpAddr, SocketAddr, TcpListener, TcpStream};
+use std::sync::Arc;
+use std::sync::Mutex as StdMutex;
+use std::time::Duration;
+use std::u16;
+use std::u32;
+
+use crate::runtime::prelude::*;
+use crate::runtime::task;
+use crate::runtime::{Async, Context, JoinHandle, PadSink, PadSinkRef, Task, TaskState};
+
+use super::SlowClientPolicy;
+
+const DEFAULT_HOST: Option<&str> = Some("127.0.0.1");
+const DEFAULT_PORT: i32 = 4953;
+const DEFAULT_BUFFERS_MAX: u32 = 100;
+const DEFAULT_SLOW_CLIENT_POLICY: SlowClientPolicy = SlowClientPolicy::Disconnect;
+const DEFAULT_CONTEXT: &str = "";
+const DEFAULT_CONTEXT_WAIT: Duration = Duration::ZERO;
+
+#[derive(Debug, Clone)]
+struct Settings {
+    host: Option<String>,
+    port: i32,
+    buffers_max: u32,
+    slow_client_policy: SlowClientPolicy,
+    context: String,
+    context_wait: Duration,
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Settings {
+            host: DEFAULT_HOST.map(Into::into),
+            port: DEFAULT_PORT,
+            buffers_max: DEFAULT_BUFFERS_MAX,
+            slow_client_policy: DEFAULT_SLOW_CLIENT_POLICY,
+            context: DEFAULT_CONTEXT.into(),
+            context_wait: DEFAULT_CONTEXT_WAIT,
+        }
+    }
+}
+
+static CAT: Lazy<gst::DebugCategory> = Lazy::new(|| {
+    gst::DebugCategory::new(
+        "ts-tcpserversink",
+        gst::DebugColorFlags::empty(),
+        Some("Thread-sharing TCP server sink"),
+    )
+});
+
+#[derive(Debug)]
+enum TaskItem {
+    Buffer(gst::Buffer),
+    Event(gst::Event),
+}
+
+/// An item in the queue of a client.
+#[derive(Debug)]
+enum ClientItem {
+    Buffer(gst::Buffer),
+    /// Signalled once everything queued before was sent to the client
+    Drain(oneshot::Sender<()>),
+}
+
+/// A connected client with its own queue of buffers to send.
+#[derive(Debug)]
+struct Client {
+    addr: SocketAddr,
+    sender: mpsc::Sender<ClientItem>,
+    writer: JoinHandle<()>,
+    /// Whether buffers are dropped until the next buffer that can be decoded on its own
+    dropping: bool,
+}
+
+#[derive(Debug, Default)]
+struct Clients {
+    clients: Vec<Client>,
+    streamheader: Vec<gst::Buffer>,
+}
+
+impl Clients {
+    fn add(
+        &mut self,
+        element: &super::TcpServerSink,
+        socket: Async<TcpStream>,
+        addr: SocketAddr,
+        buffers_max: u32,
+    ) {
+        gst_info!(CAT, obj: element, "Adding client {:?}", addr);
+
+        // The channel has room for one buffer per sender in addition to its buffer size
+        let (sender, receiver) = mpsc::channel(buffers_max as usize - 1);
+
+        let writer = Context::current()
+            .expect("Clients must be added from a Context")
+            .spawn(write_to_client(
+                element.clone(),
+                addr,
+                socket,
+                self.streamheader.clone(),
+                receiver,
+            ));
+
+        // Nothing that was sent before can be used by the client, so it only starts receiving
+        // buffers with the next keyframe
+        self.clients.push(Client {
+            addr,
+            sender,
+            writer,
+            dropping: true,
+        });
+    }
+
+    fn send(
+        &mut self,
+        element: &super::TcpServerSink,
+        buffer: &gst::Buffer,
+        policy: SlowClientPolicy,
+    ) {
+        let is_delta_unit = buffer.flags().contains(gst::BufferFlags::DELTA_UNIT);
+
+        for mut client in mem::take(&mut self.clients) {
+            // Delta units can't be decoded without the buffers that were dropped before
+            if client.dropping && is_delta_unit {
+                gst_trace!(
+                    CAT,
+                    obj: element,
+                    "Dropping delta unit for client {:?}",
+                    client.addr
+                );
+                self.clients.push(client);
+                continue;
+            }
+
+            match client.sender.try_send(ClientItem::Buffer(buffer.clone())) {
+                Ok(()) => {
+                    if client.dropping {
+                        gst_debug!(
+                            CAT,
+                            obj: element,
+                            "Sending to client {:?} from keyframe on",
+                            client.addr
+                        );
+                        client.dropping = false;
+                    }
+                    self.clients.push(client);
+                }
+                Err(err) if err.is_full() => match policy {
+                    SlowClientPolicy::Drop => {
+                        gst_debug!(
+                            CAT,
+                            obj: element,
+                            "Client {:?} too slow, dropping buffers until the next keyframe",
+                            client.addr
+                        );
+                        client.dropping = true;
+                        self.clients.push(client);
+                    }
+                    SlowClientPolicy::Disconnect => {
+                        gst_warning!(
+                            CAT,
+                            obj: element,
+                            "Client {:?} too slow, disconnecting",
+                            client.addr
+                        );
+                        client.writer.cancel();
+                    }
+                },
+                Err(_) => {
+                    gst_info!(CAT, obj: element, "Removing client {:?}", client.addr);
+                }
+            }
+        }
+    }
+
+    /// Returns a future that resolves once everything queued so far was sent to all clients
+    fn drain(&self) -> impl Future<Output = ()> {
+        let senders = self
+            .clients
+            .iter()
+            .map(|client| client.sender.clone())
+            .collect::<Vec<_>>();
+
+        future::join_all(senders.into_iter().map(|mut sender| async move {
+            let (drained_sender, drained_receiver) = oneshot::channel();
+            if sender.send(ClientItem::Drain(drained_sender)).await.is_ok() {
+                // Clients that are disconnected meanwhile drop the sender
+                let _ = drained_receiver.await;
+            }
+        }))
+        .map(|_| ())
+    }
+
+    fn clear(&mut self) {
+        for client in self.clients.drain(..) {
+            client.writer.cancel();
+        }
+    }
+}
+
+async fn write_to_client(
+    element: super::TcpServerSink,
+    addr: SocketAddr,
+    mut socket: Async<TcpStream>,
+    streamheader: Vec<gst::Buffer>,
+    receiver: mpsc::Receiver<ClientItem>,
+) {
+    // New clients first need the headers to be able to handle the stream
+    let mut items =
+        futures::stream::iter(streamheader.into_iter().map(ClientItem::Buffer)).chain(receiver);
+
+    while let Some(item) = items.next().await {
+        let buffer = match item {
+            ClientItem::Buffer(buffer) => buffer,
+            ClientItem::Drain(drained_sender) => {
+                let _ = drained_sender.send(());
+                continue;
+            }
+        };
+
+        let data = match buffer.map_readable() {
+            Ok(data) => data,
+            Err(_) => {
+                gst_error!(CAT, obj: &element, "Failed to map buffer readable");
+                break;
+            }
+        };
+
+        if let Err(err) = socket.write_all(&data).await {
+            gst_info!(
+                CAT,
+                obj: &element,
+                "Failed to send to client {:?}: {}",
+                addr,
+                err
+            );
+            break;
+        }
+    }
+
+    gst_debug!(CAT, obj: &element, "Stopped sending to client {:?}", addr);
+}
+
+async fn accept_clients(
+    element: super::TcpServerSink,
+    listener: Arc<Async<TcpListener>>,
+    clients: Arc<StdMutex<Clients>>,
+    buffers_max: u32,
+) {
+    loop {
+        match listener.accept().await {
+            Ok((socket, addr)) => {
+                clients
+                    .lock()
+                    .unwrap()
+                    .add(&element, socket, addr, buffers_max);
+            }
+            Err(err) => {
+                element_error!(
+                    element,
+                    gst::ResourceError::Failed,
+                    ["Failed to accept client: {}", err]
+                );
+                break;
+            }
+        }
+    }
+}
+
+#[derive(Clone, Debug, Default)]
+struct TcpServerSinkPadHandler(Arc<Mutex<Option<mpsc::Sender<TaskItem>>>>);
+
+impl PadSinkHandler for TcpServerSinkPadHandler {
+    type ElementImpl = TcpServerSink;
+
+    fn sink_chain(
+        &self,
+        _pad: &PadSinkRef,
+        _tcpserversink: &TcpServerSink,
+        element: &gst::Element,
+        buffer: gst::Buffer,
+    ) -> BoxFuture<'static, Result<gst::FlowSuccess, gst::FlowError>> {
+        let sender = Arc::clone(&self.0);
+        let element = element.clone().downcast::<super::TcpServerSink>().unwrap();
+
+        async move {
+            if let Some(sender) = sender.lock().await.as_mut() {
+                if sender.send(TaskItem::Buffer(buffer)).await.is_err() {
+                    gst_debug!(CAT, obj: &element, "Flushing");
+                    return Err(gst::FlowError::Flushing);
+                }
+            }
+            Ok(gst::FlowSuccess::Ok)
+        }
+        .boxed()
+    }
+
+    fn sink_chain_list(
+        &self,
+        _pad: &PadSinkRef,
+        _tcpserversink: &TcpServerSink,
+        element: &gst::Element,
+        list: gst::BufferList,
+    ) -> BoxFuture<'static, Result<gst::FlowSuccess, gst::FlowError>> {
+        let sender = Arc::clone(&self.0);
+        let element = element.clone().downcast::<super::TcpServerSink>().unwrap();
+
+        async move {
+            if let Some(sender) = sender.lock().await.as_mut() {
+                for buffer in list.iter_owned() {
+                    if sender.send(TaskItem::Buffer(buffer)).await.is_err() {
+                        gst_debug!(CAT, obj: &element, "Flushing");
+                        return Err(gst::FlowError::Flushing);
+                    }
+                }
+            }
+
+            Ok(gst::FlowSuccess::Ok)
+        }
+        .boxed()
+    }
+
+    fn sink_event_serialized(
+        &self,
+        _pad: &PadSinkRef,
+        _tcpserversink: &TcpServerSink,
+        element: &gst::Element,
+        event: gst::Event,
+    ) -> BoxFuture<'static, bool> {
+        let sender = Arc::clone(&self.0);
+        let element = element.clone().downcast::<super::TcpServerSink>().unwrap();
+
+        async move {
+            if let EventView::FlushStop(_) = event.view() {
+                let tcpserversink = TcpServerSink::from_instance(&element);
+                return tcpserversink.task.flush_stop().is_ok();
+            } else if let Some(sender) = sender.lock().await.as_mut() {
+                if sender.send(TaskItem::Event(event)).await.is_err() {
+                    gst_debug!(CAT, obj: &element, "Flushing");
+                }
+            }
+
+            true
+        }
+        .boxed()
+    }
+
+    fn sink_event(
+        &self,
+        _pad: &PadSinkRef,
+        tcpserversink: &TcpServerSink,
+        _element: &gst::Element,
+        event: gst::Event,
+    ) -> bool {
+        if let EventView::FlushStart(..) = event.view() {
+            return tcpserversink.task.flush_start().is_ok();
+        }
+
+        true
+    }
+}
+
+#[derive(Debug)]
+struct TcpServerSinkTask {
+    element: super::TcpServerSink,
+    sink_pad_handler: TcpServerSinkPadHandler,
+    saddr: SocketAddr,
+    buffers_max: u32,
+    slow_client_policy: SlowClientPolicy,
+    clients: Arc<StdMutex<Clients>>,
+    listener: Option<Arc<Async<TcpListener>>>,
+    acceptor: Option<JoinHandle<()>>,
+    receiver: Option<mpsc::Receiver<TaskItem>>,
+}
+
+impl TcpServerSinkTask {
+    fn new(
+        element: &super::TcpServerSink,
+        sink_pad_handler: &TcpServerSinkPadHandler,
+        saddr: SocketAddr,
+        settings: &Settings,
+        clients: &Arc<StdMutex<Clients>>,
+    ) -> Self {
+        TcpServerSinkTask {
+            element: element.clone(),
+            sink_pad_handler: sink_pad_handler.clone(),
+            saddr,
+            buffers_max: settings.buffers_max,
+            slow_client_policy: settings.slow_client_policy,
+            clients: Arc::clone(clients),
+            listener: None,
+            acceptor: None,
+            receiver: None,
+        }
+    }
+
+    async fn handle_event(&mut self, event: gst::Event) {
+        match event.view() {
+            EventView::Caps(e) => {
+                let streamheader = e
+                    .caps()
+                    .structure(0)
+                    .and_then(|s| s.get::<gst::Array>("streamheader").ok())
+                    .map(|streamheader| {
+                        streamheader
+                            .as_slice()
+                            .iter()
+                            .filter_map(|v| v.get::<gst::Buffer>().ok())
+                            .collect()
+                    })
+                    .unwrap_or_default();
+
+                self.clients.lock().unwrap().streamheader = streamheader;
+            }
+            EventView::Eos(_) => {
+                // EOS is only posted once all clients received everything before it
+                let drained = self.clients.lock().unwrap().drain();
+                drained.await;
+
+                gst_debug!(CAT, obj: &self.element, "All clients drained");
+                let _ = self
+                    .element
+                    .post_message(gst::message::Eos::builder().src(&self.element).build());
+            }
+            EventView::SinkMessage(e) => {
+                let _ = self.element.post_message(e.message());
+            }
+            _ => (),
+        }
+    }
+}
+
+impl TaskImpl for TcpServerSinkTask {
+    fn prepare(&mut self) -> BoxFuture<'_, Result<(), gst::ErrorMessage>> {
+        async move {
+            gst_log!(CAT, obj: &self.element, "Preparing task listening on {:?}", self.saddr);
+
+            let listener = Async::<TcpListener>::bind(self.saddr).map_err(|err| {
+                error_msg!(
+                    gst::ResourceError::OpenWrite,
+                    ["Failed to listen on {:?}: {}", self.saddr, err]
+                )
+            })?;
+            self.listener = Some(Arc::new(listener));
+
+            gst_log!(CAT, obj: &self.element, "Task prepared");
+            Ok(())
+        }
+        .boxed()
+    }
+
+    fn unprepare(&mut self) -> BoxFuture<'_, ()> {
+        async move {
+            gst_log!(CAT, obj: &self.element, "Unpreparing task");
+            self.listener = None;
+            gst_log!(CAT, obj: &self.element, "Task unprepared");
+        }
+        .boxed()
+    }
+
+    fn handle_action_error(
+        &mut self,
+        trigger: task::Trigger,
+        state: TaskState,
+        err: gst::ErrorMessage,
+    ) -> BoxFuture<'_, task::Trigger> {
+        async move {
+            match trigger {
+                task::Trigger::Prepare => {
+                    gst_error!(CAT, "Task preparation failed: {:?}", err);
+                    self.element.post_error_message(err);
+
+                    task::Trigger::Error
+                }
+                other => unreachable!("Action error for {:?} in state {:?}", other, state),
+            }
+        }
+        .boxed()
+    }
+
+    fn start(&mut self) -> BoxFuture<'_, Result<(), gst::ErrorMessage>> {
+        async move {
+            gst_log!(CAT, obj: &self.element, "Starting task");
+
+            let (sender, receiver) = mpsc::channel(0);
+            *self.sink_pad_handler.0.lock().await = Some(sender);
+            self.receiver = Some(receiver);
+
+            self.acceptor = Some(
+                Context::current()
+                    .expect("Task started outside of a Context")
+                    .spawn(accept_clients(
+                        self.element.clone(),
+                        Arc::clone(self.listener.as_ref().unwrap()),
+                        Arc::clone(&self.clients),
+                        self.buffers_max,
+                    )),
+            );
+
+            gst_log!(CAT, obj: &self.element, "Task started");
+            Ok(())
+        }
+        .boxed()
+    }
+
+    fn iterate(&mut self) -> BoxFuture<'_, Result<(), gst::FlowError>> {
+        async move {
+            match self.receiver.as_mut().unwrap().next().await {
+                Some(TaskItem::Buffer(buffer)) => {
+                    gst_log!(CAT, obj: &self.element, "Sending {:?} to all clients", buffer);
+                    self.clients.lock().unwrap().send(
+                        &self.element,
+                        &buffer,
+                        self.slow_client_policy,
+                    );
+                    Ok(())
+                }
+                Some(TaskItem::Event(event)) => {
+                    self.handle_event(event).await;
+                    Ok(())
+                }
+                None => Err(gst::FlowError::Flushing),
+            }
+        }
+        .boxed()
+    }
+
+    fn stop(&mut self) -> BoxFuture<'_, Result<(), gst::ErrorMessage>> {
+        async move {
+            gst_log!(CAT, obj: &self.element, "Stopping task");
+
+            if let Some(acceptor) = self.acceptor.take() {
+                acceptor.cancel();
+            }
+            self.clients.lock().unwrap().clear();
+
+            gst_log!(CAT, obj: &self.element, "Task stopped");
+            Ok(())
+        }
+        .boxed()
+    }
+}
+
+#[derive(Debug)]
+pub struct TcpServerSink {
+    sink_pad: PadSink,
+    sink_pad_handler: TcpServerSinkPadHandler,
+    task: Task,
+    clients: Arc<StdMutex<Clients>>,
+    settings: StdMutex<Settings>,
+}
+
+impl TcpServerSink {
+    fn prepare(&self, element: &super::TcpServerSink) -> Result<(), gst::ErrorMessage> {
+        let settings = self.settings.lock().unwrap().clone();
+
+        gst_debug!(CAT, obj: element, "Preparing");
+
+        let context =
+            Context::acquire(&settings.context, settings.context_wait).map_err(|err| {
+                error_msg!(
+                    gst::ResourceError::OpenWrite,
+                    ["Failed to acquire Context: {}", err]
+                )
+            })?;
+
+        let host: IpAddr = match settings.host {
+            None => {
+                return Err(error_msg!(gst::ResourceError::Settings, ["No host set"]));
+            }
+            Some(ref host) => match host.parse() {
+                Err(err) => {
+                    return Err(error_msg!(
+                        gst::ResourceError::Settings,
+                        ["Invalid host '{}' set: {}", host, err]
+                    ));
+                }
+                Ok(host) => host,
+            },
+        };
+        let saddr = SocketAddr::new(host, settings.port as u16);
+
+        self.task
+            .prepare(
+                TcpServerSinkTask::new(
+                    element,
+                    &self.sink_pad_handler,
+                    saddr,
+                    &settings,
+                    &self.clients,
+                ),
+                context,
+            )
+            .map_err(|err| {
+                error_msg!(
+                    gst::ResourceError::OpenWrite,
+                    ["Error preparing Task: {:?}", err]
+                )
+            })?;
+
+        gst_debug!(CAT, obj: element, "Prepared");
+
+        Ok(())
+    }
+
+    fn unprepare(&self, element: &super::TcpServerSink) {
+        gst_debug!(CAT, obj: element, "Unpreparing");
+        self.task.unprepare().unwrap();
+        gst_debug!(CAT, obj: element, "Unprepared");
+    }
+
+    fn stop(&self, element: &super::TcpServerSink) -> Result<(), gst::ErrorMessage> {
+        gst_debug!(CAT, obj: element, "Stopping");
+        self.task.stop()?;
+        gst_debug!(CAT, obj: element, "Stopped");
+        Ok(())
+    }
+
+    fn start(&self, element: &super::TcpServerSink) -> Result<(), gst::ErrorMessage> {
+        gst_debug!(CAT, obj: element, "Starting");
+        self.task.start()?;
+        gst_debug!(CAT, obj: element, "Started");
+        Ok(())
+    }
+}
+
+#[glib::object_subclass]
+impl ObjectSubclass for TcpServerSink {
+    const NAME: &'static str = "RsTsTcpServerSink";
+    type Type = super::TcpServerSink;
+    type ParentType = gst::Element;
+
+    fn with_class(klass: &Self::Class) -> Self {
+        let sink_pad_handler = TcpServerSinkPadHandler::default();
+
+        Self {
+            sink_pad: PadSink::new(
+                gst::Pad::from_template(&klass.pad_template("sink").unwrap(), Some("sink")),
+                sink_pad_handler.clone(),
+            ),
+            sink_pad_handler,
+            task: Task::default(),
+            clients: Arc::new(StdMutex::new(Clients::default())),
+            settings: StdMutex::new(Settings::default()),
+        }
+    }
+}
+
+impl ObjectImpl for TcpServerSink {
+    fn properties() -> &'static [glib::ParamSpec] {
+        static PROPERTIES: Lazy<Vec<glib::ParamSpec>> = Lazy::new(|| {
+            vec![
+                glib::ParamSpecString::new(
+                    "context",
+                    "Context",
+                    "Context name to share threads with",
+                    Some(DEFAULT_CONTEXT),
+                    glib::ParamFlags::READWRITE,
+                ),
+                glib::ParamSpecUInt::new(
+                    "context-wait",
+                    "Context Wait",
+                    "Throttle poll loop to run at most once every this many ms",
+                    0,
+                    1000,
+                    DEFAULT_CONTEXT_WAIT.as_millis() as u32,
+                    glib::ParamFlags::READWRITE,
+                ),
+                glib::ParamSpecString::new(
+                    "host",
+                    "Host",
+                    "The host IP address to listen on",
+                    DEFAULT_HOST,
+                    glib::ParamFlags::READWRITE,
+                ),
+                glib::ParamSpecInt::new(
+                    "port",
+                    "Port",
+                    "Port to listen on",
+                    0,
+                    u16::MAX as i32,
+                    DEFAULT_PORT,
+                    glib::ParamFlags::READWRITE,
+                ),
+                glib::ParamSpecUInt::new(
+                    "buffers-max",
+                    "Buffers Max",
+                    "Maximum number of buffers queued for each client",
+                    1,
+                    u32::MAX,
+                    DEFAULT_BUFFERS_MAX,
+                    glib::ParamFlags::READWRITE,
+                ),
+                glib::ParamSpecEnum::new(
+                    "slow-client-policy",
+                    "Slow Client Policy",
+                    "What to do with clients whose queue is full",
+                    SlowClientPolicy::static_type(),
+                    DEFAULT_SLOW_CLIENT_POLICY as i32,
+                    glib::ParamFlags::READWRITE,
+                ),
+                glib::ParamSpecUInt::new(
+                    "num-clients",
+                    "Number of Clients",
+                    "Number of currently connected clients",
+                    0,
+                    u32::MAX,
+                    0,
+                    glib::ParamFlags::READABLE,
+                ),
+            ]
+        });
+
+        PROPERTIES.as_ref()
+    }
+
+    fn set_property(
+        &self,
+        _obj: &Self::Type,
+        _id: usize,
+        value: &glib::Value,
+        pspec: &glib::ParamSpec,
+    ) {
+        let mut settings = self.settings.lock().unwrap();
+        match pspec.name() {
+            "host" => {
+                settings.host = value.get().expect("type checked upstream");
+            }
+            "port" => {
+                settings.port = value.get().expect("type checked upstream");
+            }
+            "buffers-max" => {
+                settings.buffers_max = value.get().expect("type checked upstream");
+            }
+            "slow-client-policy" => {
+                settings.slow_client_policy = value.get().expect("type checked upstream");
+            }
+            "context" => {
+                settings.context = value
+                    .get::<Option<String>>()
+                    .expect("type checked upstream")
+                    .unwrap_or_else(|| DEFAULT_CONTEXT.into());
+            }
+            "context-wait" => {
+                settings.context_wait = Duration::from_millis(
+                    value.get::<u32>().expect("type checked upstream").into(),
+                );
+            }
+            _ => unimplemented!(),
+        }
+    }
+
+    fn property(&self, _obj: &Self::Type, _id: usize, pspec: &glib::ParamSpec) -> glib::Value {
+        let settings = self.settings.lock().unwrap();
+        match pspec.name() {
+            "host" => settings.host.to_value(),
+            "port" => settings.port.to_value(),
+            "buffers-max" => settings.buffers_max.to_value(),
+            "slow-client-policy" => settings.slow_client_policy.to_value(),
+            "num-clients" => (self.clients.lock().unwrap().clients.len() as u32).to_value(),
+            "context" => settings.context.to_value(),
+            "context-wait" => (settings.context_wait.as_millis() as u32).to_value(),
+            _ => unimplemented!(),
+        }
+    }
+
+    fn constructed(&self, obj: &Self::Type) {
+        self.parent_constructed(obj);
+
+        obj.add_pad(self.sink_pad.gst_pad()).unwrap();
+
+        crate::set_element_flags(obj, gst::ElementFlags::SINK);
+    }
+}
+
+impl GstObjectImpl for TcpServerSink {}
+
+impl ElementImpl for TcpServerSink {
+    fn metadata() -> Option<&'static gst::subclass::ElementMetadata> {
+        static ELEMENT_METADATA: Lazy<gst::subclass::ElementMetadata> = Lazy::new(|| {
+            gst::subclass::ElementMetadata::new(
+                "Thread-sharing TCP server sink",
+                "Sink/Network",
+                "Sends data to all clients connected via TCP",
+                "Sebastian Dröge <sebastian@centricular.com>",
+            )
+        });
+
+        Some(&*ELEMENT_METADATA)
+    }
+
+    fn pad_templates() -> &'static [gst::PadTemplate] {
+        static PAD_TEMPLATES: Lazy<Vec<gst::PadTemplate>> = Lazy::new(|| {
+            let caps = gst::Caps::new_any();
+
+            let sink_pad_template = gst::PadTemplate::new(
+                "sink",
+                gst::PadDirection::Sink,
+                gst::PadPresence::Always,
+                &caps,
+            )
+            .unwrap();
+
+            vec![sink_pad_template]
+        });
+
+        PAD_TEMPLATES.as_ref()
+    }
+
+    fn change_state(
+        &self,
+        element: &Self::Type,
+        transition: gst::StateChange,
+    ) -> Result<gst::StateChangeSuccess, gst::StateChangeError> {
+        gst_trace!(CAT, obj: element, "Changing state {:?}", transition);
+
+        match transition {
+            gst::StateChange::NullToReady => {
+                self.prepare(element).map_err(|err| {
+                    element.post_error_message(err);
+                    gst::StateChangeError
+                })?;
+            }
+            gst::StateChange::ReadyToPaused => {
+                self.start(element).map_err(|_| gst::StateChangeError)?;
+            }
+            gst::StateChange::PausedToReady => {
+                self.stop(element).map_err(|_| gst::StateChangeError)?;
+            }
+            gst::StateChange::ReadyToNull => {
+                self.unprepare(element);
+            }
+            _ => (),
+        }
+
+        self.parent_change_state(element, transition)
+    }
+}
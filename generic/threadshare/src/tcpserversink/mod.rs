@@ -0,0 +1,55 @@
+// Copyright (C) 2026 agent <agent@local>
+//
+// This library is free software; you can redistribute it and/or
+// modify it under the terms of the GNU Library General Public
+// License as published by the Free Software Foundation; either
+// version 2 of the License, or (at your option) any later version.
+//
+// This library is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the GNU
+// Library General Public License for more details.
+//
+// You should have received a copy of the GNU Library General Public
+// License along with this library; if not, write to the
+// Free Software Foundation, Inc., 51 Franklin Street, Suite 500,
+// Boston, MA 02110-1335, USA.
+
+use gst::glib;
+use gst::prelude::*;
+
+mod imp;
+
+#[derive(Debug, Eq, PartialEq, Ord, PartialOrd, Hash, Clone, Copy, glib::Enum)]
+#[repr(u32)]
+#[enum_type(name = "GstTsTcpServerSinkSlowClientPolicy")]
+pub enum SlowClientPolicy {
+    #[enum_value(
+        name = "Drop: Drop buffers for a client until its queue has room again and the next keyframe arrives.",
+        nick = "drop"
+    )]
+    Drop,
+    #[enum_value(
+        name = "Disconnect: Disconnect a client once its queue is full.",
+        nick = "disconnect"
+    )]
+    Disconnect,
+}
+
+glib::wrapper! {
+    pub struct TcpServerSink(ObjectSubclass<imp::TcpServerSink>) @extends gst::Element, gst::Object;
+}
+
+// GStreamer elements need to be thread-safe. For the private implementation this is automatically
+// enforced but for the public wrapper type we need to specify this manually.
+unsafe impl Send for TcpServerSink {}
+unsafe impl Sync for TcpServerSink {}
+
+pub fn register(plugin: &gst::Plugin) -> Result<(), glib::BoolError> {
+    gst::Element::register(
+        Some(plugin),
+        "ts-tcpserversink",
+        gst::Rank::None,
+        TcpServerSink::static_type(),
+    )
+}
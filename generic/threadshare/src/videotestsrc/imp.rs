@@ -0,0 +1,747 @@
+// Copyright (C) 2026 agent <agent@local>
+//
+// This library is free software; you can redistribute it and/or
+// modify it under the terms of the GNU Library General Public
+// License as published by the Free Software Foundation; either
+// version 2 of the License, or (at your option) any later version.
+//
+// This library is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the GNU
+// Library General Public License for more details.
+//
+// You should have received a copy of the GNU Library General Public
+// License along with this library; if not, write to the
+// Free Software Foundation, Inc., 51 Franklin Street, Suite 500,
+// Boston, MA 02110-1335, USA.
+
+use futures::future::BoxFuture;
+use futures::prelude::*;
+
+use gst::glib;
+use gst::prelude::*;
+use gst::subclass::prelude::*;
+use gst::{gst_debug, gst_error, gst_log, gst_trace};
+
+use once_cell::sync::Lazy;
+
+use std::sync::Arc;
+use std::sync::Mutex as StdMutex;
+use std::time::Duration;
+use std::u32;
+
+use crate::runtime::prelude::*;
+use crate::runtime::{time, Context, PadSrc, PadSrcRef, PadSrcWeak, Task, Timer};
+
+use super::VideoTestSrcPattern;
+
+const DEFAULT_CONTEXT: &str = "";
+const DEFAULT_CONTEXT_WAIT: Duration = Duration::ZERO;
+const DEFAULT_WIDTH: u32 = 320;
+const DEFAULT_HEIGHT: u32 = 240;
+const DEFAULT_FRAMERATE_NUM: i32 = 30;
+const DEFAULT_FRAMERATE_DEN: i32 = 1;
+const DEFAULT_PATTERN: VideoTestSrcPattern = VideoTestSrcPattern::Bars;
+const DEFAULT_NUM_BUFFERS: i32 = -1;
+
+/// Keeps the size of the RGBA frames at 1 GiB or below.
+const MAX_DIMENSION: u32 = 16384;
+/// Keeps the frame duration, and with it the period of the timer, at 1ms or above.
+const MAX_FRAMERATE: i32 = 1000;
+
+/// 75% color bars, from left to right.
+const BARS: [[u8; 4]; 7] = [
+    [191, 191, 191, 255],
+    [191, 191, 0, 255],
+    [0, 191, 191, 255],
+    [0, 191, 0, 255],
+    [191, 0, 191, 255],
+    [191, 0, 0, 255],
+    [0, 0, 191, 255],
+];
+
+#[derive(Debug, Clone)]
+struct Settings {
+    context: String,
+    context_wait: Duration,
+    width: u32,
+    height: u32,
+    framerate: gst::Fraction,
+    pattern: VideoTestSrcPattern,
+    num_buffers: i32,
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Settings {
+            context: DEFAULT_CONTEXT.into(),
+            context_wait: DEFAULT_CONTEXT_WAIT,
+            width: DEFAULT_WIDTH,
+            height: DEFAULT_HEIGHT,
+            framerate: gst::Fraction::new(DEFAULT_FRAMERATE_NUM, DEFAULT_FRAMERATE_DEN),
+            pattern: DEFAULT_PATTERN,
+            num_buffers: DEFAULT_NUM_BUFFERS,
+        }
+    }
+}
+
+static CAT: Lazy<gst::DebugCategory> = Lazy::new(|| {
+    gst::DebugCategory::new(
+        "ts-videotestsrc",
+        gst::DebugColorFlags::empty(),
+        Some("Thread-sharing video test source"),
+    )
+});
+
+/// Format of the generated video, fixed when the element is prepared.
+#[derive(Debug, Clone)]
+struct VideoFormat {
+    width: u32,
+    height: u32,
+    framerate: gst::Fraction,
+}
+
+impl VideoFormat {
+    fn caps(&self) -> gst::Caps {
+        gst::Caps::builder("video/x-raw")
+            .field("format", "RGBA")
+            .field("width", self.width as i32)
+            .field("height", self.height as i32)
+            .field("framerate", self.framerate)
+            .build()
+    }
+
+    fn frame_size(&self) -> usize {
+        4 * self.width as usize * self.height as usize
+    }
+
+    fn frames_to_time(&self, frames: u64) -> gst::ClockTime {
+        frames
+            .mul_div_floor(
+                *gst::ClockTime::SECOND * *self.framerate.denom() as u64,
+                *self.framerate.numer() as u64,
+            )
+            .map(gst::ClockTime::from_nseconds)
+            .unwrap()
+    }
+
+    fn frame_duration(&self) -> gst::ClockTime {
+        self.frames_to_time(1)
+    }
+}
+
+#[derive(Clone, Debug, Default)]
+struct VideoTestSrcPadHandler(Arc<StdMutex<Option<VideoFormat>>>);
+
+impl VideoTestSrcPadHandler {
+    fn prepare(&self, format: VideoFormat) {
+        *self.0.lock().unwrap() = Some(format);
+    }
+
+    fn unprepare(&self) {
+        *self.0.lock().unwrap() = None;
+    }
+}
+
+impl PadSrcHandler for VideoTestSrcPadHandler {
+    type ElementImpl = VideoTestSrc;
+
+    fn src_event(
+        &self,
+        pad: &PadSrcRef,
+        videotestsrc: &VideoTestSrc,
+        _element: &gst::Element,
+        event: gst::Event,
+    ) -> bool {
+        use gst::EventView;
+
+        gst_log!(CAT, obj: pad.gst_pad(), "Handling {:?}", event);
+
+        let ret = match event.view() {
+            EventView::FlushStart(..) => videotestsrc.task.flush_start().is_ok(),
+            EventView::FlushStop(..) => videotestsrc.task.flush_stop().is_ok(),
+            EventView::Reconfigure(..) => true,
+            EventView::Latency(..) => true,
+            _ => false,
+        };
+
+        if ret {
+            gst_log!(CAT, obj: pad.gst_pad(), "Handled {:?}", event);
+        } else {
+            gst_log!(CAT, obj: pad.gst_pad(), "Didn't handle {:?}", event);
+        }
+
+        ret
+    }
+
+    fn src_query(
+        &self,
+        pad: &PadSrcRef,
+        _videotestsrc: &VideoTestSrc,
+        _element: &gst::Element,
+        query: &mut gst::QueryRef,
+    ) -> bool {
+        use gst::QueryView;
+
+        gst_log!(CAT, obj: pad.gst_pad(), "Handling {:?}", query);
+        let ret = match query.view_mut() {
+            QueryView::Latency(ref mut q) => match *self.0.lock().unwrap() {
+                Some(ref format) => {
+                    // A frame is only pushed once its duration elapsed
+                    q.set(true, format.frame_duration(), gst::ClockTime::NONE);
+                    true
+                }
+                None => false,
+            },
+            QueryView::Scheduling(ref mut q) => {
+                q.set(gst::SchedulingFlags::SEQUENTIAL, 1, -1, 0);
+                q.add_scheduling_modes(&[gst::PadMode::Push]);
+                true
+            }
+            QueryView::Caps(ref mut q) => {
+                let caps = match *self.0.lock().unwrap() {
+                    Some(ref format) => format.caps(),
+                    None => pad.gst_pad().pad_template_caps(),
+                };
+
+                let caps = q
+                    .filter()
+                    .map(|f| f.intersect_with_mode(&caps, gst::CapsIntersectMode::First))
+                    .unwrap_or(caps);
+
+                q.set_result(&caps);
+
+                true
+            }
+            _ => false,
+        };
+
+        if ret {
+            gst_log!(CAT, obj: pad.gst_pad(), "Handled {:?}", query);
+        } else {
+            gst_log!(CAT, obj: pad.gst_pad(), "Didn't handle {:?}", query);
+        }
+        ret
+    }
+}
+
+#[derive(Debug)]
+struct VideoTestSrcTask {
+    element: super::VideoTestSrc,
+    src_pad: PadSrcWeak,
+    format: VideoFormat,
+    pattern: VideoTestSrcPattern,
+    num_buffers: Option<u64>,
+    frame: Option<gst::Buffer>,
+    timer: Option<Timer>,
+    need_initial_events: bool,
+    need_segment: bool,
+    n_frames: u64,
+}
+
+impl VideoTestSrcTask {
+    fn new(element: &super::VideoTestSrc, src_pad: &PadSrc, settings: &Settings) -> Self {
+        VideoTestSrcTask {
+            element: element.clone(),
+            src_pad: src_pad.downgrade(),
+            format: VideoFormat {
+                width: settings.width,
+                height: settings.height,
+                framerate: settings.framerate,
+            },
+            pattern: settings.pattern,
+            num_buffers: u64::try_from(settings.num_buffers).ok(),
+            frame: None,
+            timer: None,
+            need_initial_events: true,
+            need_segment: true,
+            n_frames: 0,
+        }
+    }
+
+    async fn push_prelude(&mut self, pad: &PadSrcRef<'_>) {
+        if self.need_initial_events {
+            gst_debug!(CAT, obj: pad.gst_pad(), "Pushing initial events");
+
+            let stream_id = format!("{:08x}{:08x}", rand::random::<u32>(), rand::random::<u32>());
+            let stream_start_evt = gst::event::StreamStart::builder(&stream_id)
+                .group_id(gst::GroupId::next())
+                .build();
+            pad.push_event(stream_start_evt).await;
+
+            pad.push_event(gst::event::Caps::new(&self.format.caps()))
+                .await;
+
+            self.need_initial_events = false;
+        }
+
+        if self.need_segment {
+            let segment_evt =
+                gst::event::Segment::new(&gst::FormattedSegment::<gst::format::Time>::new());
+            pad.push_event(segment_evt).await;
+
+            self.need_segment = false;
+        }
+    }
+
+    /// Renders the frame which is pushed for every tick of the timer.
+    fn render(&self) -> gst::Buffer {
+        let width = self.format.width as usize;
+
+        let mut buffer = gst::Buffer::with_size(self.format.frame_size()).unwrap();
+        {
+            let buffer = buffer.get_mut().unwrap();
+            let mut map = buffer.map_writable().unwrap();
+
+            for line in map.chunks_exact_mut(4 * width) {
+                for (x, pixel) in line.chunks_exact_mut(4).enumerate() {
+                    let color = match self.pattern {
+                        VideoTestSrcPattern::Bars => BARS[x * BARS.len() / width],
+                        VideoTestSrcPattern::Black => [0, 0, 0, 255],
+                        VideoTestSrcPattern::White => [255, 255, 255, 255],
+                    };
+                    pixel.copy_from_slice(&color);
+                }
+            }
+        }
+
+        buffer
+    }
+
+    /// Returns the next frame.
+    ///
+    /// All frames share the memory of the rendered frame and are timestamped
+    /// from the number of frames produced so far, so that the output only
+    /// depends on the settings.
+    fn generate(&mut self) -> gst::Buffer {
+        let mut buffer = self.frame.as_ref().unwrap().copy();
+        {
+            let buffer = buffer.get_mut().unwrap();
+
+            let pts = self.format.frames_to_time(self.n_frames);
+            let end = self.format.frames_to_time(self.n_frames + 1);
+            buffer.set_pts(pts);
+            buffer.set_duration(end - pts);
+            buffer.set_offset(self.n_frames);
+            buffer.set_offset_end(self.n_frames + 1);
+            if self.n_frames == 0 {
+                buffer.set_flags(gst::BufferFlags::DISCONT);
+            }
+        }
+
+        self.n_frames += 1;
+
+        buffer
+    }
+}
+
+impl TaskImpl for VideoTestSrcTask {
+    fn prepare(&mut self) -> BoxFuture<'_, Result<(), gst::ErrorMessage>> {
+        async move {
+            gst_log!(CAT, obj: &self.element, "Preparing task");
+
+            self.frame = Some(self.render());
+
+            gst_log!(CAT, obj: &self.element, "Task prepared");
+            Ok(())
+        }
+        .boxed()
+    }
+
+    fn start(&mut self) -> BoxFuture<'_, Result<(), gst::ErrorMessage>> {
+        async move {
+            gst_log!(CAT, obj: &self.element, "Starting task");
+
+            // Restart the timer so that frames missed while paused are not pushed in a burst
+            self.timer = Some(time::interval(Duration::from_nanos(
+                self.format.frame_duration().nseconds(),
+            )));
+
+            gst_log!(CAT, obj: &self.element, "Task started");
+            Ok(())
+        }
+        .boxed()
+    }
+
+    fn iterate(&mut self) -> BoxFuture<'_, Result<(), gst::FlowError>> {
+        async move {
+            self.timer.as_mut().unwrap().next().await;
+
+            let pad = self.src_pad.upgrade().expect("PadSrc no longer exists");
+
+            if Some(self.n_frames) == self.num_buffers {
+                gst_debug!(CAT, obj: &self.element, "Reached num-buffers, pushing EOS");
+                self.push_prelude(&pad).await;
+                pad.push_event(gst::event::Eos::new()).await;
+                return Err(gst::FlowError::Eos);
+            }
+
+            self.push_prelude(&pad).await;
+
+            let buffer = self.generate();
+            gst_log!(CAT, obj: &self.element, "Forwarding {:?}", buffer);
+
+            let res = pad.push(buffer).await;
+            match res {
+                Ok(_) => {
+                    gst_log!(CAT, obj: &self.element, "Successfully pushed buffer");
+                }
+                Err(gst::FlowError::Flushing) => {
+                    gst_debug!(CAT, obj: &self.element, "Flushing");
+                }
+                Err(gst::FlowError::Eos) => {
+                    gst_debug!(CAT, obj: &self.element, "EOS");
+                    pad.push_event(gst::event::Eos::new()).await;
+                }
+                Err(err) => {
+                    gst_error!(CAT, obj: &self.element, "Got error {}", err);
+                    gst::element_error!(
+                        &self.element,
+                        gst::StreamError::Failed,
+                        ("Internal data stream error"),
+                        ["streaming stopped, reason {}", err]
+                    );
+                }
+            }
+
+            res.map(drop)
+        }
+        .boxed()
+    }
+
+    fn stop(&mut self) -> BoxFuture<'_, Result<(), gst::ErrorMessage>> {
+        async move {
+            gst_log!(CAT, obj: &self.element, "Stopping task");
+
+            self.timer = None;
+            self.need_initial_events = true;
+            self.need_segment = true;
+            self.n_frames = 0;
+
+            gst_log!(CAT, obj: &self.element, "Task stopped");
+            Ok(())
+        }
+        .boxed()
+    }
+
+    fn flush_start(&mut self) -> BoxFuture<'_, Result<(), gst::ErrorMessage>> {
+        async move {
+            gst_log!(CAT, obj: &self.element, "Starting task flush");
+
+            self.need_segment = true;
+
+            gst_log!(CAT, obj: &self.element, "Task flush started");
+            Ok(())
+        }
+        .boxed()
+    }
+}
+
+#[derive(Debug)]
+pub struct VideoTestSrc {
+    src_pad: PadSrc,
+    src_pad_handler: VideoTestSrcPadHandler,
+    task: Task,
+    settings: StdMutex<Settings>,
+}
+
+impl VideoTestSrc {
+    fn prepare(&self, element: &super::VideoTestSrc) -> Result<(), gst::ErrorMessage> {
+        let settings = self.settings.lock().unwrap().clone();
+        gst_debug!(CAT, obj: element, "Preparing");
+
+        let context =
+            Context::acquire(&settings.context, settings.context_wait).map_err(|err| {
+                gst::error_msg!(
+                    gst::ResourceError::OpenRead,
+                    ["Failed to acquire Context: {}", err]
+                )
+            })?;
+
+        let task_impl = VideoTestSrcTask::new(element, &self.src_pad, &settings);
+        self.src_pad_handler.prepare(task_impl.format.clone());
+
+        self.task.prepare(task_impl, context).map_err(|err| {
+            gst::error_msg!(
+                gst::ResourceError::OpenRead,
+                ["Error preparing Task: {:?}", err]
+            )
+        })?;
+
+        gst_debug!(CAT, obj: element, "Prepared");
+
+        Ok(())
+    }
+
+    fn unprepare(&self, element: &super::VideoTestSrc) {
+        gst_debug!(CAT, obj: element, "Unpreparing");
+
+        self.task.unprepare().unwrap();
+        self.src_pad_handler.unprepare();
+
+        gst_debug!(CAT, obj: element, "Unprepared");
+    }
+
+    fn stop(&self, element: &super::VideoTestSrc) -> Result<(), gst::ErrorMessage> {
+        gst_debug!(CAT, obj: element, "Stopping");
+        self.task.stop()?;
+        gst_debug!(CAT, obj: element, "Stopped");
+        Ok(())
+    }
+
+    fn start(&self, element: &super::VideoTestSrc) -> Result<(), gst::ErrorMessage> {
+        gst_debug!(CAT, obj: element, "Starting");
+        self.task.start()?;
+        gst_debug!(CAT, obj: element, "Started");
+        Ok(())
+    }
+
+    fn pause(&self, element: &super::VideoTestSrc) -> Result<(), gst::ErrorMessage> {
+        gst_debug!(CAT, obj: element, "Pausing");
+        self.task.pause()?;
+        gst_debug!(CAT, obj: element, "Paused");
+        Ok(())
+    }
+}
+
+#[glib::object_subclass]
+impl ObjectSubclass for VideoTestSrc {
+    const NAME: &'static str = "RsTsVideoTestSrc";
+    type Type = super::VideoTestSrc;
+    type ParentType = gst::Element;
+
+    fn with_class(klass: &Self::Class) -> Self {
+        let src_pad_handler = VideoTestSrcPadHandler::default();
+
+        Self {
+            src_pad: PadSrc::new(
+                gst::Pad::from_template(&klass.pad_template("src").unwrap(), Some("src")),
+                src_pad_handler.clone(),
+            ),
+            src_pad_handler,
+            task: Task::default(),
+            settings: StdMutex::new(Settings::default()),
+        }
+    }
+}
+
+impl ObjectImpl for VideoTestSrc {
+    fn properties() -> &'static [glib::ParamSpec] {
+        static PROPERTIES: Lazy<Vec<glib::ParamSpec>> = Lazy::new(|| {
+            vec![
+                glib::ParamSpecString::new(
+                    "context",
+                    "Context",
+                    "Context name to share threads with",
+                    Some(DEFAULT_CONTEXT),
+                    glib::ParamFlags::READWRITE,
+                ),
+                glib::ParamSpecUInt::new(
+                    "context-wait",
+                    "Context Wait",
+                    "Throttle poll loop to run at most once every this many ms",
+                    0,
+                    1000,
+                    DEFAULT_CONTEXT_WAIT.as_millis() as u32,
+                    glib::ParamFlags::READWRITE,
+                ),
+                glib::ParamSpecUInt::new(
+                    "width",
+                    "Width",
+                    "Width of the frames",
+                    1,
+                    MAX_DIMENSION,
+                    DEFAULT_WIDTH,
+                    glib::ParamFlags::READWRITE,
+                ),
+                glib::ParamSpecUInt::new(
+                    "height",
+                    "Height",
+                    "Height of the frames",
+                    1,
+                    MAX_DIMENSION,
+                    DEFAULT_HEIGHT,
+                    glib::ParamFlags::READWRITE,
+                ),
+                gst::ParamSpecFraction::new(
+                    "framerate",
+                    "Framerate",
+                    "Number of frames per second",
+                    gst::Fraction::new(1, i32::MAX),
+                    gst::Fraction::new(MAX_FRAMERATE, 1),
+                    gst::Fraction::new(DEFAULT_FRAMERATE_NUM, DEFAULT_FRAMERATE_DEN),
+                    glib::ParamFlags::READWRITE,
+                ),
+                glib::ParamSpecEnum::new(
+                    "pattern",
+                    "Pattern",
+                    "Pattern of the frames",
+                    VideoTestSrcPattern::static_type(),
+                    DEFAULT_PATTERN as i32,
+                    glib::ParamFlags::READWRITE,
+                ),
+                glib::ParamSpecInt::new(
+                    "num-buffers",
+                    "Num Buffers",
+                    "Number of buffers to output before sending EOS (-1 = unlimited)",
+                    -1,
+                    i32::MAX,
+                    DEFAULT_NUM_BUFFERS,
+                    glib::ParamFlags::READWRITE,
+                ),
+            ]
+        });
+
+        PROPERTIES.as_ref()
+    }
+
+    fn set_property(
+        &self,
+        _obj: &Self::Type,
+        _id: usize,
+        value: &glib::Value,
+        pspec: &glib::ParamSpec,
+    ) {
+        let mut settings = self.settings.lock().unwrap();
+        match pspec.name() {
+            "context" => {
+                settings.context = value
+                    .get::<Option<String>>()
+                    .expect("type checked upstream")
+                    .unwrap_or_else(|| DEFAULT_CONTEXT.into());
+            }
+            "context-wait" => {
+                settings.context_wait = Duration::from_millis(
+                    value.get::<u32>().expect("type checked upstream").into(),
+                );
+            }
+            "width" => {
+                settings.width = value.get().expect("type checked upstream");
+            }
+            "height" => {
+                settings.height = value.get().expect("type checked upstream");
+            }
+            "framerate" => {
+                settings.framerate = value.get().expect("type checked upstream");
+            }
+            "pattern" => {
+                settings.pattern = value.get().expect("type checked upstream");
+            }
+            "num-buffers" => {
+                settings.num_buffers = value.get().expect("type checked upstream");
+            }
+            _ => unimplemented!(),
+        }
+    }
+
+    fn property(&self, _obj: &Self::Type, _id: usize, pspec: &glib::ParamSpec) -> glib::Value {
+        let settings = self.settings.lock().unwrap();
+        match pspec.name() {
+            "context" => settings.context.to_value(),
+            "context-wait" => (settings.context_wait.as_millis() as u32).to_value(),
+            "width" => settings.width.to_value(),
+            "height" => settings.height.to_value(),
+            "framerate" => settings.framerate.to_value(),
+            "pattern" => settings.pattern.to_value(),
+            "num-buffers" => settings.num_buffers.to_value(),
+            _ => unimplemented!(),
+        }
+    }
+
+    fn constructed(&self, obj: &Self::Type) {
+        self.parent_constructed(obj);
+
+        obj.add_pad(self.src_pad.gst_pad()).unwrap();
+
+        crate::set_element_flags(obj, gst::ElementFlags::SOURCE);
+    }
+}
+
+impl GstObjectImpl for VideoTestSrc {}
+
+impl ElementImpl for VideoTestSrc {
+    fn metadata() -> Option<&'static gst::subclass::ElementMetadata> {
+        static ELEMENT_METADATA: Lazy<gst::subclass::ElementMetadata> = Lazy::new(|| {
+            gst::subclass::ElementMetadata::new(
+                "Thread-sharing video test source",
+                "Source/Video",
+                "Generates live raw video frames on Context timers",
+                "Sebastian Dröge <sebastian@centricular.com>",
+            )
+        });
+
+        Some(&*ELEMENT_METADATA)
+    }
+
+    fn pad_templates() -> &'static [gst::PadTemplate] {
+        static PAD_TEMPLATES: Lazy<Vec<gst::PadTemplate>> = Lazy::new(|| {
+            let caps = gst::Caps::builder("video/x-raw")
+                .field("format", "RGBA")
+                .field("width", gst::IntRange::new(1, MAX_DIMENSION as i32))
+                .field("height", gst::IntRange::new(1, MAX_DIMENSION as i32))
+                .field(
+                    "framerate",
+                    gst::FractionRange::new(
+                        gst::Fraction::new(1, i32::MAX),
+                        gst::Fraction::new(MAX_FRAMERATE, 1),
+                    ),
+                )
+                .build();
+            let src_pad_template = gst::PadTemplate::new(
+                "src",
+                gst::PadDirection::Src,
+                gst::PadPresence::Always,
+                &caps,
+            )
+            .unwrap();
+
+            vec![src_pad_template]
+        });
+
+        PAD_TEMPLATES.as_ref()
+    }
+
+    fn change_state(
+        &self,
+        element: &Self::Type,
+        transition: gst::StateChange,
+    ) -> Result<gst::StateChangeSuccess, gst::StateChangeError> {
+        gst_trace!(CAT, obj: element, "Changing state {:?}", transition);
+
+        match transition {
+            gst::StateChange::NullToReady => {
+                self.prepare(element).map_err(|err| {
+                    element.post_error_message(err);
+                    gst::StateChangeError
+                })?;
+            }
+            gst::StateChange::PlayingToPaused => {
+                self.pause(element).map_err(|_| gst::StateChangeError)?;
+            }
+            gst::StateChange::ReadyToNull => {
+                self.unprepare(element);
+            }
+            _ => (),
+        }
+
+        let mut success = self.parent_change_state(element, transition)?;
+
+        match transition {
+            gst::StateChange::ReadyToPaused => {
+                success = gst::StateChangeSuccess::NoPreroll;
+            }
+            gst::StateChange::PausedToPlaying => {
+                self.start(element).map_err(|_| gst::StateChangeError)?;
+            }
+            gst::StateChange::PlayingToPaused => {
+                success = gst::StateChangeSuccess::NoPreroll;
+            }
+            gst::StateChange::PausedToReady => {
+                self.stop(element).map_err(|_| gst::StateChangeError)?;
+            }
+            _ => (),
+        }
+
+        Ok(success)
+    }
+}
@@ -0,0 +1,51 @@
+// Copyright (C) 2026 agent <agent@local>
+//
+// This library is free software; you can redistribute it and/or
+// modify it under the terms of the GNU Library General Public
+// License as published by the Free Software Foundation; either
+// version 2 of the License, or (at your option) any later version.
+//
+// This library is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the GNU
+// Library General Public License for more details.
+//
+// You should have received a copy of the GNU Library General Public
+// License along with this library; if not, write to the
+// Free Software Foundation, Inc., 51 Franklin Street, Suite 500,
+// Boston, MA 02110-1335, USA.
+
+use gst::glib;
+use gst::prelude::*;
+
+mod imp;
+
+#[derive(Debug, Eq, PartialEq, Ord, PartialOrd, Hash, Clone, Copy, glib::Enum)]
+#[repr(u32)]
+#[enum_type(name = "GstTsVideoTestSrcPattern")]
+pub enum VideoTestSrcPattern {
+    #[enum_value(name = "Bars: Vertical color bars.", nick = "bars")]
+    Bars,
+    #[enum_value(name = "Black: Black frames.", nick = "black")]
+    Black,
+    #[enum_value(name = "White: White frames.", nick = "white")]
+    White,
+}
+
+glib::wrapper! {
+    pub struct VideoTestSrc(ObjectSubclass<imp::VideoTestSrc>) @extends gst::Element, gst::Object;
+}
+
+// GStreamer elements need to be thread-safe. For the private implementation this is automatically
+// enforced but for the public wrapper type we need to specify this manually.
+unsafe impl Send for VideoTestSrc {}
+unsafe impl Sync for VideoTestSrc {}
+
+pub fn register(plugin: &gst::Plugin) -> Result<(), glib::BoolError> {
+    gst::Element::register(
+        Some(plugin),
+        "ts-videotestsrc",
+        gst::Rank::None,
+        VideoTestSrc::static_type(),
+    )
+}
@@ -0,0 +1,128 @@
+// Copyright (C) 2026 agent <agent@local>
+//
+// This library is free software; you can redistribute it and/or
+// modify it under the terms of the GNU Library General Public
+// License as published by the Free Software Foundation; either
+// version 2 of the License, or (at your option) any later version.
+//
+// This library is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the GNU
+// Library General Public License for more details.
+//
+// You should have received a copy of the GNU Library General Public
+// License along with this library; if not, write to the
+// Free Software Foundation, Inc., 51 Franklin Street, Suite 500,
+// Boston, MA 02110-1335, USA.
+
+use gst::prelude::*;
+
+fn init() {
+    use std::sync::Once;
+    static INIT: Once = Once::new();
+
+    INIT.call_once(|| {
+        gst::init().unwrap();
+        gstthreadshare::plugin_register_static().expect("gstthreadshare audiotestsrc test");
+    });
+}
+
+#[test]
+fn push() {
+    init();
+
+    let mut h = gst_check::Harness::new("ts-audiotestsrc");
+    {
+        let src = h.element().unwrap();
+        src.set_property("context", "audiotestsrc-push");
+        src.set_property("rate", 8000u32);
+        src.set_property("channels", 2u32);
+        src.set_property("samples-per-buffer", 80u32);
+        src.set_property("num-buffers", 3i32);
+    }
+
+    h.play();
+
+    // Buffers are timestamped from the sample count: 80 samples at 8kHz are 10ms
+    for i in 0..3u64 {
+        let buffer = h.pull().unwrap();
+        assert_eq!(buffer.size(), 80 * 2 * 2);
+        assert_eq!(buffer.pts(), Some(gst::ClockTime::from_mseconds(10 * i)));
+        assert_eq!(buffer.duration(), Some(gst::ClockTime::from_mseconds(10)));
+        assert_eq!(buffer.offset(), 80 * i);
+        assert_eq!(buffer.flags().contains(gst::BufferFlags::DISCONT), i == 0);
+
+        // Both channels carry the same sine wave, which starts at 0
+        let map = buffer.map_readable().unwrap();
+        let samples = map
+            .chunks_exact(2)
+            .map(|s| i16::from_le_bytes([s[0], s[1]]))
+            .collect::<Vec<_>>();
+        for frame in samples.chunks_exact(2) {
+            assert_eq!(frame[0], frame[1]);
+        }
+        if i == 0 {
+            assert_eq!(samples[0], 0);
+            assert!(samples[2] > 0);
+        }
+    }
+
+    let mut n_events = 0;
+    loop {
+        use gst::EventView;
+
+        let event = h.pull_event().unwrap();
+        match event.view() {
+            EventView::StreamStart(..) => {
+                assert_eq!(n_events, 0);
+            }
+            EventView::Caps(ev) => {
+                assert_eq!(n_events, 1);
+                let expected = gst::Caps::builder("audio/x-raw")
+                    .field("format", "S16LE")
+                    .field("layout", "interleaved")
+                    .field("rate", 8000i32)
+                    .field("channels", 2i32)
+                    .build();
+                assert_eq!(expected.as_ref(), ev.caps());
+            }
+            EventView::Segment(..) => {
+                assert_eq!(n_events, 2);
+            }
+            EventView::Eos(..) => {
+                break;
+            }
+            _ => (),
+        }
+        n_events += 1;
+    }
+    assert!(n_events >= 2);
+    assert!(h.try_pull().is_none());
+}
+
+#[test]
+fn latency() {
+    init();
+
+    let mut h = gst_check::Harness::new("ts-audiotestsrc");
+    {
+        let src = h.element().unwrap();
+        src.set_property("context", "audiotestsrc-latency");
+        src.set_property("samples-per-buffer", 480u32);
+    }
+
+    h.play();
+    let _ = h.pull().unwrap();
+
+    let mut q = gst::query::Latency::new();
+    assert!(h
+        .element()
+        .unwrap()
+        .static_pad("src")
+        .unwrap()
+        .query(&mut q));
+
+    let (live, min, _max) = q.result();
+    assert!(live);
+    assert_eq!(min, gst::ClockTime::from_mseconds(10));
+}
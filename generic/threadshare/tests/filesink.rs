@@ -0,0 +1,96 @@
+// Copyright (C) 2026 agent <agent@local>
+//
+// This library is free software; you can redistribute it and/or
+// modify it under the terms of the GNU Library General Public
+// License as published by the Free Software Foundation; either
+// version 2 of the License, or (at your option) any later version.
+//
+// This library is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the GNU
+// Library General Public License for more details.
+//
+// You should have received a copy of the GNU Library General Public
+// License along with this library; if not, write to the
+// Free Software Foundation, Inc., 51 Franklin Street, Suite 500,
+// Boston, MA 02110-1335, USA.
+
+use gst::prelude::*;
+
+fn init() {
+    use std::sync::Once;
+    static INIT: Once = Once::new();
+
+    INIT.call_once(|| {
+        gst::init().unwrap();
+        gstthreadshare::plugin_register_static().expect("gstthreadshare filesink test");
+    });
+}
+
+#[test]
+fn write() {
+    init();
+
+    let path = std::env::temp_dir().join(format!("ts-filesink-write-{}", std::process::id()));
+
+    let mut h = gst_check::Harness::new("ts-filesink");
+    let bus = gst::Bus::new();
+    {
+        let sink = h.element().unwrap();
+        sink.set_property("context", "filesink-write");
+        sink.set_property("location", path.to_str().unwrap());
+        sink.set_bus(Some(&bus));
+    }
+
+    h.play();
+
+    assert_eq!(
+        h.push(gst::Buffer::from_slice(b"Hello ")),
+        Ok(gst::FlowSuccess::Ok)
+    );
+
+    assert_eq!(
+        h.push(gst::Buffer::from_slice(b"World")),
+        Ok(gst::FlowSuccess::Ok)
+    );
+
+    assert!(h.push_event(gst::event::Eos::new()));
+
+    // EOS is only posted once all buffers before it are written
+    let msg = bus
+        .timed_pop_filtered(gst::ClockTime::from_seconds(10), &[gst::MessageType::Eos])
+        .expect("No EOS received");
+    assert_eq!(msg.type_(), gst::MessageType::Eos);
+    assert_eq!(std::fs::read(&path).unwrap(), b"Hello World");
+
+    drop(h);
+    std::fs::remove_file(path).unwrap();
+}
+
+#[test]
+fn location_while_prepared() {
+    init();
+
+    let path = std::env::temp_dir().join(format!("ts-filesink-prepared-{}", std::process::id()));
+
+    let sink = gst::ElementFactory::make("ts-filesink", None).unwrap();
+    sink.set_property("context", "filesink-prepared");
+    sink.set_property("location", path.to_str().unwrap());
+    sink.set_state(gst::State::Ready).unwrap();
+
+    // The file is opened when preparing, so the location can't change anymore
+    sink.set_property("location", "other");
+    assert_eq!(
+        sink.property::<Option<String>>("location").as_deref(),
+        path.to_str()
+    );
+
+    sink.set_state(gst::State::Null).unwrap();
+    sink.set_property("location", "other");
+    assert_eq!(
+        sink.property::<Option<String>>("location").as_deref(),
+        Some("other")
+    );
+
+    std::fs::remove_file(path).unwrap();
+}
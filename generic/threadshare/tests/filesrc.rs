@@ -0,0 +1,151 @@
+// Copyright (C) 2026 agent <agent@local>
+//
+// This library is free software; you can redistribute it and/or
+// modify it under the terms of the GNU Library General Public
+// License as published by the Free Software Foundation; either
+// version 2 of the License, or (at your option) any later version.
+//
+// This library is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the GNU
+// Library General Public License for more details.
+//
+// You should have received a copy of the GNU Library General Public
+// License along with this library; if not, write to the
+// Free Software Foundation, Inc., 51 Franklin Street, Suite 500,
+// Boston, MA 02110-1335, USA.
+
+use gst::glib;
+use gst::prelude::*;
+
+use std::path::PathBuf;
+
+fn init() {
+    use std::sync::Once;
+    static INIT: Once = Once::new();
+
+    INIT.call_once(|| {
+        gst::init().unwrap();
+        gstthreadshare::plugin_register_static().expect("gstthreadshare filesrc test");
+    });
+}
+
+fn temp_file(name: &str, data: &[u8]) -> PathBuf {
+    let path = std::env::temp_dir().join(format!("ts-filesrc-{}-{}", name, std::process::id()));
+    std::fs::write(&path, data).unwrap();
+
+    path
+}
+
+#[test]
+fn read() {
+    init();
+
+    let path = temp_file("read", b"Hello World");
+
+    let mut h = gst_check::Harness::new("ts-filesrc");
+    {
+        let src = h.element().unwrap();
+        src.set_property("context", "filesrc-read");
+        src.set_property("location", path.to_str().unwrap());
+        src.set_property("blocksize", 4u32);
+    }
+
+    h.play();
+
+    let mut data = Vec::new();
+    for (i, expected) in [&b"Hell"[..], b"o Wo", b"rld"].iter().enumerate() {
+        let buffer = h.pull().unwrap();
+        assert_eq!(buffer.offset(), 4 * i as u64);
+        assert_eq!(buffer.offset_end(), 4 * i as u64 + expected.len() as u64);
+
+        let map = buffer.map_readable().unwrap();
+        assert_eq!(map.as_slice(), *expected);
+        data.extend_from_slice(&map);
+    }
+    assert_eq!(data, b"Hello World");
+
+    assert_eq!(
+        h.element().unwrap().query_duration::<gst::format::Bytes>(),
+        Some(gst::format::Bytes(11))
+    );
+
+    let mut n_events = 0;
+    loop {
+        use gst::EventView;
+
+        let event = h.pull_event().unwrap();
+        match event.view() {
+            EventView::StreamStart(..) => {
+                assert_eq!(n_events, 0);
+            }
+            EventView::Segment(ev) => {
+                assert_eq!(n_events, 1);
+                assert_eq!(ev.segment().format(), gst::Format::Bytes);
+            }
+            EventView::Eos(..) => {
+                break;
+            }
+            _ => (),
+        }
+        n_events += 1;
+    }
+    assert!(h.try_pull().is_none());
+
+    drop(h);
+    std::fs::remove_file(path).unwrap();
+}
+
+#[test]
+fn flush() {
+    init();
+
+    let data = (0..64 * 1024).map(|i| (i % 251) as u8).collect::<Vec<_>>();
+    let path = temp_file("flush", &data);
+
+    let mut h = gst_check::Harness::new("ts-filesrc");
+    {
+        let src = h.element().unwrap();
+        src.set_property("context", "filesrc-flush");
+        src.set_property("location", path.to_str().unwrap());
+        src.set_property("blocksize", 16u32);
+    }
+
+    h.play();
+
+    // Flushing can cancel a pending read, the buffers must still match their offsets
+    for _ in 0..50 {
+        let buffer = h.pull().unwrap();
+        let offset = buffer.offset() as usize;
+        let offset_end = buffer.offset_end() as usize;
+        assert_eq!(offset_end - offset, 16);
+
+        let map = buffer.map_readable().unwrap();
+        assert_eq!(map.as_slice(), &data[offset..offset_end]);
+
+        assert!(h.push_upstream_event(gst::event::FlushStart::new()));
+        assert!(h.push_upstream_event(gst::event::FlushStop::new(true)));
+    }
+
+    drop(h);
+    std::fs::remove_file(path).unwrap();
+}
+
+#[test]
+fn uri() {
+    init();
+
+    let path = temp_file("uri", b"");
+    let uri = glib::filename_to_uri(&path, None).unwrap();
+
+    let src = gst::ElementFactory::make("ts-filesrc", None).unwrap();
+    let handler = src.dynamic_cast_ref::<gst::URIHandler>().unwrap();
+    handler.set_uri(&uri).unwrap();
+    assert_eq!(
+        src.property::<Option<String>>("location").as_deref(),
+        path.to_str()
+    );
+    assert_eq!(handler.uri(), Some(uri));
+
+    std::fs::remove_file(path).unwrap();
+}
@@ -18,7 +18,9 @@
 use gst::gst_debug;
 use gst::prelude::*;
 
-use std::sync::mpsc;
+use std::sync::{mpsc, Mutex};
+use std::thread;
+use std::time::Duration;
 
 use once_cell::sync::Lazy;
 
@@ -161,3 +163,91 @@ fn jb_ts_pipeline() {
 
     pipeline.set_state(gst::State::Null).unwrap();
 }
+
+fn rtp_pcma_buffer(seq: u16, rtptime: u32) -> gst::Buffer {
+    let mut data = vec![0x80, 8];
+    data.extend_from_slice(&seq.to_be_bytes());
+    data.extend_from_slice(&rtptime.to_be_bytes());
+    data.extend_from_slice(&0x1234_5678u32.to_be_bytes());
+    data.resize(12 + 160, 0xd5);
+
+    gst::Buffer::from_mut_slice(data)
+}
+
+#[test]
+fn jb_retransmission() {
+    init();
+
+    const LATENCY: u32 = 200;
+    const PACKET_DURATION: Duration = Duration::from_millis(20);
+
+    let pipeline = gst::Pipeline::new(None);
+
+    let src = gst::ElementFactory::make("appsrc", Some("appsrc")).unwrap();
+    let appsrc = src.clone().dynamic_cast::<gst_app::AppSrc>().unwrap();
+    appsrc.set_caps(Some(
+        &gst::Caps::builder("application/x-rtp")
+            .field("media", "audio")
+            .field("payload", 8i32)
+            .field("clock-rate", 8000i32)
+            .field("encoding-name", "PCMA")
+            .build(),
+    ));
+    appsrc.set_format(gst::Format::Time);
+    appsrc.set_is_live(true);
+    appsrc.set_do_timestamp(true);
+
+    let jb = gst::ElementFactory::make("ts-jitterbuffer", Some("ts-jitterbuffer")).unwrap();
+    jb.set_property("context", "jb_retransmission");
+    jb.set_property("latency", LATENCY);
+    jb.set_property("do-retransmission", true);
+
+    let sink = gst::ElementFactory::make("fakesink", Some("fakesink")).unwrap();
+    sink.set_property("sync", false);
+    sink.set_property("async", false);
+
+    pipeline.add_many(&[&src, &jb, &sink]).unwrap();
+    gst::Element::link_many(&[&src, &jb, &sink]).unwrap();
+
+    // Catch the retransmission requests sent upstream by the jitterbuffer
+    let (sender, receiver) = mpsc::channel();
+    let sender = Mutex::new(sender);
+    src.static_pad("src")
+        .unwrap()
+        .add_probe(gst::PadProbeType::EVENT_UPSTREAM, move |_, info| {
+            if let Some(gst::PadProbeData::Event(ref event)) = info.data {
+                if let Some(s) = event.structure() {
+                    if s.name() == "GstRTPRetransmissionRequest" {
+                        let seqnum = s.get::<u32>("seqnum").unwrap();
+                        let _ = sender.lock().unwrap().send(seqnum);
+                    }
+                }
+            }
+
+            gst::PadProbeReturn::Ok
+        });
+
+    pipeline.set_state(gst::State::Playing).unwrap();
+
+    // Packet with seqnum 2 gets lost
+    for seq in [0u16, 1, 3, 4] {
+        appsrc
+            .push_buffer(rtp_pcma_buffer(seq, seq as u32 * 160))
+            .unwrap();
+        thread::sleep(PACKET_DURATION);
+    }
+
+    gst_debug!(CAT, "jb_retransmission: waiting for request of seqnum 2");
+    loop {
+        let seqnum = receiver.recv_timeout(Duration::from_secs(5)).unwrap();
+        gst_debug!(CAT, "jb_retransmission: got request for seqnum {}", seqnum);
+        if seqnum == 2 {
+            break;
+        }
+    }
+
+    let stats = jb.property::<gst::Structure>("stats");
+    assert!(stats.get::<u64>("num-rtx-requests").unwrap() > 0);
+
+    pipeline.set_state(gst::State::Null).unwrap();
+}
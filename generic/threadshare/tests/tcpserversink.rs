@@ -0,0 +1,285 @@
+// Copyright (C) 2026 agent <agent@local>
+//
+// This library is free software; you can redistribute it and/or
+// modify it under the terms of the GNU Library General Public
+// License as published by the Free Software Foundation; either
+// version 2 of the License, or (at your option) any later version.
+//
+// This library is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the GNU
+// Library General Public License for more details.
+//
+// You should have received a copy of the GNU Library General Public
+// License along with this library; if not, write to the
+// Free Software Foundation, Inc., 51 Franklin Street, Suite 500,
+// Boston, MA 02110-1335, USA.
+
+use gst::prelude::*;
+
+use std::io::{Read, Write};
+use std::net::TcpStream;
+use std::{thread, time};
+
+fn init() {
+    use std::sync::Once;
+    static INIT: Once = Once::new();
+
+    INIT.call_once(|| {
+        gst::init().unwrap();
+        gstthreadshare::plugin_register_static().expect("gstthreadshare tcpserversink test");
+    });
+}
+
+fn connect(port: u16) -> TcpStream {
+    // The element starts listening asynchronously
+    for _ in 0..100 {
+        if let Ok(socket) = TcpStream::connect(("127.0.0.1", port)) {
+            socket
+                .set_read_timeout(Some(time::Duration::from_secs(5)))
+                .unwrap();
+            return socket;
+        }
+        thread::sleep(time::Duration::from_millis(10));
+    }
+
+    panic!("Failed to connect to port {}", port);
+}
+
+fn wait_for_clients(element: &gst::Element, num_clients: u32) {
+    for _ in 0..100 {
+        if element.property::<u32>("num-clients") == num_clients {
+            return;
+        }
+        thread::sleep(time::Duration::from_millis(10));
+    }
+
+    panic!("Expected {} clients", num_clients);
+}
+
+#[test]
+fn test_fan_out() {
+    init();
+
+    let mut h = gst_check::Harness::new("ts-tcpserversink");
+    let tcpserversink = h.element().unwrap();
+    tcpserversink.set_property("port", 5010i32);
+    h.play();
+
+    let header = gst::Buffer::from_slice(b"HEADER");
+    let mut caps = gst::Caps::builder("foo/bar").build();
+    caps.get_mut()
+        .unwrap()
+        .structure_mut(0)
+        .unwrap()
+        .set("streamheader", gst::Array::new(&[&header]));
+    h.set_src_caps(caps);
+
+    // Buffers without any clients are dropped. Once the second one is accepted, the caps and
+    // their stream headers were handled too
+    for _ in 0..2 {
+        h.push(gst::Buffer::from_slice(b"ignored")).unwrap();
+    }
+
+    let mut clients = vec![connect(5010), connect(5010)];
+    wait_for_clients(&tcpserversink, 2);
+
+    h.push(gst::Buffer::from_slice(b"Hello")).unwrap();
+    h.push(gst::Buffer::from_slice(b"World")).unwrap();
+
+    for client in &mut clients {
+        let mut data = [0; 16];
+        client.read_exact(&mut data).unwrap();
+        assert_eq!(&data, b"HEADERHelloWorld");
+    }
+
+    // Disconnected clients are removed once sending to them failed
+    drop(clients.pop());
+    for _ in 0..100 {
+        h.push(gst::Buffer::from_slice(b"data")).unwrap();
+        if tcpserversink.property::<u32>("num-clients") == 1 {
+            break;
+        }
+        thread::sleep(time::Duration::from_millis(10));
+    }
+    assert_eq!(tcpserversink.property::<u32>("num-clients"), 1);
+}
+
+#[test]
+fn test_late_client_starts_at_keyframe() {
+    init();
+
+    let mut h = gst_check::Harness::new("ts-tcpserversink");
+    let tcpserversink = h.element().unwrap();
+    tcpserversink.set_property("port", 5013i32);
+    h.play();
+
+    let mut client = connect(5013);
+    wait_for_clients(&tcpserversink, 1);
+
+    let delta_unit = |data: &'static [u8]| {
+        let mut buffer = gst::Buffer::from_slice(data);
+        buffer
+            .get_mut()
+            .unwrap()
+            .set_flags(gst::BufferFlags::DELTA_UNIT);
+        buffer
+    };
+
+    // The client joined in the middle of the stream, so everything up to the next keyframe
+    // is useless to it
+    h.push(delta_unit(b"delta1")).unwrap();
+    h.push(gst::Buffer::from_slice(b"keyfr2")).unwrap();
+    h.push(delta_unit(b"delta3")).unwrap();
+
+    let mut data = [0; 12];
+    client.read_exact(&mut data).unwrap();
+    assert_eq!(&data, b"keyfr2delta3");
+}
+
+#[test]
+fn test_slow_client_disconnect() {
+    init();
+
+    let mut h = gst_check::Harness::new("ts-tcpserversink");
+    let tcpserversink = h.element().unwrap();
+    tcpserversink.set_property("port", 5011i32);
+    tcpserversink.set_property("buffers-max", 1u32);
+    tcpserversink.set_property_from_str("slow-client-policy", "disconnect");
+    h.play();
+
+    let mut client = connect(5011);
+    wait_for_clients(&tcpserversink, 1);
+
+    // The client never reads, so its queue fills up once the socket buffers are full
+    let data = vec![0u8; 64 * 1024];
+    for _ in 0..1000 {
+        h.push(gst::Buffer::from_slice(data.clone())).unwrap();
+        if tcpserversink.property::<u32>("num-clients") == 0 {
+            break;
+        }
+    }
+    assert_eq!(tcpserversink.property::<u32>("num-clients"), 0);
+
+    // Everything that was sent before is still received before the connection is closed
+    let mut received = Vec::new();
+    let _ = client.read_to_end(&mut received);
+    assert!(!received.is_empty());
+}
+
+#[test]
+fn test_slow_client_drop() {
+    const FRAME_SIZE: usize = 64 * 1024;
+    const NUM_FRAMES: u64 = 1000;
+
+    init();
+
+    let mut h = gst_check::Harness::new("ts-tcpserversink");
+    let tcpserversink = h.element().unwrap();
+    tcpserversink.set_property("port", 5012i32);
+    tcpserversink.set_property("buffers-max", 5u32);
+    tcpserversink.set_property_from_str("slow-client-policy", "drop");
+    h.play();
+
+    let mut client = connect(5012);
+    wait_for_clients(&tcpserversink, 1);
+
+    // Each frame carries its sequence number, every 10th frame is a keyframe
+    let frame = |seq: u64, keyframe: bool| {
+        let mut data = vec![0u8; FRAME_SIZE];
+        data[0] = keyframe as u8;
+        data[1..9].copy_from_slice(&seq.to_be_bytes());
+        let mut buffer = gst::Buffer::from_slice(data);
+        if !keyframe {
+            buffer
+                .get_mut()
+                .unwrap()
+                .set_flags(gst::BufferFlags::DELTA_UNIT);
+        }
+        buffer
+    };
+
+    // The client doesn't read yet, so its queue overflows
+    for seq in 0..NUM_FRAMES {
+        h.push(frame(seq, seq % 10 == 0)).unwrap();
+    }
+    assert_eq!(tcpserversink.property::<u32>("num-clients"), 1);
+
+    let done = std::sync::Arc::new(std::sync::atomic::AtomicBool::new(false));
+    let reader = thread::spawn({
+        let done = done.clone();
+        move || {
+            let mut data = vec![0u8; FRAME_SIZE];
+            let mut prev_seq = None;
+            let mut gaps = 0;
+
+            loop {
+                client.read_exact(&mut data).unwrap();
+                let keyframe = data[0] == 1;
+                let seq = u64::from_be_bytes(data[1..9].try_into().unwrap());
+
+                // Frames are only ever dropped up to the next keyframe
+                if let Some(prev_seq) = prev_seq {
+                    if seq != prev_seq + 1 {
+                        assert!(keyframe, "Delta frame {} after {}", seq, prev_seq);
+                        gaps += 1;
+                    }
+                }
+                prev_seq = Some(seq);
+
+                if seq >= NUM_FRAMES {
+                    done.store(true, std::sync::atomic::Ordering::SeqCst);
+                    return gaps;
+                }
+            }
+        }
+    });
+
+    // Keep sending keyframes until one of them arrived after the client caught up
+    for seq in NUM_FRAMES..NUM_FRAMES + 1000 {
+        if done.load(std::sync::atomic::Ordering::SeqCst) {
+            break;
+        }
+        h.push(frame(seq, true)).unwrap();
+        thread::sleep(time::Duration::from_millis(10));
+    }
+
+    assert!(reader.join().unwrap() > 0);
+}
+
+#[test]
+fn test_eos_after_drain() {
+    const FRAME_SIZE: usize = 64 * 1024;
+    const NUM_FRAMES: usize = 256;
+
+    init();
+
+    let mut h = gst_check::Harness::new("ts-tcpserversink");
+    let tcpserversink = h.element().unwrap();
+    tcpserversink.set_property("port", 5014i32);
+    tcpserversink.set_property("buffers-max", NUM_FRAMES as u32 + 1);
+    let bus = gst::Bus::new();
+    tcpserversink.set_bus(Some(&bus));
+    h.play();
+
+    let mut client = connect(5014);
+    wait_for_clients(&tcpserversink, 1);
+
+    // The client doesn't read yet, and this is far more than fits into the socket buffers
+    for _ in 0..NUM_FRAMES {
+        h.push(gst::Buffer::from_slice(vec![0u8; FRAME_SIZE]))
+            .unwrap();
+    }
+    assert!(h.push_event(gst::event::Eos::new()));
+
+    assert!(bus
+        .timed_pop_filtered(gst::ClockTime::from_mseconds(200), &[gst::MessageType::Eos])
+        .is_none());
+
+    let mut data = vec![0u8; NUM_FRAMES * FRAME_SIZE];
+    client.read_exact(&mut data).unwrap();
+
+    assert!(bus
+        .timed_pop_filtered(gst::ClockTime::from_seconds(5), &[gst::MessageType::Eos])
+        .is_some());
+}
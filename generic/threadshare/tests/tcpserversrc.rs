@@ -0,0 +1,118 @@
+// Copyright (C) 2026 agent <agent@local>
+//
+// This library is free software; you can redistribute it and/or
+// modify it under the terms of the GNU Library General Public
+// License as published by the Free Software Foundation; either
+// version 2 of the License, or (at your option) any later version.
+//
+// This library is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the GNU
+// Library General Public License for more details.
+//
+// You should have received a copy of the GNU Library General Public
+// License along with this library; if not, write to the
+// Free Software Foundation, Inc., 51 Franklin Street, Suite 500,
+// Boston, MA 02110-1335, USA.
+
+use gst::prelude::*;
+
+use std::io::Write;
+use std::net::TcpStream;
+use std::sync::{Arc, Mutex};
+use std::{thread, time};
+
+fn init() {
+    use std::sync::Once;
+    static INIT: Once = Once::new();
+
+    INIT.call_once(|| {
+        gst::init().unwrap();
+        gstthreadshare::plugin_register_static().expect("gstthreadshare tcpserversrc test");
+    });
+}
+
+#[test]
+fn test_push() {
+    init();
+
+    let pipeline = gst::Pipeline::new(None);
+
+    let tcpserversrc = gst::ElementFactory::make("ts-tcpserversrc", None).unwrap();
+    let appsink = gst::ElementFactory::make("appsink", None).unwrap();
+    appsink.set_property("sync", false);
+    appsink.set_property("async", false);
+
+    pipeline.add_many(&[&tcpserversrc, &appsink]).unwrap();
+    tcpserversrc.link(&appsink).unwrap();
+
+    let caps = gst::Caps::builder("foo/bar").build();
+    tcpserversrc.set_property("caps", &caps);
+    tcpserversrc.set_property("port", 5012i32);
+
+    appsink.set_property("emit-signals", true);
+
+    let samples = Arc::new(Mutex::new(Vec::new()));
+
+    let appsink = appsink.dynamic_cast::<gst_app::AppSink>().unwrap();
+    let samples_clone = samples.clone();
+    appsink.set_callbacks(
+        gst_app::AppSinkCallbacks::builder()
+            .new_sample(move |appsink| {
+                let sample = appsink.pull_sample().unwrap();
+
+                let mut samples = samples_clone.lock().unwrap();
+                samples.push(sample);
+                Ok(gst::FlowSuccess::Ok)
+            })
+            .build(),
+    );
+
+    pipeline.set_state(gst::State::Playing).unwrap();
+
+    let handler = thread::spawn(move || {
+        // The element starts listening asynchronously
+        let mut socket = loop {
+            if let Ok(socket) = TcpStream::connect("127.0.0.1:5012") {
+                break socket;
+            }
+            thread::sleep(time::Duration::from_millis(10));
+        };
+
+        let buffer = [0; 160];
+        for _ in 0..3 {
+            socket.write_all(&buffer).unwrap();
+            thread::sleep(time::Duration::from_millis(20));
+        }
+    });
+
+    // The stream ends once the client disconnected
+    let mut eos = false;
+    let bus = pipeline.bus().unwrap();
+    while let Some(msg) = bus.timed_pop(5 * gst::ClockTime::SECOND) {
+        use gst::MessageView;
+        match msg.view() {
+            MessageView::Eos(..) => {
+                eos = true;
+                break;
+            }
+            MessageView::Error(err) => panic!("{:?}", err),
+            _ => (),
+        }
+    }
+
+    assert!(eos);
+    let samples = samples.lock().unwrap();
+    for sample in samples.iter() {
+        assert_eq!(Some(caps.as_ref()), sample.caps());
+    }
+
+    let total_received_size = samples
+        .iter()
+        .fold(0, |acc, sample| acc + sample.buffer().unwrap().size());
+    assert_eq!(total_received_size, 3 * 160);
+
+    pipeline.set_state(gst::State::Null).unwrap();
+
+    handler.join().unwrap();
+}
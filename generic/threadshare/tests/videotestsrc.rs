@@ -0,0 +1,99 @@
+// Copyright (C) 2026 agent <agent@local>
+//
+// This library is free software; you can redistribute it and/or
+// modify it under the terms of the GNU Library General Public
+// License as published by the Free Software Foundation; either
+// version 2 of the License, or (at your option) any later version.
+//
+// This library is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the GNU
+// Library General Public License for more details.
+//
+// You should have received a copy of the GNU Library General Public
+// License along with this library; if not, write to the
+// Free Software Foundation, Inc., 51 Franklin Street, Suite 500,
+// Boston, MA 02110-1335, USA.
+
+use gst::prelude::*;
+
+fn init() {
+    use std::sync::Once;
+    static INIT: Once = Once::new();
+
+    INIT.call_once(|| {
+        gst::init().unwrap();
+        gstthreadshare::plugin_register_static().expect("gstthreadshare videotestsrc test");
+    });
+}
+
+#[test]
+fn push() {
+    init();
+
+    let mut h = gst_check::Harness::new("ts-videotestsrc");
+    {
+        let src = h.element().unwrap();
+        src.set_property("context", "videotestsrc-push");
+        src.set_property("width", 7u32);
+        src.set_property("height", 2u32);
+        src.set_property("framerate", gst::Fraction::new(50, 1));
+        src.set_property("num-buffers", 3i32);
+    }
+
+    h.play();
+
+    for i in 0..3u64 {
+        let buffer = h.pull().unwrap();
+        assert_eq!(buffer.size(), 7 * 2 * 4);
+        assert_eq!(buffer.pts(), Some(gst::ClockTime::from_mseconds(20 * i)));
+        assert_eq!(buffer.duration(), Some(gst::ClockTime::from_mseconds(20)));
+        assert_eq!(buffer.offset(), i);
+
+        // One pixel per bar, starting with grey and ending with blue
+        let map = buffer.map_readable().unwrap();
+        assert_eq!(&map[0..4], &[191, 191, 191, 255]);
+        assert_eq!(&map[24..28], &[0, 0, 191, 255]);
+        assert_eq!(&map[28..32], &[191, 191, 191, 255]);
+    }
+
+    let caps = h.sinkpad().unwrap().current_caps().unwrap();
+    assert_eq!(
+        caps,
+        gst::Caps::builder("video/x-raw")
+            .field("format", "RGBA")
+            .field("width", 7i32)
+            .field("height", 2i32)
+            .field("framerate", gst::Fraction::new(50, 1))
+            .build()
+    );
+
+    loop {
+        let event = h.pull_event().unwrap();
+        if let gst::EventView::Eos(..) = event.view() {
+            break;
+        }
+    }
+    assert!(h.try_pull().is_none());
+}
+
+#[test]
+fn pattern() {
+    init();
+
+    let mut h = gst_check::Harness::new("ts-videotestsrc");
+    {
+        let src = h.element().unwrap();
+        src.set_property("context", "videotestsrc-pattern");
+        src.set_property("width", 4u32);
+        src.set_property("height", 4u32);
+        src.set_property_from_str("pattern", "white");
+        src.set_property("num-buffers", 1i32);
+    }
+
+    h.play();
+
+    let buffer = h.pull().unwrap();
+    let map = buffer.map_readable().unwrap();
+    assert!(map.iter().all(|&v| v == 255));
+}
@@ -0,0 +1,917 @@
+//
+// Copyright (C) 2026 agent <agent@local>
+//
+// This Source Code Form is subject to the terms of the Mozilla Public License, v2.0.
+// If a copy of the MPL was not distributed with this file, You can obtain one at
+// <https://mozilla.org/MPL/2.0/>.
+//
+// SPDX-License-Identifier: MPL-2.0
+
+use crate::imp::{delete_fragment, new_file_stream, path_basename};
+use crate::mpd::{ContentType, Mpd, Representation};
+use crate::multivariantsink::codec_string;
+use crate::playlist::{format_date_time, SegmentFormatter};
+use gio::prelude::*;
+use glib::subclass::prelude::*;
+use gst::prelude::*;
+use gst::subclass::prelude::*;
+use gst::{gst_debug, gst_error, gst_info, gst_trace};
+use once_cell::sync::Lazy;
+use std::collections::VecDeque;
+use std::io::Write;
+use std::sync::Mutex;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+const DEFAULT_MPD_LOCATION: &str = "manifest.mpd";
+const DEFAULT_LOCATION: &str = "%s_segment%05d.m4s";
+const DEFAULT_INIT_LOCATION: &str = "%s_init.mp4";
+const DEFAULT_MAX_NUM_SEGMENT_FILES: u32 = 10;
+const DEFAULT_TARGET_DURATION: u32 = 15;
+const DEFAULT_PLAYLIST_LENGTH: u32 = 5;
+const DEFAULT_DYNAMIC: bool = true;
+
+const SIGNAL_GET_PLAYLIST_STREAM: &str = "get-playlist-stream";
+const SIGNAL_GET_FRAGMENT_STREAM: &str = "get-fragment-stream";
+const SIGNAL_DELETE_FRAGMENT: &str = "delete-fragment";
+
+static CAT: Lazy<gst::DebugCategory> = Lazy::new(|| {
+    gst::DebugCategory::new("dashsink", gst::DebugColorFlags::empty(), Some("DASH sink"))
+});
+
+#[derive(Debug, Clone)]
+struct Settings {
+    mpd_location: String,
+    mpd_root: Option<String>,
+    location: String,
+    init_location: String,
+    max_num_segment_files: u32,
+    target_duration: u32,
+    playlist_length: u32,
+    dynamic: bool,
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Self {
+            mpd_location: String::from(DEFAULT_MPD_LOCATION),
+            mpd_root: None,
+            location: String::from(DEFAULT_LOCATION),
+            init_location: String::from(DEFAULT_INIT_LOCATION),
+            max_num_segment_files: DEFAULT_MAX_NUM_SEGMENT_FILES,
+            target_duration: DEFAULT_TARGET_DURATION,
+            playlist_length: DEFAULT_PLAYLIST_LENGTH,
+            dynamic: DEFAULT_DYNAMIC,
+        }
+    }
+}
+
+impl Settings {
+    /// URI of a file inside the MPD, relative to the MPD root.
+    fn mpd_uri(&self, location: &str) -> String {
+        let filename = path_basename(location);
+
+        if let Some(mpd_root) = &self.mpd_root {
+            format!("{}/{}", mpd_root, filename)
+        } else {
+            filename
+        }
+    }
+}
+
+/// A single representation, i.e. one request pad muxed by its own `dashmp4mux`.
+struct Stream {
+    content_type: ContentType,
+    name: String,
+    pad: gst::GhostPad,
+    mux: gst::Element,
+    appsink: gst_app::AppSink,
+
+    // Locations with the `%s` replaced by the pad name
+    location: String,
+    init_location: String,
+    segment_formatter: SegmentFormatter,
+    next_segment_idx: u32,
+    old_segment_locations: VecDeque<String>,
+
+    // From the language-code and bitrate tags
+    language: Option<String>,
+    bitrate: Option<u32>,
+    // Peak bitrate of all segments so far
+    measured_bitrate: u64,
+}
+
+struct State {
+    streams: Vec<Stream>,
+    mpd: Mpd,
+    // Time since the UNIX epoch at running time zero
+    availability_start_time: Option<Duration>,
+    // Whether the MPD was written at least once
+    mpd_written: bool,
+}
+
+impl Default for State {
+    fn default() -> Self {
+        Self {
+            streams: Vec::new(),
+            mpd: Mpd::new(DEFAULT_DYNAMIC, DEFAULT_TARGET_DURATION, 0),
+            availability_start_time: None,
+            mpd_written: false,
+        }
+    }
+}
+
+#[derive(Default)]
+pub struct DashSink {
+    settings: Mutex<Settings>,
+    state: Mutex<State>,
+}
+
+impl DashSink {
+    fn start(&self, element: &super::DashSink) {
+        gst_info!(CAT, obj: element, "Starting");
+
+        let settings = self.settings.lock().unwrap();
+        let mut state = self.state.lock().unwrap();
+
+        let max_segments = if settings.dynamic {
+            settings.playlist_length as usize
+        } else {
+            0
+        };
+        state.mpd = Mpd::new(settings.dynamic, settings.target_duration, max_segments);
+        state.availability_start_time = None;
+        state.mpd_written = false;
+
+        for stream in &mut state.streams {
+            stream.next_segment_idx = 0;
+            stream.old_segment_locations.clear();
+            stream.measured_bitrate = 0;
+        }
+    }
+
+    /// Handles the output of the `dashmp4mux` of a stream.
+    ///
+    /// Each buffer list contains one fragment, which becomes one segment, and the first one is
+    /// preceded by the initialization segment.
+    fn on_stream_sample(
+        &self,
+        element: &super::DashSink,
+        name: &str,
+        sample: &gst::SampleRef,
+    ) -> Result<gst::FlowSuccess, gst::FlowError> {
+        let mut buffers = match (sample.buffer_list(), sample.buffer()) {
+            (Some(list), _) => list.iter().collect::<Vec<_>>(),
+            (None, Some(buffer)) => vec![buffer],
+            (None, None) => return Ok(gst::FlowSuccess::Ok),
+        };
+
+        if buffers.first().map_or(false, |buffer| {
+            buffer
+                .flags()
+                .contains(gst::BufferFlags::DISCONT | gst::BufferFlags::HEADER)
+        }) {
+            let init_segment = buffers.remove(0);
+            self.write_init_segment(element, name, init_segment)?;
+        }
+
+        let fragment_header = match buffers.first() {
+            Some(buffer) => *buffer,
+            None => return Ok(gst::FlowSuccess::Ok),
+        };
+        let (start, duration) = match (fragment_header.pts(), fragment_header.duration()) {
+            (Some(pts), Some(duration)) => (pts, duration),
+            _ => {
+                gst_error!(CAT, obj: element, "Fragment without timestamp or duration");
+                return Err(gst::FlowError::Error);
+            }
+        };
+
+        let location = {
+            let mut state = self.state.lock().unwrap();
+            let stream = state
+                .streams
+                .iter_mut()
+                .find(|s| s.name == name)
+                .ok_or(gst::FlowError::Flushing)?;
+
+            let location = stream.segment_formatter.segment(stream.next_segment_idx);
+            stream.next_segment_idx += 1;
+
+            location
+        };
+
+        gst_info!(CAT, obj: element, "New segment location: {}", location);
+        self.write_file(element, &location, &buffers)?;
+
+        let size = buffers
+            .iter()
+            .map(|buffer| buffer.size() as u64)
+            .sum::<u64>();
+        let bitrate = (size * 8)
+            .mul_div_ceil(gst::ClockTime::SECOND.nseconds(), duration.nseconds())
+            .unwrap_or(0);
+
+        let old_segment_locations = {
+            let settings = self.settings.lock().unwrap();
+            let mut state = self.state.lock().unwrap();
+            let state = &mut *state;
+
+            let stream = state
+                .streams
+                .iter_mut()
+                .find(|s| s.name == name)
+                .ok_or(gst::FlowError::Flushing)?;
+            stream.measured_bitrate = stream.measured_bitrate.max(bitrate);
+            let bandwidth = stream.bitrate.map_or(stream.measured_bitrate, u64::from);
+
+            // Segments are only deleted while the MPD is a sliding window
+            stream.old_segment_locations.push_back(location);
+            let max_num_segments = settings.max_num_segment_files as usize;
+            let mut old_segment_locations = Vec::new();
+            if state.mpd.is_dynamic() && max_num_segments > 0 {
+                while stream.old_segment_locations.len() > max_num_segments {
+                    old_segment_locations.push(stream.old_segment_locations.pop_front().unwrap());
+                }
+            }
+
+            if let Some(representation) = state.mpd.representation_mut(name) {
+                representation.bandwidth = bandwidth;
+            }
+            state.mpd.add_segment(
+                name,
+                start.mseconds(),
+                (start + duration).mseconds() - start.mseconds(),
+            );
+
+            old_segment_locations
+        };
+
+        self.write_mpd(element)?;
+
+        for old_segment_location in old_segment_locations {
+            if !element.emit_by_name::<bool>(SIGNAL_DELETE_FRAGMENT, &[&old_segment_location]) {
+                gst_error!(CAT, obj: element, "Could not delete fragment");
+            }
+        }
+
+        Ok(gst::FlowSuccess::Ok)
+    }
+
+    /// Writes the initialization segment of a stream and adds its representation to the MPD.
+    fn write_init_segment(
+        &self,
+        element: &super::DashSink,
+        name: &str,
+        init_segment: &gst::BufferRef,
+    ) -> Result<(), gst::FlowError> {
+        let (init_location, representation) = {
+            let settings = self.settings.lock().unwrap();
+            let state = self.state.lock().unwrap();
+            let stream = state
+                .streams
+                .iter()
+                .find(|s| s.name == name)
+                .ok_or(gst::FlowError::Flushing)?;
+
+            let caps = stream
+                .pad
+                .target()
+                .and_then(|pad| pad.current_caps())
+                .ok_or_else(|| {
+                    gst_error!(CAT, obj: element, "No caps for {}", name);
+                    gst::FlowError::NotNegotiated
+                })?;
+
+            (
+                stream.init_location.clone(),
+                representation(&settings, stream, &caps),
+            )
+        };
+
+        gst_info!(CAT, obj: element, "New init segment location: {}", init_location);
+        self.write_file(element, &init_location, &[init_segment])?;
+
+        self.state
+            .lock()
+            .unwrap()
+            .mpd
+            .add_representation(representation);
+
+        Ok(())
+    }
+
+    fn write_file(
+        &self,
+        element: &super::DashSink,
+        location: &str,
+        buffers: &[&gst::BufferRef],
+    ) -> Result<(), gst::FlowError> {
+        let mut stream = element
+            .emit_by_name::<Option<gio::OutputStream>>(SIGNAL_GET_FRAGMENT_STREAM, &[&location])
+            .ok_or_else(|| {
+                gst_error!(CAT, obj: element, "Error while getting fragment stream");
+                gst::FlowError::Error
+            })?
+            .into_write();
+
+        for buffer in buffers {
+            let map = buffer.map_readable().map_err(|_| gst::FlowError::Error)?;
+            stream.write_all(&map).map_err(|err| {
+                gst_error!(CAT, obj: element, "Could not write {}: {}", location, err);
+                gst::FlowError::Error
+            })?;
+        }
+        stream.flush().map_err(|err| {
+            gst_error!(CAT, obj: element, "Could not flush {}: {}", location, err);
+            gst::FlowError::Error
+        })?;
+
+        Ok(())
+    }
+
+    /// Writes the MPD once all streams have at least one segment.
+    fn write_mpd(&self, element: &super::DashSink) -> Result<(), gst::FlowError> {
+        let (mpd_location, content) = {
+            let mpd_location = self.settings.lock().unwrap().mpd_location.clone();
+            let mut state = self.state.lock().unwrap();
+
+            if state.streams.is_empty()
+                || !state.streams.iter().all(|s| {
+                    state
+                        .mpd
+                        .representation(&s.name)
+                        .map_or(false, |r| !r.is_empty())
+                })
+            {
+                gst_trace!(CAT, obj: element, "Not all streams have segments yet");
+                return Ok(());
+            }
+
+            let now = SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .unwrap_or(Duration::ZERO);
+            if state.availability_start_time.is_none() {
+                state.availability_start_time = running_time_zero(element, now);
+            }
+            if let Some(availability_start_time) = state.availability_start_time {
+                state
+                    .mpd
+                    .set_availability_start_time(format_date_time(availability_start_time));
+            }
+            state.mpd.set_publish_time(format_date_time(now));
+
+            let mut content = Vec::new();
+            state.mpd.write_to(&mut content).map_err(|err| {
+                gst_error!(CAT, obj: element, "Could not create MPD: {}", err);
+                gst::FlowError::Error
+            })?;
+            state.mpd_written = true;
+
+            (mpd_location, content)
+        };
+
+        gst_debug!(CAT, obj: element, "Writing MPD {}", mpd_location);
+
+        let mut stream = element
+            .emit_by_name::<Option<gio::OutputStream>>(SIGNAL_GET_PLAYLIST_STREAM, &[&mpd_location])
+            .ok_or_else(|| {
+                gst_error!(CAT, obj: element, "Could not get stream to write MPD");
+                gst::FlowError::Error
+            })?
+            .into_write();
+
+        stream
+            .write_all(&content)
+            .and_then(|_| stream.flush())
+            .map_err(|err| {
+                gst_error!(CAT, obj: element, "Could not write MPD: {}", err);
+                gst::FlowError::Error
+            })
+    }
+
+    /// Turns the MPD static with all remaining segments at the end of the stream.
+    fn write_final_mpd(&self, element: &super::DashSink) {
+        {
+            let mut state = self.state.lock().unwrap();
+            if !state.mpd_written {
+                return;
+            }
+            state.mpd.end();
+        }
+
+        gst_debug!(CAT, obj: element, "Writing final MPD");
+        if self.write_mpd(element).is_err() {
+            gst_error!(CAT, obj: element, "Could not write final MPD");
+        }
+    }
+
+    /// Keeps track of the language and bitrate tags of a stream.
+    fn handle_stream_event(&self, pad: &gst::Pad, event: &gst::EventRef) {
+        let tags = match event.view() {
+            gst::EventView::Tag(ev) => ev.tag(),
+            _ => return,
+        };
+
+        let mut state = self.state.lock().unwrap();
+        let state = &mut *state;
+        let stream = match state
+            .streams
+            .iter_mut()
+            .find(|s| s.pad.upcast_ref::<gst::Pad>() == pad)
+        {
+            Some(stream) => stream,
+            None => return,
+        };
+
+        if let Some(language) = tags.get::<gst::tags::LanguageCode>() {
+            stream.language = Some(language.get().to_string());
+        }
+        if let Some(bitrate) = tags
+            .get::<gst::tags::MaximumBitrate>()
+            .or_else(|| tags.get::<gst::tags::Bitrate>())
+            .or_else(|| tags.get::<gst::tags::NominalBitrate>())
+        {
+            stream.bitrate = Some(bitrate.get());
+        }
+
+        if let Some(representation) = state.mpd.representation_mut(&stream.name) {
+            representation.language = stream.language.clone();
+        }
+    }
+}
+
+/// Creates the representation of a stream with the given caps.
+fn representation(settings: &Settings, stream: &Stream, caps: &gst::CapsRef) -> Representation {
+    let media = SegmentFormatter::new(settings.mpd_uri(&stream.location))
+        .expect("location checked when requesting the pad")
+        .number_template();
+
+    let mut representation = Representation::new(
+        stream.name.clone(),
+        stream.content_type,
+        settings.mpd_uri(&stream.init_location),
+        media,
+        stream.next_segment_idx,
+    );
+
+    let s = caps.structure(0).unwrap();
+    representation.codecs = codec_string(caps);
+    representation.bandwidth = stream.bitrate.map_or(0, u64::from);
+    representation.language = stream.language.clone();
+    match stream.content_type {
+        ContentType::Video => {
+            representation.width = s.get::<i32>("width").ok();
+            representation.height = s.get::<i32>("height").ok();
+            representation.frame_rate = s
+                .get::<gst::Fraction>("framerate")
+                .ok()
+                .filter(|fps| fps.numer() > 0)
+                .map(|fps| (fps.numer(), fps.denom()));
+        }
+        ContentType::Audio => {
+            representation.audio_sampling_rate = s.get::<i32>("rate").ok();
+        }
+        ContentType::Text => (),
+    }
+
+    representation
+}
+
+/// Time since the UNIX epoch at running time zero of the element.
+fn running_time_zero(element: &super::DashSink, now: Duration) -> Option<Duration> {
+    let clock = element.clock()?;
+    let running_time = clock.time()?.checked_sub(element.base_time()?)?;
+
+    now.checked_sub(Duration::from_nanos(running_time.nseconds()))
+}
+
+#[glib::object_subclass]
+impl ObjectSubclass for DashSink {
+    const NAME: &'static str = "GstDashSink";
+    type Type = super::DashSink;
+    type ParentType = gst::Bin;
+}
+
+impl BinImpl for DashSink {}
+
+impl ObjectImpl for DashSink {
+    fn properties() -> &'static [glib::ParamSpec] {
+        static PROPERTIES: Lazy<Vec<glib::ParamSpec>> = Lazy::new(|| {
+            vec![
+                glib::ParamSpecString::new(
+                    "mpd-location",
+                    "MPD Location",
+                    "Location of the MPD to write.",
+                    Some(DEFAULT_MPD_LOCATION),
+                    glib::ParamFlags::READWRITE,
+                ),
+                glib::ParamSpecString::new(
+                    "mpd-root",
+                    "MPD Root",
+                    "Base path for the segments in the MPD file.",
+                    None,
+                    glib::ParamFlags::READWRITE,
+                ),
+                glib::ParamSpecString::new(
+                    "location",
+                    "File Location",
+                    "Location of the segment files to write. The first `%s` is replaced by the representation (pad) name.",
+                    Some(DEFAULT_LOCATION),
+                    glib::ParamFlags::READWRITE,
+                ),
+                glib::ParamSpecString::new(
+                    "init-location",
+                    "Init Location",
+                    "Location of the initialization segments to write. The first `%s` is replaced by the representation (pad) name.",
+                    Some(DEFAULT_INIT_LOCATION),
+                    glib::ParamFlags::READWRITE,
+                ),
+                glib::ParamSpecUInt::new(
+                    "max-files",
+                    "Max files",
+                    "Maximum number of files to keep on disk per representation. Once the maximum is reached, old files start to be deleted to make room for new ones. (0 - unlimited)",
+                    0,
+                    u32::MAX,
+                    DEFAULT_MAX_NUM_SEGMENT_FILES,
+                    glib::ParamFlags::READWRITE,
+                ),
+                glib::ParamSpecUInt::new(
+                    "target-duration",
+                    "Target duration",
+                    "The target duration in seconds of a segment/file, the same for all representations to keep the segments aligned.",
+                    0,
+                    u32::MAX,
+                    DEFAULT_TARGET_DURATION,
+                    glib::ParamFlags::READWRITE,
+                ),
+                glib::ParamSpecUInt::new(
+                    "playlist-length",
+                    "Playlist length",
+                    "Number of segments in the timelines of a dynamic MPD. If set to 0, the timelines will be infinite.",
+                    0,
+                    u32::MAX,
+                    DEFAULT_PLAYLIST_LENGTH,
+                    glib::ParamFlags::READWRITE,
+                ),
+                glib::ParamSpecBoolean::new(
+                    "dynamic",
+                    "Dynamic",
+                    "Write a dynamic MPD with a sliding window of segments that becomes static at the end of the stream, otherwise a static MPD with all segments.",
+                    DEFAULT_DYNAMIC,
+                    glib::ParamFlags::READWRITE,
+                ),
+            ]
+        });
+
+        PROPERTIES.as_ref()
+    }
+
+    fn set_property(
+        &self,
+        _obj: &Self::Type,
+        _id: usize,
+        value: &glib::Value,
+        pspec: &glib::ParamSpec,
+    ) {
+        let mut settings = self.settings.lock().unwrap();
+        match pspec.name() {
+            "mpd-location" => {
+                settings.mpd_location = value
+                    .get::<Option<String>>()
+                    .expect("type checked upstream")
+                    .unwrap_or_else(|| String::from(DEFAULT_MPD_LOCATION));
+            }
+            "mpd-root" => {
+                settings.mpd_root = value
+                    .get::<Option<String>>()
+                    .expect("type checked upstream");
+            }
+            "location" => {
+                settings.location = value
+                    .get::<Option<String>>()
+                    .expect("type checked upstream")
+                    .unwrap_or_else(|| String::from(DEFAULT_LOCATION));
+            }
+            "init-location" => {
+                settings.init_location = value
+                    .get::<Option<String>>()
+                    .expect("type checked upstream")
+                    .unwrap_or_else(|| String::from(DEFAULT_INIT_LOCATION));
+            }
+            "max-files" => {
+                settings.max_num_segment_files = value.get().expect("type checked upstream");
+            }
+            "target-duration" => {
+                settings.target_duration = value.get().expect("type checked upstream");
+            }
+            "playlist-length" => {
+                settings.playlist_length = value.get().expect("type checked upstream");
+            }
+            "dynamic" => {
+                settings.dynamic = value.get().expect("type checked upstream");
+            }
+            _ => unimplemented!(),
+        };
+    }
+
+    fn property(&self, _obj: &Self::Type, _id: usize, pspec: &glib::ParamSpec) -> glib::Value {
+        let settings = self.settings.lock().unwrap();
+        match pspec.name() {
+            "mpd-location" => settings.mpd_location.to_value(),
+            "mpd-root" => settings.mpd_root.to_value(),
+            "location" => settings.location.to_value(),
+            "init-location" => settings.init_location.to_value(),
+            "max-files" => settings.max_num_segment_files.to_value(),
+            "target-duration" => settings.target_duration.to_value(),
+            "playlist-length" => settings.playlist_length.to_value(),
+            "dynamic" => settings.dynamic.to_value(),
+            _ => unimplemented!(),
+        }
+    }
+
+    fn signals() -> &'static [glib::subclass::Signal] {
+        static SIGNALS: Lazy<Vec<glib::subclass::Signal>> = Lazy::new(|| {
+            vec![
+                glib::subclass::Signal::builder(
+                    SIGNAL_GET_PLAYLIST_STREAM,
+                    &[String::static_type().into()],
+                    gio::OutputStream::static_type().into(),
+                )
+                .class_handler(|_, args| {
+                    let element = args[0].get::<gst::Element>().expect("signal arg");
+                    let mpd_location = args[1].get::<String>().expect("signal arg");
+
+                    Some(new_file_stream(&element, &mpd_location).ok()?.to_value())
+                })
+                .accumulator(|_hint, ret, value| {
+                    // First signal handler wins
+                    *ret = value.clone();
+                    false
+                })
+                .build(),
+                glib::subclass::Signal::builder(
+                    SIGNAL_GET_FRAGMENT_STREAM,
+                    &[String::static_type().into()],
+                    gio::OutputStream::static_type().into(),
+                )
+                .class_handler(|_, args| {
+                    let element = args[0].get::<gst::Element>().expect("signal arg");
+                    let fragment_location = args[1].get::<String>().expect("signal arg");
+
+                    Some(
+                        new_file_stream(&element, &fragment_location)
+                            .ok()?
+                            .to_value(),
+                    )
+                })
+                .accumulator(|_hint, ret, value| {
+                    // First signal handler wins
+                    *ret = value.clone();
+                    false
+                })
+                .build(),
+                glib::subclass::Signal::builder(
+                    SIGNAL_DELETE_FRAGMENT,
+                    &[String::static_type().into()],
+                    glib::types::Type::BOOL.into(),
+                )
+                .class_handler(|_, args| {
+                    let element = args[0].get::<gst::Element>().expect("signal arg");
+                    let fragment_location = args[1].get::<String>().expect("signal arg");
+
+                    delete_fragment(&element, &fragment_location);
+                    Some(true.to_value())
+                })
+                .accumulator(|_hint, ret, value| {
+                    // First signal handler wins
+                    *ret = value.clone();
+                    false
+                })
+                .build(),
+            ]
+        });
+
+        SIGNALS.as_ref()
+    }
+
+    fn constructed(&self, obj: &Self::Type) {
+        self.parent_constructed(obj);
+
+        obj.set_element_flags(gst::ElementFlags::SINK);
+        obj.set_suppressed_flags(gst::ElementFlags::SINK | gst::ElementFlags::SOURCE);
+    }
+}
+
+impl GstObjectImpl for DashSink {}
+
+impl ElementImpl for DashSink {
+    fn metadata() -> Option<&'static gst::subclass::ElementMetadata> {
+        static ELEMENT_METADATA: Lazy<gst::subclass::ElementMetadata> = Lazy::new(|| {
+            gst::subclass::ElementMetadata::new(
+                "DASH sink",
+                "Sink/Muxer",
+                "Dynamic Adaptive Streaming over HTTP sink with an MPD and fragmented MP4 segments",
+                "Rafael Caricio <rafael@caricio.com>",
+            )
+        });
+
+        Some(&*ELEMENT_METADATA)
+    }
+
+    fn pad_templates() -> &'static [gst::PadTemplate] {
+        static PAD_TEMPLATES: Lazy<Vec<gst::PadTemplate>> = Lazy::new(|| {
+            ["video_%u", "audio_%u", "subtitle_%u"]
+                .iter()
+                .map(|name| {
+                    gst::PadTemplate::new(
+                        name,
+                        gst::PadDirection::Sink,
+                        gst::PadPresence::Request,
+                        &gst::Caps::new_any(),
+                    )
+                    .unwrap()
+                })
+                .collect()
+        });
+
+        PAD_TEMPLATES.as_ref()
+    }
+
+    fn change_state(
+        &self,
+        element: &Self::Type,
+        transition: gst::StateChange,
+    ) -> Result<gst::StateChangeSuccess, gst::StateChangeError> {
+        if let gst::StateChange::ReadyToPaused = transition {
+            self.start(element);
+        }
+
+        let ret = self.parent_change_state(element, transition)?;
+
+        if let gst::StateChange::PausedToReady = transition {
+            self.write_final_mpd(element);
+        }
+
+        Ok(ret)
+    }
+
+    fn request_new_pad(
+        &self,
+        element: &Self::Type,
+        templ: &gst::PadTemplate,
+        name: Option<String>,
+        _caps: Option<&gst::Caps>,
+    ) -> Option<gst::Pad> {
+        let settings = self.settings.lock().unwrap().clone();
+
+        let (content_type, prefix) = match templ.name_template().as_ref().map(|val| val.as_str()) {
+            Some("video_%u") => (ContentType::Video, "video"),
+            Some("audio_%u") => (ContentType::Audio, "audio"),
+            Some("subtitle_%u") => (ContentType::Text, "subtitle"),
+            _ => {
+                gst_debug!(CAT, obj: element, "requested_new_pad: unknown template");
+                return None;
+            }
+        };
+
+        let mut state = self.state.lock().unwrap();
+
+        let name = match name {
+            Some(name) => name,
+            None => (0..)
+                .map(|idx| format!("{}_{}", prefix, idx))
+                .find(|name| !state.streams.iter().any(|s| &s.name == name))
+                .unwrap(),
+        };
+        if state.streams.iter().any(|s| s.name == name) {
+            gst_debug!(CAT, obj: element, "requested_new_pad: {} already exists", name);
+            return None;
+        }
+
+        let location = settings.location.replacen("%s", &name, 1);
+        let segment_formatter = match SegmentFormatter::new(&location) {
+            Some(segment_formatter) => segment_formatter,
+            None => {
+                gst_error!(
+                    CAT,
+                    obj: element,
+                    "A string containing `%03d` pattern must be used as location (can be any number from 0-9)"
+                );
+                return None;
+            }
+        };
+
+        let mux = match gst::ElementFactory::make("dashmp4mux", Some(&format!("{}_mux", name))) {
+            Ok(mux) => mux,
+            Err(_) => {
+                gst_error!(CAT, obj: element, "Could not make element dashmp4mux");
+                return None;
+            }
+        };
+        mux.set_property(
+            "fragment-duration",
+            gst::ClockTime::from_seconds(settings.target_duration as u64),
+        );
+
+        let appsink = gst::ElementFactory::make("appsink", Some(&format!("{}_sink", name)))
+            .expect("Could not make element appsink")
+            .downcast::<gst_app::AppSink>()
+            .unwrap();
+        appsink.set_property("sync", false);
+        appsink.set_property("buffer-list", true);
+        appsink.set_callbacks(
+            gst_app::AppSinkCallbacks::builder()
+                .new_sample({
+                    let element_weak = element.downgrade();
+                    let name = name.clone();
+                    move |appsink| {
+                        let element = match element_weak.upgrade() {
+                            Some(element) => element,
+                            None => return Err(gst::FlowError::Flushing),
+                        };
+                        let dashsink = DashSink::from_instance(&element);
+
+                        let sample = appsink.pull_sample().map_err(|_| gst::FlowError::Eos)?;
+                        dashsink.on_stream_sample(&element, &name, &sample)
+                    }
+                })
+                .build(),
+        );
+
+        element
+            .add_many(&[&mux, appsink.upcast_ref::<gst::Element>()])
+            .unwrap();
+        mux.link(&appsink).unwrap();
+        mux.sync_state_with_parent().unwrap();
+        appsink.sync_state_with_parent().unwrap();
+
+        let peer_pad = mux.request_pad_simple("sink_%u").unwrap();
+        let pad = gst::GhostPad::from_template_with_target(templ, Some(&name), &peer_pad).unwrap();
+
+        pad.add_probe(gst::PadProbeType::EVENT_DOWNSTREAM, {
+            let element_weak = element.downgrade();
+            move |pad, info| {
+                if let (Some(element), Some(gst::PadProbeData::Event(event))) =
+                    (element_weak.upgrade(), &info.data)
+                {
+                    let dashsink = DashSink::from_instance(&element);
+                    dashsink.handle_stream_event(pad.upcast_ref(), event);
+                }
+
+                gst::PadProbeReturn::Ok
+            }
+        });
+
+        let init_location = settings.init_location.replacen("%s", &name, 1);
+        state.streams.push(Stream {
+            content_type,
+            name,
+            pad: pad.clone(),
+            mux,
+            appsink,
+            location,
+            init_location,
+            segment_formatter,
+            next_segment_idx: 0,
+            old_segment_locations: VecDeque::new(),
+            language: None,
+            bitrate: None,
+            measured_bitrate: 0,
+        });
+        drop(state);
+
+        element.add_pad(&pad).unwrap();
+        pad.set_active(true).unwrap();
+
+        Some(pad.upcast())
+    }
+
+    fn release_pad(&self, element: &Self::Type, pad: &gst::Pad) {
+        let stream = {
+            let mut state = self.state.lock().unwrap();
+            let stream = match state
+                .streams
+                .iter()
+                .position(|s| s.pad.upcast_ref::<gst::Pad>() == pad)
+            {
+                Some(idx) => state.streams.remove(idx),
+                None => return,
+            };
+            state.mpd.remove_representation(&stream.name);
+
+            stream
+        };
+
+        if let Some(peer) = stream.pad.target() {
+            stream.mux.release_request_pad(&peer);
+        }
+
+        pad.set_active(false).unwrap();
+        element.remove_pad(pad).unwrap();
+
+        let appsink = stream.appsink.upcast_ref::<gst::Element>();
+        let _ = stream.mux.set_state(gst::State::Null);
+        let _ = appsink.set_state(gst::State::Null);
+        element.remove_many(&[&stream.mux, appsink]).unwrap();
+    }
+}
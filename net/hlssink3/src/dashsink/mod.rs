@@ -0,0 +1,28 @@
+//
+// Copyright (C) 2026 agent <agent@local>
+//
+// This Source Code Form is subject to the terms of the Mozilla Public License, v2.0.
+// If a copy of the MPL was not distributed with this file, You can obtain one at
+// <https://mozilla.org/MPL/2.0/>.
+//
+// SPDX-License-Identifier: MPL-2.0
+
+use glib::prelude::*;
+
+mod imp;
+
+glib::wrapper! {
+    pub struct DashSink(ObjectSubclass<imp::DashSink>) @extends gst::Bin, gst::Element, gst::Object;
+}
+
+unsafe impl Send for DashSink {}
+unsafe impl Sync for DashSink {}
+
+pub fn register(plugin: &gst::Plugin) -> Result<(), glib::BoolError> {
+    gst::Element::register(
+        Some(plugin),
+        "dashsink",
+        gst::Rank::None,
+        DashSink::static_type(),
+    )
+}
@@ -0,0 +1,127 @@
+//
+// Copyright (C) 2026 agent <agent@local>
+//
+// This Source Code Form is subject to the terms of the Mozilla Public License, v2.0.
+// If a copy of the MPL was not distributed with this file, You can obtain one at
+// <https://mozilla.org/MPL/2.0/>.
+//
+// SPDX-License-Identifier: MPL-2.0
+
+use aes::cipher::{generic_array::GenericArray, BlockEncryptMut, KeyIvInit};
+
+const BLOCK_SIZE: usize = 16;
+
+type Aes128CbcEnc = cbc::Encryptor<aes::Aes128>;
+
+/// Encrypts a whole segment with AES-128-CBC and PKCS7 padding, as required for the `AES-128`
+/// method of `EXT-X-KEY`.
+///
+/// The segment can be passed in pieces of any size.
+pub(crate) struct SegmentEncryptor {
+    cipher: Aes128CbcEnc,
+    // Data of the last incomplete block
+    pending: Vec<u8>,
+}
+
+impl SegmentEncryptor {
+    pub(crate) fn new(key: &[u8; BLOCK_SIZE], iv: &[u8; BLOCK_SIZE]) -> Self {
+        Self {
+            cipher: Aes128CbcEnc::new(key.into(), iv.into()),
+            pending: Vec::with_capacity(BLOCK_SIZE),
+        }
+    }
+
+    /// Encrypts the next piece of the segment and returns all complete encrypted blocks.
+    pub(crate) fn encrypt(&mut self, data: &[u8]) -> Vec<u8> {
+        let mut output = Vec::with_capacity(self.pending.len() + data.len());
+        output.extend_from_slice(&self.pending);
+        output.extend_from_slice(data);
+
+        let complete_len = output.len() - output.len() % BLOCK_SIZE;
+        self.pending.clear();
+        self.pending.extend_from_slice(&output[complete_len..]);
+        output.truncate(complete_len);
+
+        for block in output.chunks_exact_mut(BLOCK_SIZE) {
+            self.cipher
+                .encrypt_block_mut(GenericArray::from_mut_slice(block));
+        }
+
+        output
+    }
+
+    /// Pads and encrypts the remaining data at the end of the segment.
+    pub(crate) fn finish(mut self) -> Vec<u8> {
+        let padding = BLOCK_SIZE - self.pending.len();
+        let mut output = std::mem::take(&mut self.pending);
+        output.resize(BLOCK_SIZE, padding as u8);
+        self.cipher
+            .encrypt_block_mut(GenericArray::from_mut_slice(&mut output));
+
+        output
+    }
+}
+
+/// Formats a key or IV as hexadecimal-sequence for the `EXT-X-KEY` tag.
+pub(crate) fn hex_sequence(data: &[u8]) -> String {
+    let mut s = String::with_capacity(2 + 2 * data.len());
+    s.push_str("0x");
+    for b in data {
+        s.push_str(&format!("{:02X}", b));
+    }
+    s
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const KEY: [u8; 16] = [
+        0x2b, 0x7e, 0x15, 0x16, 0x28, 0xae, 0xd2, 0xa6, 0xab, 0xf7, 0x15, 0x88, 0x09, 0xcf, 0x4f,
+        0x3c,
+    ];
+    const IV: [u8; 16] = [
+        0x00, 0x01, 0x02, 0x03, 0x04, 0x05, 0x06, 0x07, 0x08, 0x09, 0x0a, 0x0b, 0x0c, 0x0d, 0x0e,
+        0x0f,
+    ];
+
+    #[test]
+    fn encrypts_in_pieces_with_padding() {
+        // NIST SP 800-38A, F.2.1 CBC-AES128.Encrypt
+        let plaintext = [
+            0x6b, 0xc1, 0xbe, 0xe2, 0x2e, 0x40, 0x9f, 0x96, 0xe9, 0x3d, 0x7e, 0x11, 0x73, 0x93,
+            0x17, 0x2a, 0xae, 0x2d, 0x8a, 0x57, 0x1e, 0x03, 0xac, 0x9c, 0x9e, 0xb7, 0x6f, 0xac,
+            0x45, 0xaf, 0x8e, 0x51,
+        ];
+        let ciphertext = [
+            0x76, 0x49, 0xab, 0xac, 0x81, 0x19, 0xb2, 0x46, 0xce, 0xe9, 0x8e, 0x9b, 0x12, 0xe9,
+            0x19, 0x7d, 0x50, 0x86, 0xcb, 0x9b, 0x50, 0x72, 0x19, 0xee, 0x95, 0xdb, 0x11, 0x3a,
+            0x91, 0x76, 0x78, 0xb2,
+        ];
+
+        let mut encryptor = SegmentEncryptor::new(&KEY, &IV);
+        let mut output = encryptor.encrypt(&plaintext[..5]);
+        assert!(output.is_empty());
+        output.extend(encryptor.encrypt(&plaintext[5..20]));
+        assert_eq!(output.len(), 16);
+        output.extend(encryptor.encrypt(&plaintext[20..]));
+        assert_eq!(&output[..], &ciphertext[..]);
+
+        // A full block of padding is added to complete segments
+        assert_eq!(
+            encryptor.finish(),
+            vec![
+                0x55, 0xe2, 0x1d, 0x71, 0x00, 0xb9, 0x88, 0xff, 0xec, 0x32, 0xfe, 0xea, 0xfa, 0xf2,
+                0x35, 0x38,
+            ]
+        );
+    }
+
+    #[test]
+    fn formats_hex_sequence() {
+        assert_eq!(
+            hex_sequence(&IV),
+            "0x000102030405060708090A0B0C0D0E0F".to_string()
+        );
+    }
+}
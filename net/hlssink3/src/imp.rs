@@ -7,20 +7,39 @@
 //
 // SPDX-License-Identifier: MPL-2.0
 
-use crate::playlist::{Playlist, SegmentFormatter};
+use crate::encryption::{hex_sequence, SegmentEncryptor};
+use crate::playlist::{format_date_time, Playlist, SegmentFormatter};
+use crate::sample_aes::{
+    adts_audio_specific_config, adts_header_len, PmtRewriter, SampleEncryptor,
+};
+use crate::{EncryptionMethod, SegmentFormat};
 use gio::prelude::*;
 use glib::subclass::prelude::*;
 use gst::prelude::*;
 use gst::subclass::prelude::*;
 use gst::{gst_debug, gst_error, gst_info, gst_trace, gst_warning};
-use m3u8_rs::MediaPlaylistType;
+use m3u8_rs::{Key, MediaPlaylistType};
 use once_cell::sync::Lazy;
 use std::fs;
 use std::io::Write;
 use std::path;
 use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 
 const DEFAULT_LOCATION: &str = "segment%05d.ts";
+const DEFAULT_CMAF_LOCATION: &str = "segment%05d.m4s";
+const DEFAULT_INIT_LOCATION: &str = "init.mp4";
+const DEFAULT_PART_LOCATION: &str = "part%05d.m4s";
+const DEFAULT_PART_DURATION: u32 = 0;
+const DEFAULT_ENCRYPTION_METHOD: EncryptionMethod = EncryptionMethod::None;
+const DEFAULT_KEY_LOCATION: &str = "key%05d.key";
+const DEFAULT_KEY_ROTATION: u32 = 0;
+const DEFAULT_PROGRAM_DATE_TIME: bool = false;
+const DEFAULT_RESUME: bool = false;
+
+/// Gaps between buffers larger than this are marked as discontinuity.
+const MAX_TIMESTAMP_GAP: gst::ClockTime = gst::ClockTime::SECOND;
+const DEFAULT_SEGMENT_FORMAT: SegmentFormat = SegmentFormat::MpegTs;
 const DEFAULT_PLAYLIST_LOCATION: &str = "playlist.m3u8";
 const DEFAULT_MAX_NUM_SEGMENT_FILES: u32 = 10;
 const DEFAULT_TARGET_DURATION: u32 = 15;
@@ -45,11 +64,28 @@ struct Settings {
     max_num_segment_files: usize,
     target_duration: u32,
     send_keyframe_requests: bool,
+    segment_format: SegmentFormat,
+    init_location: String,
+    part_location: String,
+    part_formatter: SegmentFormatter,
+    part_duration: u32,
+    encryption_method: EncryptionMethod,
+    key_location: String,
+    key_formatter: SegmentFormatter,
+    key_uri: Option<String>,
+    key_uri_formatter: Option<SegmentFormatter>,
+    key_rotation: u32,
+    program_date_time: bool,
+    resume: bool,
 
     splitmuxsink: gst::Element,
     giostreamsink: gst::Element,
+    // Only created once the CMAF segment format is selected
+    cmafmux: Option<gst::Element>,
+    appsink: Option<gst_app::AppSink>,
     video_sink: bool,
     audio_sink: bool,
+    subtitle_sink: bool,
 }
 
 impl Default for Settings {
@@ -68,20 +104,91 @@ impl Default for Settings {
             max_num_segment_files: DEFAULT_MAX_NUM_SEGMENT_FILES as usize,
             target_duration: DEFAULT_TARGET_DURATION,
             send_keyframe_requests: DEFAULT_SEND_KEYFRAME_REQUESTS,
+            segment_format: DEFAULT_SEGMENT_FORMAT,
+            init_location: String::from(DEFAULT_INIT_LOCATION),
+            part_location: String::from(DEFAULT_PART_LOCATION),
+            part_formatter: SegmentFormatter::new(DEFAULT_PART_LOCATION).unwrap(),
+            part_duration: DEFAULT_PART_DURATION,
+            encryption_method: DEFAULT_ENCRYPTION_METHOD,
+            key_location: String::from(DEFAULT_KEY_LOCATION),
+            key_formatter: SegmentFormatter::new(DEFAULT_KEY_LOCATION).unwrap(),
+            key_uri: None,
+            key_uri_formatter: None,
+            key_rotation: DEFAULT_KEY_ROTATION,
+            program_date_time: DEFAULT_PROGRAM_DATE_TIME,
+            resume: DEFAULT_RESUME,
 
             splitmuxsink,
             giostreamsink,
+            cmafmux: None,
+            appsink: None,
             video_sink: false,
             audio_sink: false,
+            subtitle_sink: false,
         }
     }
 }
 
+impl Settings {
+    /// URI of a file inside the playlist, relative to the playlist root.
+    fn playlist_uri(&self, location: &str) -> String {
+        let filename = path_basename(location);
+
+        if let Some(playlist_root) = &self.playlist_root {
+            format!("{}/{}", playlist_root, filename)
+        } else {
+            filename
+        }
+    }
+}
+
+/// An encryption key and the number of segments it was used for.
+#[derive(Clone)]
+struct SegmentKey {
+    key: [u8; 16],
+    uri: String,
+    segments: u32,
+}
+
+/// Data flow of a sink pad for detecting discontinuities.
+#[derive(Default)]
+struct SinkPadState {
+    segment: Option<gst::FormattedSegment<gst::ClockTime>>,
+    caps: Option<gst::Caps>,
+    discont: bool,
+    // Maximum running time of the end of all buffers so far
+    last_end: Option<gst::ClockTime>,
+}
+
 pub(crate) struct StartedState {
     playlist: Playlist,
     fragment_opened_at: Option<gst::ClockTime>,
     current_segment_location: Option<String>,
     old_segment_locations: Vec<String>,
+    // Index of the next segment in CMAF mode, splitmuxsink keeps track of it otherwise
+    next_segment_idx: u32,
+
+    // Partial segments of the segment currently being written in Low-Latency HLS mode
+    next_part_idx: u32,
+    segment_buffers: Vec<gst::Buffer>,
+    segment_start: Option<gst::ClockTime>,
+    segment_end: Option<gst::ClockTime>,
+    current_part_locations: Vec<String>,
+    old_part_locations: Vec<Vec<String>>,
+
+    // AES-128 encryption of the current segment
+    current_key: Option<SegmentKey>,
+    next_key_idx: u32,
+    current_segment_key: Option<Key>,
+    segment_encryptor: Option<SegmentEncryptor>,
+    // SAMPLE-AES encryption of the MPEG-TS samples of the current segment
+    sample_encryptor: Option<SampleEncryptor>,
+    pmt_rewriter: Option<PmtRewriter>,
+
+    // Running time and corresponding time since the UNIX epoch for EXT-X-PROGRAM-DATE-TIME
+    program_date_time_mapping: Option<(gst::ClockTime, Duration)>,
+    // Running time of the first data after a discontinuity
+    pending_discontinuity: Option<gst::ClockTime>,
 }
 
 impl StartedState {
@@ -91,6 +198,21 @@ impl StartedState {
             current_segment_location: None,
             fragment_opened_at: None,
             old_segment_locations: Vec::new(),
+            next_segment_idx: 0,
+            next_part_idx: 0,
+            segment_buffers: Vec::new(),
+            segment_start: None,
+            segment_end: None,
+            current_part_locations: Vec::new(),
+            old_part_locations: Vec::new(),
+            current_key: None,
+            next_key_idx: 0,
+            current_segment_key: None,
+            segment_encryptor: None,
+            sample_encryptor: None,
+            pmt_rewriter: None,
+            program_date_time_mapping: None,
+            pending_discontinuity: None,
         }
     }
 
@@ -120,20 +242,615 @@ pub struct HlsSink3 {
 }
 
 impl HlsSink3 {
-    fn start(&self, element: &super::HlsSink3) {
+    fn start(&self, element: &super::HlsSink3) -> Result<(), gst::StateChangeError> {
         gst_info!(CAT, obj: element, "Starting");
 
-        let (target_duration, playlist_type) = {
+        let (target_duration, playlist_type, part_target) = {
             let settings = self.settings.lock().unwrap();
+
+            match (settings.encryption_method, settings.segment_format) {
+                (EncryptionMethod::Aes128, SegmentFormat::Cmaf) if settings.part_duration > 0 => {
+                    gst::element_error!(
+                        element,
+                        gst::LibraryError::Settings,
+                        ["AES-128 encryption is not supported with Low-Latency HLS partial segments"]
+                    );
+                    return Err(gst::StateChangeError);
+                }
+                (EncryptionMethod::SampleAes, SegmentFormat::Cmaf) if settings.key_rotation > 0 => {
+                    gst::element_error!(
+                        element,
+                        gst::LibraryError::Settings,
+                        ["Key rotation is not supported with SAMPLE-AES encryption of CMAF segments"]
+                    );
+                    return Err(gst::StateChangeError);
+                }
+                _ => (),
+            }
+
             (
                 settings.target_duration as f32,
                 settings.playlist_type.clone(),
+                part_target(&settings),
             )
         };
 
         let mut state = self.state.lock().unwrap();
         if let State::Stopped = *state {
-            *state = State::Started(StartedState::new(target_duration, playlist_type));
+            let mut started = StartedState::new(target_duration, playlist_type);
+            if let Some(part_target) = part_target {
+                started.playlist.set_part_target(part_target);
+            }
+
+            let settings = self.settings.lock().unwrap();
+            if settings.resume {
+                self.resume_playlist(element, &settings, &mut started)?;
+            }
+            if settings.encryption_method == EncryptionMethod::SampleAes
+                && settings.segment_format == SegmentFormat::MpegTs
+            {
+                started.pmt_rewriter = Some(PmtRewriter::default());
+            }
+            drop(settings);
+
+            *state = State::Started(started);
+        }
+        drop(state);
+
+        self.start_sample_aes(element)
+    }
+
+    /// Continues the playlist written at `playlist-location` by a previous run, if any.
+    fn resume_playlist(
+        &self,
+        element: &super::HlsSink3,
+        settings: &Settings,
+        state: &mut StartedState,
+    ) -> Result<(), gst::StateChangeError> {
+        let content = match fs::read(&settings.playlist_location) {
+            Ok(content) => content,
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => {
+                gst_info!(
+                    CAT,
+                    obj: element,
+                    "No playlist to resume at {}",
+                    settings.playlist_location
+                );
+                return Ok(());
+            }
+            Err(err) => {
+                gst::element_error!(
+                    element,
+                    gst::ResourceError::OpenRead,
+                    [
+                        "Could not read playlist {}: {}",
+                        settings.playlist_location,
+                        err
+                    ]
+                );
+                return Err(gst::StateChangeError);
+            }
+        };
+
+        let existing = m3u8_rs::parse_media_playlist_res(&content).map_err(|_| {
+            gst::element_error!(
+                element,
+                gst::ResourceError::Read,
+                ["Could not parse playlist {}", settings.playlist_location]
+            );
+            gst::StateChangeError
+        })?;
+
+        // Continue after the highest segment and key index of the existing playlist
+        let segment_uri_formatter =
+            SegmentFormatter::new(settings.playlist_uri(&settings.location)).unwrap();
+        let key_uri_formatter = match &settings.key_uri {
+            Some(key_uri) => SegmentFormatter::new(key_uri),
+            None => SegmentFormatter::new(settings.playlist_uri(&settings.key_location)),
+        }
+        .unwrap();
+
+        let segment_indices = existing
+            .segments
+            .iter()
+            .filter_map(|segment| segment_uri_formatter.index(&segment.uri))
+            .collect::<Vec<_>>();
+        let next_segment_idx = segment_indices
+            .iter()
+            .max()
+            .map_or(existing.media_sequence as u32, |idx| idx + 1)
+            .max(existing.media_sequence as u32 + existing.segments.len() as u32);
+        let next_key_idx = existing
+            .segments
+            .iter()
+            .filter_map(|segment| segment.key.as_ref()?.uri.as_ref())
+            .filter_map(|uri| key_uri_formatter.index(uri))
+            .max()
+            .map_or(0, |idx| idx + 1);
+
+        gst_info!(
+            CAT,
+            obj: element,
+            "Resuming playlist {} with {} segments at segment index {}",
+            settings.playlist_location,
+            existing.segments.len(),
+            next_segment_idx
+        );
+
+        // The existing segments are deleted like new ones once the maximum is reached
+        for idx in segment_indices {
+            state
+                .old_segment_locations
+                .push(settings.segment_formatter.segment(idx));
+            state.old_part_locations.push(Vec::new());
+        }
+
+        state.next_segment_idx = next_segment_idx;
+        state.next_key_idx = next_key_idx;
+        settings
+            .splitmuxsink
+            .set_property("start-index", next_segment_idx as i32);
+        state.playlist.resume(existing);
+
+        Ok(())
+    }
+
+    /// Keeps track of discontinuities and reference timestamps of the data of a sink pad.
+    fn add_sink_pad_probe(&self, element: &super::HlsSink3, pad: &gst::GhostPad) {
+        let pad_state = Mutex::new(SinkPadState::default());
+        let element_weak = element.downgrade();
+
+        pad.add_probe(
+            gst::PadProbeType::BUFFER
+                | gst::PadProbeType::BUFFER_LIST
+                | gst::PadProbeType::EVENT_DOWNSTREAM,
+            move |_pad, info| {
+                if let Some(element) = element_weak.upgrade() {
+                    let hlssink3 = HlsSink3::from_instance(&element);
+                    hlssink3.handle_sink_pad_data(&element, &mut pad_state.lock().unwrap(), info);
+                }
+
+                gst::PadProbeReturn::Ok
+            },
+        );
+    }
+
+    fn handle_sink_pad_data(
+        &self,
+        element: &super::HlsSink3,
+        pad_state: &mut SinkPadState,
+        info: &gst::PadProbeInfo,
+    ) {
+        let buffer = match &info.data {
+            Some(gst::PadProbeData::Buffer(buffer)) => buffer.as_ref(),
+            Some(gst::PadProbeData::BufferList(list)) => match list.get(0) {
+                Some(buffer) => buffer,
+                None => return,
+            },
+            Some(gst::PadProbeData::Event(event)) => {
+                match event.view() {
+                    // A new stream or different caps after data was received already
+                    gst::EventView::StreamStart(_) => {
+                        if pad_state.last_end.is_some() {
+                            pad_state.discont = true;
+                        }
+                    }
+                    gst::EventView::Caps(ev) => {
+                        let caps = ev.caps_owned();
+                        if pad_state.last_end.is_some() && pad_state.caps.as_ref() != Some(&caps) {
+                            pad_state.discont = true;
+                        }
+                        pad_state.caps = Some(caps);
+                    }
+                    gst::EventView::Segment(ev) => {
+                        pad_state.segment = ev.segment().downcast_ref::<gst::ClockTime>().cloned();
+                    }
+                    _ => (),
+                }
+
+                return;
+            }
+            _ => return,
+        };
+
+        let running_time = match (&pad_state.segment, buffer.pts()) {
+            (Some(segment), Some(pts)) => segment.to_running_time(pts),
+            _ => None,
+        };
+        let running_time = match running_time {
+            Some(running_time) => running_time,
+            None => return,
+        };
+
+        if let Some(last_end) = pad_state.last_end {
+            if running_time > last_end + MAX_TIMESTAMP_GAP {
+                gst_debug!(
+                    CAT,
+                    obj: element,
+                    "Timestamp gap from {} to {}",
+                    last_end,
+                    running_time
+                );
+                pad_state.discont = true;
+            }
+        }
+        let end = running_time + buffer.duration().unwrap_or(gst::ClockTime::ZERO);
+        pad_state.last_end = Some(pad_state.last_end.map_or(end, |last_end| last_end.max(end)));
+
+        if std::mem::take(&mut pad_state.discont) {
+            self.on_discontinuity(element, running_time);
+        }
+
+        // Upstream reference timestamps take precedence over the wall clock
+        let reference_timestamp = buffer
+            .iter_meta::<gst::ReferenceTimestampMeta>()
+            .find(|meta| {
+                meta.reference()
+                    .structure(0)
+                    .map_or(false, |s| s.name() == "timestamp/x-unix")
+            })
+            .map(|meta| meta.timestamp());
+        if let Some(reference_timestamp) = reference_timestamp {
+            let mut state = self.state.lock().unwrap();
+            if let State::Started(state) = &mut *state {
+                state.program_date_time_mapping = Some((
+                    running_time,
+                    Duration::from_nanos(reference_timestamp.nseconds()),
+                ));
+            }
+        }
+    }
+
+    /// Marks the next segment as discontinuous and starts it as soon as possible.
+    fn on_discontinuity(&self, element: &super::HlsSink3, running_time: gst::ClockTime) {
+        gst_info!(CAT, obj: element, "Discontinuity at {}", running_time);
+
+        {
+            let mut state = self.state.lock().unwrap();
+            if let State::Started(state) = &mut *state {
+                state.pending_discontinuity.get_or_insert(running_time);
+            }
+        }
+
+        let splitmuxsink = {
+            let settings = self.settings.lock().unwrap();
+            if settings.segment_format == SegmentFormat::MpegTs {
+                Some(settings.splitmuxsink.clone())
+            } else {
+                None
+            }
+        };
+        if let Some(splitmuxsink) = splitmuxsink {
+            splitmuxsink.emit_by_name::<()>("split-now", &[]);
+        }
+    }
+
+    /// Wall clock time at the start of the current segment.
+    fn program_date_time(
+        &self,
+        element: &super::HlsSink3,
+        state: &mut StartedState,
+    ) -> Option<String> {
+        let opened_at = state.fragment_opened_at?;
+
+        // Map the current running time to the wall clock if there are no reference timestamps
+        if state.program_date_time_mapping.is_none() {
+            let clock = element.clock()?;
+            let running_time = clock.time()?.checked_sub(element.base_time()?)?;
+            let now = SystemTime::now().duration_since(UNIX_EPOCH).ok()?;
+            state.program_date_time_mapping = Some((running_time, now));
+        }
+
+        let (running_time, unix_time) = state.program_date_time_mapping?;
+        let unix_time = if opened_at >= running_time {
+            unix_time + Duration::from_nanos((opened_at - running_time).nseconds())
+        } else {
+            unix_time.checked_sub(Duration::from_nanos((running_time - opened_at).nseconds()))?
+        };
+
+        Some(format_date_time(unix_time))
+    }
+
+    /// Sets up the cbcs encryption of `cmafmux` for the `SAMPLE-AES` method with CMAF segments.
+    ///
+    /// The key is used for the whole stream as `cmafmux` can't change it.
+    fn start_sample_aes(&self, element: &super::HlsSink3) -> Result<(), gst::StateChangeError> {
+        let mut state_guard = self.state.lock().unwrap();
+        let state = match &mut *state_guard {
+            State::Stopped => return Ok(()),
+            State::Started(s) => s,
+        };
+
+        let settings = self.settings.lock().unwrap();
+        if settings.encryption_method != EncryptionMethod::SampleAes
+            || settings.segment_format != SegmentFormat::Cmaf
+        {
+            return Ok(());
+        }
+        let cmafmux = match &settings.cmafmux {
+            Some(cmafmux) => cmafmux,
+            None => return Err(gst::StateChangeError),
+        };
+
+        let key = self
+            .next_segment_key(element, state, &settings)
+            .map_err(|err| {
+                gst_error!(CAT, obj: element, "Could not create key: {}", err);
+                gst::StateChangeError
+            })?;
+        let key_id = rand::random::<[u8; 16]>();
+        let iv = rand::random::<[u8; 16]>();
+
+        cmafmux.set_property_from_str("encryption-scheme", "cbcs");
+        cmafmux.set_property("key-id", gst::Buffer::from_slice(key_id));
+        cmafmux.set_property("key", gst::Buffer::from_slice(key.key));
+        cmafmux.set_property("iv", gst::Buffer::from_slice(iv));
+
+        state.playlist.set_key(Some(Key {
+            method: String::from("SAMPLE-AES"),
+            uri: Some(key.uri),
+            iv: Some(hex_sequence(&iv)),
+            keyformat: Some(String::from("identity")),
+            ..Default::default()
+        }));
+
+        Ok(())
+    }
+
+    /// Returns the encryption key for the next segment, creating a new one if the previous one
+    /// was used for `key-rotation` segments already.
+    ///
+    /// New keys are written to `key-location`.
+    fn next_segment_key(
+        &self,
+        element: &super::HlsSink3,
+        state: &mut StartedState,
+        settings: &Settings,
+    ) -> Result<SegmentKey, String> {
+        let rotate = match &state.current_key {
+            None => true,
+            Some(key) => settings.key_rotation > 0 && key.segments >= settings.key_rotation,
+        };
+
+        if rotate {
+            let key = rand::random::<[u8; 16]>();
+            let key_location = settings.key_formatter.segment(state.next_key_idx);
+            let uri = match &settings.key_uri_formatter {
+                Some(key_uri_formatter) => key_uri_formatter.segment(state.next_key_idx),
+                None => settings.playlist_uri(&key_location),
+            };
+            state.next_key_idx += 1;
+
+            gst_info!(CAT, obj: element, "New key location: {}", key_location);
+
+            let mut stream = element
+                .emit_by_name::<Option<gio::OutputStream>>(
+                    SIGNAL_GET_FRAGMENT_STREAM,
+                    &[&key_location],
+                )
+                .ok_or_else(|| String::from("Error while getting key stream"))?
+                .into_write();
+            stream
+                .write_all(&key)
+                .and_then(|_| stream.flush())
+                .map_err(|err| format!("Could not write key {}: {}", key_location, err))?;
+
+            state.current_key = Some(SegmentKey {
+                key,
+                uri,
+                segments: 0,
+            });
+        }
+
+        let key = state.current_key.as_mut().unwrap();
+        key.segments += 1;
+
+        Ok(key.clone())
+    }
+
+    /// Sets up the encryption of the MPEG-TS segment with the given index if encryption is
+    /// enabled, and remembers its key for the playlist entry of the segment.
+    ///
+    /// Returns the AES-128 encryptor of the segment data. With SAMPLE-AES, the samples are
+    /// encrypted with the key of the segment before they are muxed.
+    fn start_segment_encryption(
+        &self,
+        element: &super::HlsSink3,
+        state: &mut StartedState,
+        settings: &Settings,
+        segment_idx: u32,
+    ) -> Result<Option<SegmentEncryptor>, String> {
+        let method = match settings.encryption_method {
+            EncryptionMethod::None => return Ok(None),
+            EncryptionMethod::Aes128 => "AES-128",
+            EncryptionMethod::SampleAes => "SAMPLE-AES",
+        };
+
+        let key = self.next_segment_key(element, state, settings)?;
+        // The segment index is used as IV, which is unique for each key
+        let iv = (segment_idx as u128).to_be_bytes();
+
+        state.current_segment_key = Some(Key {
+            method: String::from(method),
+            uri: Some(key.uri),
+            iv: Some(hex_sequence(&iv)),
+            ..Default::default()
+        });
+
+        if settings.encryption_method == EncryptionMethod::SampleAes {
+            state.sample_encryptor = Some(SampleEncryptor::new(&key.key, &iv));
+            return Ok(None);
+        }
+
+        Ok(Some(SegmentEncryptor::new(&key.key, &iv)))
+    }
+
+    /// Encrypts the data written into the current MPEG-TS segment with AES-128.
+    fn encrypt_segment_data(&self, info: &mut gst::PadProbeInfo) -> gst::PadProbeReturn {
+        let mut state = self.state.lock().unwrap();
+        let encryptor = match &mut *state {
+            State::Started(StartedState {
+                segment_encryptor: Some(encryptor),
+                ..
+            }) => encryptor,
+            _ => return gst::PadProbeReturn::Ok,
+        };
+
+        let encrypt = |encryptor: &mut SegmentEncryptor, buffer: &gst::BufferRef| {
+            let map = buffer.map_readable().ok()?;
+            Some(buffer_with_data(buffer, encryptor.encrypt(&map)))
+        };
+
+        let encrypted = match info.data {
+            Some(gst::PadProbeData::Buffer(ref mut buffer)) => {
+                encrypt(encryptor, buffer).map(|encrypted| *buffer = encrypted)
+            }
+            Some(gst::PadProbeData::BufferList(ref mut list)) => list
+                .iter()
+                .map(|buffer| encrypt(encryptor, buffer))
+                .collect::<Option<gst::BufferList>>()
+                .map(|encrypted| *list = encrypted),
+            _ => Some(()),
+        };
+
+        match encrypted {
+            Some(()) => gst::PadProbeReturn::Ok,
+            None => {
+                gst_error!(CAT, "Could not encrypt unmappable buffer");
+                gst::PadProbeReturn::Drop
+            }
+        }
+    }
+
+    /// Encrypts the H.264 and AAC samples that are muxed into the current MPEG-TS segment with
+    /// SAMPLE-AES.
+    fn encrypt_samples(&self, pad: &gst::Pad, info: &mut gst::PadProbeInfo) -> gst::PadProbeReturn {
+        let buffer = match info.data {
+            Some(gst::PadProbeData::Buffer(ref mut buffer)) => buffer,
+            _ => return gst::PadProbeReturn::Ok,
+        };
+
+        let mut state = self.state.lock().unwrap();
+        let (encryptor, rewriter) = match &mut *state {
+            State::Started(StartedState {
+                sample_encryptor: Some(encryptor),
+                pmt_rewriter: Some(rewriter),
+                ..
+            }) => (encryptor, rewriter),
+            _ => return gst::PadProbeReturn::Ok,
+        };
+
+        let caps = match pad.current_caps() {
+            Some(caps) => caps,
+            None => return gst::PadProbeReturn::Ok,
+        };
+        let s = caps.structure(0).unwrap();
+
+        let map = match buffer.map_readable() {
+            Ok(map) => map,
+            Err(_) => {
+                gst_error!(CAT, "Could not encrypt unmappable buffer");
+                return gst::PadProbeReturn::Drop;
+            }
+        };
+
+        // Other streams are muxed without encryption
+        let encrypted = match s.name() {
+            "video/x-h264" => encryptor.encrypt_h264(&map),
+            "audio/mpeg" if !matches!(s.get::<i32>("mpegversion"), Ok(1)) => {
+                let header_len = match adts_header_len(&map) {
+                    Some(header_len) => {
+                        if let Some(config) = adts_audio_specific_config(&map) {
+                            rewriter.set_audio_specific_config(&config);
+                        }
+                        header_len
+                    }
+                    None => {
+                        if let Ok(codec_data) = s.get::<gst::Buffer>("codec_data") {
+                            if let Ok(codec_data) = codec_data.map_readable() {
+                                rewriter.set_audio_specific_config(&codec_data);
+                            }
+                        }
+                        0
+                    }
+                };
+                encryptor.encrypt_aac(&map, header_len)
+            }
+            _ => return gst::PadProbeReturn::Ok,
+        };
+
+        let encrypted = buffer_with_data(buffer, encrypted);
+        drop(map);
+        *buffer = encrypted;
+
+        gst::PadProbeReturn::Ok
+    }
+
+    /// Signals the SAMPLE-AES encrypted streams in the PMT of the MPEG-TS segments.
+    fn rewrite_segment_pmt(&self, info: &mut gst::PadProbeInfo) -> gst::PadProbeReturn {
+        let mut state = self.state.lock().unwrap();
+        let rewriter = match &mut *state {
+            State::Started(StartedState {
+                pmt_rewriter: Some(rewriter),
+                ..
+            }) => rewriter,
+            _ => return gst::PadProbeReturn::Ok,
+        };
+
+        let rewrite = |rewriter: &mut PmtRewriter, buffer: &gst::BufferRef| {
+            let map = buffer.map_readable().ok()?;
+            let data = rewriter.rewrite(&map)?;
+            Some(buffer_with_data(buffer, data))
+        };
+
+        match info.data {
+            Some(gst::PadProbeData::Buffer(ref mut buffer)) => {
+                if let Some(rewritten) = rewrite(rewriter, buffer) {
+                    *buffer = rewritten;
+                }
+            }
+            Some(gst::PadProbeData::BufferList(ref mut list)) => {
+                *list = list
+                    .iter()
+                    .map(|buffer| rewrite(rewriter, buffer).unwrap_or_else(|| buffer.to_owned()))
+                    .collect();
+            }
+            _ => (),
+        }
+
+        gst::PadProbeReturn::Ok
+    }
+
+    /// Writes the padding at the end of the current AES-128 encrypted MPEG-TS segment.
+    fn finish_segment_encryption(&self, element: &super::HlsSink3) {
+        let encryptor = {
+            let mut state = self.state.lock().unwrap();
+            match &mut *state {
+                State::Started(state) => state.segment_encryptor.take(),
+                State::Stopped => None,
+            }
+        };
+        let encryptor = match encryptor {
+            Some(encryptor) => encryptor,
+            None => return,
+        };
+
+        let stream = self
+            .settings
+            .lock()
+            .unwrap()
+            .giostreamsink
+            .property::<Option<gio::OutputStream>>("stream");
+        let mut stream = match stream {
+            Some(stream) => stream.into_write(),
+            None => return,
+        };
+
+        if let Err(err) = stream
+            .write_all(&encryptor.finish())
+            .and_then(|_| stream.flush())
+        {
+            gst_error!(CAT, obj: element, "Could not finish encrypted segment: {}", err);
         }
     }
 
@@ -167,6 +884,9 @@ impl HlsSink3 {
 
         state.current_segment_location = Some(segment_file_location.clone());
 
+        state.segment_encryptor =
+            self.start_segment_encryption(element, state, &settings, fragment_id)?;
+
         let fragment_stream = element
             .emit_by_name::<Option<gio::OutputStream>>(
                 SIGNAL_GET_FRAGMENT_STREAM,
@@ -187,41 +907,434 @@ impl HlsSink3 {
         Ok(segment_file_location)
     }
 
-    fn new_file_stream<P>(
+    /// Switches the muxing elements inside the bin to the given segment format.
+    fn set_segment_format(
         &self,
         element: &super::HlsSink3,
-        location: &P,
-    ) -> Result<gio::OutputStream, String>
-    where
-        P: AsRef<path::Path>,
-    {
-        let file = fs::File::create(location).map_err(move |err| {
-            let error_msg = gst::error_msg!(
-                gst::ResourceError::OpenWrite,
-                [
-                    "Could not open file {} for writing: {}",
-                    location.as_ref().to_str().unwrap(),
-                    err.to_string(),
-                ]
+        settings: &mut Settings,
+        segment_format: SegmentFormat,
+    ) {
+        if settings.segment_format == segment_format {
+            return;
+        }
+
+        if settings.video_sink || settings.audio_sink || settings.subtitle_sink {
+            gst_warning!(
+                CAT,
+                obj: element,
+                "Can't change segment format after pads were requested"
             );
-            element.post_error_message(error_msg);
-            err.to_string()
+            return;
+        }
+
+        match segment_format {
+            SegmentFormat::MpegTs => {
+                if let (Some(cmafmux), Some(appsink)) = (&settings.cmafmux, &settings.appsink) {
+                    element
+                        .remove_many(&[cmafmux, appsink.upcast_ref::<gst::Element>()])
+                        .unwrap();
+                }
+                element.add(&settings.splitmuxsink).unwrap();
+            }
+            SegmentFormat::Cmaf => {
+                if settings.cmafmux.is_none() {
+                    let cmafmux = match gst::ElementFactory::make("cmafmux", Some("cmaf_mux")) {
+                        Ok(cmafmux) => cmafmux,
+                        Err(_) => {
+                            gst_error!(CAT, obj: element, "Could not make element cmafmux");
+                            return;
+                        }
+                    };
+                    cmafmux.set_property(
+                        "fragment-duration",
+                        gst::ClockTime::from_seconds(settings.target_duration as u64),
+                    );
+                    cmafmux.set_property("chunk-duration", chunk_duration(settings.part_duration));
+
+                    let appsink = gst::ElementFactory::make("appsink", Some("cmaf_app_sink"))
+                        .expect("Could not make element appsink")
+                        .downcast::<gst_app::AppSink>()
+                        .unwrap();
+                    appsink.set_property("sync", false);
+                    appsink.set_property("buffer-list", true);
+                    appsink.set_callbacks(
+                        gst_app::AppSinkCallbacks::builder()
+                            .new_sample({
+                                let element_weak = element.downgrade();
+                                move |appsink| {
+                                    let element = match element_weak.upgrade() {
+                                        Some(element) => element,
+                                        None => return Err(gst::FlowError::Flushing),
+                                    };
+                                    let hlssink3 = HlsSink3::from_instance(&element);
+
+                                    let sample =
+                                        appsink.pull_sample().map_err(|_| gst::FlowError::Eos)?;
+                                    hlssink3.on_cmaf_sample(&element, &sample)
+                                }
+                            })
+                            .eos({
+                                let element_weak = element.downgrade();
+                                move |_appsink| {
+                                    let element = match element_weak.upgrade() {
+                                        Some(element) => element,
+                                        None => return,
+                                    };
+                                    let hlssink3 = HlsSink3::from_instance(&element);
+
+                                    if let Err(err) = hlssink3.on_cmaf_eos(&element) {
+                                        gst_error!(
+                                            CAT,
+                                            obj: &element,
+                                            "Could not finish last segment: {:?}",
+                                            err
+                                        );
+                                    }
+                                }
+                            })
+                            .build(),
+                    );
+
+                    settings.cmafmux = Some(cmafmux);
+                    settings.appsink = Some(appsink);
+                }
+
+                let cmafmux = settings.cmafmux.as_ref().unwrap();
+                let appsink = settings.appsink.as_ref().unwrap();
+
+                element.remove(&settings.splitmuxsink).unwrap();
+                element
+                    .add_many(&[cmafmux, appsink.upcast_ref::<gst::Element>()])
+                    .unwrap();
+                cmafmux.link(appsink).unwrap();
+            }
+        }
+
+        // Switch the default location to the matching file extension
+        let default_location = match segment_format {
+            SegmentFormat::MpegTs => DEFAULT_LOCATION,
+            SegmentFormat::Cmaf => DEFAULT_CMAF_LOCATION,
+        };
+        if settings.location == DEFAULT_LOCATION || settings.location == DEFAULT_CMAF_LOCATION {
+            settings.location = String::from(default_location);
+            settings.segment_formatter = SegmentFormatter::new(default_location).unwrap();
+        }
+
+        settings.segment_format = segment_format;
+    }
+
+    /// Handles the output of `cmafmux`.
+    ///
+    /// Each buffer list contains one fragment, which becomes one segment, and the first one is
+    /// preceded by the initialization segment. In Low-Latency HLS mode each buffer list is a
+    /// chunk of a fragment instead, which becomes one partial segment.
+    fn on_cmaf_sample(
+        &self,
+        element: &super::HlsSink3,
+        sample: &gst::SampleRef,
+    ) -> Result<gst::FlowSuccess, gst::FlowError> {
+        let mut buffers = match (sample.buffer_list(), sample.buffer()) {
+            (Some(list), _) => list.iter().collect::<Vec<_>>(),
+            (None, Some(buffer)) => vec![buffer],
+            (None, None) => return Ok(gst::FlowSuccess::Ok),
+        };
+
+        let (init_location, parts) = {
+            let settings = self.settings.lock().unwrap();
+
+            let init_location = if buffers.first().map_or(false, |buffer| {
+                buffer
+                    .flags()
+                    .contains(gst::BufferFlags::DISCONT | gst::BufferFlags::HEADER)
+            }) {
+                Some(settings.init_location.clone())
+            } else {
+                None
+            };
+
+            (init_location, settings.part_duration > 0)
+        };
+
+        if let Some(init_location) = init_location {
+            let init_segment = buffers.remove(0);
+            gst_info!(CAT, obj: element, "New init segment location: {}", init_location);
+            self.write_cmaf_file(element, &init_location, &[init_segment], None)?;
+
+            let uri = self.playlist_uri(&init_location);
+            let mut state = self.state.lock().unwrap();
+            if let State::Started(state) = &mut *state {
+                state.playlist.set_map(uri);
+            }
+        }
+
+        let fragment_header = match buffers.first() {
+            Some(buffer) => *buffer,
+            None => return Ok(gst::FlowSuccess::Ok),
+        };
+        let (opened_at, duration) = match (fragment_header.pts(), fragment_header.duration()) {
+            (Some(pts), Some(duration)) => (pts, duration),
+            _ => {
+                gst_error!(CAT, obj: element, "Fragment without timestamp or duration");
+                return Err(gst::FlowError::Error);
+            }
+        };
+
+        if parts {
+            // Chunks that continue a fragment are marked as delta units
+            let fragment_start = !fragment_header
+                .flags()
+                .contains(gst::BufferFlags::DELTA_UNIT);
+            return self.on_cmaf_part(element, &buffers, opened_at, duration, fragment_start);
+        }
+
+        let (segment_location, encryptor) = {
+            let mut state_guard = self.state.lock().unwrap();
+            let state = match &mut *state_guard {
+                State::Stopped => return Err(gst::FlowError::Flushing),
+                State::Started(s) => s,
+            };
+
+            let settings = self.settings.lock().unwrap();
+            let segment_idx = state.next_segment_idx;
+            let segment_location = settings.segment_formatter.segment(segment_idx);
+            let encryptor = self
+                .start_segment_encryption(element, state, &settings, segment_idx)
+                .map_err(|err| {
+                    gst_error!(CAT, obj: element, "{}", err);
+                    gst::FlowError::Error
+                })?;
+            state.next_segment_idx += 1;
+
+            (segment_location, encryptor)
+        };
+
+        self.write_cmaf_segment(
+            element,
+            &segment_location,
+            &buffers,
+            encryptor,
+            opened_at,
+            opened_at + duration,
+        )?;
+
+        Ok(gst::FlowSuccess::Ok)
+    }
+
+    /// Writes a chunk of `cmafmux` as partial segment and collects it for the full segment.
+    ///
+    /// The previous segment is complete once a chunk starts a new fragment.
+    fn on_cmaf_part(
+        &self,
+        element: &super::HlsSink3,
+        buffers: &[&gst::BufferRef],
+        pts: gst::ClockTime,
+        duration: gst::ClockTime,
+        fragment_start: bool,
+    ) -> Result<gst::FlowSuccess, gst::FlowError> {
+        if fragment_start {
+            self.finish_cmaf_segment(element)?;
+        }
+
+        let (part_location, next_part_location, independent) = {
+            let mut state_guard = self.state.lock().unwrap();
+            let state = match &mut *state_guard {
+                State::Stopped => return Err(gst::FlowError::Flushing),
+                State::Started(s) => s,
+            };
+
+            let settings = self.settings.lock().unwrap();
+            let part_location = settings.part_formatter.segment(state.next_part_idx);
+            state.next_part_idx += 1;
+            let next_part_location = settings.part_formatter.segment(state.next_part_idx);
+
+            // Only chunks starting a fragment are guaranteed to start with a video keyframe
+            let independent = fragment_start || !settings.video_sink;
+
+            (part_location, next_part_location, independent)
+        };
+
+        gst_info!(CAT, obj: element, "New part location: {}", part_location);
+        self.write_cmaf_file(element, &part_location, buffers, None)?;
+
+        let part_uri = self.playlist_uri(&part_location);
+        let next_part_uri = self.playlist_uri(&next_part_location);
+
+        let mut state_guard = self.state.lock().unwrap();
+        let state = match &mut *state_guard {
+            State::Stopped => return Err(gst::FlowError::Flushing),
+            State::Started(s) => s,
+        };
+
+        state.segment_start.get_or_insert(pts);
+        state.segment_end = Some(pts + duration);
+        state
+            .segment_buffers
+            .extend(buffers.iter().map(|buffer| buffer.to_owned()));
+        state.current_part_locations.push(part_location);
+
+        state
+            .playlist
+            .add_part(part_uri, duration.mseconds() as f32 / 1_000f32, independent);
+        state.playlist.set_preload_hint(Some(next_part_uri));
+
+        let playlist_location = self.settings.lock().unwrap().playlist_location.clone();
+        self.write_playlist_content(element, state, &playlist_location)
+            .map_err(|_| gst::FlowError::Error)?;
+
+        Ok(gst::FlowSuccess::Ok)
+    }
+
+    /// Finishes the last segment at the end of the stream in Low-Latency HLS mode.
+    fn on_cmaf_eos(&self, element: &super::HlsSink3) -> Result<(), gst::FlowError> {
+        {
+            let mut state = self.state.lock().unwrap();
+            if let State::Started(state) = &mut *state {
+                state.playlist.set_preload_hint(None);
+            }
+        }
+
+        self.finish_cmaf_segment(element)
+    }
+
+    /// Writes the segment made of all partial segments so far, if any.
+    fn finish_cmaf_segment(&self, element: &super::HlsSink3) -> Result<(), gst::FlowError> {
+        let (segment_location, buffers, start, end) = {
+            let mut state_guard = self.state.lock().unwrap();
+            let state = match &mut *state_guard {
+                State::Stopped => return Err(gst::FlowError::Flushing),
+                State::Started(s) => s,
+            };
+
+            let (start, end) = match (state.segment_start.take(), state.segment_end.take()) {
+                (Some(start), Some(end)) => (start, end),
+                _ => return Ok(()),
+            };
+            let buffers = std::mem::take(&mut state.segment_buffers);
+
+            let settings = self.settings.lock().unwrap();
+            let segment_location = settings.segment_formatter.segment(state.next_segment_idx);
+            state.next_segment_idx += 1;
+
+            (segment_location, buffers, start, end)
+        };
+
+        // AES-128 encryption is not supported together with partial segments
+        let buffers = buffers
+            .iter()
+            .map(|buffer| buffer.as_ref())
+            .collect::<Vec<_>>();
+        self.write_cmaf_segment(element, &segment_location, &buffers, None, start, end)
+    }
+
+    /// Writes a complete CMAF segment and adds it to the playlist.
+    fn write_cmaf_segment(
+        &self,
+        element: &super::HlsSink3,
+        location: &str,
+        buffers: &[&gst::BufferRef],
+        encryptor: Option<SegmentEncryptor>,
+        opened_at: gst::ClockTime,
+        closed_at: gst::ClockTime,
+    ) -> Result<(), gst::FlowError> {
+        gst_info!(CAT, obj: element, "New segment location: {}", location);
+        self.write_cmaf_file(element, location, buffers, encryptor)?;
+
+        {
+            let mut state = self.state.lock().unwrap();
+            if let State::Started(state) = &mut *state {
+                state.fragment_opened_at = Some(opened_at);
+                state.current_segment_location = Some(location.to_string());
+            }
+        }
+
+        self.write_playlist(element, Some(closed_at))
+            .map_err(|_| gst::FlowError::Error)?;
+
+        Ok(())
+    }
+
+    /// Writes the buffers to a new file, encrypting them as a whole if an encryptor is given.
+    fn write_cmaf_file(
+        &self,
+        element: &super::HlsSink3,
+        location: &str,
+        buffers: &[&gst::BufferRef],
+        mut encryptor: Option<SegmentEncryptor>,
+    ) -> Result<(), gst::FlowError> {
+        let mut stream = element
+            .emit_by_name::<Option<gio::OutputStream>>(SIGNAL_GET_FRAGMENT_STREAM, &[&location])
+            .ok_or_else(|| {
+                gst_error!(CAT, obj: element, "Error while getting fragment stream");
+                gst::FlowError::Error
+            })?
+            .into_write();
+
+        let write_error = |err: std::io::Error| {
+            gst_error!(CAT, obj: element, "Could not write {}: {}", location, err);
+            gst::FlowError::Error
+        };
+
+        for buffer in buffers {
+            let map = buffer.map_readable().map_err(|_| gst::FlowError::Error)?;
+            let res = match encryptor {
+                Some(ref mut encryptor) => stream.write_all(&encryptor.encrypt(&map)),
+                None => stream.write_all(&map),
+            };
+            res.map_err(write_error)?;
+        }
+        if let Some(encryptor) = encryptor {
+            stream.write_all(&encryptor.finish()).map_err(write_error)?;
+        }
+        stream.flush().map_err(|err| {
+            gst_error!(CAT, obj: element, "Could not flush {}: {}", location, err);
+            gst::FlowError::Error
         })?;
-        Ok(gio::WriteOutputStream::new(file).upcast())
+
+        Ok(())
     }
 
-    fn delete_fragment<P>(&self, element: &super::HlsSink3, location: &P)
-    where
-        P: AsRef<path::Path>,
-    {
-        let _ = fs::remove_file(location).map_err(|err| {
-            gst_warning!(
+    fn request_new_cmaf_pad(
+        &self,
+        element: &super::HlsSink3,
+        settings: &mut Settings,
+        templ: &gst::PadTemplate,
+    ) -> Option<gst::Pad> {
+        if settings.audio_sink || settings.video_sink || settings.subtitle_sink {
+            gst_debug!(
                 CAT,
                 obj: element,
-                "Could not delete segment file: {}",
-                err.to_string()
+                "requested_new_pad: only a single stream is supported with CMAF segments"
             );
-        });
+            return None;
+        }
+
+        let name = match templ.name_template().as_ref().map(|val| val.as_str()) {
+            Some("audio") => "audio",
+            Some("video") => "video",
+            Some("subtitle") => "subtitle",
+            _ => {
+                gst_debug!(
+                    CAT,
+                    obj: element,
+                    "requested_new_pad: not audio, video or subtitle"
+                );
+                return None;
+            }
+        };
+
+        let peer_pad = settings.cmafmux.as_ref()?.static_pad("sink").unwrap();
+        let sink_pad =
+            gst::GhostPad::from_template_with_target(templ, Some(name), &peer_pad).unwrap();
+        self.add_sink_pad_probe(element, &sink_pad);
+        element.add_pad(&sink_pad).unwrap();
+        sink_pad.set_active(true).unwrap();
+        match name {
+            "audio" => settings.audio_sink = true,
+            "video" => settings.video_sink = true,
+            _ => settings.subtitle_sink = true,
+        }
+
+        Some(sink_pad.upcast())
     }
 
     fn write_playlist(
@@ -241,12 +1354,32 @@ impl HlsSink3 {
 
         // Only add fragment if it's complete.
         if let Some(fragment_closed) = fragment_closed_at {
+            if let Some(key) = state.current_segment_key.take() {
+                state.playlist.set_key(Some(key));
+            }
+
+            if state
+                .pending_discontinuity
+                .map_or(false, |discontinuity| fragment_closed > discontinuity)
+            {
+                state.pending_discontinuity = None;
+                state.playlist.mark_discontinuity();
+            }
+
+            if self.settings.lock().unwrap().program_date_time {
+                let program_date_time = self.program_date_time(element, state);
+                state.playlist.set_program_date_time(program_date_time);
+            }
+
             let segment_filename = self.segment_filename(state);
             state.playlist.add_segment(
                 segment_filename.clone(),
                 state.fragment_duration_since(fragment_closed),
             );
             state.old_segment_locations.push(segment_filename);
+            state
+                .old_part_locations
+                .push(std::mem::take(&mut state.current_part_locations));
         }
 
         let (playlist_location, max_num_segments, max_playlist_length) = {
@@ -259,7 +1392,44 @@ impl HlsSink3 {
         };
 
         state.playlist.update_playlist_state(max_playlist_length);
+        self.write_playlist_content(element, state, &playlist_location)?;
+
+        if state.playlist.is_type_undefined() {
+            // Cleanup old segments from filesystem
+            if state.old_segment_locations.len() > max_num_segments {
+                for _ in 0..state.old_segment_locations.len() - max_num_segments {
+                    let old_segment_location = state.old_segment_locations.remove(0);
+                    if !element
+                        .emit_by_name::<bool>(SIGNAL_DELETE_FRAGMENT, &[&old_segment_location])
+                    {
+                        gst_error!(CAT, obj: element, "Could not delete fragment");
+                    }
 
+                    // The partial segments are deleted together with their segment
+                    if !state.old_part_locations.is_empty() {
+                        for old_part_location in state.old_part_locations.remove(0) {
+                            if !element
+                                .emit_by_name::<bool>(SIGNAL_DELETE_FRAGMENT, &[&old_part_location])
+                            {
+                                gst_error!(CAT, obj: element, "Could not delete part");
+                            }
+                        }
+                    }
+                }
+            }
+        }
+
+        gst_debug!(CAT, obj: element, "Wrote new playlist file!");
+        Ok(gst::StateChangeSuccess::Success)
+    }
+
+    /// Writes the current content of the playlist.
+    fn write_playlist_content(
+        &self,
+        element: &super::HlsSink3,
+        state: &StartedState,
+        playlist_location: &str,
+    ) -> Result<(), gst::StateChangeError> {
         // Acquires the playlist file handle so we can update it with new content. By default, this
         // is expected to be the same file every time.
         let mut playlist_stream = element
@@ -299,34 +1469,17 @@ impl HlsSink3 {
             gst::StateChangeError
         })?;
 
-        if state.playlist.is_type_undefined() {
-            // Cleanup old segments from filesystem
-            if state.old_segment_locations.len() > max_num_segments {
-                for _ in 0..state.old_segment_locations.len() - max_num_segments {
-                    let old_segment_location = state.old_segment_locations.remove(0);
-                    if !element
-                        .emit_by_name::<bool>(SIGNAL_DELETE_FRAGMENT, &[&old_segment_location])
-                    {
-                        gst_error!(CAT, obj: element, "Could not delete fragment");
-                    }
-                }
-            }
-        }
-
-        gst_debug!(CAT, obj: element, "Wrote new playlist file!");
-        Ok(gst::StateChangeSuccess::Success)
+        Ok(())
     }
 
     fn segment_filename(&self, state: &mut StartedState) -> String {
         assert!(state.current_segment_location.is_some());
-        let segment_filename = path_basename(state.current_segment_location.take().unwrap());
+        self.playlist_uri(&state.current_segment_location.take().unwrap())
+    }
 
-        let settings = self.settings.lock().unwrap();
-        if let Some(playlist_root) = &settings.playlist_root {
-            format!("{}/{}", playlist_root, segment_filename)
-        } else {
-            segment_filename
-        }
+    /// URI of a file inside the playlist, relative to the playlist root.
+    fn playlist_uri(&self, location: &str) -> String {
+        self.settings.lock().unwrap().playlist_uri(location)
     }
 
     fn write_final_playlist(
@@ -387,6 +1540,8 @@ impl BinImpl for HlsSink3 {
                         }
                     }
                     "splitmuxsink-fragment-closed" => {
+                        self.finish_segment_encryption(element);
+
                         let s = msg.structure().unwrap();
                         if let Ok(fragment_closed_at) = s.get::<gst::ClockTime>("running-time") {
                             self.write_playlist(element, Some(fragment_closed_at))
@@ -467,6 +1622,82 @@ impl ObjectImpl for HlsSink3 {
                     DEFAULT_SEND_KEYFRAME_REQUESTS,
                     glib::ParamFlags::READWRITE,
                 ),
+                glib::ParamSpecEnum::new(
+                    "segment-format",
+                    "Segment Format",
+                    "Format of the media segments. CMAF segments only support a single stream and require the cmafmux element.",
+                    SegmentFormat::static_type(),
+                    DEFAULT_SEGMENT_FORMAT as i32,
+                    glib::ParamFlags::READWRITE,
+                ),
+                glib::ParamSpecString::new(
+                    "init-location",
+                    "Init Location",
+                    "Location of the initialization segment to write in CMAF mode",
+                    Some(DEFAULT_INIT_LOCATION),
+                    glib::ParamFlags::READWRITE,
+                ),
+                glib::ParamSpecUInt::new(
+                    "part-duration",
+                    "Part duration",
+                    "The target duration in milliseconds of the Low-Latency HLS partial segments. Only supported with CMAF segments. (0 - disabled)",
+                    0,
+                    u32::MAX,
+                    DEFAULT_PART_DURATION,
+                    glib::ParamFlags::READWRITE,
+                ),
+                glib::ParamSpecString::new(
+                    "part-location",
+                    "Part Location",
+                    "Location of the partial segment files to write in Low-Latency HLS mode",
+                    Some(DEFAULT_PART_LOCATION),
+                    glib::ParamFlags::READWRITE,
+                ),
+                glib::ParamSpecEnum::new(
+                    "encryption-method",
+                    "Encryption Method",
+                    "Method for encrypting the segments. AES-128 is not supported with partial segments, SAMPLE-AES only encrypts H.264 and AAC in MPEG-TS segments.",
+                    EncryptionMethod::static_type(),
+                    DEFAULT_ENCRYPTION_METHOD as i32,
+                    glib::ParamFlags::READWRITE,
+                ),
+                glib::ParamSpecString::new(
+                    "key-location",
+                    "Key Location",
+                    "Location of the key files to write, which are requested via the get-fragment-stream signal",
+                    Some(DEFAULT_KEY_LOCATION),
+                    glib::ParamFlags::READWRITE,
+                ),
+                glib::ParamSpecString::new(
+                    "key-uri",
+                    "Key URI",
+                    "URI of the key files in the playlist with the same placeholder as key-location. (None - key location relative to the playlist root)",
+                    None,
+                    glib::ParamFlags::READWRITE,
+                ),
+                glib::ParamSpecUInt::new(
+                    "key-rotation",
+                    "Key rotation",
+                    "Number of segments after which a new key is used, not supported with SAMPLE-AES and CMAF segments. (0 - never rotate)",
+                    0,
+                    u32::MAX,
+                    DEFAULT_KEY_ROTATION,
+                    glib::ParamFlags::READWRITE,
+                ),
+                glib::ParamSpecBoolean::new(
+                    "program-date-time",
+                    "Program date time",
+                    "Add EXT-X-PROGRAM-DATE-TIME tags with the wall clock time of each segment, taken from upstream reference timestamps if available",
+                    DEFAULT_PROGRAM_DATE_TIME,
+                    glib::ParamFlags::READWRITE,
+                ),
+                glib::ParamSpecBoolean::new(
+                    "resume",
+                    "Resume",
+                    "Continue an existing playlist at playlist-location instead of overwriting it",
+                    DEFAULT_RESUME,
+                    glib::ParamFlags::READWRITE,
+                ),
             ]
         });
 
@@ -475,7 +1706,7 @@ impl ObjectImpl for HlsSink3 {
 
     fn set_property(
         &self,
-        _obj: &Self::Type,
+        obj: &Self::Type,
         _id: usize,
         value: &glib::Value,
         pspec: &glib::ParamSpec,
@@ -515,6 +1746,12 @@ impl ObjectImpl for HlsSink3 {
                     "max-size-time",
                     &(gst::ClockTime::from_seconds(settings.target_duration as u64)),
                 );
+                if let Some(cmafmux) = &settings.cmafmux {
+                    cmafmux.set_property(
+                        "fragment-duration",
+                        gst::ClockTime::from_seconds(settings.target_duration as u64),
+                    );
+                }
             }
             "playlist-length" => {
                 settings.playlist_length = value.get().expect("type checked upstream");
@@ -537,6 +1774,62 @@ impl ObjectImpl for HlsSink3 {
                     .splitmuxsink
                     .set_property("send-keyframe-requests", &settings.send_keyframe_requests);
             }
+            "segment-format" => {
+                let segment_format = value.get().expect("type checked upstream");
+                self.set_segment_format(obj, &mut settings, segment_format);
+            }
+            "init-location" => {
+                settings.init_location = value
+                    .get::<Option<String>>()
+                    .expect("type checked upstream")
+                    .unwrap_or_else(|| String::from(DEFAULT_INIT_LOCATION));
+            }
+            "part-duration" => {
+                settings.part_duration = value.get().expect("type checked upstream");
+                if let Some(cmafmux) = &settings.cmafmux {
+                    cmafmux.set_property("chunk-duration", chunk_duration(settings.part_duration));
+                }
+            }
+            "part-location" => {
+                settings.part_location = value
+                    .get::<Option<String>>()
+                    .expect("type checked upstream")
+                    .unwrap_or_else(|| String::from(DEFAULT_PART_LOCATION));
+                settings.part_formatter = SegmentFormatter::new(&settings.part_location).expect(
+                    "A string containing `%03d` pattern must be used (can be any number from 0-9)",
+                );
+            }
+            "encryption-method" => {
+                settings.encryption_method = value.get().expect("type checked upstream");
+            }
+            "key-location" => {
+                settings.key_location = value
+                    .get::<Option<String>>()
+                    .expect("type checked upstream")
+                    .unwrap_or_else(|| String::from(DEFAULT_KEY_LOCATION));
+                settings.key_formatter = SegmentFormatter::new(&settings.key_location).expect(
+                    "A string containing `%03d` pattern must be used (can be any number from 0-9)",
+                );
+            }
+            "key-uri" => {
+                settings.key_uri = value
+                    .get::<Option<String>>()
+                    .expect("type checked upstream");
+                settings.key_uri_formatter = settings.key_uri.as_ref().map(|key_uri| {
+                    SegmentFormatter::new(key_uri).expect(
+                        "A string containing `%03d` pattern must be used (can be any number from 0-9)",
+                    )
+                });
+            }
+            "key-rotation" => {
+                settings.key_rotation = value.get().expect("type checked upstream");
+            }
+            "program-date-time" => {
+                settings.program_date_time = value.get().expect("type checked upstream");
+            }
+            "resume" => {
+                settings.resume = value.get().expect("type checked upstream");
+            }
             _ => unimplemented!(),
         };
     }
@@ -559,6 +1852,16 @@ impl ObjectImpl for HlsSink3 {
                 .map(|ty| ty.to_string())
                 .to_value(),
             "send-keyframe-requests" => settings.send_keyframe_requests.to_value(),
+            "segment-format" => settings.segment_format.to_value(),
+            "init-location" => settings.init_location.to_value(),
+            "part-duration" => settings.part_duration.to_value(),
+            "part-location" => settings.part_location.to_value(),
+            "encryption-method" => settings.encryption_method.to_value(),
+            "key-location" => settings.key_location.to_value(),
+            "key-uri" => settings.key_uri.to_value(),
+            "key-rotation" => settings.key_rotation.to_value(),
+            "program-date-time" => settings.program_date_time.to_value(),
+            "resume" => settings.resume.to_value(),
             _ => unimplemented!(),
         }
     }
@@ -577,11 +1880,9 @@ impl ObjectImpl for HlsSink3 {
                         .expect("playlist-stream signal arg");
                     let playlist_location =
                         args[1].get::<String>().expect("playlist-stream signal arg");
-                    let hlssink3 = HlsSink3::from_instance(&element);
 
                     Some(
-                        hlssink3
-                            .new_file_stream(&element, &playlist_location)
+                        new_file_stream(element.upcast_ref(), &playlist_location)
                             .ok()?
                             .to_value(),
                     )
@@ -603,11 +1904,9 @@ impl ObjectImpl for HlsSink3 {
                         .expect("fragment-stream signal arg");
                     let fragment_location =
                         args[1].get::<String>().expect("fragment-stream signal arg");
-                    let hlssink3 = HlsSink3::from_instance(&element);
 
                     Some(
-                        hlssink3
-                            .new_file_stream(&element, &fragment_location)
+                        new_file_stream(element.upcast_ref(), &fragment_location)
                             .ok()?
                             .to_value(),
                     )
@@ -626,9 +1925,8 @@ impl ObjectImpl for HlsSink3 {
                 .class_handler(|_, args| {
                     let element = args[0].get::<super::HlsSink3>().expect("signal arg");
                     let fragment_location = args[1].get::<String>().expect("signal arg");
-                    let hlssink3 = HlsSink3::from_instance(&element);
 
-                    hlssink3.delete_fragment(&element, &fragment_location);
+                    delete_fragment(element.upcast_ref(), &fragment_location);
                     Some(true.to_value())
                 })
                 .accumulator(|_hint, ret, value| {
@@ -669,6 +1967,50 @@ impl ObjectImpl for HlsSink3 {
 
         obj.add(&settings.splitmuxsink).unwrap();
 
+        // AES-128 encryption happens on the way into the segment files
+        let giostreamsink_pad = settings.giostreamsink.static_pad("sink").unwrap();
+        giostreamsink_pad.add_probe(
+            gst::PadProbeType::BUFFER | gst::PadProbeType::BUFFER_LIST,
+            {
+                let element_weak = obj.downgrade();
+                move |_pad, info| match element_weak.upgrade() {
+                    Some(element) => HlsSink3::from_instance(&element).encrypt_segment_data(info),
+                    None => gst::PadProbeReturn::Ok,
+                }
+            },
+        );
+        giostreamsink_pad.add_probe(
+            gst::PadProbeType::BUFFER | gst::PadProbeType::BUFFER_LIST,
+            {
+                let element_weak = obj.downgrade();
+                move |_pad, info| match element_weak.upgrade() {
+                    Some(element) => HlsSink3::from_instance(&element).rewrite_segment_pmt(info),
+                    None => gst::PadProbeReturn::Ok,
+                }
+            },
+        );
+
+        // SAMPLE-AES encryption happens on the way into the muxer, after splitmuxsink decided
+        // which segment the samples belong to
+        mux.connect_pad_added({
+            let element_weak = obj.downgrade();
+            move |_mux, pad| {
+                if pad.direction() != gst::PadDirection::Sink {
+                    return;
+                }
+                let element_weak = element_weak.clone();
+                pad.add_probe(
+                    gst::PadProbeType::BUFFER,
+                    move |pad, info| match element_weak.upgrade() {
+                        Some(element) => {
+                            HlsSink3::from_instance(&element).encrypt_samples(pad, info)
+                        }
+                        None => gst::PadProbeReturn::Ok,
+                    },
+                );
+            }
+        });
+
         settings.splitmuxsink.connect("format-location", false, {
             let element_weak = obj.downgrade();
             move |args| {
@@ -732,7 +2074,21 @@ impl ElementImpl for HlsSink3 {
             )
             .unwrap();
 
-            vec![video_pad_template, audio_pad_template]
+            // Subtitles can only be muxed into CMAF segments
+            let caps = gst::Caps::new_any();
+            let subtitle_pad_template = gst::PadTemplate::new(
+                "subtitle",
+                gst::PadDirection::Sink,
+                gst::PadPresence::Request,
+                &caps,
+            )
+            .unwrap();
+
+            vec![
+                video_pad_template,
+                audio_pad_template,
+                subtitle_pad_template,
+            ]
         });
 
         PAD_TEMPLATES.as_ref()
@@ -744,7 +2100,7 @@ impl ElementImpl for HlsSink3 {
         transition: gst::StateChange,
     ) -> Result<gst::StateChangeSuccess, gst::StateChangeError> {
         if let gst::StateChange::NullToReady = transition {
-            self.start(element);
+            self.start(element)?;
         }
 
         let ret = self.parent_change_state(element, transition)?;
@@ -787,6 +2143,11 @@ impl ElementImpl for HlsSink3 {
         _caps: Option<&gst::Caps>,
     ) -> Option<gst::Pad> {
         let mut settings = self.settings.lock().unwrap();
+
+        if settings.segment_format == SegmentFormat::Cmaf {
+            return self.request_new_cmaf_pad(element, &mut settings, templ);
+        }
+
         match templ.name_template().as_ref().map(|val| val.as_str()) {
             Some("audio") => {
                 if settings.audio_sink {
@@ -802,6 +2163,7 @@ impl ElementImpl for HlsSink3 {
                 let sink_pad =
                     gst::GhostPad::from_template_with_target(templ, Some("audio"), &peer_pad)
                         .unwrap();
+                self.add_sink_pad_probe(element, &sink_pad);
                 element.add_pad(&sink_pad).unwrap();
                 sink_pad.set_active(true).unwrap();
                 settings.audio_sink = true;
@@ -822,6 +2184,7 @@ impl ElementImpl for HlsSink3 {
                 let sink_pad =
                     gst::GhostPad::from_template_with_target(templ, Some("video"), &peer_pad)
                         .unwrap();
+                self.add_sink_pad_probe(element, &sink_pad);
                 element.add_pad(&sink_pad).unwrap();
                 sink_pad.set_active(true).unwrap();
                 settings.video_sink = true;
@@ -847,28 +2210,104 @@ impl ElementImpl for HlsSink3 {
     fn release_pad(&self, element: &Self::Type, pad: &gst::Pad) {
         let mut settings = self.settings.lock().unwrap();
 
-        if !settings.audio_sink && !settings.video_sink {
+        if !settings.audio_sink && !settings.video_sink && !settings.subtitle_sink {
             return;
         }
 
         let ghost_pad = pad.downcast_ref::<gst::GhostPad>().unwrap();
         if let Some(peer) = ghost_pad.target() {
-            settings.splitmuxsink.release_request_pad(&peer);
+            // The sink pad of cmafmux is an always pad
+            if settings.segment_format == SegmentFormat::MpegTs {
+                settings.splitmuxsink.release_request_pad(&peer);
+            }
         }
 
         pad.set_active(false).unwrap();
         element.remove_pad(pad).unwrap();
 
-        if "audio" == ghost_pad.name() {
-            settings.audio_sink = false;
-        } else {
-            settings.video_sink = false;
+        match ghost_pad.name().as_str() {
+            "audio" => settings.audio_sink = false,
+            "subtitle" => settings.subtitle_sink = false,
+            _ => settings.video_sink = false,
         }
     }
 }
 
+/// Creates a file at `location` and returns a stream for writing to it.
+pub(crate) fn new_file_stream<P>(
+    element: &gst::Element,
+    location: &P,
+) -> Result<gio::OutputStream, String>
+where
+    P: AsRef<path::Path>,
+{
+    let file = fs::File::create(location).map_err(move |err| {
+        let error_msg = gst::error_msg!(
+            gst::ResourceError::OpenWrite,
+            [
+                "Could not open file {} for writing: {}",
+                location.as_ref().to_str().unwrap(),
+                err.to_string(),
+            ]
+        );
+        element.post_error_message(error_msg);
+        err.to_string()
+    })?;
+    Ok(gio::WriteOutputStream::new(file).upcast())
+}
+
+/// Deletes the file at `location`.
+pub(crate) fn delete_fragment<P>(element: &gst::Element, location: &P)
+where
+    P: AsRef<path::Path>,
+{
+    let _ = fs::remove_file(location).map_err(|err| {
+        gst_warning!(
+            CAT,
+            obj: element,
+            "Could not delete segment file: {}",
+            err.to_string()
+        );
+    });
+}
+
+/// Target duration of the partial segments in seconds, if Low-Latency HLS is enabled.
+/// Creates a buffer with new data and the flags, timestamps and metas of `buffer`.
+fn buffer_with_data(buffer: &gst::BufferRef, data: Vec<u8>) -> gst::Buffer {
+    let mut new_buffer = gst::Buffer::from_mut_slice(data);
+    {
+        let new_buffer = new_buffer.get_mut().unwrap();
+        let _ = buffer.copy_into(
+            new_buffer,
+            gst::BufferCopyFlags::FLAGS
+                | gst::BufferCopyFlags::TIMESTAMPS
+                | gst::BufferCopyFlags::META,
+            0,
+            None,
+        );
+    }
+    new_buffer
+}
+
+fn part_target(settings: &Settings) -> Option<f32> {
+    if settings.part_duration > 0 && settings.segment_format == SegmentFormat::Cmaf {
+        Some(settings.part_duration as f32 / 1_000f32)
+    } else {
+        None
+    }
+}
+
+/// Duration of the `cmafmux` chunks for the given part duration in milliseconds.
+fn chunk_duration(part_duration: u32) -> Option<gst::ClockTime> {
+    if part_duration > 0 {
+        Some(gst::ClockTime::from_mseconds(part_duration as u64))
+    } else {
+        None
+    }
+}
+
 /// The content of the last item of a path separated by `/` character.
-fn path_basename(name: impl AsRef<str>) -> String {
+pub(crate) fn path_basename(name: impl AsRef<str>) -> String {
     name.as_ref().split('/').last().unwrap().to_string()
 }
 
@@ -9,8 +9,47 @@
 
 use glib::prelude::*;
 
+mod dashsink;
+mod encryption;
 mod imp;
+mod mpd;
+mod multivariantsink;
 mod playlist;
+mod sample_aes;
+
+#[derive(Debug, Eq, PartialEq, Clone, Copy, glib::Enum)]
+#[repr(u32)]
+#[enum_type(name = "GstHlsSink3SegmentFormat")]
+pub(crate) enum SegmentFormat {
+    #[enum_value(
+        name = "MpegTs: MPEG-TS segments muxed with mpegtsmux.",
+        nick = "mpegts"
+    )]
+    MpegTs,
+    #[enum_value(
+        name = "Cmaf: CMAF (fragmented MP4) segments muxed with cmafmux.",
+        nick = "cmaf"
+    )]
+    Cmaf,
+}
+
+#[derive(Debug, Eq, PartialEq, Clone, Copy, glib::Enum)]
+#[repr(u32)]
+#[enum_type(name = "GstHlsSink3EncryptionMethod")]
+pub(crate) enum EncryptionMethod {
+    #[enum_value(name = "None: Segments are not encrypted.", nick = "none")]
+    None,
+    #[enum_value(
+        name = "Aes128: Whole segments are encrypted with AES-128-CBC.",
+        nick = "aes-128"
+    )]
+    Aes128,
+    #[enum_value(
+        name = "SampleAes: Samples are encrypted, with the cbcs scheme for CMAF segments.",
+        nick = "sample-aes"
+    )]
+    SampleAes,
+}
 
 glib::wrapper! {
     pub struct HlsSink3(ObjectSubclass<imp::HlsSink3>) @extends gst::Bin, gst::Element, gst::Object;
@@ -26,6 +65,8 @@ pub fn plugin_init(plugin: &gst::Plugin) -> Result<(), glib::BoolError> {
         gst::Rank::None,
         HlsSink3::static_type(),
     )?;
+    multivariantsink::register(plugin)?;
+    dashsink::register(plugin)?;
 
     Ok(())
 }
@@ -0,0 +1,526 @@
+//
+// Copyright (C) 2026 agent <agent@local>
+//
+// This Source Code Form is subject to the terms of the Mozilla Public License, v2.0.
+// If a copy of the MPL was not distributed with this file, You can obtain one at
+// <https://mozilla.org/MPL/2.0/>.
+//
+// SPDX-License-Identifier: MPL-2.0
+
+use std::collections::VecDeque;
+use std::fmt::Write as _;
+use std::io::Write;
+
+/// Timescale of the segment timelines, all times in the MPD are in milliseconds.
+pub const MPD_TIMESCALE: u64 = 1_000;
+
+/// Content type of a representation and its adaptation set.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ContentType {
+    Video,
+    Audio,
+    Text,
+}
+
+impl ContentType {
+    fn as_str(&self) -> &'static str {
+        match self {
+            ContentType::Video => "video",
+            ContentType::Audio => "audio",
+            ContentType::Text => "text",
+        }
+    }
+
+    fn mime_type(&self) -> &'static str {
+        match self {
+            ContentType::Video => "video/mp4",
+            ContentType::Audio => "audio/mp4",
+            ContentType::Text => "application/mp4",
+        }
+    }
+}
+
+/// A media segment in the `SegmentTimeline` of a representation.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Segment {
+    time: u64,
+    duration: u64,
+}
+
+/// A representation with its own initialization segment and numbered media segments.
+#[derive(Debug, Clone)]
+pub struct Representation {
+    pub id: String,
+    pub content_type: ContentType,
+    pub codecs: Option<String>,
+    pub bandwidth: u64,
+    pub width: Option<i32>,
+    pub height: Option<i32>,
+    pub frame_rate: Option<(i32, i32)>,
+    pub audio_sampling_rate: Option<i32>,
+    pub language: Option<String>,
+    /// URI of the initialization segment.
+    pub initialization: String,
+    /// URI template of the media segments with a `$Number$` placeholder.
+    pub media: String,
+    start_number: u32,
+    segments: VecDeque<Segment>,
+}
+
+impl Representation {
+    pub fn new(
+        id: String,
+        content_type: ContentType,
+        initialization: String,
+        media: String,
+        start_number: u32,
+    ) -> Self {
+        Self {
+            id,
+            content_type,
+            codecs: None,
+            bandwidth: 0,
+            width: None,
+            height: None,
+            frame_rate: None,
+            audio_sampling_rate: None,
+            language: None,
+            initialization,
+            media,
+            start_number,
+            segments: VecDeque::new(),
+        }
+    }
+
+    /// Whether the timeline contains no segments yet.
+    pub fn is_empty(&self) -> bool {
+        self.segments.is_empty()
+    }
+
+    fn start_time(&self) -> Option<u64> {
+        self.segments.front().map(|segment| segment.time)
+    }
+
+    fn end_time(&self) -> Option<u64> {
+        self.segments
+            .back()
+            .map(|segment| segment.time + segment.duration)
+    }
+}
+
+/// A DASH Media Presentation Description with a single period.
+///
+/// Each representation has its own `SegmentTemplate` with a `SegmentTimeline`, and
+/// representations with the same content type and language are grouped into one adaptation set.
+/// A dynamic MPD keeps a sliding window of segments and turns static once it is ended.
+#[derive(Debug, Clone)]
+pub struct Mpd {
+    dynamic: bool,
+    target_duration: u32,
+    max_segments: usize,
+    availability_start_time: Option<String>,
+    publish_time: Option<String>,
+    representations: Vec<Representation>,
+}
+
+impl Mpd {
+    /// Creates an MPD with segments of `target_duration` seconds.
+    ///
+    /// A dynamic MPD keeps only the last `max_segments` segments of each representation in the
+    /// timeline, unless `max_segments` is 0.
+    pub fn new(dynamic: bool, target_duration: u32, max_segments: usize) -> Self {
+        Self {
+            dynamic,
+            target_duration,
+            max_segments,
+            availability_start_time: None,
+            publish_time: None,
+            representations: Vec::new(),
+        }
+    }
+
+    pub fn is_dynamic(&self) -> bool {
+        self.dynamic
+    }
+
+    /// Sets the wall clock time of the start of the period, required for dynamic MPDs.
+    pub fn set_availability_start_time(&mut self, availability_start_time: String) {
+        self.availability_start_time = Some(availability_start_time);
+    }
+
+    /// Sets the wall clock time at which the MPD is written.
+    pub fn set_publish_time(&mut self, publish_time: String) {
+        self.publish_time = Some(publish_time);
+    }
+
+    pub fn add_representation(&mut self, representation: Representation) {
+        self.representations.retain(|r| r.id != representation.id);
+        self.representations.push(representation);
+    }
+
+    pub fn remove_representation(&mut self, id: &str) {
+        self.representations.retain(|r| r.id != id);
+    }
+
+    pub fn representation(&self, id: &str) -> Option<&Representation> {
+        self.representations.iter().find(|r| r.id == id)
+    }
+
+    pub fn representation_mut(&mut self, id: &str) -> Option<&mut Representation> {
+        self.representations.iter_mut().find(|r| r.id == id)
+    }
+
+    /// Adds a segment starting at `time` with `duration`, in `MPD_TIMESCALE` units, to the
+    /// timeline of a representation.
+    pub fn add_segment(&mut self, id: &str, time: u64, duration: u64) {
+        let (dynamic, max_segments) = (self.dynamic, self.max_segments);
+        let representation = match self.representation_mut(id) {
+            Some(representation) => representation,
+            None => return,
+        };
+
+        representation
+            .segments
+            .push_back(Segment { time, duration });
+
+        if dynamic && max_segments > 0 {
+            while representation.segments.len() > max_segments {
+                representation.segments.pop_front();
+                representation.start_number += 1;
+            }
+        }
+    }
+
+    /// Turns the MPD into a static one that contains all segments that are currently in it.
+    pub fn end(&mut self) {
+        self.dynamic = false;
+    }
+
+    pub fn write_to<T: Write>(&self, w: &mut T) -> std::io::Result<()> {
+        w.write_all(self.to_xml().as_bytes())
+    }
+
+    fn to_xml(&self) -> String {
+        let mut xml = String::new();
+
+        let start_time = self
+            .representations
+            .iter()
+            .filter_map(|r| r.start_time())
+            .min()
+            .unwrap_or(0);
+        let end_time = self
+            .representations
+            .iter()
+            .filter_map(|r| r.end_time())
+            .max()
+            .unwrap_or(0);
+
+        xml.push_str("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n");
+        xml.push_str("<MPD xmlns=\"urn:mpeg:dash:schema:mpd:2011\" profiles=\"urn:mpeg:dash:profile:isoff-live:2011\"");
+        if self.dynamic {
+            xml.push_str(" type=\"dynamic\"");
+            if let Some(availability_start_time) = &self.availability_start_time {
+                write!(
+                    xml,
+                    " availabilityStartTime=\"{}\"",
+                    availability_start_time
+                )
+                .unwrap();
+            }
+            if let Some(publish_time) = &self.publish_time {
+                write!(xml, " publishTime=\"{}\"", publish_time).unwrap();
+            }
+            write!(xml, " minimumUpdatePeriod=\"PT{}S\"", self.target_duration).unwrap();
+
+            // The sliding window of the shortest timeline can be played back
+            let time_shift_buffer_depth = self
+                .representations
+                .iter()
+                .filter_map(|r| Some(r.end_time()? - r.start_time()?))
+                .min()
+                .unwrap_or(0);
+            write!(
+                xml,
+                " timeShiftBufferDepth=\"{}\"",
+                format_duration(time_shift_buffer_depth)
+            )
+            .unwrap();
+        } else {
+            xml.push_str(" type=\"static\"");
+            write!(
+                xml,
+                " mediaPresentationDuration=\"{}\"",
+                format_duration(end_time - start_time)
+            )
+            .unwrap();
+        }
+        writeln!(
+            xml,
+            " maxSegmentDuration=\"PT{0}S\" minBufferTime=\"PT{0}S\">",
+            self.target_duration
+        )
+        .unwrap();
+
+        xml.push_str("  <Period id=\"0\" start=\"PT0S\">\n");
+
+        // A static MPD starts with the first segment
+        let presentation_time_offset = if self.dynamic { 0 } else { start_time };
+
+        let mut adaptation_sets: Vec<(ContentType, Option<&str>)> = Vec::new();
+        for representation in &self.representations {
+            let key = (
+                representation.content_type,
+                representation.language.as_deref(),
+            );
+            if !adaptation_sets.contains(&key) {
+                adaptation_sets.push(key);
+            }
+        }
+
+        for (idx, (content_type, language)) in adaptation_sets.iter().enumerate() {
+            write!(
+                xml,
+                "    <AdaptationSet id=\"{}\" contentType=\"{}\" mimeType=\"{}\"",
+                idx,
+                content_type.as_str(),
+                content_type.mime_type()
+            )
+            .unwrap();
+            if let Some(language) = language {
+                write!(xml, " lang=\"{}\"", escape(language)).unwrap();
+            }
+            xml.push_str(" segmentAlignment=\"true\" startWithSAP=\"1\">\n");
+
+            for representation in self
+                .representations
+                .iter()
+                .filter(|r| r.content_type == *content_type && r.language.as_deref() == *language)
+            {
+                write_representation(&mut xml, representation, presentation_time_offset);
+            }
+
+            xml.push_str("    </AdaptationSet>\n");
+        }
+
+        xml.push_str("  </Period>\n");
+        xml.push_str("</MPD>\n");
+
+        xml
+    }
+}
+
+fn write_representation(
+    xml: &mut String,
+    representation: &Representation,
+    presentation_time_offset: u64,
+) {
+    write!(
+        xml,
+        "      <Representation id=\"{}\" bandwidth=\"{}\"",
+        escape(&representation.id),
+        representation.bandwidth
+    )
+    .unwrap();
+    if let Some(codecs) = &representation.codecs {
+        write!(xml, " codecs=\"{}\"", escape(codecs)).unwrap();
+    }
+    if let (Some(width), Some(height)) = (representation.width, representation.height) {
+        write!(xml, " width=\"{}\" height=\"{}\"", width, height).unwrap();
+    }
+    if let Some((numer, denom)) = representation.frame_rate {
+        if denom == 1 {
+            write!(xml, " frameRate=\"{}\"", numer).unwrap();
+        } else {
+            write!(xml, " frameRate=\"{}/{}\"", numer, denom).unwrap();
+        }
+    }
+    if let Some(rate) = representation.audio_sampling_rate {
+        write!(xml, " audioSamplingRate=\"{}\"", rate).unwrap();
+    }
+    xml.push_str(">\n");
+
+    write!(
+        xml,
+        "        <SegmentTemplate timescale=\"{}\" presentationTimeOffset=\"{}\" startNumber=\"{}\" initialization=\"{}\" media=\"{}\">\n",
+        MPD_TIMESCALE,
+        presentation_time_offset,
+        representation.start_number,
+        escape(&representation.initialization),
+        escape(&representation.media)
+    )
+    .unwrap();
+    xml.push_str("          <SegmentTimeline>\n");
+
+    // Consecutive segments with the same duration are collapsed into one entry
+    let mut entries: Vec<(Segment, u32)> = Vec::new();
+    for segment in &representation.segments {
+        match entries.last_mut() {
+            Some((first, repeat))
+                if first.duration == segment.duration
+                    && first.time + first.duration * (*repeat as u64 + 1) == segment.time =>
+            {
+                *repeat += 1;
+            }
+            _ => entries.push((*segment, 0)),
+        }
+    }
+
+    let mut next_time = None;
+    for (segment, repeat) in entries {
+        xml.push_str("            <S");
+        // The start time is only needed if it doesn't follow the previous entry
+        if next_time != Some(segment.time) {
+            write!(xml, " t=\"{}\"", segment.time).unwrap();
+        }
+        write!(xml, " d=\"{}\"", segment.duration).unwrap();
+        if repeat > 0 {
+            write!(xml, " r=\"{}\"", repeat).unwrap();
+        }
+        xml.push_str("/>\n");
+
+        next_time = Some(segment.time + segment.duration * (repeat as u64 + 1));
+    }
+
+    xml.push_str("          </SegmentTimeline>\n");
+    xml.push_str("        </SegmentTemplate>\n");
+    xml.push_str("      </Representation>\n");
+}
+
+/// Formats a duration in `MPD_TIMESCALE` units as ISO 8601 duration.
+fn format_duration(duration: u64) -> String {
+    format!(
+        "PT{}.{:03}S",
+        duration / MPD_TIMESCALE,
+        duration % MPD_TIMESCALE
+    )
+}
+
+/// Escapes a value for use in an XML attribute.
+fn escape(value: &str) -> String {
+    value
+        .replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn video_representation() -> Representation {
+        let mut representation = Representation::new(
+            String::from("video_0"),
+            ContentType::Video,
+            String::from("video_0_init.mp4"),
+            String::from("video_0_segment$Number%05d$.m4s"),
+            0,
+        );
+        representation.codecs = Some(String::from("avc1.64001f"));
+        representation.bandwidth = 1_000_000;
+        representation.width = Some(1280);
+        representation.height = Some(720);
+        representation.frame_rate = Some((30, 1));
+        representation
+    }
+
+    #[test]
+    fn dynamic_mpd_keeps_sliding_window() {
+        let mut mpd = Mpd::new(true, 2, 3);
+        mpd.set_availability_start_time(String::from("2021-12-01T00:00:00.000Z"));
+        mpd.set_publish_time(String::from("2021-12-01T00:00:10.000Z"));
+        mpd.add_representation(video_representation());
+
+        for idx in 0..5 {
+            mpd.add_segment("video_0", idx * 2_000, 2_000);
+        }
+
+        let mut content = Vec::new();
+        mpd.write_to(&mut content).unwrap();
+        assert_eq!(
+            r###"<?xml version="1.0" encoding="UTF-8"?>
+<MPD xmlns="urn:mpeg:dash:schema:mpd:2011" profiles="urn:mpeg:dash:profile:isoff-live:2011" type="dynamic" availabilityStartTime="2021-12-01T00:00:00.000Z" publishTime="2021-12-01T00:00:10.000Z" minimumUpdatePeriod="PT2S" timeShiftBufferDepth="PT6.000S" maxSegmentDuration="PT2S" minBufferTime="PT2S">
+  <Period id="0" start="PT0S">
+    <AdaptationSet id="0" contentType="video" mimeType="video/mp4" segmentAlignment="true" startWithSAP="1">
+      <Representation id="video_0" bandwidth="1000000" codecs="avc1.64001f" width="1280" height="720" frameRate="30">
+        <SegmentTemplate timescale="1000" presentationTimeOffset="0" startNumber="2" initialization="video_0_init.mp4" media="video_0_segment$Number%05d$.m4s">
+          <SegmentTimeline>
+            <S t="4000" d="2000" r="2"/>
+          </SegmentTimeline>
+        </SegmentTemplate>
+      </Representation>
+    </AdaptationSet>
+  </Period>
+</MPD>
+"###,
+            String::from_utf8(content).unwrap()
+        );
+    }
+
+    #[test]
+    fn static_mpd_starts_with_first_segment() {
+        let mut mpd = Mpd::new(true, 2, 2);
+        mpd.add_representation(video_representation());
+        let mut audio = Representation::new(
+            String::from("audio_0"),
+            ContentType::Audio,
+            String::from("audio_0_init.mp4"),
+            String::from("audio_0_segment$Number%05d$.m4s"),
+            0,
+        );
+        audio.codecs = Some(String::from("mp4a.40.2"));
+        audio.bandwidth = 128_000;
+        audio.audio_sampling_rate = Some(48_000);
+        audio.language = Some(String::from("en"));
+        mpd.add_representation(audio);
+
+        for (time, duration) in [(1_000, 2_000), (3_000, 2_000), (5_000, 1_500)] {
+            mpd.add_segment("video_0", time, duration);
+        }
+        // A gap in the timeline
+        for (time, duration) in [(1_000, 2_000), (4_000, 2_000)] {
+            mpd.add_segment("audio_0", time, duration);
+        }
+        mpd.end();
+
+        let mut content = Vec::new();
+        mpd.write_to(&mut content).unwrap();
+        assert_eq!(
+            r###"<?xml version="1.0" encoding="UTF-8"?>
+<MPD xmlns="urn:mpeg:dash:schema:mpd:2011" profiles="urn:mpeg:dash:profile:isoff-live:2011" type="static" mediaPresentationDuration="PT5.500S" maxSegmentDuration="PT2S" minBufferTime="PT2S">
+  <Period id="0" start="PT0S">
+    <AdaptationSet id="0" contentType="video" mimeType="video/mp4" segmentAlignment="true" startWithSAP="1">
+      <Representation id="video_0" bandwidth="1000000" codecs="avc1.64001f" width="1280" height="720" frameRate="30">
+        <SegmentTemplate timescale="1000" presentationTimeOffset="1000" startNumber="1" initialization="video_0_init.mp4" media="video_0_segment$Number%05d$.m4s">
+          <SegmentTimeline>
+            <S t="3000" d="2000"/>
+            <S d="1500"/>
+          </SegmentTimeline>
+        </SegmentTemplate>
+      </Representation>
+    </AdaptationSet>
+    <AdaptationSet id="1" contentType="audio" mimeType="audio/mp4" lang="en" segmentAlignment="true" startWithSAP="1">
+      <Representation id="audio_0" bandwidth="128000" codecs="mp4a.40.2" audioSamplingRate="48000">
+        <SegmentTemplate timescale="1000" presentationTimeOffset="1000" startNumber="0" initialization="audio_0_init.mp4" media="audio_0_segment$Number%05d$.m4s">
+          <SegmentTimeline>
+            <S t="1000" d="2000"/>
+            <S t="4000" d="2000"/>
+          </SegmentTimeline>
+        </SegmentTemplate>
+      </Representation>
+    </AdaptationSet>
+  </Period>
+</MPD>
+"###,
+            String::from_utf8(content).unwrap()
+        );
+    }
+
+    #[test]
+    fn durations_are_formatted() {
+        assert_eq!(format_duration(0), "PT0.000S");
+        assert_eq!(format_duration(6_500), "PT6.500S");
+        assert_eq!(format_duration(3_600_042), "PT3600.042S");
+    }
+}
@@ -0,0 +1,1258 @@
+//
+// Copyright (C) 2026 agent <agent@local>
+//
+// This Source Code Form is subject to the terms of the Mozilla Public License, v2.0.
+// If a copy of the MPL was not distributed with this file, You can obtain one at
+// <https://mozilla.org/MPL/2.0/>.
+//
+// SPDX-License-Identifier: MPL-2.0
+
+use crate::imp::{delete_fragment, new_file_stream};
+use crate::SegmentFormat;
+use gio::prelude::*;
+use glib::subclass::prelude::*;
+use gst::prelude::*;
+use gst::subclass::prelude::*;
+use gst::{gst_debug, gst_error, gst_info, gst_trace};
+use m3u8_rs::{AlternativeMedia, AlternativeMediaType, MasterPlaylist, VariantStream};
+use once_cell::sync::Lazy;
+use std::io::Write;
+use std::path;
+use std::sync::Mutex;
+
+const DEFAULT_MASTER_PLAYLIST_LOCATION: &str = "master.m3u8";
+const DEFAULT_LOCATION: &str = "%s_segment%05d.ts";
+const DEFAULT_CMAF_LOCATION: &str = "%s_segment%05d.m4s";
+const DEFAULT_PLAYLIST_LOCATION: &str = "%s.m3u8";
+const DEFAULT_INIT_LOCATION: &str = "%s_init.mp4";
+const DEFAULT_MAX_NUM_SEGMENT_FILES: u32 = 10;
+const DEFAULT_TARGET_DURATION: u32 = 15;
+const DEFAULT_PLAYLIST_LENGTH: u32 = 5;
+const DEFAULT_SEGMENT_FORMAT: SegmentFormat = SegmentFormat::MpegTs;
+
+const SIGNAL_GET_PLAYLIST_STREAM: &str = "get-playlist-stream";
+const SIGNAL_GET_FRAGMENT_STREAM: &str = "get-fragment-stream";
+const SIGNAL_DELETE_FRAGMENT: &str = "delete-fragment";
+
+const AUDIO_GROUP_ID: &str = "audio";
+const SUBTITLES_GROUP_ID: &str = "subtitles";
+
+static CAT: Lazy<gst::DebugCategory> = Lazy::new(|| {
+    gst::DebugCategory::new(
+        "hlsmultivariantsink",
+        gst::DebugColorFlags::empty(),
+        Some("HLS multivariant sink"),
+    )
+});
+
+#[derive(Debug, Clone)]
+struct Settings {
+    master_playlist_location: String,
+    location: String,
+    playlist_location: String,
+    init_location: String,
+    max_num_segment_files: u32,
+    target_duration: u32,
+    playlist_length: u32,
+    playlist_type: Option<String>,
+    segment_format: SegmentFormat,
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Self {
+            master_playlist_location: String::from(DEFAULT_MASTER_PLAYLIST_LOCATION),
+            location: String::from(DEFAULT_LOCATION),
+            playlist_location: String::from(DEFAULT_PLAYLIST_LOCATION),
+            init_location: String::from(DEFAULT_INIT_LOCATION),
+            max_num_segment_files: DEFAULT_MAX_NUM_SEGMENT_FILES,
+            target_duration: DEFAULT_TARGET_DURATION,
+            playlist_length: DEFAULT_PLAYLIST_LENGTH,
+            playlist_type: None,
+            segment_format: DEFAULT_SEGMENT_FORMAT,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum RenditionType {
+    Video,
+    Audio,
+    Subtitle,
+}
+
+/// A single rendition, i.e. one request pad with its own `hlssink3` and media playlist.
+struct Rendition {
+    rendition_type: RenditionType,
+    name: String,
+    pad: gst::GhostPad,
+    hlssink3: gst::Element,
+    playlist_location: String,
+
+    caps: Option<gst::Caps>,
+    // From the language-code and title tags
+    language: Option<String>,
+    title: Option<String>,
+    // From the bitrate tags
+    max_bitrate: Option<u32>,
+    bitrate: Option<u32>,
+
+    measurement: BitrateMeasurement,
+}
+
+impl Rendition {
+    /// Peak bitrate for the `BANDWIDTH` attribute, either from the tags or measured.
+    fn bandwidth(&self) -> u64 {
+        self.max_bitrate
+            .map(u64::from)
+            .or(self.measurement.peak)
+            .or_else(|| self.bitrate.map(u64::from))
+            .unwrap_or(0)
+    }
+
+    /// Average bitrate for the `AVERAGE-BANDWIDTH` attribute, either measured or from the tags.
+    fn average_bandwidth(&self) -> Option<u64> {
+        self.measurement
+            .average
+            .or_else(|| self.bitrate.map(u64::from))
+    }
+}
+
+/// Measures the average bitrate of a rendition and the peak bitrate of its segments.
+#[derive(Debug, Default)]
+struct BitrateMeasurement {
+    // Bytes and timestamps of all buffers so far
+    bytes: u64,
+    first_pts: Option<gst::ClockTime>,
+    last_pts: Option<gst::ClockTime>,
+    // Bytes and start of the current segment
+    segment_bytes: u64,
+    segment_start_pts: Option<gst::ClockTime>,
+    // Updated at the end of each segment
+    peak: Option<u64>,
+    average: Option<u64>,
+}
+
+impl BitrateMeasurement {
+    fn add_buffer(&mut self, buffer: &gst::BufferRef) {
+        self.bytes += buffer.size() as u64;
+        self.segment_bytes += buffer.size() as u64;
+
+        if let Some(pts) = buffer.pts() {
+            if self.first_pts.map_or(true, |first| pts < first) {
+                self.first_pts = Some(pts);
+            }
+            let end = pts + buffer.duration().unwrap_or(gst::ClockTime::ZERO);
+            if self.last_pts.map_or(true, |last| end > last) {
+                self.last_pts = Some(end);
+            }
+        }
+    }
+
+    /// Updates the average and peak bitrate with the segment that just ended.
+    fn end_segment(&mut self) {
+        let (first, last) = match (self.first_pts, self.last_pts) {
+            (Some(first), Some(last)) => (first, last),
+            _ => return,
+        };
+
+        let bitrate = |bytes: u64, duration: gst::ClockTime| {
+            if duration == gst::ClockTime::ZERO {
+                None
+            } else {
+                (bytes * 8).mul_div_ceil(gst::ClockTime::SECOND.nseconds(), duration.nseconds())
+            }
+        };
+
+        if let Some(average) = bitrate(self.bytes, last - first) {
+            self.average = Some(average);
+        }
+
+        let segment_start = self.segment_start_pts.unwrap_or(first);
+        if let Some(segment_bitrate) = last
+            .checked_sub(segment_start)
+            .and_then(|duration| bitrate(self.segment_bytes, duration))
+        {
+            self.peak = Some(
+                self.peak
+                    .map_or(segment_bitrate, |peak| peak.max(segment_bitrate)),
+            );
+        }
+
+        self.segment_bytes = 0;
+        self.segment_start_pts = Some(last);
+    }
+}
+
+#[derive(Default)]
+struct State {
+    renditions: Vec<Rendition>,
+    // Content of the last written master playlist
+    master_playlist: Option<Vec<u8>>,
+    // Sequence number of the last keyframe request forwarded from the reference rendition
+    keyframe_request_seqnum: Option<gst::Seqnum>,
+}
+
+#[derive(Default)]
+pub struct HlsMultivariantSink {
+    settings: Mutex<Settings>,
+    state: Mutex<State>,
+}
+
+impl HlsMultivariantSink {
+    /// Creates the `hlssink3` for a new rendition and forwards its signals.
+    fn create_rendition_sink(
+        &self,
+        element: &super::HlsMultivariantSink,
+        settings: &Settings,
+        name: &str,
+    ) -> Option<(gst::Element, String)> {
+        let hlssink3 = match gst::ElementFactory::make("hlssink3", Some(name)) {
+            Ok(hlssink3) => hlssink3,
+            Err(_) => {
+                gst_error!(CAT, obj: element, "Could not make element hlssink3");
+                return None;
+            }
+        };
+
+        // Switch the segment format first as it changes the default location
+        hlssink3.set_property("segment-format", settings.segment_format);
+
+        let location = if settings.segment_format == SegmentFormat::Cmaf
+            && settings.location == DEFAULT_LOCATION
+        {
+            DEFAULT_CMAF_LOCATION
+        } else {
+            settings.location.as_str()
+        };
+        let playlist_location = settings.playlist_location.replacen("%s", name, 1);
+
+        hlssink3.set_properties(&[
+            ("location", &location.replacen("%s", name, 1)),
+            ("playlist-location", &playlist_location),
+            (
+                "init-location",
+                &settings.init_location.replacen("%s", name, 1),
+            ),
+            ("target-duration", &settings.target_duration),
+            ("playlist-length", &settings.playlist_length),
+            ("max-files", &settings.max_num_segment_files),
+            ("playlist-type", &settings.playlist_type),
+        ]);
+
+        hlssink3.connect(SIGNAL_GET_PLAYLIST_STREAM, false, {
+            let element_weak = element.downgrade();
+            let name = name.to_string();
+            move |args| {
+                let element = element_weak.upgrade()?;
+                let location = args[1].get::<String>().expect("signal arg");
+                let sink = HlsMultivariantSink::from_instance(&element);
+
+                // The media playlist is written after each segment, so all renditions have
+                // produced data once all of them wrote their playlist
+                sink.rendition_playlist_updated(&element, &name);
+
+                Some(
+                    element
+                        .emit_by_name::<Option<gio::OutputStream>>(
+                            SIGNAL_GET_PLAYLIST_STREAM,
+                            &[&location],
+                        )
+                        .to_value(),
+                )
+            }
+        });
+
+        hlssink3.connect(SIGNAL_GET_FRAGMENT_STREAM, false, {
+            let element_weak = element.downgrade();
+            move |args| {
+                let element = element_weak.upgrade()?;
+                let location = args[1].get::<String>().expect("signal arg");
+
+                Some(
+                    element
+                        .emit_by_name::<Option<gio::OutputStream>>(
+                            SIGNAL_GET_FRAGMENT_STREAM,
+                            &[&location],
+                        )
+                        .to_value(),
+                )
+            }
+        });
+
+        hlssink3.connect(SIGNAL_DELETE_FRAGMENT, false, {
+            let element_weak = element.downgrade();
+            move |args| {
+                let element = element_weak.upgrade()?;
+                let location = args[1].get::<String>().expect("signal arg");
+
+                Some(
+                    element
+                        .emit_by_name::<bool>(SIGNAL_DELETE_FRAGMENT, &[&location])
+                        .to_value(),
+                )
+            }
+        });
+
+        Some((hlssink3, playlist_location))
+    }
+
+    /// Keeps track of the caps, tags and bitrate of the data going into a rendition.
+    fn handle_rendition_data(
+        &self,
+        element: &super::HlsMultivariantSink,
+        pad: &gst::Pad,
+        data: &gst::PadProbeData,
+    ) {
+        let changed = {
+            let mut state = self.state.lock().unwrap();
+            let rendition = match state
+                .renditions
+                .iter_mut()
+                .find(|r| r.pad.upcast_ref::<gst::Pad>() == pad)
+            {
+                Some(rendition) => rendition,
+                None => return,
+            };
+
+            match data {
+                gst::PadProbeData::Buffer(buffer) => {
+                    rendition.measurement.add_buffer(buffer);
+
+                    false
+                }
+                gst::PadProbeData::Event(event) => match event.view() {
+                    gst::EventView::Caps(ev) => {
+                        gst_debug!(CAT, obj: pad, "Received caps {:?}", ev.caps());
+                        rendition.caps = Some(ev.caps_owned());
+
+                        true
+                    }
+                    gst::EventView::Tag(ev) => {
+                        let tags = ev.tag();
+                        if let Some(language) = tags.get::<gst::tags::LanguageCode>() {
+                            rendition.language = Some(language.get().to_string());
+                        }
+                        if let Some(title) = tags.get::<gst::tags::Title>() {
+                            rendition.title = Some(title.get().to_string());
+                        }
+                        if let Some(max_bitrate) = tags.get::<gst::tags::MaximumBitrate>() {
+                            rendition.max_bitrate = Some(max_bitrate.get());
+                        }
+                        if let Some(bitrate) = tags
+                            .get::<gst::tags::Bitrate>()
+                            .or_else(|| tags.get::<gst::tags::NominalBitrate>())
+                        {
+                            rendition.bitrate = Some(bitrate.get());
+                        }
+
+                        true
+                    }
+                    _ => false,
+                },
+                _ => false,
+            }
+        };
+
+        // Update the master playlist if it was already written before
+        if changed && self.state.lock().unwrap().master_playlist.is_some() {
+            self.write_master_playlist(element);
+        }
+    }
+
+    /// Aligns the segments of all video renditions at the same keyframes.
+    ///
+    /// The first video rendition is the reference: its keyframe requests are forwarded to all
+    /// other video renditions, and the requests these make themselves are dropped. All video
+    /// encoders therefore produce keyframes at the same running times, where the segments of all
+    /// renditions start as they use the same target duration.
+    fn handle_keyframe_request(
+        &self,
+        element: &super::HlsMultivariantSink,
+        pad: &gst::Pad,
+        event: &gst::Event,
+    ) -> gst::PadProbeReturn {
+        if event.type_() != gst::EventType::CustomUpstream
+            || event
+                .structure()
+                .map_or(true, |s| s.name() != "GstForceKeyUnit")
+        {
+            return gst::PadProbeReturn::Ok;
+        }
+
+        let other_pads = {
+            let mut state = self.state.lock().unwrap();
+
+            let mut video_pads = state
+                .renditions
+                .iter()
+                .filter(|r| r.rendition_type == RenditionType::Video)
+                .map(|r| r.pad.clone().upcast::<gst::Pad>());
+            let reference_pad = match video_pads.next() {
+                Some(reference_pad) => reference_pad,
+                None => return gst::PadProbeReturn::Ok,
+            };
+
+            if &reference_pad != pad {
+                let other_pads = video_pads.collect::<Vec<_>>();
+                if !other_pads.contains(pad)
+                    || state.keyframe_request_seqnum == Some(event.seqnum())
+                {
+                    return gst::PadProbeReturn::Ok;
+                }
+
+                gst_trace!(CAT, obj: pad, "Dropping keyframe request of non-reference rendition");
+                return gst::PadProbeReturn::Drop;
+            }
+
+            let other_pads = video_pads.collect::<Vec<_>>();
+            state.keyframe_request_seqnum = Some(event.seqnum());
+            other_pads
+        };
+
+        for other_pad in other_pads {
+            gst_debug!(
+                CAT,
+                obj: element,
+                "Forwarding keyframe request from {} to {}",
+                pad.name(),
+                other_pad.name()
+            );
+            other_pad.push_event(event.clone());
+        }
+
+        gst::PadProbeReturn::Ok
+    }
+
+    fn rendition_playlist_updated(&self, element: &super::HlsMultivariantSink, name: &str) {
+        {
+            let mut state = self.state.lock().unwrap();
+            if let Some(rendition) = state.renditions.iter_mut().find(|r| r.name == name) {
+                rendition.measurement.end_segment();
+            }
+        }
+
+        self.write_master_playlist(element);
+    }
+
+    /// Writes the master playlist once all renditions have caps and produced data, and whenever
+    /// its content changes afterwards.
+    fn write_master_playlist(&self, element: &super::HlsMultivariantSink) {
+        let (master_playlist_location, content) = {
+            let settings = self.settings.lock().unwrap();
+            let mut state = self.state.lock().unwrap();
+
+            if state.renditions.is_empty()
+                || !state
+                    .renditions
+                    .iter()
+                    .all(|r| r.caps.is_some() && r.measurement.peak.is_some())
+            {
+                gst_trace!(CAT, obj: element, "Not all renditions are ready yet");
+                return;
+            }
+
+            let playlist = master_playlist(&settings, &state.renditions);
+            let mut content = Vec::new();
+            if let Err(err) = playlist.write_to(&mut content) {
+                gst_error!(CAT, obj: element, "Could not create master playlist: {}", err);
+                return;
+            }
+
+            if state.master_playlist.as_ref() == Some(&content) {
+                return;
+            }
+            state.master_playlist = Some(content.clone());
+
+            (settings.master_playlist_location.clone(), content)
+        };
+
+        gst_info!(
+            CAT,
+            obj: element,
+            "Writing master playlist {}",
+            master_playlist_location
+        );
+
+        let mut stream = match element.emit_by_name::<Option<gio::OutputStream>>(
+            SIGNAL_GET_PLAYLIST_STREAM,
+            &[&master_playlist_location],
+        ) {
+            Some(stream) => stream.into_write(),
+            None => {
+                gst_error!(
+                    CAT,
+                    obj: element,
+                    "Could not get stream to write master playlist"
+                );
+                return;
+            }
+        };
+
+        if let Err(err) = stream.write_all(&content).and_then(|_| stream.flush()) {
+            gst_error!(
+                CAT,
+                obj: element,
+                "Could not write master playlist: {}",
+                err
+            );
+        }
+    }
+}
+
+/// Creates the master playlist with one variant stream per video rendition and the audio and
+/// subtitle renditions as alternative media.
+///
+/// If there are no video renditions, each audio rendition is a variant stream.
+fn master_playlist(settings: &Settings, renditions: &[Rendition]) -> MasterPlaylist {
+    let of_type = |rendition_type| {
+        renditions
+            .iter()
+            .filter(move |r| r.rendition_type == rendition_type)
+    };
+
+    let has_video = of_type(RenditionType::Video).next().is_some();
+    let audio_group = if has_video {
+        of_type(RenditionType::Audio).next()
+    } else {
+        None
+    };
+    let has_subtitles = of_type(RenditionType::Subtitle).next().is_some();
+
+    let mut alternatives = Vec::new();
+    if audio_group.is_some() {
+        for (idx, rendition) in of_type(RenditionType::Audio).enumerate() {
+            alternatives.push(alternative_media(
+                settings,
+                rendition,
+                AlternativeMediaType::Audio,
+                AUDIO_GROUP_ID,
+                idx == 0,
+            ));
+        }
+    }
+    for (idx, rendition) in of_type(RenditionType::Subtitle).enumerate() {
+        alternatives.push(alternative_media(
+            settings,
+            rendition,
+            AlternativeMediaType::Subtitles,
+            SUBTITLES_GROUP_ID,
+            idx == 0,
+        ));
+    }
+
+    let variant_type = if has_video {
+        RenditionType::Video
+    } else {
+        RenditionType::Audio
+    };
+
+    let variants = of_type(variant_type)
+        .enumerate()
+        .map(|(idx, rendition)| {
+            let caps = rendition.caps.as_ref().expect("no caps");
+            let s = caps.structure(0).unwrap();
+
+            let mut codecs = codec_string(caps).into_iter().collect::<Vec<_>>();
+            let mut bandwidth = rendition.bandwidth();
+            let mut average_bandwidth = rendition.average_bandwidth();
+            if let Some(audio) = audio_group {
+                codecs.extend(audio.caps.as_ref().and_then(|caps| codec_string(caps)));
+                bandwidth += audio.bandwidth();
+                average_bandwidth = average_bandwidth
+                    .zip(audio.average_bandwidth())
+                    .map(|(video, audio)| video + audio);
+            }
+
+            let resolution = match (s.get::<i32>("width"), s.get::<i32>("height")) {
+                (Ok(width), Ok(height)) => Some(format!("{}x{}", width, height)),
+                _ => None,
+            };
+            let frame_rate = s
+                .get::<gst::Fraction>("framerate")
+                .ok()
+                .filter(|fps| fps.numer() > 0 && fps.denom() > 0)
+                .map(|fps| format!("{:.3}", fps.numer() as f64 / fps.denom() as f64));
+
+            VariantStream {
+                uri: playlist_uri(settings, rendition),
+                bandwidth: bandwidth.to_string(),
+                average_bandwidth: average_bandwidth.map(|bandwidth| bandwidth.to_string()),
+                codecs: if codecs.is_empty() {
+                    None
+                } else {
+                    Some(codecs.join(","))
+                },
+                resolution,
+                frame_rate,
+                audio: audio_group.map(|_| String::from(AUDIO_GROUP_ID)),
+                subtitles: if has_subtitles {
+                    Some(String::from(SUBTITLES_GROUP_ID))
+                } else {
+                    None
+                },
+                // The alternative media is written before the first variant stream
+                alternatives: if idx == 0 {
+                    alternatives.clone()
+                } else {
+                    Vec::new()
+                },
+                ..Default::default()
+            }
+        })
+        .collect();
+
+    MasterPlaylist {
+        version: match settings.segment_format {
+            SegmentFormat::MpegTs => 3,
+            SegmentFormat::Cmaf => 6,
+        },
+        variants,
+        // All segments start with a keyframe
+        independent_segments: true,
+        ..Default::default()
+    }
+}
+
+fn alternative_media(
+    settings: &Settings,
+    rendition: &Rendition,
+    media_type: AlternativeMediaType,
+    group_id: &str,
+    default: bool,
+) -> AlternativeMedia {
+    AlternativeMedia {
+        media_type,
+        uri: Some(playlist_uri(settings, rendition)),
+        group_id: String::from(group_id),
+        language: rendition.language.clone(),
+        name: rendition
+            .title
+            .clone()
+            .unwrap_or_else(|| rendition.name.clone()),
+        default,
+        autoselect: true,
+        ..Default::default()
+    }
+}
+
+/// URI of the media playlist of a rendition relative to the master playlist.
+fn playlist_uri(settings: &Settings, rendition: &Rendition) -> String {
+    let location = path::Path::new(&rendition.playlist_location);
+    path::Path::new(&settings.master_playlist_location)
+        .parent()
+        .and_then(|dir| location.strip_prefix(dir).ok())
+        .unwrap_or(location)
+        .to_string_lossy()
+        .into_owned()
+}
+
+/// Returns the RFC 6381 codec string for the caps, if known.
+pub(crate) fn codec_string(caps: &gst::CapsRef) -> Option<String> {
+    let s = caps.structure(0).unwrap();
+    let codec_data = s
+        .get::<&gst::BufferRef>("codec_data")
+        .ok()
+        .and_then(|codec_data| codec_data.map_readable().ok());
+
+    match s.name() {
+        "video/x-h264" => {
+            let fourcc = if s.get::<&str>("stream-format") == Ok("avc3") {
+                "avc3"
+            } else {
+                "avc1"
+            };
+
+            let (profile, constraints, level) = match codec_data {
+                Some(ref map) if map.len() >= 4 => (map[1], map[2], map[3]),
+                _ => {
+                    let profile = match s.get::<&str>("profile").ok()? {
+                        "constrained-baseline" | "baseline" => 66,
+                        "main" => 77,
+                        "high" => 100,
+                        "high-10" => 110,
+                        "high-4:2:2" => 122,
+                        "high-4:4:4" => 244,
+                        _ => return None,
+                    };
+                    let level = s.get::<&str>("level").ok()?.parse::<f32>().ok()?;
+
+                    (profile, 0, (level * 10.0).round() as u8)
+                }
+            };
+
+            Some(format!(
+                "{}.{:02x}{:02x}{:02x}",
+                fourcc, profile, constraints, level
+            ))
+        }
+        "video/x-h265" => {
+            let fourcc = if s.get::<&str>("stream-format") == Ok("hev1") {
+                "hev1"
+            } else {
+                "hvc1"
+            };
+
+            match codec_data {
+                Some(ref map) if map.len() >= 13 => {
+                    let profile_space = ["", "A", "B", "C"][(map[1] >> 6) as usize];
+                    let tier = if map[1] & 0x20 != 0 { "H" } else { "L" };
+                    let profile = map[1] & 0x1f;
+                    let compatibility =
+                        u32::from_be_bytes([map[2], map[3], map[4], map[5]]).reverse_bits();
+                    let level = map[12];
+
+                    // Trailing zero constraint bytes are omitted
+                    let constraints = &map[6..12];
+                    let constraints_len = constraints
+                        .iter()
+                        .rposition(|b| *b != 0)
+                        .map_or(0, |pos| pos + 1);
+
+                    let mut codec = format!(
+                        "{}.{}{}.{:X}.{}{}",
+                        fourcc, profile_space, profile, compatibility, tier, level
+                    );
+                    for b in &constraints[..constraints_len] {
+                        codec.push_str(&format!(".{:X}", b));
+                    }
+
+                    Some(codec)
+                }
+                _ => {
+                    let (profile, compatibility) = match s.get::<&str>("profile").ok()? {
+                        "main" => (1, 6),
+                        "main-10" => (2, 4),
+                        _ => return None,
+                    };
+                    let tier = if s.get::<&str>("tier") == Ok("high") {
+                        "H"
+                    } else {
+                        "L"
+                    };
+                    let level = s.get::<&str>("level").ok()?.parse::<f32>().ok()?;
+
+                    Some(format!(
+                        "{}.{}.{}.{}{}.B0",
+                        fourcc,
+                        profile,
+                        compatibility,
+                        tier,
+                        (level * 30.0).round() as u32
+                    ))
+                }
+            }
+        }
+        "audio/mpeg" => match s.get::<i32>("mpegversion") {
+            Ok(1) => Some(String::from("mp4a.40.34")),
+            _ => {
+                // Audio object type from the AudioSpecificConfig
+                let object_type = match codec_data {
+                    Some(ref map) if !map.is_empty() => map[0] >> 3,
+                    _ => 2,
+                };
+
+                Some(format!("mp4a.40.{}", object_type))
+            }
+        },
+        "audio/x-opus" => Some(String::from("opus")),
+        "audio/x-flac" => Some(String::from("fLaC")),
+        "application/x-subtitle-vtt" | "application/x-subtitle-vtt-fragmented" => {
+            Some(String::from("wvtt"))
+        }
+        "application/ttml+xml" => Some(String::from("stpp.ttml.im1t")),
+        _ => None,
+    }
+}
+
+#[glib::object_subclass]
+impl ObjectSubclass for HlsMultivariantSink {
+    const NAME: &'static str = "GstHlsMultivariantSink";
+    type Type = super::HlsMultivariantSink;
+    type ParentType = gst::Bin;
+}
+
+impl BinImpl for HlsMultivariantSink {}
+
+impl ObjectImpl for HlsMultivariantSink {
+    fn properties() -> &'static [glib::ParamSpec] {
+        static PROPERTIES: Lazy<Vec<glib::ParamSpec>> = Lazy::new(|| {
+            vec![
+                glib::ParamSpecString::new(
+                    "master-playlist-location",
+                    "Master Playlist Location",
+                    "Location of the master playlist to write.",
+                    Some(DEFAULT_MASTER_PLAYLIST_LOCATION),
+                    glib::ParamFlags::READWRITE,
+                ),
+                glib::ParamSpecString::new(
+                    "location",
+                    "File Location",
+                    "Location of the segment files to write. The first `%s` is replaced by the rendition (pad) name.",
+                    Some(DEFAULT_LOCATION),
+                    glib::ParamFlags::READWRITE,
+                ),
+                glib::ParamSpecString::new(
+                    "playlist-location",
+                    "Playlist Location",
+                    "Location of the media playlists to write. The first `%s` is replaced by the rendition (pad) name.",
+                    Some(DEFAULT_PLAYLIST_LOCATION),
+                    glib::ParamFlags::READWRITE,
+                ),
+                glib::ParamSpecString::new(
+                    "init-location",
+                    "Init Location",
+                    "Location of the initialization segments to write in CMAF mode. The first `%s` is replaced by the rendition (pad) name.",
+                    Some(DEFAULT_INIT_LOCATION),
+                    glib::ParamFlags::READWRITE,
+                ),
+                glib::ParamSpecUInt::new(
+                    "max-files",
+                    "Max files",
+                    "Maximum number of files to keep on disk per rendition. Once the maximum is reached, old files start to be deleted to make room for new ones.",
+                    0,
+                    u32::MAX,
+                    DEFAULT_MAX_NUM_SEGMENT_FILES,
+                    glib::ParamFlags::READWRITE,
+                ),
+                glib::ParamSpecUInt::new(
+                    "target-duration",
+                    "Target duration",
+                    "The target duration in seconds of a segment/file, the same for all renditions. Segments are aligned at the keyframes requested by the first video rendition.",
+                    0,
+                    u32::MAX,
+                    DEFAULT_TARGET_DURATION,
+                    glib::ParamFlags::READWRITE,
+                ),
+                glib::ParamSpecUInt::new(
+                    "playlist-length",
+                    "Playlist length",
+                    "Length of the media playlists. If set to 0, the playlists will be infinite.",
+                    0,
+                    u32::MAX,
+                    DEFAULT_PLAYLIST_LENGTH,
+                    glib::ParamFlags::READWRITE,
+                ),
+                glib::ParamSpecString::new(
+                    "playlist-type",
+                    "Playlist Type",
+                    "The type of the media playlists to use, see hlssink3.",
+                    None,
+                    glib::ParamFlags::READWRITE,
+                ),
+                glib::ParamSpecEnum::new(
+                    "segment-format",
+                    "Segment Format",
+                    "Format of the media segments. Subtitle renditions require CMAF segments.",
+                    SegmentFormat::static_type(),
+                    DEFAULT_SEGMENT_FORMAT as i32,
+                    glib::ParamFlags::READWRITE,
+                ),
+            ]
+        });
+
+        PROPERTIES.as_ref()
+    }
+
+    fn set_property(
+        &self,
+        _obj: &Self::Type,
+        _id: usize,
+        value: &glib::Value,
+        pspec: &glib::ParamSpec,
+    ) {
+        let mut settings = self.settings.lock().unwrap();
+        match pspec.name() {
+            "master-playlist-location" => {
+                settings.master_playlist_location = value
+                    .get::<Option<String>>()
+                    .expect("type checked upstream")
+                    .unwrap_or_else(|| String::from(DEFAULT_MASTER_PLAYLIST_LOCATION));
+            }
+            "location" => {
+                settings.location = value
+                    .get::<Option<String>>()
+                    .expect("type checked upstream")
+                    .unwrap_or_else(|| String::from(DEFAULT_LOCATION));
+            }
+            "playlist-location" => {
+                settings.playlist_location = value
+                    .get::<Option<String>>()
+                    .expect("type checked upstream")
+                    .unwrap_or_else(|| String::from(DEFAULT_PLAYLIST_LOCATION));
+            }
+            "init-location" => {
+                settings.init_location = value
+                    .get::<Option<String>>()
+                    .expect("type checked upstream")
+                    .unwrap_or_else(|| String::from(DEFAULT_INIT_LOCATION));
+            }
+            "max-files" => {
+                settings.max_num_segment_files = value.get().expect("type checked upstream");
+            }
+            "target-duration" => {
+                settings.target_duration = value.get().expect("type checked upstream");
+            }
+            "playlist-length" => {
+                settings.playlist_length = value.get().expect("type checked upstream");
+            }
+            "playlist-type" => {
+                settings.playlist_type = value.get().expect("type checked upstream");
+            }
+            "segment-format" => {
+                settings.segment_format = value.get().expect("type checked upstream");
+            }
+            _ => unimplemented!(),
+        };
+    }
+
+    fn property(&self, _obj: &Self::Type, _id: usize, pspec: &glib::ParamSpec) -> glib::Value {
+        let settings = self.settings.lock().unwrap();
+        match pspec.name() {
+            "master-playlist-location" => settings.master_playlist_location.to_value(),
+            "location" => settings.location.to_value(),
+            "playlist-location" => settings.playlist_location.to_value(),
+            "init-location" => settings.init_location.to_value(),
+            "max-files" => settings.max_num_segment_files.to_value(),
+            "target-duration" => settings.target_duration.to_value(),
+            "playlist-length" => settings.playlist_length.to_value(),
+            "playlist-type" => settings.playlist_type.to_value(),
+            "segment-format" => settings.segment_format.to_value(),
+            _ => unimplemented!(),
+        }
+    }
+
+    fn signals() -> &'static [glib::subclass::Signal] {
+        static SIGNALS: Lazy<Vec<glib::subclass::Signal>> = Lazy::new(|| {
+            vec![
+                glib::subclass::Signal::builder(
+                    SIGNAL_GET_PLAYLIST_STREAM,
+                    &[String::static_type().into()],
+                    gio::OutputStream::static_type().into(),
+                )
+                .class_handler(|_, args| {
+                    let element = args[0].get::<gst::Element>().expect("signal arg");
+                    let playlist_location = args[1].get::<String>().expect("signal arg");
+
+                    Some(
+                        new_file_stream(&element, &playlist_location)
+                            .ok()?
+                            .to_value(),
+                    )
+                })
+                .accumulator(|_hint, ret, value| {
+                    // First signal handler wins
+                    *ret = value.clone();
+                    false
+                })
+                .build(),
+                glib::subclass::Signal::builder(
+                    SIGNAL_GET_FRAGMENT_STREAM,
+                    &[String::static_type().into()],
+                    gio::OutputStream::static_type().into(),
+                )
+                .class_handler(|_, args| {
+                    let element = args[0].get::<gst::Element>().expect("signal arg");
+                    let fragment_location = args[1].get::<String>().expect("signal arg");
+
+                    Some(
+                        new_file_stream(&element, &fragment_location)
+                            .ok()?
+                            .to_value(),
+                    )
+                })
+                .accumulator(|_hint, ret, value| {
+                    // First signal handler wins
+                    *ret = value.clone();
+                    false
+                })
+                .build(),
+                glib::subclass::Signal::builder(
+                    SIGNAL_DELETE_FRAGMENT,
+                    &[String::static_type().into()],
+                    glib::types::Type::BOOL.into(),
+                )
+                .class_handler(|_, args| {
+                    let element = args[0].get::<gst::Element>().expect("signal arg");
+                    let fragment_location = args[1].get::<String>().expect("signal arg");
+
+                    delete_fragment(&element, &fragment_location);
+                    Some(true.to_value())
+                })
+                .accumulator(|_hint, ret, value| {
+                    // First signal handler wins
+                    *ret = value.clone();
+                    false
+                })
+                .build(),
+            ]
+        });
+
+        SIGNALS.as_ref()
+    }
+
+    fn constructed(&self, obj: &Self::Type) {
+        self.parent_constructed(obj);
+
+        obj.set_element_flags(gst::ElementFlags::SINK);
+        obj.set_suppressed_flags(gst::ElementFlags::SINK | gst::ElementFlags::SOURCE);
+    }
+}
+
+impl GstObjectImpl for HlsMultivariantSink {}
+
+impl ElementImpl for HlsMultivariantSink {
+    fn metadata() -> Option<&'static gst::subclass::ElementMetadata> {
+        static ELEMENT_METADATA: Lazy<gst::subclass::ElementMetadata> = Lazy::new(|| {
+            gst::subclass::ElementMetadata::new(
+                "HTTP Live Streaming multivariant sink",
+                "Sink/Muxer",
+                "HTTP Live Streaming sink with multiple renditions and a master playlist",
+                "Rafael Caricio <rafael@caricio.com>",
+            )
+        });
+
+        Some(&*ELEMENT_METADATA)
+    }
+
+    fn pad_templates() -> &'static [gst::PadTemplate] {
+        static PAD_TEMPLATES: Lazy<Vec<gst::PadTemplate>> = Lazy::new(|| {
+            ["video_%u", "audio_%u", "subtitle_%u"]
+                .iter()
+                .map(|name| {
+                    gst::PadTemplate::new(
+                        name,
+                        gst::PadDirection::Sink,
+                        gst::PadPresence::Request,
+                        &gst::Caps::new_any(),
+                    )
+                    .unwrap()
+                })
+                .collect()
+        });
+
+        PAD_TEMPLATES.as_ref()
+    }
+
+    fn change_state(
+        &self,
+        element: &Self::Type,
+        transition: gst::StateChange,
+    ) -> Result<gst::StateChangeSuccess, gst::StateChangeError> {
+        if let gst::StateChange::ReadyToPaused = transition {
+            let mut state = self.state.lock().unwrap();
+            state.master_playlist = None;
+            state.keyframe_request_seqnum = None;
+            for rendition in &mut state.renditions {
+                rendition.measurement = BitrateMeasurement::default();
+            }
+        }
+
+        self.parent_change_state(element, transition)
+    }
+
+    fn request_new_pad(
+        &self,
+        element: &Self::Type,
+        templ: &gst::PadTemplate,
+        name: Option<String>,
+        _caps: Option<&gst::Caps>,
+    ) -> Option<gst::Pad> {
+        let settings = self.settings.lock().unwrap().clone();
+
+        let (rendition_type, prefix, child_pad_name) =
+            match templ.name_template().as_ref().map(|val| val.as_str()) {
+                Some("video_%u") => (RenditionType::Video, "video", "video"),
+                Some("audio_%u") => (RenditionType::Audio, "audio", "audio"),
+                Some("subtitle_%u") => (RenditionType::Subtitle, "subtitle", "subtitle"),
+                _ => {
+                    gst_debug!(CAT, obj: element, "requested_new_pad: unknown template");
+                    return None;
+                }
+            };
+
+        if rendition_type == RenditionType::Subtitle
+            && settings.segment_format != SegmentFormat::Cmaf
+        {
+            gst_error!(
+                CAT,
+                obj: element,
+                "Subtitle renditions are only supported with CMAF segments"
+            );
+            return None;
+        }
+
+        let mut state = self.state.lock().unwrap();
+
+        let name = match name {
+            Some(name) => name,
+            None => (0..)
+                .map(|idx| format!("{}_{}", prefix, idx))
+                .find(|name| !state.renditions.iter().any(|r| &r.name == name))
+                .unwrap(),
+        };
+        if state.renditions.iter().any(|r| r.name == name) {
+            gst_debug!(CAT, obj: element, "requested_new_pad: {} already exists", name);
+            return None;
+        }
+
+        let (hlssink3, playlist_location) =
+            self.create_rendition_sink(element, &settings, &name)?;
+        element.add(&hlssink3).unwrap();
+        hlssink3.sync_state_with_parent().unwrap();
+
+        let peer_pad = hlssink3.request_pad_simple(child_pad_name).unwrap();
+        let pad = gst::GhostPad::from_template_with_target(templ, Some(&name), &peer_pad).unwrap();
+
+        pad.add_probe(gst::PadProbeType::EVENT_UPSTREAM, {
+            let element_weak = element.downgrade();
+            move |pad, info| match (element_weak.upgrade(), &info.data) {
+                (Some(element), Some(gst::PadProbeData::Event(event))) => {
+                    let sink = HlsMultivariantSink::from_instance(&element);
+                    sink.handle_keyframe_request(&element, pad.upcast_ref(), event)
+                }
+                _ => gst::PadProbeReturn::Ok,
+            }
+        });
+
+        pad.add_probe(
+            gst::PadProbeType::BUFFER | gst::PadProbeType::EVENT_DOWNSTREAM,
+            {
+                let element_weak = element.downgrade();
+                move |pad, info| {
+                    if let (Some(element), Some(data)) = (element_weak.upgrade(), &info.data) {
+                        let sink = HlsMultivariantSink::from_instance(&element);
+                        sink.handle_rendition_data(&element, pad.upcast_ref(), data);
+                    }
+
+                    gst::PadProbeReturn::Ok
+                }
+            },
+        );
+
+        state.renditions.push(Rendition {
+            rendition_type,
+            name,
+            pad: pad.clone(),
+            hlssink3,
+            playlist_location,
+            caps: None,
+            language: None,
+            title: None,
+            max_bitrate: None,
+            bitrate: None,
+            measurement: BitrateMeasurement::default(),
+        });
+        drop(state);
+
+        element.add_pad(&pad).unwrap();
+        pad.set_active(true).unwrap();
+
+        Some(pad.upcast())
+    }
+
+    fn release_pad(&self, element: &Self::Type, pad: &gst::Pad) {
+        let rendition = {
+            let mut state = self.state.lock().unwrap();
+            match state
+                .renditions
+                .iter()
+                .position(|r| r.pad.upcast_ref::<gst::Pad>() == pad)
+            {
+                Some(idx) => state.renditions.remove(idx),
+                None => return,
+            }
+        };
+
+        if let Some(peer) = rendition.pad.target() {
+            rendition.hlssink3.release_request_pad(&peer);
+        }
+
+        pad.set_active(false).unwrap();
+        element.remove_pad(pad).unwrap();
+
+        let _ = rendition.hlssink3.set_state(gst::State::Null);
+        element.remove(&rendition.hlssink3).unwrap();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn buffer(size: usize, pts: u64) -> gst::Buffer {
+        let mut buffer = gst::Buffer::with_size(size).unwrap();
+        {
+            let buffer = buffer.get_mut().unwrap();
+            buffer.set_pts(gst::ClockTime::from_seconds(pts));
+            buffer.set_duration(gst::ClockTime::SECOND);
+        }
+        buffer
+    }
+
+    #[test]
+    fn peak_and_average_bitrate() {
+        gst::init().unwrap();
+
+        let mut measurement = BitrateMeasurement::default();
+
+        // First segment of 2s with 1000 bytes per second
+        measurement.add_buffer(&buffer(1000, 0));
+        measurement.add_buffer(&buffer(1000, 1));
+        measurement.end_segment();
+        assert_eq!(measurement.peak, Some(8_000));
+        assert_eq!(measurement.average, Some(8_000));
+
+        // Second segment of 2s with 3000 bytes per second
+        measurement.add_buffer(&buffer(3000, 2));
+        measurement.add_buffer(&buffer(3000, 3));
+        measurement.end_segment();
+        assert_eq!(measurement.peak, Some(24_000));
+        assert_eq!(measurement.average, Some(16_000));
+
+        // Third segment of 2s with 500 bytes per second doesn't lower the peak
+        measurement.add_buffer(&buffer(500, 4));
+        measurement.add_buffer(&buffer(500, 5));
+        measurement.end_segment();
+        assert_eq!(measurement.peak, Some(24_000));
+        assert_eq!(measurement.average, Some(12_000));
+    }
+
+    #[test]
+    fn codec_strings() {
+        gst::init().unwrap();
+
+        let caps = gst::Caps::builder("video/x-h264")
+            .field("stream-format", "avc")
+            .field(
+                "codec_data",
+                gst::Buffer::from_slice([0x01, 0x64, 0x00, 0x1f, 0xff]),
+            )
+            .build();
+        assert_eq!(codec_string(&caps).as_deref(), Some("avc1.64001f"));
+
+        let caps = gst::Caps::builder("video/x-h264")
+            .field("stream-format", "byte-stream")
+            .field("profile", "main")
+            .field("level", "3.1")
+            .build();
+        assert_eq!(codec_string(&caps).as_deref(), Some("avc1.4d001f"));
+
+        let caps = gst::Caps::builder("video/x-h265")
+            .field("stream-format", "hvc1")
+            .field(
+                "codec_data",
+                gst::Buffer::from_slice([
+                    0x01, 0x01, 0x60, 0x00, 0x00, 0x00, 0x90, 0x00, 0x00, 0x00, 0x00, 0x00, 0x5d,
+                ]),
+            )
+            .build();
+        assert_eq!(codec_string(&caps).as_deref(), Some("hvc1.1.6.L93.90"));
+
+        let caps = gst::Caps::builder("audio/mpeg")
+            .field("mpegversion", 4i32)
+            .field("codec_data", gst::Buffer::from_slice([0x12, 0x10]))
+            .build();
+        assert_eq!(codec_string(&caps).as_deref(), Some("mp4a.40.2"));
+    }
+}
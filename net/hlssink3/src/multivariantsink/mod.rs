@@ -0,0 +1,30 @@
+//
+// Copyright (C) 2026 agent <agent@local>
+//
+// This Source Code Form is subject to the terms of the Mozilla Public License, v2.0.
+// If a copy of the MPL was not distributed with this file, You can obtain one at
+// <https://mozilla.org/MPL/2.0/>.
+//
+// SPDX-License-Identifier: MPL-2.0
+
+use glib::prelude::*;
+
+mod imp;
+
+pub(crate) use imp::codec_string;
+
+glib::wrapper! {
+    pub struct HlsMultivariantSink(ObjectSubclass<imp::HlsMultivariantSink>) @extends gst::Bin, gst::Element, gst::Object;
+}
+
+unsafe impl Send for HlsMultivariantSink {}
+unsafe impl Sync for HlsMultivariantSink {}
+
+pub fn register(plugin: &gst::Plugin) -> Result<(), glib::BoolError> {
+    gst::Element::register(
+        Some(plugin),
+        "hlsmultivariantsink",
+        gst::Rank::None,
+        HlsMultivariantSink::static_type(),
+    )
+}
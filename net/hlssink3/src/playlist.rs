@@ -7,12 +7,19 @@
 //
 // SPDX-License-Identifier: MPL-2.0
 
-use m3u8_rs::{MediaPlaylist, MediaPlaylistType, MediaSegment};
+use m3u8_rs::{ExtTag, Key, Map, MediaPlaylist, MediaPlaylistType, MediaSegment};
 use once_cell::sync::Lazy;
 use regex::Regex;
 use std::io::Write;
 
 const GST_M3U8_PLAYLIST_VERSION: usize = 3;
+/// `EXT-X-MAP` in playlists without `EXT-X-I-FRAMES-ONLY` requires version 6.
+const GST_M3U8_PLAYLIST_MAP_VERSION: usize = 6;
+/// Partial segments must be removed once they are more than this many target durations from the
+/// end of the playlist.
+const PART_MAX_AGE_TARGET_DURATIONS: f32 = 3.0;
+/// Tags of Low-Latency HLS that are written by `Playlist` itself.
+const LOW_LATENCY_TAGS: [&str; 4] = ["X-PART", "X-PART-INF", "X-SERVER-CONTROL", "X-PRELOAD-HINT"];
 
 static SEGMENT_IDX_PATTERN: Lazy<regex::Regex> = Lazy::new(|| Regex::new(r"(%0(\d+)d)").unwrap());
 
@@ -27,6 +34,37 @@ pub struct Playlist {
     playlist_index: i32,
     status: PlaylistRenderState,
     turn_vod: bool,
+    map: Option<Map>,
+    key: Option<Key>,
+    // Properties of the next segment
+    discontinuity: bool,
+    program_date_time: Option<String>,
+    // Low-Latency HLS partial segments, only if a part target duration is set
+    part_target: Option<f32>,
+    open_parts: Vec<Part>,
+    preload_hint: Option<String>,
+}
+
+/// A partial segment of Low-Latency HLS.
+#[derive(Debug, Clone)]
+struct Part {
+    uri: String,
+    duration: f32,
+    independent: bool,
+}
+
+impl Part {
+    fn to_tag(&self) -> ExtTag {
+        let mut rest = format!("DURATION={:.5},URI=\"{}\"", self.duration, self.uri);
+        if self.independent {
+            rest.push_str(",INDEPENDENT=YES");
+        }
+
+        ExtTag {
+            tag: String::from("X-PART"),
+            rest: Some(rest),
+        }
+    }
 }
 
 impl Playlist {
@@ -54,22 +92,151 @@ impl Playlist {
             playlist_index: 0,
             status: PlaylistRenderState::Init,
             turn_vod,
+            map: None,
+            key: None,
+            discontinuity: false,
+            program_date_time: None,
+            part_target: None,
+            open_parts: vec![],
+            preload_hint: None,
+        }
+    }
+
+    /// Sets the encryption key for the following segments.
+    ///
+    /// An `EXT-X-KEY` tag is written in front of the next segment if the key changed.
+    pub fn set_key(&mut self, key: Option<Key>) {
+        self.key = key;
+    }
+
+    /// Marks the next segment as discontinuous to the previous one.
+    pub fn mark_discontinuity(&mut self) {
+        self.discontinuity = true;
+    }
+
+    /// Sets the `EXT-X-PROGRAM-DATE-TIME` of the next segment.
+    pub fn set_program_date_time(&mut self, program_date_time: Option<String>) {
+        self.program_date_time = program_date_time;
+    }
+
+    /// Continues an existing playlist, e.g. one written by a previous run.
+    ///
+    /// The existing segments are kept and the media sequence continues after them. The next
+    /// segment is marked as discontinuous.
+    pub fn resume(&mut self, existing: MediaPlaylist) {
+        self.inner.version = self.inner.version.max(existing.version);
+        self.inner.media_sequence = existing.media_sequence;
+        self.inner.discontinuity_sequence = existing.discontinuity_sequence;
+        self.inner.segments = existing.segments;
+        self.playlist_index = self.inner.media_sequence + self.inner.segments.len() as i32;
+
+        // Partial segments of the previous run are not available anymore
+        for segment in &mut self.inner.segments {
+            segment
+                .unknown_tags
+                .retain(|tag| !LOW_LATENCY_TAGS.contains(&tag.tag.as_str()));
+        }
+
+        self.map = self
+            .inner
+            .segments
+            .iter()
+            .rev()
+            .find_map(|segment| segment.map.clone());
+        self.discontinuity = true;
+    }
+
+    /// Enables Low-Latency HLS with the given maximum duration of partial segments.
+    ///
+    /// This adds the `EXT-X-PART-INF` and `EXT-X-SERVER-CONTROL` tags to the playlist.
+    pub fn set_part_target(&mut self, part_target: f32) {
+        self.part_target = Some(part_target);
+    }
+
+    /// Adds a new partial segment to the segment that is currently being written.
+    ///
+    /// The parts are listed at the end of the playlist until the segment is added with
+    /// `add_segment`.
+    pub fn add_part(&mut self, uri: String, duration: f32, independent: bool) {
+        self.open_parts.push(Part {
+            uri,
+            duration,
+            independent,
+        });
+    }
+
+    /// Sets the URI of the partial segment that will be available next.
+    pub fn set_preload_hint(&mut self, uri: Option<String>) {
+        self.preload_hint = uri;
+    }
+
+    /// Sets the media initialization section for the following segments.
+    ///
+    /// An `EXT-X-MAP` tag is written in front of the next segment if the initialization section
+    /// changed, and the playlist version is raised accordingly.
+    pub fn set_map(&mut self, uri: String) {
+        if self.map.as_ref().map(|map| map.uri == uri) != Some(true) {
+            self.map = Some(Map {
+                uri,
+                byte_range: None,
+            });
+            self.inner.version = self.inner.version.max(GST_M3U8_PLAYLIST_MAP_VERSION);
         }
     }
 
     /// Adds a new segment to the playlist.
     pub fn add_segment(&mut self, uri: String, duration: f32) {
+        // Only write the initialization section if it's different from the previous segment's
+        let current_map = self
+            .inner
+            .segments
+            .iter()
+            .rev()
+            .find_map(|segment| segment.map.as_ref());
+        let discontinuity = std::mem::take(&mut self.discontinuity);
+        // The initialization section is repeated after discontinuities as it might have changed
+        let map = if discontinuity
+            || current_map.map(|map| &map.uri) != self.map.as_ref().map(|map| &map.uri)
+        {
+            self.map.clone()
+        } else {
+            None
+        };
+
+        // Same for the encryption key
+        let current_key = self
+            .inner
+            .segments
+            .iter()
+            .rev()
+            .find_map(|segment| segment.key.as_ref())
+            .filter(|key| key.method != "NONE");
+        let key = if current_key != self.key.as_ref() {
+            // Segments of a resumed playlist might have been encrypted
+            Some(self.key.clone().unwrap_or_else(|| Key {
+                method: String::from("NONE"),
+                ..Default::default()
+            }))
+        } else {
+            None
+        };
+
         self.inner.segments.push(MediaSegment {
             uri,
             duration,
             title: None,
             byte_range: None,
-            discontinuity: false,
-            key: None,
-            map: None,
-            program_date_time: None,
+            discontinuity,
+            key,
+            map,
+            program_date_time: self.program_date_time.take(),
             daterange: None,
-            unknown_tags: vec![],
+            // The parts of the segment are written in front of it
+            unknown_tags: self
+                .open_parts
+                .drain(..)
+                .map(|part| part.to_tag())
+                .collect(),
         });
     }
 
@@ -81,6 +248,7 @@ impl Playlist {
     /// to date.
     pub fn update_playlist_state(&mut self, max_playlist_length: usize) {
         self.start();
+        self.remove_old_parts();
 
         if !self.is_type_undefined() {
             return;
@@ -89,7 +257,20 @@ impl Playlist {
         // Remove oldest segments if playlist is at maximum expected capacity
         if self.inner.segments.len() > max_playlist_length {
             for _ in 0..self.inner.segments.len() - max_playlist_length {
-                let _ = self.inner.segments.remove(0);
+                let removed = self.inner.segments.remove(0);
+                if removed.discontinuity {
+                    self.inner.discontinuity_sequence += 1;
+                }
+
+                // Keep the initialization section and key for the remaining segments
+                if let Some(first) = self.inner.segments.first_mut() {
+                    if first.map.is_none() {
+                        first.map = removed.map;
+                    }
+                    if first.key.is_none() {
+                        first.key = removed.key;
+                    }
+                }
             }
         }
 
@@ -97,6 +278,26 @@ impl Playlist {
         self.inner.media_sequence = self.playlist_index as i32 - self.inner.segments.len() as i32;
     }
 
+    /// Removes the partial segments that are too far from the end of the playlist.
+    fn remove_old_parts(&mut self) {
+        if self.part_target.is_none() {
+            return;
+        }
+
+        let max_age = self.inner.target_duration * PART_MAX_AGE_TARGET_DURATIONS;
+        let mut age = self
+            .open_parts
+            .iter()
+            .map(|part| part.duration)
+            .sum::<f32>();
+        for segment in self.inner.segments.iter_mut().rev() {
+            age += segment.duration;
+            if age > max_age {
+                segment.unknown_tags.retain(|tag| tag.tag != "X-PART");
+            }
+        }
+    }
+
     /// Sets the playlist to started state.
     fn start(&mut self) {
         self.status = PlaylistRenderState::Started;
@@ -105,6 +306,10 @@ impl Playlist {
 
     /// Sets the playlist to stopped state.
     pub fn stop(&mut self) {
+        // No more parts will follow
+        self.open_parts.clear();
+        self.preload_hint = None;
+
         match &self.inner.playlist_type {
             None => self.inner.end_list = false,
             Some(defined) => match defined {
@@ -138,7 +343,57 @@ impl Playlist {
 
     /// Writes the playlist in textual format to the provided `Write` reference.
     pub fn write_to<T: Write>(&self, w: &mut T) -> std::io::Result<()> {
-        self.inner.write_to(w)
+        // The Low-Latency HLS tags are not supported by `m3u8_rs` and are added around its
+        // output, which also needs fixing up for AES-128 encrypted segments
+        let mut playlist = vec![];
+        self.inner.write_to(&mut playlist)?;
+        let playlist = String::from_utf8(playlist)
+            .map_err(|err| std::io::Error::new(std::io::ErrorKind::InvalidData, err))?;
+
+        // An `EXT-X-KEY` also applies to the initialization sections that follow it, but those are
+        // never encrypted. Encryption is disabled around each `EXT-X-MAP` and the key is repeated
+        // for the next segment.
+        let mut current_key = None;
+        let mut pending_key = None;
+        for line in playlist.lines() {
+            if line.starts_with("#EXT-X-KEY:") {
+                pending_key = Some(line);
+                continue;
+            }
+
+            if line.starts_with("#EXT-X-MAP:") {
+                if let Some(key) = current_key.take() {
+                    writeln!(w, "#EXT-X-KEY:METHOD=NONE")?;
+                    pending_key = pending_key.or(Some(key));
+                }
+            } else if let Some(key) = pending_key.take() {
+                writeln!(w, "{}", key)?;
+                current_key = Some(key).filter(|key| key.contains("METHOD=AES-128"));
+            }
+
+            writeln!(w, "{}", line)?;
+
+            if let Some(part_target) = self.part_target {
+                if line.starts_with("#EXT-X-TARGETDURATION:") {
+                    writeln!(
+                        w,
+                        "#EXT-X-SERVER-CONTROL:CAN-BLOCK-RELOAD=YES,PART-HOLD-BACK={:.5}",
+                        part_target * 3.0
+                    )?;
+                    writeln!(w, "#EXT-X-PART-INF:PART-TARGET={:.5}", part_target)?;
+                }
+            }
+        }
+
+        for part in &self.open_parts {
+            let tag = part.to_tag();
+            writeln!(w, "#EXT-{}:{}", tag.tag, tag.rest.unwrap_or_default())?;
+        }
+        if let Some(preload_hint) = &self.preload_hint {
+            writeln!(w, "#EXT-X-PRELOAD-HINT:TYPE=PART,URI=\"{}\"", preload_hint)?;
+        }
+
+        Ok(())
     }
 }
 
@@ -207,6 +462,66 @@ impl SegmentFormatter {
         let padded_number = left_pad_zeroes(self.padding_len, id);
         format!("{}{}{}", self.prefix, padded_number, self.suffix)
     }
+
+    /// Returns the DASH `SegmentTemplate` media URI with a `$Number$` identifier in place of the
+    /// placeholder.
+    pub fn number_template(&self) -> String {
+        format!(
+            "{}$Number%0{}d${}",
+            self.prefix, self.padding_len, self.suffix
+        )
+    }
+
+    /// Returns the id of a segment location created by this formatter, if it matches.
+    pub fn index(&self, location: &str) -> Option<u32> {
+        location
+            .strip_prefix(&self.prefix)?
+            .strip_suffix(&self.suffix)?
+            .parse()
+            .ok()
+    }
+}
+
+/// Formats a time since the UNIX epoch as ISO 8601 date and time in UTC with millisecond
+/// precision, as used by `EXT-X-PROGRAM-DATE-TIME`.
+///
+/// # Examples
+///
+/// ```rust,ignore
+/// let date_time = format_date_time(std::time::Duration::from_millis(1_638_316_800_500));
+/// assert_eq!(date_time, "2021-12-01T00:00:00.500Z");
+/// ```
+pub(crate) fn format_date_time(unix_time: std::time::Duration) -> String {
+    let secs = unix_time.as_secs();
+    let (hours, minutes, seconds) = (secs % 86_400 / 3_600, secs % 3_600 / 60, secs % 60);
+
+    // Civil date from the number of days since the epoch, see
+    // <http://howardhinnant.github.io/date_algorithms.html#civil_from_days>
+    let days = (secs / 86_400) as i64 + 719_468;
+    let era = days / 146_097;
+    let day_of_era = days - era * 146_097;
+    let year_of_era =
+        (day_of_era - day_of_era / 1_460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let month_index = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * month_index + 2) / 5 + 1;
+    let month = if month_index < 10 {
+        month_index + 3
+    } else {
+        month_index - 9
+    };
+    let year = year_of_era + era * 400 + if month <= 2 { 1 } else { 0 };
+
+    format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}.{:03}Z",
+        year,
+        month,
+        day,
+        hours,
+        minutes,
+        seconds,
+        unix_time.subsec_millis()
+    )
 }
 
 /// Transforms a number to a zero padded string representation.
@@ -254,6 +569,244 @@ mod tests {
         assert_eq!("part-9999.ts", formatter.segment(9999));
     }
 
+    #[test]
+    fn segment_index_is_parsed() {
+        let formatter = SegmentFormatter::new("segment%05d.ts").unwrap();
+
+        assert_eq!(Some(16), formatter.index("segment00016.ts"));
+        assert_eq!(Some(123456), formatter.index("segment123456.ts"));
+        assert_eq!(None, formatter.index("segment00016.m4s"));
+        assert_eq!(None, formatter.index("part00016.ts"));
+    }
+
+    #[test]
+    fn number_template_is_created() {
+        let formatter = SegmentFormatter::new("video_0_segment%05d.m4s").unwrap();
+        assert_eq!(
+            "video_0_segment$Number%05d$.m4s",
+            formatter.number_template()
+        );
+    }
+
+    #[test]
+    fn map_is_written_once_and_kept_on_removal() {
+        let mut playlist = Playlist::new(2.0, None);
+        playlist.set_map("init.mp4".to_string());
+        for idx in 0..3 {
+            playlist.add_segment(format!("segment{}.m4s", idx), 2.0);
+            playlist.update_playlist_state(2);
+        }
+
+        let mut output = vec![];
+        playlist.write_to(&mut output).unwrap();
+        let output = String::from_utf8(output).unwrap();
+
+        assert!(output.contains("#EXT-X-VERSION:6\n"));
+        assert_eq!(output.matches("#EXT-X-MAP:URI=\"init.mp4\"").count(), 1);
+        assert!(output.find("#EXT-X-MAP").unwrap() < output.find("segment1.m4s").unwrap());
+        assert!(!output.contains("segment0.m4s"));
+    }
+
+    #[test]
+    fn key_is_written_on_change_and_kept_on_removal() {
+        let key = |idx: u32| Key {
+            method: String::from("AES-128"),
+            uri: Some(format!("key{}.key", idx)),
+            iv: None,
+            ..Default::default()
+        };
+
+        let mut playlist = Playlist::new(2.0, None);
+        for idx in 0..4 {
+            // Rotate the key every two segments
+            playlist.set_key(Some(key(idx / 2)));
+            playlist.add_segment(format!("segment{}.ts", idx), 2.0);
+            playlist.update_playlist_state(3);
+        }
+
+        let mut output = vec![];
+        playlist.write_to(&mut output).unwrap();
+        let output = String::from_utf8(output).unwrap();
+
+        assert!(!output.contains("segment0.ts"));
+        assert_eq!(output.matches("#EXT-X-KEY:").count(), 2);
+        assert!(
+            output
+                .find("#EXT-X-KEY:METHOD=AES-128,URI=\"key0.key\"")
+                .unwrap()
+                < output.find("segment1.ts").unwrap()
+        );
+        assert!(
+            output
+                .find("#EXT-X-KEY:METHOD=AES-128,URI=\"key1.key\"")
+                .unwrap()
+                < output.find("segment2.ts").unwrap()
+        );
+    }
+
+    #[test]
+    fn map_is_not_encrypted() {
+        let mut playlist = Playlist::new(2.0, None);
+        playlist.set_map("init.mp4".to_string());
+        playlist.set_key(Some(Key {
+            method: String::from("AES-128"),
+            uri: Some(String::from("key0.key")),
+            iv: None,
+            ..Default::default()
+        }));
+        for idx in 0..3 {
+            // The initialization section is repeated after the discontinuity
+            if idx == 2 {
+                playlist.mark_discontinuity();
+            }
+            playlist.add_segment(format!("segment{}.m4s", idx), 2.0);
+            playlist.update_playlist_state(3);
+        }
+
+        let mut output = vec![];
+        playlist.write_to(&mut output).unwrap();
+        let output = String::from_utf8(output).unwrap();
+
+        assert!(output.contains(
+            "#EXT-X-MAP:URI=\"init.mp4\"\n\
+            #EXT-X-KEY:METHOD=AES-128,URI=\"key0.key\"\n\
+            #EXTINF:2,\nsegment0.m4s\n"
+        ));
+        assert!(output.contains(
+            "#EXT-X-KEY:METHOD=NONE\n\
+            #EXT-X-MAP:URI=\"init.mp4\"\n\
+            #EXT-X-KEY:METHOD=AES-128,URI=\"key0.key\"\n\
+            #EXTINF:2,\nsegment2.m4s\n"
+        ));
+        assert_eq!(output.matches("#EXT-X-KEY:METHOD=AES-128").count(), 2);
+    }
+
+    #[test]
+    fn parts_are_written_for_open_and_recent_segments() {
+        let mut playlist = Playlist::new(2.0, None);
+        playlist.set_part_target(0.5);
+        playlist.set_map("init.mp4".to_string());
+
+        for segment_idx in 0..5 {
+            for part_idx in 0..4 {
+                playlist.add_part(
+                    format!("part{}.{}.m4s", segment_idx, part_idx),
+                    0.5,
+                    part_idx == 0,
+                );
+            }
+            playlist.add_segment(format!("segment{}.m4s", segment_idx), 2.0);
+            playlist.update_playlist_state(10);
+        }
+        playlist.add_part("part5.0.m4s".to_string(), 0.5, true);
+        playlist.set_preload_hint(Some("part5.1.m4s".to_string()));
+
+        let mut output = vec![];
+        playlist.write_to(&mut output).unwrap();
+        let output = String::from_utf8(output).unwrap();
+
+        assert!(output.contains(
+            "#EXT-X-TARGETDURATION:2\n\
+            #EXT-X-SERVER-CONTROL:CAN-BLOCK-RELOAD=YES,PART-HOLD-BACK=1.50000\n\
+            #EXT-X-PART-INF:PART-TARGET=0.50000\n"
+        ));
+        // Parts more than three target durations from the end are removed
+        assert!(!output.contains("part1.3.m4s"));
+        assert!(
+            output.contains("#EXT-X-PART:DURATION=0.50000,URI=\"part2.0.m4s\",INDEPENDENT=YES\n")
+        );
+        assert!(output.contains("#EXT-X-PART:DURATION=0.50000,URI=\"part4.3.m4s\"\n"));
+        assert!(output.ends_with(
+            "segment4.m4s\n\
+            #EXT-X-PART:DURATION=0.50000,URI=\"part5.0.m4s\",INDEPENDENT=YES\n\
+            #EXT-X-PRELOAD-HINT:TYPE=PART,URI=\"part5.1.m4s\"\n"
+        ));
+
+        playlist.stop();
+        let mut output = vec![];
+        playlist.write_to(&mut output).unwrap();
+        let output = String::from_utf8(output).unwrap();
+        assert!(!output.contains("part5.0.m4s"));
+        assert!(!output.contains("#EXT-X-PRELOAD-HINT"));
+    }
+
+    #[test]
+    fn discontinuities_and_program_date_time() {
+        let mut playlist = Playlist::new(2.0, None);
+        for idx in 0..4 {
+            if idx == 2 {
+                playlist.mark_discontinuity();
+            }
+            playlist.set_program_date_time(Some(format_date_time(std::time::Duration::from_secs(
+                1_638_316_800 + 2 * idx,
+            ))));
+            playlist.add_segment(format!("segment{}.ts", idx), 2.0);
+            playlist.update_playlist_state(2);
+        }
+
+        let mut output = vec![];
+        playlist.write_to(&mut output).unwrap();
+        let output = String::from_utf8(output).unwrap();
+        assert!(output.contains("#EXT-X-DISCONTINUITY\n"));
+        assert!(!output.contains("#EXT-X-DISCONTINUITY-SEQUENCE"));
+        assert!(output.contains("#EXT-X-PROGRAM-DATE-TIME:2021-12-01T00:00:06.000Z\n"));
+
+        // The discontinuity sequence is increased once the discontinuity is removed
+        playlist.add_segment("segment4.ts".to_string(), 2.0);
+        playlist.update_playlist_state(2);
+
+        let mut output = vec![];
+        playlist.write_to(&mut output).unwrap();
+        let output = String::from_utf8(output).unwrap();
+        assert!(!output.contains("#EXT-X-DISCONTINUITY\n"));
+        assert!(output.contains("#EXT-X-DISCONTINUITY-SEQUENCE:1\n"));
+    }
+
+    #[test]
+    fn resume_continues_media_sequence() {
+        let mut previous = Playlist::new(2.0, None);
+        previous.set_map("init.mp4".to_string());
+        for idx in 0..3 {
+            previous.add_segment(format!("segment{}.m4s", idx), 2.0);
+            previous.update_playlist_state(2);
+        }
+        let mut output = vec![];
+        previous.write_to(&mut output).unwrap();
+        let existing = m3u8_rs::parse_media_playlist_res(&output).unwrap();
+
+        let mut playlist = Playlist::new(2.0, None);
+        playlist.resume(existing);
+        playlist.set_map("init.mp4".to_string());
+        playlist.add_segment("segment3.m4s".to_string(), 2.0);
+        playlist.update_playlist_state(2);
+
+        let mut output = vec![];
+        playlist.write_to(&mut output).unwrap();
+        let output = String::from_utf8(output).unwrap();
+        assert!(output.contains("#EXT-X-MEDIA-SEQUENCE:2\n"));
+        assert!(output.contains("segment2.m4s\n"));
+        // The initialization section is repeated after the discontinuity
+        assert!(output.contains(
+            "#EXT-X-DISCONTINUITY\n#EXT-X-MAP:URI=\"init.mp4\"\n#EXTINF:2,\nsegment3.m4s\n"
+        ));
+    }
+
+    #[test]
+    fn formats_date_time() {
+        assert_eq!(
+            format_date_time(std::time::Duration::ZERO),
+            "1970-01-01T00:00:00.000Z"
+        );
+        assert_eq!(
+            format_date_time(std::time::Duration::from_millis(1_638_316_800_500)),
+            "2021-12-01T00:00:00.500Z"
+        );
+        assert_eq!(
+            format_date_time(std::time::Duration::from_secs(951_782_400 + 3_723)),
+            "2000-02-29T01:02:03.000Z"
+        );
+    }
+
     #[test]
     fn padding_numbers() {
         assert_eq!("001", left_pad_zeroes(3, 1));
@@ -0,0 +1,484 @@
+//
+// Copyright (C) 2026 agent <agent@local>
+//
+// This Source Code Form is subject to the terms of the Mozilla Public License, v2.0.
+// If a copy of the MPL was not distributed with this file, You can obtain one at
+// <https://mozilla.org/MPL/2.0/>.
+//
+// SPDX-License-Identifier: MPL-2.0
+
+//! `SAMPLE-AES` encryption of MPEG-TS segments as specified in Apple's "MPEG-2 Stream
+//! Encryption Format for HTTP Live Streaming".
+//!
+//! The H.264 and AAC elementary streams are encrypted before they are muxed, and the PMT written
+//! by the muxer is rewritten afterwards to signal the encrypted stream types.
+
+use aes::cipher::{generic_array::GenericArray, BlockEncryptMut, KeyIvInit};
+
+const BLOCK_SIZE: usize = 16;
+const TS_PACKET_SIZE: usize = 188;
+
+const STREAM_TYPE_AAC: u8 = 0x0f;
+const STREAM_TYPE_H264: u8 = 0x1b;
+const STREAM_TYPE_ENCRYPTED_AAC: u8 = 0xcf;
+const STREAM_TYPE_ENCRYPTED_H264: u8 = 0xdb;
+
+type Aes128CbcEnc = cbc::Encryptor<aes::Aes128>;
+
+/// Encrypts the samples of the H.264 and AAC elementary streams of one segment.
+///
+/// The CBC chain is restarted with the same IV for every NAL unit and audio frame.
+pub(crate) struct SampleEncryptor {
+    key: [u8; BLOCK_SIZE],
+    iv: [u8; BLOCK_SIZE],
+}
+
+impl SampleEncryptor {
+    pub(crate) fn new(key: &[u8; BLOCK_SIZE], iv: &[u8; BLOCK_SIZE]) -> Self {
+        Self { key: *key, iv: *iv }
+    }
+
+    fn cipher(&self) -> Aes128CbcEnc {
+        Aes128CbcEnc::new(&self.key.into(), &self.iv.into())
+    }
+
+    /// Encrypts the slice NAL units of an H.264 access unit in byte-stream format.
+    ///
+    /// The first 32 bytes of each slice stay clear, followed by one encrypted block out of every
+    /// ten blocks. Slices of 48 bytes or less are not encrypted at all.
+    pub(crate) fn encrypt_h264(&self, data: &[u8]) -> Vec<u8> {
+        let mut output = Vec::with_capacity(data.len() + data.len() / 64);
+
+        let mut pos = 0;
+        for (start, end) in nal_units(data) {
+            output.extend_from_slice(&data[pos..start]);
+            pos = end;
+
+            let nal = &data[start..end];
+            if !matches!(nal[0] & 0x1f, 1 | 5) || nal.len() <= 48 {
+                output.extend_from_slice(nal);
+                continue;
+            }
+
+            let mut rbsp = remove_emulation_prevention(nal);
+            let mut cipher = self.cipher();
+            let mut offset = 32;
+            while rbsp.len() - offset > BLOCK_SIZE {
+                cipher.encrypt_block_mut(GenericArray::from_mut_slice(
+                    &mut rbsp[offset..offset + BLOCK_SIZE],
+                ));
+                offset = usize::min(offset + 10 * BLOCK_SIZE, rbsp.len());
+            }
+            add_emulation_prevention(&rbsp, &mut output);
+        }
+        output.extend_from_slice(&data[pos..]);
+
+        output
+    }
+
+    /// Encrypts one AAC frame that starts with a header of `header_len` bytes, which is 0 for raw
+    /// frames.
+    ///
+    /// The first 16 bytes after the header stay clear, followed by all complete blocks.
+    pub(crate) fn encrypt_aac(&self, data: &[u8], header_len: usize) -> Vec<u8> {
+        let mut output = data.to_vec();
+        if output.len() < header_len + BLOCK_SIZE {
+            return output;
+        }
+
+        let encrypted = &mut output[header_len + BLOCK_SIZE..];
+        let encrypted_len = encrypted.len() - encrypted.len() % BLOCK_SIZE;
+        let mut cipher = self.cipher();
+        for block in encrypted[..encrypted_len].chunks_exact_mut(BLOCK_SIZE) {
+            cipher.encrypt_block_mut(GenericArray::from_mut_slice(block));
+        }
+
+        output
+    }
+}
+
+/// Returns the length of the ADTS header at the start of `data`, if any.
+pub(crate) fn adts_header_len(data: &[u8]) -> Option<usize> {
+    if data.len() < 7 || data[0] != 0xff || data[1] & 0xf6 != 0xf0 {
+        return None;
+    }
+
+    // Without protection_absent, the header is followed by a CRC
+    Some(if data[1] & 0x01 != 0 { 7 } else { 9 })
+}
+
+/// Creates the AudioSpecificConfig that corresponds to an ADTS header.
+pub(crate) fn adts_audio_specific_config(header: &[u8]) -> Option<Vec<u8>> {
+    adts_header_len(header)?;
+
+    let object_type = (header[2] >> 6) + 1;
+    let frequency_index = (header[2] >> 2) & 0x0f;
+    let channel_configuration = ((header[2] & 0x01) << 2) | (header[3] >> 6);
+
+    Some(vec![
+        (object_type << 3) | (frequency_index >> 1),
+        ((frequency_index & 0x01) << 7) | (channel_configuration << 3),
+    ])
+}
+
+/// Returns the start and end of all NAL units of an H.264 byte-stream, without start codes and
+/// trailing zero bytes.
+fn nal_units(data: &[u8]) -> Vec<(usize, usize)> {
+    let mut starts = Vec::new();
+    let mut i = 0;
+    while i + 3 <= data.len() {
+        if data[i..i + 3] == [0, 0, 1] {
+            starts.push(i + 3);
+            i += 3;
+        } else {
+            i += 1;
+        }
+    }
+
+    starts
+        .iter()
+        .enumerate()
+        .filter_map(|(idx, &start)| {
+            let mut end = starts.get(idx + 1).map_or(data.len(), |next| next - 3);
+            while end > start && data[end - 1] == 0 {
+                end -= 1;
+            }
+            if end > start {
+                Some((start, end))
+            } else {
+                None
+            }
+        })
+        .collect()
+}
+
+fn remove_emulation_prevention(nal: &[u8]) -> Vec<u8> {
+    let mut rbsp = Vec::with_capacity(nal.len());
+    let mut zeros = 0;
+    for &b in nal {
+        if zeros >= 2 && b == 0x03 {
+            zeros = 0;
+            continue;
+        }
+        zeros = if b == 0 { zeros + 1 } else { 0 };
+        rbsp.push(b);
+    }
+    rbsp
+}
+
+fn add_emulation_prevention(rbsp: &[u8], output: &mut Vec<u8>) {
+    let mut zeros = 0;
+    for &b in rbsp {
+        if zeros >= 2 && b <= 0x03 {
+            output.push(0x03);
+            zeros = 0;
+        }
+        zeros = if b == 0 { zeros + 1 } else { 0 };
+        output.push(b);
+    }
+    // The NAL unit must not end with a zero byte
+    if zeros > 0 {
+        output.push(0x03);
+    }
+}
+
+/// Rewrites the PMT of MPEG-TS packets to signal the `SAMPLE-AES` encrypted H.264 and AAC
+/// streams.
+///
+/// The PMT PID is taken from the PAT, and the PMT has to fit into a single packet.
+#[derive(Default)]
+pub(crate) struct PmtRewriter {
+    pmt_pid: Option<u16>,
+    audio_specific_config: Vec<u8>,
+}
+
+impl PmtRewriter {
+    /// Sets the AudioSpecificConfig of the AAC stream, which is part of its audio setup
+    /// information in the PMT.
+    pub(crate) fn set_audio_specific_config(&mut self, config: &[u8]) {
+        self.audio_specific_config = config.to_vec();
+    }
+
+    /// Returns the rewritten packets if `data` contains a PMT.
+    pub(crate) fn rewrite(&mut self, data: &[u8]) -> Option<Vec<u8>> {
+        let mut output = None;
+
+        for (idx, packet) in data.chunks_exact(TS_PACKET_SIZE).enumerate() {
+            if packet[0] != 0x47 || packet[1] & 0x40 == 0 {
+                continue;
+            }
+
+            let pid = u16::from_be_bytes([packet[1] & 0x1f, packet[2]]);
+            let payload_start = match packet[3] & 0x30 {
+                0x10 => 4,
+                0x30 => 5 + packet[4] as usize,
+                _ => continue,
+            };
+            // Skip the pointer field
+            let section_start = match packet.get(payload_start) {
+                Some(&pointer) => payload_start + 1 + pointer as usize,
+                None => continue,
+            };
+            let section = match packet.get(section_start..) {
+                Some(section) => section,
+                None => continue,
+            };
+
+            if pid == 0 {
+                self.pmt_pid = pat_pmt_pid(section).or(self.pmt_pid);
+            } else if Some(pid) == self.pmt_pid {
+                let pmt = match rewrite_pmt(section, &self.audio_specific_config) {
+                    Some(pmt) if payload_start + 1 + pmt.len() <= TS_PACKET_SIZE => pmt,
+                    _ => continue,
+                };
+
+                let output = output.get_or_insert_with(|| data.to_vec());
+                let packet = &mut output[idx * TS_PACKET_SIZE..(idx + 1) * TS_PACKET_SIZE];
+                packet[payload_start] = 0;
+                packet[payload_start + 1..payload_start + 1 + pmt.len()].copy_from_slice(&pmt);
+                for b in &mut packet[payload_start + 1 + pmt.len()..] {
+                    *b = 0xff;
+                }
+            }
+        }
+
+        output
+    }
+}
+
+/// Returns the end of a PSI section including its CRC.
+fn section_end(section: &[u8]) -> Option<usize> {
+    let end = 3 + (u16::from_be_bytes([section.get(1)? & 0x0f, *section.get(2)?]) as usize);
+    if end > section.len() {
+        return None;
+    }
+    Some(end)
+}
+
+/// Returns the PID of the first program's PMT.
+fn pat_pmt_pid(section: &[u8]) -> Option<u16> {
+    if section.first() != Some(&0x00) {
+        return None;
+    }
+    let end = section_end(section)?;
+
+    section
+        .get(8..end.checked_sub(4)?)?
+        .chunks_exact(4)
+        .find(|program| program[0..2] != [0, 0])
+        .map(|program| u16::from_be_bytes([program[2] & 0x1f, program[3]]))
+}
+
+/// Changes the stream types of H.264 and AAC and adds the descriptors of the encrypted streams.
+fn rewrite_pmt(section: &[u8], audio_specific_config: &[u8]) -> Option<Vec<u8>> {
+    if section.first() != Some(&0x02) {
+        return None;
+    }
+    let end = section_end(section)?;
+    if end < 16 {
+        return None;
+    }
+
+    let program_info_end = 12 + u16::from_be_bytes([section[10] & 0x0f, section[11]]) as usize;
+    let mut pmt = section.get(..program_info_end)?.to_vec();
+
+    let mut pos = program_info_end;
+    while pos + 5 <= end - 4 {
+        let stream_type = section[pos];
+        let es_info_len = u16::from_be_bytes([section[pos + 3] & 0x0f, section[pos + 4]]) as usize;
+        let descriptors = section.get(pos + 5..pos + 5 + es_info_len)?;
+
+        let (stream_type, extra_descriptors) = match stream_type {
+            STREAM_TYPE_H264 => (
+                STREAM_TYPE_ENCRYPTED_H264,
+                private_data_indicator_descriptor(b"zavc"),
+            ),
+            STREAM_TYPE_AAC => {
+                let mut extra_descriptors = private_data_indicator_descriptor(b"aacd");
+                extra_descriptors.extend(audio_setup_descriptor(audio_specific_config));
+                (STREAM_TYPE_ENCRYPTED_AAC, extra_descriptors)
+            }
+            _ => (stream_type, Vec::new()),
+        };
+
+        let es_info_len = es_info_len + extra_descriptors.len();
+        pmt.push(stream_type);
+        pmt.extend_from_slice(&section[pos + 1..pos + 3]);
+        pmt.push((section[pos + 3] & 0xf0) | (es_info_len >> 8) as u8);
+        pmt.push(es_info_len as u8);
+        pmt.extend_from_slice(descriptors);
+        pmt.extend_from_slice(&extra_descriptors);
+
+        pos += 5 + descriptors.len();
+    }
+
+    // The section length includes the CRC
+    let section_len = pmt.len() - 3 + 4;
+    if section_len > 0x3fd {
+        return None;
+    }
+    pmt[1] = (pmt[1] & 0xf0) | (section_len >> 8) as u8;
+    pmt[2] = section_len as u8;
+    let crc = crc32_mpeg2(&pmt);
+    pmt.extend_from_slice(&crc.to_be_bytes());
+
+    Some(pmt)
+}
+
+fn private_data_indicator_descriptor(format: &[u8; 4]) -> Vec<u8> {
+    let mut descriptor = vec![0x0f, 4];
+    descriptor.extend_from_slice(format);
+    descriptor
+}
+
+/// Registration descriptor with the audio setup information of the encrypted AAC stream.
+fn audio_setup_descriptor(audio_specific_config: &[u8]) -> Vec<u8> {
+    let mut descriptor = vec![0x05, 0];
+    descriptor.extend_from_slice(b"apad");
+    descriptor.extend_from_slice(b"zaac");
+    // Priming and version
+    descriptor.extend_from_slice(&[0, 0, 1]);
+    descriptor.push(audio_specific_config.len() as u8);
+    descriptor.extend_from_slice(audio_specific_config);
+    descriptor[1] = (descriptor.len() - 2) as u8;
+    descriptor
+}
+
+fn crc32_mpeg2(data: &[u8]) -> u32 {
+    let mut crc = 0xffff_ffffu32;
+    for &b in data {
+        crc ^= (b as u32) << 24;
+        for _ in 0..8 {
+            crc = if crc & 0x8000_0000 != 0 {
+                (crc << 1) ^ 0x04c1_1db7
+            } else {
+                crc << 1
+            };
+        }
+    }
+    crc
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const KEY: [u8; 16] = [0x01; 16];
+    const IV: [u8; 16] = [0x02; 16];
+
+    #[test]
+    fn encrypts_h264_slices() {
+        let mut slice = vec![0x65];
+        slice.extend((1..220).map(|i| i as u8 | 0x80));
+        let sps = [0x67, 0x42, 0x00, 0x00, 0x03, 0x01];
+
+        let mut data = vec![0, 0, 0, 1];
+        data.extend_from_slice(&sps);
+        data.extend_from_slice(&[0, 0, 0, 1]);
+        data.extend_from_slice(&slice);
+
+        let encrypted = SampleEncryptor::new(&KEY, &IV).encrypt_h264(&data);
+
+        // Parameter sets and the clear leader of the slice are unchanged
+        assert_eq!(&encrypted[..14 + 32], &data[..14 + 32]);
+
+        // Only the first of every ten blocks is encrypted, with the IV reset for each NAL unit
+        let nal_start = 14;
+        let rbsp = remove_emulation_prevention(&encrypted[nal_start..]);
+        let mut expected = slice.clone();
+        let mut cipher = Aes128CbcEnc::new(&KEY.into(), &IV.into());
+        cipher.encrypt_block_mut(GenericArray::from_mut_slice(&mut expected[32..48]));
+        cipher.encrypt_block_mut(GenericArray::from_mut_slice(&mut expected[192..208]));
+        assert_eq!(rbsp, expected);
+        assert_eq!(&rbsp[48..192], &slice[48..192]);
+        assert_eq!(&rbsp[208..], &slice[208..]);
+    }
+
+    #[test]
+    fn keeps_short_slices_clear() {
+        let mut data = vec![0, 0, 0, 1, 0x41];
+        data.extend([0x80; 47]);
+
+        assert_eq!(SampleEncryptor::new(&KEY, &IV).encrypt_h264(&data), data);
+    }
+
+    #[test]
+    fn escapes_encrypted_slices() {
+        let rbsp = [0x65, 0x00, 0x00, 0x01, 0x00, 0x00, 0x00];
+        let mut escaped = Vec::new();
+        add_emulation_prevention(&rbsp, &mut escaped);
+
+        assert_eq!(
+            escaped,
+            vec![0x65, 0x00, 0x00, 0x03, 0x01, 0x00, 0x00, 0x03, 0x00, 0x03]
+        );
+        assert_eq!(remove_emulation_prevention(&escaped[..9]), rbsp.to_vec());
+    }
+
+    #[test]
+    fn encrypts_aac_frames() {
+        let mut frame = vec![0xff, 0xf1, 0x50, 0x80, 0x05, 0x1f, 0xfc];
+        frame.extend((0..40).map(|i| i as u8));
+        assert_eq!(adts_header_len(&frame), Some(7));
+
+        let encrypted = SampleEncryptor::new(&KEY, &IV).encrypt_aac(&frame, 7);
+
+        let mut expected = frame.clone();
+        let mut cipher = Aes128CbcEnc::new(&KEY.into(), &IV.into());
+        cipher.encrypt_block_mut(GenericArray::from_mut_slice(&mut expected[23..39]));
+        assert_eq!(encrypted, expected);
+    }
+
+    #[test]
+    fn converts_adts_headers() {
+        // AAC-LC, 44.1kHz, stereo
+        let header = [0xff, 0xf1, 0x50, 0x80, 0x05, 0x1f, 0xfc];
+        assert_eq!(adts_audio_specific_config(&header), Some(vec![0x12, 0x10]));
+    }
+
+    #[test]
+    fn rewrites_pmt() {
+        // PAT with program 1 at PID 0x1000
+        let mut pat = vec![0x47, 0x40, 0x00, 0x10, 0x00];
+        let mut section = vec![
+            0x00, 0xb0, 0x0d, 0x00, 0x01, 0xc1, 0x00, 0x00, 0x00, 0x01, 0xf0, 0x00,
+        ];
+        section.extend(crc32_mpeg2(&section).to_be_bytes());
+        pat.extend(section);
+        pat.resize(TS_PACKET_SIZE, 0xff);
+
+        // PMT with H.264 on PID 0x41 and AAC on PID 0x42
+        let mut pmt = vec![0x47, 0x50, 0x00, 0x10, 0x00];
+        let mut section = vec![
+            0x02, 0xb0, 0x17, 0x00, 0x01, 0xc1, 0x00, 0x00, 0xe0, 0x41, 0xf0, 0x00, 0x1b, 0xe0,
+            0x41, 0xf0, 0x00, 0x0f, 0xe0, 0x42, 0xf0, 0x00,
+        ];
+        section.extend(crc32_mpeg2(&section).to_be_bytes());
+        pmt.extend(section);
+        pmt.resize(TS_PACKET_SIZE, 0xff);
+
+        let mut data = pat.clone();
+        data.extend_from_slice(&pmt);
+
+        let mut rewriter = PmtRewriter::default();
+        rewriter.set_audio_specific_config(&[0x12, 0x10]);
+        let rewritten = rewriter.rewrite(&data).unwrap();
+        assert_eq!(&rewritten[..TS_PACKET_SIZE], &pat[..]);
+
+        let section = &rewritten[TS_PACKET_SIZE + 5..];
+        let end = section_end(section).unwrap();
+        assert_eq!(crc32_mpeg2(&section[..end]), 0);
+        assert_eq!(
+            &section[12..end - 4],
+            &[
+                0xdb, 0xe0, 0x41, 0xf0, 0x06, 0x0f, 0x04, b'z', b'a', b'v', b'c', 0xcf, 0xe0, 0x42,
+                0xf0, 0x16, 0x0f, 0x04, b'a', b'a', b'c', b'd', 0x05, 0x0e, b'a', b'p', b'a', b'd',
+                b'z', b'a', b'a', b'c', 0x00, 0x00, 0x01, 0x02, 0x12, 0x10,
+            ][..]
+        );
+        assert!(section[end..].iter().all(|&b| b == 0xff));
+
+        // Packets without PMT are left alone
+        assert!(rewriter.rewrite(&pat).is_none());
+    }
+}
@@ -58,6 +58,7 @@ fn init() {
     INIT.call_once(|| {
         gst::init().unwrap();
         gsthlssink3::plugin_register_static().expect("hlssink3 test");
+        gstfmp4::plugin_register_static().expect("hlssink3 test");
     });
 }
 
@@ -100,6 +101,42 @@ impl Write for MemoryPlaylistFile {
     }
 }
 
+/// Keeps track of the number of bytes written to each file.
+struct MemoryFragmentFile {
+    location: String,
+    sizes: Arc<Mutex<Vec<(String, usize)>>>,
+}
+
+impl Write for MemoryFragmentFile {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        let mut sizes = self.sizes.lock().unwrap();
+        if let Some((_, size)) = sizes.iter_mut().find(|(l, _)| *l == self.location) {
+            *size += buf.len();
+        }
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Keeps the data written to a file.
+struct MemoryDataFile {
+    data: Arc<Mutex<Vec<u8>>>,
+}
+
+impl Write for MemoryDataFile {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.data.lock().unwrap().extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
 #[test]
 fn test_hlssink3_element_with_video_content() -> Result<(), ()> {
     init();
@@ -430,3 +467,1039 @@ segments/my-own-filename-000.ts
 
     Ok(())
 }
+
+#[test]
+fn test_hlssink3_element_with_cmaf_content() -> Result<(), ()> {
+    init();
+
+    const BUFFER_NB: i32 = 250;
+
+    let pipeline = gst::Pipeline::new(Some("cmaf_pipeline"));
+
+    let video_src = try_create_element!("videotestsrc");
+    video_src.set_property("is-live", true);
+    video_src.set_property("num-buffers", BUFFER_NB);
+
+    let x264enc = try_create_element!("x264enc");
+    let h264parse = try_create_element!("h264parse");
+
+    let hlssink3 = gst::ElementFactory::make("hlssink3", Some("test_hlssink3"))
+        .expect("Must be able to instantiate hlssink3");
+    hlssink3.set_property_from_str("segment-format", "cmaf");
+    hlssink3.set_property("target-duration", 2u32);
+    hlssink3.set_property("playlist-length", 2u32);
+    hlssink3.set_property("max-files", 2u32);
+    assert_eq!(
+        hlssink3.property::<String>("location"),
+        "segment%05d.m4s".to_string()
+    );
+
+    let (hls_events_sender, hls_events_receiver) = mpsc::sync_channel(20);
+    let playlist_content = Arc::new(Mutex::new(String::from("")));
+
+    hlssink3.connect("get-playlist-stream", false, {
+        let hls_events_sender = hls_events_sender.clone();
+        let playlist_content = playlist_content.clone();
+        move |args| {
+            let location = args[1].get::<String>().expect("No location given");
+
+            hls_events_sender
+                .try_send(HlsSinkEvent::GetPlaylistStream(location))
+                .expect("Send playlist event");
+
+            let playlist = MemoryPlaylistFile {
+                handler: Arc::clone(&playlist_content),
+            };
+            playlist.clear_content();
+            let output = gio::WriteOutputStream::new(playlist);
+            Some(output.to_value())
+        }
+    });
+
+    hlssink3.connect("get-fragment-stream", false, {
+        let hls_events_sender = hls_events_sender.clone();
+        move |args| {
+            let location = args[1].get::<String>().expect("No location given");
+
+            hls_events_sender
+                .try_send(HlsSinkEvent::GetFragmentStream(location))
+                .expect("Send fragment event");
+
+            let stream = gio::MemoryOutputStream::new_resizable();
+            Some(stream.to_value())
+        }
+    });
+
+    hlssink3.connect("delete-fragment", false, move |args| {
+        let location = args[1].get::<String>().expect("No location given");
+        hls_events_sender
+            .try_send(HlsSinkEvent::DeleteFragment(location))
+            .expect("Send delete fragment event");
+        Some(true.to_value())
+    });
+
+    try_or_pause!(pipeline.add_many(&[&video_src, &x264enc, &h264parse, &hlssink3,]));
+    try_or_pause!(gst::Element::link_many(&[
+        &video_src, &x264enc, &h264parse, &hlssink3
+    ]));
+
+    pipeline.set_state(gst::State::Playing).unwrap();
+
+    let mut eos = false;
+    let bus = pipeline.bus().unwrap();
+    while let Some(msg) = bus.timed_pop(gst::ClockTime::NONE) {
+        use gst::MessageView;
+        match msg.view() {
+            MessageView::Eos(..) => {
+                eos = true;
+                break;
+            }
+            MessageView::Error(..) => unreachable!(),
+            _ => (),
+        }
+    }
+
+    pipeline.set_state(gst::State::Null).unwrap();
+    assert!(eos);
+
+    let mut actual_events = Vec::new();
+    while let Ok(event) = hls_events_receiver.recv_timeout(Duration::from_millis(1)) {
+        actual_events.push(event);
+    }
+
+    // The init segment is written once before the first media segment
+    {
+        use self::HlsSinkEvent::*;
+        assert_eq!(actual_events[0], GetFragmentStream("init.mp4".to_string()));
+        assert_eq!(
+            actual_events[1],
+            GetFragmentStream("segment00000.m4s".to_string())
+        );
+        assert_eq!(
+            actual_events
+                .iter()
+                .filter(|event| **event == GetFragmentStream("init.mp4".to_string()))
+                .count(),
+            1
+        );
+    }
+
+    let contents = playlist_content.lock().unwrap();
+    assert!(contents.starts_with("#EXTM3U\n#EXT-X-VERSION:6\n"));
+    assert_eq!(contents.matches("#EXT-X-MAP:URI=\"init.mp4\"").count(), 1);
+    assert!(contents.contains(".m4s\n"));
+
+    Ok(())
+}
+
+#[test]
+fn test_hlsmultivariantsink_element_with_video_and_audio() -> Result<(), ()> {
+    init();
+
+    let pipeline = gst::Pipeline::new(Some("multivariant_pipeline"));
+
+    let video_src = try_create_element!("videotestsrc");
+    video_src.set_property("is-live", true);
+    video_src.set_property("num-buffers", 250i32);
+
+    let x264enc = try_create_element!("x264enc");
+    let h264parse = try_create_element!("h264parse");
+
+    let audio_src = try_create_element!("audiotestsrc");
+    audio_src.set_property("is-live", true);
+    audio_src.set_property("num-buffers", 360i32);
+
+    let hls_avenc_aac = try_or_pause!(gst::ElementFactory::make(
+        "avenc_aac",
+        Some("hls_avenc_aac")
+    ));
+
+    let sink = gst::ElementFactory::make("hlsmultivariantsink", Some("test_multivariant"))
+        .expect("Must be able to instantiate hlsmultivariantsink");
+    sink.set_property("target-duration", 2u32);
+
+    let master_content = Arc::new(Mutex::new(String::from("")));
+    let (hls_events_sender, hls_events_receiver) = mpsc::sync_channel(100);
+
+    sink.connect("get-playlist-stream", false, {
+        let master_content = master_content.clone();
+        let hls_events_sender = hls_events_sender.clone();
+        move |args| {
+            let location = args[1].get::<String>().expect("No location given");
+
+            if location != "master.m3u8" {
+                let _ = hls_events_sender.try_send(HlsSinkEvent::GetPlaylistStream(location));
+                let stream = gio::MemoryOutputStream::new_resizable();
+                return Some(stream.to_value());
+            }
+
+            let playlist = MemoryPlaylistFile {
+                handler: Arc::clone(&master_content),
+            };
+            playlist.clear_content();
+            let output = gio::WriteOutputStream::new(playlist);
+            Some(output.to_value())
+        }
+    });
+
+    sink.connect("get-fragment-stream", false, move |args| {
+        let location = args[1].get::<String>().expect("No location given");
+        let _ = hls_events_sender.try_send(HlsSinkEvent::GetFragmentStream(location));
+        let stream = gio::MemoryOutputStream::new_resizable();
+        Some(stream.to_value())
+    });
+
+    sink.connect("delete-fragment", false, move |_| Some(true.to_value()));
+
+    try_or_pause!(pipeline.add_many(&[
+        &video_src,
+        &x264enc,
+        &h264parse,
+        &audio_src,
+        &hls_avenc_aac,
+        &sink
+    ]));
+    try_or_pause!(gst::Element::link_many(&[&video_src, &x264enc, &h264parse]));
+    try_or_pause!(audio_src.link(&hls_avenc_aac));
+
+    let video_pad = sink.request_pad_simple("video_%u").unwrap();
+    assert_eq!(video_pad.name(), "video_0");
+    let audio_pad = sink.request_pad_simple("audio_%u").unwrap();
+    assert_eq!(audio_pad.name(), "audio_0");
+    // Subtitles require CMAF segments
+    assert!(sink.request_pad_simple("subtitle_%u").is_none());
+
+    try_or_pause!(h264parse.static_pad("src").unwrap().link(&video_pad));
+    try_or_pause!(hls_avenc_aac.static_pad("src").unwrap().link(&audio_pad));
+
+    pipeline.set_state(gst::State::Playing).unwrap();
+
+    let mut eos = false;
+    let bus = pipeline.bus().unwrap();
+    while let Some(msg) = bus.timed_pop(gst::ClockTime::NONE) {
+        use gst::MessageView;
+        match msg.view() {
+            MessageView::Eos(..) => {
+                eos = true;
+                break;
+            }
+            MessageView::Error(..) => unreachable!(),
+            _ => (),
+        }
+    }
+
+    pipeline.set_state(gst::State::Null).unwrap();
+    assert!(eos);
+
+    let mut actual_events = Vec::new();
+    while let Ok(event) = hls_events_receiver.recv_timeout(Duration::from_millis(1)) {
+        actual_events.push(event);
+    }
+
+    // Each rendition has its own media playlist and segments
+    {
+        use self::HlsSinkEvent::*;
+        assert!(actual_events.contains(&GetPlaylistStream("video_0.m3u8".to_string())));
+        assert!(actual_events.contains(&GetPlaylistStream("audio_0.m3u8".to_string())));
+        assert!(actual_events.contains(&GetFragmentStream("video_0_segment00000.ts".to_string())));
+        assert!(actual_events.contains(&GetFragmentStream("audio_0_segment00000.ts".to_string())));
+    }
+
+    let contents = master_content.lock().unwrap();
+    assert!(contents.starts_with("#EXTM3U\n"));
+    assert!(contents.contains("#EXT-X-INDEPENDENT-SEGMENTS"));
+    assert!(contents.contains("TYPE=AUDIO"));
+    assert!(contents.contains("GROUP-ID=\"audio\""));
+    assert!(contents.contains("URI=\"audio_0.m3u8\""));
+    assert!(contents.contains("#EXT-X-STREAM-INF:"));
+    assert!(contents.contains("BANDWIDTH="));
+    assert!(contents.contains("AVERAGE-BANDWIDTH="));
+    assert!(contents.contains("CODECS=\"avc1."));
+    assert!(contents.contains("mp4a.40.2"));
+    assert!(contents.contains("RESOLUTION=320x240"));
+    assert!(contents.contains("AUDIO=\"audio\""));
+    assert!(contents.contains("\nvideo_0.m3u8\n"));
+
+    Ok(())
+}
+
+#[test]
+fn test_hlssink3_element_with_low_latency_parts() -> Result<(), ()> {
+    init();
+
+    const BUFFER_NB: i32 = 250;
+
+    let pipeline = gst::Pipeline::new(Some("low_latency_pipeline"));
+
+    let video_src = try_create_element!("videotestsrc");
+    video_src.set_property("is-live", true);
+    video_src.set_property("num-buffers", BUFFER_NB);
+
+    let x264enc = try_create_element!("x264enc");
+    let h264parse = try_create_element!("h264parse");
+
+    let hlssink3 = gst::ElementFactory::make("hlssink3", Some("test_hlssink3"))
+        .expect("Must be able to instantiate hlssink3");
+    hlssink3.set_property_from_str("segment-format", "cmaf");
+    hlssink3.set_property("target-duration", 2u32);
+    hlssink3.set_property("part-duration", 500u32);
+    hlssink3.set_property("playlist-length", 3u32);
+    hlssink3.set_property("max-files", 3u32);
+
+    // Stand-in for the HTTP server that serves the playlist with blocking reloads, keeps every
+    // version of the playlist
+    let playlist_versions = Arc::new(Mutex::new(Vec::<String>::new()));
+    let playlist_content = Arc::new(Mutex::new(String::from("")));
+    let (hls_events_sender, hls_events_receiver) = mpsc::sync_channel(1000);
+
+    hlssink3.connect("get-playlist-stream", false, {
+        let playlist_content = playlist_content.clone();
+        let playlist_versions = playlist_versions.clone();
+        move |_args| {
+            {
+                let content = playlist_content.lock().unwrap();
+                if !content.is_empty() {
+                    playlist_versions.lock().unwrap().push(content.clone());
+                }
+            }
+
+            let playlist = MemoryPlaylistFile {
+                handler: Arc::clone(&playlist_content),
+            };
+            playlist.clear_content();
+            let output = gio::WriteOutputStream::new(playlist);
+            Some(output.to_value())
+        }
+    });
+
+    hlssink3.connect("get-fragment-stream", false, {
+        let hls_events_sender = hls_events_sender.clone();
+        move |args| {
+            let location = args[1].get::<String>().expect("No location given");
+
+            hls_events_sender
+                .try_send(HlsSinkEvent::GetFragmentStream(location))
+                .expect("Send fragment event");
+
+            let stream = gio::MemoryOutputStream::new_resizable();
+            Some(stream.to_value())
+        }
+    });
+
+    hlssink3.connect("delete-fragment", false, move |args| {
+        let location = args[1].get::<String>().expect("No location given");
+        hls_events_sender
+            .try_send(HlsSinkEvent::DeleteFragment(location))
+            .expect("Send delete fragment event");
+        Some(true.to_value())
+    });
+
+    try_or_pause!(pipeline.add_many(&[&video_src, &x264enc, &h264parse, &hlssink3,]));
+    try_or_pause!(gst::Element::link_many(&[
+        &video_src, &x264enc, &h264parse, &hlssink3
+    ]));
+
+    pipeline.set_state(gst::State::Playing).unwrap();
+
+    let mut eos = false;
+    let bus = pipeline.bus().unwrap();
+    while let Some(msg) = bus.timed_pop(gst::ClockTime::NONE) {
+        use gst::MessageView;
+        match msg.view() {
+            MessageView::Eos(..) => {
+                eos = true;
+                break;
+            }
+            MessageView::Error(..) => unreachable!(),
+            _ => (),
+        }
+    }
+
+    pipeline.set_state(gst::State::Null).unwrap();
+    assert!(eos);
+
+    let mut actual_events = Vec::new();
+    while let Ok(event) = hls_events_receiver.recv_timeout(Duration::from_millis(1)) {
+        actual_events.push(event);
+    }
+
+    {
+        use self::HlsSinkEvent::*;
+        assert_eq!(actual_events[0], GetFragmentStream("init.mp4".to_string()));
+        assert_eq!(
+            actual_events[1],
+            GetFragmentStream("part00000.m4s".to_string())
+        );
+        assert!(actual_events.contains(&GetFragmentStream("segment00000.m4s".to_string())));
+        // Parts are deleted together with their segment
+        assert!(actual_events.contains(&DeleteFragment("segment00000.m4s".to_string())));
+        assert!(actual_events.contains(&DeleteFragment("part00000.m4s".to_string())));
+    }
+
+    // While the stream is running the playlist advertises the parts of the open segment and
+    // the next part
+    let playlist_versions = playlist_versions.lock().unwrap();
+    let live_playlist = playlist_versions
+        .iter()
+        .find(|playlist| playlist.contains("#EXT-X-PRELOAD-HINT"))
+        .expect("No playlist with preload hint");
+    assert!(live_playlist.contains("#EXT-X-VERSION:6\n"));
+    assert!(live_playlist
+        .contains("#EXT-X-SERVER-CONTROL:CAN-BLOCK-RELOAD=YES,PART-HOLD-BACK=1.50000\n"));
+    assert!(live_playlist.contains("#EXT-X-PART-INF:PART-TARGET=0.50000\n"));
+    assert!(live_playlist.contains("#EXT-X-PART:DURATION="));
+    assert!(live_playlist.contains(",URI=\"part00000.m4s\",INDEPENDENT=YES\n"));
+    assert!(live_playlist.contains("#EXT-X-PRELOAD-HINT:TYPE=PART,URI=\"part0000"));
+
+    // The final playlist has no parts of unfinished segments
+    let contents = playlist_content.lock().unwrap();
+    assert!(!contents.contains("#EXT-X-PRELOAD-HINT"));
+    assert!(contents.ends_with(".m4s\n"));
+
+    Ok(())
+}
+
+#[test]
+fn test_hlssink3_element_with_aes128_encryption() -> Result<(), ()> {
+    init();
+
+    const BUFFER_NB: i32 = 250;
+
+    let pipeline = gst::Pipeline::new(Some("aes128_pipeline"));
+
+    let video_src = try_create_element!("videotestsrc");
+    video_src.set_property("is-live", true);
+    video_src.set_property("num-buffers", BUFFER_NB);
+
+    let x264enc = try_create_element!("x264enc");
+    let h264parse = try_create_element!("h264parse");
+
+    let hlssink3 = gst::ElementFactory::make("hlssink3", Some("test_hlssink3"))
+        .expect("Must be able to instantiate hlssink3");
+    hlssink3.set_property("target-duration", 2u32);
+    hlssink3.set_property("playlist-length", 5u32);
+    hlssink3.set_property("max-files", 5u32);
+    hlssink3.set_property_from_str("encryption-method", "aes-128");
+    hlssink3.set_property("key-rotation", 2u32);
+    hlssink3.set_property("key-uri", "https://keys.example.com/key%03d");
+
+    let playlist_content = Arc::new(Mutex::new(String::from("")));
+    let fragment_sizes = Arc::new(Mutex::new(Vec::new()));
+
+    hlssink3.connect("get-playlist-stream", false, {
+        let playlist_content = playlist_content.clone();
+        move |_args| {
+            let playlist = MemoryPlaylistFile {
+                handler: Arc::clone(&playlist_content),
+            };
+            playlist.clear_content();
+            let output = gio::WriteOutputStream::new(playlist);
+            Some(output.to_value())
+        }
+    });
+
+    hlssink3.connect("get-fragment-stream", false, {
+        let fragment_sizes = fragment_sizes.clone();
+        move |args| {
+            let location = args[1].get::<String>().expect("No location given");
+
+            fragment_sizes.lock().unwrap().push((location.clone(), 0));
+            let output = gio::WriteOutputStream::new(MemoryFragmentFile {
+                location,
+                sizes: fragment_sizes.clone(),
+            });
+            Some(output.to_value())
+        }
+    });
+
+    hlssink3.connect("delete-fragment", false, move |_| Some(true.to_value()));
+
+    try_or_pause!(pipeline.add_many(&[&video_src, &x264enc, &h264parse, &hlssink3,]));
+    try_or_pause!(gst::Element::link_many(&[
+        &video_src, &x264enc, &h264parse, &hlssink3
+    ]));
+
+    pipeline.set_state(gst::State::Playing).unwrap();
+
+    let mut eos = false;
+    let bus = pipeline.bus().unwrap();
+    while let Some(msg) = bus.timed_pop(gst::ClockTime::NONE) {
+        use gst::MessageView;
+        match msg.view() {
+            MessageView::Eos(..) => {
+                eos = true;
+                break;
+            }
+            MessageView::Error(..) => unreachable!(),
+            _ => (),
+        }
+    }
+
+    pipeline.set_state(gst::State::Null).unwrap();
+    assert!(eos);
+
+    // A new key is written before every second segment, and all segments are padded to the
+    // AES block size
+    let fragment_sizes = fragment_sizes.lock().unwrap();
+    assert_eq!(
+        fragment_sizes
+            .iter()
+            .map(|(location, _)| location.as_str())
+            .collect::<Vec<_>>(),
+        vec![
+            "key00000.key",
+            "segment00000.ts",
+            "segment00001.ts",
+            "key00001.key",
+            "segment00002.ts",
+            "segment00003.ts",
+            "key00002.key",
+            "segment00004.ts",
+        ]
+    );
+    for (location, size) in fragment_sizes.iter() {
+        if location.ends_with(".key") {
+            assert_eq!(*size, 16);
+        } else {
+            assert!(*size > 0);
+            assert_eq!(*size % 16, 0);
+        }
+    }
+
+    let contents = playlist_content.lock().unwrap();
+    assert_eq!(contents.matches("#EXT-X-KEY:METHOD=AES-128,").count(), 5);
+    assert!(contents.contains(
+        "#EXT-X-KEY:METHOD=AES-128,URI=\"https://keys.example.com/key000\",IV=0x00000000000000000000000000000000\n"
+    ));
+    assert!(contents.contains(
+        "#EXT-X-KEY:METHOD=AES-128,URI=\"https://keys.example.com/key001\",IV=0x00000000000000000000000000000003\n"
+    ));
+    assert!(contents.contains(
+        "#EXT-X-KEY:METHOD=AES-128,URI=\"https://keys.example.com/key002\",IV=0x00000000000000000000000000000004\n"
+    ));
+
+    Ok(())
+}
+
+#[test]
+fn test_hlssink3_element_with_sample_aes_encryption() -> Result<(), ()> {
+    init();
+
+    const BUFFER_NB: i32 = 250;
+
+    let pipeline = gst::Pipeline::new(Some("sample_aes_pipeline"));
+
+    let video_src = try_create_element!("videotestsrc");
+    video_src.set_property("is-live", true);
+    video_src.set_property("num-buffers", BUFFER_NB);
+
+    let x264enc = try_create_element!("x264enc");
+    let h264parse = try_create_element!("h264parse");
+
+    let hlssink3 = gst::ElementFactory::make("hlssink3", Some("test_hlssink3"))
+        .expect("Must be able to instantiate hlssink3");
+    hlssink3.set_property("target-duration", 2u32);
+    hlssink3.set_property("playlist-length", 5u32);
+    hlssink3.set_property("max-files", 5u32);
+    hlssink3.set_property_from_str("encryption-method", "sample-aes");
+    hlssink3.set_property("key-rotation", 2u32);
+
+    let playlist_content = Arc::new(Mutex::new(String::from("")));
+    let fragment_sizes = Arc::new(Mutex::new(Vec::new()));
+    let first_segment = Arc::new(Mutex::new(Vec::new()));
+
+    hlssink3.connect("get-playlist-stream", false, {
+        let playlist_content = playlist_content.clone();
+        move |_args| {
+            let playlist = MemoryPlaylistFile {
+                handler: Arc::clone(&playlist_content),
+            };
+            playlist.clear_content();
+            let output = gio::WriteOutputStream::new(playlist);
+            Some(output.to_value())
+        }
+    });
+
+    hlssink3.connect("get-fragment-stream", false, {
+        let fragment_sizes = fragment_sizes.clone();
+        let first_segment = first_segment.clone();
+        move |args| {
+            let location = args[1].get::<String>().expect("No location given");
+
+            if location == "segment00000.ts" {
+                let output = gio::WriteOutputStream::new(MemoryDataFile {
+                    data: first_segment.clone(),
+                });
+                return Some(output.to_value());
+            }
+
+            fragment_sizes.lock().unwrap().push((location.clone(), 0));
+            let output = gio::WriteOutputStream::new(MemoryFragmentFile {
+                location,
+                sizes: fragment_sizes.clone(),
+            });
+            Some(output.to_value())
+        }
+    });
+
+    hlssink3.connect("delete-fragment", false, move |_| Some(true.to_value()));
+
+    try_or_pause!(pipeline.add_many(&[&video_src, &x264enc, &h264parse, &hlssink3,]));
+    try_or_pause!(gst::Element::link_many(&[
+        &video_src, &x264enc, &h264parse, &hlssink3
+    ]));
+
+    pipeline.set_state(gst::State::Playing).unwrap();
+
+    let mut eos = false;
+    let bus = pipeline.bus().unwrap();
+    while let Some(msg) = bus.timed_pop(gst::ClockTime::NONE) {
+        use gst::MessageView;
+        match msg.view() {
+            MessageView::Eos(..) => {
+                eos = true;
+                break;
+            }
+            MessageView::Error(..) => unreachable!(),
+            _ => (),
+        }
+    }
+
+    pipeline.set_state(gst::State::Null).unwrap();
+    assert!(eos);
+
+    // Keys are rotated like with AES-128, but the segments are not padded
+    let fragment_sizes = fragment_sizes.lock().unwrap();
+    assert_eq!(
+        fragment_sizes
+            .iter()
+            .map(|(location, _)| location.as_str())
+            .collect::<Vec<_>>(),
+        vec![
+            "key00000.key",
+            "segment00001.ts",
+            "key00001.key",
+            "segment00002.ts",
+            "segment00003.ts",
+            "key00002.key",
+            "segment00004.ts",
+        ]
+    );
+    for (location, size) in fragment_sizes.iter() {
+        if location.ends_with(".key") {
+            assert_eq!(*size, 16);
+        } else {
+            assert!(*size > 0);
+            assert_eq!(*size % 188, 0);
+        }
+    }
+
+    // The PMT signals the encrypted H.264 stream
+    let first_segment = first_segment.lock().unwrap();
+    assert_eq!(first_segment.len() % 188, 0);
+    let pmt = first_segment
+        .chunks_exact(188)
+        .find(|packet| packet[1] & 0x40 != 0 && packet[4] == 0 && packet[5] == 0x02)
+        .expect("No PMT in the first segment");
+    let program_info_len = (((pmt[15] & 0x0f) as usize) << 8) | pmt[16] as usize;
+    let stream = &pmt[17 + program_info_len..];
+    assert_eq!(stream[0], 0xdb);
+    assert_eq!(&stream[5..11], &[0x0f, 0x04, b'z', b'a', b'v', b'c']);
+
+    let contents = playlist_content.lock().unwrap();
+    assert_eq!(contents.matches("#EXT-X-KEY:METHOD=SAMPLE-AES,").count(), 5);
+    assert!(contents.contains(
+        "#EXT-X-KEY:METHOD=SAMPLE-AES,URI=\"key00000.key\",IV=0x00000000000000000000000000000000\n"
+    ));
+
+    Ok(())
+}
+
+#[test]
+fn test_hlssink3_element_rejects_cmaf_sample_aes_key_rotation() -> Result<(), ()> {
+    init();
+
+    if gst::ElementFactory::find("cmafmux").is_none() {
+        eprintln!("Could not find cmafmux plugin, skipping test");
+        return Ok(());
+    }
+
+    let hlssink3 = gst::ElementFactory::make("hlssink3", Some("test_hlssink3"))
+        .expect("Must be able to instantiate hlssink3");
+    hlssink3.set_property_from_str("segment-format", "cmaf");
+    hlssink3.set_property_from_str("encryption-method", "sample-aes");
+    hlssink3.set_property("key-rotation", 2u32);
+
+    assert!(hlssink3.set_state(gst::State::Ready).is_err());
+    hlssink3.set_state(gst::State::Null).unwrap();
+
+    Ok(())
+}
+
+#[test]
+fn test_hlssink3_element_with_cmaf_aes128_encryption() -> Result<(), ()> {
+    init();
+
+    const BUFFER_NB: i32 = 250;
+
+    let pipeline = gst::Pipeline::new(Some("cmaf_aes128_pipeline"));
+
+    let video_src = try_create_element!("videotestsrc");
+    video_src.set_property("is-live", true);
+    video_src.set_property("num-buffers", BUFFER_NB);
+
+    let x264enc = try_create_element!("x264enc");
+    let h264parse = try_create_element!("h264parse");
+
+    let hlssink3 = gst::ElementFactory::make("hlssink3", Some("test_hlssink3"))
+        .expect("Must be able to instantiate hlssink3");
+    hlssink3.set_property_from_str("segment-format", "cmaf");
+    hlssink3.set_property("target-duration", 2u32);
+    hlssink3.set_property("playlist-length", 10u32);
+    hlssink3.set_property("max-files", 10u32);
+    hlssink3.set_property_from_str("encryption-method", "aes-128");
+
+    let playlist_content = Arc::new(Mutex::new(String::from("")));
+    let fragment_sizes = Arc::new(Mutex::new(Vec::new()));
+
+    hlssink3.connect("get-playlist-stream", false, {
+        let playlist_content = playlist_content.clone();
+        move |_args| {
+            let playlist = MemoryPlaylistFile {
+                handler: Arc::clone(&playlist_content),
+            };
+            playlist.clear_content();
+            let output = gio::WriteOutputStream::new(playlist);
+            Some(output.to_value())
+        }
+    });
+
+    hlssink3.connect("get-fragment-stream", false, {
+        let fragment_sizes = fragment_sizes.clone();
+        move |args| {
+            let location = args[1].get::<String>().expect("No location given");
+
+            fragment_sizes.lock().unwrap().push((location.clone(), 0));
+            let output = gio::WriteOutputStream::new(MemoryFragmentFile {
+                location,
+                sizes: fragment_sizes.clone(),
+            });
+            Some(output.to_value())
+        }
+    });
+
+    hlssink3.connect("delete-fragment", false, move |_| Some(true.to_value()));
+
+    try_or_pause!(pipeline.add_many(&[&video_src, &x264enc, &h264parse, &hlssink3,]));
+    try_or_pause!(gst::Element::link_many(&[
+        &video_src, &x264enc, &h264parse, &hlssink3
+    ]));
+
+    pipeline.set_state(gst::State::Playing).unwrap();
+
+    let mut eos = false;
+    let bus = pipeline.bus().unwrap();
+    while let Some(msg) = bus.timed_pop(gst::ClockTime::NONE) {
+        use gst::MessageView;
+        match msg.view() {
+            MessageView::Eos(..) => {
+                eos = true;
+                break;
+            }
+            MessageView::Error(..) => unreachable!(),
+            _ => (),
+        }
+    }
+
+    pipeline.set_state(gst::State::Null).unwrap();
+    assert!(eos);
+
+    // The initialization segment is written in the clear, the media segments are encrypted as a
+    // whole and padded to the AES block size
+    let fragment_sizes = fragment_sizes.lock().unwrap();
+    assert_eq!(
+        fragment_sizes
+            .iter()
+            .take(3)
+            .map(|(location, _)| location.as_str())
+            .collect::<Vec<_>>(),
+        vec!["init.mp4", "key00000.key", "segment00000.m4s"]
+    );
+    for (location, size) in fragment_sizes.iter() {
+        if location.ends_with(".key") {
+            assert_eq!(*size, 16);
+        } else if location.ends_with(".m4s") {
+            assert!(*size > 0);
+            assert_eq!(*size % 16, 0);
+        }
+    }
+
+    // The key only applies to the segments and not to the initialization section
+    let contents = playlist_content.lock().unwrap();
+    assert_eq!(contents.matches("#EXT-X-MAP:URI=\"init.mp4\"").count(), 1);
+    assert!(contents.contains(
+        "#EXT-X-MAP:URI=\"init.mp4\"\n#EXT-X-KEY:METHOD=AES-128,URI=\"key00000.key\",IV=0x00000000000000000000000000000000\n"
+    ));
+
+    Ok(())
+}
+
+#[test]
+fn test_hlssink3_element_resumes_playlist_with_program_date_time() -> Result<(), ()> {
+    init();
+
+    const BUFFER_NB: i32 = 50;
+
+    let pipeline = gst::Pipeline::new(Some("video_pipeline"));
+
+    let video_src = try_create_element!("videotestsrc");
+    video_src.set_property("is-live", true);
+    video_src.set_property("num-buffers", BUFFER_NB);
+
+    let x264enc = try_create_element!("x264enc");
+    let h264parse = try_create_element!("h264parse");
+
+    // Playlist of a previous run of the pipeline
+    let dir = std::env::temp_dir().join(format!("hlssink3-resume-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let playlist_location = dir.join("main.m3u8");
+    std::fs::write(
+        &playlist_location,
+        r###"#EXTM3U
+#EXT-X-VERSION:3
+#EXT-X-TARGETDURATION:15
+#EXT-X-MEDIA-SEQUENCE:5
+#EXTINF:10,
+segment00005.ts
+#EXTINF:10,
+segment00006.ts
+"###,
+    )
+    .unwrap();
+
+    let hlssink3 = gst::ElementFactory::make("hlssink3", Some("test_hlssink3"))
+        .expect("Must be able to instantiate hlssink3");
+    hlssink3.set_property(
+        "location",
+        dir.join("segment%05d.ts").to_str().unwrap().to_value(),
+    );
+    hlssink3.set_property(
+        "playlist-location",
+        playlist_location.to_str().unwrap().to_value(),
+    );
+    hlssink3.set_property("program-date-time", true);
+    hlssink3.set_property("resume", true);
+
+    let (hls_events_sender, hls_events_receiver) = mpsc::sync_channel(20);
+    let playlist_content = Arc::new(Mutex::new(String::from("")));
+
+    hlssink3.connect("get-playlist-stream", false, {
+        let playlist_content = playlist_content.clone();
+        move |_args| {
+            let playlist = MemoryPlaylistFile {
+                handler: Arc::clone(&playlist_content),
+            };
+            playlist.clear_content();
+            let output = gio::WriteOutputStream::new(playlist);
+            Some(output.to_value())
+        }
+    });
+
+    hlssink3.connect("get-fragment-stream", false, move |args| {
+        let location = args[1].get::<String>().expect("No location given");
+
+        hls_events_sender
+            .try_send(HlsSinkEvent::GetFragmentStream(location))
+            .expect("Send fragment event");
+
+        let stream = gio::MemoryOutputStream::new_resizable();
+        Some(stream.to_value())
+    });
+
+    try_or_pause!(pipeline.add_many(&[&video_src, &x264enc, &h264parse, &hlssink3,]));
+    try_or_pause!(gst::Element::link_many(&[
+        &video_src, &x264enc, &h264parse, &hlssink3
+    ]));
+
+    pipeline.set_state(gst::State::Playing).unwrap();
+
+    let mut eos = false;
+    let bus = pipeline.bus().unwrap();
+    while let Some(msg) = bus.timed_pop(gst::ClockTime::NONE) {
+        use gst::MessageView;
+        match msg.view() {
+            MessageView::Eos(..) => {
+                eos = true;
+                break;
+            }
+            MessageView::Error(..) => unreachable!(),
+            _ => (),
+        }
+    }
+
+    pipeline.set_state(gst::State::Null).unwrap();
+    std::fs::remove_dir_all(&dir).unwrap();
+    assert!(eos);
+
+    // The new segment continues after the last segment of the previous run
+    let mut actual_events = Vec::new();
+    while let Ok(event) = hls_events_receiver.recv_timeout(Duration::from_millis(1)) {
+        actual_events.push(event);
+    }
+    assert_eq!(
+        vec![HlsSinkEvent::GetFragmentStream(
+            dir.join("segment00007.ts").to_str().unwrap().to_string()
+        )],
+        actual_events
+    );
+
+    let contents = playlist_content.lock().unwrap();
+    assert!(contents.contains("#EXT-X-MEDIA-SEQUENCE:5\n"));
+    assert!(contents.contains("segment00005.ts\n"));
+    assert!(contents.contains("segment00006.ts\n#EXT-X-DISCONTINUITY\n#EXT-X-PROGRAM-DATE-TIME:"));
+    assert!(contents.ends_with("segment00007.ts\n"));
+
+    Ok(())
+}
+
+#[test]
+fn test_dashsink_element_with_video_and_audio() -> Result<(), ()> {
+    init();
+
+    let pipeline = gst::Pipeline::new(Some("dash_pipeline"));
+
+    let video_src = try_create_element!("videotestsrc");
+    video_src.set_property("is-live", true);
+    video_src.set_property("num-buffers", 250i32);
+
+    let x264enc = try_create_element!("x264enc");
+    x264enc.set_property("key-int-max", 30u32);
+    let h264parse = try_create_element!("h264parse");
+
+    let audio_src = try_create_element!("audiotestsrc");
+    audio_src.set_property("is-live", true);
+    audio_src.set_property("num-buffers", 360i32);
+
+    let dash_avenc_aac = try_or_pause!(gst::ElementFactory::make(
+        "avenc_aac",
+        Some("dash_avenc_aac")
+    ));
+    let aacparse = try_create_element!("aacparse");
+
+    let sink = gst::ElementFactory::make("dashsink", Some("test_dashsink"))
+        .expect("Must be able to instantiate dashsink");
+    sink.set_property("target-duration", 2u32);
+    sink.set_property("playlist-length", 2u32);
+    sink.set_property("max-files", 3u32);
+
+    let mpd_content = Arc::new(Mutex::new(String::from("")));
+    let (dash_events_sender, dash_events_receiver) = mpsc::sync_channel(100);
+
+    sink.connect("get-playlist-stream", false, {
+        let mpd_content = mpd_content.clone();
+        let dash_events_sender = dash_events_sender.clone();
+        move |args| {
+            let location = args[1].get::<String>().expect("No location given");
+            let _ = dash_events_sender.try_send(HlsSinkEvent::GetPlaylistStream(location));
+
+            let mpd = MemoryPlaylistFile {
+                handler: Arc::clone(&mpd_content),
+            };
+            mpd.clear_content();
+            let output = gio::WriteOutputStream::new(mpd);
+            Some(output.to_value())
+        }
+    });
+
+    sink.connect("get-fragment-stream", false, {
+        let dash_events_sender = dash_events_sender.clone();
+        move |args| {
+            let location = args[1].get::<String>().expect("No location given");
+            let _ = dash_events_sender.try_send(HlsSinkEvent::GetFragmentStream(location));
+            let stream = gio::MemoryOutputStream::new_resizable();
+            Some(stream.to_value())
+        }
+    });
+
+    sink.connect("delete-fragment", false, move |args| {
+        let location = args[1].get::<String>().expect("No location given");
+        let _ = dash_events_sender.try_send(HlsSinkEvent::DeleteFragment(location));
+        Some(true.to_value())
+    });
+
+    try_or_pause!(pipeline.add_many(&[
+        &video_src,
+        &x264enc,
+        &h264parse,
+        &audio_src,
+        &dash_avenc_aac,
+        &aacparse,
+        &sink
+    ]));
+    try_or_pause!(gst::Element::link_many(&[&video_src, &x264enc, &h264parse]));
+    try_or_pause!(gst::Element::link_many(&[
+        &audio_src,
+        &dash_avenc_aac,
+        &aacparse
+    ]));
+
+    let video_pad = sink.request_pad_simple("video_%u").unwrap();
+    assert_eq!(video_pad.name(), "video_0");
+    let audio_pad = sink.request_pad_simple("audio_%u").unwrap();
+    assert_eq!(audio_pad.name(), "audio_0");
+
+    try_or_pause!(h264parse.static_pad("src").unwrap().link(&video_pad));
+    try_or_pause!(aacparse.static_pad("src").unwrap().link(&audio_pad));
+
+    pipeline.set_state(gst::State::Playing).unwrap();
+
+    let mut eos = false;
+    let bus = pipeline.bus().unwrap();
+    while let Some(msg) = bus.timed_pop(gst::ClockTime::NONE) {
+        use gst::MessageView;
+        match msg.view() {
+            MessageView::Eos(..) => {
+                eos = true;
+                break;
+            }
+            MessageView::Error(..) => unreachable!(),
+            _ => (),
+        }
+    }
+
+    pipeline.set_state(gst::State::Null).unwrap();
+    assert!(eos);
+
+    let mut actual_events = Vec::new();
+    while let Ok(event) = dash_events_receiver.recv_timeout(Duration::from_millis(1)) {
+        actual_events.push(event);
+    }
+
+    {
+        use self::HlsSinkEvent::*;
+        assert!(actual_events.contains(&GetPlaylistStream("manifest.mpd".to_string())));
+        assert!(actual_events.contains(&GetFragmentStream("video_0_init.mp4".to_string())));
+        assert!(actual_events.contains(&GetFragmentStream("audio_0_init.mp4".to_string())));
+        assert!(actual_events.contains(&GetFragmentStream("video_0_segment00003.m4s".to_string())));
+        assert!(actual_events.contains(&GetFragmentStream("audio_0_segment00003.m4s".to_string())));
+        // Only `max-files` segments are kept per representation
+        assert!(actual_events.contains(&DeleteFragment("video_0_segment00000.m4s".to_string())));
+        assert!(actual_events.contains(&DeleteFragment("audio_0_segment00000.m4s".to_string())));
+    }
+
+    // The final MPD is static with the last `playlist-length` segments
+    let contents = mpd_content.lock().unwrap();
+    assert!(contents.starts_with("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n<MPD "));
+    assert!(contents.contains(" type=\"static\""));
+    assert!(contents.contains("contentType=\"video\""));
+    assert!(contents.contains("contentType=\"audio\""));
+    assert!(contents.contains("codecs=\"avc1."));
+    assert!(contents.contains("codecs=\"mp4a.40.2\""));
+    assert!(contents.contains("width=\"320\" height=\"240\""));
+    assert!(contents.contains("initialization=\"video_0_init.mp4\""));
+    assert!(contents.contains("media=\"video_0_segment$Number%05d$.m4s\""));
+    assert!(contents.contains("media=\"audio_0_segment$Number%05d$.m4s\""));
+    assert_eq!(contents.matches("<SegmentTimeline>").count(), 2);
+
+    Ok(())
+}
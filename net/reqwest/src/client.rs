@@ -0,0 +1,608 @@
+// Copyright (C) 2016-2018 Sebastian Dröge <sebastian@centricular.com>
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! HTTP client setup shared by all elements of the plugin.
+
+use std::sync::Mutex;
+use std::time::Duration;
+
+use futures::future;
+use futures::prelude::*;
+use reqwest::header::{self, HeaderMap, HeaderName, HeaderValue};
+use reqwest::{Client, Method, RequestBuilder};
+use tokio::runtime;
+use url::Url;
+
+use once_cell::sync::Lazy;
+
+use gst::glib;
+use gst::prelude::*;
+use gst::{gst_debug, gst_warning};
+
+use crate::session::ReqwestSession;
+
+pub(crate) const DEFAULT_TIMEOUT: u32 = 15;
+pub(crate) const DEFAULT_COMPRESS: bool = false;
+pub(crate) const DEFAULT_KEEP_ALIVE: bool = true;
+
+pub(crate) const REQWEST_CLIENT_CONTEXT: &str = "gst.reqwest.client";
+
+/// The client used by an element for its requests.
+#[derive(Clone, Debug)]
+pub(crate) struct ClientContext(pub(crate) Client);
+
+static CAT: Lazy<gst::DebugCategory> = Lazy::new(|| {
+    gst::DebugCategory::new(
+        "reqwestclient",
+        gst::DebugColorFlags::empty(),
+        Some("Rust HTTP client"),
+    )
+});
+
+pub(crate) static RUNTIME: Lazy<runtime::Runtime> = Lazy::new(|| {
+    runtime::Builder::new_multi_thread()
+        .enable_all()
+        .worker_threads(1)
+        .build()
+        .unwrap()
+});
+
+/// Names of the properties that change the proxy configuration of the client.
+pub(crate) const PROXY_PROPERTIES: &[&str] = &["proxy", "proxy-id", "proxy-pw"];
+
+/// Properties for configuring the HTTP requests of an element.
+pub(crate) fn properties(default_user_agent: &str) -> Vec<glib::ParamSpec> {
+    vec![
+        glib::ParamSpecString::new(
+            "user-agent",
+            "User-Agent",
+            "Value of the User-Agent HTTP request header field",
+            Some(default_user_agent),
+            glib::ParamFlags::READWRITE | gst::PARAM_FLAG_MUTABLE_READY,
+        ),
+        glib::ParamSpecString::new(
+            "user-id",
+            "User-id",
+            "HTTP location URI user id for authentication",
+            None,
+            glib::ParamFlags::READWRITE | gst::PARAM_FLAG_MUTABLE_READY,
+        ),
+        glib::ParamSpecString::new(
+            "user-pw",
+            "User-pw",
+            "HTTP location URI user password for authentication",
+            None,
+            glib::ParamFlags::READWRITE | gst::PARAM_FLAG_MUTABLE_READY,
+        ),
+        glib::ParamSpecUInt::new(
+            "timeout",
+            "Timeout",
+            "Value in seconds to timeout a blocking I/O (0 = No timeout).",
+            0,
+            3600,
+            DEFAULT_TIMEOUT,
+            glib::ParamFlags::READWRITE | gst::PARAM_FLAG_MUTABLE_READY,
+        ),
+        glib::ParamSpecBoolean::new(
+            "compress",
+            "Compress",
+            "Allow compressed content encodings",
+            DEFAULT_COMPRESS,
+            glib::ParamFlags::READWRITE | gst::PARAM_FLAG_MUTABLE_READY,
+        ),
+        glib::ParamSpecBoxed::new(
+            "extra-headers",
+            "Extra Headers",
+            "Extra headers to append to the HTTP request",
+            gst::Structure::static_type(),
+            glib::ParamFlags::READWRITE | gst::PARAM_FLAG_MUTABLE_READY,
+        ),
+        glib::ParamSpecBoxed::new(
+            "cookies",
+            "Cookies",
+            "HTTP request cookies",
+            Vec::<String>::static_type(),
+            glib::ParamFlags::READWRITE | gst::PARAM_FLAG_MUTABLE_READY,
+        ),
+        glib::ParamSpecBoolean::new(
+            "keep-alive",
+            "Keep Alive",
+            "Use HTTP persistent connections",
+            DEFAULT_KEEP_ALIVE,
+            glib::ParamFlags::READWRITE | gst::PARAM_FLAG_MUTABLE_READY,
+        ),
+        glib::ParamSpecString::new(
+            "proxy",
+            "Proxy",
+            "HTTP proxy server URI",
+            Some(""),
+            glib::ParamFlags::READWRITE | gst::PARAM_FLAG_MUTABLE_READY,
+        ),
+        glib::ParamSpecString::new(
+            "proxy-id",
+            "Proxy-id",
+            "HTTP proxy URI user id for authentication",
+            Some(""),
+            glib::ParamFlags::READWRITE | gst::PARAM_FLAG_MUTABLE_READY,
+        ),
+        glib::ParamSpecString::new(
+            "proxy-pw",
+            "Proxy-pw",
+            "HTTP proxy URI user password for authentication",
+            Some(""),
+            glib::ParamFlags::READWRITE | gst::PARAM_FLAG_MUTABLE_READY,
+        ),
+    ]
+}
+
+/// Settings for the HTTP requests of an element.
+#[derive(Debug, Clone)]
+pub(crate) struct ClientSettings {
+    default_user_agent: &'static str,
+    pub(crate) user_agent: String,
+    pub(crate) user_id: Option<String>,
+    pub(crate) user_pw: Option<String>,
+    pub(crate) timeout: u32,
+    pub(crate) compress: bool,
+    pub(crate) extra_headers: Option<gst::Structure>,
+    pub(crate) cookies: Vec<String>,
+    pub(crate) keep_alive: bool,
+    // Notes about souphttpsrc compatibility:
+    // Internal representation of no proxy is None,
+    // but externally Some("").
+    // Default is set from env var 'http_proxy'.
+    // Prepends http:// if not protocol specified.
+    pub(crate) proxy: Option<String>,
+    // Nullable fields that behave normally:
+    pub(crate) proxy_id: Option<String>,
+    pub(crate) proxy_pw: Option<String>,
+}
+
+impl ClientSettings {
+    pub(crate) fn new(default_user_agent: &'static str) -> Self {
+        ClientSettings {
+            default_user_agent,
+            user_agent: default_user_agent.into(),
+            user_id: None,
+            user_pw: None,
+            timeout: DEFAULT_TIMEOUT,
+            compress: DEFAULT_COMPRESS,
+            extra_headers: None,
+            cookies: Vec::new(),
+            keep_alive: DEFAULT_KEEP_ALIVE,
+            proxy: match proxy_from_str(std::env::var("http_proxy").ok()) {
+                Ok(a) => a,
+                Err(_) => None,
+            },
+            proxy_id: None,
+            proxy_pw: None,
+        }
+    }
+
+    /// Whether `name` is one of the properties returned by `properties()`.
+    pub(crate) fn has_property(name: &str) -> bool {
+        matches!(
+            name,
+            "user-agent"
+                | "user-id"
+                | "user-pw"
+                | "timeout"
+                | "compress"
+                | "extra-headers"
+                | "cookies"
+                | "keep-alive"
+                | "proxy"
+                | "proxy-id"
+                | "proxy-pw"
+        )
+    }
+
+    /// Sets one of the properties returned by `properties()`.
+    ///
+    /// Returns `true` if the proxy configuration changed and the client has to be re-created.
+    pub(crate) fn set_property(
+        &mut self,
+        value: &glib::Value,
+        pspec: &glib::ParamSpec,
+    ) -> Result<bool, glib::Error> {
+        match pspec.name() {
+            "user-agent" => {
+                self.user_agent = value
+                    .get::<Option<String>>()
+                    .expect("type checked upstream")
+                    .unwrap_or_else(|| self.default_user_agent.into());
+            }
+            "user-id" => {
+                self.user_id = value.get().expect("type checked upstream");
+            }
+            "user-pw" => {
+                self.user_pw = value.get().expect("type checked upstream");
+            }
+            "timeout" => {
+                self.timeout = value.get().expect("type checked upstream");
+            }
+            "compress" => {
+                self.compress = value.get().expect("type checked upstream");
+            }
+            "extra-headers" => {
+                self.extra_headers = value.get().expect("type checked upstream");
+            }
+            "cookies" => {
+                self.cookies = value.get::<Vec<String>>().expect("type checked upstream");
+            }
+            "keep-alive" => {
+                self.keep_alive = value.get().expect("type checked upstream");
+            }
+            "proxy" => {
+                let proxy = proxy_from_str(
+                    value
+                        .get::<Option<String>>()
+                        .expect("type checked upstream"),
+                )?;
+                return Ok(Self::replace(&mut self.proxy, proxy));
+            }
+            "proxy-id" => {
+                let proxy_id = value
+                    .get::<Option<String>>()
+                    .expect("type checked upstream");
+                return Ok(Self::replace(&mut self.proxy_id, proxy_id));
+            }
+            "proxy-pw" => {
+                let proxy_pw = value
+                    .get::<Option<String>>()
+                    .expect("type checked upstream");
+                return Ok(Self::replace(&mut self.proxy_pw, proxy_pw));
+            }
+            _ => unimplemented!(),
+        }
+
+        Ok(false)
+    }
+
+    fn replace(target: &mut Option<String>, value: Option<String>) -> bool {
+        if *target == value {
+            return false;
+        }
+
+        *target = value;
+        true
+    }
+
+    /// Gets one of the properties returned by `properties()`.
+    pub(crate) fn property(&self, pspec: &glib::ParamSpec) -> glib::Value {
+        match pspec.name() {
+            "user-agent" => self.user_agent.to_value(),
+            "user-id" => self.user_id.to_value(),
+            "user-pw" => self.user_pw.to_value(),
+            "timeout" => self.timeout.to_value(),
+            "compress" => self.compress.to_value(),
+            "extra-headers" => self.extra_headers.to_value(),
+            "cookies" => self.cookies.to_value(),
+            "keep-alive" => self.keep_alive.to_value(),
+            // return None values as Some("") for compatibility with souphttpsrc
+            "proxy" => self.proxy.as_deref().unwrap_or("").to_value(),
+            "proxy-id" => self.proxy_id.to_value(),
+            "proxy-pw" => self.proxy_pw.to_value(),
+            _ => unimplemented!(),
+        }
+    }
+}
+
+pub(crate) fn proxy_from_str(s: Option<String>) -> Result<Option<String>, glib::Error> {
+    match s {
+        None => Ok(None),
+        Some(s) if s.is_empty() => Ok(None),
+        Some(not_empty_str) => {
+            // If no protocol specified, prepend http for compatibility
+            // https://gstreamer.freedesktop.org/documentation/soup/souphttpsrc.html
+            let url_string = if !not_empty_str.contains("://") {
+                format!("http://{}", not_empty_str)
+            } else {
+                not_empty_str
+            };
+            match reqwest::Url::parse(&url_string) {
+                Ok(url) => {
+                    // this may urlencode and add trailing /
+                    Ok(Some(url.to_string()))
+                }
+                Err(err) => Err(glib::Error::new(
+                    gst::URIError::BadUri,
+                    format!("Failed to parse URI '{}': {:?}", url_string, err).as_str(),
+                )),
+            }
+        }
+    }
+}
+
+/// The client of an element, either created by the element itself or taken from a
+/// `ReqwestSession` shared with other elements via a `gst::Context`.
+#[derive(Debug, Default)]
+pub(crate) struct ElementClient {
+    client: Mutex<Option<ClientContext>>,
+    external_session: Mutex<Option<ReqwestSession>>,
+}
+
+impl ElementClient {
+    /// Throws away the client of the element, a new one is created or acquired on the next
+    /// call to `ensure()`.
+    pub(crate) fn reset(&self) {
+        *self.client.lock().unwrap() = None;
+    }
+
+    /// Stores the session of a context set on the element.
+    pub(crate) fn set_context(&self, context: &gst::Context) {
+        if context.context_type() == REQWEST_CLIENT_CONTEXT {
+            let mut external_session = self.external_session.lock().unwrap();
+            let s = context.structure();
+            *external_session = s.get::<ReqwestSession>("session").ok();
+        }
+    }
+
+    /// Returns the client of the element, creating it if necessary.
+    ///
+    /// Elements without a proxy use the session of a context set by the application or acquired
+    /// from other elements via `pad`, and otherwise share a newly created session with other
+    /// elements. Elements with a proxy always use a client of their own.
+    pub(crate) fn ensure(
+        &self,
+        element: &gst::Element,
+        pad: &gst::Pad,
+        settings: &ClientSettings,
+    ) -> Result<ClientContext, gst::ErrorMessage> {
+        let mut client_guard = self.client.lock().unwrap();
+        if let Some(ref client) = *client_guard {
+            gst_debug!(CAT, obj: element, "Using already configured client");
+            return Ok(client.clone());
+        }
+
+        // The shared session never uses a proxy, because proxy is client specific. The
+        // alternative would be different contexts for different proxy settings, or one context
+        // with a map from proxy settings to session, but then, how and when to discard those,
+        // retaining reuse benefits?
+        if let Some(proxy) = &settings.proxy {
+            // Proxy is url-checked on property set but perhaps this might still fail.
+            let mut p = reqwest::Proxy::all(proxy).map_err(|err| {
+                gst::error_msg!(gst::ResourceError::OpenRead, ["Bad proxy URI: {}", err])
+            })?;
+            if let Some(proxy_id) = &settings.proxy_id {
+                let proxy_pw = settings.proxy_pw.as_deref().unwrap_or("");
+                p = p.basic_auth(proxy_id, proxy_pw);
+            }
+
+            gst_debug!(CAT, obj: element, "Creating new client");
+            let client = Client::builder()
+                .cookie_store(true)
+                .gzip(true)
+                .proxy(p)
+                .build()
+                .map_err(|err| {
+                    gst::error_msg!(
+                        gst::ResourceError::OpenRead,
+                        ["Failed to create Client: {}", err]
+                    )
+                })?;
+
+            let client = ClientContext(client);
+            *client_guard = Some(client.clone());
+
+            return Ok(client);
+        }
+
+        // Attempt to acquire an existing session from the application or another element
+        // instance
+        let mut q = gst::query::Context::new(REQWEST_CLIENT_CONTEXT);
+        if pad.peer_query(&mut q) {
+            if let Some(context) = q.context_owned() {
+                element.set_context(&context);
+            }
+        } else {
+            let _ = element.post_message(
+                gst::message::NeedContext::builder(REQWEST_CLIENT_CONTEXT)
+                    .src(element)
+                    .build(),
+            );
+        }
+
+        // Hopefully now, self.set_context will have been synchronously called
+        let external_session = self.external_session.lock().unwrap().clone();
+        let session = match external_session {
+            Some(session) => {
+                gst_debug!(CAT, obj: element, "Using shared session");
+                session
+            }
+            None => {
+                gst_debug!(CAT, obj: element, "Sharing new session with other elements");
+                let session = ReqwestSession::new();
+                let context = session.context();
+                element.set_context(&context);
+                let _ = element.post_message(
+                    gst::message::HaveContext::builder(context)
+                        .src(element)
+                        .build(),
+                );
+
+                session
+            }
+        };
+
+        let client = ClientContext(session.client()?);
+        *client_guard = Some(client.clone());
+
+        Ok(client)
+    }
+}
+
+/// Creates a request with the headers and authentication of the settings.
+pub(crate) fn build_request(
+    element: &gst::Element,
+    client: &ClientContext,
+    method: Method,
+    uri: Url,
+    settings: &ClientSettings,
+) -> RequestBuilder {
+    let req = client
+        .0
+        .request(method, uri)
+        .headers(request_headers(element, settings));
+
+    if let Some(ref user_id) = settings.user_id {
+        // HTTP auth available
+        req.basic_auth(user_id, settings.user_pw.clone())
+    } else {
+        req
+    }
+}
+
+/// Headers for a request with the given settings.
+pub(crate) fn request_headers(element: &gst::Element, settings: &ClientSettings) -> HeaderMap {
+    use headers::{Connection, HeaderMapExt, UserAgent};
+
+    let mut headers = HeaderMap::new();
+
+    if settings.keep_alive {
+        headers.typed_insert(Connection::keep_alive());
+    } else {
+        headers.typed_insert(Connection::close());
+    }
+
+    headers.typed_insert(settings.user_agent.parse::<UserAgent>().unwrap());
+
+    if !settings.compress {
+        // Compression is the default
+        headers.insert(
+            header::ACCEPT_ENCODING,
+            "identity".parse::<HeaderValue>().unwrap(),
+        );
+    };
+
+    if let Some(ref extra_headers) = settings.extra_headers {
+        for (field, value) in extra_headers.iter() {
+            let field = match HeaderName::try_from(field) {
+                Ok(field) => field,
+                Err(err) => {
+                    gst_warning!(
+                        CAT,
+                        obj: element,
+                        "Failed to transform extra-header field name '{}' to header name: {}",
+                        field,
+                        err,
+                    );
+
+                    continue;
+                }
+            };
+
+            let mut append_header = |field: &HeaderName, value: &glib::Value| {
+                let value = match value.transform::<String>() {
+                    Ok(value) => value,
+                    Err(_) => {
+                        gst_warning!(
+                            CAT,
+                            obj: element,
+                            "Failed to transform extra-header '{}' value to string",
+                            field
+                        );
+                        return;
+                    }
+                };
+
+                let value = value.get::<Option<&str>>().unwrap().unwrap_or("");
+
+                let value = match value.parse::<HeaderValue>() {
+                    Ok(value) => value,
+                    Err(_) => {
+                        gst_warning!(
+                            CAT,
+                            obj: element,
+                            "Failed to transform extra-header '{}' value to header value",
+                            field
+                        );
+                        return;
+                    }
+                };
+
+                headers.append(field.clone(), value);
+            };
+
+            if let Ok(values) = value.get::<gst::ArrayRef>() {
+                for value in values.as_slice() {
+                    append_header(&field, value);
+                }
+            } else if let Ok(values) = value.get::<gst::ListRef>() {
+                for value in values.as_slice() {
+                    append_header(&field, value);
+                }
+            } else {
+                append_header(&field, value);
+            }
+        }
+    }
+
+    if !settings.cookies.is_empty() {
+        headers.insert(
+            header::COOKIE,
+            settings.cookies.join("; ").parse::<HeaderValue>().unwrap(),
+        );
+    }
+
+    headers
+}
+
+/// Runs `future` on the runtime and blocks until it finished, timed out after `timeout` seconds
+/// or was aborted via `canceller`.
+///
+/// Returns `Err(None)` if the future was aborted.
+pub(crate) fn wait<F, T>(
+    canceller: &Mutex<Option<future::AbortHandle>>,
+    timeout: u32,
+    future: F,
+) -> Result<T, Option<gst::ErrorMessage>>
+where
+    F: Send + Future<Output = Result<T, gst::ErrorMessage>>,
+    T: Send + 'static,
+{
+    let mut canceller_guard = canceller.lock().unwrap();
+    let (abort_handle, abort_registration) = future::AbortHandle::new_pair();
+    canceller_guard.replace(abort_handle);
+    drop(canceller_guard);
+
+    // Wrap in a timeout
+    let future = async {
+        if timeout == 0 {
+            future.await
+        } else {
+            let res = tokio::time::timeout(Duration::from_secs(timeout.into()), future).await;
+
+            match res {
+                Ok(res) => res,
+                Err(_) => Err(gst::error_msg!(
+                    gst::ResourceError::Read,
+                    ["Request timeout"]
+                )),
+            }
+        }
+    };
+
+    // And make abortable
+    let future = async {
+        match future::Abortable::new(future, abort_registration).await {
+            Ok(res) => res.map_err(Some),
+            Err(_) => Err(None),
+        }
+    };
+
+    let res = {
+        let _enter = RUNTIME.enter();
+        futures::executor::block_on(future)
+    };
+
+    /* Clear out the canceller */
+    let _ = canceller.lock().unwrap().take();
+
+    res
+}
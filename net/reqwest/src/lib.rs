@@ -7,11 +7,26 @@
 // except according to those terms.
 
 use gst::glib;
+use gst::prelude::*;
 
+mod client;
+mod reqwesthlssrc;
+mod reqwesthttpsink;
 mod reqwesthttpsrc;
+mod reqwesticydemux;
+mod session;
+
+pub use session::{HttpVersion, ReqwestSession};
 
 fn plugin_init(plugin: &gst::Plugin) -> Result<(), glib::BoolError> {
-    reqwesthttpsrc::register(plugin)
+    // Register the session type so that applications can create it by name
+    ReqwestSession::static_type();
+
+    reqwesthttpsrc::register(plugin)?;
+    reqwesthlssrc::register(plugin)?;
+    reqwesthttpsink::register(plugin)?;
+    reqwesticydemux::register(plugin)?;
+    Ok(())
 }
 
 gst::plugin_define!(
@@ -0,0 +1,1103 @@
+// Copyright (C) 2026 agent <agent@local>
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+use std::collections::VecDeque;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use futures::channel::oneshot;
+use futures::future;
+use futures::prelude::*;
+use m3u8_rs::{MediaPlaylist, MediaSegment, Playlist};
+use reqwest::{header, Method, RequestBuilder, StatusCode};
+use url::Url;
+
+use once_cell::sync::Lazy;
+
+use gst::glib;
+use gst::prelude::*;
+use gst::subclass::prelude::*;
+use gst::{gst_debug, gst_error, gst_info, gst_log};
+use gst_base::prelude::*;
+use gst_base::subclass::base_src::CreateSuccess;
+use gst_base::subclass::prelude::*;
+
+use crate::client::{self, ClientContext, ClientSettings, ElementClient};
+
+const DEFAULT_LOCATION: Option<Url> = None;
+const DEFAULT_USER_AGENT: &str = concat!(
+    "GStreamer reqwesthlssrc ",
+    env!("CARGO_PKG_VERSION"),
+    "-",
+    env!("COMMIT_ID")
+);
+const DEFAULT_PREFETCH_SEGMENTS: u32 = 2;
+const DEFAULT_CONNECTION_SPEED: u32 = 0;
+const DEFAULT_BITRATE_LIMIT: f64 = 0.8;
+
+/// Number of segments before the end of a live playlist at which playback starts.
+const LIVE_START_SEGMENTS: usize = 3;
+
+/// Weight of the latest measurement in the throughput estimate.
+const THROUGHPUT_WEIGHT: f64 = 0.3;
+
+#[derive(Debug, Clone)]
+struct Settings {
+    location: Option<Url>,
+    prefetch_segments: u32,
+    connection_speed: u32,
+    bitrate_limit: f64,
+    client: ClientSettings,
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Settings {
+            location: DEFAULT_LOCATION,
+            prefetch_segments: DEFAULT_PREFETCH_SEGMENTS,
+            connection_speed: DEFAULT_CONNECTION_SPEED,
+            bitrate_limit: DEFAULT_BITRATE_LIMIT,
+            client: ClientSettings::new(DEFAULT_USER_AGENT),
+        }
+    }
+}
+
+/// A variant stream of a master playlist, or the media playlist itself.
+#[derive(Debug, Clone)]
+struct Variant {
+    uri: Url,
+    bandwidth: u64,
+}
+
+/// A resource referenced by a playlist, or a byte range of it.
+#[derive(Debug, Clone, PartialEq)]
+struct Resource {
+    uri: Url,
+    /// Offset and length
+    byte_range: Option<(u64, u64)>,
+}
+
+/// A downloaded resource.
+#[derive(Debug)]
+struct Download {
+    buffer: gst::Buffer,
+    elapsed: Duration,
+}
+
+/// A segment that is downloaded in the background.
+#[derive(Debug)]
+struct PendingSegment {
+    sequence: u64,
+    discont: bool,
+    download: tokio::task::JoinHandle<Result<Download, gst::ErrorMessage>>,
+}
+
+#[derive(Debug)]
+struct Started {
+    client: ClientContext,
+    /// Sorted by ascending bandwidth
+    variants: Vec<Variant>,
+    variant: usize,
+    playlist: MediaPlaylist,
+    last_refresh: Instant,
+    /// Media sequence number of the next segment to download
+    next_sequence: u64,
+    pending: VecDeque<PendingSegment>,
+    /// Resolves once the last queued download finished. Downloads wait for their predecessor
+    /// so that each of them measures the throughput of the connection on its own.
+    last_done: Option<oneshot::Receiver<()>>,
+    /// Whether the next queued segment is discontinuous to the previous one
+    discont: bool,
+    /// Initialization section of the last queued segment
+    init_section: Option<Resource>,
+    /// Estimated throughput in bits per second
+    throughput: Option<f64>,
+    offset: u64,
+}
+
+#[derive(Debug)]
+enum State {
+    Stopped,
+    Started(Box<Started>),
+}
+
+impl Default for State {
+    fn default() -> Self {
+        State::Stopped
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct ReqwestHlsSrc {
+    client: ElementClient,
+    settings: Mutex<Settings>,
+    state: Mutex<State>,
+    canceller: Mutex<Option<future::AbortHandle>>,
+}
+
+static CAT: Lazy<gst::DebugCategory> = Lazy::new(|| {
+    gst::DebugCategory::new(
+        "reqwesthlssrc",
+        gst::DebugColorFlags::empty(),
+        Some("Rust HLS source"),
+    )
+});
+
+/// Downloads `uri` completely, or only the given offset and length of it.
+async fn fetch(
+    req: RequestBuilder,
+    uri: Url,
+    byte_range: Option<(u64, u64)>,
+) -> Result<Download, gst::ErrorMessage> {
+    let start = Instant::now();
+
+    let req = match byte_range {
+        Some((offset, length)) if length > 0 => req.header(
+            header::RANGE,
+            format!("bytes={}-{}", offset, offset + length - 1),
+        ),
+        _ => req,
+    };
+
+    let res = req.send().await.map_err(|err| {
+        gst::error_msg!(
+            gst::ResourceError::OpenRead,
+            ["Failed to fetch {}: {:?}", uri, err]
+        )
+    })?;
+
+    match res.status() {
+        status if status.is_success() => (),
+        StatusCode::NOT_FOUND => {
+            return Err(gst::error_msg!(
+                gst::ResourceError::NotFound,
+                ["Resource '{}' not found", uri]
+            ));
+        }
+        StatusCode::UNAUTHORIZED
+        | StatusCode::PAYMENT_REQUIRED
+        | StatusCode::FORBIDDEN
+        | StatusCode::PROXY_AUTHENTICATION_REQUIRED => {
+            return Err(gst::error_msg!(
+                gst::ResourceError::NotAuthorized,
+                ["Not Authorized for resource '{}': {}", uri, res.status()]
+            ));
+        }
+        status => {
+            return Err(gst::error_msg!(
+                gst::ResourceError::OpenRead,
+                ["Request for '{}' failed: {}", uri, status]
+            ));
+        }
+    }
+
+    let partial = res.status() == StatusCode::PARTIAL_CONTENT;
+    let mut data = res.bytes().await.map_err(|err| {
+        gst::error_msg!(
+            gst::ResourceError::Read,
+            ["Failed to read {}: {:?}", uri, err]
+        )
+    })?;
+
+    // Servers without support for range requests return the whole resource
+    if let Some((offset, length)) = byte_range.filter(|_| !partial) {
+        let end = offset + length;
+        if end > data.len() as u64 {
+            return Err(gst::error_msg!(
+                gst::ResourceError::Read,
+                ["Byte range {}-{} is outside of {}", offset, end, uri]
+            ));
+        }
+        data = data.slice(offset as usize..end as usize);
+    }
+
+    Ok(Download {
+        buffer: gst::Buffer::from_slice(data),
+        elapsed: start.elapsed(),
+    })
+}
+
+/// Offset and length of the byte range of the segment at `idx`, if any.
+///
+/// Without an explicit offset, the range starts after the one of the previous segment.
+fn segment_byte_range(segments: &[MediaSegment], idx: usize) -> Option<(u64, u64)> {
+    let byte_range = segments[idx].byte_range.as_ref()?;
+    let offset = match byte_range.offset {
+        Some(offset) => offset as u64,
+        None => idx
+            .checked_sub(1)
+            .filter(|&previous| segments[previous].uri == segments[idx].uri)
+            .and_then(|previous| segment_byte_range(segments, previous))
+            .map_or(0, |(offset, length)| offset + length),
+    };
+
+    Some((offset, byte_range.length as u64))
+}
+
+/// The initialization section (`EXT-X-MAP`) that applies to the segment at `idx`, if any.
+fn init_section(
+    base: &Url,
+    segments: &[MediaSegment],
+    idx: usize,
+) -> Result<Option<Resource>, gst::ErrorMessage> {
+    let map = match segments[..=idx]
+        .iter()
+        .rev()
+        .find_map(|segment| segment.map.as_ref())
+    {
+        Some(map) => map,
+        None => return Ok(None),
+    };
+
+    let uri = base.join(&map.uri).map_err(|err| {
+        gst::error_msg!(
+            gst::StreamError::Format,
+            [
+                "Invalid initialization section URI '{}': {:?}",
+                map.uri,
+                err
+            ]
+        )
+    })?;
+    let byte_range = map.byte_range.as_ref().map(|byte_range| {
+        (
+            byte_range.offset.unwrap_or(0) as u64,
+            byte_range.length as u64,
+        )
+    });
+
+    Ok(Some(Resource { uri, byte_range }))
+}
+
+/// Index of the variant with the highest bandwidth below `bandwidth`, or the lowest variant.
+fn select_variant(variants: &[Variant], bandwidth: f64) -> usize {
+    variants
+        .iter()
+        .rposition(|variant| variant.bandwidth as f64 <= bandwidth)
+        .unwrap_or(0)
+}
+
+impl ReqwestHlsSrc {
+    fn set_location(&self, uri: Option<&str>) -> Result<(), glib::Error> {
+        let state = self.state.lock().unwrap();
+        if let State::Started { .. } = *state {
+            return Err(glib::Error::new(
+                gst::URIError::BadState,
+                "Changing the `location` property on a started `reqwesthlssrc` is not supported",
+            ));
+        }
+
+        let mut settings = self.settings.lock().unwrap();
+
+        let uri = match uri {
+            Some(uri) => uri,
+            None => {
+                settings.location = DEFAULT_LOCATION;
+                return Ok(());
+            }
+        };
+
+        let uri = Url::parse(uri).map_err(|err| {
+            glib::Error::new(
+                gst::URIError::BadUri,
+                format!("Failed to parse URI '{}': {:?}", uri, err).as_str(),
+            )
+        })?;
+
+        if uri.scheme() != "http" && uri.scheme() != "https" {
+            return Err(glib::Error::new(
+                gst::URIError::UnsupportedProtocol,
+                format!("Unsupported URI scheme '{}'", uri.scheme()).as_str(),
+            ));
+        }
+
+        settings.location = Some(uri);
+
+        Ok(())
+    }
+
+    /// Set a client property and perform necessary state checks and modifications to client.
+    fn set_client_prop(
+        &self,
+        value: &glib::Value,
+        pspec: &glib::ParamSpec,
+    ) -> Result<(), glib::Error> {
+        // Proxy props can only be changed when not started.
+        let state = self.state.lock().unwrap();
+        if client::PROXY_PROPERTIES.contains(&pspec.name()) {
+            if let State::Started { .. } = *state {
+                return Err(glib::Error::new(
+                    gst::URIError::BadState,
+                    &format!(
+                        "Changing the `{}` property on a started `reqwesthlssrc` is not supported",
+                        pspec.name()
+                    ),
+                ));
+            }
+        }
+
+        let mut settings = self.settings.lock().unwrap();
+        if settings.client.set_property(value, pspec)? {
+            self.client.reset();
+        }
+
+        Ok(())
+    }
+
+    fn wait<F, T>(&self, timeout: u32, future: F) -> Result<T, Option<gst::ErrorMessage>>
+    where
+        F: Send + Future<Output = Result<T, gst::ErrorMessage>>,
+        T: Send + 'static,
+    {
+        client::wait(&self.canceller, timeout, future)
+    }
+
+    fn fetch_playlist(
+        &self,
+        src: &super::ReqwestHlsSrc,
+        client: &ClientContext,
+        settings: &Settings,
+        uri: &Url,
+    ) -> Result<Playlist, Option<gst::ErrorMessage>> {
+        gst_debug!(CAT, obj: src, "Fetching playlist {}", uri);
+
+        let req = client::build_request(
+            src.upcast_ref(),
+            client,
+            Method::GET,
+            uri.clone(),
+            &settings.client,
+        );
+        let download = self.wait(settings.client.timeout, fetch(req, uri.clone(), None))?;
+
+        let map = download.buffer.map_readable().unwrap();
+        m3u8_rs::parse_playlist_res(&map).map_err(|_| {
+            Some(gst::error_msg!(
+                gst::StreamError::Format,
+                ["Failed to parse playlist {}", uri]
+            ))
+        })
+    }
+
+    fn fetch_media_playlist(
+        &self,
+        src: &super::ReqwestHlsSrc,
+        client: &ClientContext,
+        settings: &Settings,
+        uri: &Url,
+    ) -> Result<MediaPlaylist, Option<gst::ErrorMessage>> {
+        match self.fetch_playlist(src, client, settings, uri)? {
+            Playlist::MediaPlaylist(playlist) => Ok(playlist),
+            Playlist::MasterPlaylist(_) => Err(Some(gst::error_msg!(
+                gst::StreamError::Format,
+                ["Expected a media playlist at {}", uri]
+            ))),
+        }
+    }
+
+    /// Bandwidth in bits per second that the selected variant may use.
+    fn available_bandwidth(&self, started: &Started, settings: &Settings) -> f64 {
+        if settings.connection_speed != 0 {
+            settings.connection_speed as f64 * 1000.0
+        } else {
+            started.throughput.unwrap_or(0.0) * settings.bitrate_limit
+        }
+    }
+
+    fn open(
+        &self,
+        src: &super::ReqwestHlsSrc,
+        uri: Url,
+        settings: &Settings,
+    ) -> Result<Started, Option<gst::ErrorMessage>> {
+        let client = self.client.ensure(
+            src.upcast_ref(),
+            &src.static_pad("src").unwrap(),
+            &settings.client,
+        )?;
+
+        let (variants, playlist) = match self.fetch_playlist(src, &client, settings, &uri)? {
+            Playlist::MediaPlaylist(playlist) => {
+                (vec![Variant { uri, bandwidth: 0 }], Some(playlist))
+            }
+            Playlist::MasterPlaylist(master) => {
+                let mut variants = master
+                    .variants
+                    .iter()
+                    .filter(|variant| !variant.is_i_frame)
+                    .map(|variant| {
+                        let variant_uri = uri.join(&variant.uri).map_err(|err| {
+                            gst::error_msg!(
+                                gst::StreamError::Format,
+                                ["Invalid variant URI '{}': {:?}", variant.uri, err]
+                            )
+                        })?;
+
+                        Ok::<_, gst::ErrorMessage>(Variant {
+                            uri: variant_uri,
+                            bandwidth: variant.bandwidth.parse().unwrap_or(0),
+                        })
+                    })
+                    .collect::<Result<Vec<_>, _>>()?;
+
+                if variants.is_empty() {
+                    return Err(Some(gst::error_msg!(
+                        gst::StreamError::Format,
+                        ["Master playlist {} contains no variants", uri]
+                    )));
+                }
+
+                variants.sort_by_key(|variant| variant.bandwidth);
+
+                (variants, None)
+            }
+        };
+
+        let variant = select_variant(&variants, settings.connection_speed as f64 * 1000.0);
+        let playlist = match playlist {
+            Some(playlist) => playlist,
+            None => self.fetch_media_playlist(src, &client, settings, &variants[variant].uri)?,
+        };
+
+        gst_info!(
+            CAT,
+            obj: src,
+            "Starting with variant {} of {} bits/s",
+            variants[variant].uri,
+            variants[variant].bandwidth
+        );
+
+        // Live streams start close to the end of the playlist
+        let first_segment = if playlist.end_list {
+            0
+        } else {
+            playlist.segments.len().saturating_sub(LIVE_START_SEGMENTS)
+        };
+
+        Ok(Started {
+            client,
+            variants,
+            variant,
+            next_sequence: playlist.media_sequence as u64 + first_segment as u64,
+            playlist,
+            last_refresh: Instant::now(),
+            pending: VecDeque::new(),
+            last_done: None,
+            discont: true,
+            init_section: None,
+            throughput: None,
+            offset: 0,
+        })
+    }
+
+    /// Returns the variant to switch to if the current one doesn't fit the available bandwidth.
+    fn variant_switch(
+        &self,
+        src: &super::ReqwestHlsSrc,
+        started: &Started,
+        settings: &Settings,
+    ) -> Option<usize> {
+        if started.variants.len() < 2 {
+            return None;
+        }
+
+        let bandwidth = self.available_bandwidth(started, settings);
+        let variant = select_variant(&started.variants, bandwidth);
+        if variant == started.variant {
+            return None;
+        }
+
+        gst_info!(
+            CAT,
+            obj: src,
+            "Switching to variant {} of {} bits/s for available bandwidth of {} bits/s",
+            started.variants[variant].uri,
+            started.variants[variant].bandwidth,
+            bandwidth as u64,
+        );
+
+        Some(variant)
+    }
+
+    /// Fetches the media playlist of the given variant and switches to it.
+    ///
+    /// The state is not locked while the playlist is fetched.
+    fn update_playlist(
+        &self,
+        src: &super::ReqwestHlsSrc,
+        settings: &Settings,
+        variant: usize,
+    ) -> Result<(), Option<gst::ErrorMessage>> {
+        let (client, uri) = match *self.state.lock().unwrap() {
+            State::Started(ref started) => (
+                started.client.clone(),
+                started.variants[variant].uri.clone(),
+            ),
+            State::Stopped => return Err(None),
+        };
+
+        let playlist = self.fetch_media_playlist(src, &client, settings, &uri)?;
+
+        let mut state = self.state.lock().unwrap();
+        let started = match *state {
+            State::Started(ref mut started) => started,
+            State::Stopped => return Err(None),
+        };
+
+        started.playlist = playlist;
+        started.last_refresh = Instant::now();
+        if variant != started.variant {
+            started.variant = variant;
+            started.discont = true;
+        }
+
+        Ok(())
+    }
+
+    /// Starts downloading segments until `count` segments are queued.
+    ///
+    /// Returns the variant whose media playlist has to be fetched before more segments can be
+    /// queued.
+    fn queue_segments(
+        &self,
+        src: &super::ReqwestHlsSrc,
+        started: &mut Started,
+        settings: &Settings,
+        count: usize,
+    ) -> Result<Option<usize>, Option<gst::ErrorMessage>> {
+        if let Some(variant) = self.variant_switch(src, started, settings) {
+            return Ok(Some(variant));
+        }
+
+        while started.pending.len() < count {
+            let media_sequence = started.playlist.media_sequence as u64;
+            if started.next_sequence < media_sequence {
+                gst_info!(
+                    CAT,
+                    obj: src,
+                    "Segment {} dropped out of the playlist, continuing with {}",
+                    started.next_sequence,
+                    media_sequence
+                );
+                started.next_sequence = media_sequence;
+                started.discont = true;
+            }
+
+            let idx = (started.next_sequence - media_sequence) as usize;
+            let segment = match started.playlist.segments.get(idx) {
+                Some(segment) => segment,
+                None if started.playlist.end_list => break,
+                None => {
+                    // Reload live playlists at most every half target duration
+                    let refresh_interval =
+                        Duration::from_secs_f32(started.playlist.target_duration / 2.0);
+                    if started.last_refresh.elapsed() < refresh_interval {
+                        break;
+                    }
+
+                    gst_debug!(CAT, obj: src, "Refreshing playlist");
+                    return Ok(Some(started.variant));
+                }
+            };
+
+            if let Some(ref key) = segment.key {
+                if key.method != "NONE" {
+                    return Err(Some(gst::error_msg!(
+                        gst::StreamError::DecryptNokey,
+                        ["Encrypted segments ({}) are not supported", key.method]
+                    )));
+                }
+            }
+
+            let base = &started.variants[started.variant].uri;
+            let uri = base.join(&segment.uri).map_err(|err| {
+                gst::error_msg!(
+                    gst::StreamError::Format,
+                    ["Invalid segment URI '{}': {:?}", segment.uri, err]
+                )
+            })?;
+            let byte_range = segment_byte_range(&started.playlist.segments, idx);
+
+            // A new initialization section is output before the first segment that uses it
+            let init_section = init_section(base, &started.playlist.segments, idx)?;
+            let init = if init_section != started.init_section {
+                started.init_section = init_section.clone();
+                init_section
+            } else {
+                None
+            };
+
+            gst_debug!(
+                CAT,
+                obj: src,
+                "Queueing segment {} from {} with byte range {:?}",
+                started.next_sequence,
+                uri,
+                byte_range,
+            );
+
+            let init = init.map(|init| {
+                gst_debug!(
+                    CAT,
+                    obj: src,
+                    "Queueing initialization section from {} with byte range {:?}",
+                    init.uri,
+                    init.byte_range,
+                );
+
+                let req = client::build_request(
+                    src.upcast_ref(),
+                    &started.client,
+                    Method::GET,
+                    init.uri.clone(),
+                    &settings.client,
+                );
+                (req, init)
+            });
+            let req = client::build_request(
+                src.upcast_ref(),
+                &started.client,
+                Method::GET,
+                uri.clone(),
+                &settings.client,
+            );
+            let previous_done = started.last_done.take();
+            let (done_sender, done_receiver) = oneshot::channel();
+            started.last_done = Some(done_receiver);
+
+            let download = client::RUNTIME.spawn(async move {
+                if let Some(previous_done) = previous_done {
+                    let _ = previous_done.await;
+                }
+
+                let res = async {
+                    let download = match init {
+                        Some((init_req, init)) => {
+                            let init = fetch(init_req, init.uri, init.byte_range).await?;
+                            let segment = fetch(req, uri, byte_range).await?;
+                            Download {
+                                buffer: init.buffer.append(segment.buffer),
+                                elapsed: init.elapsed + segment.elapsed,
+                            }
+                        }
+                        None => fetch(req, uri, byte_range).await?,
+                    };
+
+                    Ok::<_, gst::ErrorMessage>(download)
+                }
+                .await;
+                let _ = done_sender.send(());
+
+                res
+            });
+
+            started.pending.push_back(PendingSegment {
+                sequence: started.next_sequence,
+                discont: started.discont || segment.discontinuity,
+                download,
+            });
+            started.discont = false;
+            started.next_sequence += 1;
+        }
+
+        Ok(None)
+    }
+
+    /// Waits until the next segment is due for downloading, returns `None` at the end of the
+    /// stream.
+    fn next_segment(
+        &self,
+        src: &super::ReqwestHlsSrc,
+        settings: &Settings,
+    ) -> Result<Option<PendingSegment>, Option<gst::ErrorMessage>> {
+        loop {
+            let mut state = self.state.lock().unwrap();
+            let started = match *state {
+                State::Started(ref mut started) => started,
+                State::Stopped => {
+                    return Err(Some(gst::error_msg!(
+                        gst::LibraryError::Failed,
+                        ["Not started yet"]
+                    )));
+                }
+            };
+
+            if let Some(variant) = self.queue_segments(src, started, settings, 1)? {
+                drop(state);
+                self.update_playlist(src, settings, variant)?;
+                continue;
+            }
+
+            if let Some(segment) = started.pending.pop_front() {
+                // Download the configured number of segments ahead of the current one
+                let prefetch_segments = settings.prefetch_segments as usize;
+                if let Some(variant) =
+                    self.queue_segments(src, started, settings, prefetch_segments)?
+                {
+                    drop(state);
+                    if let Err(err) = self.update_playlist(src, settings, variant) {
+                        if let State::Started(ref mut started) = *self.state.lock().unwrap() {
+                            started.pending.push_front(segment);
+                        }
+                        return Err(err);
+                    }
+                }
+
+                return Ok(Some(segment));
+            }
+
+            if started.playlist.end_list {
+                return Ok(None);
+            }
+
+            // Wait for the live playlist to be updated
+            let refresh_interval = Duration::from_secs_f32(started.playlist.target_duration / 2.0);
+            let delay = refresh_interval.saturating_sub(started.last_refresh.elapsed());
+            drop(state);
+
+            gst_log!(CAT, obj: src, "Waiting {:?} for new segments", delay);
+            self.wait(0, async {
+                tokio::time::sleep(delay).await;
+                Ok(())
+            })?;
+        }
+    }
+}
+
+impl ObjectImpl for ReqwestHlsSrc {
+    fn properties() -> &'static [glib::ParamSpec] {
+        static PROPERTIES: Lazy<Vec<glib::ParamSpec>> = Lazy::new(|| {
+            let mut properties = vec![
+                glib::ParamSpecString::new(
+                    "location",
+                    "Location",
+                    "URL of the HLS master or media playlist",
+                    None,
+                    glib::ParamFlags::READWRITE | gst::PARAM_FLAG_MUTABLE_READY,
+                ),
+                glib::ParamSpecUInt::new(
+                    "prefetch-segments",
+                    "Prefetch Segments",
+                    "Number of segments to download ahead of the current one",
+                    0,
+                    16,
+                    DEFAULT_PREFETCH_SEGMENTS,
+                    glib::ParamFlags::READWRITE | gst::PARAM_FLAG_MUTABLE_READY,
+                ),
+                glib::ParamSpecUInt::new(
+                    "connection-speed",
+                    "Connection Speed",
+                    "Network connection speed in kbps used for selecting variants (0 = measure)",
+                    0,
+                    u32::MAX / 1000,
+                    DEFAULT_CONNECTION_SPEED,
+                    glib::ParamFlags::READWRITE | gst::PARAM_FLAG_MUTABLE_PLAYING,
+                ),
+                glib::ParamSpecDouble::new(
+                    "bitrate-limit",
+                    "Bitrate Limit",
+                    "Fraction of the measured throughput that the selected variant may use",
+                    0.0,
+                    1.0,
+                    DEFAULT_BITRATE_LIMIT,
+                    glib::ParamFlags::READWRITE | gst::PARAM_FLAG_MUTABLE_PLAYING,
+                ),
+            ];
+            properties.extend(client::properties(DEFAULT_USER_AGENT));
+
+            properties
+        });
+
+        PROPERTIES.as_ref()
+    }
+
+    fn set_property(
+        &self,
+        obj: &Self::Type,
+        _id: usize,
+        value: &glib::Value,
+        pspec: &glib::ParamSpec,
+    ) {
+        let res = match pspec.name() {
+            "location" => {
+                let location = value.get::<Option<&str>>().expect("type checked upstream");
+                self.set_location(location)
+            }
+            "prefetch-segments" => {
+                let mut settings = self.settings.lock().unwrap();
+                settings.prefetch_segments = value.get().expect("type checked upstream");
+                Ok(())
+            }
+            "connection-speed" => {
+                let mut settings = self.settings.lock().unwrap();
+                settings.connection_speed = value.get().expect("type checked upstream");
+                Ok(())
+            }
+            "bitrate-limit" => {
+                let mut settings = self.settings.lock().unwrap();
+                settings.bitrate_limit = value.get().expect("type checked upstream");
+                Ok(())
+            }
+            name if ClientSettings::has_property(name) => self.set_client_prop(value, pspec),
+            _ => unimplemented!(),
+        };
+
+        if let Err(err) = res {
+            gst_error!(
+                CAT,
+                obj: obj,
+                "Failed to set property `{}`: {:?}",
+                pspec.name(),
+                err
+            );
+        }
+    }
+
+    fn property(&self, _obj: &Self::Type, _id: usize, pspec: &glib::ParamSpec) -> glib::Value {
+        let settings = self.settings.lock().unwrap();
+
+        match pspec.name() {
+            "location" => settings.location.as_ref().map(Url::to_string).to_value(),
+            "prefetch-segments" => settings.prefetch_segments.to_value(),
+            "connection-speed" => settings.connection_speed.to_value(),
+            "bitrate-limit" => settings.bitrate_limit.to_value(),
+            name if ClientSettings::has_property(name) => settings.client.property(pspec),
+            _ => unimplemented!(),
+        }
+    }
+
+    fn constructed(&self, obj: &Self::Type) {
+        self.parent_constructed(obj);
+        obj.set_automatic_eos(false);
+        obj.set_format(gst::Format::Bytes);
+    }
+}
+
+impl GstObjectImpl for ReqwestHlsSrc {}
+
+impl ElementImpl for ReqwestHlsSrc {
+    fn metadata() -> Option<&'static gst::subclass::ElementMetadata> {
+        static ELEMENT_METADATA: Lazy<gst::subclass::ElementMetadata> = Lazy::new(|| {
+            gst::subclass::ElementMetadata::new(
+                "HLS Source",
+                "Source/Network/HTTP",
+                "Read the segments of an HLS stream, adapting the variant to the network throughput",
+                "Sebastian Dröge <sebastian@centricular.com>",
+            )
+        });
+
+        Some(&*ELEMENT_METADATA)
+    }
+
+    fn pad_templates() -> &'static [gst::PadTemplate] {
+        static PAD_TEMPLATES: Lazy<Vec<gst::PadTemplate>> = Lazy::new(|| {
+            let caps = gst::Caps::new_any();
+            let src_pad_template = gst::PadTemplate::new(
+                "src",
+                gst::PadDirection::Src,
+                gst::PadPresence::Always,
+                &caps,
+            )
+            .unwrap();
+
+            vec![src_pad_template]
+        });
+
+        PAD_TEMPLATES.as_ref()
+    }
+
+    fn set_context(&self, element: &Self::Type, context: &gst::Context) {
+        self.client.set_context(context);
+
+        self.parent_set_context(element, context);
+    }
+
+    fn change_state(
+        &self,
+        element: &Self::Type,
+        transition: gst::StateChange,
+    ) -> Result<gst::StateChangeSuccess, gst::StateChangeError> {
+        if let gst::StateChange::ReadyToNull = transition {
+            self.client.reset();
+        }
+
+        self.parent_change_state(element, transition)
+    }
+}
+
+impl BaseSrcImpl for ReqwestHlsSrc {
+    fn is_seekable(&self, _src: &Self::Type) -> bool {
+        false
+    }
+
+    fn unlock(&self, _src: &Self::Type) -> Result<(), gst::ErrorMessage> {
+        let canceller = self.canceller.lock().unwrap();
+        if let Some(ref canceller) = *canceller {
+            canceller.abort();
+        }
+        Ok(())
+    }
+
+    fn start(&self, src: &Self::Type) -> Result<(), gst::ErrorMessage> {
+        *self.state.lock().unwrap() = State::Stopped;
+
+        let settings = self.settings.lock().unwrap().clone();
+        let uri = settings.location.clone().ok_or_else(|| {
+            gst::error_msg!(gst::CoreError::StateChange, ["Can't start without an URI"])
+        })?;
+
+        gst_debug!(CAT, obj: src, "Starting for URI {}", uri);
+
+        let started = self.open(src, uri, &settings).map_err(|err| {
+            err.unwrap_or_else(|| {
+                gst::error_msg!(gst::LibraryError::Failed, ["Interrupted during start"])
+            })
+        })?;
+        *self.state.lock().unwrap() = State::Started(Box::new(started));
+
+        Ok(())
+    }
+
+    fn stop(&self, src: &Self::Type) -> Result<(), gst::ErrorMessage> {
+        gst_debug!(CAT, obj: src, "Stopping");
+
+        if let State::Started(started) =
+            std::mem::replace(&mut *self.state.lock().unwrap(), State::Stopped)
+        {
+            for segment in started.pending {
+                segment.download.abort();
+            }
+        }
+
+        Ok(())
+    }
+
+    fn query(&self, element: &Self::Type, query: &mut gst::QueryRef) -> bool {
+        use gst::QueryView;
+
+        match query.view_mut() {
+            QueryView::Scheduling(ref mut q) => {
+                q.set(
+                    gst::SchedulingFlags::SEQUENTIAL | gst::SchedulingFlags::BANDWIDTH_LIMITED,
+                    1,
+                    -1,
+                    0,
+                );
+                q.add_scheduling_modes(&[gst::PadMode::Push]);
+                true
+            }
+            _ => BaseSrcImplExt::parent_query(self, element, query),
+        }
+    }
+}
+
+impl PushSrcImpl for ReqwestHlsSrc {
+    fn create(
+        &self,
+        src: &Self::Type,
+        _buffer: Option<&mut gst::BufferRef>,
+    ) -> Result<CreateSuccess, gst::FlowError> {
+        let settings = self.settings.lock().unwrap().clone();
+
+        let mut segment = match self.next_segment(src, &settings) {
+            Ok(Some(segment)) => segment,
+            Ok(None) => {
+                gst_debug!(CAT, obj: src, "End of stream");
+                return Err(gst::FlowError::Eos);
+            }
+            Err(Some(err)) => {
+                gst_debug!(CAT, obj: src, "Error {:?}", err);
+                src.post_error_message(err);
+                return Err(gst::FlowError::Error);
+            }
+            Err(None) => {
+                gst_debug!(CAT, obj: src, "Flushing");
+                return Err(gst::FlowError::Flushing);
+            }
+        };
+
+        let sequence = segment.sequence;
+        let res = self.wait(settings.client.timeout, async {
+            (&mut segment.download).await.map_err(|err| {
+                gst::error_msg!(
+                    gst::LibraryError::Failed,
+                    ["Download of segment {} failed: {}", sequence, err]
+                )
+            })?
+        });
+
+        let mut state = self.state.lock().unwrap();
+        let started = match *state {
+            State::Started(ref mut started) => started,
+            State::Stopped => {
+                segment.download.abort();
+                gst::element_error!(src, gst::LibraryError::Failed, ["Not started yet"]);
+
+                return Err(gst::FlowError::Error);
+            }
+        };
+
+        let Download {
+            mut buffer,
+            elapsed,
+        } = match res {
+            Ok(download) => download,
+            Err(Some(err)) => {
+                gst_debug!(CAT, obj: src, "Error {:?}", err);
+                src.post_error_message(err);
+                return Err(gst::FlowError::Error);
+            }
+            Err(None) => {
+                // Keep the download around for when we continue
+                gst_debug!(CAT, obj: src, "Flushing");
+                started.pending.push_front(segment);
+                return Err(gst::FlowError::Flushing);
+            }
+        };
+
+        let size = buffer.size() as u64;
+        if !elapsed.is_zero() {
+            let throughput = size as f64 * 8.0 / elapsed.as_secs_f64();
+            started.throughput = Some(match started.throughput {
+                Some(estimate) => {
+                    estimate * (1.0 - THROUGHPUT_WEIGHT) + throughput * THROUGHPUT_WEIGHT
+                }
+                None => throughput,
+            });
+        }
+
+        gst_debug!(
+            CAT,
+            obj: src,
+            "Segment {} of {} bytes downloaded in {:?}, estimated throughput {:?} bits/s",
+            sequence,
+            size,
+            elapsed,
+            started.throughput.map(|throughput| throughput as u64),
+        );
+
+        {
+            let buffer = buffer.get_mut().unwrap();
+            buffer.set_offset(started.offset);
+            buffer.set_offset_end(started.offset + size);
+            if segment.discont {
+                buffer.set_flags(gst::BufferFlags::DISCONT);
+            }
+        }
+        started.offset += size;
+
+        Ok(CreateSuccess::NewBuffer(buffer))
+    }
+}
+
+#[glib::object_subclass]
+impl ObjectSubclass for ReqwestHlsSrc {
+    const NAME: &'static str = "ReqwestHlsSrc";
+    type Type = super::ReqwestHlsSrc;
+    type ParentType = gst_base::PushSrc;
+}
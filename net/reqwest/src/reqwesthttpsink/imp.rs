@@ -0,0 +1,724 @@
+// Copyright (C) 2026 agent <agent@local>
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+use std::io;
+use std::sync::Mutex;
+
+use futures::channel::mpsc;
+use futures::future;
+use futures::prelude::*;
+use reqwest::{Method, RequestBuilder, StatusCode};
+use url::Url;
+
+use once_cell::sync::Lazy;
+
+use gst::glib;
+use gst::prelude::*;
+use gst::subclass::prelude::*;
+use gst::{gst_debug, gst_error, gst_info, gst_trace};
+use gst_base::subclass::prelude::*;
+
+use super::{FragmentMode, UploadMethod};
+use crate::client::{self, ClientContext, ClientSettings, ElementClient};
+
+const DEFAULT_LOCATION: Option<Url> = None;
+const DEFAULT_USER_AGENT: &str = concat!(
+    "GStreamer reqwesthttpsink ",
+    env!("CARGO_PKG_VERSION"),
+    "-",
+    env!("COMMIT_ID")
+);
+const DEFAULT_METHOD: UploadMethod = UploadMethod::Post;
+const DEFAULT_FRAGMENT_MODE: FragmentMode = FragmentMode::None;
+
+/// Number of buffers queued for the request body before rendering blocks.
+const BODY_QUEUE_SIZE: usize = 4;
+
+const SIGNAL_GET_LOCATION: &str = "get-location";
+
+#[derive(Debug, Clone)]
+struct Settings {
+    location: Option<Url>,
+    method: UploadMethod,
+    fragment_mode: FragmentMode,
+    client: ClientSettings,
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Settings {
+            location: DEFAULT_LOCATION,
+            method: DEFAULT_METHOD,
+            fragment_mode: DEFAULT_FRAGMENT_MODE,
+            client: ClientSettings::new(DEFAULT_USER_AGENT),
+        }
+    }
+}
+
+/// A request whose body is streamed from the rendered buffers.
+#[derive(Debug)]
+struct Upload {
+    uri: Url,
+    body: mpsc::Sender<Result<Vec<u8>, io::Error>>,
+    response: tokio::task::JoinHandle<Result<(), gst::ErrorMessage>>,
+}
+
+#[derive(Debug)]
+enum State {
+    Stopped,
+    Started {
+        client: ClientContext,
+        upload: Option<Upload>,
+        fragment_index: u32,
+    },
+}
+
+impl Default for State {
+    fn default() -> Self {
+        State::Stopped
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct ReqwestHttpSink {
+    client: ElementClient,
+    settings: Mutex<Settings>,
+    state: Mutex<State>,
+    canceller: Mutex<Option<future::AbortHandle>>,
+}
+
+static CAT: Lazy<gst::DebugCategory> = Lazy::new(|| {
+    gst::DebugCategory::new(
+        "reqwesthttpsink",
+        gst::DebugColorFlags::empty(),
+        Some("Rust HTTP sink"),
+    )
+});
+
+/// Sends the request and checks the response once the body is complete.
+async fn send(req: RequestBuilder, uri: Url) -> Result<(), gst::ErrorMessage> {
+    let res = req.send().await.map_err(|err| {
+        gst::error_msg!(
+            gst::ResourceError::Write,
+            ["Failed to upload to {}: {:?}", uri, err]
+        )
+    })?;
+
+    match res.status() {
+        status if status.is_success() => Ok(()),
+        StatusCode::NOT_FOUND => Err(gst::error_msg!(
+            gst::ResourceError::NotFound,
+            ["Resource '{}' not found", uri]
+        )),
+        StatusCode::UNAUTHORIZED
+        | StatusCode::PAYMENT_REQUIRED
+        | StatusCode::FORBIDDEN
+        | StatusCode::PROXY_AUTHENTICATION_REQUIRED => Err(gst::error_msg!(
+            gst::ResourceError::NotAuthorized,
+            ["Not Authorized for resource '{}': {}", uri, res.status()]
+        )),
+        status => Err(gst::error_msg!(
+            gst::ResourceError::Write,
+            ["Upload to '{}' failed: {}", uri, status]
+        )),
+    }
+}
+
+/// Replaces the first `%d` or `%0<width>d` in `template` by `index`.
+fn format_location(template: &str, index: u32) -> String {
+    for (start, _) in template.match_indices('%') {
+        let rest = &template[start + 1..];
+        let digits = rest.bytes().take_while(u8::is_ascii_digit).count();
+        if rest[digits..].starts_with('d') {
+            let width = rest[..digits].parse::<usize>().unwrap_or(0);
+            return format!(
+                "{}{:0width$}{}",
+                &template[..start],
+                index,
+                &rest[digits + 1..],
+                width = width
+            );
+        }
+    }
+
+    template.to_string()
+}
+
+impl ReqwestHttpSink {
+    fn set_location(&self, uri: Option<&str>) -> Result<(), glib::Error> {
+        let state = self.state.lock().unwrap();
+        if let State::Started { .. } = *state {
+            return Err(glib::Error::new(
+                gst::URIError::BadState,
+                "Changing the `location` property on a started `reqwesthttpsink` is not supported",
+            ));
+        }
+
+        let mut settings = self.settings.lock().unwrap();
+
+        let uri = match uri {
+            Some(uri) => uri,
+            None => {
+                settings.location = DEFAULT_LOCATION;
+                return Ok(());
+            }
+        };
+
+        let uri = Url::parse(uri).map_err(|err| {
+            glib::Error::new(
+                gst::URIError::BadUri,
+                format!("Failed to parse URI '{}': {:?}", uri, err).as_str(),
+            )
+        })?;
+
+        if uri.scheme() != "http" && uri.scheme() != "https" {
+            return Err(glib::Error::new(
+                gst::URIError::UnsupportedProtocol,
+                format!("Unsupported URI scheme '{}'", uri.scheme()).as_str(),
+            ));
+        }
+
+        settings.location = Some(uri);
+
+        Ok(())
+    }
+
+    /// Set a client property and perform necessary state checks and modifications to client.
+    fn set_client_prop(
+        &self,
+        value: &glib::Value,
+        pspec: &glib::ParamSpec,
+    ) -> Result<(), glib::Error> {
+        // Proxy props can only be changed when not started.
+        let state = self.state.lock().unwrap();
+        if client::PROXY_PROPERTIES.contains(&pspec.name()) {
+            if let State::Started { .. } = *state {
+                return Err(glib::Error::new(
+                    gst::URIError::BadState,
+                    &format!(
+                        "Changing the `{}` property on a started `reqwesthttpsink` is not supported",
+                        pspec.name()
+                    ),
+                ));
+            }
+        }
+
+        let mut settings = self.settings.lock().unwrap();
+        if settings.client.set_property(value, pspec)? {
+            self.client.reset();
+        }
+
+        Ok(())
+    }
+
+    fn wait<F, T>(&self, timeout: u32, future: F) -> Result<T, Option<gst::ErrorMessage>>
+    where
+        F: Send + Future<Output = Result<T, gst::ErrorMessage>>,
+        T: Send + 'static,
+    {
+        client::wait(&self.canceller, timeout, future)
+    }
+
+    /// Starts a request for the fragment `index`, or for the whole stream if `index` is `None`.
+    fn begin_upload(
+        &self,
+        element: &super::ReqwestHttpSink,
+        client: &ClientContext,
+        settings: &Settings,
+        index: Option<u32>,
+    ) -> Result<Upload, gst::ErrorMessage> {
+        let (method, uri) = match index {
+            None => {
+                let method = match settings.method {
+                    UploadMethod::Post => Method::POST,
+                    UploadMethod::Put => Method::PUT,
+                };
+                (method, settings.location.clone())
+            }
+            Some(index) => {
+                let location = element
+                    .emit_by_name::<Option<String>>(SIGNAL_GET_LOCATION, &[&index])
+                    .ok_or_else(|| {
+                        gst::error_msg!(
+                            gst::ResourceError::Settings,
+                            ["No location for fragment {}", index]
+                        )
+                    })?;
+                let uri = Url::parse(&location).map_err(|err| {
+                    gst::error_msg!(
+                        gst::ResourceError::Settings,
+                        ["Failed to parse URI '{}': {:?}", location, err]
+                    )
+                })?;
+
+                (Method::PUT, Some(uri))
+            }
+        };
+
+        let uri = uri.ok_or_else(|| {
+            gst::error_msg!(
+                gst::ResourceError::Settings,
+                ["Can't upload without an URI"]
+            )
+        })?;
+
+        gst_info!(CAT, obj: element, "Starting {} request to {}", method, uri);
+
+        let (body, receiver) = mpsc::channel(BODY_QUEUE_SIZE);
+        let req = client::build_request(
+            element.upcast_ref(),
+            client,
+            method,
+            uri.clone(),
+            &settings.client,
+        )
+        .body(reqwest::Body::wrap_stream(receiver));
+        let response = client::RUNTIME.spawn(send(req, uri.clone()));
+
+        Ok(Upload {
+            uri,
+            body,
+            response,
+        })
+    }
+
+    /// Completes the body of `upload` and waits for the response.
+    fn finish_upload(
+        &self,
+        element: &super::ReqwestHttpSink,
+        upload: Upload,
+        settings: &Settings,
+    ) -> Result<(), Option<gst::ErrorMessage>> {
+        let Upload {
+            uri,
+            body,
+            mut response,
+        } = upload;
+
+        gst_debug!(CAT, obj: element, "Finishing request to {}", uri);
+
+        drop(body);
+        let res = self.wait(settings.client.timeout, async {
+            (&mut response).await.map_err(|err| {
+                gst::error_msg!(
+                    gst::LibraryError::Failed,
+                    ["Upload to {} failed: {}", uri, err]
+                )
+            })?
+        });
+
+        match res {
+            Ok(()) => gst_debug!(CAT, obj: element, "Request to {} finished", uri),
+            Err(Some(ref err)) => gst_error!(CAT, obj: element, "Request failed: {}", err),
+            Err(None) => response.abort(),
+        }
+
+        res
+    }
+}
+
+impl ObjectImpl for ReqwestHttpSink {
+    fn properties() -> &'static [glib::ParamSpec] {
+        static PROPERTIES: Lazy<Vec<glib::ParamSpec>> = Lazy::new(|| {
+            let mut properties = vec![
+                glib::ParamSpecString::new(
+                    "location",
+                    "Location",
+                    "URL to upload to. In fragment mode, the first %d is replaced by the fragment index",
+                    None,
+                    glib::ParamFlags::READWRITE | gst::PARAM_FLAG_MUTABLE_READY,
+                ),
+                glib::ParamSpecEnum::new(
+                    "method",
+                    "Method",
+                    "HTTP method for streaming the upload (fragments are always uploaded with PUT)",
+                    UploadMethod::static_type(),
+                    DEFAULT_METHOD as i32,
+                    glib::ParamFlags::READWRITE | gst::PARAM_FLAG_MUTABLE_READY,
+                ),
+                glib::ParamSpecEnum::new(
+                    "fragment-mode",
+                    "Fragment Mode",
+                    "Upload fragments of the stream in separate requests, starting at buffers with the given flags",
+                    FragmentMode::static_type(),
+                    DEFAULT_FRAGMENT_MODE as i32,
+                    glib::ParamFlags::READWRITE | gst::PARAM_FLAG_MUTABLE_READY,
+                ),
+            ];
+            properties.extend(client::properties(DEFAULT_USER_AGENT));
+
+            properties
+        });
+
+        PROPERTIES.as_ref()
+    }
+
+    fn signals() -> &'static [glib::subclass::Signal] {
+        static SIGNALS: Lazy<Vec<glib::subclass::Signal>> = Lazy::new(|| {
+            vec![glib::subclass::Signal::builder(
+                SIGNAL_GET_LOCATION,
+                &[u32::static_type().into()],
+                String::static_type().into(),
+            )
+            .class_handler(|_, args| {
+                let element = args[0]
+                    .get::<super::ReqwestHttpSink>()
+                    .expect("get-location signal arg");
+                let index = args[1].get::<u32>().expect("get-location signal arg");
+
+                let imp = ReqwestHttpSink::from_instance(&element);
+                let settings = imp.settings.lock().unwrap();
+
+                Some(
+                    settings
+                        .location
+                        .as_ref()
+                        .map(|location| format_location(location.as_str(), index))
+                        .to_value(),
+                )
+            })
+            .accumulator(|_hint, ret, value| {
+                // First signal handler wins
+                *ret = value.clone();
+                false
+            })
+            .build()]
+        });
+
+        SIGNALS.as_ref()
+    }
+
+    fn set_property(
+        &self,
+        obj: &Self::Type,
+        _id: usize,
+        value: &glib::Value,
+        pspec: &glib::ParamSpec,
+    ) {
+        let res = match pspec.name() {
+            "location" => {
+                let location = value.get::<Option<&str>>().expect("type checked upstream");
+                self.set_location(location)
+            }
+            "method" => {
+                let mut settings = self.settings.lock().unwrap();
+                settings.method = value.get::<UploadMethod>().expect("type checked upstream");
+                Ok(())
+            }
+            "fragment-mode" => {
+                let mut settings = self.settings.lock().unwrap();
+                settings.fragment_mode =
+                    value.get::<FragmentMode>().expect("type checked upstream");
+                Ok(())
+            }
+            name if ClientSettings::has_property(name) => self.set_client_prop(value, pspec),
+            _ => unimplemented!(),
+        };
+
+        if let Err(err) = res {
+            gst_error!(
+                CAT,
+                obj: obj,
+                "Failed to set property `{}`: {:?}",
+                pspec.name(),
+                err
+            );
+        }
+    }
+
+    fn property(&self, _obj: &Self::Type, _id: usize, pspec: &glib::ParamSpec) -> glib::Value {
+        let settings = self.settings.lock().unwrap();
+
+        match pspec.name() {
+            "location" => settings.location.as_ref().map(Url::to_string).to_value(),
+            "method" => settings.method.to_value(),
+            "fragment-mode" => settings.fragment_mode.to_value(),
+            name if ClientSettings::has_property(name) => settings.client.property(pspec),
+            _ => unimplemented!(),
+        }
+    }
+}
+
+impl GstObjectImpl for ReqwestHttpSink {}
+
+impl ElementImpl for ReqwestHttpSink {
+    fn metadata() -> Option<&'static gst::subclass::ElementMetadata> {
+        static ELEMENT_METADATA: Lazy<gst::subclass::ElementMetadata> = Lazy::new(|| {
+            gst::subclass::ElementMetadata::new(
+                "HTTP Sink",
+                "Sink/Network/HTTP",
+                "Upload stream to an HTTP/HTTPS location",
+                "Sebastian Dröge <sebastian@centricular.com>",
+            )
+        });
+
+        Some(&*ELEMENT_METADATA)
+    }
+
+    fn pad_templates() -> &'static [gst::PadTemplate] {
+        static PAD_TEMPLATES: Lazy<Vec<gst::PadTemplate>> = Lazy::new(|| {
+            let caps = gst::Caps::new_any();
+            let sink_pad_template = gst::PadTemplate::new(
+                "sink",
+                gst::PadDirection::Sink,
+                gst::PadPresence::Always,
+                &caps,
+            )
+            .unwrap();
+
+            vec![sink_pad_template]
+        });
+
+        PAD_TEMPLATES.as_ref()
+    }
+
+    fn set_context(&self, element: &Self::Type, context: &gst::Context) {
+        self.client.set_context(context);
+
+        self.parent_set_context(element, context);
+    }
+
+    fn change_state(
+        &self,
+        element: &Self::Type,
+        transition: gst::StateChange,
+    ) -> Result<gst::StateChangeSuccess, gst::StateChangeError> {
+        if let gst::StateChange::ReadyToNull = transition {
+            self.client.reset();
+        }
+
+        self.parent_change_state(element, transition)
+    }
+}
+
+impl BaseSinkImpl for ReqwestHttpSink {
+    fn start(&self, element: &Self::Type) -> Result<(), gst::ErrorMessage> {
+        let mut state = self.state.lock().unwrap();
+        let settings = self.settings.lock().unwrap().clone();
+
+        if settings.fragment_mode == FragmentMode::None && settings.location.is_none() {
+            return Err(gst::error_msg!(
+                gst::CoreError::StateChange,
+                ["Can't start without an URI"]
+            ));
+        }
+
+        let client = self.client.ensure(
+            element.upcast_ref(),
+            &element.static_pad("sink").unwrap(),
+            &settings.client,
+        )?;
+
+        *state = State::Started {
+            client,
+            upload: None,
+            fragment_index: 0,
+        };
+
+        gst_debug!(CAT, obj: element, "Started");
+
+        Ok(())
+    }
+
+    fn stop(&self, element: &Self::Type) -> Result<(), gst::ErrorMessage> {
+        if let State::Started {
+            upload: Some(upload),
+            ..
+        } = std::mem::replace(&mut *self.state.lock().unwrap(), State::Stopped)
+        {
+            // Abort instead of completing the body so that the server doesn't consider the
+            // upload complete
+            gst_info!(CAT, obj: element, "Aborting request to {}", upload.uri);
+            upload.response.abort();
+        }
+
+        gst_debug!(CAT, obj: element, "Stopped");
+
+        Ok(())
+    }
+
+    fn render(
+        &self,
+        element: &Self::Type,
+        buffer: &gst::Buffer,
+    ) -> Result<gst::FlowSuccess, gst::FlowError> {
+        let settings = self.settings.lock().unwrap().clone();
+
+        let mut state = self.state.lock().unwrap();
+        let (client, upload, fragment_index) = match *state {
+            State::Started {
+                ref client,
+                ref mut upload,
+                ref mut fragment_index,
+            } => (client.clone(), upload, fragment_index),
+            State::Stopped => {
+                gst::element_error!(element, gst::CoreError::Failed, ["Not started yet"]);
+                return Err(gst::FlowError::Error);
+            }
+        };
+
+        let new_fragment = match settings.fragment_mode {
+            FragmentMode::None => false,
+            FragmentMode::KeyUnit => !buffer.flags().contains(gst::BufferFlags::DELTA_UNIT),
+            FragmentMode::Discont => buffer.flags().contains(gst::BufferFlags::DISCONT),
+        };
+
+        let mut previous_upload = None;
+        let mut current_upload = upload.take();
+        if new_fragment {
+            previous_upload = current_upload.take();
+        }
+
+        let index = if current_upload.is_none() && settings.fragment_mode != FragmentMode::None {
+            let index = *fragment_index;
+            *fragment_index += 1;
+            Some(index)
+        } else {
+            None
+        };
+        drop(state);
+
+        let handle_error = |err: Option<gst::ErrorMessage>| match err {
+            Some(err) => {
+                element.post_error_message(err);
+                gst::FlowError::Error
+            }
+            None => {
+                gst_debug!(CAT, obj: element, "Flushing");
+                gst::FlowError::Flushing
+            }
+        };
+
+        if let Some(previous_upload) = previous_upload {
+            self.finish_upload(element, previous_upload, &settings)
+                .map_err(handle_error)?;
+        }
+
+        let mut current_upload = match current_upload {
+            Some(upload) => upload,
+            None => self
+                .begin_upload(element, &client, &settings, index)
+                .map_err(|err| handle_error(Some(err)))?,
+        };
+
+        gst_trace!(
+            CAT,
+            obj: element,
+            "Uploading {} bytes to {}",
+            buffer.size(),
+            current_upload.uri
+        );
+
+        let data = buffer
+            .map_readable()
+            .map_err(|_| {
+                gst::element_error!(element, gst::CoreError::Failed, ["Failed to map buffer"]);
+                gst::FlowError::Error
+            })?
+            .to_vec();
+
+        let body = &mut current_upload.body;
+        let res = self.wait(settings.client.timeout, async {
+            Ok(body.send(Ok(data)).await.is_ok())
+        });
+
+        match res {
+            Ok(true) => (),
+            Ok(false) => {
+                // The request finished before the body was complete, report its result
+                let uri = current_upload.uri.clone();
+                gst_debug!(CAT, obj: element, "Request to {} finished early", uri);
+
+                let err = match self.finish_upload(element, current_upload, &settings) {
+                    Ok(()) => Some(gst::error_msg!(
+                        gst::ResourceError::Write,
+                        ["Server closed the upload to {} early", uri]
+                    )),
+                    Err(err) => err,
+                };
+
+                return Err(handle_error(err));
+            }
+            Err(Some(err)) => return Err(handle_error(Some(err))),
+            Err(None) => {
+                // Keep the request around for when we continue
+                if let State::Started { ref mut upload, .. } = *self.state.lock().unwrap() {
+                    *upload = Some(current_upload);
+                }
+
+                return Err(handle_error(None));
+            }
+        }
+
+        match *self.state.lock().unwrap() {
+            State::Started { ref mut upload, .. } => {
+                *upload = Some(current_upload);
+                Ok(gst::FlowSuccess::Ok)
+            }
+            State::Stopped => {
+                current_upload.response.abort();
+                Err(gst::FlowError::Flushing)
+            }
+        }
+    }
+
+    fn unlock(&self, _element: &Self::Type) -> Result<(), gst::ErrorMessage> {
+        let canceller = self.canceller.lock().unwrap();
+        if let Some(ref canceller) = *canceller {
+            canceller.abort();
+        }
+        Ok(())
+    }
+
+    fn event(&self, element: &Self::Type, event: gst::Event) -> bool {
+        if let gst::EventView::Eos(_) = event.view() {
+            let upload = match *self.state.lock().unwrap() {
+                State::Started { ref mut upload, .. } => upload.take(),
+                State::Stopped => None,
+            };
+
+            if let Some(upload) = upload {
+                let settings = self.settings.lock().unwrap().clone();
+                if let Err(err) = self.finish_upload(element, upload, &settings) {
+                    if let Some(err) = err {
+                        element.post_error_message(err);
+                    }
+                    return false;
+                }
+            }
+        }
+
+        BaseSinkImplExt::parent_event(self, element, event)
+    }
+}
+
+impl URIHandlerImpl for ReqwestHttpSink {
+    const URI_TYPE: gst::URIType = gst::URIType::Sink;
+
+    fn protocols() -> &'static [&'static str] {
+        &["http", "https"]
+    }
+
+    fn uri(&self, _element: &Self::Type) -> Option<String> {
+        let settings = self.settings.lock().unwrap();
+
+        settings.location.as_ref().map(Url::to_string)
+    }
+
+    fn set_uri(&self, _element: &Self::Type, uri: &str) -> Result<(), glib::Error> {
+        self.set_location(Some(uri))
+    }
+}
+
+#[glib::object_subclass]
+impl ObjectSubclass for ReqwestHttpSink {
+    const NAME: &'static str = "ReqwestHttpSink";
+    type Type = super::ReqwestHttpSink;
+    type ParentType = gst_base::BaseSink;
+    type Interfaces = (gst::URIHandler,);
+}
@@ -0,0 +1,56 @@
+// Copyright (C) 2026 agent <agent@local>
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+use gst::glib;
+use gst::prelude::*;
+
+mod imp;
+
+#[derive(Debug, Eq, PartialEq, Ord, PartialOrd, Hash, Clone, Copy, glib::Enum)]
+#[repr(u32)]
+#[enum_type(name = "GstReqwestHttpSinkMethod")]
+pub(crate) enum UploadMethod {
+    #[enum_value(name = "Post: Upload with a POST request.", nick = "post")]
+    Post,
+    #[enum_value(name = "Put: Upload with a PUT request.", nick = "put")]
+    Put,
+}
+
+#[derive(Debug, Eq, PartialEq, Ord, PartialOrd, Hash, Clone, Copy, glib::Enum)]
+#[repr(u32)]
+#[enum_type(name = "GstReqwestHttpSinkFragmentMode")]
+pub(crate) enum FragmentMode {
+    #[enum_value(name = "None: Stream all buffers in a single request.", nick = "none")]
+    None,
+    #[enum_value(
+        name = "KeyUnit: Start a new fragment at each buffer that is not a delta unit.",
+        nick = "key-unit"
+    )]
+    KeyUnit,
+    #[enum_value(
+        name = "Discont: Start a new fragment at each discontinuous buffer.",
+        nick = "discont"
+    )]
+    Discont,
+}
+
+glib::wrapper! {
+    pub struct ReqwestHttpSink(ObjectSubclass<imp::ReqwestHttpSink>) @extends gst_base::BaseSink, gst::Element, gst::Object, @implements gst::URIHandler;
+}
+
+unsafe impl Send for ReqwestHttpSink {}
+unsafe impl Sync for ReqwestHttpSink {}
+
+pub fn register(plugin: &gst::Plugin) -> Result<(), glib::BoolError> {
+    gst::Element::register(
+        Some(plugin),
+        "reqwesthttpsink",
+        gst::Rank::Marginal,
+        ReqwestHttpSink::static_type(),
+    )
+}
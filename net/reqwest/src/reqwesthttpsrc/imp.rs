@@ -5,14 +5,13 @@
 // <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
 // option. This file may not be copied, modified, or distributed
 // except according to those terms.
-use std::sync::{Arc, Mutex};
+use std::sync::Mutex;
 use std::time::Duration;
 use std::u64;
 
 use futures::future;
 use futures::prelude::*;
-use reqwest::{Client, Response, StatusCode};
-use tokio::runtime;
+use reqwest::{Method, Response, StatusCode};
 use url::Url;
 
 use once_cell::sync::Lazy;
@@ -25,6 +24,8 @@ use gst_base::prelude::*;
 use gst_base::subclass::base_src::CreateSuccess;
 use gst_base::subclass::prelude::*;
 
+use crate::client::{self, ClientSettings, ElementClient};
+
 const DEFAULT_LOCATION: Option<Url> = None;
 const DEFAULT_USER_AGENT: &str = concat!(
     "GStreamer reqwesthttpsrc ",
@@ -33,94 +34,34 @@ const DEFAULT_USER_AGENT: &str = concat!(
     env!("COMMIT_ID")
 );
 const DEFAULT_IS_LIVE: bool = false;
-const DEFAULT_TIMEOUT: u32 = 15;
-const DEFAULT_COMPRESS: bool = false;
 const DEFAULT_IRADIO_MODE: bool = true;
-const DEFAULT_KEEP_ALIVE: bool = true;
+const DEFAULT_RETRIES: i32 = 3;
+const DEFAULT_RETRY_DELAY: u32 = 500;
+const DEFAULT_RETRY_BACKOFF: f64 = 2.0;
 
 #[derive(Debug, Clone)]
 struct Settings {
     location: Option<Url>,
-    user_agent: String,
-    user_id: Option<String>,
-    user_pw: Option<String>,
-    timeout: u32,
-    compress: bool,
-    extra_headers: Option<gst::Structure>,
-    cookies: Vec<String>,
     iradio_mode: bool,
-    keep_alive: bool,
-    // Notes about souphttpsrc compatibility:
-    // Internal representation of no proxy is None,
-    // but externally Some("").
-    // Default is set from env var 'http_proxy'.
-    // Prepends http:// if not protocol specified.
-    proxy: Option<String>,
-    // Nullable fields that behave normally:
-    proxy_id: Option<String>,
-    proxy_pw: Option<String>,
+    retries: i32,
+    retry_delay: u32,
+    retry_backoff: f64,
+    client: ClientSettings,
 }
 
 impl Default for Settings {
     fn default() -> Self {
         Settings {
             location: DEFAULT_LOCATION,
-            user_agent: DEFAULT_USER_AGENT.into(),
-            user_id: None,
-            user_pw: None,
-            timeout: DEFAULT_TIMEOUT,
-            compress: DEFAULT_COMPRESS,
-            extra_headers: None,
-            cookies: Vec::new(),
             iradio_mode: DEFAULT_IRADIO_MODE,
-            keep_alive: DEFAULT_KEEP_ALIVE,
-            proxy: match proxy_from_str(std::env::var("http_proxy").ok()) {
-                Ok(a) => a,
-                Err(_) => None,
-            },
-            proxy_id: None,
-            proxy_pw: None,
-        }
-    }
-}
-
-fn proxy_from_str(s: Option<String>) -> Result<Option<String>, glib::Error> {
-    match s {
-        None => Ok(None),
-        Some(s) if s.is_empty() => Ok(None),
-        Some(not_empty_str) => {
-            // If no protocol specified, prepend http for compatibility
-            // https://gstreamer.freedesktop.org/documentation/soup/souphttpsrc.html
-            let url_string = if !not_empty_str.contains("://") {
-                format!("http://{}", not_empty_str)
-            } else {
-                not_empty_str
-            };
-            match reqwest::Url::parse(&url_string) {
-                Ok(url) => {
-                    // this may urlencode and add trailing /
-                    Ok(Some(url.to_string()))
-                }
-                Err(err) => Err(glib::Error::new(
-                    gst::URIError::BadUri,
-                    format!("Failed to parse URI '{}': {:?}", url_string, err).as_str(),
-                )),
-            }
+            retries: DEFAULT_RETRIES,
+            retry_delay: DEFAULT_RETRY_DELAY,
+            retry_backoff: DEFAULT_RETRY_BACKOFF,
+            client: ClientSettings::new(DEFAULT_USER_AGENT),
         }
     }
 }
 
-const REQWEST_CLIENT_CONTEXT: &str = "gst.reqwest.client";
-
-#[derive(Clone, Debug, glib::Boxed)]
-#[boxed_type(name = "ReqwestClientContext")]
-struct ClientContext(Arc<ClientContextInner>);
-
-#[derive(Debug)]
-struct ClientContextInner {
-    client: Client,
-}
-
 #[allow(clippy::large_enum_variant)]
 #[derive(Debug)]
 enum State {
@@ -134,6 +75,10 @@ enum State {
         stop: Option<u64>,
         caps: Option<gst::Caps>,
         tags: Option<gst::TagList>,
+        // Consecutive failed attempts to read from the resource
+        failed_attempts: u32,
+        // Whether the next buffer is discontinuous to the previous one after reconnecting
+        discont: bool,
     },
 }
 
@@ -145,8 +90,7 @@ impl Default for State {
 
 #[derive(Debug, Default)]
 pub struct ReqwestHttpSrc {
-    client: Mutex<Option<ClientContext>>,
-    external_client: Mutex<Option<ClientContext>>,
+    client: ElementClient,
     settings: Mutex<Settings>,
     state: Mutex<State>,
     canceller: Mutex<Option<future::AbortHandle>>,
@@ -160,14 +104,6 @@ static CAT: Lazy<gst::DebugCategory> = Lazy::new(|| {
     )
 });
 
-static RUNTIME: Lazy<runtime::Runtime> = Lazy::new(|| {
-    runtime::Builder::new_multi_thread()
-        .enable_all()
-        .worker_threads(1)
-        .build()
-        .unwrap()
-});
-
 impl ReqwestHttpSrc {
     fn set_location(
         &self,
@@ -209,126 +145,36 @@ impl ReqwestHttpSrc {
         Ok(())
     }
 
-    /// Set a proxy-related property and perform necessary state checks and modifications to client.
-    fn set_proxy_prop<F>(
+    /// Set a client property and perform necessary state checks and modifications to client.
+    fn set_client_prop(
         &self,
-        property_name: &str,
-        desired_value: Option<String>,
-        prop_memory_location: F,
-    ) -> Result<(), glib::Error>
-    where
-        F: Fn(&mut Settings) -> &mut Option<String>,
-    {
+        value: &glib::Value,
+        pspec: &glib::ParamSpec,
+    ) -> Result<(), glib::Error> {
         // Proxy props can only be changed when not started.
         let state = self.state.lock().unwrap();
-        if let State::Started { .. } = *state {
-            return Err(glib::Error::new(
-                gst::URIError::BadState,
-                &format!(
-                    "Changing the `{}` property on a started `reqwesthttpsrc` is not supported",
-                    property_name
-                ),
-            ));
-        }
-
-        // Get memory address of specific variable to change.
-        let mut settings = self.settings.lock().unwrap();
-        let target_variable = prop_memory_location(&mut settings);
-        if &desired_value == target_variable {
-            return Ok(());
+        if client::PROXY_PROPERTIES.contains(&pspec.name()) {
+            if let State::Started { .. } = *state {
+                return Err(glib::Error::new(
+                    gst::URIError::BadState,
+                    &format!(
+                        "Changing the `{}` property on a started `reqwesthttpsrc` is not supported",
+                        pspec.name()
+                    ),
+                ));
+            }
         }
 
         // If the Proxy is changed we need to throw away the old client since it isn't properly
         // configured with a proxy anymore. Since element is not started, an existing client
         // without proxy will be used, or a new one with/without proxy will be built on next call
         // to ensure_client.
-        *self.client.lock().unwrap() = None;
-        *target_variable = desired_value;
-
-        Ok(())
-    }
-
-    fn ensure_client(
-        &self,
-        src: &super::ReqwestHttpSrc,
-        proxy: Option<String>,
-        proxy_id: Option<String>,
-        proxy_pw: Option<String>,
-    ) -> Result<ClientContext, gst::ErrorMessage> {
-        let mut client_guard = self.client.lock().unwrap();
-        if let Some(ref client) = *client_guard {
-            gst_debug!(CAT, obj: src, "Using already configured client");
-            return Ok(client.clone());
-        }
-
-        // Attempt to acquire an existing client context from another element instance
-        // unless using proxy, because proxy is client specific.
-        if proxy.is_none() {
-            let srcpad = src.static_pad("src").unwrap();
-            let mut q = gst::query::Context::new(REQWEST_CLIENT_CONTEXT);
-            if srcpad.peer_query(&mut q) {
-                if let Some(context) = q.context_owned() {
-                    src.set_context(&context);
-                }
-            } else {
-                let _ = src.post_message(
-                    gst::message::NeedContext::builder(REQWEST_CLIENT_CONTEXT)
-                        .src(src)
-                        .build(),
-                );
-            }
-
-            // Hopefully now, self.set_context will have been synchronously called
-            if let Some(client) = self.external_client.lock().unwrap().clone() {
-                gst_debug!(CAT, obj: src, "Using shared client");
-                *client_guard = Some(client.clone());
-
-                return Ok(client);
-            }
-        }
-
-        let mut builder = Client::builder().cookie_store(true).gzip(true);
-
-        if let Some(proxy) = &proxy {
-            // Proxy is url-checked on property set but perhaps this might still fail.
-            let mut p = reqwest::Proxy::all(proxy).map_err(|err| {
-                gst::error_msg!(gst::ResourceError::OpenRead, ["Bad proxy URI: {}", err])
-            })?;
-            if let Some(proxy_id) = &proxy_id {
-                let proxy_pw = proxy_pw.as_deref().unwrap_or("");
-                p = p.basic_auth(proxy_id, proxy_pw);
-            }
-            builder = builder.proxy(p);
-        }
-
-        gst_debug!(CAT, obj: src, "Creating new client");
-        let client = ClientContext(Arc::new(ClientContextInner {
-            client: builder.build().map_err(|err| {
-                gst::error_msg!(
-                    gst::ResourceError::OpenRead,
-                    ["Failed to create Client: {}", err]
-                )
-            })?,
-        }));
-
-        // Share created client with other elements, unless using proxy. Shared client never uses proxy.
-        // The alternative would be different contexts for different proxy settings, or one context with a
-        // map from proxy settings to client, but then, how and when to discard those, retaining reuse benefits?
-        if proxy.is_none() {
-            gst_debug!(CAT, obj: src, "Sharing new client with other elements");
-            let mut context = gst::Context::new(REQWEST_CLIENT_CONTEXT, true);
-            {
-                let context = context.get_mut().unwrap();
-                let s = context.structure_mut();
-                s.set("client", &client);
-            }
-            src.set_context(&context);
-            let _ = src.post_message(gst::message::HaveContext::builder(context).src(src).build());
+        let mut settings = self.settings.lock().unwrap();
+        if settings.client.set_property(value, pspec)? {
+            self.client.reset();
         }
 
-        *client_guard = Some(client.clone());
-
-        Ok(client)
+        Ok(())
     }
 
     fn do_request(
@@ -338,27 +184,28 @@ impl ReqwestHttpSrc {
         start: u64,
         stop: Option<u64>,
     ) -> Result<State, Option<gst::ErrorMessage>> {
-        use headers::{Connection, ContentLength, ContentRange, HeaderMapExt, Range, UserAgent};
-        use reqwest::header::{self, HeaderMap, HeaderName, HeaderValue};
+        use headers::{ContentLength, ContentRange, HeaderMapExt, Range};
+        use reqwest::header::{self, HeaderMap};
 
         gst_debug!(CAT, obj: src, "Creating new request for {}", uri);
 
         let settings = self.settings.lock().unwrap().clone();
 
-        let req = self
-            .ensure_client(src, settings.proxy, settings.proxy_id, settings.proxy_pw)?
-            .0
-            .client
-            .get(uri.clone());
+        let http_client = self.client.ensure(
+            src.upcast_ref(),
+            &src.static_pad("src").unwrap(),
+            &settings.client,
+        )?;
+        let req = client::build_request(
+            src.upcast_ref(),
+            &http_client,
+            Method::GET,
+            uri.clone(),
+            &settings.client,
+        );
 
         let mut headers = HeaderMap::new();
 
-        if settings.keep_alive {
-            headers.typed_insert(Connection::keep_alive());
-        } else {
-            headers.typed_insert(Connection::close());
-        }
-
         match (start != 0, stop) {
             (false, None) => (),
             (true, None) => {
@@ -369,100 +216,13 @@ impl ReqwestHttpSrc {
             }
         }
 
-        headers.typed_insert(settings.user_agent.parse::<UserAgent>().unwrap());
-
-        if !settings.compress {
-            // Compression is the default
-            headers.insert(
-                header::ACCEPT_ENCODING,
-                "identity".parse::<HeaderValue>().unwrap(),
-            );
-        };
-
-        if let Some(ref extra_headers) = settings.extra_headers {
-            for (field, value) in extra_headers.iter() {
-                let field = match HeaderName::try_from(field) {
-                    Ok(field) => field,
-                    Err(err) => {
-                        gst_warning!(
-                            CAT,
-                            obj: src,
-                            "Failed to transform extra-header field name '{}' to header name: {}",
-                            field,
-                            err,
-                        );
-
-                        continue;
-                    }
-                };
-
-                let mut append_header = |field: &HeaderName, value: &glib::Value| {
-                    let value = match value.transform::<String>() {
-                        Ok(value) => value,
-                        Err(_) => {
-                            gst_warning!(
-                                CAT,
-                                obj: src,
-                                "Failed to transform extra-header '{}' value to string",
-                                field
-                            );
-                            return;
-                        }
-                    };
-
-                    let value = value.get::<Option<&str>>().unwrap().unwrap_or("");
-
-                    let value = match value.parse::<HeaderValue>() {
-                        Ok(value) => value,
-                        Err(_) => {
-                            gst_warning!(
-                                CAT,
-                                obj: src,
-                                "Failed to transform extra-header '{}' value to header value",
-                                field
-                            );
-                            return;
-                        }
-                    };
-
-                    headers.append(field.clone(), value);
-                };
-
-                if let Ok(values) = value.get::<gst::ArrayRef>() {
-                    for value in values.as_slice() {
-                        append_header(&field, value);
-                    }
-                } else if let Ok(values) = value.get::<gst::ListRef>() {
-                    for value in values.as_slice() {
-                        append_header(&field, value);
-                    }
-                } else {
-                    append_header(&field, value);
-                }
-            }
-        }
-
-        if !settings.cookies.is_empty() {
-            headers.insert(
-                header::COOKIE,
-                settings.cookies.join("; ").parse::<HeaderValue>().unwrap(),
-            );
-        }
-
         if settings.iradio_mode {
             headers.insert("icy-metadata", "1".parse().unwrap());
         }
 
-        // Add all headers for the request here
+        // Add all remaining headers for the request here
         let req = req.headers(headers);
 
-        let req = if let Some(ref user_id) = settings.user_id {
-            // HTTP auth available
-            req.basic_auth(user_id, settings.user_pw)
-        } else {
-            req
-        };
-
         gst_debug!(CAT, obj: src, "Sending new request: {:?}", req);
 
         let future = async {
@@ -614,62 +374,127 @@ impl ReqwestHttpSrc {
             stop,
             caps,
             tags: if tags.n_tags() > 0 { Some(tags) } else { None },
+            failed_attempts: 0,
+            discont: false,
         })
     }
 
-    fn wait<F, T>(&self, future: F) -> Result<T, Option<gst::ErrorMessage>>
-    where
-        F: Send + Future<Output = Result<T, gst::ErrorMessage>>,
-        T: Send + 'static,
-    {
-        let timeout = self.settings.lock().unwrap().timeout;
-
-        let mut canceller = self.canceller.lock().unwrap();
-        let (abort_handle, abort_registration) = future::AbortHandle::new_pair();
-        canceller.replace(abort_handle);
-        drop(canceller);
+    /// Re-issues the request after reading from the response failed with `err`.
+    ///
+    /// Seekable resources are resumed at the current position. Other resources are requested
+    /// again and the next buffer is marked as discontinuous.
+    fn reconnect(
+        &self,
+        src: &super::ReqwestHttpSrc,
+        mut err: gst::ErrorMessage,
+    ) -> Result<(), gst::FlowError> {
+        let settings = self.settings.lock().unwrap().clone();
+        let mut state = self.state.lock().unwrap();
 
-        // Wrap in a timeout
-        let future = async {
-            if timeout == 0 {
-                future.await
-            } else {
-                let res = tokio::time::timeout(Duration::from_secs(timeout.into()), future).await;
+        let (uri, seekable, position, stop, mut failed_attempts) = match *state {
+            State::Started {
+                ref uri,
+                seekable,
+                position,
+                stop,
+                failed_attempts,
+                ..
+            } => (uri.clone(), seekable, position, stop, failed_attempts),
+            State::Stopped => {
+                gst::element_error!(src, gst::LibraryError::Failed, ["Not started yet"]);
 
-                match res {
-                    Ok(res) => res,
-                    Err(_) => Err(gst::error_msg!(
-                        gst::ResourceError::Read,
-                        ["Request timeout"]
-                    )),
-                }
+                return Err(gst::FlowError::Error);
             }
         };
 
-        // And make abortable
-        let future = async {
-            match future::Abortable::new(future, abort_registration).await {
-                Ok(res) => res.map_err(Some),
-                Err(_) => Err(None),
+        loop {
+            if settings.retries >= 0 && failed_attempts >= settings.retries as u32 {
+                gst_error!(CAT, obj: src, "Giving up after {} retries", failed_attempts);
+                src.post_error_message(err);
+                return Err(gst::FlowError::Error);
             }
-        };
 
-        let res = {
-            let _enter = RUNTIME.enter();
-            futures::executor::block_on(future)
-        };
+            let delay =
+                settings.retry_delay as f64 * settings.retry_backoff.powi(failed_attempts as i32);
+            let delay = Duration::from_millis(delay.min(u32::MAX as f64) as u64);
+            failed_attempts += 1;
+
+            gst_warning!(
+                CAT,
+                obj: src,
+                "Reading at offset {} failed, retrying in {:?} (attempt {}): {:?}",
+                position,
+                delay,
+                failed_attempts,
+                err
+            );
+
+            // The delay is not subject to the request timeout
+            let res = client::wait(&self.canceller, 0, async {
+                tokio::time::sleep(delay).await;
+                Ok(())
+            });
+            if res.is_err() {
+                gst_debug!(CAT, obj: src, "Flushing");
+                return Err(gst::FlowError::Flushing);
+            }
+
+            let res = if seekable {
+                self.do_request(src, uri.clone(), position, stop)
+            } else {
+                self.do_request(src, uri.clone(), 0, None)
+            };
+
+            match res {
+                Ok(mut new_state) => {
+                    if let State::Started {
+                        position: ref mut new_position,
+                        seekable: ref mut new_seekable,
+                        ref mut size,
+                        failed_attempts: ref mut new_failed_attempts,
+                        ref mut discont,
+                        ..
+                    } = new_state
+                    {
+                        *new_failed_attempts = failed_attempts;
+
+                        // Continue the offsets of the previous response
+                        if !seekable {
+                            *new_position = position;
+                            *new_seekable = false;
+                            *size = None;
+                            *discont = true;
+                        }
+                    }
+
+                    *state = new_state;
+
+                    return Ok(());
+                }
+                Err(Some(new_err)) => err = new_err,
+                Err(None) => {
+                    gst_debug!(CAT, obj: src, "Flushing");
+                    return Err(gst::FlowError::Flushing);
+                }
+            }
+        }
+    }
 
-        /* Clear out the canceller */
-        let _ = self.canceller.lock().unwrap().take();
+    fn wait<F, T>(&self, future: F) -> Result<T, Option<gst::ErrorMessage>>
+    where
+        F: Send + Future<Output = Result<T, gst::ErrorMessage>>,
+        T: Send + 'static,
+    {
+        let timeout = self.settings.lock().unwrap().client.timeout;
 
-        res
+        client::wait(&self.canceller, timeout, future)
     }
 }
 
 impl ObjectImpl for ReqwestHttpSrc {
     fn properties() -> &'static [glib::ParamSpec] {
         static PROPERTIES: Lazy<Vec<glib::ParamSpec>> = Lazy::new(|| {
-            vec![
+            let mut properties = vec![
                 glib::ParamSpecString::new(
                     "location",
                     "Location",
@@ -677,13 +502,6 @@ impl ObjectImpl for ReqwestHttpSrc {
                     None,
                     glib::ParamFlags::READWRITE | gst::PARAM_FLAG_MUTABLE_READY,
                 ),
-                glib::ParamSpecString::new(
-                    "user-agent",
-                    "User-Agent",
-                    "Value of the User-Agent HTTP request header field",
-                    DEFAULT_USER_AGENT.into(),
-                    glib::ParamFlags::READWRITE | gst::PARAM_FLAG_MUTABLE_READY,
-                ),
                 glib::ParamSpecBoolean::new(
                     "is-live",
                     "Is Live",
@@ -691,50 +509,6 @@ impl ObjectImpl for ReqwestHttpSrc {
                     DEFAULT_IS_LIVE,
                     glib::ParamFlags::READWRITE | gst::PARAM_FLAG_MUTABLE_READY,
                 ),
-                glib::ParamSpecString::new(
-                    "user-id",
-                    "User-id",
-                    "HTTP location URI user id for authentication",
-                    None,
-                    glib::ParamFlags::READWRITE | gst::PARAM_FLAG_MUTABLE_READY,
-                ),
-                glib::ParamSpecString::new(
-                    "user-pw",
-                    "User-pw",
-                    "HTTP location URI user password for authentication",
-                    None,
-                    glib::ParamFlags::READWRITE | gst::PARAM_FLAG_MUTABLE_READY,
-                ),
-                glib::ParamSpecUInt::new(
-                    "timeout",
-                    "Timeout",
-                    "Value in seconds to timeout a blocking I/O (0 = No timeout).",
-                    0,
-                    3600,
-                    DEFAULT_TIMEOUT,
-                    glib::ParamFlags::READWRITE | gst::PARAM_FLAG_MUTABLE_READY,
-                ),
-                glib::ParamSpecBoolean::new(
-                    "compress",
-                    "Compress",
-                    "Allow compressed content encodings",
-                    DEFAULT_COMPRESS,
-                    glib::ParamFlags::READWRITE | gst::PARAM_FLAG_MUTABLE_READY,
-                ),
-                glib::ParamSpecBoxed::new(
-                    "extra-headers",
-                    "Extra Headers",
-                    "Extra headers to append to the HTTP request",
-                    gst::Structure::static_type(),
-                    glib::ParamFlags::READWRITE | gst::PARAM_FLAG_MUTABLE_READY,
-                ),
-                glib::ParamSpecBoxed::new(
-                    "cookies",
-                    "Cookies",
-                    "HTTP request cookies",
-                    Vec::<String>::static_type(),
-                    glib::ParamFlags::READWRITE | gst::PARAM_FLAG_MUTABLE_READY,
-                ),
                 glib::ParamSpecBoolean::new(
                     "iradio-mode",
                     "I-Radio Mode",
@@ -742,35 +516,37 @@ impl ObjectImpl for ReqwestHttpSrc {
                     DEFAULT_IRADIO_MODE,
                     glib::ParamFlags::READWRITE | gst::PARAM_FLAG_MUTABLE_READY,
                 ),
-                glib::ParamSpecBoolean::new(
-                    "keep-alive",
-                    "Keep Alive",
-                    "Use HTTP persistent connections",
-                    DEFAULT_KEEP_ALIVE,
-                    glib::ParamFlags::READWRITE | gst::PARAM_FLAG_MUTABLE_READY,
-                ),
-                glib::ParamSpecString::new(
-                    "proxy",
-                    "Proxy",
-                    "HTTP proxy server URI",
-                    Some(""),
-                    glib::ParamFlags::READWRITE | gst::PARAM_FLAG_MUTABLE_READY,
+                glib::ParamSpecInt::new(
+                    "retries",
+                    "Retries",
+                    "Maximum number of retries after the connection failed while reading (-1 = unlimited)",
+                    -1,
+                    i32::MAX,
+                    DEFAULT_RETRIES,
+                    glib::ParamFlags::READWRITE | gst::PARAM_FLAG_MUTABLE_PLAYING,
                 ),
-                glib::ParamSpecString::new(
-                    "proxy-id",
-                    "Proxy-id",
-                    "HTTP proxy URI user id for authentication",
-                    Some(""),
-                    glib::ParamFlags::READWRITE | gst::PARAM_FLAG_MUTABLE_READY,
+                glib::ParamSpecUInt::new(
+                    "retry-delay",
+                    "Retry Delay",
+                    "Delay in milliseconds before the first retry",
+                    0,
+                    u32::MAX,
+                    DEFAULT_RETRY_DELAY,
+                    glib::ParamFlags::READWRITE | gst::PARAM_FLAG_MUTABLE_PLAYING,
                 ),
-                glib::ParamSpecString::new(
-                    "proxy-pw",
-                    "Proxy-pw",
-                    "HTTP proxy URI user password for authentication",
-                    Some(""),
-                    glib::ParamFlags::READWRITE | gst::PARAM_FLAG_MUTABLE_READY,
+                glib::ParamSpecDouble::new(
+                    "retry-backoff",
+                    "Retry Backoff",
+                    "Factor by which the delay grows with each consecutive retry",
+                    1.0,
+                    100.0,
+                    DEFAULT_RETRY_BACKOFF,
+                    glib::ParamFlags::READWRITE | gst::PARAM_FLAG_MUTABLE_PLAYING,
                 ),
-            ]
+            ];
+            properties.extend(client::properties(DEFAULT_USER_AGENT));
+
+            properties
         });
 
         PROPERTIES.as_ref()
@@ -788,95 +564,33 @@ impl ObjectImpl for ReqwestHttpSrc {
                 let location = value.get::<Option<&str>>().expect("type checked upstream");
                 self.set_location(obj, location)
             }
-            "user-agent" => {
-                let mut settings = self.settings.lock().unwrap();
-                let user_agent = value
-                    .get::<Option<String>>()
-                    .expect("type checked upstream")
-                    .unwrap_or_else(|| DEFAULT_USER_AGENT.into());
-                settings.user_agent = user_agent;
-                Ok(())
-            }
             "is-live" => {
                 let is_live = value.get().expect("type checked upstream");
                 obj.set_live(is_live);
                 Ok(())
             }
-            "user-id" => {
-                let mut settings = self.settings.lock().unwrap();
-                let user_id = value.get().expect("type checked upstream");
-                settings.user_id = user_id;
-                Ok(())
-            }
-            "user-pw" => {
-                let mut settings = self.settings.lock().unwrap();
-                let user_pw = value.get().expect("type checked upstream");
-                settings.user_pw = user_pw;
-                Ok(())
-            }
-            "timeout" => {
-                let mut settings = self.settings.lock().unwrap();
-                let timeout = value.get().expect("type checked upstream");
-                settings.timeout = timeout;
-                Ok(())
-            }
-            "compress" => {
-                let mut settings = self.settings.lock().unwrap();
-                let compress = value.get().expect("type checked upstream");
-                settings.compress = compress;
-                Ok(())
-            }
-            "extra-headers" => {
+            "iradio-mode" => {
                 let mut settings = self.settings.lock().unwrap();
-                let extra_headers = value.get().expect("type checked upstream");
-                settings.extra_headers = extra_headers;
+                let iradio_mode = value.get().expect("type checked upstream");
+                settings.iradio_mode = iradio_mode;
                 Ok(())
             }
-            "cookies" => {
+            "retries" => {
                 let mut settings = self.settings.lock().unwrap();
-                settings.cookies = value.get::<Vec<String>>().expect("type checked upstream");
+                settings.retries = value.get().expect("type checked upstream");
                 Ok(())
             }
-            "iradio-mode" => {
+            "retry-delay" => {
                 let mut settings = self.settings.lock().unwrap();
-                let iradio_mode = value.get().expect("type checked upstream");
-                settings.iradio_mode = iradio_mode;
+                settings.retry_delay = value.get().expect("type checked upstream");
                 Ok(())
             }
-            "keep-alive" => {
+            "retry-backoff" => {
                 let mut settings = self.settings.lock().unwrap();
-                let keep_alive = value.get().expect("type checked upstream");
-                settings.keep_alive = keep_alive;
+                settings.retry_backoff = value.get().expect("type checked upstream");
                 Ok(())
             }
-            "proxy" => {
-                let proxy = proxy_from_str(
-                    value
-                        .get::<Option<String>>()
-                        .expect("type checked upstream"),
-                );
-                match proxy {
-                    Ok(proxy) => self
-                        .set_proxy_prop(pspec.name(), proxy, move |settings| &mut settings.proxy),
-                    Err(e) => Err(e),
-                }
-            }
-            "proxy-id" => {
-                let proxy_id = value
-                    .get::<Option<String>>()
-                    .expect("type checked upstream");
-                self.set_proxy_prop(pspec.name(), proxy_id, move |settings| {
-                    &mut settings.proxy_id
-                })
-            }
-            "proxy-pw" => {
-                let proxy_pw = value
-                    .get::<Option<String>>()
-                    .expect("type checked upstream");
-                self.set_proxy_prop(pspec.name(), proxy_pw, move |settings| {
-                    &mut settings.proxy_pw
-                })
-            }
+            name if ClientSettings::has_property(name) => self.set_client_prop(value, pspec),
             _ => unimplemented!(),
         };
 
@@ -899,54 +613,27 @@ impl ObjectImpl for ReqwestHttpSrc {
 
                 location.to_value()
             }
-            "user-agent" => {
-                let settings = self.settings.lock().unwrap();
-                settings.user_agent.to_value()
-            }
             "is-live" => obj.is_live().to_value(),
-            "user-id" => {
-                let settings = self.settings.lock().unwrap();
-                settings.user_id.to_value()
-            }
-            "user-pw" => {
-                let settings = self.settings.lock().unwrap();
-                settings.user_pw.to_value()
-            }
-            "timeout" => {
-                let settings = self.settings.lock().unwrap();
-                settings.timeout.to_value()
-            }
-            "compress" => {
+            "iradio-mode" => {
                 let settings = self.settings.lock().unwrap();
-                settings.compress.to_value()
+                settings.iradio_mode.to_value()
             }
-            "extra-headers" => {
+            "retries" => {
                 let settings = self.settings.lock().unwrap();
-                settings.extra_headers.to_value()
+                settings.retries.to_value()
             }
-            "cookies" => {
+            "retry-delay" => {
                 let settings = self.settings.lock().unwrap();
-                settings.cookies.to_value()
+                settings.retry_delay.to_value()
             }
-            "iradio-mode" => {
+            "retry-backoff" => {
                 let settings = self.settings.lock().unwrap();
-                settings.iradio_mode.to_value()
+                settings.retry_backoff.to_value()
             }
-            "keep-alive" => {
+            name if ClientSettings::has_property(name) => {
                 let settings = self.settings.lock().unwrap();
-                settings.keep_alive.to_value()
+                settings.client.property(pspec)
             }
-            // return None values as Some("") for compatibility with souphttpsrc
-            "proxy" => self
-                .settings
-                .lock()
-                .unwrap()
-                .proxy
-                .as_deref()
-                .unwrap_or("")
-                .to_value(),
-            "proxy-id" => self.settings.lock().unwrap().proxy_id.to_value(),
-            "proxy-pw" => self.settings.lock().unwrap().proxy_pw.to_value(),
             _ => unimplemented!(),
         }
     }
@@ -992,14 +679,7 @@ impl ElementImpl for ReqwestHttpSrc {
     }
 
     fn set_context(&self, element: &Self::Type, context: &gst::Context) {
-        if context.context_type() == REQWEST_CLIENT_CONTEXT {
-            let mut external_client = self.external_client.lock().unwrap();
-            let s = context.structure();
-            *external_client = s
-                .get::<&ClientContext>("client")
-                .map(|c| Some(c.clone()))
-                .unwrap_or(None);
-        }
+        self.client.set_context(context);
 
         self.parent_set_context(element, context);
     }
@@ -1010,7 +690,7 @@ impl ElementImpl for ReqwestHttpSrc {
         transition: gst::StateChange,
     ) -> Result<gst::StateChangeSuccess, gst::StateChangeError> {
         if let gst::StateChange::ReadyToNull = transition {
-            *self.client.lock().unwrap() = None;
+            self.client.reset();
         }
 
         self.parent_change_state(element, transition)
@@ -1142,81 +822,84 @@ impl PushSrcImpl for ReqwestHttpSrc {
         src: &Self::Type,
         _buffer: Option<&mut gst::BufferRef>,
     ) -> Result<CreateSuccess, gst::FlowError> {
-        let mut state = self.state.lock().unwrap();
+        let (res, offset, current_response) = loop {
+            let mut state = self.state.lock().unwrap();
+
+            let (response, position, caps, tags) = match *state {
+                State::Started {
+                    ref mut response,
+                    ref mut position,
+                    ref mut tags,
+                    ref mut caps,
+                    ..
+                } => (response, position, caps, tags),
+                State::Stopped => {
+                    gst::element_error!(src, gst::LibraryError::Failed, ["Not started yet"]);
+
+                    return Err(gst::FlowError::Error);
+                }
+            };
 
-        let (response, position, caps, tags) = match *state {
-            State::Started {
-                ref mut response,
-                ref mut position,
-                ref mut tags,
-                ref mut caps,
-                ..
-            } => (response, position, caps, tags),
-            State::Stopped => {
-                gst::element_error!(src, gst::LibraryError::Failed, ["Not started yet"]);
+            let offset = *position;
 
-                return Err(gst::FlowError::Error);
-            }
-        };
+            let mut current_response = match response.take() {
+                Some(response) => response,
+                None => {
+                    gst_error!(CAT, obj: src, "Don't have a response");
+                    gst::element_error!(src, gst::ResourceError::Read, ["Don't have a response"]);
 
-        let offset = *position;
+                    return Err(gst::FlowError::Error);
+                }
+            };
 
-        let mut current_response = match response.take() {
-            Some(response) => response,
-            None => {
-                gst_error!(CAT, obj: src, "Don't have a response");
-                gst::element_error!(src, gst::ResourceError::Read, ["Don't have a response"]);
+            let tags = tags.take();
+            let caps = caps.take();
+            drop(state);
 
-                return Err(gst::FlowError::Error);
+            if let Some(caps) = caps {
+                gst_debug!(CAT, obj: src, "Setting caps {:?}", caps);
+                src.set_caps(&caps)
+                    .map_err(|_| gst::FlowError::NotNegotiated)?;
             }
-        };
-
-        let tags = tags.take();
-        let caps = caps.take();
-        drop(state);
-
-        if let Some(caps) = caps {
-            gst_debug!(CAT, obj: src, "Setting caps {:?}", caps);
-            src.set_caps(&caps)
-                .map_err(|_| gst::FlowError::NotNegotiated)?;
-        }
 
-        if let Some(tags) = tags {
-            gst_debug!(CAT, obj: src, "Sending iradio tags {:?}", tags);
-            let pad = src.static_pad("src").unwrap();
-            pad.push_event(gst::event::Tag::new(tags));
-        }
+            if let Some(tags) = tags {
+                gst_debug!(CAT, obj: src, "Sending iradio tags {:?}", tags);
+                let pad = src.static_pad("src").unwrap();
+                pad.push_event(gst::event::Tag::new(tags));
+            }
 
-        let future = async {
-            current_response.chunk().await.map_err(move |err| {
-                gst::error_msg!(
-                    gst::ResourceError::Read,
-                    ["Failed to read chunk at offset {}: {:?}", offset, err]
-                )
-            })
-        };
-        let res = self.wait(future);
+            let future = async {
+                current_response.chunk().await.map_err(move |err| {
+                    gst::error_msg!(
+                        gst::ResourceError::Read,
+                        ["Failed to read chunk at offset {}: {:?}", offset, err]
+                    )
+                })
+            };
+            let res = self.wait(future);
 
-        let res = match res {
-            Ok(res) => res,
-            Err(Some(err)) => {
-                gst_debug!(CAT, obj: src, "Error {:?}", err);
-                src.post_error_message(err);
-                return Err(gst::FlowError::Error);
-            }
-            Err(None) => {
-                gst_debug!(CAT, obj: src, "Flushing");
-                return Err(gst::FlowError::Flushing);
+            match res {
+                Ok(res) => break (res, offset, current_response),
+                Err(Some(err)) => {
+                    gst_debug!(CAT, obj: src, "Error {:?}", err);
+                    self.reconnect(src, err)?;
+                }
+                Err(None) => {
+                    gst_debug!(CAT, obj: src, "Flushing");
+                    return Err(gst::FlowError::Flushing);
+                }
             }
         };
 
         let mut state = self.state.lock().unwrap();
-        let (response, position) = match *state {
+        let (response, position, failed_attempts, discont) = match *state {
             State::Started {
                 ref mut response,
                 ref mut position,
+                ref mut failed_attempts,
+                ref mut discont,
                 ..
-            } => (response, position),
+            } => (response, position, failed_attempts, discont),
             State::Stopped => {
                 gst::element_error!(src, gst::LibraryError::Failed, ["Not started yet"]);
 
@@ -1239,6 +922,7 @@ impl PushSrcImpl for ReqwestHttpSrc {
                 assert_ne!(chunk.len(), 0);
 
                 *position += size as u64;
+                *failed_attempts = 0;
 
                 let mut buffer = gst::Buffer::from_slice(chunk);
 
@@ -1248,6 +932,10 @@ impl PushSrcImpl for ReqwestHttpSrc {
                     let buffer = buffer.get_mut().unwrap();
                     buffer.set_offset(offset);
                     buffer.set_offset_end(offset + size as u64);
+                    if *discont {
+                        buffer.set_flags(gst::BufferFlags::DISCONT);
+                        *discont = false;
+                    }
                 }
 
                 Ok(CreateSuccess::NewBuffer(buffer))
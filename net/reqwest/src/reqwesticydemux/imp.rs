@@ -0,0 +1,610 @@
+// Copyright (C) 2026 agent <agent@local>
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+use gst::glib;
+use gst::prelude::*;
+use gst::subclass::prelude::*;
+use gst::{gst_debug, gst_error, gst_log, gst_trace};
+
+use once_cell::sync::Lazy;
+
+use std::cmp;
+use std::mem;
+use std::sync::Mutex;
+
+static CAT: Lazy<gst::DebugCategory> = Lazy::new(|| {
+    gst::DebugCategory::new(
+        "reqwesticydemux",
+        gst::DebugColorFlags::empty(),
+        Some("Rust ICY Demuxer"),
+    )
+});
+
+/// Amount of audio data that is collected for typefinding if the content type is unknown
+const TYPEFIND_SIZE: usize = 8192;
+
+#[derive(Debug)]
+enum Parser {
+    /// Number of audio bytes until the next metadata block
+    Data(usize),
+    /// The next byte is the length of the metadata block in units of 16 bytes
+    Length,
+    /// Collecting a metadata block of the given length
+    Metadata(usize, Vec<u8>),
+}
+
+#[derive(Debug)]
+enum Item {
+    Buffer(gst::Buffer),
+    Event(gst::Event),
+}
+
+#[derive(Debug)]
+struct State {
+    metadata_interval: Option<usize>,
+    parser: Parser,
+    // The output caps are only known after typefinding, until then all
+    // buffers and serialized events are queued up
+    need_typefind: bool,
+    pending: Vec<Item>,
+    upstream_tags: Option<gst::TagList>,
+    offset: u64,
+    discont: bool,
+}
+
+impl Default for State {
+    fn default() -> Self {
+        Self {
+            metadata_interval: None,
+            parser: Parser::Data(0),
+            need_typefind: false,
+            pending: Vec::new(),
+            upstream_tags: None,
+            offset: 0,
+            discont: true,
+        }
+    }
+}
+
+impl State {
+    /// Splits the buffer into the audio data and tag events for the metadata blocks
+    fn process(
+        &mut self,
+        element: &super::ReqwestIcyDemux,
+        buffer: &gst::Buffer,
+    ) -> Result<Vec<Item>, gst::FlowError> {
+        let metadata_interval = self.metadata_interval.ok_or_else(|| {
+            gst_error!(CAT, obj: element, "No metadata interval known");
+            gst::FlowError::NotNegotiated
+        })?;
+
+        // After reconnecting upstream the stream starts again with a full interval
+        if buffer.flags().contains(gst::BufferFlags::DISCONT) {
+            self.parser = Parser::Data(metadata_interval);
+            self.discont = true;
+        }
+
+        let map = buffer.map_readable().map_err(|_| {
+            gst_error!(CAT, obj: element, "Failed to map buffer");
+            gst::FlowError::Error
+        })?;
+        let data = map.as_slice();
+
+        let mut items = Vec::new();
+        let mut pos = 0;
+        while pos < data.len() {
+            match self.parser {
+                Parser::Data(remaining) => {
+                    let size = cmp::min(remaining, data.len() - pos);
+
+                    let mut outbuf = buffer
+                        .copy_region(gst::BufferCopyFlags::MEMORY, pos, Some(size))
+                        .map_err(|_| {
+                            gst_error!(CAT, obj: element, "Failed to copy buffer region");
+                            gst::FlowError::Error
+                        })?;
+                    {
+                        let outbuf = outbuf.get_mut().unwrap();
+                        outbuf.set_offset(self.offset);
+                        outbuf.set_offset_end(self.offset + size as u64);
+                        if mem::replace(&mut self.discont, false) {
+                            outbuf.set_flags(gst::BufferFlags::DISCONT);
+                        }
+                    }
+                    self.offset += size as u64;
+                    pos += size;
+                    items.push(Item::Buffer(outbuf));
+
+                    self.parser = if size == remaining {
+                        Parser::Length
+                    } else {
+                        Parser::Data(remaining - size)
+                    };
+                }
+                Parser::Length => {
+                    let length = data[pos] as usize * 16;
+                    pos += 1;
+
+                    self.parser = if length == 0 {
+                        Parser::Data(metadata_interval)
+                    } else {
+                        Parser::Metadata(length, Vec::with_capacity(length))
+                    };
+                }
+                Parser::Metadata(length, ref mut metadata) => {
+                    let size = cmp::min(length - metadata.len(), data.len() - pos);
+                    metadata.extend_from_slice(&data[pos..(pos + size)]);
+                    pos += size;
+
+                    if metadata.len() < length {
+                        continue;
+                    }
+
+                    let tags = parse_metadata(metadata);
+                    self.parser = Parser::Data(metadata_interval);
+
+                    if let Some(tags) = tags {
+                        gst_debug!(CAT, obj: element, "Got tags {:?}", tags);
+
+                        // Keep the tags from the HTTP headers around
+                        let tags = match self.upstream_tags {
+                            Some(ref upstream_tags) => {
+                                upstream_tags.merge(&tags, gst::TagMergeMode::Replace)
+                            }
+                            None => tags,
+                        };
+                        items.push(Item::Event(gst::event::Tag::new(tags)));
+                    }
+                }
+            }
+        }
+
+        Ok(items)
+    }
+}
+
+/// Parses a metadata block of the form `StreamTitle='...';StreamUrl='...';`
+fn parse_metadata(data: &[u8]) -> Option<gst::TagList> {
+    // Metadata blocks are padded with zeroes
+    let end = data.iter().position(|&b| b == 0).unwrap_or(data.len());
+    let data = &data[..end];
+
+    // Most servers send UTF-8 but some still use ISO-8859-1
+    let metadata = match std::str::from_utf8(data) {
+        Ok(metadata) => metadata.to_string(),
+        Err(_) => data.iter().map(|&b| b as char).collect(),
+    };
+
+    let mut tags = gst::TagList::new();
+    {
+        let tags = tags.get_mut().unwrap();
+
+        for entry in metadata.split("';") {
+            let (key, value) = match entry.split_once("='") {
+                Some((key, value)) if !value.is_empty() => (key.trim(), value),
+                _ => continue,
+            };
+
+            match key {
+                "StreamTitle" => tags.add::<gst::tags::Title>(&value, gst::TagMergeMode::Replace),
+                "StreamUrl" => tags.add::<gst::tags::Homepage>(&value, gst::TagMergeMode::Replace),
+                _ => (),
+            }
+        }
+    }
+
+    if tags.is_empty() {
+        None
+    } else {
+        Some(tags)
+    }
+}
+
+/// Maps the content type of the stream to caps that the decoders and parsers understand
+fn content_type_to_caps(content_type: &str) -> Option<gst::Caps> {
+    let content_type = content_type.parse::<mime::Mime>().ok()?;
+
+    match content_type.essence_str() {
+        "audio/mpeg" | "audio/mp3" | "audio/x-mpeg" => Some(
+            gst::Caps::builder("audio/mpeg")
+                .field("mpegversion", 1i32)
+                .build(),
+        ),
+        "audio/aac" | "audio/aacp" | "audio/x-aac" => Some(
+            gst::Caps::builder("audio/mpeg")
+                .field("mpegversion", 4i32)
+                .field("stream-format", "adts")
+                .build(),
+        ),
+        "application/ogg" | "audio/ogg" | "audio/x-ogg" => {
+            Some(gst::Caps::builder("application/ogg").build())
+        }
+        "audio/flac" | "audio/x-flac" => Some(gst::Caps::builder("audio/x-flac").build()),
+        _ => None,
+    }
+}
+
+pub struct ReqwestIcyDemux {
+    srcpad: gst::Pad,
+    sinkpad: gst::Pad,
+    state: Mutex<State>,
+}
+
+impl ReqwestIcyDemux {
+    fn sink_chain(
+        &self,
+        pad: &gst::Pad,
+        element: &super::ReqwestIcyDemux,
+        buffer: gst::Buffer,
+    ) -> Result<gst::FlowSuccess, gst::FlowError> {
+        gst_log!(CAT, obj: pad, "Handling buffer {:?}", buffer);
+
+        let mut state = self.state.lock().unwrap();
+        let mut items = state.process(element, &buffer)?;
+
+        let mut caps = None;
+        if state.need_typefind {
+            state.pending.append(&mut items);
+
+            let size = state
+                .pending
+                .iter()
+                .map(|item| match item {
+                    Item::Buffer(buffer) => buffer.size(),
+                    Item::Event(_) => 0,
+                })
+                .sum::<usize>();
+            if size < TYPEFIND_SIZE {
+                return Ok(gst::FlowSuccess::Ok);
+            }
+
+            caps = Some(self.typefind(element, &mut state)?);
+            items = mem::take(&mut state.pending);
+        }
+        drop(state);
+
+        if let Some(caps) = caps {
+            self.srcpad.push_event(gst::event::Caps::new(&caps));
+        }
+
+        self.push_items(items)
+    }
+
+    fn typefind(
+        &self,
+        element: &super::ReqwestIcyDemux,
+        state: &mut State,
+    ) -> Result<gst::Caps, gst::FlowError> {
+        let mut data = Vec::new();
+        for item in &state.pending {
+            if let Item::Buffer(buffer) = item {
+                let map = buffer.map_readable().map_err(|_| gst::FlowError::Error)?;
+                data.extend_from_slice(&map);
+            }
+        }
+
+        match gst_base::type_find_helper_for_data(Some(element), &data) {
+            Ok((caps, probability)) => {
+                gst_debug!(
+                    CAT,
+                    obj: element,
+                    "Found caps {} with probability {:?}",
+                    caps,
+                    probability
+                );
+                state.need_typefind = false;
+
+                Ok(caps)
+            }
+            Err(_) => {
+                gst::element_error!(
+                    element,
+                    gst::StreamError::TypeNotFound,
+                    ["Could not detect the type of the stream"]
+                );
+
+                Err(gst::FlowError::Error)
+            }
+        }
+    }
+
+    fn push_items(&self, items: Vec<Item>) -> Result<gst::FlowSuccess, gst::FlowError> {
+        for item in items {
+            match item {
+                Item::Buffer(buffer) => {
+                    self.srcpad.push(buffer)?;
+                }
+                Item::Event(event) => {
+                    self.srcpad.push_event(event);
+                }
+            }
+        }
+
+        Ok(gst::FlowSuccess::Ok)
+    }
+
+    fn sink_event(
+        &self,
+        pad: &gst::Pad,
+        element: &super::ReqwestIcyDemux,
+        event: gst::Event,
+    ) -> bool {
+        use gst::EventView;
+
+        gst_log!(CAT, obj: pad, "Handling event {:?}", event);
+
+        match event.view() {
+            EventView::Caps(e) => {
+                let s = e.caps().structure(0).unwrap();
+
+                let metadata_interval = match s.get::<i32>("metadata-interval") {
+                    Ok(metadata_interval) if metadata_interval > 0 => metadata_interval as usize,
+                    _ => {
+                        gst_error!(CAT, obj: pad, "No metadata interval in caps {}", s);
+                        return false;
+                    }
+                };
+                let caps = s
+                    .get::<&str>("content-type")
+                    .ok()
+                    .and_then(content_type_to_caps);
+
+                let mut state = self.state.lock().unwrap();
+                state.metadata_interval = Some(metadata_interval);
+                state.parser = Parser::Data(metadata_interval);
+                state.need_typefind = caps.is_none();
+                drop(state);
+
+                match caps {
+                    Some(caps) => {
+                        gst_debug!(CAT, obj: pad, "Using caps {} from content type", caps);
+                        self.srcpad.push_event(gst::event::Caps::new(&caps))
+                    }
+                    None => {
+                        gst_debug!(CAT, obj: pad, "Unknown content type, typefinding");
+                        true
+                    }
+                }
+            }
+            EventView::Segment(_) => {
+                // The byte positions of upstream include the metadata blocks
+                let segment = gst::FormattedSegment::<gst::format::Bytes>::new();
+                let event = gst::event::Segment::builder(&segment)
+                    .seqnum(event.seqnum())
+                    .build();
+
+                self.forward_serialized_event(pad, element, event)
+            }
+            EventView::Tag(e) => {
+                let tags = e.tag_owned();
+                if tags.scope() == gst::TagScope::Stream {
+                    let mut state = self.state.lock().unwrap();
+                    state.upstream_tags = Some(tags);
+                }
+
+                self.forward_serialized_event(pad, element, event)
+            }
+            EventView::Eos(_) => {
+                let mut state = self.state.lock().unwrap();
+                let have_data = state
+                    .pending
+                    .iter()
+                    .any(|item| matches!(item, Item::Buffer(_)));
+                if state.need_typefind && have_data {
+                    let caps = match self.typefind(element, &mut state) {
+                        Ok(caps) => caps,
+                        Err(_) => return false,
+                    };
+                    let items = mem::take(&mut state.pending);
+                    drop(state);
+
+                    self.srcpad.push_event(gst::event::Caps::new(&caps));
+                    let _ = self.push_items(items);
+                } else {
+                    drop(state);
+                }
+
+                pad.event_default(Some(element), event)
+            }
+            EventView::FlushStop(_) => {
+                let mut state = self.state.lock().unwrap();
+                state.pending.clear();
+                drop(state);
+
+                pad.event_default(Some(element), event)
+            }
+            EventView::StreamStart(_) => pad.event_default(Some(element), event),
+            _ if event.is_serialized() => self.forward_serialized_event(pad, element, event),
+            _ => pad.event_default(Some(element), event),
+        }
+    }
+
+    fn forward_serialized_event(
+        &self,
+        pad: &gst::Pad,
+        element: &super::ReqwestIcyDemux,
+        event: gst::Event,
+    ) -> bool {
+        let mut state = self.state.lock().unwrap();
+        if state.need_typefind {
+            gst_trace!(CAT, obj: pad, "Queueing event {:?} until typefinding", event);
+            state.pending.push(Item::Event(event));
+            return true;
+        }
+        drop(state);
+
+        pad.event_default(Some(element), event)
+    }
+
+    fn src_event(
+        &self,
+        pad: &gst::Pad,
+        element: &super::ReqwestIcyDemux,
+        event: gst::Event,
+    ) -> bool {
+        use gst::EventView;
+
+        gst_log!(CAT, obj: pad, "Handling event {:?}", event);
+
+        match event.view() {
+            EventView::Seek(_) => {
+                gst_debug!(CAT, obj: pad, "Seeking is not supported");
+                false
+            }
+            _ => pad.event_default(Some(element), event),
+        }
+    }
+
+    fn src_query(
+        &self,
+        pad: &gst::Pad,
+        element: &super::ReqwestIcyDemux,
+        query: &mut gst::QueryRef,
+    ) -> bool {
+        use gst::QueryView;
+
+        gst_log!(CAT, obj: pad, "Handling query {:?}", query);
+
+        match query.view_mut() {
+            QueryView::Seeking(mut q) => {
+                let fmt = q.format();
+                q.set(
+                    false,
+                    gst::GenericFormattedValue::new(fmt, -1),
+                    gst::GenericFormattedValue::new(fmt, -1),
+                );
+                true
+            }
+            _ => pad.query_default(Some(element), query),
+        }
+    }
+}
+
+#[glib::object_subclass]
+impl ObjectSubclass for ReqwestIcyDemux {
+    const NAME: &'static str = "ReqwestIcyDemux";
+    type Type = super::ReqwestIcyDemux;
+    type ParentType = gst::Element;
+
+    fn with_class(klass: &Self::Class) -> Self {
+        let templ = klass.pad_template("sink").unwrap();
+        let sinkpad = gst::Pad::builder_with_template(&templ, Some("sink"))
+            .chain_function(|pad, parent, buffer| {
+                ReqwestIcyDemux::catch_panic_pad_function(
+                    parent,
+                    || Err(gst::FlowError::Error),
+                    |demux, element| demux.sink_chain(pad, element, buffer),
+                )
+            })
+            .event_function(|pad, parent, event| {
+                ReqwestIcyDemux::catch_panic_pad_function(
+                    parent,
+                    || false,
+                    |demux, element| demux.sink_event(pad, element, event),
+                )
+            })
+            .build();
+
+        let templ = klass.pad_template("src").unwrap();
+        let srcpad = gst::Pad::builder_with_template(&templ, Some("src"))
+            .event_function(|pad, parent, event| {
+                ReqwestIcyDemux::catch_panic_pad_function(
+                    parent,
+                    || false,
+                    |demux, element| demux.src_event(pad, element, event),
+                )
+            })
+            .query_function(|pad, parent, query| {
+                ReqwestIcyDemux::catch_panic_pad_function(
+                    parent,
+                    || false,
+                    |demux, element| demux.src_query(pad, element, query),
+                )
+            })
+            .build();
+
+        Self {
+            srcpad,
+            sinkpad,
+            state: Mutex::new(State::default()),
+        }
+    }
+}
+
+impl ObjectImpl for ReqwestIcyDemux {
+    fn constructed(&self, obj: &Self::Type) {
+        self.parent_constructed(obj);
+
+        obj.add_pad(&self.sinkpad).unwrap();
+        obj.add_pad(&self.srcpad).unwrap();
+    }
+}
+
+impl GstObjectImpl for ReqwestIcyDemux {}
+
+impl ElementImpl for ReqwestIcyDemux {
+    fn metadata() -> Option<&'static gst::subclass::ElementMetadata> {
+        static ELEMENT_METADATA: Lazy<gst::subclass::ElementMetadata> = Lazy::new(|| {
+            gst::subclass::ElementMetadata::new(
+                "ICY Demuxer",
+                "Codec/Demuxer/Metadata",
+                "Strip the interleaved ICY metadata from internet radio streams and output it as tags",
+                "Sebastian Dröge <sebastian@centricular.com>",
+            )
+        });
+
+        Some(&*ELEMENT_METADATA)
+    }
+
+    fn pad_templates() -> &'static [gst::PadTemplate] {
+        static PAD_TEMPLATES: Lazy<Vec<gst::PadTemplate>> = Lazy::new(|| {
+            let caps = gst::Caps::new_any();
+            let src_pad_template = gst::PadTemplate::new(
+                "src",
+                gst::PadDirection::Src,
+                gst::PadPresence::Always,
+                &caps,
+            )
+            .unwrap();
+
+            let caps = gst::Caps::builder("application/x-icy")
+                .field("metadata-interval", gst::IntRange::<i32>::new(1, i32::MAX))
+                .build();
+            let sink_pad_template = gst::PadTemplate::new(
+                "sink",
+                gst::PadDirection::Sink,
+                gst::PadPresence::Always,
+                &caps,
+            )
+            .unwrap();
+
+            vec![src_pad_template, sink_pad_template]
+        });
+
+        PAD_TEMPLATES.as_ref()
+    }
+
+    fn change_state(
+        &self,
+        element: &Self::Type,
+        transition: gst::StateChange,
+    ) -> Result<gst::StateChangeSuccess, gst::StateChangeError> {
+        gst_trace!(CAT, obj: element, "Changing state {:?}", transition);
+
+        match transition {
+            gst::StateChange::ReadyToPaused | gst::StateChange::PausedToReady => {
+                // Reset the whole state
+                let mut state = self.state.lock().unwrap();
+                *state = State::default();
+            }
+            _ => (),
+        }
+
+        self.parent_change_state(element, transition)
+    }
+}
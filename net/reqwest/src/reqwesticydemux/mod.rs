@@ -0,0 +1,28 @@
+// Copyright (C) 2026 agent <agent@local>
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+use gst::glib;
+use gst::prelude::*;
+
+mod imp;
+
+glib::wrapper! {
+    pub struct ReqwestIcyDemux(ObjectSubclass<imp::ReqwestIcyDemux>) @extends gst::Element, gst::Object;
+}
+
+unsafe impl Send for ReqwestIcyDemux {}
+unsafe impl Sync for ReqwestIcyDemux {}
+
+pub fn register(plugin: &gst::Plugin) -> Result<(), glib::BoolError> {
+    gst::Element::register(
+        Some(plugin),
+        "reqwesticydemux",
+        gst::Rank::Marginal,
+        ReqwestIcyDemux::static_type(),
+    )
+}
@@ -0,0 +1,356 @@
+// Copyright (C) 2026 agent <agent@local>
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+use gst::glib;
+use gst::prelude::*;
+use gst::subclass::prelude::*;
+use gst::{gst_debug, gst_warning};
+
+use once_cell::sync::Lazy;
+
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use reqwest::cookie::Jar;
+use reqwest::{Certificate, Client, Identity};
+use url::Url;
+
+use super::HttpVersion;
+
+static CAT: Lazy<gst::DebugCategory> = Lazy::new(|| {
+    gst::DebugCategory::new(
+        "reqwestsession",
+        gst::DebugColorFlags::empty(),
+        Some("Rust HTTP session"),
+    )
+});
+
+const DEFAULT_COOKIE_STORE: bool = true;
+const DEFAULT_HTTP_VERSION: HttpVersion = HttpVersion::Auto;
+const DEFAULT_CONNECT_TIMEOUT: u32 = 0;
+const DEFAULT_POOL_IDLE_TIMEOUT: u32 = 90;
+const DEFAULT_POOL_MAX_IDLE_PER_HOST: u32 = u32::MAX;
+
+#[derive(Debug, Clone)]
+struct Settings {
+    cookie_store: bool,
+    ca_file: Option<String>,
+    client_certificate_file: Option<String>,
+    client_certificate_password: Option<String>,
+    http_version: HttpVersion,
+    connect_timeout: u32,
+    pool_idle_timeout: u32,
+    pool_max_idle_per_host: u32,
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Settings {
+            cookie_store: DEFAULT_COOKIE_STORE,
+            ca_file: None,
+            client_certificate_file: None,
+            client_certificate_password: None,
+            http_version: DEFAULT_HTTP_VERSION,
+            connect_timeout: DEFAULT_CONNECT_TIMEOUT,
+            pool_idle_timeout: DEFAULT_POOL_IDLE_TIMEOUT,
+            pool_max_idle_per_host: DEFAULT_POOL_MAX_IDLE_PER_HOST,
+        }
+    }
+}
+
+#[derive(Default)]
+pub struct ReqwestSession {
+    settings: Mutex<Settings>,
+    cookies: Arc<Jar>,
+    client: Mutex<Option<Client>>,
+}
+
+impl ReqwestSession {
+    pub(super) fn client(
+        &self,
+        session: &super::ReqwestSession,
+    ) -> Result<Client, gst::ErrorMessage> {
+        let mut client_guard = self.client.lock().unwrap();
+        if let Some(ref client) = *client_guard {
+            return Ok(client.clone());
+        }
+
+        let settings = self.settings.lock().unwrap().clone();
+        gst_debug!(CAT, obj: session, "Creating new client");
+
+        let mut builder = Client::builder()
+            .gzip(true)
+            .pool_max_idle_per_host(settings.pool_max_idle_per_host as usize);
+
+        if settings.pool_idle_timeout == 0 {
+            builder = builder.pool_idle_timeout(None::<Duration>);
+        } else {
+            builder =
+                builder.pool_idle_timeout(Duration::from_secs(settings.pool_idle_timeout.into()));
+        }
+
+        if settings.connect_timeout != 0 {
+            builder = builder.connect_timeout(Duration::from_secs(settings.connect_timeout.into()));
+        }
+
+        if settings.cookie_store {
+            builder = builder.cookie_provider(self.cookies.clone());
+        }
+
+        builder = match settings.http_version {
+            HttpVersion::Auto => builder,
+            HttpVersion::Http1 => builder.http1_only(),
+            HttpVersion::Http2 => builder.http2_prior_knowledge(),
+        };
+
+        if let Some(ref ca_file) = settings.ca_file {
+            for certificate in load_certificates(ca_file)? {
+                builder = builder.add_root_certificate(certificate);
+            }
+        }
+
+        if let Some(ref client_certificate_file) = settings.client_certificate_file {
+            let der = std::fs::read(client_certificate_file).map_err(|err| {
+                gst::error_msg!(
+                    gst::ResourceError::OpenRead,
+                    [
+                        "Failed to read client certificate '{}': {}",
+                        client_certificate_file,
+                        err
+                    ]
+                )
+            })?;
+            let password = settings.client_certificate_password.as_deref();
+            let identity =
+                Identity::from_pkcs12_der(&der, password.unwrap_or("")).map_err(|err| {
+                    gst::error_msg!(
+                        gst::ResourceError::Settings,
+                        ["Invalid client certificate: {}", err]
+                    )
+                })?;
+            builder = builder.identity(identity);
+        }
+
+        let client = builder.build().map_err(|err| {
+            gst::error_msg!(
+                gst::ResourceError::OpenRead,
+                ["Failed to create Client: {}", err]
+            )
+        })?;
+
+        *client_guard = Some(client.clone());
+
+        Ok(client)
+    }
+
+    pub(super) fn add_cookie(
+        &self,
+        session: &super::ReqwestSession,
+        cookie: &str,
+        url: &str,
+    ) -> bool {
+        match Url::parse(url) {
+            Ok(url) => {
+                self.cookies.add_cookie_str(cookie, &url);
+                true
+            }
+            Err(err) => {
+                gst_warning!(CAT, obj: session, "Invalid cookie URL '{}': {}", url, err);
+                false
+            }
+        }
+    }
+}
+
+/// Loads all certificates of a PEM file, which usually contains a bundle of certificates.
+fn load_certificates(ca_file: &str) -> Result<Vec<Certificate>, gst::ErrorMessage> {
+    let pem = std::fs::read_to_string(ca_file).map_err(|err| {
+        gst::error_msg!(
+            gst::ResourceError::OpenRead,
+            ["Failed to read CA file '{}': {}", ca_file, err]
+        )
+    })?;
+
+    let certificates = pem
+        .split_inclusive("-----END CERTIFICATE-----")
+        .filter(|pem| pem.contains("-----BEGIN CERTIFICATE-----"))
+        .map(|pem| {
+            Certificate::from_pem(pem.as_bytes()).map_err(|err| {
+                gst::error_msg!(
+                    gst::ResourceError::Settings,
+                    ["Invalid certificate in CA file '{}': {}", ca_file, err]
+                )
+            })
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+
+    if certificates.is_empty() {
+        return Err(gst::error_msg!(
+            gst::ResourceError::Settings,
+            ["No certificates in CA file '{}'", ca_file]
+        ));
+    }
+
+    Ok(certificates)
+}
+
+#[glib::object_subclass]
+impl ObjectSubclass for ReqwestSession {
+    const NAME: &'static str = "ReqwestSession";
+    type Type = super::ReqwestSession;
+    type ParentType = glib::Object;
+}
+
+impl ObjectImpl for ReqwestSession {
+    fn properties() -> &'static [glib::ParamSpec] {
+        static PROPERTIES: Lazy<Vec<glib::ParamSpec>> = Lazy::new(|| {
+            vec![
+                glib::ParamSpecBoolean::new(
+                    "cookie-store",
+                    "Cookie Store",
+                    "Store the cookies of responses and send them with later requests",
+                    DEFAULT_COOKIE_STORE,
+                    glib::ParamFlags::READWRITE | glib::ParamFlags::CONSTRUCT_ONLY,
+                ),
+                glib::ParamSpecString::new(
+                    "ca-file",
+                    "CA File",
+                    "PEM file with additional certificate authorities to trust",
+                    None,
+                    glib::ParamFlags::READWRITE | glib::ParamFlags::CONSTRUCT_ONLY,
+                ),
+                glib::ParamSpecString::new(
+                    "client-certificate-file",
+                    "Client Certificate File",
+                    "PKCS #12 file with the certificate and private key for TLS client authentication",
+                    None,
+                    glib::ParamFlags::READWRITE | glib::ParamFlags::CONSTRUCT_ONLY,
+                ),
+                glib::ParamSpecString::new(
+                    "client-certificate-password",
+                    "Client Certificate Password",
+                    "Password of the client certificate file",
+                    None,
+                    glib::ParamFlags::READWRITE | glib::ParamFlags::CONSTRUCT_ONLY,
+                ),
+                glib::ParamSpecEnum::new(
+                    "http-version",
+                    "HTTP Version",
+                    "HTTP version to use for requests",
+                    HttpVersion::static_type(),
+                    DEFAULT_HTTP_VERSION as i32,
+                    glib::ParamFlags::READWRITE | glib::ParamFlags::CONSTRUCT_ONLY,
+                ),
+                glib::ParamSpecUInt::new(
+                    "connect-timeout",
+                    "Connect Timeout",
+                    "Value in seconds to timeout connecting to a server (0 = No timeout)",
+                    0,
+                    3600,
+                    DEFAULT_CONNECT_TIMEOUT,
+                    glib::ParamFlags::READWRITE | glib::ParamFlags::CONSTRUCT_ONLY,
+                ),
+                glib::ParamSpecUInt::new(
+                    "pool-idle-timeout",
+                    "Pool Idle Timeout",
+                    "Value in seconds after which idle connections are closed (0 = Never)",
+                    0,
+                    u32::MAX,
+                    DEFAULT_POOL_IDLE_TIMEOUT,
+                    glib::ParamFlags::READWRITE | glib::ParamFlags::CONSTRUCT_ONLY,
+                ),
+                glib::ParamSpecUInt::new(
+                    "pool-max-idle-per-host",
+                    "Pool Max Idle Per Host",
+                    "Maximum number of idle connections kept per host",
+                    0,
+                    u32::MAX,
+                    DEFAULT_POOL_MAX_IDLE_PER_HOST,
+                    glib::ParamFlags::READWRITE | glib::ParamFlags::CONSTRUCT_ONLY,
+                ),
+            ]
+        });
+
+        PROPERTIES.as_ref()
+    }
+
+    fn signals() -> &'static [glib::subclass::Signal] {
+        static SIGNALS: Lazy<Vec<glib::subclass::Signal>> = Lazy::new(|| {
+            vec![glib::subclass::Signal::builder(
+                "add-cookie",
+                &[String::static_type().into(), String::static_type().into()],
+                bool::static_type().into(),
+            )
+            .action()
+            .class_handler(|_, args| {
+                let session = args[0].get::<super::ReqwestSession>().expect("signal arg");
+                let cookie = args[1].get::<String>().expect("signal arg");
+                let url = args[2].get::<String>().expect("signal arg");
+
+                Some(session.add_cookie(&cookie, &url).to_value())
+            })
+            .build()]
+        });
+
+        SIGNALS.as_ref()
+    }
+
+    fn set_property(
+        &self,
+        _obj: &Self::Type,
+        _id: usize,
+        value: &glib::Value,
+        pspec: &glib::ParamSpec,
+    ) {
+        let mut settings = self.settings.lock().unwrap();
+
+        match pspec.name() {
+            "cookie-store" => {
+                settings.cookie_store = value.get().expect("type checked upstream");
+            }
+            "ca-file" => {
+                settings.ca_file = value.get().expect("type checked upstream");
+            }
+            "client-certificate-file" => {
+                settings.client_certificate_file = value.get().expect("type checked upstream");
+            }
+            "client-certificate-password" => {
+                settings.client_certificate_password = value.get().expect("type checked upstream");
+            }
+            "http-version" => {
+                settings.http_version = value.get().expect("type checked upstream");
+            }
+            "connect-timeout" => {
+                settings.connect_timeout = value.get().expect("type checked upstream");
+            }
+            "pool-idle-timeout" => {
+                settings.pool_idle_timeout = value.get().expect("type checked upstream");
+            }
+            "pool-max-idle-per-host" => {
+                settings.pool_max_idle_per_host = value.get().expect("type checked upstream");
+            }
+            _ => unimplemented!(),
+        }
+    }
+
+    fn property(&self, _obj: &Self::Type, _id: usize, pspec: &glib::ParamSpec) -> glib::Value {
+        let settings = self.settings.lock().unwrap();
+
+        match pspec.name() {
+            "cookie-store" => settings.cookie_store.to_value(),
+            "ca-file" => settings.ca_file.to_value(),
+            "client-certificate-file" => settings.client_certificate_file.to_value(),
+            "client-certificate-password" => settings.client_certificate_password.to_value(),
+            "http-version" => settings.http_version.to_value(),
+            "connect-timeout" => settings.connect_timeout.to_value(),
+            "pool-idle-timeout" => settings.pool_idle_timeout.to_value(),
+            "pool-max-idle-per-host" => settings.pool_max_idle_per_host.to_value(),
+            _ => unimplemented!(),
+        }
+    }
+}
@@ -0,0 +1,81 @@
+// Copyright (C) 2026 agent <agent@local>
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+use gst::glib;
+use gst::subclass::prelude::*;
+
+use crate::client::REQWEST_CLIENT_CONTEXT;
+
+mod imp;
+
+#[derive(Debug, Eq, PartialEq, Ord, PartialOrd, Hash, Clone, Copy, glib::Enum)]
+#[repr(u32)]
+#[enum_type(name = "GstReqwestSessionHttpVersion")]
+pub enum HttpVersion {
+    #[enum_value(
+        name = "Auto: Negotiate HTTP/2 for TLS connections and use HTTP/1.1 otherwise.",
+        nick = "auto"
+    )]
+    Auto,
+    #[enum_value(name = "Http1: Only use HTTP/1.1.", nick = "http1")]
+    Http1,
+    #[enum_value(
+        name = "Http2: Only use HTTP/2, also for connections without TLS.",
+        nick = "http2"
+    )]
+    Http2,
+}
+
+glib::wrapper! {
+    /// HTTP session that is shared by the elements of the plugin.
+    ///
+    /// The session owns the connection pool, the cookie jar and the TLS configuration. It is
+    /// shared with elements and pipelines via a `gst.reqwest.client` context that stores it in its
+    /// `session` field, see `ReqwestSession::context()`. Elements without a context create their
+    /// own session and share it with the other elements of their pipeline.
+    ///
+    /// All properties are construct-only as the underlying client is created on first use.
+    pub struct ReqwestSession(ObjectSubclass<imp::ReqwestSession>);
+}
+
+unsafe impl Send for ReqwestSession {}
+unsafe impl Sync for ReqwestSession {}
+
+impl ReqwestSession {
+    pub fn new() -> ReqwestSession {
+        glib::Object::new(&[]).expect("Failed to create session")
+    }
+
+    /// Creates a context for sharing the session with elements and pipelines.
+    pub fn context(&self) -> gst::Context {
+        let mut context = gst::Context::new(REQWEST_CLIENT_CONTEXT, true);
+        {
+            let context = context.get_mut().unwrap();
+            let s = context.structure_mut();
+            s.set("session", self);
+        }
+
+        context
+    }
+
+    /// Adds a cookie in `Set-Cookie` header format for `url` to the cookie jar of the session.
+    pub fn add_cookie(&self, cookie: &str, url: &str) -> bool {
+        imp::ReqwestSession::from_instance(self).add_cookie(self, cookie, url)
+    }
+
+    /// Returns the client of the session, creating it on first use.
+    pub(crate) fn client(&self) -> Result<reqwest::Client, gst::ErrorMessage> {
+        imp::ReqwestSession::from_instance(self).client(self)
+    }
+}
+
+impl Default for ReqwestSession {
+    fn default() -> Self {
+        Self::new()
+    }
+}
@@ -0,0 +1,326 @@
+// Copyright (C) 2026 agent <agent@local>
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+use gst::prelude::*;
+
+use std::sync::mpsc;
+use std::time::Duration;
+
+fn init() {
+    use std::sync::Once;
+    static INIT: Once = Once::new();
+
+    INIT.call_once(|| {
+        // clear this environment because it affects the default settings
+        std::env::remove_var("http_proxy");
+        gst::init().unwrap();
+        gstreqwest::plugin_register_static().expect("reqwesthttpsink tests");
+    });
+}
+
+/// A request received by the HTTP server of the test harness
+#[derive(Debug)]
+struct Request {
+    method: hyper::Method,
+    path: String,
+    headers: hyper::HeaderMap,
+    body: Vec<u8>,
+}
+
+/// Our custom test harness around the HTTP sink
+struct Harness {
+    sink: gst::Element,
+    pad: gst::Pad,
+    bus: gst::Bus,
+    requests: mpsc::Receiver<Request>,
+    rt: Option<tokio::runtime::Runtime>,
+}
+
+impl Harness {
+    /// Creates a new HTTP sink and test harness around it
+    ///
+    /// `status`: Status code of all HTTP responses
+    /// `setup_func`: Setup function for the HTTP sink, gets the URL of the HTTP server
+    fn new<G: FnOnce(&gst::Element, &str)>(status: hyper::StatusCode, setup_func: G) -> Harness {
+        use hyper::service::{make_service_fn, service_fn};
+        use hyper::{Body, Response, Server};
+
+        // Create the HTTP sink
+        let sink = gst::ElementFactory::make("reqwesthttpsink", None).unwrap();
+        sink.set_property("sync", false);
+        sink.set_property("async", false);
+
+        // Source pad that pushes everything into the sink
+        let pad = gst::Pad::new(Some("src"), gst::PadDirection::Src);
+        pad.link(&sink.static_pad("sink").unwrap()).unwrap();
+
+        let bus = gst::Bus::new();
+        sink.set_bus(Some(&bus));
+
+        // Create the tokio runtime used for the HTTP server in this test
+        let rt = tokio::runtime::Builder::new_multi_thread()
+            .enable_all()
+            .build()
+            .unwrap();
+
+        // Every request is sent to the test after its body was received completely
+        let (sender, requests) = mpsc::sync_channel(16);
+        let make_service = make_service_fn(move |_ctx| {
+            let sender = sender.clone();
+            async move {
+                Ok::<_, hyper::Error>(service_fn(move |req: hyper::Request<Body>| {
+                    let sender = sender.clone();
+                    async move {
+                        let (parts, body) = req.into_parts();
+                        let body = hyper::body::to_bytes(body).await?;
+
+                        let _ = sender.send(Request {
+                            method: parts.method,
+                            path: parts.uri.path().to_string(),
+                            headers: parts.headers,
+                            body: body.to_vec(),
+                        });
+
+                        Ok::<_, hyper::Error>(
+                            Response::builder()
+                                .status(status)
+                                .body(Body::empty())
+                                .unwrap(),
+                        )
+                    }
+                }))
+            }
+        });
+
+        // Create an HTTP sever that listens on localhost on some random, free port
+        let addr = ([127, 0, 0, 1], 0).into();
+        let (local_addr_sender, local_addr_receiver) = tokio::sync::oneshot::channel();
+
+        // Spawn the server in the background so that it can handle requests
+        rt.spawn(async move {
+            let server = Server::bind(&addr).serve(make_service);
+            local_addr_sender.send(server.local_addr()).unwrap();
+
+            let _ = server.await;
+        });
+
+        let local_addr = futures::executor::block_on(local_addr_receiver).unwrap();
+        let url = format!("http://{}/", local_addr);
+        sink.set_property("location", &url);
+
+        // Let the test setup anything needed on the HTTP sink now
+        setup_func(&sink, &url);
+
+        Harness {
+            sink,
+            pad,
+            bus,
+            requests,
+            rt: Some(rt),
+        }
+    }
+
+    /// Starts the sink and sends the initial events
+    fn play(&self) {
+        self.pad.set_active(true).unwrap();
+        self.sink.set_state(gst::State::Playing).unwrap();
+
+        assert!(self.pad.push_event(gst::event::StreamStart::new("test")));
+        assert!(self.pad.push_event(gst::event::Caps::new(
+            &gst::Caps::builder("application/octet-stream").build()
+        )));
+        let segment = gst::FormattedSegment::<gst::ClockTime>::new();
+        assert!(self.pad.push_event(gst::event::Segment::new(&segment)));
+    }
+
+    fn push(
+        &self,
+        data: &'static [u8],
+        flags: gst::BufferFlags,
+    ) -> Result<gst::FlowSuccess, gst::FlowError> {
+        let mut buffer = gst::Buffer::from_slice(data);
+        buffer.get_mut().unwrap().set_flags(flags);
+
+        self.pad.push(buffer)
+    }
+
+    fn push_eos(&self) -> bool {
+        self.pad.push_event(gst::event::Eos::new())
+    }
+
+    /// Waits for the next request the HTTP server received
+    fn wait_for_request(&self) -> Request {
+        self.requests
+            .recv_timeout(Duration::from_secs(10))
+            .expect("No request received")
+    }
+
+    /// Waits for EOS or an error message from the sink
+    fn wait_for_message(&self) -> gst::Message {
+        self.bus
+            .timed_pop_filtered(
+                gst::ClockTime::from_seconds(10),
+                &[gst::MessageType::Eos, gst::MessageType::Error],
+            )
+            .expect("No message received")
+    }
+}
+
+impl Drop for Harness {
+    fn drop(&mut self) {
+        // Shut down everything that was set up for this test harness
+        // and wait until the tokio runtime exited
+        self.pad.set_active(false).unwrap();
+        self.sink.set_state(gst::State::Null).unwrap();
+
+        self.rt.take().unwrap();
+    }
+}
+
+#[test]
+fn test_chunked_post() {
+    init();
+
+    let h = Harness::new(hyper::StatusCode::OK, |sink, _url| {
+        sink.set_property("user-agent", "test user-agent");
+        sink.set_property("user-id", "user");
+        sink.set_property("user-pw", "password");
+        sink.set_property(
+            "extra-headers",
+            gst::Structure::builder("headers")
+                .field("foo", "bar")
+                .build(),
+        );
+    });
+
+    h.play();
+    h.push(b"Hello ", gst::BufferFlags::empty()).unwrap();
+    h.push(b"World", gst::BufferFlags::empty()).unwrap();
+    assert!(h.push_eos());
+
+    // Everything is streamed in a single chunked request
+    let req = h.wait_for_request();
+    assert_eq!(req.method, hyper::Method::POST);
+    assert_eq!(req.path, "/");
+    assert_eq!(req.headers.get("transfer-encoding").unwrap(), "chunked");
+    assert_eq!(req.headers.get("user-agent").unwrap(), "test user-agent");
+    assert_eq!(req.headers.get("foo").unwrap(), "bar");
+    assert_eq!(
+        req.headers.get("authorization").unwrap(),
+        "Basic dXNlcjpwYXNzd29yZA=="
+    );
+    assert_eq!(req.body, b"Hello World");
+
+    assert_eq!(h.wait_for_message().type_(), gst::MessageType::Eos);
+}
+
+#[test]
+fn test_put() {
+    init();
+
+    let h = Harness::new(hyper::StatusCode::CREATED, |sink, _url| {
+        sink.set_property_from_str("method", "put");
+    });
+
+    h.play();
+    h.push(b"Hello World", gst::BufferFlags::empty()).unwrap();
+    assert!(h.push_eos());
+
+    let req = h.wait_for_request();
+    assert_eq!(req.method, hyper::Method::PUT);
+    assert_eq!(req.body, b"Hello World");
+
+    assert_eq!(h.wait_for_message().type_(), gst::MessageType::Eos);
+}
+
+#[test]
+fn test_key_unit_fragments() {
+    init();
+
+    let h = Harness::new(hyper::StatusCode::OK, |sink, url| {
+        sink.set_property("location", format!("{}fragment%05d.ts", url));
+        sink.set_property_from_str("fragment-mode", "key-unit");
+    });
+
+    h.play();
+    h.push(b"A", gst::BufferFlags::empty()).unwrap();
+    h.push(b"a", gst::BufferFlags::DELTA_UNIT).unwrap();
+    h.push(b"B", gst::BufferFlags::empty()).unwrap();
+    h.push(b"b", gst::BufferFlags::DELTA_UNIT).unwrap();
+    assert!(h.push_eos());
+
+    // Each key unit starts a new request
+    let req = h.wait_for_request();
+    assert_eq!(req.method, hyper::Method::PUT);
+    assert_eq!(req.path, "/fragment00000.ts");
+    assert_eq!(req.body, b"Aa");
+
+    let req = h.wait_for_request();
+    assert_eq!(req.method, hyper::Method::PUT);
+    assert_eq!(req.path, "/fragment00001.ts");
+    assert_eq!(req.body, b"Bb");
+
+    assert_eq!(h.wait_for_message().type_(), gst::MessageType::Eos);
+}
+
+#[test]
+fn test_discont_fragments_with_get_location() {
+    init();
+
+    let h = Harness::new(hyper::StatusCode::OK, |sink, url| {
+        sink.set_property_from_str("fragment-mode", "discont");
+
+        let url = url.to_string();
+        sink.connect("get-location", false, move |args| {
+            let index = args[1].get::<u32>().expect("No index given");
+
+            Some(format!("{}custom/{}", url, index).to_value())
+        });
+    });
+
+    h.play();
+    h.push(b"A", gst::BufferFlags::empty()).unwrap();
+    h.push(b"a", gst::BufferFlags::empty()).unwrap();
+    h.push(b"B", gst::BufferFlags::DISCONT).unwrap();
+    assert!(h.push_eos());
+
+    // The locations of the fragments are taken from the signal
+    let req = h.wait_for_request();
+    assert_eq!(req.path, "/custom/0");
+    assert_eq!(req.body, b"Aa");
+
+    let req = h.wait_for_request();
+    assert_eq!(req.path, "/custom/1");
+    assert_eq!(req.body, b"B");
+
+    assert_eq!(h.wait_for_message().type_(), gst::MessageType::Eos);
+}
+
+#[test]
+fn test_404_error() {
+    init();
+
+    let h = Harness::new(hyper::StatusCode::NOT_FOUND, |_sink, _url| {});
+
+    h.play();
+    h.push(b"Hello World", gst::BufferFlags::empty()).unwrap();
+
+    // The response is only known once the body was completed
+    assert!(!h.push_eos());
+
+    let msg = h.wait_for_message();
+    match msg.view() {
+        gst::MessageView::Error(err) => {
+            assert_eq!(
+                err.error().kind::<gst::ResourceError>(),
+                Some(gst::ResourceError::NotFound)
+            );
+        }
+        _ => panic!("Expected error but got {:?}", msg),
+    }
+}
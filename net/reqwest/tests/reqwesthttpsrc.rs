@@ -54,13 +54,27 @@ impl Harness {
     >(
         http_func: F,
         setup_func: G,
+    ) -> Harness {
+        Harness::new_with_element("reqwesthttpsrc", http_func, setup_func)
+    }
+
+    /// Creates a new source from `factory` and test harness around it
+    ///
+    /// The `location` of the source points to the root of the HTTP server.
+    fn new_with_element<
+        F: FnMut(hyper::Request<hyper::Body>) -> hyper::Response<hyper::Body> + Send + 'static,
+        G: FnOnce(&gst::Element),
+    >(
+        factory: &str,
+        http_func: F,
+        setup_func: G,
     ) -> Harness {
         use hyper::service::{make_service_fn, service_fn};
         use hyper::Server;
         use std::sync::{Arc, Mutex};
 
-        // Create the HTTP source
-        let src = gst::ElementFactory::make("reqwesthttpsrc", None).unwrap();
+        // Create the source
+        let src = gst::ElementFactory::make(factory, None).unwrap();
 
         // Sender/receiver for the messages we generate from various places for the tests
         //
@@ -1184,6 +1198,85 @@ fn test_cookies() {
     assert_eq!(num_bytes, 12);
 }
 
+#[test]
+fn test_shared_session() {
+    init();
+
+    let session = gstreqwest::ReqwestSession::new();
+
+    // Set up a harness that checks if the cookie added to the session by the application is sent
+    let mut h = Harness::new(
+        |req| {
+            use hyper::{Body, Response};
+
+            let headers = req.headers();
+            let cookies = headers
+                .get("Cookie")
+                .expect("No cookies set")
+                .to_str()
+                .unwrap();
+            assert!(cookies.split(';').any(|c| c == "foo=bar"));
+            Response::builder().body(Body::from("Hello World")).unwrap()
+        },
+        {
+            let session = session.clone();
+            move |src| {
+                src.set_context(&session.context());
+            }
+        },
+    );
+
+    let location = h.src.property::<String>("location");
+    assert!(session.emit_by_name::<bool>("add-cookie", &[&"foo=bar", &location]));
+
+    // Set the HTTP source to Playing so that everything can start
+    h.run(|src| {
+        src.set_state(gst::State::Playing).unwrap();
+    });
+
+    let mut num_bytes = 0;
+    while let Some(buffer) = h.wait_buffer_or_eos() {
+        num_bytes += buffer.size();
+    }
+    assert_eq!(num_bytes, 11);
+
+    // The source must not have replaced the session of the application
+    let context = h.src.context("gst.reqwest.client").expect("No context");
+    let shared_session = context
+        .structure()
+        .get::<gstreqwest::ReqwestSession>("session")
+        .unwrap();
+    assert_eq!(shared_session, session);
+}
+
+#[test]
+fn test_session_invalid_ca_file() {
+    init();
+
+    let session = glib::Object::new::<gstreqwest::ReqwestSession>(&[(
+        "ca-file",
+        &"/this/file/does/not/exist.pem",
+    )])
+    .unwrap();
+
+    let mut h = Harness::new(
+        |_req| unreachable!(),
+        move |src| {
+            src.set_context(&session.context());
+        },
+    );
+
+    h.run(|src| {
+        let _ = src.set_state(gst::State::Playing);
+    });
+
+    let err_code = h.wait_for_error();
+    assert_eq!(
+        err_code.kind::<gst::ResourceError>(),
+        Some(gst::ResourceError::OpenRead)
+    );
+}
+
 #[test]
 fn test_proxy_prop_souphttpsrc_compatibility() {
     init();
@@ -1285,3 +1378,444 @@ fn test_proxy() {
     // Don't leave threads hanging around.
     proxy_server.join().unwrap();
 }
+
+/// Creates a response body that sends `data` and then fails
+fn aborted_body(data: &'static str) -> hyper::Body {
+    let (mut sender, body) = hyper::Body::channel();
+    sender.try_send_data(data.into()).unwrap();
+    sender.abort();
+
+    body
+}
+
+#[test]
+fn test_retry_resumes_seekable_resource() {
+    init();
+
+    // The first response is aborted after "Hello ", the second request has to resume from there
+    let mut requests = 0;
+    let mut h = Harness::new(
+        move |req| {
+            use hyper::{Body, Response};
+
+            requests += 1;
+            match requests {
+                1 => {
+                    assert_eq!(req.headers().get("Range"), None);
+                    Response::builder()
+                        .header("content-length", 11)
+                        .header("accept-ranges", "bytes")
+                        .body(aborted_body("Hello "))
+                        .unwrap()
+                }
+                2 => {
+                    assert_eq!(req.headers().get("Range").unwrap(), "bytes=6-");
+                    Response::builder()
+                        .status(hyper::StatusCode::PARTIAL_CONTENT)
+                        .header("content-length", 5)
+                        .header("accept-ranges", "bytes")
+                        .header("content-range", "bytes 6-10/11")
+                        .body(Body::from("World"))
+                        .unwrap()
+                }
+                _ => panic!("Unexpected request"),
+            }
+        },
+        |src| {
+            src.set_property("retry-delay", 10u32);
+        },
+    );
+
+    h.run(|src| {
+        src.set_state(gst::State::Playing).unwrap();
+    });
+
+    // The data is continuous, including the offsets
+    let mut data = Vec::new();
+    while let Some(buffer) = h.wait_buffer_or_eos() {
+        assert_eq!(buffer.offset(), data.len() as u64);
+        assert!(!buffer.flags().contains(gst::BufferFlags::DISCONT));
+        data.extend_from_slice(&buffer.map_readable().unwrap());
+    }
+
+    assert_eq!(data, b"Hello World");
+}
+
+#[test]
+fn test_retry_reconnects_non_seekable_resource() {
+    init();
+
+    // The first response is aborted after "Hello ", the second request starts a new response
+    let mut requests = 0;
+    let mut h = Harness::new(
+        move |req| {
+            use hyper::{Body, Response};
+
+            assert_eq!(req.headers().get("Range"), None);
+
+            requests += 1;
+            match requests {
+                1 => Response::new(aborted_body("Hello ")),
+                2 => Response::new(Body::from("World")),
+                _ => panic!("Unexpected request"),
+            }
+        },
+        |src| {
+            src.set_property("retry-delay", 10u32);
+        },
+    );
+
+    h.run(|src| {
+        src.set_state(gst::State::Playing).unwrap();
+    });
+
+    // The first buffer after reconnecting is marked as discontinuous
+    let mut buffers = Vec::new();
+    while let Some(buffer) = h.wait_buffer_or_eos() {
+        let map = buffer.map_readable().unwrap();
+        buffers.push((
+            String::from_utf8(map.to_vec()).unwrap(),
+            buffer.offset(),
+            buffer.flags().contains(gst::BufferFlags::DISCONT),
+        ));
+    }
+
+    assert_eq!(
+        buffers,
+        vec![
+            (String::from("Hello "), 0, false),
+            (String::from("World"), 6, true)
+        ]
+    );
+}
+
+#[test]
+fn test_retries_exhausted() {
+    init();
+
+    // The first response is aborted after "Hello " and reconnecting fails afterwards
+    let mut requests = 0;
+    let mut h = Harness::new(
+        move |_req| {
+            use hyper::{Body, Response};
+
+            requests += 1;
+            match requests {
+                1 => Response::new(aborted_body("Hello ")),
+                2 | 3 => Response::builder()
+                    .status(hyper::StatusCode::SERVICE_UNAVAILABLE)
+                    .body(Body::empty())
+                    .unwrap(),
+                _ => panic!("Unexpected request"),
+            }
+        },
+        |src| {
+            src.set_property("retries", 2i32);
+            src.set_property("retry-delay", 10u32);
+        },
+    );
+
+    h.run(|src| {
+        src.set_state(gst::State::Playing).unwrap();
+    });
+
+    let buffer = h.wait_buffer_or_eos().unwrap();
+    assert_eq!(&*buffer.map_readable().unwrap(), b"Hello ");
+
+    // The error of the last attempt is reported
+    let err_code = h.wait_for_error();
+    if let Some(err) = err_code.kind::<gst::ResourceError>() {
+        assert_eq!(err, gst::ResourceError::OpenRead);
+    } else {
+        panic!("unexpected error : {:?}", err_code);
+    }
+}
+
+/// Serves an HLS media playlist and its segments
+fn hls_media_response(
+    req: &hyper::Request<hyper::Body>,
+    playlist: &str,
+) -> hyper::Response<hyper::Body> {
+    use hyper::{Body, Response};
+
+    match req.uri().path() {
+        "/" => Response::new(Body::from(playlist.to_string())),
+        path => Response::new(Body::from(format!("Segment {}", &path[1..]))),
+    }
+}
+
+#[test]
+fn test_hls_media_playlist() {
+    init();
+
+    let playlist = "#EXTM3U
+#EXT-X-VERSION:3
+#EXT-X-TARGETDURATION:2
+#EXT-X-MEDIA-SEQUENCE:0
+#EXTINF:2.0,
+segment0.ts
+#EXTINF:2.0,
+segment1.ts
+#EXTINF:2.0,
+segment2.ts
+#EXT-X-ENDLIST
+";
+
+    // Set up a harness that serves the playlist and checks if the client settings are used for
+    // all requests
+    let mut h = Harness::new_with_element(
+        "reqwesthlssrc",
+        move |req| {
+            assert_eq!(req.headers().get("user-agent").unwrap(), "test user-agent");
+            assert_eq!(req.headers().get("foo").unwrap(), "bar");
+
+            hls_media_response(&req, playlist)
+        },
+        |src| {
+            src.set_property("user-agent", "test user-agent");
+            src.set_property(
+                "extra-headers",
+                gst::Structure::builder("headers")
+                    .field("foo", "bar")
+                    .build(),
+            );
+        },
+    );
+
+    h.run(|src| {
+        src.set_state(gst::State::Playing).unwrap();
+    });
+
+    // Each segment is output as one buffer, in playlist order
+    let mut segments = Vec::new();
+    while let Some(buffer) = h.wait_buffer_or_eos() {
+        assert_eq!(
+            buffer.flags().contains(gst::BufferFlags::DISCONT),
+            segments.is_empty()
+        );
+        let map = buffer.map_readable().unwrap();
+        segments.push(String::from_utf8(map.to_vec()).unwrap());
+    }
+
+    assert_eq!(
+        segments,
+        vec![
+            "Segment segment0.ts",
+            "Segment segment1.ts",
+            "Segment segment2.ts"
+        ]
+    );
+}
+
+#[test]
+fn test_hls_live_playlist_refresh() {
+    init();
+
+    // The first playlist request returns two segments, later ones an additional segment and
+    // the end of the stream
+    let mut playlist_requests = 0;
+    let mut h = Harness::new_with_element(
+        "reqwesthlssrc",
+        move |req| {
+            let mut playlist = String::from(
+                "#EXTM3U
+#EXT-X-VERSION:3
+#EXT-X-TARGETDURATION:1
+#EXT-X-MEDIA-SEQUENCE:0
+#EXTINF:1.0,
+segment0.ts
+#EXTINF:1.0,
+segment1.ts
+",
+            );
+
+            if req.uri().path() == "/" {
+                playlist_requests += 1;
+                if playlist_requests > 1 {
+                    playlist.push_str("#EXTINF:1.0,\nsegment2.ts\n#EXT-X-ENDLIST\n");
+                }
+            }
+
+            hls_media_response(&req, &playlist)
+        },
+        |_src| {},
+    );
+
+    h.run(|src| {
+        src.set_state(gst::State::Playing).unwrap();
+    });
+
+    let mut segments = Vec::new();
+    while let Some(buffer) = h.wait_buffer_or_eos() {
+        let map = buffer.map_readable().unwrap();
+        segments.push(String::from_utf8(map.to_vec()).unwrap());
+    }
+
+    assert_eq!(
+        segments,
+        vec![
+            "Segment segment0.ts",
+            "Segment segment1.ts",
+            "Segment segment2.ts"
+        ]
+    );
+}
+
+#[test]
+fn test_hls_master_playlist_switches_variant() {
+    init();
+
+    const SEGMENT_SIZE: usize = 100_000;
+
+    // Set up a harness with a low and a high bandwidth variant. The measured local throughput
+    // is far above the bandwidth of the high variant.
+    let mut h = Harness::new_with_element(
+        "reqwesthlssrc",
+        |req| {
+            use hyper::{Body, Response};
+
+            let media_playlist = |variant: &str| {
+                format!(
+                    "#EXTM3U
+#EXT-X-VERSION:3
+#EXT-X-TARGETDURATION:2
+#EXT-X-MEDIA-SEQUENCE:0
+#EXTINF:2.0,
+{variant}0.ts
+#EXTINF:2.0,
+{variant}1.ts
+#EXTINF:2.0,
+{variant}2.ts
+#EXT-X-ENDLIST
+",
+                    variant = variant
+                )
+            };
+
+            match req.uri().path() {
+                "/" => Response::new(Body::from(
+                    "#EXTM3U
+#EXT-X-VERSION:3
+#EXT-X-STREAM-INF:BANDWIDTH=1000000
+high.m3u8
+#EXT-X-STREAM-INF:BANDWIDTH=1000
+low.m3u8
+",
+                )),
+                "/low.m3u8" => Response::new(Body::from(media_playlist("low"))),
+                "/high.m3u8" => Response::new(Body::from(media_playlist("high"))),
+                path if path.starts_with("/low") => {
+                    Response::new(Body::from(vec![b'l'; SEGMENT_SIZE]))
+                }
+                path if path.starts_with("/high") => {
+                    Response::new(Body::from(vec![b'h'; SEGMENT_SIZE]))
+                }
+                path => panic!("Unexpected request for {}", path),
+            }
+        },
+        |src| {
+            // Only download one segment at a time so that the variant is selected for each
+            // segment
+            src.set_property("prefetch-segments", 0u32);
+        },
+    );
+
+    h.run(|src| {
+        src.set_state(gst::State::Playing).unwrap();
+    });
+
+    // Playback starts with the lowest variant and switches to the highest one after the
+    // throughput was measured, with a discontinuity at the switch
+    let mut segments = Vec::new();
+    while let Some(buffer) = h.wait_buffer_or_eos() {
+        assert_eq!(buffer.size(), SEGMENT_SIZE);
+        let map = buffer.map_readable().unwrap();
+        segments.push((map[0], buffer.flags().contains(gst::BufferFlags::DISCONT)));
+    }
+
+    assert_eq!(segments, vec![(b'l', true), (b'h', true), (b'h', false)]);
+}
+
+#[test]
+fn test_hls_cmaf_playlist() {
+    init();
+
+    const MEDIA: &[u8] = b"INITmedia1media2";
+
+    let playlist = "#EXTM3U
+#EXT-X-VERSION:7
+#EXT-X-TARGETDURATION:2
+#EXT-X-MEDIA-SEQUENCE:0
+#EXT-X-MAP:URI=\"init.mp4\"
+#EXTINF:2.0,
+segment0.m4s
+#EXTINF:2.0,
+segment1.m4s
+#EXT-X-MAP:URI=\"media.mp4\",BYTERANGE=\"4@0\"
+#EXTINF:2.0,
+#EXT-X-BYTERANGE:6@4
+media.mp4
+#EXTINF:2.0,
+#EXT-X-BYTERANGE:6
+media.mp4
+#EXT-X-ENDLIST
+";
+
+    // Set up a harness that serves byte ranges of the media file
+    let mut h = Harness::new_with_element(
+        "reqwesthlssrc",
+        move |req| {
+            use hyper::{Body, Response, StatusCode};
+
+            if req.uri().path() != "/media.mp4" {
+                return hls_media_response(&req, playlist);
+            }
+
+            let range = req
+                .headers()
+                .get("range")
+                .expect("No byte range requested")
+                .to_str()
+                .unwrap();
+            let (start, end) = range
+                .strip_prefix("bytes=")
+                .and_then(|range| range.split_once('-'))
+                .unwrap();
+            let (start, end) = (
+                start.parse::<usize>().unwrap(),
+                end.parse::<usize>().unwrap(),
+            );
+
+            Response::builder()
+                .status(StatusCode::PARTIAL_CONTENT)
+                .header(
+                    "content-range",
+                    format!("bytes {}-{}/{}", start, end, MEDIA.len()),
+                )
+                .body(Body::from(&MEDIA[start..=end]))
+                .unwrap()
+        },
+        |_src| {},
+    );
+
+    h.run(|src| {
+        src.set_state(gst::State::Playing).unwrap();
+    });
+
+    let mut segments = Vec::new();
+    while let Some(buffer) = h.wait_buffer_or_eos() {
+        let map = buffer.map_readable().unwrap();
+        segments.push(String::from_utf8(map.to_vec()).unwrap());
+    }
+
+    // Each initialization section is output once before the first segment that uses it
+    assert_eq!(
+        segments,
+        vec![
+            "Segment init.mp4Segment segment0.m4s",
+            "Segment segment1.m4s",
+            "INITmedia1",
+            "media2",
+        ]
+    );
+}
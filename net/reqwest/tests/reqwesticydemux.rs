@@ -0,0 +1,155 @@
+// Copyright (C) 2026 agent <agent@local>
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+use gst::prelude::*;
+
+fn init() {
+    use std::sync::Once;
+    static INIT: Once = Once::new();
+
+    INIT.call_once(|| {
+        gst::init().unwrap();
+        gstreqwest::plugin_register_static().expect("reqwesticydemux tests");
+    });
+}
+
+fn setup(metadata_interval: i32) -> gst_check::Harness {
+    let mut h = gst_check::Harness::new("reqwesticydemux");
+    h.set_src_caps(
+        gst::Caps::builder("application/x-icy")
+            .field("metadata-interval", metadata_interval)
+            .field("content-type", "audio/mpeg")
+            .build(),
+    );
+
+    h
+}
+
+fn push(h: &mut gst_check::Harness, data: &[u8], flags: gst::BufferFlags) {
+    let mut buffer = gst::Buffer::from_mut_slice(Vec::from(data));
+    buffer.get_mut().unwrap().set_flags(flags);
+
+    assert_eq!(h.push(buffer), Ok(gst::FlowSuccess::Ok));
+}
+
+/// Pulls all queued buffers and returns their data and flags
+fn pull_buffers(h: &mut gst_check::Harness) -> Vec<(Vec<u8>, gst::BufferFlags)> {
+    let mut buffers = Vec::new();
+    while h.buffers_in_queue() > 0 {
+        let buffer = h.pull().unwrap();
+        let map = buffer.map_readable().unwrap();
+        buffers.push((map.to_vec(), buffer.flags()));
+    }
+
+    buffers
+}
+
+/// Pulls all queued events and returns the titles of the tag events
+fn pull_titles(h: &mut gst_check::Harness) -> Vec<String> {
+    use gst::EventView;
+
+    let mut titles = Vec::new();
+    while h.events_in_queue() > 0 {
+        let ev = h.pull_event().unwrap();
+
+        if let EventView::Tag(ev) = ev.view() {
+            if let Some(title) = ev.tag().get::<gst::tags::Title>() {
+                titles.push(title.get().to_string());
+            }
+        }
+    }
+
+    titles
+}
+
+#[test]
+fn test_strip_metadata() {
+    init();
+
+    let mut h = setup(4);
+
+    push(&mut h, b"abcd", gst::BufferFlags::DISCONT);
+    assert_eq!(
+        pull_buffers(&mut h),
+        vec![(b"abcd".to_vec(), gst::BufferFlags::DISCONT)]
+    );
+    assert!(pull_titles(&mut h).is_empty());
+
+    let caps = h.sinkpad().unwrap().current_caps().unwrap();
+    assert_eq!(
+        caps,
+        gst::Caps::builder("audio/mpeg")
+            .field("mpegversion", 1i32)
+            .build()
+    );
+
+    // Metadata block of 2 * 16 bytes, split over multiple buffers
+    let mut metadata = b"StreamTitle='Hello';".to_vec();
+    metadata.resize(32, 0);
+
+    let mut data = vec![2];
+    data.extend_from_slice(&metadata);
+    data.extend_from_slice(b"efgh\0ij");
+
+    push(&mut h, &data[..10], gst::BufferFlags::empty());
+    assert!(pull_buffers(&mut h).is_empty());
+    assert!(pull_titles(&mut h).is_empty());
+
+    push(&mut h, &data[10..], gst::BufferFlags::empty());
+    assert_eq!(pull_titles(&mut h), vec![String::from("Hello")]);
+    assert_eq!(
+        pull_buffers(&mut h),
+        vec![
+            (b"efgh".to_vec(), gst::BufferFlags::empty()),
+            (b"ij".to_vec(), gst::BufferFlags::empty())
+        ]
+    );
+}
+
+#[test]
+fn test_discont_restarts_interval() {
+    init();
+
+    let mut h = setup(4);
+
+    push(&mut h, b"ab", gst::BufferFlags::DISCONT);
+
+    // After a reconnect upstream the stream starts with a full interval again
+    push(&mut h, b"cdef\0gh", gst::BufferFlags::DISCONT);
+
+    assert_eq!(
+        pull_buffers(&mut h),
+        vec![
+            (b"ab".to_vec(), gst::BufferFlags::DISCONT),
+            (b"cdef".to_vec(), gst::BufferFlags::DISCONT),
+            (b"gh".to_vec(), gst::BufferFlags::empty())
+        ]
+    );
+    assert!(pull_titles(&mut h).is_empty());
+}
+
+#[test]
+fn test_latin1_metadata() {
+    init();
+
+    let mut h = setup(2);
+
+    let mut data = b"ab\x02StreamTitle='Caf\xe9';".to_vec();
+    data.resize(3 + 32, 0);
+    data.extend_from_slice(b"cd");
+
+    push(&mut h, &data, gst::BufferFlags::DISCONT);
+    assert_eq!(pull_titles(&mut h), vec![String::from("Café")]);
+    assert_eq!(
+        pull_buffers(&mut h),
+        vec![
+            (b"ab".to_vec(), gst::BufferFlags::DISCONT),
+            (b"cd".to_vec(), gst::BufferFlags::empty())
+        ]
+    );
+}
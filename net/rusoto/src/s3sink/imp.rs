@@ -13,25 +13,33 @@ use gst::{gst_debug, gst_error, gst_info, gst_log, gst_trace, gst_warning};
 
 use gst_base::subclass::prelude::*;
 
-use futures::future;
-use rusoto_core::{region::Region, request::HttpClient};
-use rusoto_credential::StaticProvider;
+use bytes::Bytes;
+use futures::prelude::*;
+use futures::{future, stream};
+use rusoto_core::{region::Region, RusotoError};
 use rusoto_s3::{
     AbortMultipartUploadRequest, CompleteMultipartUploadRequest, CompletedMultipartUpload,
-    CompletedPart, CreateMultipartUploadRequest, S3Client, UploadPartRequest, S3,
+    CompletedPart, CreateMultipartUploadRequest, S3Client, UploadPartError, UploadPartRequest, S3,
 };
+use tokio::task::JoinHandle;
 
 use once_cell::sync::Lazy;
 
 use std::collections::HashMap;
 use std::sync::Mutex;
+use std::time::Duration;
 
 use crate::s3url::*;
-use crate::s3utils::{self, WaitError};
+use crate::s3utils::{self, AddressingStyle, WaitError};
 
 use super::OnError;
 
 const DEFAULT_MULTIPART_UPLOAD_ON_ERROR: OnError = OnError::DoNothing;
+const DEFAULT_MAX_CONCURRENT_UPLOADS: u32 = 4;
+const DEFAULT_RETRY_ATTEMPTS: u32 = 3;
+const DEFAULT_RETRY_DELAY: u32 = 500;
+
+type PartUpload = JoinHandle<Result<CompletedPart, RusotoError<UploadPartError>>>;
 
 struct Started {
     client: S3Client,
@@ -39,6 +47,7 @@ struct Started {
     upload_id: String,
     part_number: i64,
     completed_parts: Vec<CompletedPart>,
+    uploads: stream::FuturesUnordered<PartUpload>,
 }
 
 impl Started {
@@ -49,9 +58,18 @@ impl Started {
             upload_id,
             part_number: 0,
             completed_parts: Vec::new(),
+            uploads: stream::FuturesUnordered::new(),
         }
     }
 
+    /// Aborts all part uploads that are still in flight.
+    pub fn abort_uploads(&mut self) {
+        for upload in self.uploads.iter() {
+            upload.abort();
+        }
+        self.uploads.clear();
+    }
+
     pub fn increment_part_number(&mut self) -> Result<i64, gst::ErrorMessage> {
         // https://docs.aws.amazon.com/AmazonS3/latest/dev/qfacts.html
         const MAX_MULTIPART_NUMBER: i64 = 10000;
@@ -90,10 +108,12 @@ struct Settings {
     key: Option<String>,
     content_type: Option<String>,
     buffer_size: u64,
-    access_key: Option<String>,
-    secret_access_key: Option<String>,
+    client: s3utils::ClientSettings,
     metadata: Option<gst::Structure>,
     multipart_upload_on_error: OnError,
+    max_concurrent_uploads: u32,
+    retry_attempts: u32,
+    retry_delay: u32,
 }
 
 impl Settings {
@@ -158,10 +178,12 @@ impl Default for Settings {
             key: None,
             content_type: None,
             buffer_size: DEFAULT_BUFFER_SIZE,
-            access_key: None,
-            secret_access_key: None,
+            client: s3utils::ClientSettings::default(),
             metadata: None,
             multipart_upload_on_error: DEFAULT_MULTIPART_UPLOAD_ON_ERROR,
+            max_concurrent_uploads: DEFAULT_MAX_CONCURRENT_UPLOADS,
+            retry_attempts: DEFAULT_RETRY_ATTEMPTS,
+            retry_delay: DEFAULT_RETRY_DELAY,
         }
     }
 }
@@ -183,97 +205,101 @@ static CAT: Lazy<gst::DebugCategory> = Lazy::new(|| {
     )
 });
 
-impl S3Sink {
-    fn flush_current_buffer(
-        &self,
-        element: &super::S3Sink,
-    ) -> Result<(), Option<gst::ErrorMessage>> {
-        let upload_part_req = self.create_upload_part_request()?;
-        let part_number = upload_part_req.part_number;
+/// Returns the delay before retrying a part upload after `attempt` failed retries, or `None` if
+/// all `retry_attempts` are used up. The delay doubles with every attempt.
+fn retry_delay(attempt: u32, retry_attempts: u32, initial_delay: Duration) -> Option<Duration> {
+    if attempt >= retry_attempts {
+        return None;
+    }
 
-        let mut state = self.state.lock().unwrap();
-        let state = match *state {
-            State::Started(ref mut started_state) => started_state,
-            State::Stopped => {
-                unreachable!("Element should be started");
-            }
+    Some(initial_delay.saturating_mul(2u32.saturating_pow(attempt)))
+}
+
+/// Returns `true` if another part upload can be started without exceeding
+/// `max_concurrent_uploads`.
+fn can_start_upload(uploads_in_flight: usize, max_concurrent_uploads: usize) -> bool {
+    uploads_in_flight < max_concurrent_uploads
+}
+
+/// Uploads a part, retrying failed attempts with exponential backoff.
+async fn upload_part(
+    element: super::S3Sink,
+    client: S3Client,
+    req: UploadPartRequest,
+    body: Bytes,
+    retry_attempts: u32,
+    initial_retry_delay: Duration,
+) -> Result<CompletedPart, RusotoError<UploadPartError>> {
+    let part_number = req.part_number;
+    let mut attempt = 0;
+
+    loop {
+        let req = UploadPartRequest {
+            body: Some(rusoto_core::ByteStream::new_with_size(
+                stream::once(future::ready(Ok::<_, std::io::Error>(body.clone()))),
+                body.len(),
+            )),
+            bucket: req.bucket.clone(),
+            key: req.key.clone(),
+            upload_id: req.upload_id.clone(),
+            part_number,
+            ..Default::default()
         };
 
-        let upload_part_req_future = state.client.upload_part(upload_part_req);
+        match client.upload_part(req).await {
+            Ok(output) => {
+                gst_info!(CAT, obj: &element, "Uploaded part {}", part_number);
 
-        let output =
-            s3utils::wait(&self.canceller, upload_part_req_future).map_err(|err| match err {
-                WaitError::FutureError(err) => {
-                    let settings = self.settings.lock().unwrap();
-                    match settings.multipart_upload_on_error {
-                        OnError::Abort => {
-                            gst_log!(
-                                CAT,
-                                obj: element,
-                                "Aborting multipart upload request with id: {}",
-                                state.upload_id
-                            );
-                            match self.abort_multipart_upload_request(state) {
-                                Ok(()) => {
-                                    gst_log!(
-                                        CAT,
-                                        obj: element,
-                                        "Aborting multipart upload request succeeded."
-                                    );
-                                }
-                                Err(err) => gst_error!(
-                                    CAT,
-                                    obj: element,
-                                    "Aborting multipart upload failed: {}",
-                                    err.to_string()
-                                ),
-                            }
-                        }
-                        OnError::Complete => {
-                            gst_log!(
-                                CAT,
-                                obj: element,
-                                "Completing multipart upload request with id: {}",
-                                state.upload_id
-                            );
-                            match self.complete_multipart_upload_request(state) {
-                                Ok(()) => {
-                                    gst_log!(
-                                        CAT,
-                                        obj: element,
-                                        "Complete multipart upload request succeeded."
-                                    );
-                                }
-                                Err(err) => gst_error!(
-                                    CAT,
-                                    obj: element,
-                                    "Completing multipart upload failed: {}",
-                                    err.to_string()
-                                ),
-                            }
-                        }
-                        OnError::DoNothing => (),
-                    }
-                    Some(gst::error_msg!(
-                        gst::ResourceError::OpenWrite,
-                        ["Failed to upload part: {}", err]
-                    ))
-                }
-                WaitError::Cancelled => None,
-            })?;
+                return Ok(CompletedPart {
+                    e_tag: output.e_tag,
+                    part_number: Some(part_number),
+                });
+            }
+            Err(err)
+                if matches!(
+                    err,
+                    RusotoError::Credentials(_) | RusotoError::Validation(_)
+                ) =>
+            {
+                return Err(err);
+            }
+            Err(err) => {
+                let delay = match retry_delay(attempt, retry_attempts, initial_retry_delay) {
+                    Some(delay) => delay,
+                    None => return Err(err),
+                };
 
-        state.completed_parts.push(CompletedPart {
-            e_tag: output.e_tag,
-            part_number: Some(part_number),
-        });
-        gst_info!(CAT, obj: element, "Uploaded part {}", part_number);
+                attempt += 1;
+                gst_warning!(
+                    CAT,
+                    obj: &element,
+                    "Failed to upload part {}, retrying in {:?} ({}/{}): {}",
+                    part_number,
+                    delay,
+                    attempt,
+                    retry_attempts,
+                    err
+                );
 
-        Ok(())
+                tokio::time::sleep(delay).await;
+            }
+        }
     }
+}
 
-    fn create_upload_part_request(&self) -> Result<UploadPartRequest, gst::ErrorMessage> {
-        let url = self.url.lock().unwrap();
+impl S3Sink {
+    fn flush_current_buffer(
+        &self,
+        element: &super::S3Sink,
+    ) -> Result<(), Option<gst::ErrorMessage>> {
+        let url = self.url.lock().unwrap().clone();
         let settings = self.settings.lock().unwrap();
+        let max_concurrent_uploads = settings.max_concurrent_uploads as usize;
+        let retry_attempts = settings.retry_attempts;
+        let retry_delay = Duration::from_millis(settings.retry_delay.into());
+        let buffer_size = settings.buffer_size as usize;
+        drop(settings);
+
         let mut state = self.state.lock().unwrap();
         let state = match *state {
             State::Started(ref mut started_state) => started_state,
@@ -282,20 +308,156 @@ impl S3Sink {
             }
         };
 
+        // Bound the memory usage by waiting for uploads to finish before starting a new one
+        while !can_start_upload(state.uploads.len(), max_concurrent_uploads) {
+            self.wait_for_part_upload(element, state)?;
+        }
+
         let part_number = state.increment_part_number()?;
-        Ok(UploadPartRequest {
-            body: Some(rusoto_core::ByteStream::from(std::mem::replace(
-                &mut state.buffer,
-                Vec::with_capacity(settings.buffer_size as usize),
-            ))),
-            bucket: url.as_ref().unwrap().bucket.to_owned(),
-            key: url.as_ref().unwrap().object.to_owned(),
+        let body = Bytes::from(std::mem::replace(
+            &mut state.buffer,
+            Vec::with_capacity(buffer_size),
+        ));
+        let url = url.as_ref().unwrap();
+        let upload_part_req = UploadPartRequest {
+            bucket: url.bucket.to_owned(),
+            key: url.object.to_owned(),
             upload_id: state.upload_id.to_owned(),
             part_number,
             ..Default::default()
+        };
+
+        gst_debug!(
+            CAT,
+            obj: element,
+            "Uploading part {} of {} bytes",
+            part_number,
+            body.len()
+        );
+        state.uploads.push(s3utils::RUNTIME.spawn(upload_part(
+            element.clone(),
+            state.client.clone(),
+            upload_part_req,
+            body,
+            retry_attempts,
+            retry_delay,
+        )));
+
+        Ok(())
+    }
+
+    /// Waits for the next part upload to finish.
+    ///
+    /// Returns `Ok(None)` if no uploads are in flight.
+    fn next_part_upload(
+        &self,
+        state: &mut Started,
+    ) -> Result<Option<CompletedPart>, WaitError<String>> {
+        let uploads = &mut state.uploads;
+        s3utils::wait(&self.canceller, async move {
+            match uploads.next().await {
+                Some(Ok(res)) => res.map(Some).map_err(|err| err.to_string()),
+                Some(Err(err)) => Err(err.to_string()),
+                None => Ok(None),
+            }
         })
     }
 
+    /// Waits for the next part upload to finish and keeps track of the completed part.
+    ///
+    /// Returns `Ok(false)` if no uploads are in flight anymore. On errors the multipart upload
+    /// is handled according to the `on-error` property.
+    fn wait_for_part_upload(
+        &self,
+        element: &super::S3Sink,
+        state: &mut Started,
+    ) -> Result<bool, Option<gst::ErrorMessage>> {
+        match self.next_part_upload(state) {
+            Ok(Some(completed_part)) => {
+                state.completed_parts.push(completed_part);
+                Ok(true)
+            }
+            Ok(None) => Ok(false),
+            Err(WaitError::FutureError(err)) => {
+                self.handle_upload_error(element, state);
+
+                Err(Some(gst::error_msg!(
+                    gst::ResourceError::OpenWrite,
+                    ["Failed to upload part: {}", err]
+                )))
+            }
+            Err(WaitError::Cancelled) => Err(None),
+        }
+    }
+
+    fn handle_upload_error(&self, element: &super::S3Sink, state: &mut Started) {
+        let on_error = self.settings.lock().unwrap().multipart_upload_on_error;
+
+        match on_error {
+            OnError::Abort => {
+                state.abort_uploads();
+
+                gst_log!(
+                    CAT,
+                    obj: element,
+                    "Aborting multipart upload request with id: {}",
+                    state.upload_id
+                );
+                match self.abort_multipart_upload_request(state) {
+                    Ok(()) => {
+                        gst_log!(
+                            CAT,
+                            obj: element,
+                            "Aborting multipart upload request succeeded."
+                        );
+                    }
+                    Err(err) => gst_error!(
+                        CAT,
+                        obj: element,
+                        "Aborting multipart upload failed: {}",
+                        err.to_string()
+                    ),
+                }
+            }
+            OnError::Complete => {
+                // Complete with all parts that can still be uploaded successfully
+                loop {
+                    match self.next_part_upload(state) {
+                        Ok(Some(completed_part)) => state.completed_parts.push(completed_part),
+                        Ok(None) | Err(WaitError::Cancelled) => break,
+                        Err(WaitError::FutureError(err)) => {
+                            gst_warning!(CAT, obj: element, "Failed to upload part: {}", err)
+                        }
+                    }
+                }
+                state.abort_uploads();
+
+                gst_log!(
+                    CAT,
+                    obj: element,
+                    "Completing multipart upload request with id: {}",
+                    state.upload_id
+                );
+                match self.complete_multipart_upload_request(state) {
+                    Ok(()) => {
+                        gst_log!(
+                            CAT,
+                            obj: element,
+                            "Complete multipart upload request succeeded."
+                        );
+                    }
+                    Err(err) => gst_error!(
+                        CAT,
+                        obj: element,
+                        "Completing multipart upload failed: {}",
+                        err.to_string()
+                    ),
+                }
+            }
+            OnError::DoNothing => state.abort_uploads(),
+        }
+    }
+
     fn create_complete_multipart_upload_request(
         &self,
         started_state: &mut Started,
@@ -411,6 +573,20 @@ impl S3Sink {
             }
         };
 
+        loop {
+            match self.wait_for_part_upload(element, started_state) {
+                Ok(true) => (),
+                Ok(false) => break,
+                Err(Some(err)) => return Err(err),
+                Err(None) => {
+                    return Err(gst::error_msg!(
+                        gst::LibraryError::Failed,
+                        ["Interrupted during stop"]
+                    ))
+                }
+            }
+        }
+
         self.complete_multipart_upload_request(started_state)
     }
 
@@ -432,21 +608,7 @@ impl S3Sink {
             }
         };
 
-        let client = match (
-            settings.access_key.as_ref(),
-            settings.secret_access_key.as_ref(),
-        ) {
-            (Some(access_key), Some(secret_access_key)) => {
-                let creds =
-                    StaticProvider::new_minimal(access_key.clone(), secret_access_key.clone());
-                S3Client::new_with(
-                    HttpClient::new().expect("failed to create request dispatcher"),
-                    creds,
-                    s3url.region.clone(),
-                )
-            }
-            _ => S3Client::new(s3url.region.clone()),
-        };
+        let client = s3utils::create_client(s3url.region.clone(), &s3url.bucket, &settings.client)?;
 
         let create_multipart_req = self.create_create_multipart_upload_request(&s3url, &settings);
         let create_multipart_req_future = client.create_multipart_upload(create_multipart_req);
@@ -626,6 +788,42 @@ impl ObjectImpl for S3Sink {
                     None,
                     glib::ParamFlags::READWRITE | gst::PARAM_FLAG_MUTABLE_READY,
                 ),
+                glib::ParamSpecString::new(
+                    "session-token",
+                    "Session Token",
+                    "AWS temporary Session Token (used together with the access keys)",
+                    None,
+                    glib::ParamFlags::READWRITE | gst::PARAM_FLAG_MUTABLE_READY,
+                ),
+                glib::ParamSpecString::new(
+                    "role-arn",
+                    "Role ARN",
+                    "ARN of an IAM role to assume for accessing the object",
+                    None,
+                    glib::ParamFlags::READWRITE | gst::PARAM_FLAG_MUTABLE_READY,
+                ),
+                glib::ParamSpecString::new(
+                    "profile",
+                    "Profile",
+                    "Name of the profile in the AWS credentials file to use",
+                    None,
+                    glib::ParamFlags::READWRITE | gst::PARAM_FLAG_MUTABLE_READY,
+                ),
+                glib::ParamSpecString::new(
+                    "endpoint-uri",
+                    "Endpoint URI",
+                    "Custom S3 endpoint URI (e.g. of an S3-compatible server), overrides the endpoint of the region",
+                    None,
+                    glib::ParamFlags::READWRITE | gst::PARAM_FLAG_MUTABLE_READY,
+                ),
+                glib::ParamSpecEnum::new(
+                    "addressing-style",
+                    "Addressing Style",
+                    "Whether the bucket is part of the path or of the host name of the requests",
+                    AddressingStyle::static_type(),
+                    AddressingStyle::default() as i32,
+                    glib::ParamFlags::READWRITE | gst::PARAM_FLAG_MUTABLE_READY,
+                ),
                 glib::ParamSpecBoxed::new(
                     "metadata",
                     "Metadata",
@@ -641,6 +839,33 @@ impl ObjectImpl for S3Sink {
                     DEFAULT_MULTIPART_UPLOAD_ON_ERROR as i32,
                     glib::ParamFlags::READWRITE | gst::PARAM_FLAG_MUTABLE_READY,
                 ),
+                glib::ParamSpecUInt::new(
+                    "max-concurrent-uploads",
+                    "Max concurrent uploads",
+                    "Maximum number of parts that are uploaded concurrently. Bounds the memory usage to this number of parts plus one.",
+                    1,
+                    64,
+                    DEFAULT_MAX_CONCURRENT_UPLOADS,
+                    glib::ParamFlags::READWRITE | gst::PARAM_FLAG_MUTABLE_READY,
+                ),
+                glib::ParamSpecUInt::new(
+                    "retry-attempts",
+                    "Retry attempts",
+                    "Number of times a failed part upload is retried before handling the error according to on-error.",
+                    0,
+                    100,
+                    DEFAULT_RETRY_ATTEMPTS,
+                    glib::ParamFlags::READWRITE | gst::PARAM_FLAG_MUTABLE_READY,
+                ),
+                glib::ParamSpecUInt::new(
+                    "retry-delay",
+                    "Retry delay",
+                    "Delay (in ms) before the first retry of a failed part upload, doubled for every further retry.",
+                    0,
+                    60_000,
+                    DEFAULT_RETRY_DELAY,
+                    glib::ParamFlags::READWRITE | gst::PARAM_FLAG_MUTABLE_READY,
+                ),
             ]
         });
 
@@ -705,10 +930,27 @@ impl ObjectImpl for S3Sink {
                 let _ = self.set_uri(obj, value.get().expect("type checked upstream"));
             }
             "access-key" => {
-                settings.access_key = value.get().expect("type checked upstream");
+                settings.client.access_key = value.get().expect("type checked upstream");
             }
             "secret-access-key" => {
-                settings.secret_access_key = value.get().expect("type checked upstream");
+                settings.client.secret_access_key = value.get().expect("type checked upstream");
+            }
+            "session-token" => {
+                settings.client.session_token = value.get().expect("type checked upstream");
+            }
+            "role-arn" => {
+                settings.client.role_arn = value.get().expect("type checked upstream");
+            }
+            "profile" => {
+                settings.client.profile = value.get().expect("type checked upstream");
+            }
+            "endpoint-uri" => {
+                settings.client.endpoint_uri = value.get().expect("type checked upstream");
+            }
+            "addressing-style" => {
+                settings.client.addressing_style = value
+                    .get::<AddressingStyle>()
+                    .expect("type checked upstream");
             }
             "metadata" => {
                 settings.metadata = value.get().expect("type checked upstream");
@@ -717,6 +959,16 @@ impl ObjectImpl for S3Sink {
                 settings.multipart_upload_on_error =
                     value.get::<OnError>().expect("type checked upstream");
             }
+            "max-concurrent-uploads" => {
+                settings.max_concurrent_uploads =
+                    value.get::<u32>().expect("type checked upstream");
+            }
+            "retry-attempts" => {
+                settings.retry_attempts = value.get::<u32>().expect("type checked upstream");
+            }
+            "retry-delay" => {
+                settings.retry_delay = value.get::<u32>().expect("type checked upstream");
+            }
             _ => unimplemented!(),
         }
     }
@@ -737,10 +989,18 @@ impl ObjectImpl for S3Sink {
 
                 url.to_value()
             }
-            "access-key" => settings.access_key.to_value(),
-            "secret-access-key" => settings.secret_access_key.to_value(),
+            "access-key" => settings.client.access_key.to_value(),
+            "secret-access-key" => settings.client.secret_access_key.to_value(),
+            "session-token" => settings.client.session_token.to_value(),
+            "role-arn" => settings.client.role_arn.to_value(),
+            "profile" => settings.client.profile.to_value(),
+            "endpoint-uri" => settings.client.endpoint_uri.to_value(),
+            "addressing-style" => settings.client.addressing_style.to_value(),
             "metadata" => settings.metadata.to_value(),
             "on-error" => settings.multipart_upload_on_error.to_value(),
+            "max-concurrent-uploads" => settings.max_concurrent_uploads.to_value(),
+            "retry-attempts" => settings.retry_attempts.to_value(),
+            "retry-delay" => settings.retry_delay.to_value(),
             _ => unimplemented!(),
         }
     }
@@ -803,6 +1063,9 @@ impl BaseSinkImpl for S3Sink {
 
     fn stop(&self, element: &Self::Type) -> Result<(), gst::ErrorMessage> {
         let mut state = self.state.lock().unwrap();
+        if let State::Started(ref mut started_state) = *state {
+            started_state.abort_uploads();
+        }
         *state = State::Stopped;
         gst_info!(CAT, obj: element, "Stopped");
 
@@ -868,3 +1131,50 @@ impl BaseSinkImpl for S3Sink {
         BaseSinkImplExt::parent_event(self, element, event)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn retry_delay_doubles() {
+        let initial = Duration::from_millis(500);
+
+        assert_eq!(retry_delay(0, 3, initial), Some(Duration::from_millis(500)));
+        assert_eq!(
+            retry_delay(1, 3, initial),
+            Some(Duration::from_millis(1000))
+        );
+        assert_eq!(
+            retry_delay(2, 3, initial),
+            Some(Duration::from_millis(2000))
+        );
+        assert_eq!(retry_delay(3, 3, initial), None);
+    }
+
+    #[test]
+    fn retry_delay_without_retries() {
+        assert_eq!(retry_delay(0, 0, Duration::from_millis(500)), None);
+    }
+
+    #[test]
+    fn retry_delay_saturates() {
+        assert_eq!(
+            retry_delay(1, 3, Duration::MAX / 2 + Duration::from_secs(1)),
+            Some(Duration::MAX)
+        );
+        assert_eq!(
+            retry_delay(40, u32::MAX, Duration::from_secs(1)),
+            Some(Duration::from_secs(u32::MAX.into()))
+        );
+    }
+
+    #[test]
+    fn upload_slots() {
+        assert!(can_start_upload(0, 1));
+        assert!(!can_start_upload(1, 1));
+        assert!(can_start_upload(3, 4));
+        assert!(!can_start_upload(4, 4));
+        assert!(!can_start_upload(5, 4));
+    }
+}
@@ -6,26 +6,148 @@
 // option. This file may not be copied, modified, or distributed
 // except according to those terms.
 
+use std::collections::VecDeque;
 use std::sync::Mutex;
 
-use bytes::Bytes;
+use bytes::{Bytes, BytesMut};
 use futures::future;
 use once_cell::sync::Lazy;
-use rusoto_core::request::HttpClient;
-use rusoto_credential::StaticProvider;
 use rusoto_s3::{GetObjectRequest, HeadObjectRequest, S3Client, S3};
+use tokio::task::JoinHandle;
 
 use gst::glib;
 use gst::prelude::*;
 use gst::subclass::prelude::*;
-use gst::{gst_debug, gst_error, gst_info};
+use gst::{gst_debug, gst_error, gst_info, gst_log};
 
 use gst_base::prelude::*;
 use gst_base::subclass::base_src::CreateSuccess;
 use gst_base::subclass::prelude::*;
 
 use crate::s3url::*;
-use crate::s3utils::{self, WaitError};
+use crate::s3utils::{self, AddressingStyle, WaitError};
+
+const DEFAULT_PREFETCH_CHUNKS: u32 = 4;
+const DEFAULT_PREFETCH_CHUNK_SIZE: u64 = 1024 * 1024;
+
+enum ChunkData {
+    Pending(JoinHandle<Result<Bytes, String>>),
+    Done(Bytes),
+}
+
+/// A range of the object that was requested ahead of time
+struct Chunk {
+    offset: u64,
+    size: u64,
+    data: ChunkData,
+}
+
+impl Drop for Chunk {
+    fn drop(&mut self) {
+        // Nobody is going to read this chunk anymore
+        if let ChunkData::Pending(ref request) = self.data {
+            request.abort();
+        }
+    }
+}
+
+/// Consecutive chunks of the object, starting at the current read position
+struct Prefetcher {
+    chunks: VecDeque<Chunk>,
+    max_chunks: usize,
+    chunk_size: u64,
+    /* End offset of the last read, used to detect sequential reads */
+    read_end: u64,
+}
+
+impl Prefetcher {
+    fn new(max_chunks: u32, chunk_size: u64) -> Self {
+        Prefetcher {
+            chunks: VecDeque::new(),
+            max_chunks: max_chunks as usize,
+            chunk_size,
+            read_end: 0,
+        }
+    }
+
+    /// Drops all chunks that are not needed anymore for reading from `offset`
+    fn seek(&mut self, offset: u64) {
+        while let Some(chunk) = self.chunks.front() {
+            if chunk.offset <= offset && offset < chunk.offset + chunk.size {
+                break;
+            }
+
+            self.chunks.pop_front();
+        }
+    }
+
+    /// Number of chunks to keep requested ahead of a read at `offset`
+    fn read_ahead(&self, offset: u64) -> usize {
+        // Only read ahead for sequential reads, random access (e.g. while a demuxer is looking
+        // for the headers of a file) would otherwise request lots of data that is never used
+        if offset == self.read_end {
+            self.max_chunks
+        } else {
+            1
+        }
+    }
+
+    /// Requests further chunks with `fetch` until `count` chunks are queued or the end of the
+    /// object is reached. If no chunks are queued, the first chunk starts at `offset`
+    fn fill<F>(&mut self, offset: u64, size: u64, count: usize, mut fetch: F)
+    where
+        F: FnMut(u64, u64) -> JoinHandle<Result<Bytes, String>>,
+    {
+        let mut next = self
+            .chunks
+            .back()
+            .map(|chunk| chunk.offset + chunk.size)
+            .unwrap_or(offset);
+
+        while self.chunks.len() < count && next < size {
+            let chunk_size = std::cmp::min(self.chunk_size, size - next);
+
+            self.chunks.push_back(Chunk {
+                offset: next,
+                size: chunk_size,
+                data: ChunkData::Pending(fetch(next, chunk_size)),
+            });
+            next += chunk_size;
+        }
+    }
+}
+
+async fn get_range(
+    client: S3Client,
+    url: GstS3Url,
+    offset: u64,
+    length: u64,
+) -> Result<Bytes, String> {
+    let request = GetObjectRequest {
+        bucket: url.bucket,
+        key: url.object,
+        range: Some(format!("bytes={}-{}", offset, offset + length - 1)),
+        version_id: url.version,
+        ..Default::default()
+    };
+
+    let output = client
+        .get_object(request)
+        .await
+        .map_err(|err| err.to_string())?;
+    let mut body = output
+        .body
+        .ok_or_else(|| String::from("No body in response"))?;
+    let bytes = s3utils::collect_stream(&mut body)
+        .await
+        .map_err(|err| err.to_string())?;
+
+    if bytes.len() as u64 != length {
+        return Err(format!("Expected {} bytes but got {}", length, bytes.len()));
+    }
+
+    Ok(bytes)
+}
 
 #[allow(clippy::large_enum_variant)]
 enum StreamingState {
@@ -34,6 +156,7 @@ enum StreamingState {
         url: GstS3Url,
         client: S3Client,
         size: u64,
+        prefetcher: Prefetcher,
     },
 }
 
@@ -43,11 +166,22 @@ impl Default for StreamingState {
     }
 }
 
-#[derive(Default)]
 struct Settings {
     url: Option<GstS3Url>,
-    access_key: Option<String>,
-    secret_access_key: Option<String>,
+    client: s3utils::ClientSettings,
+    prefetch_chunks: u32,
+    prefetch_chunk_size: u64,
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Settings {
+            url: None,
+            client: s3utils::ClientSettings::default(),
+            prefetch_chunks: DEFAULT_PREFETCH_CHUNKS,
+            prefetch_chunk_size: DEFAULT_PREFETCH_CHUNK_SIZE,
+        }
+    }
 }
 
 #[derive(Default)]
@@ -74,24 +208,10 @@ impl S3Src {
         };
     }
 
-    fn connect(self: &S3Src, url: &GstS3Url) -> S3Client {
+    fn connect(self: &S3Src, url: &GstS3Url) -> Result<S3Client, gst::ErrorMessage> {
         let settings = self.settings.lock().unwrap();
 
-        match (
-            settings.access_key.as_ref(),
-            settings.secret_access_key.as_ref(),
-        ) {
-            (Some(access_key), Some(secret_access_key)) => {
-                let creds =
-                    StaticProvider::new_minimal(access_key.clone(), secret_access_key.clone());
-                S3Client::new_with(
-                    HttpClient::new().expect("failed to create request dispatcher"),
-                    creds,
-                    url.region.clone(),
-                )
-            }
-            _ => S3Client::new(url.region.clone()),
-        }
+        s3utils::create_client(url.region.clone(), &url.bucket, &settings.client)
     }
 
     fn set_uri(self: &S3Src, _: &super::S3Src, url_str: Option<&str>) -> Result<(), glib::Error> {
@@ -129,7 +249,7 @@ impl S3Src {
         src: &super::S3Src,
         client: &S3Client,
         url: &GstS3Url,
-    ) -> Result<u64, gst::ErrorMessage> {
+    ) -> Result<(u64, Option<String>), gst::ErrorMessage> {
         let request = HeadObjectRequest {
             bucket: url.bucket.clone(),
             key: url.object.clone(),
@@ -150,8 +270,16 @@ impl S3Src {
         })?;
 
         if let Some(size) = output.content_length {
-            gst_info!(CAT, obj: src, "HEAD success, content length = {}", size);
-            Ok(size as u64)
+            gst_info!(
+                CAT,
+                obj: src,
+                "HEAD success, content length = {}, version = {:?}",
+                size,
+                output.version_id
+            );
+            // Unversioned objects report "null" as their version
+            let version = output.version_id.filter(|version| version != "null");
+            Ok((size as u64, version))
         } else {
             Err(gst::error_msg!(
                 gst::ResourceError::Read,
@@ -224,6 +352,111 @@ impl S3Src {
             WaitError::Cancelled => None,
         })
     }
+
+    /* Returns the bytes from the prefetched chunks, Some(error) if one occured, or a None
+     * error if interrupted */
+    fn get_prefetched(
+        self: &S3Src,
+        src: &super::S3Src,
+        offset: u64,
+        length: u64,
+    ) -> Result<Bytes, Option<gst::ErrorMessage>> {
+        let mut state = self.state.lock().unwrap();
+
+        let (url, client, size, prefetcher) = match *state {
+            StreamingState::Started {
+                ref url,
+                ref client,
+                size,
+                ref mut prefetcher,
+            } => (url, client, size, prefetcher),
+            StreamingState::Stopped => {
+                return Err(Some(gst::error_msg!(
+                    gst::LibraryError::Failed,
+                    ["Cannot GET before start()"]
+                )));
+            }
+        };
+
+        let count = prefetcher.read_ahead(offset);
+        if offset != prefetcher.read_end {
+            gst_debug!(CAT, obj: src, "Non-sequential read at {}", offset);
+        }
+
+        prefetcher.seek(offset);
+
+        let end = offset + length;
+        let mut position = offset;
+        let mut pieces = Vec::new();
+
+        while position < end {
+            prefetcher.fill(position, size, count, |offset, length| {
+                s3utils::RUNTIME.spawn(get_range(client.clone(), url.clone(), offset, length))
+            });
+
+            let chunk = prefetcher.chunks.front_mut().expect("No chunk for reading");
+
+            if let ChunkData::Pending(ref mut request) = chunk.data {
+                gst_log!(
+                    CAT,
+                    obj: src,
+                    "Waiting for chunk {}-{}",
+                    chunk.offset,
+                    chunk.offset + chunk.size - 1
+                );
+
+                let res = s3utils::wait(&self.canceller, async move {
+                    match request.await {
+                        Ok(res) => res,
+                        Err(err) => Err(err.to_string()),
+                    }
+                });
+
+                match res {
+                    Ok(bytes) => chunk.data = ChunkData::Done(bytes),
+                    Err(WaitError::FutureError(err)) => {
+                        prefetcher.chunks.clear();
+                        return Err(Some(gst::error_msg!(
+                            gst::ResourceError::Read,
+                            ["Could not read: {}", err]
+                        )));
+                    }
+                    Err(WaitError::Cancelled) => return Err(None),
+                }
+            }
+
+            let bytes = match chunk.data {
+                ChunkData::Done(ref bytes) => bytes,
+                ChunkData::Pending(..) => unreachable!(),
+            };
+
+            let chunk_end = chunk.offset + chunk.size;
+            let piece_end = std::cmp::min(end, chunk_end);
+            pieces
+                .push(bytes.slice(
+                    (position - chunk.offset) as usize..(piece_end - chunk.offset) as usize,
+                ));
+            position = piece_end;
+
+            if position == chunk_end {
+                prefetcher.chunks.pop_front();
+            }
+        }
+
+        prefetcher.read_end = end;
+
+        gst_debug!(CAT, obj: src, "Read {} bytes at {}", length, offset);
+
+        if pieces.len() == 1 {
+            Ok(pieces.pop().unwrap())
+        } else {
+            let mut data = BytesMut::with_capacity(length as usize);
+            for piece in pieces {
+                data.extend_from_slice(&piece);
+            }
+            Ok(data.freeze())
+        }
+    }
 }
 
 #[glib::object_subclass]
@@ -245,6 +478,24 @@ impl ObjectImpl for S3Src {
                     None,
                     glib::ParamFlags::READWRITE | gst::PARAM_FLAG_MUTABLE_READY,
                 ),
+                glib::ParamSpecUInt::new(
+                    "prefetch-chunks",
+                    "Prefetch Chunks",
+                    "Number of chunks to request concurrently ahead of sequential reads (0 = no prefetching)",
+                    0,
+                    64,
+                    DEFAULT_PREFETCH_CHUNKS,
+                    glib::ParamFlags::READWRITE | gst::PARAM_FLAG_MUTABLE_READY,
+                ),
+                glib::ParamSpecUInt64::new(
+                    "prefetch-chunk-size",
+                    "Prefetch Chunk Size",
+                    "Size in bytes of the ranged requests for prefetching",
+                    64 * 1024,
+                    1024 * 1024 * 1024,
+                    DEFAULT_PREFETCH_CHUNK_SIZE,
+                    glib::ParamFlags::READWRITE | gst::PARAM_FLAG_MUTABLE_READY,
+                ),
                 glib::ParamSpecString::new(
                     "access-key",
                     "Access Key",
@@ -259,6 +510,42 @@ impl ObjectImpl for S3Src {
                     None,
                     glib::ParamFlags::READWRITE | gst::PARAM_FLAG_MUTABLE_READY,
                 ),
+                glib::ParamSpecString::new(
+                    "session-token",
+                    "Session Token",
+                    "AWS temporary Session Token (used together with the access keys)",
+                    None,
+                    glib::ParamFlags::READWRITE | gst::PARAM_FLAG_MUTABLE_READY,
+                ),
+                glib::ParamSpecString::new(
+                    "role-arn",
+                    "Role ARN",
+                    "ARN of an IAM role to assume for accessing the object",
+                    None,
+                    glib::ParamFlags::READWRITE | gst::PARAM_FLAG_MUTABLE_READY,
+                ),
+                glib::ParamSpecString::new(
+                    "profile",
+                    "Profile",
+                    "Name of the profile in the AWS credentials file to use",
+                    None,
+                    glib::ParamFlags::READWRITE | gst::PARAM_FLAG_MUTABLE_READY,
+                ),
+                glib::ParamSpecString::new(
+                    "endpoint-uri",
+                    "Endpoint URI",
+                    "Custom S3 endpoint URI (e.g. of an S3-compatible server), overrides the endpoint of the region",
+                    None,
+                    glib::ParamFlags::READWRITE | gst::PARAM_FLAG_MUTABLE_READY,
+                ),
+                glib::ParamSpecEnum::new(
+                    "addressing-style",
+                    "Addressing Style",
+                    "Whether the bucket is part of the path or of the host name of the requests",
+                    AddressingStyle::static_type(),
+                    AddressingStyle::default() as i32,
+                    glib::ParamFlags::READWRITE | gst::PARAM_FLAG_MUTABLE_READY,
+                ),
             ]
         });
 
@@ -276,13 +563,43 @@ impl ObjectImpl for S3Src {
             "uri" => {
                 let _ = self.set_uri(obj, value.get().expect("type checked upstream"));
             }
+            "prefetch-chunks" => {
+                let mut settings = self.settings.lock().unwrap();
+                settings.prefetch_chunks = value.get().expect("type checked upstream");
+            }
+            "prefetch-chunk-size" => {
+                let mut settings = self.settings.lock().unwrap();
+                settings.prefetch_chunk_size = value.get().expect("type checked upstream");
+            }
             "access-key" => {
                 let mut settings = self.settings.lock().unwrap();
-                settings.access_key = value.get().expect("type checked upstream");
+                settings.client.access_key = value.get().expect("type checked upstream");
             }
             "secret-access-key" => {
                 let mut settings = self.settings.lock().unwrap();
-                settings.secret_access_key = value.get().expect("type checked upstream");
+                settings.client.secret_access_key = value.get().expect("type checked upstream");
+            }
+            "session-token" => {
+                let mut settings = self.settings.lock().unwrap();
+                settings.client.session_token = value.get().expect("type checked upstream");
+            }
+            "role-arn" => {
+                let mut settings = self.settings.lock().unwrap();
+                settings.client.role_arn = value.get().expect("type checked upstream");
+            }
+            "profile" => {
+                let mut settings = self.settings.lock().unwrap();
+                settings.client.profile = value.get().expect("type checked upstream");
+            }
+            "endpoint-uri" => {
+                let mut settings = self.settings.lock().unwrap();
+                settings.client.endpoint_uri = value.get().expect("type checked upstream");
+            }
+            "addressing-style" => {
+                let mut settings = self.settings.lock().unwrap();
+                settings.client.addressing_style = value
+                    .get::<AddressingStyle>()
+                    .expect("type checked upstream");
             }
             _ => unimplemented!(),
         }
@@ -300,8 +617,15 @@ impl ObjectImpl for S3Src {
 
                 url.to_value()
             }
-            "access-key" => settings.access_key.to_value(),
-            "secret-access-key" => settings.secret_access_key.to_value(),
+            "prefetch-chunks" => settings.prefetch_chunks.to_value(),
+            "prefetch-chunk-size" => settings.prefetch_chunk_size.to_value(),
+            "access-key" => settings.client.access_key.to_value(),
+            "secret-access-key" => settings.client.secret_access_key.to_value(),
+            "session-token" => settings.client.session_token.to_value(),
+            "role-arn" => settings.client.role_arn.to_value(),
+            "profile" => settings.client.profile.to_value(),
+            "endpoint-uri" => settings.client.endpoint_uri.to_value(),
+            "addressing-style" => settings.client.addressing_style.to_value(),
             _ => unimplemented!(),
         }
     }
@@ -387,7 +711,7 @@ impl BaseSrcImpl for S3Src {
         }
 
         let settings = self.settings.lock().unwrap();
-        let s3url = match settings.url {
+        let mut s3url = match settings.url {
             Some(ref url) => url.clone(),
             None => {
                 return Err(gst::error_msg!(
@@ -396,15 +720,22 @@ impl BaseSrcImpl for S3Src {
                 ));
             }
         };
+        let prefetcher = Prefetcher::new(settings.prefetch_chunks, settings.prefetch_chunk_size);
         drop(settings);
 
-        let s3client = self.connect(&s3url);
-        let size = self.head(src, &s3client, &s3url)?;
+        let s3client = self.connect(&s3url)?;
+        let (size, version) = self.head(src, &s3client, &s3url)?;
+
+        // Read all ranges from the same version of the object, even if it is replaced meanwhile
+        if s3url.version.is_none() {
+            s3url.version = version;
+        }
 
         *state = StreamingState::Started {
             url: s3url,
             client: s3client,
             size,
+            prefetcher,
         };
 
         Ok(())
@@ -447,8 +778,29 @@ impl BaseSrcImpl for S3Src {
         buffer: Option<&mut gst::BufferRef>,
         length: u32,
     ) -> Result<CreateSuccess, gst::FlowError> {
-        // FIXME: sanity check on offset and length
-        let data = self.get(src, offset, u64::from(length));
+        let (size, prefetch) = match *self.state.lock().unwrap() {
+            StreamingState::Started {
+                size,
+                ref prefetcher,
+                ..
+            } => (size, prefetcher.max_chunks > 0),
+            StreamingState::Stopped => {
+                gst::element_error!(src, gst::LibraryError::Failed, ["Not started yet"]);
+                return Err(gst::FlowError::Error);
+            }
+        };
+
+        if offset >= size {
+            gst_debug!(CAT, obj: src, "Reached end of object at {}", offset);
+            return Err(gst::FlowError::Eos);
+        }
+
+        let length = std::cmp::min(u64::from(length), size - offset);
+        let data = if prefetch {
+            self.get_prefetched(src, offset, length)
+        } else {
+            self.get(src, offset, length)
+        };
 
         match data {
             /* Got data */
@@ -472,8 +824,49 @@ impl BaseSrcImpl for S3Src {
         }
     }
 
-    /* FIXME: implement */
-    fn do_seek(&self, _: &Self::Type, _: &mut gst::Segment) -> bool {
+    fn do_seek(&self, src: &Self::Type, segment: &mut gst::Segment) -> bool {
+        let segment = match segment.downcast_mut::<gst::format::Bytes>() {
+            Some(segment) => segment,
+            None => {
+                gst_error!(CAT, obj: src, "Can only seek in bytes");
+                return false;
+            }
+        };
+
+        let mut state = self.state.lock().unwrap();
+
+        let (url, client, size, prefetcher) = match *state {
+            StreamingState::Started {
+                ref url,
+                ref client,
+                size,
+                ref mut prefetcher,
+            } => (url, client, size, prefetcher),
+            StreamingState::Stopped => {
+                gst::element_error!(src, gst::LibraryError::Failed, ["Not started yet"]);
+                return false;
+            }
+        };
+
+        let start = segment.start().map(|start| *start).unwrap_or(0);
+
+        gst_debug!(CAT, obj: src, "Seeking to {}", start);
+
+        if start > size {
+            gst_error!(CAT, obj: src, "Seek position {} after end {}", start, size);
+            return false;
+        }
+
+        // Already request the data at the new position while the seek is completed
+        if prefetcher.max_chunks > 0 {
+            prefetcher.seek(start);
+            prefetcher.fill(start, size, 1, |offset, length| {
+                s3utils::RUNTIME.spawn(get_range(client.clone(), url.clone(), offset, length))
+            });
+        }
+
+        segment.set_time(segment.start());
+
         true
     }
 
@@ -482,3 +875,102 @@ impl BaseSrcImpl for S3Src {
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Fills `prefetcher` with chunks that complete immediately and returns the requested ranges
+    fn fill(prefetcher: &mut Prefetcher, offset: u64, size: u64, count: usize) -> Vec<(u64, u64)> {
+        let mut requests = Vec::new();
+
+        prefetcher.fill(offset, size, count, |offset, length| {
+            requests.push((offset, length));
+            s3utils::RUNTIME.spawn(future::ready(Ok(Bytes::from(vec![0; length as usize]))))
+        });
+
+        requests
+    }
+
+    fn chunks(prefetcher: &Prefetcher) -> Vec<(u64, u64)> {
+        prefetcher
+            .chunks
+            .iter()
+            .map(|chunk| (chunk.offset, chunk.size))
+            .collect()
+    }
+
+    #[test]
+    fn sequential_read_ahead() {
+        let mut prefetcher = Prefetcher::new(4, 100);
+
+        let count = prefetcher.read_ahead(0);
+        assert_eq!(count, 4);
+        assert_eq!(
+            fill(&mut prefetcher, 0, 1000, count),
+            vec![(0, 100), (100, 100), (200, 100), (300, 100)]
+        );
+
+        // Consume the first chunk, only one more chunk is requested to keep 4 queued
+        prefetcher.chunks.pop_front();
+        prefetcher.read_end = 100;
+        let count = prefetcher.read_ahead(100);
+        assert_eq!(count, 4);
+        assert_eq!(fill(&mut prefetcher, 100, 1000, count), vec![(400, 100)]);
+    }
+
+    #[test]
+    fn non_sequential_read_ahead() {
+        let mut prefetcher = Prefetcher::new(4, 100);
+        prefetcher.read_end = 100;
+
+        let count = prefetcher.read_ahead(500);
+        assert_eq!(count, 1);
+
+        prefetcher.seek(500);
+        assert_eq!(fill(&mut prefetcher, 500, 1000, count), vec![(500, 100)]);
+    }
+
+    #[test]
+    fn seek_into_chunk() {
+        let mut prefetcher = Prefetcher::new(4, 100);
+        fill(&mut prefetcher, 0, 1000, 4);
+
+        // The chunk containing the new position is kept, the ones before it are dropped
+        prefetcher.seek(250);
+        assert_eq!(chunks(&prefetcher), vec![(200, 100), (300, 100)]);
+
+        // Further chunks continue after the last queued one
+        assert_eq!(
+            fill(&mut prefetcher, 250, 1000, 4),
+            vec![(400, 100), (500, 100)]
+        );
+    }
+
+    #[test]
+    fn seek_outside_chunks() {
+        let mut prefetcher = Prefetcher::new(4, 100);
+        fill(&mut prefetcher, 0, 1000, 4);
+
+        prefetcher.seek(650);
+        assert!(prefetcher.chunks.is_empty());
+
+        // Without queued chunks the next chunk starts exactly at the requested offset
+        assert_eq!(fill(&mut prefetcher, 650, 1000, 1), vec![(650, 100)]);
+    }
+
+    #[test]
+    fn clamp_at_object_size() {
+        let mut prefetcher = Prefetcher::new(4, 100);
+
+        assert_eq!(
+            fill(&mut prefetcher, 0, 250, 4),
+            vec![(0, 100), (100, 100), (200, 50)]
+        );
+        assert!(fill(&mut prefetcher, 0, 250, 4).is_empty());
+
+        prefetcher.seek(250);
+        assert!(prefetcher.chunks.is_empty());
+        assert!(fill(&mut prefetcher, 250, 250, 4).is_empty());
+    }
+}
@@ -9,12 +9,32 @@
 use bytes::{buf::BufMut, Bytes, BytesMut};
 use futures::stream::TryStreamExt;
 use futures::{future, Future};
+use gst::{glib, gst_warning};
 use once_cell::sync::Lazy;
-use rusoto_core::ByteStream;
-use std::sync::Mutex;
+use rusoto_core::request::{
+    DispatchSignedRequest, DispatchSignedRequestFuture, HttpClient, HttpDispatchError,
+};
+use rusoto_core::{region::Region, ByteStream};
+use rusoto_credential::{
+    AutoRefreshingProvider, DefaultCredentialsProvider, ProfileProvider, ProvideAwsCredentials,
+    StaticProvider,
+};
+use rusoto_s3::S3Client;
+use rusoto_signature::SignedRequest;
+use rusoto_sts::{StsAssumeRoleSessionCredentialsProvider, StsClient};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
 use tokio::runtime;
 
-static RUNTIME: Lazy<runtime::Runtime> = Lazy::new(|| {
+static CAT: Lazy<gst::DebugCategory> = Lazy::new(|| {
+    gst::DebugCategory::new(
+        "rusotos3utils",
+        gst::DebugColorFlags::empty(),
+        Some("Amazon S3 utilities"),
+    )
+});
+
+pub static RUNTIME: Lazy<runtime::Runtime> = Lazy::new(|| {
     runtime::Builder::new_multi_thread()
         .enable_all()
         .worker_threads(2)
@@ -69,18 +89,288 @@ where
     res
 }
 
+pub async fn collect_stream(stream: &mut ByteStream) -> Result<Bytes, std::io::Error> {
+    let mut collect = BytesMut::new();
+
+    // Loop over the stream and collect till we're done
+    while let Some(item) = stream.try_next().await? {
+        collect.put(item)
+    }
+
+    Ok(collect.freeze())
+}
+
 pub fn wait_stream(
     canceller: &Mutex<Option<future::AbortHandle>>,
     stream: &mut ByteStream,
 ) -> Result<Bytes, WaitError<std::io::Error>> {
-    wait(canceller, async move {
-        let mut collect = BytesMut::new();
+    wait(canceller, collect_stream(stream))
+}
 
-        // Loop over the stream and collect till we're done
-        while let Some(item) = stream.try_next().await? {
-            collect.put(item)
-        }
+#[derive(Debug, Eq, PartialEq, Clone, Copy, glib::Enum)]
+#[repr(u32)]
+#[enum_type(name = "GstS3AddressingStyle")]
+pub enum AddressingStyle {
+    #[enum_value(
+        name = "Path: Objects are addressed as <endpoint>/<bucket>/<key>.",
+        nick = "path"
+    )]
+    Path,
+    #[enum_value(
+        name = "VirtualHosted: Objects are addressed as <bucket>.<endpoint>/<key>.",
+        nick = "virtual-hosted"
+    )]
+    VirtualHosted,
+}
+
+impl Default for AddressingStyle {
+    fn default() -> Self {
+        AddressingStyle::Path
+    }
+}
 
-        Ok::<Bytes, std::io::Error>(collect.freeze())
+/// Credentials and endpoint configuration shared by the S3 elements
+#[derive(Clone, Debug, Default)]
+pub struct ClientSettings {
+    pub access_key: Option<String>,
+    pub secret_access_key: Option<String>,
+    pub session_token: Option<String>,
+    pub role_arn: Option<String>,
+    pub profile: Option<String>,
+    pub endpoint_uri: Option<String>,
+    pub addressing_style: AddressingStyle,
+}
+
+fn dispatcher() -> Result<HttpClient, gst::ErrorMessage> {
+    HttpClient::new().map_err(|err| {
+        gst::error_msg!(
+            gst::LibraryError::Init,
+            ["Failed to create request dispatcher: {}", err]
+        )
     })
 }
+
+/// Dispatches the requests of an S3 client with virtual-hosted-style addressing
+///
+/// rusoto only creates path-style requests, so the bucket is moved from the path of each request
+/// to the host name, and the request is signed again afterwards.
+struct VirtualHostedDispatcher<P> {
+    dispatcher: Arc<HttpClient>,
+    provider: Arc<P>,
+    bucket: String,
+}
+
+impl<P> DispatchSignedRequest for VirtualHostedDispatcher<P>
+where
+    P: ProvideAwsCredentials + Send + Sync + 'static,
+{
+    fn dispatch(
+        &self,
+        mut request: SignedRequest,
+        timeout: Option<Duration>,
+    ) -> DispatchSignedRequestFuture {
+        let dispatcher = self.dispatcher.clone();
+        let provider = self.provider.clone();
+
+        request.path = virtual_hosted_path(&request.path, &self.bucket);
+        let hostname = format!("{}.{}", self.bucket, request.hostname());
+        request.set_hostname(Some(hostname));
+
+        Box::pin(async move {
+            let credentials = provider.credentials().await.map_err(|err| {
+                HttpDispatchError::new(format!("Failed to get credentials: {}", err))
+            })?;
+            request.sign(&credentials);
+
+            dispatcher.dispatch(request, timeout).await
+        })
+    }
+}
+
+/// Returns the path of a path-style request without the bucket
+fn virtual_hosted_path(path: &str, bucket: &str) -> String {
+    match path
+        .strip_prefix('/')
+        .and_then(|path| path.strip_prefix(bucket))
+    {
+        Some("") => String::from("/"),
+        Some(key) if key.starts_with('/') => key.to_owned(),
+        _ => path.to_owned(),
+    }
+}
+
+/// Creates an S3 client with the configured addressing style
+fn s3_client<P>(
+    provider: P,
+    region: Region,
+    bucket: &str,
+    addressing_style: AddressingStyle,
+) -> Result<S3Client, gst::ErrorMessage>
+where
+    P: ProvideAwsCredentials + Send + Sync + 'static,
+{
+    match addressing_style {
+        AddressingStyle::Path => Ok(S3Client::new_with(dispatcher()?, provider, region)),
+        AddressingStyle::VirtualHosted => {
+            let provider = Arc::new(provider);
+            let dispatcher = VirtualHostedDispatcher {
+                dispatcher: Arc::new(dispatcher()?),
+                provider: provider.clone(),
+                bucket: bucket.to_owned(),
+            };
+
+            Ok(S3Client::new_with(dispatcher, provider, region))
+        }
+    }
+}
+
+fn new_client<P>(
+    provider: P,
+    region: Region,
+    bucket: &str,
+    settings: &ClientSettings,
+) -> Result<S3Client, gst::ErrorMessage>
+where
+    P: ProvideAwsCredentials + Send + Sync + 'static,
+{
+    let role_arn = match settings.role_arn {
+        Some(ref role_arn) => role_arn,
+        None => return s3_client(provider, region, bucket, settings.addressing_style),
+    };
+
+    // The credentials of the provider are only used for assuming the role, all S3 requests
+    // are done with the temporary credentials of the role
+    let sts_client = StsClient::new_with(dispatcher()?, provider, region.clone());
+    let provider = StsAssumeRoleSessionCredentialsProvider::new(
+        sts_client,
+        role_arn.to_owned(),
+        String::from("gst-rusoto"),
+        None,
+        None,
+        None,
+        None,
+    );
+    let provider = AutoRefreshingProvider::new(provider).map_err(|err| {
+        gst::error_msg!(
+            gst::ResourceError::Settings,
+            [
+                "Failed to create credentials provider for role '{}': {}",
+                role_arn,
+                err
+            ]
+        )
+    })?;
+
+    s3_client(provider, region, bucket, settings.addressing_style)
+}
+
+/// Returns the region with the endpoint replaced by the configured endpoint URI, if any
+///
+/// The name of the region is kept as it is still used for signing the requests.
+fn client_region(region: Region, settings: &ClientSettings) -> Region {
+    match settings.endpoint_uri {
+        Some(ref endpoint) => Region::Custom {
+            name: region.name().to_owned(),
+            endpoint: endpoint.clone(),
+        },
+        None => region,
+    }
+}
+
+/// Creates an S3 client for the given region and bucket
+///
+/// Explicitly configured keys take precedence over a named profile, which in turn takes
+/// precedence over the default provider chain of rusoto. If a role is configured, it is
+/// assumed with the credentials found that way.
+pub fn create_client(
+    region: Region,
+    bucket: &str,
+    settings: &ClientSettings,
+) -> Result<S3Client, gst::ErrorMessage> {
+    let region = client_region(region, settings);
+
+    if settings.session_token.is_some()
+        && (settings.access_key.is_none() || settings.secret_access_key.is_none())
+    {
+        gst_warning!(
+            CAT,
+            "Ignoring session token as it is only used together with access key and secret access key"
+        );
+    }
+
+    match (
+        settings.access_key.as_ref(),
+        settings.secret_access_key.as_ref(),
+        settings.profile.as_ref(),
+    ) {
+        (Some(access_key), Some(secret_access_key), _) => {
+            let provider = StaticProvider::new(
+                access_key.clone(),
+                secret_access_key.clone(),
+                settings.session_token.clone(),
+                None,
+            );
+            new_client(provider, region, bucket, settings)
+        }
+        (_, _, Some(profile)) => {
+            let mut provider = ProfileProvider::new().map_err(|err| {
+                gst::error_msg!(
+                    gst::ResourceError::Settings,
+                    ["Failed to load AWS profiles: {}", err]
+                )
+            })?;
+            provider.set_profile(profile.as_str());
+            new_client(provider, region, bucket, settings)
+        }
+        _ => {
+            let provider = DefaultCredentialsProvider::new().map_err(|err| {
+                gst::error_msg!(
+                    gst::ResourceError::Settings,
+                    ["Failed to create credentials provider: {}", err]
+                )
+            })?;
+            new_client(provider, region, bucket, settings)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn path_without_bucket() {
+        assert_eq!(
+            virtual_hosted_path("/my-bucket/my/key", "my-bucket"),
+            "/my/key"
+        );
+        assert_eq!(virtual_hosted_path("/my-bucket", "my-bucket"), "/");
+        assert_eq!(
+            virtual_hosted_path("/my-bucket-2/key", "my-bucket"),
+            "/my-bucket-2/key"
+        );
+    }
+
+    #[test]
+    fn region_without_endpoint() {
+        let settings = ClientSettings::default();
+
+        assert_eq!(client_region(Region::EuWest2, &settings), Region::EuWest2);
+    }
+
+    #[test]
+    fn region_with_endpoint() {
+        let settings = ClientSettings {
+            endpoint_uri: Some(String::from("http://localhost:9000")),
+            ..Default::default()
+        };
+
+        assert_eq!(
+            client_region(Region::EuWest2, &settings),
+            Region::Custom {
+                name: String::from("eu-west-2"),
+                endpoint: String::from("http://localhost:9000"),
+            }
+        );
+    }
+}
@@ -0,0 +1,264 @@
+// Copyright (C) 2026 agent <agent@local>
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+use gst::prelude::*;
+
+use hyper::{Body, Method, Request, Response, StatusCode};
+use std::sync::{Arc, Mutex};
+
+const OBJECT: &[u8] = b"Hello S3";
+const ROLE_ARN: &str = "arn:aws:iam::123456789012:role/gst-test";
+
+fn init() {
+    use std::sync::Once;
+    static INIT: Once = Once::new();
+
+    INIT.call_once(|| {
+        gst::init().unwrap();
+        gstrusoto::plugin_register_static().expect("rusotos3src test");
+    });
+}
+
+/// A request received by the S3 stand-in
+#[derive(Debug, Clone)]
+struct ReceivedRequest {
+    method: Method,
+    path: String,
+    /// Access key ID from the credential scope of the signature
+    access_key: Option<String>,
+    security_token: Option<String>,
+    body: String,
+}
+
+/// Local stand-in for the S3 and STS endpoints
+///
+/// It serves `OBJECT` for any bucket and key, hands out temporary credentials for any role
+/// and records all requests it receives.
+struct Server {
+    uri: String,
+    requests: Arc<Mutex<Vec<ReceivedRequest>>>,
+    _rt: tokio::runtime::Runtime,
+}
+
+impl Server {
+    fn new() -> Server {
+        use hyper::service::{make_service_fn, service_fn};
+
+        let rt = tokio::runtime::Builder::new_multi_thread()
+            .worker_threads(1)
+            .enable_all()
+            .build()
+            .unwrap();
+
+        let requests = Arc::new(Mutex::new(Vec::new()));
+        let requests_clone = requests.clone();
+        let make_service = make_service_fn(move |_ctx| {
+            let requests = requests_clone.clone();
+            async move {
+                Ok::<_, hyper::Error>(service_fn(move |req| {
+                    let requests = requests.clone();
+                    async move {
+                        let request = ReceivedRequest::read(req).await?;
+                        let response = respond(&request);
+                        requests.lock().unwrap().push(request);
+                        Ok::<_, hyper::Error>(response)
+                    }
+                }))
+            }
+        });
+
+        let local_addr = {
+            let _enter = rt.enter();
+            let server = hyper::Server::bind(&([127, 0, 0, 1], 0).into()).serve(make_service);
+            let local_addr = server.local_addr();
+            rt.spawn(server);
+            local_addr
+        };
+
+        Server {
+            uri: format!("http://{}", local_addr),
+            requests,
+            _rt: rt,
+        }
+    }
+
+    fn requests(&self) -> Vec<ReceivedRequest> {
+        self.requests.lock().unwrap().clone()
+    }
+
+    fn request(&self, method: Method) -> ReceivedRequest {
+        self.requests()
+            .into_iter()
+            .find(|request| request.method == method)
+            .unwrap_or_else(|| panic!("No {} request received", method))
+    }
+}
+
+impl ReceivedRequest {
+    async fn read(req: Request<Body>) -> Result<ReceivedRequest, hyper::Error> {
+        let header = |name: &str| {
+            req.headers()
+                .get(name)
+                .map(|value| value.to_str().unwrap().to_owned())
+        };
+
+        let access_key = header("authorization").and_then(|authorization| {
+            let credential = authorization.split("Credential=").nth(1)?;
+            credential.split('/').next().map(String::from)
+        });
+        let security_token = header("x-amz-security-token");
+        let method = req.method().clone();
+        let path = req.uri().path().to_owned();
+        let body = hyper::body::to_bytes(req.into_body()).await?;
+
+        Ok(ReceivedRequest {
+            method,
+            path,
+            access_key,
+            security_token,
+            body: String::from_utf8_lossy(&body).into_owned(),
+        })
+    }
+}
+
+fn respond(request: &ReceivedRequest) -> Response<Body> {
+    match request.method {
+        // STS AssumeRole
+        Method::POST => Response::builder()
+            .header("content-type", "text/xml")
+            .body(Body::from(format!(
+                r#"<AssumeRoleResponse xmlns="https://sts.amazonaws.com/doc/2011-06-15/">
+  <AssumeRoleResult>
+    <Credentials>
+      <AccessKeyId>ROLEACCESSKEY</AccessKeyId>
+      <SecretAccessKey>role-secret-access-key</SecretAccessKey>
+      <SessionToken>role-session-token</SessionToken>
+      <Expiration>2099-01-01T00:00:00Z</Expiration>
+    </Credentials>
+    <AssumedRoleUser>
+      <Arn>{}/gst-rusoto</Arn>
+      <AssumedRoleId>AROA123456789:gst-rusoto</AssumedRoleId>
+    </AssumedRoleUser>
+  </AssumeRoleResult>
+  <ResponseMetadata>
+    <RequestId>00000000-0000-0000-0000-000000000000</RequestId>
+  </ResponseMetadata>
+</AssumeRoleResponse>"#,
+                ROLE_ARN
+            )))
+            .unwrap(),
+        Method::HEAD => Response::builder()
+            .header("content-length", OBJECT.len())
+            .body(Body::empty())
+            .unwrap(),
+        Method::GET => Response::new(Body::from(OBJECT)),
+        _ => Response::builder()
+            .status(StatusCode::METHOD_NOT_ALLOWED)
+            .body(Body::empty())
+            .unwrap(),
+    }
+}
+
+/// Reads the object from the stand-in with the given settings on the source
+fn read_object<F: FnOnce(&gst::Element)>(server: &Server, setup_func: F) {
+    init();
+
+    let mut h = gst_check::Harness::new("rusotos3src");
+    {
+        let src = h.element().unwrap();
+        src.set_property("uri", "s3://us-east-1/gst-bucket/gst-object");
+        src.set_property("endpoint-uri", &server.uri);
+        src.set_property("prefetch-chunks", 0u32);
+        setup_func(&src);
+    }
+
+    h.play();
+
+    let buffer = h.pull().unwrap();
+    assert_eq!(buffer.map_readable().unwrap().as_slice(), OBJECT);
+}
+
+#[test]
+fn test_explicit_keys_take_precedence_over_profile() {
+    let server = Server::new();
+
+    read_object(&server, |src| {
+        src.set_property("access-key", "EXPLICITACCESSKEY");
+        src.set_property("secret-access-key", "explicit-secret-access-key");
+        src.set_property("session-token", "explicit-session-token");
+        src.set_property("profile", "gst-test-missing-profile");
+    });
+
+    for request in server.requests() {
+        assert_eq!(request.path, "/gst-bucket/gst-object");
+        assert_eq!(request.access_key.as_deref(), Some("EXPLICITACCESSKEY"));
+        assert_eq!(
+            request.security_token.as_deref(),
+            Some("explicit-session-token")
+        );
+    }
+    server.request(Method::HEAD);
+    server.request(Method::GET);
+}
+
+#[test]
+fn test_profile_credentials() {
+    let server = Server::new();
+
+    let credentials = std::env::temp_dir().join(format!(
+        "gst-rusoto-test-credentials-{}",
+        std::process::id()
+    ));
+    std::fs::write(
+        &credentials,
+        "[gst-test]\n\
+         aws_access_key_id = PROFILEACCESSKEY\n\
+         aws_secret_access_key = profile-secret-access-key\n",
+    )
+    .unwrap();
+    std::env::set_var("AWS_SHARED_CREDENTIALS_FILE", &credentials);
+
+    read_object(&server, |src| {
+        src.set_property("profile", "gst-test");
+    });
+
+    let _ = std::fs::remove_file(&credentials);
+
+    for request in server.requests() {
+        assert_eq!(request.access_key.as_deref(), Some("PROFILEACCESSKEY"));
+        assert_eq!(request.security_token, None);
+    }
+    server.request(Method::HEAD);
+}
+
+#[test]
+fn test_assume_role() {
+    let server = Server::new();
+
+    read_object(&server, |src| {
+        src.set_property("access-key", "EXPLICITACCESSKEY");
+        src.set_property("secret-access-key", "explicit-secret-access-key");
+        src.set_property("role-arn", ROLE_ARN);
+    });
+
+    // The role is assumed with the configured credentials...
+    let assume_role = server.request(Method::POST);
+    assert!(assume_role.body.contains("Action=AssumeRole"));
+    assert!(assume_role.body.contains("RoleArn="));
+    assert_eq!(assume_role.access_key.as_deref(), Some("EXPLICITACCESSKEY"));
+
+    // ...and the object is read with the temporary credentials of the role
+    for method in [Method::HEAD, Method::GET] {
+        let request = server.request(method);
+        assert_eq!(request.access_key.as_deref(), Some("ROLEACCESSKEY"));
+        assert_eq!(
+            request.security_token.as_deref(),
+            Some("role-session-token")
+        );
+    }
+}